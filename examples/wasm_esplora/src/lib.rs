@@ -0,0 +1,74 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+use bdk::bitcoin::Network;
+use bdk::blockchain::{noop_progress, EsploraBlockchain};
+use bdk::database::MemoryDatabase;
+use bdk::wallet::AddressIndex;
+use bdk::Wallet;
+
+use wasm_bindgen::prelude::*;
+
+fn to_js_error<E: std::fmt::Display>(e: E) -> JsValue {
+    JsValue::from_str(&e.to_string())
+}
+
+/// Wallet synced with an Esplora server from the browser
+#[wasm_bindgen]
+pub struct WebWallet {
+    wallet: Wallet<EsploraBlockchain, MemoryDatabase>,
+}
+
+#[wasm_bindgen]
+impl WebWallet {
+    /// Create a testnet wallet for `descriptor` and `change_descriptor`, that uses the Esplora
+    /// server at `esplora_url`
+    pub async fn new(
+        descriptor: String,
+        change_descriptor: Option<String>,
+        esplora_url: String,
+    ) -> Result<WebWallet, JsValue> {
+        let blockchain = EsploraBlockchain::new(&esplora_url, None, 20);
+        let wallet = Wallet::new(
+            descriptor.as_str(),
+            change_descriptor.as_deref(),
+            Network::Testnet,
+            MemoryDatabase::default(),
+            blockchain,
+        )
+        .await
+        .map_err(to_js_error)?;
+
+        Ok(WebWallet { wallet })
+    }
+
+    /// Sync the wallet with the Esplora server
+    pub async fn sync(&self) -> Result<(), JsValue> {
+        self.wallet
+            .sync(noop_progress(), None)
+            .await
+            .map_err(to_js_error)
+    }
+
+    /// Return the balance of the wallet, in satoshis
+    pub fn balance(&self) -> Result<f64, JsValue> {
+        Ok(self.wallet.get_balance().map_err(to_js_error)? as f64)
+    }
+
+    /// Return a new receiving address
+    pub fn new_address(&self) -> Result<String, JsValue> {
+        Ok(self
+            .wallet
+            .get_address(AddressIndex::New)
+            .map_err(to_js_error)?
+            .address
+            .to_string())
+    }
+}
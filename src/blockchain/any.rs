@@ -142,6 +142,9 @@ impl Blockchain for AnyBlockchain {
     fn get_tx(&self, txid: &Txid) -> Result<Option<Transaction>, Error> {
         maybe_await!(impl_inner_method!(self, get_tx, txid))
     }
+    fn get_txs(&self, txids: &[Txid]) -> Result<Vec<Option<Transaction>>, Error> {
+        maybe_await!(impl_inner_method!(self, get_txs, txids))
+    }
     fn broadcast(&self, tx: &Transaction) -> Result<(), Error> {
         maybe_await!(impl_inner_method!(self, broadcast, tx))
     }
@@ -152,6 +155,16 @@ impl Blockchain for AnyBlockchain {
     fn estimate_fee(&self, target: usize) -> Result<FeeRate, Error> {
         maybe_await!(impl_inner_method!(self, estimate_fee, target))
     }
+    fn get_median_time_past(&self) -> Result<Option<u32>, Error> {
+        maybe_await!(impl_inner_method!(self, get_median_time_past))
+    }
+}
+
+#[maybe_async]
+impl FeeEstimator for AnyBlockchain {
+    fn fee_estimates(&self, targets: &[usize]) -> Result<FeeEstimates, Error> {
+        maybe_await!(impl_inner_method!(self, fee_estimates, targets))
+    }
 }
 
 impl_from!(electrum::ElectrumBlockchain, AnyBlockchain, Electrum, #[cfg(feature = "electrum")]);
@@ -185,8 +198,10 @@ impl_from!(compact_filters::CompactFiltersBlockchain, AnyBlockchain, CompactFilt
 ///         url: "ssl://electrum.blockstream.info:50002".into(),
 ///         retry: 2,
 ///         socks5: None,
+///         socks5_credentials: None,
 ///         timeout: None,
 ///         stop_gap: 20,
+///         validate_domain: true,
 ///     })
 /// );
 /// # }
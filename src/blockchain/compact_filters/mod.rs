@@ -67,11 +67,12 @@ mod peer;
 mod store;
 mod sync;
 
-use super::{Blockchain, Capability, ConfigurableBlockchain, Progress};
+use super::{Blockchain, Capability, ConfigurableBlockchain, FeeEstimator, Progress};
 use crate::database::{BatchDatabase, BatchOperations, DatabaseUtils};
 use crate::error::Error;
 use crate::types::{KeychainKind, LocalUtxo, TransactionDetails};
-use crate::{ConfirmationTime, FeeRate};
+use crate::wallet::time::get_timestamp;
+use crate::{ConfirmationTime, FeeEstimates, FeeRate};
 
 use peer::*;
 use store::*;
@@ -208,6 +209,7 @@ impl CompactFiltersBlockchain {
                 sent: outgoing,
                 confirmation_time: ConfirmationTime::new(height, timestamp),
                 verified: height.is_some(),
+                last_evicted: None,
                 fee: Some(inputs_sum.saturating_sub(outputs_sum)),
             };
 
@@ -454,6 +456,13 @@ impl Blockchain for CompactFiltersBlockchain {
     }
 }
 
+impl FeeEstimator for CompactFiltersBlockchain {
+    fn fee_estimates(&self, _targets: &[usize]) -> Result<FeeEstimates, Error> {
+        // TODO
+        Ok(FeeEstimates::new(get_timestamp()))
+    }
+}
+
 /// Data to connect to a Bitcoin P2P peer
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone, PartialEq)]
 pub struct BitcoinPeerConfig {
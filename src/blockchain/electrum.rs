@@ -24,20 +24,73 @@
 //! # Ok::<(), bdk::Error>(())
 //! ```
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 #[allow(unused_imports)]
-use log::{debug, error, info, trace};
+use log::{debug, error, info, trace, warn};
 
-use bitcoin::{BlockHeader, Script, Transaction, Txid};
+use bitcoin::hashes::Hash;
+use bitcoin::{BlockHash, BlockHeader, Network, Script, Transaction, TxMerkleNode, Txid};
 
 use electrum_client::{Client, ConfigBuilder, ElectrumApi, Socks5Config};
 
+use self::spv::{HeaderChain, MerkleProof};
+use self::tx_cache::TxCache;
 use self::utils::{ElectrumLikeSync, ElsGetHistoryRes};
 use super::*;
 use crate::database::BatchDatabase;
 use crate::error::Error;
-use crate::FeeRate;
+use crate::wallet::time::get_timestamp;
+use crate::{FeeEstimates, FeeRate};
+
+/// Errors returned by [`ElectrumBlockchain::broadcast_and_check`]
+#[derive(Debug)]
+pub enum BroadcastError {
+    /// The server rejected the transaction, because it's invalid or doesn't meet its policy
+    ///
+    /// Contains the message returned by the server.
+    Rejected(String),
+    /// The server accepted the transaction, but it can't be found in its mempool
+    NotPropagated(Txid),
+    /// The server can't be reached, or its response is invalid
+    Transport(electrum_client::Error),
+}
+
+impl fmt::Display for BroadcastError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BroadcastError::Rejected(message) => write!(f, "Transaction rejected: {}", message),
+            BroadcastError::NotPropagated(txid) => {
+                write!(f, "Transaction {} not found in the mempool", txid)
+            }
+            BroadcastError::Transport(e) => write!(f, "Electrum error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BroadcastError {}
+
+impl From<electrum_client::Error> for BroadcastError {
+    fn from(err: electrum_client::Error) -> Self {
+        match err {
+            // errors returned by the server, as opposed to the ones of the connection
+            electrum_client::Error::Protocol(value) => BroadcastError::Rejected(
+                value
+                    .get("message")
+                    .and_then(|message| message.as_str())
+                    .map(|message| message.to_string())
+                    .unwrap_or_else(|| value.to_string()),
+            ),
+            e => BroadcastError::Transport(e),
+        }
+    }
+}
 
 /// Wrapper over an Electrum Client that implements the required blockchain traits
 ///
@@ -46,6 +99,8 @@ use crate::FeeRate;
 pub struct ElectrumBlockchain {
     client: Client,
     stop_gap: usize,
+    headers: Option<Mutex<HeaderChain>>,
+    tx_cache: Option<Arc<dyn TxCache>>,
 }
 
 impl std::convert::From<Client> for ElectrumBlockchain {
@@ -53,8 +108,65 @@ impl std::convert::From<Client> for ElectrumBlockchain {
         ElectrumBlockchain {
             client,
             stop_gap: 20,
+            headers: None,
+            tx_cache: None,
+        }
+    }
+}
+
+impl ElectrumBlockchain {
+    /// Validate the proof of work of the block headers downloaded from the server
+    ///
+    /// The headers are kept in memory and checked to connect to each other, and the merkle proofs
+    /// of the newly confirmed transactions are verified against them. See the
+    /// [`spv`](crate::blockchain::spv) module for the details.
+    ///
+    /// Without it the confirmation heights reported by `blockchain.scripthash.get_history` are
+    /// trusted, and a malicious server can make up confirmations.
+    pub fn with_header_validation(mut self, network: Network) -> Self {
+        self.headers = Some(Mutex::new(HeaderChain::new(network)));
+        self
+    }
+
+    /// Check `tx_cache` before downloading a raw transaction, and add to it the ones downloaded
+    ///
+    /// The raw transactions are already saved in the wallet's database, but a cache can be shared
+    /// between wallets, or kept while the database is rebuilt from scratch.
+    pub fn with_tx_cache(mut self, tx_cache: Arc<dyn TxCache>) -> Self {
+        self.tx_cache = Some(tx_cache);
+        self
+    }
+
+    /// Broadcast a transaction, then check that the server can return it from its mempool
+    ///
+    /// Unlike [`Blockchain::broadcast`], the errors tell apart a transaction rejected by the server
+    /// from a failure to reach it, see [`BroadcastError`]. Returns the txid of the transaction.
+    pub fn broadcast_and_check(&self, tx: &Transaction) -> Result<Txid, BroadcastError> {
+        let txid = self.client.transaction_broadcast(tx)?;
+
+        match self.client.transaction_get(&txid) {
+            Ok(found) if found.txid() == txid => Ok(txid),
+            Ok(_) | Err(electrum_client::Error::Protocol(_)) => {
+                Err(BroadcastError::NotPropagated(txid))
+            }
+            Err(e) => Err(BroadcastError::Transport(e)),
         }
     }
+
+    /// Return the validated block headers, if header validation is enabled
+    pub fn headers(&self) -> Option<HeaderChain> {
+        self.headers
+            .as_ref()
+            .map(|headers| headers.lock().unwrap().clone())
+    }
+
+    /// Return the minimum fee rate of the transactions relayed by the server
+    ///
+    /// The fee rates returned by [`Blockchain::estimate_fee`] and
+    /// [`FeeEstimator::fee_estimates`] are never lower than this.
+    pub fn relay_fee(&self) -> Result<FeeRate, Error> {
+        relay_fee(&self.client)
+    }
 }
 
 impl Blockchain for ElectrumBlockchain {
@@ -73,12 +185,62 @@ impl Blockchain for ElectrumBlockchain {
         database: &mut D,
         progress_update: P,
     ) -> Result<(), Error> {
-        self.client
-            .electrum_like_setup(self.stop_gap, database, progress_update)
+        match &self.tx_cache {
+            Some(tx_cache) => CachedSync::new(&self.client, tx_cache.as_ref()).electrum_like_setup(
+                self.stop_gap,
+                self.headers.as_ref(),
+                true,
+                database,
+                progress_update,
+            ),
+            None => self.client.electrum_like_setup(
+                self.stop_gap,
+                self.headers.as_ref(),
+                true,
+                database,
+                progress_update,
+            ),
+        }
+    }
+
+    fn sync<D: BatchDatabase, P: Progress>(
+        &self,
+        database: &mut D,
+        progress_update: P,
+    ) -> Result<(), Error> {
+        match &self.tx_cache {
+            Some(tx_cache) => CachedSync::new(&self.client, tx_cache.as_ref()).electrum_like_setup(
+                self.stop_gap,
+                self.headers.as_ref(),
+                false,
+                database,
+                progress_update,
+            ),
+            None => self.client.electrum_like_setup(
+                self.stop_gap,
+                self.headers.as_ref(),
+                false,
+                database,
+                progress_update,
+            ),
+        }
     }
 
     fn get_tx(&self, txid: &Txid) -> Result<Option<Transaction>, Error> {
-        Ok(self.client.transaction_get(txid).map(Option::Some)?)
+        if let Some(tx) = self.tx_cache.as_ref().and_then(|cache| cache.get(txid)) {
+            return Ok(Some(tx));
+        }
+
+        let tx = self.client.transaction_get(txid)?;
+        if let Some(cache) = &self.tx_cache {
+            cache.insert(&tx);
+        }
+
+        Ok(Some(tx))
+    }
+
+    fn get_txs(&self, txids: &[Txid]) -> Result<Vec<Option<Transaction>>, Error> {
+        batch_get_txs(&self.client, txids, self.stop_gap)
     }
 
     fn broadcast(&self, tx: &Transaction) -> Result<(), Error> {
@@ -95,12 +257,81 @@ impl Blockchain for ElectrumBlockchain {
     }
 
     fn estimate_fee(&self, target: usize) -> Result<FeeRate, Error> {
-        Ok(FeeRate::from_btc_per_kvb(
-            self.client.estimate_fee(target)? as f32
+        estimate_fee_with_floor(&self.client, target)
+    }
+
+    fn get_median_time_past(&self) -> Result<Option<u32>, Error> {
+        let tip = self.get_height()?;
+
+        Ok(Some(
+            self.client
+                .els_median_time_past(tip, self.headers.as_ref())?,
         ))
     }
 }
 
+impl FeeEstimator for ElectrumBlockchain {
+    fn fee_estimates(&self, targets: &[usize]) -> Result<FeeEstimates, Error> {
+        batch_fee_estimates(&self.client, targets)
+    }
+}
+
+fn batch_get_txs(
+    client: &Client,
+    txids: &[Txid],
+    chunk_size: usize,
+) -> Result<Vec<Option<Transaction>>, Error> {
+    let mut txs = Vec::with_capacity(txids.len());
+    for chunk in txids.chunks(chunk_size.max(1)) {
+        txs.extend(
+            client
+                .batch_transaction_get(chunk)?
+                .into_iter()
+                .map(Option::Some),
+        );
+    }
+
+    Ok(txs)
+}
+
+fn relay_fee(client: &Client) -> Result<FeeRate, Error> {
+    Ok(FeeRate::from_btc_per_kvb(client.relay_fee()? as f32))
+}
+
+// The server returns -1 when it doesn't have enough data, and it may return estimates lower than
+// its relay fee when the mempool is empty: in both cases the relay fee is returned instead
+fn estimate_fee_with_floor(client: &Client, target: usize) -> Result<FeeRate, Error> {
+    let relay_fee = relay_fee(client)?;
+    let estimate = FeeRate::from_btc_per_kvb(client.estimate_fee(target)? as f32);
+
+    Ok(match estimate > relay_fee {
+        true => estimate,
+        false => relay_fee,
+    })
+}
+
+fn batch_fee_estimates(client: &Client, targets: &[usize]) -> Result<FeeEstimates, Error> {
+    let relay_fee = relay_fee(client)?;
+    let rates = client.batch_estimate_fee(targets.iter().cloned())?;
+
+    let mut estimates = FeeEstimates::new(get_timestamp());
+    for (target, rate) in targets.iter().zip(rates) {
+        // the server returns -1 when it doesn't have enough data
+        if rate > 0.0 {
+            let rate = FeeRate::from_btc_per_kvb(rate as f32);
+            estimates.insert(
+                *target,
+                match rate > relay_fee {
+                    true => rate,
+                    false => relay_fee,
+                },
+            );
+        }
+    }
+
+    Ok(estimates)
+}
+
 impl ElectrumLikeSync for Client {
     fn els_batch_script_get_history<'s, I: IntoIterator<Item = &'s Script> + Clone>(
         &self,
@@ -139,6 +370,30 @@ impl ElectrumLikeSync for Client {
     ) -> Result<Vec<BlockHeader>, Error> {
         self.batch_block_header(heights).map_err(Error::Electrum)
     }
+
+    fn els_batch_transaction_get_merkle<I: IntoIterator<Item = (Txid, u32)> + Clone>(
+        &self,
+        txs: I,
+    ) -> Result<Vec<MerkleProof>, Error> {
+        txs.into_iter()
+            .map(|(txid, height)| {
+                let res = self.transaction_get_merkle(&txid, height as usize)?;
+                Ok::<_, Error>(MerkleProof {
+                    block_height: res.block_height as u32,
+                    pos: res.pos,
+                    // the hashes are returned in their reversed hex representation
+                    merkle: res
+                        .merkle
+                        .into_iter()
+                        .map(|mut hash| {
+                            hash.reverse();
+                            TxMerkleNode::from_inner(hash)
+                        })
+                        .collect(),
+                })
+            })
+            .collect()
+    }
 }
 
 /// Configuration for an [`ElectrumBlockchain`]
@@ -150,30 +405,705 @@ pub struct ElectrumBlockchainConfig {
     pub url: String,
     /// URL of the socks5 proxy server or a Tor service
     pub socks5: Option<String>,
+    /// Optional socks5 proxy credentials
+    pub socks5_credentials: Option<(String, String)>,
     /// Request retry count
     pub retry: u8,
     /// Request timeout (seconds)
     pub timeout: Option<u8>,
     /// Stop searching addresses for transactions after finding an unused gap of this length
     pub stop_gap: usize,
+    /// Validate the domain of the server's TLS certificate when connecting with `ssl://` (default: true)
+    ///
+    /// Disabling it allows connecting to servers with a self-signed certificate, but makes the
+    /// connection vulnerable to man-in-the-middle attacks.
+    #[serde(default = "default_validate_domain")]
+    pub validate_domain: bool,
+}
+
+fn default_validate_domain() -> bool {
+    true
+}
+
+impl ElectrumBlockchainConfig {
+    /// Create a new configuration for the server at `url`
+    ///
+    /// The connection is direct, with TLS domain validation, no timeout, 3 retries and a stop
+    /// gap of 20. The other fields can be changed afterwards.
+    pub fn new(url: &str) -> Self {
+        ElectrumBlockchainConfig {
+            url: url.to_string(),
+            socks5: None,
+            socks5_credentials: None,
+            retry: 3,
+            timeout: None,
+            stop_gap: 20,
+            validate_domain: true,
+        }
+    }
 }
 
 impl ConfigurableBlockchain for ElectrumBlockchain {
     type Config = ElectrumBlockchainConfig;
 
     fn from_config(config: &Self::Config) -> Result<Self, Error> {
-        let socks5 = config.socks5.as_ref().map(Socks5Config::new);
-        let electrum_config = ConfigBuilder::new()
-            .retry(config.retry)
-            .timeout(config.timeout)?
-            .socks5(socks5)?
-            .build();
-
         Ok(ElectrumBlockchain {
-            client: Client::from_config(config.url.as_str(), electrum_config)?,
+            client: new_client(config, config.retry)?,
             stop_gap: config.stop_gap,
+            headers: None,
+            tx_cache: None,
+        })
+    }
+}
+
+fn new_client(config: &ElectrumBlockchainConfig, retry: u8) -> Result<Client, Error> {
+    let socks5 = config
+        .socks5
+        .as_ref()
+        .map(|proxy| match &config.socks5_credentials {
+            Some((username, password)) => {
+                Socks5Config::with_credentials(proxy, username.clone(), password.clone())
+            }
+            None => Socks5Config::new(proxy),
+        });
+    let electrum_config = ConfigBuilder::new()
+        .retry(retry)
+        .timeout(config.timeout)?
+        .socks5(socks5)?
+        .validate_domain(config.validate_domain)
+        .build();
+
+    Ok(Client::from_config(config.url.as_str(), electrum_config)?)
+}
+
+/// Update received by an [`ElectrumSubscriber`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ElectrumUpdate {
+    /// A new block has been found
+    ///
+    /// The confirmations of the wallet's transactions may have changed, or a reorg may have
+    /// happened.
+    NewTip {
+        /// Height of the new tip
+        height: u32,
+        /// Header of the new tip
+        header: BlockHeader,
+    },
+    /// The history of a subscribed script has changed
+    ScriptChanged(Script),
+    /// The connection was lost and has been re-established
+    ///
+    /// The notifications sent while disconnected are lost, so the wallet should be fully synced.
+    Reconnected,
+}
+
+/// Long-running Electrum connection that keeps the scripthash and header subscriptions open
+///
+/// Instead of syncing the wallet at regular intervals, the subscriber waits for the server to
+/// notify a new block or a change in the history of one of the wallet's scripts, and reports them
+/// as [`ElectrumUpdate`]s. The wallet only needs to be synced when an update is received.
+///
+/// The subscriber uses its own connection, without the retries of the [`Client`], so that a
+/// dropped connection is detected: it's then opened again, the subscriptions are restored and
+/// [`ElectrumUpdate::Reconnected`] is returned.
+///
+/// ## Example
+///
+/// ```no_run
+/// # use std::sync::mpsc;
+/// # use std::time::Duration;
+/// # use bdk::bitcoin::Network;
+/// # use bdk::blockchain::{ConfigurableBlockchain, noop_progress};
+/// # use bdk::blockchain::electrum::{ElectrumBlockchain, ElectrumBlockchainConfig, ElectrumSubscriber};
+/// # use bdk::database::MemoryDatabase;
+/// # use bdk::Wallet;
+/// let config = ElectrumBlockchainConfig::new("ssl://electrum.blockstream.info:60002");
+/// let wallet = Wallet::new(
+///     "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)",
+///     None,
+///     Network::Testnet,
+///     MemoryDatabase::default(),
+///     ElectrumBlockchain::from_config(&config)?,
+/// )?;
+/// wallet.sync(noop_progress(), None)?;
+///
+/// let mut subscriber = ElectrumSubscriber::from_config(&config)?;
+/// subscriber.subscribe_wallet(&wallet)?;
+///
+/// let (sender, receiver) = mpsc::channel();
+/// std::thread::spawn(move || subscriber.run(sender, Duration::from_secs(5)));
+///
+/// for _update in receiver {
+///     wallet.sync(noop_progress(), None)?;
+///     // subscribe to the addresses revealed during the sync, if any
+///     // ...
+/// }
+/// # Ok::<(), bdk::Error>(())
+/// ```
+pub struct ElectrumSubscriber {
+    config: ElectrumBlockchainConfig,
+    client: Client,
+    scripts: HashSet<Script>,
+}
+
+impl ElectrumSubscriber {
+    /// Connect to the server in `config` and subscribe to the new blocks
+    ///
+    /// The `retry` and `stop_gap` of the configuration are ignored.
+    pub fn from_config(config: &ElectrumBlockchainConfig) -> Result<Self, Error> {
+        let client = new_client(config, 0)?;
+        client.block_headers_subscribe()?;
+
+        Ok(ElectrumSubscriber {
+            config: config.clone(),
+            client,
+            scripts: HashSet::new(),
         })
     }
+
+    /// Subscribe to the history of `scripts`
+    ///
+    /// Scripts that are already subscribed are skipped.
+    pub fn subscribe_scripts<I: IntoIterator<Item = Script>>(
+        &mut self,
+        scripts: I,
+    ) -> Result<(), Error> {
+        let new_scripts = scripts
+            .into_iter()
+            .filter(|script| !self.scripts.contains(script))
+            .collect::<HashSet<_>>();
+        if new_scripts.is_empty() {
+            return Ok(());
+        }
+
+        self.client.batch_script_subscribe(new_scripts.iter())?;
+        self.scripts.extend(new_scripts);
+
+        Ok(())
+    }
+
+    /// Subscribe to the script pubkeys of `wallet` and to its watchlist
+    ///
+    /// This should be called again after every sync, to subscribe to the newly revealed
+    /// addresses.
+    pub fn subscribe_wallet<B, D: BatchDatabase>(
+        &mut self,
+        wallet: &crate::Wallet<B, D>,
+    ) -> Result<(), Error> {
+        self.subscribe_scripts(wallet.list_script_pubkeys()?)
+    }
+
+    /// Stop receiving the updates of `script`, returning whether it was subscribed
+    pub fn unsubscribe_script(&mut self, script: &Script) -> Result<bool, Error> {
+        if !self.scripts.remove(script) {
+            return Ok(false);
+        }
+
+        Ok(self.client.script_unsubscribe(script)?)
+    }
+
+    /// Return the subscribed scripts
+    pub fn scripts(&self) -> &HashSet<Script> {
+        &self.scripts
+    }
+
+    /// Check for new notifications, returning the updates received since the last call
+    ///
+    /// If the connection was dropped a new one is opened and [`ElectrumUpdate::Reconnected`] is
+    /// returned. If the server can't be reached the error is returned, and the connection is
+    /// attempted again at the next call.
+    pub fn poll(&mut self) -> Result<Vec<ElectrumUpdate>, Error> {
+        // the notifications are only read from the socket while waiting for a response
+        if let Err(e) = self.client.ping() {
+            debug!("Electrum connection lost: {}", e);
+            self.reconnect()?;
+            return Ok(vec![ElectrumUpdate::Reconnected]);
+        }
+
+        let mut updates = vec![];
+        // only the last notification matters, and it's enough to reach the tip
+        let mut tip = None;
+        while let Some(notification) = self.client.block_headers_pop()? {
+            tip = Some(notification);
+        }
+        if let Some(notification) = tip {
+            updates.push(ElectrumUpdate::NewTip {
+                height: notification.height as u32,
+                header: notification.header,
+            });
+        }
+
+        for script in &self.scripts {
+            let mut changed = false;
+            while self.client.script_pop(script)?.is_some() {
+                changed = true;
+            }
+            if changed {
+                updates.push(ElectrumUpdate::ScriptChanged(script.clone()));
+            }
+        }
+
+        Ok(updates)
+    }
+
+    /// Poll the server every `interval`, sending the updates to `sender`
+    ///
+    /// This blocks until `sender`'s receiver is dropped, so it's usually run in its own thread.
+    /// Connection errors are logged and retried at the next interval.
+    pub fn run(mut self, sender: Sender<ElectrumUpdate>, interval: Duration) {
+        loop {
+            match self.poll() {
+                Ok(updates) => {
+                    for update in updates {
+                        if sender.send(update).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(e) => error!("Electrum subscriber: {}", e),
+            }
+
+            thread::sleep(interval);
+        }
+    }
+
+    fn reconnect(&mut self) -> Result<(), Error> {
+        let client = new_client(&self.config, 0)?;
+        client.block_headers_subscribe()?;
+        client.batch_script_subscribe(self.scripts.iter())?;
+        self.client = client;
+
+        info!("Electrum subscriber reconnected to {}", self.config.url);
+
+        Ok(())
+    }
+}
+
+struct PoolServer {
+    config: ElectrumBlockchainConfig,
+    client: Mutex<Option<Arc<Client>>>,
+}
+
+impl PoolServer {
+    // Return the client, connecting to the server if needed
+    fn client(&self) -> Result<Arc<Client>, Error> {
+        let mut client = self.client.lock().unwrap();
+        if client.is_none() {
+            *client = Some(Arc::new(new_client(&self.config, self.config.retry)?));
+        }
+
+        Ok(Arc::clone(client.as_ref().unwrap()))
+    }
+
+    // Drop the connection, so that a new one is opened the next time the server is used
+    fn disconnect(&self) {
+        *self.client.lock().unwrap() = None;
+    }
+}
+
+/// Blockchain backend that connects to multiple Electrum servers, failing over to the next one
+/// when a request fails
+///
+/// The requests are sent to one server at a time. When one of them fails with a connection
+/// error the server is dropped, and the request is retried with the following ones in the list,
+/// so that a sync can continue on a different server even if it fails midway. The connection to
+/// the dropped server is opened again the next time it's its turn.
+///
+/// With [`ElectrumPool::with_tip_cross_check`] the tips of all the servers are compared before
+/// syncing, to detect a server that is lying about the chain or is following a fork.
+///
+/// ## Example
+///
+/// ```no_run
+/// # use bdk::blockchain::electrum::{ElectrumBlockchainConfig, ElectrumPool};
+/// let pool = ElectrumPool::new(vec![
+///     ElectrumBlockchainConfig::new("ssl://electrum.blockstream.info:60002"),
+///     ElectrumBlockchainConfig::new("ssl://testnet.aranguren.org:51002"),
+/// ])?
+/// .with_tip_cross_check();
+/// # Ok::<(), bdk::Error>(())
+/// ```
+pub struct ElectrumPool {
+    servers: Vec<PoolServer>,
+    current: AtomicUsize,
+    stop_gap: usize,
+    cross_check_tips: bool,
+    headers: Option<Mutex<HeaderChain>>,
+    tx_cache: Option<Arc<dyn TxCache>>,
+}
+
+impl ElectrumPool {
+    /// Create a new pool for the servers in `configs`, tried in order
+    ///
+    /// The servers are only connected to when they are first used. The stop gap of the first
+    /// configuration is used for the sync.
+    pub fn new(configs: Vec<ElectrumBlockchainConfig>) -> Result<Self, Error> {
+        let stop_gap = configs
+            .first()
+            .map(|config| config.stop_gap)
+            .ok_or_else(|| Error::Generic("No Electrum servers in the pool".to_string()))?;
+
+        Ok(ElectrumPool {
+            servers: configs
+                .into_iter()
+                .map(|config| PoolServer {
+                    config,
+                    client: Mutex::new(None),
+                })
+                .collect(),
+            current: AtomicUsize::new(0),
+            stop_gap,
+            cross_check_tips: false,
+            headers: None,
+            tx_cache: None,
+        })
+    }
+
+    /// Compare the tips of all the servers before syncing and when asked for the current height,
+    /// see [`ElectrumPool::cross_check_tips`]
+    pub fn with_tip_cross_check(mut self) -> Self {
+        self.cross_check_tips = true;
+        self
+    }
+
+    /// Validate the proof of work of the block headers downloaded from the servers, see
+    /// [`ElectrumBlockchain::with_header_validation`]
+    pub fn with_header_validation(mut self, network: Network) -> Self {
+        self.headers = Some(Mutex::new(HeaderChain::new(network)));
+        self
+    }
+
+    /// Check `tx_cache` before downloading a raw transaction, see
+    /// [`ElectrumBlockchain::with_tx_cache`]
+    pub fn with_tx_cache(mut self, tx_cache: Arc<dyn TxCache>) -> Self {
+        self.tx_cache = Some(tx_cache);
+        self
+    }
+
+    /// Return the minimum fee rate of the transactions relayed by the server in use, see
+    /// [`ElectrumBlockchain::relay_fee`]
+    pub fn relay_fee(&self) -> Result<FeeRate, Error> {
+        self.call(relay_fee)
+    }
+
+    /// Return the URL of the server currently in use
+    pub fn current_server(&self) -> &str {
+        &self.servers[self.current.load(Ordering::SeqCst)].config.url
+    }
+
+    /// Ping all the servers, returning the URL of each of them and the result of the ping
+    ///
+    /// The servers that can't be reached are disconnected. If the server in use is one of them,
+    /// the following healthy server is used from now on.
+    pub fn check_servers(&self) -> Vec<(&str, Result<(), Error>)> {
+        let results = self
+            .servers
+            .iter()
+            .map(|server| {
+                let result = server
+                    .client()
+                    .and_then(|client| Ok(client.ping()?))
+                    .map_err(|e| {
+                        server.disconnect();
+                        e
+                    });
+                (server.config.url.as_str(), result)
+            })
+            .collect::<Vec<_>>();
+
+        let current = self.current.load(Ordering::SeqCst);
+        if let Some(healthy) = (0..results.len())
+            .map(|i| (current + i) % results.len())
+            .find(|index| results[*index].1.is_ok())
+        {
+            self.current.store(healthy, Ordering::SeqCst);
+        }
+
+        results
+    }
+
+    /// Check that all the servers that can be reached agree on the chain, returning its height
+    ///
+    /// The block at the height of the lowest tip reported is requested to every server, and
+    /// compared. An [`Error::Generic`] is returned if a server reports a different block, or if
+    /// none of them can be reached.
+    pub fn cross_check_tips(&self) -> Result<u32, Error> {
+        let mut tips = vec![];
+        for server in &self.servers {
+            match server
+                .client()
+                .and_then(|client| Ok(client.block_headers_subscribe()?))
+            {
+                Ok(tip) => tips.push((server, tip)),
+                Err(e) => {
+                    warn!("Electrum server {} failed: {}", server.config.url, e);
+                    server.disconnect();
+                }
+            }
+        }
+
+        let height = tips
+            .iter()
+            .map(|(_, tip)| tip.height)
+            .min()
+            .ok_or_else(|| Error::Generic("No Electrum server can be reached".to_string()))?;
+
+        let mut expected: Option<BlockHash> = None;
+        for (server, tip) in &tips {
+            let header = match tip.height == height {
+                true => tip.header,
+                false => server.client()?.block_header(height)?,
+            };
+
+            match expected {
+                None => expected = Some(header.block_hash()),
+                Some(hash) if hash != header.block_hash() => {
+                    return Err(Error::Generic(format!(
+                        "Electrum server {} reports a different block at height {}",
+                        server.config.url, height
+                    )))
+                }
+                Some(_) => {}
+            }
+        }
+
+        Ok(height as u32)
+    }
+
+    // Run `f` with the current server, failing over to the following ones on connection errors
+    fn call<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: Fn(&Client) -> Result<T, Error>,
+    {
+        let start = self.current.load(Ordering::SeqCst);
+        let mut last_error = None;
+        for i in 0..self.servers.len() {
+            let index = (start + i) % self.servers.len();
+            let server = &self.servers[index];
+
+            match server.client().and_then(|client| f(&client)) {
+                Err(e @ Error::Electrum(_)) => {
+                    warn!("Electrum server {} failed: {}", server.config.url, e);
+                    server.disconnect();
+                    last_error = Some(e);
+                }
+                result => {
+                    self.current.store(index, Ordering::SeqCst);
+                    return result;
+                }
+            }
+        }
+
+        Err(last_error.expect("The pool has at least one server"))
+    }
+}
+
+impl Blockchain for ElectrumPool {
+    fn get_capabilities(&self) -> HashSet<Capability> {
+        vec![
+            Capability::FullHistory,
+            Capability::GetAnyTx,
+            Capability::AccurateFees,
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    fn setup<D: BatchDatabase, P: Progress>(
+        &self,
+        database: &mut D,
+        progress_update: P,
+    ) -> Result<(), Error> {
+        if self.cross_check_tips {
+            self.cross_check_tips()?;
+        }
+
+        match &self.tx_cache {
+            Some(tx_cache) => CachedSync::new(self, tx_cache.as_ref()).electrum_like_setup(
+                self.stop_gap,
+                self.headers.as_ref(),
+                true,
+                database,
+                progress_update,
+            ),
+            None => self.electrum_like_setup(
+                self.stop_gap,
+                self.headers.as_ref(),
+                true,
+                database,
+                progress_update,
+            ),
+        }
+    }
+
+    fn sync<D: BatchDatabase, P: Progress>(
+        &self,
+        database: &mut D,
+        progress_update: P,
+    ) -> Result<(), Error> {
+        if self.cross_check_tips {
+            self.cross_check_tips()?;
+        }
+
+        match &self.tx_cache {
+            Some(tx_cache) => CachedSync::new(self, tx_cache.as_ref()).electrum_like_setup(
+                self.stop_gap,
+                self.headers.as_ref(),
+                false,
+                database,
+                progress_update,
+            ),
+            None => self.electrum_like_setup(
+                self.stop_gap,
+                self.headers.as_ref(),
+                false,
+                database,
+                progress_update,
+            ),
+        }
+    }
+
+    fn get_tx(&self, txid: &Txid) -> Result<Option<Transaction>, Error> {
+        self.call(|client| Ok(client.transaction_get(txid).map(Option::Some)?))
+    }
+
+    fn get_txs(&self, txids: &[Txid]) -> Result<Vec<Option<Transaction>>, Error> {
+        self.call(|client| batch_get_txs(client, txids, self.stop_gap))
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<(), Error> {
+        self.call(|client| Ok(client.transaction_broadcast(tx).map(|_| ())?))
+    }
+
+    fn get_height(&self) -> Result<u32, Error> {
+        if self.cross_check_tips {
+            return self.cross_check_tips();
+        }
+
+        self.call(|client| {
+            Ok(client
+                .block_headers_subscribe()
+                .map(|data| data.height as u32)?)
+        })
+    }
+
+    fn estimate_fee(&self, target: usize) -> Result<FeeRate, Error> {
+        self.call(|client| estimate_fee_with_floor(client, target))
+    }
+
+    fn get_median_time_past(&self) -> Result<Option<u32>, Error> {
+        let tip = self.get_height()?;
+
+        Ok(Some(self.els_median_time_past(tip, self.headers.as_ref())?))
+    }
+}
+
+impl FeeEstimator for ElectrumPool {
+    fn fee_estimates(&self, targets: &[usize]) -> Result<FeeEstimates, Error> {
+        self.call(|client| batch_fee_estimates(client, targets))
+    }
+}
+
+impl ElectrumLikeSync for ElectrumPool {
+    fn els_batch_script_get_history<'s, I: IntoIterator<Item = &'s Script> + Clone>(
+        &self,
+        scripts: I,
+    ) -> Result<Vec<Vec<ElsGetHistoryRes>>, Error> {
+        self.call(|client| client.els_batch_script_get_history(scripts.clone()))
+    }
+
+    fn els_batch_transaction_get<'s, I: IntoIterator<Item = &'s Txid> + Clone>(
+        &self,
+        txids: I,
+    ) -> Result<Vec<Transaction>, Error> {
+        self.call(|client| client.els_batch_transaction_get(txids.clone()))
+    }
+
+    fn els_batch_block_header<I: IntoIterator<Item = u32> + Clone>(
+        &self,
+        heights: I,
+    ) -> Result<Vec<BlockHeader>, Error> {
+        self.call(|client| client.els_batch_block_header(heights.clone()))
+    }
+
+    fn els_batch_transaction_get_merkle<I: IntoIterator<Item = (Txid, u32)> + Clone>(
+        &self,
+        txs: I,
+    ) -> Result<Vec<MerkleProof>, Error> {
+        self.call(|client| client.els_batch_transaction_get_merkle(txs.clone()))
+    }
+}
+
+// Wrapper that checks a `TxCache` before downloading the raw transactions during a sync
+struct CachedSync<'a, C> {
+    inner: &'a C,
+    tx_cache: &'a dyn TxCache,
+}
+
+impl<'a, C: ElectrumLikeSync> CachedSync<'a, C> {
+    fn new(inner: &'a C, tx_cache: &'a dyn TxCache) -> Self {
+        CachedSync { inner, tx_cache }
+    }
+}
+
+impl<'a, C: ElectrumLikeSync> ElectrumLikeSync for CachedSync<'a, C> {
+    fn els_batch_script_get_history<'s, I: IntoIterator<Item = &'s Script> + Clone>(
+        &self,
+        scripts: I,
+    ) -> Result<Vec<Vec<ElsGetHistoryRes>>, Error> {
+        self.inner.els_batch_script_get_history(scripts)
+    }
+
+    fn els_batch_transaction_get<'s, I: IntoIterator<Item = &'s Txid> + Clone>(
+        &self,
+        txids: I,
+    ) -> Result<Vec<Transaction>, Error> {
+        let txids = txids.into_iter().collect::<Vec<_>>();
+        let mut txs = HashMap::new();
+        let mut to_download = vec![];
+        for txid in &txids {
+            match self.tx_cache.get(txid) {
+                Some(tx) => {
+                    txs.insert(**txid, tx);
+                }
+                None => to_download.push(*txid),
+            }
+        }
+        debug!(
+            "{} transactions found in the cache, {} to download",
+            txs.len(),
+            to_download.len()
+        );
+
+        if !to_download.is_empty() {
+            for tx in self
+                .inner
+                .els_batch_transaction_get(to_download.iter().cloned())?
+            {
+                self.tx_cache.insert(&tx);
+                txs.insert(tx.txid(), tx);
+            }
+        }
+
+        txids
+            .into_iter()
+            .map(|txid| txs.get(txid).cloned().ok_or(Error::TransactionNotFound))
+            .collect()
+    }
+
+    fn els_batch_block_header<I: IntoIterator<Item = u32> + Clone>(
+        &self,
+        heights: I,
+    ) -> Result<Vec<BlockHeader>, Error> {
+        self.inner.els_batch_block_header(heights)
+    }
+
+    fn els_batch_transaction_get_merkle<I: IntoIterator<Item = (Txid, u32)> + Clone>(
+        &self,
+        txs: I,
+    ) -> Result<Vec<MerkleProof>, Error> {
+        self.inner.els_batch_transaction_get_merkle(txs)
+    }
 }
 
 #[cfg(test)]
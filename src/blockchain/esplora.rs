@@ -21,38 +21,68 @@
 //! let blockchain = EsploraBlockchain::new("https://blockstream.info/testnet/api", None, 20);
 //! # Ok::<(), bdk::Error>(())
 //! ```
+//!
+//! Private deployments that require authentication can be reached by adding HTTP headers to
+//! every request, and the requests can be sent through a proxy:
+//!
+//! ```no_run
+//! # use std::collections::HashMap;
+//! # use bdk::blockchain::esplora::EsploraBlockchain;
+//! let mut headers = HashMap::new();
+//! headers.insert("X-API-Key".to_string(), "secret".to_string());
+//!
+//! let blockchain = EsploraBlockchain::new("https://esplora.example.com/api", None, 20)
+//!     .with_http_headers(&headers)?
+//!     .with_proxy("http://127.0.0.1:8080")?;
+//! # Ok::<(), bdk::Error>(())
+//! ```
 
 use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
 
 use bitcoin::consensus::{self, deserialize, serialize};
 use bitcoin::hashes::hex::{FromHex, ToHex};
 use bitcoin::hashes::{sha256, Hash};
-use bitcoin::{BlockHash, BlockHeader, Script, Transaction, Txid};
+use bitcoin::{BlockHash, BlockHeader, Network, Script, Transaction, Txid};
 use futures::stream::{self, FuturesOrdered, StreamExt, TryStreamExt};
 #[allow(unused_imports)]
 use log::{debug, error, info, trace};
-use reqwest::{Client, StatusCode};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
 use serde::Deserialize;
 
 use crate::database::BatchDatabase;
 use crate::error::Error;
+use crate::wallet::time::get_timestamp;
 use crate::wallet::utils::ChunksIterator;
-use crate::FeeRate;
+use crate::{FeeEstimates, FeeRate};
 
 use super::*;
 
-use self::utils::{ElectrumLikeSync, ElsGetHistoryRes};
+use self::spv::{HeaderChain, MerkleProof};
+use self::utils::{
+    save_transaction_details_and_utxos, utxos_deps, ElectrumLikeSync, ElsGetHistoryRes,
+};
 
 const DEFAULT_CONCURRENT_REQUESTS: u8 = 4;
+const DEFAULT_MAX_RETRIES: u8 = 3;
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(500);
+const DEFAULT_FEE_ESTIMATES_MAX_AGE: u64 = 60;
 
 #[derive(Debug)]
-struct UrlClient {
-    url: String,
+pub(crate) struct UrlClient {
+    pub(crate) url: String,
     // We use the async client instead of the blocking one because it automatically uses `fetch`
     // when the target platform is wasm32.
-    client: Client,
-    concurrency: u8,
+    pub(crate) client: Client,
+    concurrency: AtomicU8,
+    max_retries: u8,
+    retry_delay: Duration,
+    http_headers: HeaderMap,
+    basic_auth: Option<(String, Option<String>)>,
 }
 
 /// Structure that implements the logic to sync with Esplora
@@ -61,8 +91,11 @@ struct UrlClient {
 /// See the [`blockchain::esplora`](crate::blockchain::esplora) module for a usage example.
 #[derive(Debug)]
 pub struct EsploraBlockchain {
-    url_client: UrlClient,
+    pub(crate) url_client: UrlClient,
     stop_gap: usize,
+    headers: Option<Mutex<HeaderChain>>,
+    fee_estimates: Mutex<Option<FeeEstimates>>,
+    fee_estimates_max_age: u64,
 }
 
 impl std::convert::From<UrlClient> for EsploraBlockchain {
@@ -70,6 +103,9 @@ impl std::convert::From<UrlClient> for EsploraBlockchain {
         EsploraBlockchain {
             url_client,
             stop_gap: 20,
+            headers: None,
+            fee_estimates: Mutex::new(None),
+            fee_estimates_max_age: DEFAULT_FEE_ESTIMATES_MAX_AGE,
         }
     }
 }
@@ -81,10 +117,312 @@ impl EsploraBlockchain {
             url_client: UrlClient {
                 url: base_url.to_string(),
                 client: Client::new(),
-                concurrency: concurrency.unwrap_or(DEFAULT_CONCURRENT_REQUESTS),
+                concurrency: AtomicU8::new(concurrency.unwrap_or(DEFAULT_CONCURRENT_REQUESTS)),
+                max_retries: DEFAULT_MAX_RETRIES,
+                retry_delay: DEFAULT_RETRY_DELAY,
+                http_headers: HeaderMap::new(),
+                basic_auth: None,
             },
             stop_gap,
+            headers: None,
+            fee_estimates: Mutex::new(None),
+            fee_estimates_max_age: DEFAULT_FEE_ESTIMATES_MAX_AGE,
+        }
+    }
+
+    /// Validate the proof of work of the block headers downloaded from the server
+    ///
+    /// The headers are kept in memory and checked to connect to each other, and the merkle proofs
+    /// of the newly confirmed transactions are verified against them. See the
+    /// [`spv`](crate::blockchain::spv) module for the details.
+    pub fn with_header_validation(mut self, network: Network) -> Self {
+        self.headers = Some(Mutex::new(HeaderChain::new(network)));
+        self
+    }
+
+    /// Retry the requests up to `max_retries` times when the server is rate limiting them
+    /// (HTTP 429), when it's temporarily unavailable (HTTP 5xx) or when the connection fails
+    ///
+    /// The first retry waits `retry_delay`, and the delay doubles at every following one. By
+    /// default the requests are retried 3 times, starting after 500 milliseconds. On wasm32 the
+    /// connection errors are never retried, because they can't be told apart from the others.
+    ///
+    /// The delays are waited with the timer of tokio: with the `async-interface` feature the
+    /// requests must be run within a tokio runtime, except on wasm32.
+    pub fn with_retry(mut self, max_retries: u8, retry_delay: Duration) -> Self {
+        self.url_client.max_retries = max_retries;
+        self.url_client.retry_delay = retry_delay;
+        self
+    }
+
+    /// Add the HTTP headers in `headers` to every request sent to the server
+    ///
+    /// This can be used to authenticate to private deployments with an API key. The headers are
+    /// added to the ones set by previous calls.
+    pub fn with_http_headers(mut self, headers: &HashMap<String, String>) -> Result<Self, Error> {
+        for (name, value) in headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| EsploraError::InvalidHttpHeader(name.clone()))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|_| EsploraError::InvalidHttpHeader(name.to_string()))?;
+            self.url_client.http_headers.insert(name, value);
+        }
+
+        Ok(self)
+    }
+
+    /// Authenticate to the server with HTTP basic authentication
+    pub fn with_basic_auth(mut self, username: &str, password: Option<&str>) -> Self {
+        self.url_client.basic_auth =
+            Some((username.to_string(), password.map(ToString::to_string)));
+        self
+    }
+
+    /// Send the requests through the proxy at `proxy_url`
+    ///
+    /// HTTP and HTTPS proxies are always supported, SOCKS5 proxies (eg. `socks5h://127.0.0.1:9050`
+    /// to reach a Tor hidden service) require the `esplora-socks` feature. The proxy can't be set
+    /// on wasm32, where the requests are sent by the browser.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_proxy(mut self, proxy_url: &str) -> Result<Self, Error> {
+        let proxy = reqwest::Proxy::all(proxy_url).map_err(EsploraError::from)?;
+        self.url_client.client = Client::builder()
+            .proxy(proxy)
+            .build()
+            .map_err(EsploraError::from)?;
+
+        Ok(self)
+    }
+
+    /// Change the number of parallel requests sent to the server
+    ///
+    /// This can be called while the wallet is syncing, for example to throttle the requests when
+    /// the server starts rate limiting them: the new value is used from the next batch of requests.
+    pub fn set_concurrency(&self, concurrency: u8) {
+        self.url_client
+            .concurrency
+            .store(concurrency.max(1), Ordering::SeqCst);
+    }
+
+    /// Return the number of parallel requests sent to the server
+    pub fn concurrency(&self) -> u8 {
+        self.url_client.concurrency.load(Ordering::SeqCst)
+    }
+
+    /// Reuse the fee estimates downloaded from the server for up to `max_age`
+    ///
+    /// Esplora returns the estimates for all the targets at once, so they are downloaded once and
+    /// shared by [`Blockchain::estimate_fee`] and [`FeeEstimator::fee_estimates`] until they
+    /// become stale. The default is one minute, a zero `max_age` disables the cache.
+    pub fn with_fee_estimates_max_age(mut self, max_age: Duration) -> Self {
+        self.fee_estimates_max_age = max_age.as_secs();
+        self
+    }
+
+    /// Return the status of the transaction with `txid`, as seen by the server
+    ///
+    /// Transactions unknown to the server are reported as [`TxStatusEvent::Evicted`].
+    #[maybe_async]
+    pub fn get_tx_status(&self, txid: &Txid) -> Result<TxStatusEvent, Error> {
+        let status = match await_or_block!(self.url_client._get_tx_status(txid))? {
+            None => return Ok(TxStatusEvent::Evicted),
+            Some(status) => status,
+        };
+
+        match status.block_height {
+            Some(height) if status.confirmed => {
+                let tip = await_or_block!(self.url_client._get_height())?;
+                Ok(TxStatusEvent::Confirmed {
+                    height,
+                    confirmations: tip.saturating_sub(height) + 1,
+                })
+            }
+            _ => Ok(TxStatusEvent::Unconfirmed),
+        }
+    }
+
+    /// Broadcast a transaction, then poll its status every `poll_interval` until it reaches
+    /// `confirmations` confirmations or it's evicted from the mempool of the server
+    ///
+    /// `on_status` is called with the first status and then every time it changes, including
+    /// every new confirmation and a transaction going back to the mempool after a reorg. Returns
+    /// the last status, which is either [`TxStatusEvent::Evicted`] or the one that reached the
+    /// requested depth. With zero `confirmations` this returns as soon as the transaction is in
+    /// the mempool.
+    #[maybe_async]
+    pub fn broadcast_and_wait<F: FnMut(TxStatusEvent)>(
+        &self,
+        tx: &Transaction,
+        confirmations: u32,
+        poll_interval: Duration,
+        mut on_status: F,
+    ) -> Result<TxStatusEvent, Error> {
+        let txid = tx.txid();
+        await_or_block!(self.url_client._broadcast(tx))?;
+
+        let mut last_status = None;
+        loop {
+            let status = maybe_await!(self.get_tx_status(&txid))?;
+            if last_status != Some(status) {
+                on_status(status);
+                last_status = Some(status);
+            }
+
+            match status {
+                TxStatusEvent::Evicted | TxStatusEvent::Replaced(_) => return Ok(status),
+                TxStatusEvent::Unconfirmed if confirmations == 0 => return Ok(status),
+                TxStatusEvent::Confirmed {
+                    confirmations: current,
+                    ..
+                } if current >= confirmations => return Ok(status),
+                _ => {}
+            }
+
+            await_or_block!(sleep(poll_interval));
+        }
+    }
+
+    /// Refresh the spent status of the wallet's UTXOs, without downloading the history of its
+    /// script pubkeys
+    ///
+    /// The server is asked which transaction spends every output that is still unspent in
+    /// `database`: the spending transactions are downloaded and saved, and the outputs they spend
+    /// are removed from the UTXOs. This is much cheaper than [`Blockchain::sync`], but it doesn't
+    /// find new incoming transactions, so it's meant to be run between regular syncs, eg. right
+    /// before creating a transaction.
+    ///
+    /// Returns the number of spending transactions found.
+    #[maybe_async]
+    pub fn refresh_utxos<D: BatchDatabase>(&self, database: &mut D) -> Result<usize, Error> {
+        let utxos = database.iter_utxos()?;
+        let txids = utxos
+            .iter()
+            .map(|utxo| utxo.outpoint.txid)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        let future = async {
+            let mut outspends = HashMap::new();
+            for chunk in txids.chunks(self.url_client.concurrency()) {
+                let futs: FuturesOrdered<_> = chunk
+                    .iter()
+                    .map(|txid| self.url_client._get_outspends(txid))
+                    .collect();
+                let partial_results: Vec<Vec<EsploraOutspend>> = futs.try_collect().await?;
+                outspends.extend(chunk.iter().cloned().zip(partial_results));
+            }
+            Ok::<_, EsploraError>(outspends)
+        };
+        let outspends = await_or_block!(future)?;
+
+        // txid -> height of the transactions that spend our utxos
+        let mut spending: HashMap<Txid, Option<u32>> = HashMap::new();
+        for utxo in utxos.iter() {
+            let outspend = outspends
+                .get(&utxo.outpoint.txid)
+                .and_then(|outspends| outspends.get(utxo.outpoint.vout as usize));
+            if let Some(EsploraOutspend {
+                spent: true,
+                txid: Some(txid),
+                status,
+            }) = outspend
+            {
+                let height = status
+                    .as_ref()
+                    .filter(|status| status.confirmed)
+                    .and_then(|status| status.block_height);
+                spending.insert(*txid, height);
+            }
+        }
+        debug!("{} transactions spend our utxos", spending.len());
+        if spending.is_empty() {
+            return Ok(0);
+        }
+
+        let chunk_size = self.url_client.concurrency();
+        let txs_raw_in_db: HashMap<Txid, Transaction> = database
+            .iter_raw_txs()?
+            .into_iter()
+            .map(|tx| (tx.txid(), tx))
+            .collect();
+        let utxos_deps = utxos_deps(database, &txs_raw_in_db)?;
+        let spending_txids = spending.keys().cloned().collect::<HashSet<_>>();
+        maybe_await!(self.url_client.download_and_save_needed_raw_txs(
+            &spending_txids,
+            &txs_raw_in_db,
+            chunk_size,
+            database
+        ))?;
+        let timestamps = maybe_await!(self.url_client.download_needed_headers(
+            &spending,
+            &HashMap::new(),
+            chunk_size,
+            self.headers.as_ref()
+        ))?;
+        if let Some(headers) = self.headers.as_ref() {
+            let txid_height = spending
+                .iter()
+                .filter_map(|(txid, height)| height.map(|height| (*txid, height)))
+                .collect();
+            maybe_await!(self.url_client.download_and_verify_merkle_proofs(
+                txid_height,
+                chunk_size,
+                headers
+            ))?;
+        }
+
+        let mut batch = database.begin_batch();
+        for (txid, height) in spending.iter() {
+            save_transaction_details_and_utxos(
+                txid,
+                database,
+                timestamps.get(txid).cloned(),
+                *height,
+                &mut batch,
+                &utxos_deps,
+            )?;
+            let tx = database
+                .get_raw_tx(txid)?
+                .ok_or(Error::TransactionNotFound)?;
+            for input in tx.input.iter() {
+                batch.del_utxo(&input.previous_output)?;
+            }
+        }
+        database.commit_batch(batch)?;
+
+        Ok(spending.len())
+    }
+
+    /// Return the validated block headers, if header validation is enabled
+    pub fn headers(&self) -> Option<HeaderChain> {
+        self.headers
+            .as_ref()
+            .map(|headers| headers.lock().unwrap().clone())
+    }
+
+    #[maybe_async]
+    fn cached_fee_estimates(&self) -> Result<FeeEstimates, Error> {
+        let now = get_timestamp();
+        if let Some(estimates) = &*self.fee_estimates.lock().unwrap() {
+            if self.fee_estimates_max_age > 0
+                && !estimates.is_stale(now, self.fee_estimates_max_age)
+            {
+                return Ok(estimates.clone());
+            }
+        }
+
+        let response = await_or_block!(self.url_client._get_fee_estimates())?;
+        let mut estimates = FeeEstimates::new(now);
+        for (target, rate) in response {
+            let target = target
+                .parse::<usize>()
+                .map_err(|e| Error::Generic(e.to_string()))?;
+            estimates.insert(target, FeeRate::from_sat_per_vb(rate as f32));
         }
+        *self.fee_estimates.lock().unwrap() = Some(estimates.clone());
+
+        Ok(estimates)
     }
 }
 
@@ -105,15 +443,50 @@ impl Blockchain for EsploraBlockchain {
         database: &mut D,
         progress_update: P,
     ) -> Result<(), Error> {
-        maybe_await!(self
-            .url_client
-            .electrum_like_setup(self.stop_gap, database, progress_update))
+        maybe_await!(self.url_client.electrum_like_setup(
+            self.stop_gap,
+            self.headers.as_ref(),
+            true,
+            database,
+            progress_update
+        ))
+    }
+
+    fn sync<D: BatchDatabase, P: Progress>(
+        &self,
+        database: &mut D,
+        progress_update: P,
+    ) -> Result<(), Error> {
+        maybe_await!(self.url_client.electrum_like_setup(
+            self.stop_gap,
+            self.headers.as_ref(),
+            false,
+            database,
+            progress_update
+        ))
     }
 
     fn get_tx(&self, txid: &Txid) -> Result<Option<Transaction>, Error> {
         Ok(await_or_block!(self.url_client._get_tx(txid))?)
     }
 
+    fn get_txs(&self, txids: &[Txid]) -> Result<Vec<Option<Transaction>>, Error> {
+        let future = async {
+            let mut txs = Vec::with_capacity(txids.len());
+            for chunk in txids.chunks(self.url_client.concurrency()) {
+                let futs: FuturesOrdered<_> = chunk
+                    .iter()
+                    .map(|txid| self.url_client._get_tx(txid))
+                    .collect();
+                let partial_results: Vec<Option<Transaction>> = futs.try_collect().await?;
+                txs.extend(partial_results);
+            }
+            Ok::<_, EsploraError>(txs)
+        };
+
+        Ok(await_or_block!(future)?)
+    }
+
     fn broadcast(&self, tx: &Transaction) -> Result<(), Error> {
         Ok(await_or_block!(self.url_client._broadcast(tx))?)
     }
@@ -123,20 +496,26 @@ impl Blockchain for EsploraBlockchain {
     }
 
     fn estimate_fee(&self, target: usize) -> Result<FeeRate, Error> {
-        let estimates = await_or_block!(self.url_client._get_fee_estimates())?;
+        let estimates = maybe_await!(self.cached_fee_estimates())?;
 
-        let fee_val = estimates
-            .into_iter()
-            .map(|(k, v)| Ok::<_, std::num::ParseIntError>((k.parse::<usize>()?, v)))
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|e| Error::Generic(e.to_string()))?
-            .into_iter()
-            .take_while(|(k, _)| k <= &target)
-            .map(|(_, v)| v)
-            .last()
-            .unwrap_or(1.0);
+        Ok(estimates
+            .get(target)
+            .unwrap_or_else(FeeRate::default_min_relay_fee))
+    }
+
+    fn get_median_time_past(&self) -> Result<Option<u32>, Error> {
+        let tip = await_or_block!(self.url_client._get_height())?;
 
-        Ok(FeeRate::from_sat_per_vb(fee_val as f32))
+        Ok(Some(maybe_await!(self
+            .url_client
+            .els_median_time_past(tip, self.headers.as_ref()))?))
+    }
+}
+
+#[maybe_async]
+impl FeeEstimator for EsploraBlockchain {
+    fn fee_estimates(&self, _targets: &[usize]) -> Result<FeeEstimates, Error> {
+        maybe_await!(self.cached_fee_estimates())
     }
 }
 
@@ -145,11 +524,52 @@ impl UrlClient {
         sha256::Hash::hash(script.as_bytes()).into_inner().to_hex()
     }
 
+    fn concurrency(&self) -> usize {
+        self.concurrency.load(Ordering::SeqCst).max(1) as usize
+    }
+
+    // Add the headers and the credentials configured by the user to `request`
+    fn authenticate(&self, request: RequestBuilder) -> RequestBuilder {
+        let request = request.headers(self.http_headers.clone());
+        match &self.basic_auth {
+            Some((username, password)) => request.basic_auth(username, password.as_ref()),
+            None => request,
+        }
+    }
+
+    // Send the request built by `request`, retrying it with an exponential backoff on the errors
+    // that are likely to be temporary
+    pub(crate) async fn send_with_retry<F>(&self, request: F) -> Result<Response, EsploraError>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        let mut delay = self.retry_delay;
+        let mut attempts = 0;
+        loop {
+            let can_retry = attempts < self.max_retries;
+            match self.authenticate(request()).send().await {
+                Ok(resp) if can_retry && is_retryable_status(resp.status()) => {
+                    debug!(
+                        "Esplora returned {}, retrying in {:?}",
+                        resp.status(),
+                        delay
+                    );
+                }
+                Err(e) if can_retry && is_transient_error(&e) => {
+                    debug!("Esplora request failed: {}, retrying in {:?}", e, delay);
+                }
+                result => return Ok(result?),
+            }
+
+            sleep(delay).await;
+            delay *= 2;
+            attempts += 1;
+        }
+    }
+
     async fn _get_tx(&self, txid: &Txid) -> Result<Option<Transaction>, EsploraError> {
         let resp = self
-            .client
-            .get(&format!("{}/tx/{}/raw", self.url, txid))
-            .send()
+            .send_with_retry(|| self.client.get(&format!("{}/tx/{}/raw", self.url, txid)))
             .await?;
 
         if let StatusCode::NOT_FOUND = resp.status() {
@@ -169,9 +589,10 @@ impl UrlClient {
 
     async fn _get_header(&self, block_height: u32) -> Result<BlockHeader, EsploraError> {
         let resp = self
-            .client
-            .get(&format!("{}/block-height/{}", self.url, block_height))
-            .send()
+            .send_with_retry(|| {
+                self.client
+                    .get(&format!("{}/block-height/{}", self.url, block_height))
+            })
             .await?;
 
         if let StatusCode::NOT_FOUND = resp.status() {
@@ -182,9 +603,10 @@ impl UrlClient {
             .map_err(|_| EsploraError::HeaderHeightNotFound(block_height))?;
 
         let resp = self
-            .client
-            .get(&format!("{}/block/{}/header", self.url, hash))
-            .send()
+            .send_with_retry(|| {
+                self.client
+                    .get(&format!("{}/block/{}/header", self.url, hash))
+            })
             .await?;
 
         let header = deserialize(&Vec::from_hex(&resp.text().await?)?)?;
@@ -192,22 +614,61 @@ impl UrlClient {
         Ok(header)
     }
 
+    async fn _get_merkle_proof(&self, txid: &Txid) -> Result<MerkleProof, EsploraError> {
+        let resp = self
+            .send_with_retry(|| {
+                self.client
+                    .get(&format!("{}/tx/{}/merkle-proof", self.url, txid))
+            })
+            .await?;
+
+        if let StatusCode::NOT_FOUND = resp.status() {
+            return Err(EsploraError::TransactionNotFound(*txid));
+        }
+
+        Ok(resp.error_for_status()?.json::<MerkleProof>().await?)
+    }
+
     async fn _broadcast(&self, transaction: &Transaction) -> Result<(), EsploraError> {
-        self.client
-            .post(&format!("{}/tx", self.url))
-            .body(serialize(transaction).to_hex())
-            .send()
-            .await?
-            .error_for_status()?;
+        let body = serialize(transaction).to_hex();
+        self.send_with_retry(|| {
+            self.client
+                .post(&format!("{}/tx", self.url))
+                .body(body.clone())
+        })
+        .await?
+        .error_for_status()?;
 
         Ok(())
     }
 
+    async fn _get_tx_status(&self, txid: &Txid) -> Result<Option<EsploraTxStatus>, EsploraError> {
+        let resp = self
+            .send_with_retry(|| self.client.get(&format!("{}/tx/{}/status", self.url, txid)))
+            .await?;
+
+        if let StatusCode::NOT_FOUND = resp.status() {
+            return Ok(None);
+        }
+
+        Ok(Some(resp.error_for_status()?.json().await?))
+    }
+
+    async fn _get_outspends(&self, txid: &Txid) -> Result<Vec<EsploraOutspend>, EsploraError> {
+        Ok(self
+            .send_with_retry(|| {
+                self.client
+                    .get(&format!("{}/tx/{}/outspends", self.url, txid))
+            })
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
     async fn _get_height(&self) -> Result<u32, EsploraError> {
         let req = self
-            .client
-            .get(&format!("{}/blocks/tip/height", self.url))
-            .send()
+            .send_with_retry(|| self.client.get(&format!("{}/blocks/tip/height", self.url)))
             .await?;
 
         Ok(req.error_for_status()?.text().await?.parse()?)
@@ -222,21 +683,21 @@ impl UrlClient {
 
         // Add the unconfirmed transactions first
         result.extend(
-            self.client
-                .get(&format!(
+            self.send_with_retry(|| {
+                self.client.get(&format!(
                     "{}/scripthash/{}/txs/mempool",
                     self.url, scripthash
                 ))
-                .send()
-                .await?
-                .error_for_status()?
-                .json::<Vec<EsploraGetHistory>>()
-                .await?
-                .into_iter()
-                .map(|x| ElsGetHistoryRes {
-                    tx_hash: x.txid,
-                    height: x.status.block_height.unwrap_or(0) as i32,
-                }),
+            })
+            .await?
+            .error_for_status()?
+            .json::<Vec<EsploraGetHistory>>()
+            .await?
+            .into_iter()
+            .map(|x| ElsGetHistoryRes {
+                tx_hash: x.txid,
+                height: x.status.block_height.unwrap_or(0) as i32,
+            }),
         );
 
         debug!(
@@ -250,12 +711,12 @@ impl UrlClient {
         let mut last_txid = String::new();
         loop {
             let response = self
-                .client
-                .get(&format!(
-                    "{}/scripthash/{}/txs/chain/{}",
-                    self.url, scripthash, last_txid
-                ))
-                .send()
+                .send_with_retry(|| {
+                    self.client.get(&format!(
+                        "{}/scripthash/{}/txs/chain/{}",
+                        self.url, scripthash, last_txid
+                    ))
+                })
                 .await?
                 .error_for_status()?
                 .json::<Vec<EsploraGetHistory>>()
@@ -282,9 +743,7 @@ impl UrlClient {
 
     async fn _get_fee_estimates(&self) -> Result<HashMap<String, f64>, EsploraError> {
         Ok(self
-            .client
-            .get(&format!("{}/fee-estimates", self.url,))
-            .send()
+            .send_with_retry(|| self.client.get(&format!("{}/fee-estimates", self.url,)))
             .await?
             .error_for_status()?
             .json::<HashMap<String, f64>>()
@@ -292,6 +751,48 @@ impl UrlClient {
     }
 }
 
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn is_transient_error(e: &reqwest::Error) -> bool {
+    e.is_connect() || e.is_timeout()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn is_transient_error(_e: &reqwest::Error) -> bool {
+    false
+}
+
+// Sleep with the timer of the tokio runtime, which is enabled by `await_or_block!` in the blocking
+// interface and must be provided by the caller with `async-interface`
+#[cfg(not(target_arch = "wasm32"))]
+async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await;
+}
+
+// Sleep with the `setTimeout` of the JavaScript runtime, available both in the browsers and in
+// node.js
+#[cfg(target_arch = "wasm32")]
+async fn sleep(duration: Duration) {
+    use wasm_bindgen::{JsCast, JsValue};
+
+    let promise = js_sys::Promise::new(&mut |resolve, _reject| {
+        let set_timeout = js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("setTimeout"))
+            .ok()
+            .and_then(|set_timeout| set_timeout.dyn_into::<js_sys::Function>().ok());
+        let delay = JsValue::from_f64(duration.as_millis() as f64);
+        let scheduled = set_timeout
+            .map(|set_timeout| set_timeout.call2(&JsValue::NULL, &resolve, &delay).is_ok())
+            .unwrap_or(false);
+        if !scheduled {
+            let _ = resolve.call0(&JsValue::NULL);
+        }
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
+
 #[maybe_async]
 impl ElectrumLikeSync for UrlClient {
     fn els_batch_script_get_history<'s, I: IntoIterator<Item = &'s Script>>(
@@ -300,7 +801,7 @@ impl ElectrumLikeSync for UrlClient {
     ) -> Result<Vec<Vec<ElsGetHistoryRes>>, Error> {
         let future = async {
             let mut results = vec![];
-            for chunk in ChunksIterator::new(scripts.into_iter(), self.concurrency as usize) {
+            for chunk in ChunksIterator::new(scripts.into_iter(), self.concurrency()) {
                 let mut futs = FuturesOrdered::new();
                 for script in chunk {
                     futs.push(self._script_get_history(&script));
@@ -320,7 +821,7 @@ impl ElectrumLikeSync for UrlClient {
     ) -> Result<Vec<Transaction>, Error> {
         let future = async {
             let mut results = vec![];
-            for chunk in ChunksIterator::new(txids.into_iter(), self.concurrency as usize) {
+            for chunk in ChunksIterator::new(txids.into_iter(), self.concurrency()) {
                 let mut futs = FuturesOrdered::new();
                 for txid in chunk {
                     futs.push(self._get_tx_no_opt(&txid));
@@ -340,7 +841,7 @@ impl ElectrumLikeSync for UrlClient {
     ) -> Result<Vec<BlockHeader>, Error> {
         let future = async {
             let mut results = vec![];
-            for chunk in ChunksIterator::new(heights.into_iter(), self.concurrency as usize) {
+            for chunk in ChunksIterator::new(heights.into_iter(), self.concurrency()) {
                 let mut futs = FuturesOrdered::new();
                 for height in chunk {
                     futs.push(self._get_header(height));
@@ -353,6 +854,26 @@ impl ElectrumLikeSync for UrlClient {
 
         await_or_block!(future)
     }
+
+    fn els_batch_transaction_get_merkle<I: IntoIterator<Item = (Txid, u32)>>(
+        &self,
+        txs: I,
+    ) -> Result<Vec<MerkleProof>, Error> {
+        let future = async {
+            let mut results = vec![];
+            for chunk in ChunksIterator::new(txs.into_iter(), self.concurrency()) {
+                let mut futs = FuturesOrdered::new();
+                for (txid, _) in chunk.iter() {
+                    futs.push(self._get_merkle_proof(txid));
+                }
+                let partial_results: Vec<MerkleProof> = futs.try_collect().await?;
+                results.extend(partial_results);
+            }
+            Ok(stream::iter(results).collect().await)
+        };
+
+        await_or_block!(future)
+    }
 }
 
 #[derive(Deserialize)]
@@ -366,6 +887,41 @@ struct EsploraGetHistory {
     status: EsploraGetHistoryStatus,
 }
 
+#[derive(Deserialize)]
+struct EsploraTxStatus {
+    confirmed: bool,
+    block_height: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct EsploraOutspend {
+    spent: bool,
+    txid: Option<Txid>,
+    status: Option<EsploraTxStatus>,
+}
+
+/// Status of a transaction, as returned by [`EsploraBlockchain::get_tx_status`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxStatusEvent {
+    /// The transaction is in the mempool
+    Unconfirmed,
+    /// The transaction is confirmed
+    Confirmed {
+        /// Height of the block that contains the transaction
+        height: u32,
+        /// Number of confirmations, including the block that contains the transaction
+        confirmations: u32,
+    },
+    /// The transaction is unknown to the server: it has been evicted from the mempool, replaced
+    /// or it has never been broadcast
+    Evicted,
+    /// The transaction has been replaced by the transaction with this txid
+    ///
+    /// Only reported by backends that keep track of the replacements, like
+    /// [`MempoolSpaceBlockchain`](crate::blockchain::mempool_space::MempoolSpaceBlockchain).
+    Replaced(Txid),
+}
+
 /// Configuration for an [`EsploraBlockchain`]
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone, PartialEq)]
 pub struct EsploraBlockchainConfig {
@@ -377,17 +933,46 @@ pub struct EsploraBlockchainConfig {
     pub concurrency: Option<u8>,
     /// Stop searching addresses for transactions after finding an unused gap of this length
     pub stop_gap: usize,
+    /// Number of times the requests are retried on temporary errors (default: 3), see
+    /// [`EsploraBlockchain::with_retry`]
+    pub max_retries: Option<u8>,
+    /// Proxy used to reach the esplora service, see [`EsploraBlockchain::with_proxy`]
+    pub proxy: Option<String>,
+    /// HTTP headers added to every request, eg. to send an API key
+    pub http_headers: Option<HashMap<String, String>>,
 }
 
 impl ConfigurableBlockchain for EsploraBlockchain {
     type Config = EsploraBlockchainConfig;
 
     fn from_config(config: &Self::Config) -> Result<Self, Error> {
-        Ok(EsploraBlockchain::new(
+        let mut blockchain = EsploraBlockchain::new(
             config.base_url.as_str(),
             config.concurrency,
             config.stop_gap,
-        ))
+        )
+        .with_retry(
+            config.max_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+            DEFAULT_RETRY_DELAY,
+        );
+        if let Some(headers) = &config.http_headers {
+            blockchain = blockchain.with_http_headers(headers)?;
+        }
+        if let Some(proxy) = &config.proxy {
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                blockchain = blockchain.with_proxy(proxy)?;
+            }
+            #[cfg(target_arch = "wasm32")]
+            {
+                return Err(Error::Generic(format!(
+                    "Can't use the proxy {} on wasm32",
+                    proxy
+                )));
+            }
+        }
+
+        Ok(blockchain)
     }
 }
 
@@ -409,6 +994,8 @@ pub enum EsploraError {
     HeaderHeightNotFound(u32),
     /// Header hash not found
     HeaderHashNotFound(BlockHash),
+    /// Invalid HTTP header name or value
+    InvalidHttpHeader(String),
 }
 
 impl fmt::Display for EsploraError {
@@ -0,0 +1,429 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! mempool.space
+//!
+//! This module defines [`MempoolSpaceBlockchain`], an [`EsploraBlockchain`] that also understands
+//! the endpoints specific to [mempool.space](https://mempool.space/docs/api/rest) and its
+//! self-hosted instances:
+//!
+//! * the fee estimates are based on the recommended fees and on the projected blocks of the
+//!   mempool, instead of the estimates of Bitcoin Core;
+//! * [`MempoolSpaceBlockchain::get_tx_status`] tells apart a replaced transaction from an evicted
+//!   one.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use bdk::blockchain::mempool_space::MempoolSpaceBlockchain;
+//! let blockchain = MempoolSpaceBlockchain::new("https://mempool.space/testnet/api", None, 20);
+//! let fees = blockchain.recommended_fees()?;
+//! println!("Next block: {} sat/vB", fees.fastest.as_sat_vb());
+//! # Ok::<(), bdk::Error>(())
+//! ```
+
+use std::collections::HashSet;
+use std::ops::Deref;
+
+use bitcoin::{Transaction, Txid};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use super::esplora::{EsploraBlockchain, EsploraError, TxStatusEvent, UrlClient};
+use super::*;
+use crate::database::BatchDatabase;
+use crate::error::Error;
+use crate::wallet::time::get_timestamp;
+use crate::{FeeEstimates, FeeRate};
+
+/// Fee rates recommended by mempool.space
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecommendedFees {
+    /// Fee rate to confirm in the next block
+    pub fastest: FeeRate,
+    /// Fee rate to confirm within half an hour
+    pub half_hour: FeeRate,
+    /// Fee rate to confirm within an hour
+    pub hour: FeeRate,
+    /// Fee rate to confirm eventually, without overpaying
+    pub economy: FeeRate,
+    /// Minimum fee rate accepted in the mempool
+    pub minimum: FeeRate,
+}
+
+impl RecommendedFees {
+    // Fee rate to confirm within `target` blocks, never below the mempool minimum
+    fn for_target(&self, target: usize) -> FeeRate {
+        let fee_rate = match target {
+            0..=1 => self.fastest,
+            2..=3 => self.half_hour,
+            4..=6 => self.hour,
+            _ => self.economy,
+        };
+
+        if fee_rate.as_sat_vb() < self.minimum.as_sat_vb() {
+            self.minimum
+        } else {
+            fee_rate
+        }
+    }
+}
+
+/// Block that would be mined from the current mempool, as projected by mempool.space
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProjectedBlock {
+    /// Virtual size of the block
+    pub vsize: u64,
+    /// Number of transactions in the block
+    pub tx_count: u64,
+    /// Sum of the fees of the transactions in the block, in satoshis
+    pub total_fees: u64,
+    /// Median fee rate of the transactions in the block
+    pub median_fee: FeeRate,
+    /// Fee rates of the transactions in the block, from the lowest to the highest
+    pub fee_range: Vec<FeeRate>,
+}
+
+/// Replacements of a transaction, as known by mempool.space
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RbfStatus {
+    /// Most recent transaction that replaced this one, directly or through other replacements
+    pub replaced_by: Option<Txid>,
+    /// Transactions directly replaced by this one
+    pub replaces: Vec<Txid>,
+}
+
+impl RbfStatus {
+    fn from_response(txid: &Txid, response: MempoolRbfResponse) -> Self {
+        RbfStatus {
+            // the root of the tree of replacements is the most recent transaction
+            replaced_by: response
+                .replacements
+                .map(|root| root.tx.txid)
+                .filter(|root| root != txid),
+            replaces: response.replaces.unwrap_or_default(),
+        }
+    }
+}
+
+/// [`EsploraBlockchain`] that also uses the endpoints specific to mempool.space
+///
+/// All the methods of [`EsploraBlockchain`] are available through [`Deref`], and an instance
+/// configured with its builder methods can be converted with [`From`].
+///
+/// For a usage example see [this module](crate::blockchain::mempool_space)'s documentation.
+#[derive(Debug)]
+pub struct MempoolSpaceBlockchain {
+    esplora: EsploraBlockchain,
+}
+
+impl std::convert::From<EsploraBlockchain> for MempoolSpaceBlockchain {
+    fn from(esplora: EsploraBlockchain) -> Self {
+        MempoolSpaceBlockchain { esplora }
+    }
+}
+
+impl Deref for MempoolSpaceBlockchain {
+    type Target = EsploraBlockchain;
+
+    fn deref(&self) -> &Self::Target {
+        &self.esplora
+    }
+}
+
+impl MempoolSpaceBlockchain {
+    /// Create a new instance of the client from a base URL, eg. `https://mempool.space/api`
+    pub fn new(base_url: &str, concurrency: Option<u8>, stop_gap: usize) -> Self {
+        EsploraBlockchain::new(base_url, concurrency, stop_gap).into()
+    }
+
+    /// Return the fee rates recommended by the server
+    #[maybe_async]
+    pub fn recommended_fees(&self) -> Result<RecommendedFees, Error> {
+        let fees: MempoolRecommendedFees =
+            await_or_block!(get_json(&self.esplora.url_client, "/v1/fees/recommended"))?;
+
+        Ok(RecommendedFees {
+            fastest: FeeRate::from_sat_per_vb(fees.fastest_fee),
+            half_hour: FeeRate::from_sat_per_vb(fees.half_hour_fee),
+            hour: FeeRate::from_sat_per_vb(fees.hour_fee),
+            economy: FeeRate::from_sat_per_vb(fees.economy_fee),
+            minimum: FeeRate::from_sat_per_vb(fees.minimum_fee),
+        })
+    }
+
+    /// Return the blocks that would be mined from the current mempool, starting from the next one
+    ///
+    /// The last block contains all the remaining transactions, so it can be larger than the
+    /// maximum block size.
+    #[maybe_async]
+    pub fn projected_blocks(&self) -> Result<Vec<ProjectedBlock>, Error> {
+        let blocks: Vec<MempoolProjectedBlock> = await_or_block!(get_json(
+            &self.esplora.url_client,
+            "/v1/fees/mempool-blocks"
+        ))?;
+
+        Ok(blocks
+            .into_iter()
+            .map(|block| ProjectedBlock {
+                vsize: block.block_v_size as u64,
+                tx_count: block.n_tx,
+                total_fees: block.total_fees,
+                median_fee: FeeRate::from_sat_per_vb(block.median_fee),
+                fee_range: block
+                    .fee_range
+                    .into_iter()
+                    .map(FeeRate::from_sat_per_vb)
+                    .collect(),
+            })
+            .collect())
+    }
+
+    /// Return the fee histogram of the mempool
+    ///
+    /// Every entry contains a fee rate and the virtual size of the transactions paying at least
+    /// that fee rate, but less than the fee rate of the previous entry. The entries are sorted
+    /// from the highest fee rate to the lowest.
+    #[maybe_async]
+    pub fn fee_histogram(&self) -> Result<Vec<(FeeRate, u64)>, Error> {
+        let mempool: MempoolInfo = await_or_block!(get_json(&self.esplora.url_client, "/mempool"))?;
+
+        Ok(mempool
+            .fee_histogram
+            .into_iter()
+            .map(|(fee_rate, vsize)| (FeeRate::from_sat_per_vb(fee_rate), vsize as u64))
+            .collect())
+    }
+
+    /// Return the replacements of the transaction with `txid`
+    #[maybe_async]
+    pub fn get_rbf_status(&self, txid: &Txid) -> Result<RbfStatus, Error> {
+        let url_client = &self.esplora.url_client;
+        let future = async {
+            let resp = url_client
+                .send_with_retry(|| {
+                    url_client
+                        .client
+                        .get(&format!("{}/v1/tx/{}/rbf", url_client.url, txid))
+                })
+                .await?;
+
+            if let StatusCode::NOT_FOUND = resp.status() {
+                return Ok(None);
+            }
+
+            Ok::<_, EsploraError>(Some(resp.error_for_status()?.json().await?))
+        };
+
+        Ok(match await_or_block!(future)? {
+            Some(response) => RbfStatus::from_response(txid, response),
+            None => RbfStatus::default(),
+        })
+    }
+
+    /// Return the status of the transaction with `txid`
+    ///
+    /// Unlike [`EsploraBlockchain::get_tx_status`], a transaction that left the mempool because
+    /// it has been replaced is reported as [`TxStatusEvent::Replaced`].
+    #[maybe_async]
+    pub fn get_tx_status(&self, txid: &Txid) -> Result<TxStatusEvent, Error> {
+        match maybe_await!(self.esplora.get_tx_status(txid))? {
+            TxStatusEvent::Evicted => Ok(maybe_await!(self.get_rbf_status(txid))?
+                .replaced_by
+                .map_or(TxStatusEvent::Evicted, TxStatusEvent::Replaced)),
+            status => Ok(status),
+        }
+    }
+}
+
+#[maybe_async]
+impl Blockchain for MempoolSpaceBlockchain {
+    fn get_capabilities(&self) -> HashSet<Capability> {
+        maybe_await!(self.esplora.get_capabilities())
+    }
+
+    fn setup<D: BatchDatabase, P: 'static + Progress>(
+        &self,
+        database: &mut D,
+        progress_update: P,
+    ) -> Result<(), Error> {
+        maybe_await!(self.esplora.setup(database, progress_update))
+    }
+
+    fn sync<D: BatchDatabase, P: 'static + Progress>(
+        &self,
+        database: &mut D,
+        progress_update: P,
+    ) -> Result<(), Error> {
+        maybe_await!(self.esplora.sync(database, progress_update))
+    }
+
+    fn get_tx(&self, txid: &Txid) -> Result<Option<Transaction>, Error> {
+        maybe_await!(self.esplora.get_tx(txid))
+    }
+
+    fn get_txs(&self, txids: &[Txid]) -> Result<Vec<Option<Transaction>>, Error> {
+        maybe_await!(self.esplora.get_txs(txids))
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<(), Error> {
+        maybe_await!(self.esplora.broadcast(tx))
+    }
+
+    fn get_height(&self) -> Result<u32, Error> {
+        maybe_await!(self.esplora.get_height())
+    }
+
+    fn estimate_fee(&self, target: usize) -> Result<FeeRate, Error> {
+        Ok(maybe_await!(self.recommended_fees())?.for_target(target))
+    }
+
+    fn get_median_time_past(&self) -> Result<Option<u32>, Error> {
+        maybe_await!(self.esplora.get_median_time_past())
+    }
+}
+
+#[maybe_async]
+impl FeeEstimator for MempoolSpaceBlockchain {
+    /// Estimate the fee rates from the projected blocks
+    ///
+    /// The fee rate for a target of `n` blocks is the median fee rate of the `n`-th projected
+    /// block. Targets after the last projected block are left out.
+    fn fee_estimates(&self, targets: &[usize]) -> Result<FeeEstimates, Error> {
+        let blocks = maybe_await!(self.projected_blocks())?;
+
+        let mut estimates = FeeEstimates::new(get_timestamp());
+        for target in targets {
+            if let Some(block) = blocks.get(target.saturating_sub(1)) {
+                estimates.insert(*target, block.median_fee);
+            }
+        }
+
+        Ok(estimates)
+    }
+}
+
+async fn get_json<T: DeserializeOwned>(
+    url_client: &UrlClient,
+    path: &str,
+) -> Result<T, EsploraError> {
+    Ok(url_client
+        .send_with_retry(|| {
+            url_client
+                .client
+                .get(&format!("{}{}", url_client.url, path))
+        })
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MempoolRecommendedFees {
+    fastest_fee: f32,
+    half_hour_fee: f32,
+    hour_fee: f32,
+    economy_fee: f32,
+    minimum_fee: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MempoolProjectedBlock {
+    block_v_size: f64,
+    n_tx: u64,
+    total_fees: u64,
+    median_fee: f32,
+    fee_range: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct MempoolInfo {
+    fee_histogram: Vec<(f32, f64)>,
+}
+
+#[derive(Deserialize)]
+struct MempoolRbfResponse {
+    replacements: Option<MempoolRbfNode>,
+    replaces: Option<Vec<Txid>>,
+}
+
+#[derive(Deserialize)]
+struct MempoolRbfNode {
+    tx: MempoolRbfTx,
+}
+
+#[derive(Deserialize)]
+struct MempoolRbfTx {
+    txid: Txid,
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_recommended_fees_for_target() {
+        let fees = RecommendedFees {
+            fastest: FeeRate::from_sat_per_vb(20.0),
+            half_hour: FeeRate::from_sat_per_vb(15.0),
+            hour: FeeRate::from_sat_per_vb(10.0),
+            economy: FeeRate::from_sat_per_vb(1.0),
+            minimum: FeeRate::from_sat_per_vb(2.0),
+        };
+
+        assert_eq!(fees.for_target(1), fees.fastest);
+        assert_eq!(fees.for_target(3), fees.half_hour);
+        assert_eq!(fees.for_target(6), fees.hour);
+        // the economy fee rate is below the mempool minimum
+        assert_eq!(fees.for_target(144), fees.minimum);
+    }
+
+    #[test]
+    fn test_rbf_status() {
+        let txid =
+            Txid::from_str("8d5eb8bc2ec64ee9b6e7c5a1d3d3c07e8f9d8e4e2a1f9b3c5d7e9f1a2b3c4d5e")
+                .unwrap();
+        let replacement =
+            Txid::from_str("4e3b1f0a6c8d2e5f7a9b1c3d5e7f9a0b2c4d6e8f0a1b3c5d7e9f1a3b5c7d9e0f")
+                .unwrap();
+
+        let response: MempoolRbfResponse = serde_json::from_str(&format!(
+            r#"{{"replacements":{{"tx":{{"txid":"{}"}},"time":1700000000,"fullRbf":false,"replaces":[]}},"replaces":null}}"#,
+            replacement
+        ))
+        .unwrap();
+        let status = RbfStatus::from_response(&txid, response);
+        assert_eq!(status.replaced_by, Some(replacement));
+        assert!(status.replaces.is_empty());
+
+        // the most recent transaction is the root of its own tree of replacements
+        let response: MempoolRbfResponse = serde_json::from_str(&format!(
+            r#"{{"replacements":{{"tx":{{"txid":"{}"}},"time":1700000000,"replaces":[]}},"replaces":["{}"]}}"#,
+            replacement, txid
+        ))
+        .unwrap();
+        let status = RbfStatus::from_response(&replacement, response);
+        assert_eq!(status.replaced_by, None);
+        assert_eq!(status.replaces, vec![txid]);
+
+        let response: MempoolRbfResponse =
+            serde_json::from_str(r#"{"replacements":null,"replaces":null}"#).unwrap();
+        assert_eq!(
+            RbfStatus::from_response(&txid, response),
+            RbfStatus::default()
+        );
+    }
+}
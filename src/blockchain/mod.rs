@@ -18,6 +18,7 @@
 
 use std::collections::HashSet;
 use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Arc;
 
@@ -25,11 +26,14 @@ use bitcoin::{Transaction, Txid};
 
 use crate::database::BatchDatabase;
 use crate::error::Error;
-use crate::FeeRate;
+use crate::{FeeEstimates, FeeRate};
 
 #[cfg(any(feature = "electrum", feature = "esplora"))]
 pub(crate) mod utils;
 
+pub mod spv;
+pub mod tx_cache;
+
 #[cfg(any(feature = "electrum", feature = "esplora", feature = "compact_filters"))]
 pub mod any;
 #[cfg(any(feature = "electrum", feature = "esplora", feature = "compact_filters"))]
@@ -57,6 +61,12 @@ pub mod esplora;
 #[cfg(feature = "esplora")]
 pub use self::esplora::EsploraBlockchain;
 
+#[cfg(feature = "mempool-space")]
+#[cfg_attr(docsrs, doc(cfg(feature = "mempool-space")))]
+pub mod mempool_space;
+#[cfg(feature = "mempool-space")]
+pub use self::mempool_space::MempoolSpaceBlockchain;
+
 #[cfg(feature = "compact_filters")]
 #[cfg_attr(docsrs, doc(cfg(feature = "compact_filters")))]
 pub mod compact_filters;
@@ -125,6 +135,18 @@ pub trait Blockchain {
 
     /// Fetch a transaction from the blockchain given its txid
     fn get_tx(&self, txid: &Txid) -> Result<Option<Transaction>, Error>;
+    /// Fetch multiple transactions from the blockchain given their txids
+    ///
+    /// The transactions are returned in the same order as `txids`. If not overridden, it calls
+    /// [`Blockchain::get_tx`] once per txid, so backends that can batch requests should override it.
+    fn get_txs(&self, txids: &[Txid]) -> Result<Vec<Option<Transaction>>, Error> {
+        let mut txs = Vec::with_capacity(txids.len());
+        for txid in txids {
+            txs.push(maybe_await!(self.get_tx(txid))?);
+        }
+
+        Ok(txs)
+    }
     /// Broadcast a transaction
     fn broadcast(&self, tx: &Transaction) -> Result<(), Error>;
 
@@ -132,6 +154,40 @@ pub trait Blockchain {
     fn get_height(&self) -> Result<u32, Error>;
     /// Estimate the fee rate required to confirm a transaction in a given `target` of blocks
     fn estimate_fee(&self, target: usize) -> Result<FeeRate, Error>;
+    /// Return the median time past (BIP113) of the current tip, if the backend can compute it
+    ///
+    /// This is the time that transactions with a time-based nLockTime are compared to. If not
+    /// overridden, it returns `None`.
+    fn get_median_time_past(&self) -> Result<Option<u32>, Error> {
+        Ok(None)
+    }
+}
+
+/// Trait for backends that can estimate the fee rates required to confirm a transaction
+///
+/// The [`FeeEstimates`] returned can be passed directly to
+/// [`TxBuilder::fee_estimate`](crate::wallet::tx_builder::TxBuilder::fee_estimate).
+#[maybe_async]
+pub trait FeeEstimator {
+    /// Estimate the fee rates required to confirm a transaction within each of `targets` blocks
+    ///
+    /// Targets the backend has no data for are left out. Backends that return a fixed set of
+    /// targets in a single request, like Esplora, include all of them.
+    fn fee_estimates(&self, targets: &[usize]) -> Result<FeeEstimates, Error>;
+}
+
+/// Trait for backends that can broadcast a package of related transactions atomically
+///
+/// A package is made of a child transaction and its unconfirmed parents, sorted so that every
+/// transaction comes after the ones it spends. The fee rate of the package is evaluated as a
+/// whole, so a parent that pays less than the minimum relay fee, or no fee at all, can be paid
+/// for by its child.
+#[maybe_async]
+pub trait BroadcastPackage {
+    /// Broadcast the transactions in `package` together
+    ///
+    /// Either all the transactions are accepted in the mempool, or an error is returned.
+    fn broadcast_package(&self, package: &[Transaction]) -> Result<(), Error>;
 }
 
 /// Trait for [`Blockchain`] types that can be created given a configuration
@@ -187,6 +243,55 @@ impl Progress for NoopProgress {
     }
 }
 
+/// Type that wraps a [`Progress`] and makes the sync stop when a flag is set
+///
+/// The flag is checked at every progress update, which the backends send between their batches of
+/// requests. Once it's set, the next update fails with [`Error::SyncCancelled`] and the sync stops
+/// without saving the transactions found: only the raw transactions already downloaded are kept in
+/// the database, so that they are not downloaded again by the next sync.
+///
+/// ```
+/// # use std::sync::atomic::Ordering;
+/// # use bdk::blockchain::{noop_progress, CancellableProgress, Progress};
+/// # use bdk::Error;
+/// let progress = CancellableProgress::new(noop_progress());
+/// let cancel = progress.cancel_flag();
+///
+/// // usually set from a different thread while the wallet is syncing
+/// cancel.store(true, Ordering::SeqCst);
+/// assert!(matches!(progress.update(10.0, None), Err(Error::SyncCancelled)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct CancellableProgress<P> {
+    inner: P,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<P: Progress> CancellableProgress<P> {
+    /// Wrap `inner`, forwarding the updates to it until the sync is cancelled
+    pub fn new(inner: P) -> Self {
+        CancellableProgress {
+            inner,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Return the flag that cancels the sync when set to `true`
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.cancelled)
+    }
+}
+
+impl<P: Progress> Progress for CancellableProgress<P> {
+    fn update(&self, progress: f32, message: Option<String>) -> Result<(), Error> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            return Err(Error::SyncCancelled);
+        }
+
+        self.inner.update(progress, message)
+    }
+}
+
 /// Type that implements [`Progress`] and logs at level `INFO` every update received
 #[derive(Clone, Copy)]
 pub struct LogProgress;
@@ -233,6 +338,9 @@ impl<T: Blockchain> Blockchain for Arc<T> {
     fn get_tx(&self, txid: &Txid) -> Result<Option<Transaction>, Error> {
         maybe_await!(self.deref().get_tx(txid))
     }
+    fn get_txs(&self, txids: &[Txid]) -> Result<Vec<Option<Transaction>>, Error> {
+        maybe_await!(self.deref().get_txs(txids))
+    }
     fn broadcast(&self, tx: &Transaction) -> Result<(), Error> {
         maybe_await!(self.deref().broadcast(tx))
     }
@@ -243,4 +351,21 @@ impl<T: Blockchain> Blockchain for Arc<T> {
     fn estimate_fee(&self, target: usize) -> Result<FeeRate, Error> {
         maybe_await!(self.deref().estimate_fee(target))
     }
+    fn get_median_time_past(&self) -> Result<Option<u32>, Error> {
+        maybe_await!(self.deref().get_median_time_past())
+    }
+}
+
+#[maybe_async]
+impl<T: FeeEstimator> FeeEstimator for Arc<T> {
+    fn fee_estimates(&self, targets: &[usize]) -> Result<FeeEstimates, Error> {
+        maybe_await!(self.deref().fee_estimates(targets))
+    }
+}
+
+#[maybe_async]
+impl<T: BroadcastPackage> BroadcastPackage for Arc<T> {
+    fn broadcast_package(&self, package: &[Transaction]) -> Result<(), Error> {
+        maybe_await!(self.deref().broadcast_package(package))
+    }
 }
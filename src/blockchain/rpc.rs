@@ -29,13 +29,21 @@
 //! let blockchain = RpcBlockchain::from_config(&config);
 //! ```
 
-use crate::bitcoin::consensus::deserialize;
+use crate::bitcoin::consensus::{deserialize, serialize};
+use crate::bitcoin::hashes::hex::ToHex;
 use crate::bitcoin::{Address, Network, OutPoint, Transaction, TxOut, Txid};
-use crate::blockchain::{Blockchain, Capability, ConfigurableBlockchain, Progress};
+use crate::blockchain::{
+    Blockchain, BroadcastPackage, Capability, ConfigurableBlockchain, FeeEstimator, Progress,
+};
 use crate::database::{BatchDatabase, DatabaseUtils};
 use crate::descriptor::{get_checksum, IntoWalletDescriptor};
+#[cfg(feature = "silent-payments")]
+use crate::wallet::silent_payments::{compute_tweak, BlockTweaks, SilentPaymentSource};
+use crate::wallet::time::get_timestamp;
 use crate::wallet::utils::SecpCtx;
-use crate::{ConfirmationTime, Error, FeeRate, KeychainKind, LocalUtxo, TransactionDetails};
+use crate::{
+    ConfirmationTime, Error, FeeEstimates, FeeRate, KeychainKind, LocalUtxo, TransactionDetails,
+};
 use bitcoincore_rpc::json::{
     GetAddressInfoResultLabel, ImportMultiOptions, ImportMultiRequest,
     ImportMultiRequestScriptPubkey, ImportMultiRescanSince,
@@ -191,13 +199,16 @@ impl Blockchain for RpcBlockchain {
             if let Some(mut known_tx) = known_txs.get_mut(&txid) {
                 let confirmation_time =
                     ConfirmationTime::new(tx_result.info.blockheight, tx_result.info.blocktime);
-                if confirmation_time != known_tx.confirmation_time {
-                    // reorg may change tx height
+                // reorg may change tx height, and evicted txs may be back in the mempool
+                if confirmation_time != known_tx.confirmation_time
+                    || known_tx.last_evicted.is_some()
+                {
                     debug!(
                         "updating tx({}) confirmation time to: {:?}",
                         txid, confirmation_time
                     );
                     known_tx.confirmation_time = confirmation_time;
+                    known_tx.last_evicted = None;
                     db.set_tx(&known_tx)?;
                 }
             } else {
@@ -234,6 +245,7 @@ impl Blockchain for RpcBlockchain {
                     sent,
                     fee: tx_result.fee.map(|f| f.as_sat().abs() as u64),
                     verified: true,
+                    last_evicted: None,
                 };
                 debug!(
                     "saving tx: {} tx_result.fee:{:?} td.fees:{:?}",
@@ -243,10 +255,15 @@ impl Blockchain for RpcBlockchain {
             }
         }
 
-        for known_txid in known_txs.keys() {
-            if !list_txs_ids.contains(known_txid) {
-                debug!("removing tx: {}", known_txid);
-                db.del_tx(known_txid, false)?;
+        // txs that are not in the list anymore have been replaced or dropped from the mempool. The
+        // utxos are updated below from the ones reported by the node
+        let timestamp = get_timestamp();
+        for known_tx in known_txs.values_mut() {
+            if known_tx.last_evicted.is_none() && !list_txs_ids.contains(&known_tx.txid) {
+                debug!("tx({}) evicted", known_tx.txid);
+                known_tx.confirmation_time = None;
+                known_tx.last_evicted = Some(timestamp);
+                db.set_tx(known_tx)?;
             }
         }
 
@@ -308,6 +325,120 @@ impl Blockchain for RpcBlockchain {
 
         Ok(FeeRate::from_sat_per_vb((sat_per_kb / 1000f64) as f32))
     }
+
+    fn get_median_time_past(&self) -> Result<Option<u32>, Error> {
+        Ok(Some(self.client.get_blockchain_info()?.median_time as u32))
+    }
+}
+
+impl FeeEstimator for RpcBlockchain {
+    fn fee_estimates(&self, targets: &[usize]) -> Result<FeeEstimates, Error> {
+        let mut estimates = FeeEstimates::new(get_timestamp());
+        for target in targets {
+            let fee_rate = self
+                .client
+                .estimate_smart_fee(*target as u16, None)?
+                .fee_rate;
+            if let Some(sat_per_kb) = fee_rate {
+                let sat_per_vb = sat_per_kb.as_sat() as f64 / 1000f64;
+                estimates.insert(*target, FeeRate::from_sat_per_vb(sat_per_vb as f32));
+            }
+        }
+
+        Ok(estimates)
+    }
+}
+
+/// Broadcasts the package with `submitpackage`, which requires Bitcoin Core 26 or later
+impl BroadcastPackage for RpcBlockchain {
+    fn broadcast_package(&self, package: &[Transaction]) -> Result<(), Error> {
+        if package.is_empty() {
+            return Err(Error::Generic("Empty package".to_string()));
+        }
+
+        let package: Vec<String> = package.iter().map(|tx| serialize(tx).to_hex()).collect();
+        let result: SubmitPackageResult = self.client.call("submitpackage", &[package.into()])?;
+        result.check()
+    }
+}
+
+#[derive(Deserialize)]
+struct SubmitPackageTxResult {
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SubmitPackageResult {
+    // only returned since Bitcoin Core 28, older versions fail the call instead
+    package_msg: Option<String>,
+    #[serde(rename = "tx-results", default)]
+    tx_results: HashMap<String, SubmitPackageTxResult>,
+}
+
+impl SubmitPackageResult {
+    fn check(self) -> Result<(), Error> {
+        let mut errors = self
+            .tx_results
+            .into_iter()
+            .filter_map(|(wtxid, result)| result.error.map(|error| format!("{}: {}", wtxid, error)))
+            .collect::<Vec<_>>();
+        match self.package_msg {
+            Some(message) if message != "success" => errors.insert(0, message),
+            _ => {}
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Generic(format!(
+                "Package rejected: {}",
+                errors.join(", ")
+            )))
+        }
+    }
+}
+
+/// Computes the tweak data from full blocks. The node must have `-txindex` enabled to fetch the
+/// outputs spent by the transactions.
+#[cfg(feature = "silent-payments")]
+impl SilentPaymentSource for RpcBlockchain {
+    fn get_block_tweaks(&self, height: u32) -> Result<BlockTweaks, Error> {
+        let block_hash = self.client.get_block_hash(height as u64)?;
+        let block = self.client.get_block(&block_hash)?;
+        let secp = SecpCtx::new();
+
+        let mut transactions = Vec::new();
+        for tx in block.txdata.into_iter().skip(1) {
+            // Skip fetching the previous outputs of transactions that can't contain payments
+            if !tx.output.iter().any(|output| {
+                output.script_pubkey.is_witness_program() && output.script_pubkey.len() == 34
+            }) {
+                continue;
+            }
+
+            let prevouts = tx
+                .input
+                .iter()
+                .map(|txin| {
+                    self.client
+                        .get_raw_transaction(&txin.previous_output.txid, None)?
+                        .output
+                        .get(txin.previous_output.vout as usize)
+                        .cloned()
+                        .ok_or(Error::InvalidOutpoint(txin.previous_output))
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            if let Some(tweak) = compute_tweak(&tx, &prevouts, &secp) {
+                transactions.push((tx, tweak));
+            }
+        }
+
+        Ok(BlockTweaks {
+            height,
+            timestamp: block.header.time as u64,
+            transactions,
+        })
+    }
 }
 
 impl ConfigurableBlockchain for RpcBlockchain {
@@ -420,6 +551,37 @@ fn list_wallet_dir(client: &Client) -> Result<Vec<String>, Error> {
     Ok(result.wallets.into_iter().map(|n| n.name).collect())
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_submit_package_result() {
+        let result: SubmitPackageResult = serde_json::from_str(
+            r#"{"package_msg":"success","tx-results":{"aa":{"txid":"bb","vsize":141}},"replaced-transactions":[]}"#,
+        )
+        .unwrap();
+        assert!(result.check().is_ok());
+
+        // Bitcoin Core 26 and 27 don't return `package_msg`
+        let result: SubmitPackageResult =
+            serde_json::from_str(r#"{"tx-results":{"aa":{"txid":"bb"}}}"#).unwrap();
+        assert!(result.check().is_ok());
+
+        let result: SubmitPackageResult = serde_json::from_str(
+            r#"{"package_msg":"transaction failed","tx-results":{"aa":{"txid":"bb","error":"min relay fee not met"}}}"#,
+        )
+        .unwrap();
+        match result.check() {
+            Err(Error::Generic(message)) => assert_eq!(
+                message,
+                "Package rejected: transaction failed, aa: min relay fee not met"
+            ),
+            _ => panic!("the package should be rejected"),
+        }
+    }
+}
+
 #[cfg(test)]
 #[cfg(feature = "test-rpc")]
 crate::bdk_blockchain_tests! {
@@ -0,0 +1,465 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! SPV validation
+//!
+//! This module contains the checks used to validate the data returned by untrusted servers, like
+//! Electrum and Esplora: every block header must have a valid proof of work for its network and
+//! connect to the headers already stored at the heights right before and after it, and the
+//! [`MerkleProof`] of a confirmed transaction must lead to the merkle root of a validated header.
+//!
+//! The Electrum and Esplora blockchains run these checks on the headers and proofs they download
+//! when they are built with `with_header_validation()`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use bitcoin::consensus::params::Params;
+use bitcoin::hashes::{Hash, HashEngine};
+use bitcoin::{BlockHash, BlockHeader, Network, TxMerkleNode, Txid};
+
+use serde::{Deserialize, Serialize};
+
+/// Errors that can happen while validating block headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpvError {
+    /// The hash of the header at this height doesn't match its target
+    InvalidProofOfWork(u32),
+    /// The target of the header at this height is easier than the network's minimum difficulty
+    TargetAboveLimit(u32),
+    /// The header at this height doesn't connect to the previous stored header
+    Disconnected(u32),
+    /// No header is stored at this height
+    MissingHeader(u32),
+    /// The merkle proof of this transaction doesn't match the merkle root of its block
+    InvalidMerkleProof(Txid),
+}
+
+impl fmt::Display for SpvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for SpvError {}
+
+/// Number of blocks used to compute the median time past
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// Compute the median time past (BIP113) of the last block in `headers`
+///
+/// `headers` must be sorted by height and end with the block's header: only the last
+/// [`MEDIAN_TIME_SPAN`] headers are used. Returns `None` if `headers` is empty.
+pub fn median_time_past(headers: &[BlockHeader]) -> Option<u32> {
+    let mut times = headers
+        .iter()
+        .rev()
+        .take(MEDIAN_TIME_SPAN)
+        .map(|header| header.time)
+        .collect::<Vec<_>>();
+    times.sort_unstable();
+
+    times.get(times.len() / 2).copied()
+}
+
+/// Validate the proof of work of a block `header` at `height`, returning its hash
+pub fn validate_header(
+    header: &BlockHeader,
+    height: u32,
+    network: Network,
+) -> Result<BlockHash, SpvError> {
+    let target = header.target();
+    if target > Params::new(network).pow_limit {
+        return Err(SpvError::TargetAboveLimit(height));
+    }
+
+    header
+        .validate_pow(&target)
+        .map_err(|_| SpvError::InvalidProofOfWork(height))?;
+
+    Ok(header.block_hash())
+}
+
+/// Validated block headers, indexed by height
+///
+/// Headers don't need to be contiguous: a header is only required to connect to the ones stored
+/// at the previous and next heights, if present.
+#[derive(Debug, Clone)]
+pub struct HeaderChain {
+    network: Network,
+    headers: BTreeMap<u32, BlockHeader>,
+}
+
+impl HeaderChain {
+    /// Create an empty chain of headers for `network`
+    pub fn new(network: Network) -> Self {
+        HeaderChain {
+            network,
+            headers: BTreeMap::new(),
+        }
+    }
+
+    /// Return the network of the headers
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Validate and store the `header` at `height`
+    ///
+    /// Fails if the proof of work is not valid or if the header doesn't connect to the stored
+    /// header at `height - 1`. Stored headers at higher heights that don't connect to the new one
+    /// have been reorganized out of the chain and are removed.
+    pub fn insert(&mut self, height: u32, header: BlockHeader) -> Result<BlockHash, SpvError> {
+        let hash = validate_header(&header, height, self.network)?;
+
+        if let Some(prev) = height.checked_sub(1).and_then(|h| self.headers.get(&h)) {
+            if prev.block_hash() != header.prev_blockhash {
+                return Err(SpvError::Disconnected(height));
+            }
+        }
+
+        if let Some(next) = self.headers.get(&(height + 1)) {
+            if next.prev_blockhash != hash {
+                self.headers.split_off(&(height + 1));
+            }
+        }
+        self.headers.insert(height, header);
+
+        Ok(hash)
+    }
+
+    /// Return the header stored at `height`
+    pub fn get(&self, height: u32) -> Option<&BlockHeader> {
+        self.headers.get(&height)
+    }
+
+    /// Return the height and header of the highest stored header
+    pub fn tip(&self) -> Option<(u32, &BlockHeader)> {
+        self.headers
+            .iter()
+            .next_back()
+            .map(|(height, header)| (*height, header))
+    }
+
+    /// Return the median time past of the block at `height`
+    ///
+    /// Returns `None` unless the headers of the block and of the [`MEDIAN_TIME_SPAN`] - 1 blocks
+    /// before it (or all of them, close to the genesis) are stored.
+    pub fn median_time_past(&self, height: u32) -> Option<u32> {
+        let from = height.saturating_sub(MEDIAN_TIME_SPAN as u32 - 1);
+        let headers = self
+            .headers
+            .range(from..=height)
+            .map(|(_, header)| *header)
+            .collect::<Vec<_>>();
+
+        match headers.len() as u32 == height - from + 1 {
+            true => median_time_past(&headers),
+            false => None,
+        }
+    }
+
+    /// Verify the merkle `proof` of `txid` against the header stored at the proof's height
+    pub fn verify_merkle_proof(&self, txid: &Txid, proof: &MerkleProof) -> Result<(), SpvError> {
+        let header = self
+            .get(proof.block_height)
+            .ok_or(SpvError::MissingHeader(proof.block_height))?;
+
+        proof.verify(txid, header)
+    }
+}
+
+/// Proof of the inclusion of a transaction in a block
+///
+/// This is the format returned by Electrum's `blockchain.transaction.get_merkle` and Esplora's
+/// `/tx/:txid/merkle-proof` calls.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    /// Height of the block containing the transaction
+    pub block_height: u32,
+    /// Position of the transaction in the block
+    pub pos: usize,
+    /// Hashes of the merkle branch, from the transaction up to the root
+    pub merkle: Vec<TxMerkleNode>,
+}
+
+impl MerkleProof {
+    /// Compute the merkle root of the block from `txid` and the merkle branch
+    pub fn merkle_root(&self, txid: &Txid) -> TxMerkleNode {
+        let mut pos = self.pos;
+        let mut current = TxMerkleNode::from_inner(txid.into_inner());
+        for node in self.merkle.iter() {
+            let mut engine = TxMerkleNode::engine();
+            if pos % 2 == 0 {
+                engine.input(&current[..]);
+                engine.input(&node[..]);
+            } else {
+                engine.input(&node[..]);
+                engine.input(&current[..]);
+            }
+            current = TxMerkleNode::from_engine(engine);
+            pos /= 2;
+        }
+
+        current
+    }
+
+    /// Verify that `txid` is included in the block of `header`
+    pub fn verify(&self, txid: &Txid, header: &BlockHeader) -> Result<(), SpvError> {
+        if self.merkle_root(txid) != header.merkle_root {
+            return Err(SpvError::InvalidMerkleProof(*txid));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::blockdata::constants::genesis_block;
+    use bitcoin::{Block, Transaction};
+
+    use super::*;
+
+    // Mine a regtest header with `merkle_root` on top of `prev`
+    fn mine_with_root(prev: &BlockHeader, merkle_root: TxMerkleNode) -> BlockHeader {
+        let mut header = BlockHeader {
+            version: 1,
+            prev_blockhash: prev.block_hash(),
+            merkle_root,
+            time: prev.time + 600,
+            bits: prev.bits,
+            nonce: 0,
+        };
+        while header.validate_pow(&header.target()).is_err() {
+            header.nonce += 1;
+        }
+
+        header
+    }
+
+    fn mine(prev: &BlockHeader, seed: u32) -> BlockHeader {
+        mine_with_root(prev, TxMerkleNode::hash(&seed.to_le_bytes()))
+    }
+
+    // Build a block with `num_txs` transactions on top of `prev`
+    fn block(prev: &BlockHeader, num_txs: u32) -> Block {
+        let txdata = (0..num_txs)
+            .map(|lock_time| Transaction {
+                version: 1,
+                lock_time,
+                input: vec![],
+                output: vec![],
+            })
+            .collect::<Vec<_>>();
+        let mut block = Block {
+            header: *prev,
+            txdata,
+        };
+        block.header = mine_with_root(prev, block.merkle_root());
+
+        block
+    }
+
+    // Compute the merkle proof of the transaction at `pos` in `block`
+    fn merkle_proof(block: &Block, pos: usize, block_height: u32) -> MerkleProof {
+        let mut level = block
+            .txdata
+            .iter()
+            .map(|tx| TxMerkleNode::from_inner(tx.txid().into_inner()))
+            .collect::<Vec<_>>();
+        let mut index = pos;
+        let mut merkle = vec![];
+        while level.len() > 1 {
+            if level.len() % 2 == 1 {
+                level.push(*level.last().unwrap());
+            }
+            merkle.push(level[index ^ 1]);
+            level = level
+                .chunks(2)
+                .map(|pair| {
+                    let mut engine = TxMerkleNode::engine();
+                    engine.input(&pair[0][..]);
+                    engine.input(&pair[1][..]);
+                    TxMerkleNode::from_engine(engine)
+                })
+                .collect();
+            index /= 2;
+        }
+
+        MerkleProof {
+            block_height,
+            pos,
+            merkle,
+        }
+    }
+
+    #[test]
+    fn test_validate_header() {
+        let genesis = genesis_block(Network::Bitcoin).header;
+        assert_eq!(
+            validate_header(&genesis, 0, Network::Bitcoin),
+            Ok(genesis.block_hash())
+        );
+
+        let mut invalid = genesis;
+        invalid.nonce += 1;
+        assert_eq!(
+            validate_header(&invalid, 0, Network::Bitcoin),
+            Err(SpvError::InvalidProofOfWork(0))
+        );
+
+        // Regtest difficulty is too low for mainnet
+        let regtest = mine(&genesis_block(Network::Regtest).header, 0);
+        assert!(validate_header(&regtest, 1, Network::Regtest).is_ok());
+        assert_eq!(
+            validate_header(&regtest, 1, Network::Bitcoin),
+            Err(SpvError::TargetAboveLimit(1))
+        );
+    }
+
+    #[test]
+    fn test_header_chain() {
+        let genesis = genesis_block(Network::Regtest).header;
+        let first = mine(&genesis, 0);
+        let second = mine(&first, 0);
+
+        let mut chain = HeaderChain::new(Network::Regtest);
+        assert_eq!(chain.tip(), None);
+        chain.insert(2, second).unwrap();
+        chain.insert(0, genesis).unwrap();
+        assert_eq!(chain.tip(), Some((2, &second)));
+
+        assert_eq!(
+            chain.insert(1, mine(&second, 0)),
+            Err(SpvError::Disconnected(1))
+        );
+        assert_eq!(chain.get(1), None);
+
+        chain.insert(1, first).unwrap();
+        assert_eq!(chain.get(1), Some(&first));
+        assert_eq!(chain.tip(), Some((2, &second)));
+    }
+
+    #[test]
+    fn test_header_chain_reorg() {
+        let genesis = genesis_block(Network::Regtest).header;
+        let first = mine(&genesis, 0);
+        let second = mine(&first, 0);
+        let third = mine(&second, 0);
+
+        let mut chain = HeaderChain::new(Network::Regtest);
+        for (height, header) in [genesis, first, second, third].iter().enumerate() {
+            chain.insert(height as u32, *header).unwrap();
+        }
+
+        let reorg = mine(&first, 1);
+        chain.insert(2, reorg).unwrap();
+        assert_eq!(chain.get(2), Some(&reorg));
+        assert_eq!(chain.get(3), None);
+        assert_eq!(chain.tip(), Some((2, &reorg)));
+    }
+
+    #[test]
+    fn test_median_time_past() {
+        let genesis = genesis_block(Network::Regtest).header;
+        let header_at = |time| BlockHeader { time, ..genesis };
+
+        assert_eq!(median_time_past(&[]), None);
+        assert_eq!(median_time_past(&[header_at(10)]), Some(10));
+        assert_eq!(
+            median_time_past(&[header_at(30), header_at(10), header_at(20), header_at(15)]),
+            Some(20)
+        );
+        // only the last 11 headers are used
+        let headers = (0..20).map(header_at).collect::<Vec<_>>();
+        assert_eq!(median_time_past(&headers), Some(14));
+
+        let mut chain = HeaderChain::new(Network::Regtest);
+        let mut prev = genesis;
+        chain.insert(0, genesis).unwrap();
+        for height in 1..=12 {
+            prev = mine(&prev, 0);
+            chain.insert(height, prev).unwrap();
+        }
+        assert_eq!(chain.median_time_past(12), Some(genesis.time + 7 * 600));
+        assert_eq!(chain.median_time_past(2), Some(genesis.time + 600));
+        assert_eq!(chain.median_time_past(13), None);
+
+        let mut sparse = HeaderChain::new(Network::Regtest);
+        sparse.insert(12, prev).unwrap();
+        assert_eq!(sparse.median_time_past(12), None);
+    }
+
+    #[test]
+    fn test_merkle_proof() {
+        let genesis = genesis_block(Network::Regtest).header;
+        let block = block(&genesis, 5);
+
+        let mut chain = HeaderChain::new(Network::Regtest);
+        chain.insert(1, block.header).unwrap();
+
+        for (pos, tx) in block.txdata.iter().enumerate() {
+            let proof = merkle_proof(&block, pos, 1);
+            assert_eq!(proof.merkle.len(), 3);
+            assert_eq!(proof.merkle_root(&tx.txid()), block.header.merkle_root);
+            assert_eq!(chain.verify_merkle_proof(&tx.txid(), &proof), Ok(()));
+        }
+
+        let txid = block.txdata[2].txid();
+        let other_txid = block.txdata[3].txid();
+        let proof = merkle_proof(&block, 2, 1);
+        assert_eq!(
+            chain.verify_merkle_proof(&other_txid, &proof),
+            Err(SpvError::InvalidMerkleProof(other_txid))
+        );
+
+        let wrong_pos = MerkleProof {
+            pos: 3,
+            ..proof.clone()
+        };
+        assert_eq!(
+            chain.verify_merkle_proof(&txid, &wrong_pos),
+            Err(SpvError::InvalidMerkleProof(txid))
+        );
+
+        let wrong_height = MerkleProof {
+            block_height: 2,
+            ..proof
+        };
+        assert_eq!(
+            chain.verify_merkle_proof(&txid, &wrong_height),
+            Err(SpvError::MissingHeader(2))
+        );
+    }
+
+    #[test]
+    fn test_merkle_proof_single_tx() {
+        let genesis = genesis_block(Network::Regtest).header;
+        let block = block(&genesis, 1);
+        let proof = merkle_proof(&block, 0, 1);
+
+        assert!(proof.merkle.is_empty());
+        assert_eq!(proof.verify(&block.txdata[0].txid(), &block.header), Ok(()));
+    }
+
+    #[test]
+    fn test_merkle_proof_serde() {
+        // From esplora's `/tx/:txid/merkle-proof`
+        let json = r#"{"block_height":1,"merkle":["f9b2c2a8b0fd0b1c9f07f1b0b1d8cb7bc3e7b84a4a3f3a0c8e7b4f5a2e1d3c4b"],"pos":1}"#;
+        let proof: MerkleProof = serde_json::from_str(json).unwrap();
+        assert_eq!(proof.block_height, 1);
+        assert_eq!(proof.pos, 1);
+        assert_eq!(
+            proof.merkle[0].to_string(),
+            "f9b2c2a8b0fd0b1c9f07f1b0b1d8cb7bc3e7b84a4a3f3a0c8e7b4f5a2e1d3c4b"
+        );
+    }
+}
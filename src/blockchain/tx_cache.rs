@@ -0,0 +1,174 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Raw transaction cache
+//!
+//! This module defines the [`TxCache`] trait, a cache of raw transactions keyed by txid that
+//! blockchain backends can check before downloading a transaction. Transactions never change once
+//! their txid is known, so entries never need to be invalidated.
+//!
+//! Two implementations are provided: [`MemoryTxCache`], which only lives as long as the backend,
+//! and [`FileTxCache`], which stores every transaction in its own file and can be shared between
+//! runs.
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use bdk::blockchain::electrum::ElectrumBlockchain;
+//! # use bdk::blockchain::tx_cache::FileTxCache;
+//! let client = electrum_client::Client::new("ssl://electrum.blockstream.info:50002")?;
+//! let cache = FileTxCache::new("/tmp/bdk-tx-cache")?;
+//! let blockchain = ElectrumBlockchain::from(client).with_tx_cache(Arc::new(cache));
+//! # Ok::<(), bdk::Error>(())
+//! ```
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::{Transaction, Txid};
+
+use log::warn;
+
+use crate::error::Error;
+
+/// Trait for caches of raw transactions
+///
+/// A cache failure must never make the sync fail: implementations should treat the errors as
+/// cache misses.
+pub trait TxCache: Send + Sync {
+    /// Return the transaction with `txid`, if it's in the cache
+    fn get(&self, txid: &Txid) -> Option<Transaction>;
+    /// Add a transaction to the cache
+    fn insert(&self, tx: &Transaction);
+}
+
+/// In-memory [`TxCache`]
+#[derive(Debug, Default)]
+pub struct MemoryTxCache {
+    txs: Mutex<HashMap<Txid, Transaction>>,
+}
+
+impl MemoryTxCache {
+    /// Create a new empty cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the number of transactions in the cache
+    pub fn len(&self) -> usize {
+        self.txs.lock().unwrap().len()
+    }
+
+    /// Return whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.txs.lock().unwrap().is_empty()
+    }
+}
+
+impl TxCache for MemoryTxCache {
+    fn get(&self, txid: &Txid) -> Option<Transaction> {
+        self.txs.lock().unwrap().get(txid).cloned()
+    }
+
+    fn insert(&self, tx: &Transaction) {
+        self.txs.lock().unwrap().insert(tx.txid(), tx.clone());
+    }
+}
+
+/// [`TxCache`] that stores every transaction in its own file, named after its txid
+#[derive(Debug)]
+pub struct FileTxCache {
+    dir: PathBuf,
+}
+
+impl FileTxCache {
+    /// Create a cache in the directory `dir`, which is created if it doesn't exist
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Result<Self, Error> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|e| Error::Generic(e.to_string()))?;
+
+        Ok(FileTxCache { dir })
+    }
+
+    fn path(&self, txid: &Txid) -> PathBuf {
+        self.dir.join(txid.to_string())
+    }
+}
+
+impl TxCache for FileTxCache {
+    fn get(&self, txid: &Txid) -> Option<Transaction> {
+        let bytes = fs::read(self.path(txid)).ok()?;
+        match deserialize::<Transaction>(&bytes) {
+            // don't trust a file that has been corrupted or renamed
+            Ok(tx) if tx.txid() == *txid => Some(tx),
+            _ => {
+                warn!("Invalid transaction {} in the cache", txid);
+                None
+            }
+        }
+    }
+
+    fn insert(&self, tx: &Transaction) {
+        let txid = tx.txid();
+        if let Err(e) = fs::write(self.path(&txid), serialize(tx)) {
+            warn!("Can't save transaction {} in the cache: {}", txid, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use bitcoin::hashes::hex::FromHex;
+
+    use super::*;
+
+    fn test_tx() -> Transaction {
+        deserialize(&Vec::<u8>::from_hex("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_memory_tx_cache() {
+        let cache = MemoryTxCache::new();
+        let tx = test_tx();
+        assert!(cache.is_empty());
+        assert_eq!(cache.get(&tx.txid()), None);
+
+        cache.insert(&tx);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get(&tx.txid()), Some(tx));
+    }
+
+    #[test]
+    fn test_file_tx_cache() {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("bdk_tx_cache_{}", time.as_nanos()));
+
+        let tx = test_tx();
+        let cache = FileTxCache::new(&dir).unwrap();
+        assert_eq!(cache.get(&tx.txid()), None);
+        cache.insert(&tx);
+        assert_eq!(cache.get(&tx.txid()), Some(tx.clone()));
+
+        // the transactions are still there when the cache is opened again
+        let cache = FileTxCache::new(&dir).unwrap();
+        assert_eq!(cache.get(&tx.txid()), Some(tx.clone()));
+
+        // corrupted files are ignored
+        fs::write(dir.join(tx.txid().to_string()), b"invalid").unwrap();
+        assert_eq!(cache.get(&tx.txid()), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -10,6 +10,7 @@
 // licenses.
 
 use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
 
 #[allow(unused_imports)]
 use log::{debug, error, info, trace};
@@ -18,11 +19,12 @@ use rand::thread_rng;
 
 use bitcoin::{BlockHeader, OutPoint, Script, Transaction, Txid};
 
+use super::spv::{median_time_past, HeaderChain, MerkleProof, SpvError, MEDIAN_TIME_SPAN};
 use super::*;
-use crate::database::{BatchDatabase, BatchOperations, DatabaseUtils};
+use crate::database::{evict_txs, BatchDatabase, BatchOperations, DatabaseUtils};
 use crate::error::Error;
-use crate::types::{ConfirmationTime, KeychainKind, LocalUtxo, TransactionDetails};
-use crate::wallet::time::Instant;
+use crate::types::{ConfirmationTime, KeychainKind, LocalUtxo, TransactionDetails, WatchedTx};
+use crate::wallet::time::{get_timestamp, Instant};
 use crate::wallet::utils::ChunksIterator;
 
 #[derive(Debug)]
@@ -49,17 +51,32 @@ pub trait ElectrumLikeSync {
         heights: I,
     ) -> Result<Vec<BlockHeader>, Error>;
 
+    fn els_batch_transaction_get_merkle<I: IntoIterator<Item = (Txid, u32)> + Clone>(
+        &self,
+        txs: I,
+    ) -> Result<Vec<MerkleProof>, Error>;
+
     // Provided methods down here...
 
+    /// Scan the script pubkeys of the wallet and save their transactions in the database
+    ///
+    /// With `full_scan` the script pubkeys are checked in chunks of `stop_gap` until a chunk
+    /// without any history is found. Otherwise the script pubkeys up to the last used index in the
+    /// database are always checked, even if there's a gap between them, and then the scan
+    /// continues looking for new used script pubkeys like a full scan.
+    ///
+    /// The progress goes up to 50% while scanning the script pubkeys, then to 75% once the new
+    /// transactions are downloaded and to 100% when the database is updated.
     fn electrum_like_setup<D: BatchDatabase, P: Progress>(
         &self,
         stop_gap: usize,
+        headers: Option<&Mutex<HeaderChain>>,
+        full_scan: bool,
         db: &mut D,
-        _progress_update: P,
+        progress_update: P,
     ) -> Result<(), Error> {
-        // TODO: progress
         let start = Instant::new();
-        debug!("start setup");
+        debug!("start setup, full_scan: {}", full_scan);
 
         let chunk_size = stop_gap;
 
@@ -70,9 +87,26 @@ pub trait ElectrumLikeSync {
         let mut wallet_chains = vec![KeychainKind::Internal, KeychainKind::External];
         // shuffling improve privacy, the server doesn't know my first request is from my internal or external addresses
         wallet_chains.shuffle(&mut thread_rng());
+
+        let mut last_indexes = HashMap::new();
+        let mut wallet_scripts = HashMap::new();
+        for keychain in wallet_chains.iter() {
+            if !full_scan {
+                if let Some(last_index) = db.get_last_index(*keychain)? {
+                    last_indexes.insert(keychain, last_index);
+                }
+            }
+            wallet_scripts.insert(keychain, db.iter_script_pubkeys(Some(*keychain))?);
+        }
+        let total_scripts = wallet_scripts.values().map(Vec::len).sum::<usize>().max(1);
+        let mut scanned_scripts = 0;
+
         // download history of our internal and external script_pubkeys
         for keychain in wallet_chains.iter() {
-            let script_iter = db.iter_script_pubkeys(Some(*keychain))?.into_iter();
+            let script_iter = wallet_scripts
+                .remove(keychain)
+                .unwrap_or_default()
+                .into_iter();
 
             for (i, chunk) in ChunksIterator::new(script_iter, stop_gap).enumerate() {
                 // TODO if i == last, should create another chunk of addresses in db
@@ -86,12 +120,10 @@ pub trait ElectrumLikeSync {
                 if let Some(max) = max_index {
                     max_indexes.insert(keychain, max + (i * chunk_size) as u32);
                 }
+
                 let flattened: Vec<ElsGetHistoryRes> = call_result.into_iter().flatten().collect();
                 debug!("#{} of {:?} results:{}", i, keychain, flattened.len());
-                if flattened.is_empty() {
-                    // Didn't find anything in the last `stop_gap` script_pubkeys, breaking
-                    break;
-                }
+                let chunk_is_empty = flattened.is_empty();
 
                 for el in flattened {
                     // el.height = -1 means unconfirmed with unconfirmed parents
@@ -104,14 +136,39 @@ pub trait ElectrumLikeSync {
                     }
                     history_txs_id.insert(el.tx_hash);
                 }
+
+                scanned_scripts += chunk.len();
+                progress_update.update(
+                    scanned_scripts as f32 / total_scripts as f32 * 50.0,
+                    Some(format!(
+                        "Scanned the {:?} script pubkeys up to index {}, {} transactions found",
+                        keychain,
+                        (i * chunk_size + chunk.len()).saturating_sub(1),
+                        history_txs_id.len()
+                    )),
+                )?;
+
+                let known_used_after_chunk =
+                    last_indexes.get(keychain).map_or(false, |last_index| {
+                        *last_index as usize >= (i + 1) * chunk_size
+                    });
+                if chunk_is_empty && !known_used_after_chunk {
+                    // Didn't find anything in the last `stop_gap` script_pubkeys, breaking
+                    break;
+                }
             }
         }
 
-        // saving max indexes
+        // saving max indexes. A sync never lowers the last index, since it may not check the
+        // script pubkeys that were only used by transactions that have been replaced since
         info!("max indexes are: {:?}", max_indexes);
         for keychain in wallet_chains.iter() {
             if let Some(index) = max_indexes.get(keychain) {
-                db.set_last_index(*keychain, *index)?;
+                let index = match last_indexes.get(keychain) {
+                    Some(last_index) => *index.max(last_index),
+                    None => *index,
+                };
+                db.set_last_index(*keychain, index)?;
             }
         }
 
@@ -128,28 +185,79 @@ pub trait ElectrumLikeSync {
             .collect();
         let utxos_deps = utxos_deps(db, &txs_raw_in_db)?;
 
-        // download new txs and headers
+        // download new txs and headers. The raw txs of confirmed txs we already know about are not
+        // needed, they may have been pruned from the db
+        let needed_txs_id: HashSet<Txid> = history_txs_id
+            .iter()
+            .filter(|txid| {
+                txs_details_in_db
+                    .get(txid)
+                    .map_or(true, |details| details.confirmation_time.is_none())
+            })
+            .cloned()
+            .collect();
+        progress_update.update(
+            50.0,
+            Some(format!("Downloading {} transactions", needed_txs_id.len())),
+        )?;
         let new_txs = maybe_await!(self.download_and_save_needed_raw_txs(
-            &history_txs_id,
+            &needed_txs_id,
             &txs_raw_in_db,
             chunk_size,
             db
         ))?;
+        progress_update.update(
+            75.0,
+            Some(format!("Downloaded {} transactions", new_txs.len())),
+        )?;
         let new_timestamps = maybe_await!(self.download_needed_headers(
             &txid_height,
             &txs_details_in_db,
-            chunk_size
+            chunk_size,
+            headers
         ))?;
+        if let Some(headers) = headers {
+            let txid_height = new_timestamps
+                .keys()
+                .filter_map(|txid| txid_height.get(txid).cloned().flatten().map(|h| (*txid, h)))
+                .collect();
+            maybe_await!(self.download_and_verify_merkle_proofs(txid_height, chunk_size, headers))?;
+        }
+
+        let watched_history = maybe_await!(self.download_watched_history(db, chunk_size))?;
 
         let mut batch = db.begin_batch();
 
+        for (script, history) in watched_history {
+            batch.set_watched_script(&script, &history)?;
+        }
+
         // save any tx details not in db but in history_txs_id or with different height/timestamp
         for txid in history_txs_id.iter() {
             let height = txid_height.get(txid).cloned().flatten();
             let timestamp = new_timestamps.get(txid).cloned();
             if let Some(tx_details) = txs_details_in_db.get(txid) {
+                if tx_details.last_evicted.is_some() {
+                    // the tx was evicted but it's back in the mempool or in a block: save it again
+                    // to recompute its utxos, and remove the ones it spends
+                    save_transaction_details_and_utxos(
+                        txid,
+                        db,
+                        timestamp,
+                        height,
+                        &mut batch,
+                        &utxos_deps,
+                    )?;
+                    let tx = db.get_raw_tx(txid)?.ok_or(Error::TransactionNotFound)?;
+                    for input in tx.input.iter() {
+                        batch.del_utxo(&input.previous_output)?;
+                    }
+                    continue;
+                }
+
                 // check if tx height matches, otherwise updates it. timestamp is not in the if clause
-                // because we are not asking headers for confirmed tx we know about
+                // because we are not asking headers for confirmed tx we know about, unless they
+                // have been moved to a different block
                 if tx_details.confirmation_time.as_ref().map(|c| c.height) != height {
                     let confirmation_time = ConfirmationTime::new(height, timestamp);
                     let mut new_tx_details = tx_details.clone();
@@ -168,13 +276,6 @@ pub trait ElectrumLikeSync {
             }
         }
 
-        // remove any tx details in db but not in history_txs_id
-        for txid in txs_details_in_db.keys() {
-            if !history_txs_id.contains(txid) {
-                batch.del_tx(txid, false)?;
-            }
-        }
-
         // remove any spent utxo
         for new_tx in new_txs.iter() {
             for input in new_tx.input.iter() {
@@ -183,11 +284,54 @@ pub trait ElectrumLikeSync {
         }
 
         db.commit_batch(batch)?;
+
+        // mark as evicted any tx in db but not in history_txs_id, they have been replaced or
+        // dropped from the mempool
+        let evicted = txs_details_in_db
+            .values()
+            .filter(|tx| tx.last_evicted.is_none() && !history_txs_id.contains(&tx.txid))
+            .map(|tx| tx.txid)
+            .collect::<Vec<_>>();
+        if !evicted.is_empty() {
+            evict_txs(db, &evicted, get_timestamp())?;
+        }
+        progress_update.update(100.0, Some("Sync completed".to_string()))?;
         info!("finish setup, elapsed {:?}ms", start.elapsed().as_millis());
 
         Ok(())
     }
 
+    /// download the history of the scripts in the watchlist. Their txs are not part of the wallet, so they
+    /// are only listed and not downloaded
+    fn download_watched_history<D: BatchDatabase>(
+        &self,
+        db: &D,
+        chunk_size: usize,
+    ) -> Result<Vec<(Script, Vec<WatchedTx>)>, Error> {
+        let watched_scripts = db.iter_watched_scripts()?;
+        if !watched_scripts.is_empty() {
+            info!("{} watched scripts to sync", watched_scripts.len());
+        }
+
+        let mut watched_history = vec![];
+        for chunk in ChunksIterator::new(watched_scripts.into_iter(), chunk_size) {
+            let call_result: Vec<Vec<ElsGetHistoryRes>> =
+                maybe_await!(self.els_batch_script_get_history(chunk.iter()))?;
+            for (script, history) in chunk.into_iter().zip(call_result) {
+                let history = history
+                    .into_iter()
+                    .map(|el| WatchedTx {
+                        txid: el.tx_hash,
+                        height: Some(el.height).filter(|h| *h > 0).map(|h| h as u32),
+                    })
+                    .collect();
+                watched_history.push((script, history));
+            }
+        }
+
+        Ok(watched_history)
+    }
+
     /// download txs identified by `history_txs_id` and theirs previous outputs if not already present in db
     fn download_and_save_needed_raw_txs<D: BatchDatabase>(
         &self,
@@ -231,29 +375,51 @@ pub trait ElectrumLikeSync {
     }
 
     /// download headers at heights in `txid_height` if tx details not already present, returns a map Txid -> timestamp
+    ///
+    /// if `headers` is set every downloaded header is validated and stored in it
     fn download_needed_headers(
         &self,
         txid_height: &HashMap<Txid, Option<u32>>,
         txs_details_in_db: &HashMap<Txid, TransactionDetails>,
         chunk_size: usize,
+        headers: Option<&Mutex<HeaderChain>>,
     ) -> Result<HashMap<Txid, u64>, Error> {
         let mut txid_timestamp = HashMap::new();
+        // txs confirmed at a different height have been reorganized: their new block must be
+        // downloaded, and validated if `headers` is set, like for any newly confirmed tx
         let txid_in_db_with_conf: HashSet<_> = txs_details_in_db
             .values()
-            .filter_map(|details| details.confirmation_time.as_ref().map(|_| details.txid))
+            .filter(|details| {
+                let height = details.confirmation_time.as_ref().map(|c| c.height);
+                height.is_some() && txid_height.get(&details.txid) == Some(&height)
+            })
+            .map(|details| details.txid)
             .collect();
         let needed_txid_height: HashMap<&Txid, u32> = txid_height
             .iter()
             .filter(|(t, _)| !txid_in_db_with_conf.contains(*t))
             .filter_map(|(t, o)| o.map(|h| (t, h)))
             .collect();
-        let needed_heights: HashSet<u32> = needed_txid_height.values().cloned().collect();
+        // sorted so that every header is validated against the previous one, when present
+        let mut needed_heights: Vec<u32> = needed_txid_height
+            .values()
+            .cloned()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        needed_heights.sort_unstable();
         if !needed_heights.is_empty() {
             info!("{} headers to download for timestamp", needed_heights.len());
             let mut height_timestamp: HashMap<u32, u64> = HashMap::new();
             for chunk in ChunksIterator::new(needed_heights.into_iter(), chunk_size) {
                 let call_result: Vec<BlockHeader> =
                     maybe_await!(self.els_batch_block_header(chunk.clone()))?;
+                if let Some(headers) = headers {
+                    let mut headers = headers.lock().unwrap();
+                    for (height, header) in chunk.iter().zip(call_result.iter()) {
+                        headers.insert(*height, *header)?;
+                    }
+                }
                 height_timestamp.extend(
                     chunk
                         .into_iter()
@@ -271,6 +437,50 @@ pub trait ElectrumLikeSync {
         Ok(txid_timestamp)
     }
 
+    /// download the merkle proofs of the txs confirmed at the heights in `txid_height` and verify them against `headers`
+    fn download_and_verify_merkle_proofs(
+        &self,
+        txid_height: Vec<(Txid, u32)>,
+        chunk_size: usize,
+        headers: &Mutex<HeaderChain>,
+    ) -> Result<(), Error> {
+        info!("{} merkle proofs to verify", txid_height.len());
+        for chunk in ChunksIterator::new(txid_height.into_iter(), chunk_size) {
+            let proofs: Vec<MerkleProof> =
+                maybe_await!(self.els_batch_transaction_get_merkle(chunk.clone()))?;
+            let headers = headers.lock().unwrap();
+            for ((txid, height), proof) in chunk.iter().zip(proofs.iter()) {
+                // the proof must be for the block the tx has been reported in
+                if proof.block_height != *height {
+                    return Err(SpvError::InvalidMerkleProof(*txid).into());
+                }
+                headers.verify_merkle_proof(txid, proof)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// download the last headers up to `tip` and compute its median time past
+    ///
+    /// if `headers` is set every downloaded header is validated and stored in it
+    fn els_median_time_past(
+        &self,
+        tip: u32,
+        headers: Option<&Mutex<HeaderChain>>,
+    ) -> Result<u32, Error> {
+        let from = tip.saturating_sub(MEDIAN_TIME_SPAN as u32 - 1);
+        let call_result: Vec<BlockHeader> = maybe_await!(self.els_batch_block_header(from..=tip))?;
+        if let Some(headers) = headers {
+            let mut headers = headers.lock().unwrap();
+            for (height, header) in (from..=tip).zip(call_result.iter()) {
+                headers.insert(height, *header)?;
+            }
+        }
+
+        median_time_past(&call_result).ok_or_else(|| SpvError::MissingHeader(tip).into())
+    }
+
     fn download_and_save_in_chunks<D: BatchDatabase>(
         &self,
         to_download: Vec<&Txid>,
@@ -293,7 +503,7 @@ pub trait ElectrumLikeSync {
     }
 }
 
-fn save_transaction_details_and_utxos<D: BatchDatabase>(
+pub(crate) fn save_transaction_details_and_utxos<D: BatchDatabase>(
     txid: &Txid,
     db: &mut D,
     timestamp: Option<u64>,
@@ -362,6 +572,7 @@ fn save_transaction_details_and_utxos<D: BatchDatabase>(
         confirmation_time: ConfirmationTime::new(height, timestamp),
         fee: Some(inputs_sum.saturating_sub(outputs_sum)), /* if the tx is a coinbase, fees would be negative */
         verified: height.is_some(),
+        last_evicted: None,
     };
     updates.set_tx(&tx_details)?;
 
@@ -370,7 +581,7 @@ fn save_transaction_details_and_utxos<D: BatchDatabase>(
 
 /// returns utxo dependency as the inputs needed for the utxo to exist
 /// `tx_raw_in_db` must contains utxo's generating txs or errors witt [crate::Error::TransactionNotFound]
-fn utxos_deps<D: BatchDatabase>(
+pub(crate) fn utxos_deps<D: BatchDatabase>(
     db: &mut D,
     tx_raw_in_db: &HashMap<Txid, Transaction>,
 ) -> Result<HashMap<OutPoint, OutPoint>, Error> {
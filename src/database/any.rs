@@ -132,6 +132,18 @@ impl BatchOperations for AnyDatabase {
     fn set_last_index(&mut self, keychain: KeychainKind, value: u32) -> Result<(), Error> {
         impl_inner_method!(AnyDatabase, self, set_last_index, keychain, value)
     }
+    fn set_used_mark(&mut self, keychain: KeychainKind, child: u32) -> Result<(), Error> {
+        impl_inner_method!(AnyDatabase, self, set_used_mark, keychain, child)
+    }
+    fn set_watched_script(&mut self, script: &Script, history: &[WatchedTx]) -> Result<(), Error> {
+        impl_inner_method!(AnyDatabase, self, set_watched_script, script, history)
+    }
+    fn set_sweep_index(&mut self, keychain: KeychainKind, value: u32) -> Result<(), Error> {
+        impl_inner_method!(AnyDatabase, self, set_sweep_index, keychain, value)
+    }
+    fn set_swept_utxo(&mut self, outpoint: &OutPoint) -> Result<(), Error> {
+        impl_inner_method!(AnyDatabase, self, set_swept_utxo, outpoint)
+    }
 
     fn del_script_pubkey_from_path(
         &mut self,
@@ -168,6 +180,18 @@ impl BatchOperations for AnyDatabase {
     fn del_last_index(&mut self, keychain: KeychainKind) -> Result<Option<u32>, Error> {
         impl_inner_method!(AnyDatabase, self, del_last_index, keychain)
     }
+    fn del_used_mark(&mut self, keychain: KeychainKind, child: u32) -> Result<bool, Error> {
+        impl_inner_method!(AnyDatabase, self, del_used_mark, keychain, child)
+    }
+    fn del_watched_script(&mut self, script: &Script) -> Result<Option<Vec<WatchedTx>>, Error> {
+        impl_inner_method!(AnyDatabase, self, del_watched_script, script)
+    }
+    fn del_sweep_index(&mut self, keychain: KeychainKind) -> Result<Option<u32>, Error> {
+        impl_inner_method!(AnyDatabase, self, del_sweep_index, keychain)
+    }
+    fn del_swept_utxo(&mut self, outpoint: &OutPoint) -> Result<bool, Error> {
+        impl_inner_method!(AnyDatabase, self, del_swept_utxo, outpoint)
+    }
 }
 
 impl Database for AnyDatabase {
@@ -184,6 +208,9 @@ impl Database for AnyDatabase {
             bytes
         )
     }
+    fn get_descriptor_checksum(&self, keychain: KeychainKind) -> Result<Option<Vec<u8>>, Error> {
+        impl_inner_method!(AnyDatabase, self, get_descriptor_checksum, keychain)
+    }
 
     fn iter_script_pubkeys(&self, keychain: Option<KeychainKind>) -> Result<Vec<Script>, Error> {
         impl_inner_method!(AnyDatabase, self, iter_script_pubkeys, keychain)
@@ -197,6 +224,30 @@ impl Database for AnyDatabase {
     fn iter_txs(&self, include_raw: bool) -> Result<Vec<TransactionDetails>, Error> {
         impl_inner_method!(AnyDatabase, self, iter_txs, include_raw)
     }
+    fn utxos_iter(&self, keychain: Option<KeychainKind>) -> DatabaseIter<'_, LocalUtxo> {
+        impl_inner_method!(AnyDatabase, self, utxos_iter, keychain)
+    }
+    fn raw_txs_iter(&self) -> DatabaseIter<'_, Transaction> {
+        impl_inner_method!(AnyDatabase, self, raw_txs_iter)
+    }
+    fn txs_iter(&self, include_raw: bool) -> DatabaseIter<'_, TransactionDetails> {
+        impl_inner_method!(AnyDatabase, self, txs_iter, include_raw)
+    }
+    fn iter_txs_by_height(
+        &self,
+        from_height: u32,
+        to_height: u32,
+        include_raw: bool,
+    ) -> Result<Vec<TransactionDetails>, Error> {
+        impl_inner_method!(
+            AnyDatabase,
+            self,
+            iter_txs_by_height,
+            from_height,
+            to_height,
+            include_raw
+        )
+    }
 
     fn get_script_pubkey_from_path(
         &self,
@@ -229,6 +280,24 @@ impl Database for AnyDatabase {
     fn get_last_index(&self, keychain: KeychainKind) -> Result<Option<u32>, Error> {
         impl_inner_method!(AnyDatabase, self, get_last_index, keychain)
     }
+    fn iter_used_marks(&self, keychain: KeychainKind) -> Result<Vec<u32>, Error> {
+        impl_inner_method!(AnyDatabase, self, iter_used_marks, keychain)
+    }
+    fn has_used_mark(&self, keychain: KeychainKind, child: u32) -> Result<bool, Error> {
+        impl_inner_method!(AnyDatabase, self, has_used_mark, keychain, child)
+    }
+    fn iter_watched_scripts(&self) -> Result<Vec<Script>, Error> {
+        impl_inner_method!(AnyDatabase, self, iter_watched_scripts)
+    }
+    fn get_watched_script(&self, script: &Script) -> Result<Option<Vec<WatchedTx>>, Error> {
+        impl_inner_method!(AnyDatabase, self, get_watched_script, script)
+    }
+    fn get_sweep_index(&self, keychain: KeychainKind) -> Result<Option<u32>, Error> {
+        impl_inner_method!(AnyDatabase, self, get_sweep_index, keychain)
+    }
+    fn iter_swept_utxos(&self) -> Result<Vec<OutPoint>, Error> {
+        impl_inner_method!(AnyDatabase, self, iter_swept_utxos)
+    }
 
     fn increment_last_index(&mut self, keychain: KeychainKind) -> Result<u32, Error> {
         impl_inner_method!(AnyDatabase, self, increment_last_index, keychain)
@@ -256,6 +325,18 @@ impl BatchOperations for AnyBatch {
     fn set_last_index(&mut self, keychain: KeychainKind, value: u32) -> Result<(), Error> {
         impl_inner_method!(AnyBatch, self, set_last_index, keychain, value)
     }
+    fn set_used_mark(&mut self, keychain: KeychainKind, child: u32) -> Result<(), Error> {
+        impl_inner_method!(AnyBatch, self, set_used_mark, keychain, child)
+    }
+    fn set_watched_script(&mut self, script: &Script, history: &[WatchedTx]) -> Result<(), Error> {
+        impl_inner_method!(AnyBatch, self, set_watched_script, script, history)
+    }
+    fn set_sweep_index(&mut self, keychain: KeychainKind, value: u32) -> Result<(), Error> {
+        impl_inner_method!(AnyBatch, self, set_sweep_index, keychain, value)
+    }
+    fn set_swept_utxo(&mut self, outpoint: &OutPoint) -> Result<(), Error> {
+        impl_inner_method!(AnyBatch, self, set_swept_utxo, outpoint)
+    }
 
     fn del_script_pubkey_from_path(
         &mut self,
@@ -286,6 +367,18 @@ impl BatchOperations for AnyBatch {
     fn del_last_index(&mut self, keychain: KeychainKind) -> Result<Option<u32>, Error> {
         impl_inner_method!(AnyBatch, self, del_last_index, keychain)
     }
+    fn del_used_mark(&mut self, keychain: KeychainKind, child: u32) -> Result<bool, Error> {
+        impl_inner_method!(AnyBatch, self, del_used_mark, keychain, child)
+    }
+    fn del_watched_script(&mut self, script: &Script) -> Result<Option<Vec<WatchedTx>>, Error> {
+        impl_inner_method!(AnyBatch, self, del_watched_script, script)
+    }
+    fn del_sweep_index(&mut self, keychain: KeychainKind) -> Result<Option<u32>, Error> {
+        impl_inner_method!(AnyBatch, self, del_sweep_index, keychain)
+    }
+    fn del_swept_utxo(&mut self, outpoint: &OutPoint) -> Result<bool, Error> {
+        impl_inner_method!(AnyBatch, self, del_swept_utxo, outpoint)
+    }
 }
 
 impl BatchDatabase for AnyDatabase {
@@ -314,6 +407,13 @@ impl BatchDatabase for AnyDatabase {
     }
 }
 
+#[maybe_async]
+impl PersistDatabase for AnyDatabase {
+    fn persist(&self) -> Result<(), Error> {
+        maybe_await!(impl_inner_method!(AnyDatabase, self, persist))
+    }
+}
+
 /// Configuration type for a [`sled::Tree`] database
 #[cfg(feature = "key-value-db")]
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
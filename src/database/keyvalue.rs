@@ -17,8 +17,8 @@ use bitcoin::consensus::encode::{deserialize, serialize};
 use bitcoin::hash_types::Txid;
 use bitcoin::{OutPoint, Script, Transaction};
 
-use crate::database::memory::MapKey;
-use crate::database::{BatchDatabase, BatchOperations, Database};
+use crate::database::memory::{after, MapKey};
+use crate::database::{BatchDatabase, BatchOperations, Database, DatabaseIter, PersistDatabase};
 use crate::error::Error;
 use crate::types::*;
 
@@ -67,6 +67,13 @@ macro_rules! impl_batch_operations {
 
             self.insert(key, value)$($after_insert)*;
 
+            // index the confirmed txs by height. Stale entries left by a change of height are
+            // skipped when iterating
+            if let Some(ref confirmation_time) = transaction.confirmation_time {
+                let key = MapKey::TxHeight((Some(confirmation_time.height), Some(&transaction.txid))).as_map_key();
+                self.insert(key, Vec::<u8>::new())$($after_insert)*;
+            }
+
             // insert the raw_tx if present
             if let Some(ref tx) = transaction.transaction {
                 self.set_raw_tx(tx)?;
@@ -82,6 +89,34 @@ macro_rules! impl_batch_operations {
             Ok(())
         }
 
+        fn set_used_mark(&mut self, keychain: KeychainKind, child: u32) -> Result<(), Error> {
+            let key = MapKey::UsedMark((keychain, Some(child))).as_map_key();
+            self.insert(key, Vec::<u8>::new())$($after_insert)*;
+
+            Ok(())
+        }
+
+        fn set_watched_script(&mut self, script: &Script, history: &[WatchedTx]) -> Result<(), Error> {
+            let key = MapKey::WatchedScript(Some(script)).as_map_key();
+            self.insert(key, serde_json::to_vec(history)?)$($after_insert)*;
+
+            Ok(())
+        }
+
+        fn set_sweep_index(&mut self, keychain: KeychainKind, value: u32) -> Result<(), Error> {
+            let key = MapKey::SweepIndex(keychain).as_map_key();
+            self.insert(key, &value.to_be_bytes())$($after_insert)*;
+
+            Ok(())
+        }
+
+        fn set_swept_utxo(&mut self, outpoint: &OutPoint) -> Result<(), Error> {
+            let key = MapKey::SweptUtxo(Some(outpoint)).as_map_key();
+            self.insert(key, Vec::<u8>::new())$($after_insert)*;
+
+            Ok(())
+        }
+
         fn del_script_pubkey_from_path(&mut self, keychain: KeychainKind, path: u32) -> Result<Option<Script>, Error> {
             let key = MapKey::Path((Some(keychain), Some(path))).as_map_key();
             let res = self.remove(key);
@@ -149,6 +184,12 @@ macro_rules! impl_batch_operations {
                     let mut val: TransactionDetails = serde_json::from_slice(&b)?;
                     val.transaction = raw_tx;
 
+                    if let Some(ref confirmation_time) = val.confirmation_time {
+                        let key = MapKey::TxHeight((Some(confirmation_time.height), Some(txid))).as_map_key();
+                        let res = self.remove(key);
+                        $process_delete!(res);
+                    }
+
                     Ok(Some(val))
                 }
             }
@@ -168,6 +209,45 @@ macro_rules! impl_batch_operations {
                 }
             }
         }
+
+        fn del_used_mark(&mut self, keychain: KeychainKind, child: u32) -> Result<bool, Error> {
+            let key = MapKey::UsedMark((keychain, Some(child))).as_map_key();
+            let res = self.remove(key);
+            let res = $process_delete!(res);
+
+            Ok(res.is_some())
+        }
+
+        fn del_watched_script(&mut self, script: &Script) -> Result<Option<Vec<WatchedTx>>, Error> {
+            let key = MapKey::WatchedScript(Some(script)).as_map_key();
+            let res = self.remove(key);
+            let res = $process_delete!(res);
+
+            Ok(res.map(|b| serde_json::from_slice(&b)).transpose()?)
+        }
+
+        fn del_sweep_index(&mut self, keychain: KeychainKind) -> Result<Option<u32>, Error> {
+            let key = MapKey::SweepIndex(keychain).as_map_key();
+            let res = self.remove(key);
+            let res = $process_delete!(res);
+
+            match res {
+                None => Ok(None),
+                Some(b) => {
+                    let array: [u8; 4] = b.as_ref().try_into().map_err(|_| Error::InvalidU32Bytes(b.to_vec()))?;
+                    let val = u32::from_be_bytes(array);
+                    Ok(Some(val))
+                }
+            }
+        }
+
+        fn del_swept_utxo(&mut self, outpoint: &OutPoint) -> Result<bool, Error> {
+            let key = MapKey::SweptUtxo(Some(outpoint)).as_map_key();
+            let res = self.remove(key);
+            let res = $process_delete!(res);
+
+            Ok(res.is_some())
+        }
     }
 }
 
@@ -211,6 +291,11 @@ impl Database for Tree {
         }
     }
 
+    fn get_descriptor_checksum(&self, keychain: KeychainKind) -> Result<Option<Vec<u8>>, Error> {
+        let key = MapKey::DescriptorChecksum(keychain).as_map_key();
+        Ok(self.get(key)?.map(|b| b.to_vec()))
+    }
+
     fn iter_script_pubkeys(&self, keychain: Option<KeychainKind>) -> Result<Vec<Script>, Error> {
         let key = MapKey::Path((keychain, None)).as_map_key();
         self.scan_prefix(key)
@@ -222,49 +307,107 @@ impl Database for Tree {
     }
 
     fn iter_utxos(&self) -> Result<Vec<LocalUtxo>, Error> {
+        self.utxos_iter(None).collect()
+    }
+
+    fn iter_raw_txs(&self) -> Result<Vec<Transaction>, Error> {
+        self.raw_txs_iter().collect()
+    }
+
+    fn iter_txs(&self, include_raw: bool) -> Result<Vec<TransactionDetails>, Error> {
+        self.txs_iter(include_raw).collect()
+    }
+
+    fn utxos_iter(&self, keychain: Option<KeychainKind>) -> DatabaseIter<'_, LocalUtxo> {
         let key = MapKey::Utxo(None).as_map_key();
-        self.scan_prefix(key)
-            .map(|x| -> Result<_, Error> {
-                let (k, v) = x?;
-                let outpoint = deserialize(&k[1..])?;
+        Box::new(
+            self.scan_prefix(key)
+                .map(|x| -> Result<_, Error> {
+                    let (k, v) = x?;
+                    let outpoint = deserialize(&k[1..])?;
 
-                let mut val: serde_json::Value = serde_json::from_slice(&v)?;
-                let txout = serde_json::from_value(val["t"].take())?;
-                let keychain = serde_json::from_value(val["i"].take())?;
+                    let mut val: serde_json::Value = serde_json::from_slice(&v)?;
+                    let txout = serde_json::from_value(val["t"].take())?;
+                    let keychain = serde_json::from_value(val["i"].take())?;
 
-                Ok(LocalUtxo {
-                    outpoint,
-                    txout,
-                    keychain,
+                    Ok(LocalUtxo {
+                        outpoint,
+                        txout,
+                        keychain,
+                    })
                 })
-            })
-            .collect()
+                .filter(move |utxo| match (keychain, utxo) {
+                    (Some(keychain), Ok(utxo)) => utxo.keychain == keychain,
+                    _ => true,
+                }),
+        )
     }
 
-    fn iter_raw_txs(&self) -> Result<Vec<Transaction>, Error> {
+    fn raw_txs_iter(&self) -> DatabaseIter<'_, Transaction> {
         let key = MapKey::RawTx(None).as_map_key();
-        self.scan_prefix(key)
-            .map(|x| -> Result<_, Error> {
-                let (_, v) = x?;
-                Ok(deserialize(&v)?)
-            })
-            .collect()
+        Box::new(self.scan_prefix(key).map(|x| -> Result<_, Error> {
+            let (_, v) = x?;
+            Ok(deserialize(&v)?)
+        }))
     }
 
-    fn iter_txs(&self, include_raw: bool) -> Result<Vec<TransactionDetails>, Error> {
+    fn txs_iter(&self, include_raw: bool) -> DatabaseIter<'_, TransactionDetails> {
         let key = MapKey::Transaction(None).as_map_key();
-        self.scan_prefix(key)
-            .map(|x| -> Result<_, Error> {
-                let (k, v) = x?;
-                let mut txdetails: TransactionDetails = serde_json::from_slice(&v)?;
-                if include_raw {
-                    let txid = deserialize(&k[1..])?;
-                    txdetails.transaction = self.get_raw_tx(&txid)?;
+        Box::new(self.scan_prefix(key).map(move |x| -> Result<_, Error> {
+            let (k, v) = x?;
+            let mut txdetails: TransactionDetails = serde_json::from_slice(&v)?;
+            if include_raw {
+                let txid = deserialize(&k[1..])?;
+                txdetails.transaction = self.get_raw_tx(&txid)?;
+            }
+
+            Ok(txdetails)
+        }))
+    }
+
+    fn iter_txs_by_height(
+        &self,
+        from_height: u32,
+        to_height: u32,
+        include_raw: bool,
+    ) -> Result<Vec<TransactionDetails>, Error> {
+        if from_height > to_height {
+            return Ok(vec![]);
+        }
+
+        // databases created by older versions don't have the index yet: build it the first time
+        // it's needed
+        let indexed_key = MapKey::TxHeightIndexed.as_map_key();
+        if !self.contains_key(&indexed_key)? {
+            for tx in self.iter_txs(false)? {
+                if let Some(confirmation_time) = tx.confirmation_time {
+                    let key = MapKey::TxHeight((Some(confirmation_time.height), Some(&tx.txid)))
+                        .as_map_key();
+                    self.insert(key, Vec::<u8>::new())?;
                 }
+            }
+            self.insert(indexed_key, Vec::<u8>::new())?;
+        }
 
-                Ok(txdetails)
-            })
-            .collect()
+        let from = MapKey::TxHeight((Some(from_height), None)).as_map_key();
+        let to = MapKey::TxHeight((Some(to_height), None)).as_map_key();
+        let mut txs = vec![];
+        for x in self.range(from..after(&to)) {
+            let (k, _) = x?;
+            let array: [u8; 4] = k[1..5]
+                .try_into()
+                .map_err(|_| Error::InvalidU32Bytes(k[1..5].to_vec()))?;
+            let height = u32::from_be_bytes(array);
+            let txid = deserialize(&k[5..])?;
+            match self.get_tx(&txid, include_raw)? {
+                Some(tx) if tx.confirmation_time.as_ref().map(|c| c.height) == Some(height) => {
+                    txs.push(tx)
+                }
+                _ => {}
+            }
+        }
+
+        Ok(txs)
     }
 
     fn get_script_pubkey_from_path(
@@ -342,6 +485,66 @@ impl Database for Tree {
             .transpose()
     }
 
+    fn iter_used_marks(&self, keychain: KeychainKind) -> Result<Vec<u32>, Error> {
+        let key = MapKey::UsedMark((keychain, None)).as_map_key();
+        self.scan_prefix(key)
+            .map(|x| -> Result<_, Error> {
+                let (k, _) = x?;
+                let array: [u8; 4] = k[2..]
+                    .try_into()
+                    .map_err(|_| Error::InvalidU32Bytes(k[2..].to_vec()))?;
+                Ok(u32::from_be_bytes(array))
+            })
+            .collect()
+    }
+
+    fn has_used_mark(&self, keychain: KeychainKind, child: u32) -> Result<bool, Error> {
+        let key = MapKey::UsedMark((keychain, Some(child))).as_map_key();
+        Ok(self.contains_key(key)?)
+    }
+
+    fn iter_watched_scripts(&self) -> Result<Vec<Script>, Error> {
+        let key = MapKey::WatchedScript(None).as_map_key();
+        self.scan_prefix(key)
+            .map(|x| -> Result<_, Error> {
+                let (k, _) = x?;
+                Ok(deserialize(&k[1..])?)
+            })
+            .collect()
+    }
+
+    fn get_watched_script(&self, script: &Script) -> Result<Option<Vec<WatchedTx>>, Error> {
+        let key = MapKey::WatchedScript(Some(script)).as_map_key();
+        Ok(self
+            .get(key)?
+            .map(|b| serde_json::from_slice(&b))
+            .transpose()?)
+    }
+
+    fn get_sweep_index(&self, keychain: KeychainKind) -> Result<Option<u32>, Error> {
+        let key = MapKey::SweepIndex(keychain).as_map_key();
+        self.get(key)?
+            .map(|b| -> Result<_, Error> {
+                let array: [u8; 4] = b
+                    .as_ref()
+                    .try_into()
+                    .map_err(|_| Error::InvalidU32Bytes(b.to_vec()))?;
+                let val = u32::from_be_bytes(array);
+                Ok(val)
+            })
+            .transpose()
+    }
+
+    fn iter_swept_utxos(&self) -> Result<Vec<OutPoint>, Error> {
+        let key = MapKey::SweptUtxo(None).as_map_key();
+        self.scan_prefix(key)
+            .map(|x| -> Result<_, Error> {
+                let (k, _) = x?;
+                Ok(deserialize(&k[1..])?)
+            })
+            .collect()
+    }
+
     // inserts 0 if not present
     fn increment_last_index(&mut self, keychain: KeychainKind) -> Result<u32, Error> {
         let key = MapKey::LastIndex(keychain).as_map_key();
@@ -381,6 +584,18 @@ impl BatchDatabase for Tree {
     }
 }
 
+#[maybe_async]
+impl PersistDatabase for Tree {
+    fn persist(&self) -> Result<(), Error> {
+        #[cfg(all(not(target_arch = "wasm32"), not(feature = "async-interface")))]
+        self.flush()?;
+        #[cfg(any(target_arch = "wasm32", feature = "async-interface"))]
+        self.flush_async().await?;
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use lazy_static::lazy_static;
@@ -466,4 +681,29 @@ mod test {
     fn test_last_index() {
         crate::database::test::test_last_index(get_tree());
     }
+
+    #[test]
+    fn test_used_mark() {
+        crate::database::test::test_used_mark(get_tree());
+    }
+
+    #[test]
+    fn test_sweep_progress() {
+        crate::database::test::test_sweep_progress(get_tree());
+    }
+
+    #[test]
+    fn test_iterators() {
+        crate::database::test::test_iterators(get_tree());
+    }
+
+    #[test]
+    fn test_txs_by_height() {
+        crate::database::test::test_txs_by_height(get_tree());
+    }
+
+    #[test]
+    fn test_migrate() {
+        crate::database::test::test_migrate(get_tree());
+    }
 }
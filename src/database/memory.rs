@@ -15,13 +15,16 @@
 //! [`BTreeMap`].
 
 use std::collections::BTreeMap;
+use std::convert::TryInto;
 use std::ops::Bound::{Excluded, Included};
 
 use bitcoin::consensus::encode::{deserialize, serialize};
 use bitcoin::hash_types::Txid;
-use bitcoin::{OutPoint, Script, Transaction};
+use bitcoin::{OutPoint, Script, Transaction, TxOut};
 
-use crate::database::{BatchDatabase, BatchOperations, ConfigurableDatabase, Database};
+use crate::database::{
+    BatchDatabase, BatchOperations, ConfigurableDatabase, Database, DatabaseIter, PersistDatabase,
+};
 use crate::error::Error;
 use crate::types::*;
 
@@ -32,6 +35,12 @@ use crate::types::*;
 // transactions         t<txid> -> tx details
 // deriv indexes        c{i,e} -> u32
 // descriptor checksum  d{i,e} -> vec<u8>
+// used marks           a{i,e}<path> -> ()
+// height index         h<height><txid> -> ()
+// height index marker  H -> ()
+// watched scripts      w<script> -> history
+// sweep indexes        n{i,e} -> u32
+// swept utxos          x<outpoint> -> ()
 
 pub(crate) enum MapKey<'a> {
     Path((Option<KeychainKind>, Option<u32>)),
@@ -41,6 +50,12 @@ pub(crate) enum MapKey<'a> {
     Transaction(Option<&'a Txid>),
     LastIndex(KeychainKind),
     DescriptorChecksum(KeychainKind),
+    UsedMark((KeychainKind, Option<u32>)),
+    TxHeight((Option<u32>, Option<&'a Txid>)),
+    TxHeightIndexed,
+    WatchedScript(Option<&'a Script>),
+    SweepIndex(KeychainKind),
+    SweptUtxo(Option<&'a OutPoint>),
 }
 
 impl MapKey<'_> {
@@ -59,6 +74,12 @@ impl MapKey<'_> {
             MapKey::Transaction(_) => b"t".to_vec(),
             MapKey::LastIndex(st) => [b"c", st.as_ref()].concat(),
             MapKey::DescriptorChecksum(st) => [b"d", st.as_ref()].concat(),
+            MapKey::UsedMark((st, _)) => [b"a", st.as_ref()].concat(),
+            MapKey::TxHeight(_) => b"h".to_vec(),
+            MapKey::TxHeightIndexed => b"H".to_vec(),
+            MapKey::WatchedScript(_) => b"w".to_vec(),
+            MapKey::SweepIndex(st) => [b"n", st.as_ref()].concat(),
+            MapKey::SweptUtxo(_) => b"x".to_vec(),
         }
     }
 
@@ -69,6 +90,16 @@ impl MapKey<'_> {
             MapKey::Utxo(Some(s)) => serialize(*s),
             MapKey::RawTx(Some(s)) => serialize(*s),
             MapKey::Transaction(Some(s)) => serialize(*s),
+            MapKey::UsedMark((_, Some(child))) => child.to_be_bytes().to_vec(),
+            MapKey::WatchedScript(Some(s)) => serialize(*s),
+            MapKey::SweptUtxo(Some(s)) => serialize(*s),
+            MapKey::TxHeight((Some(height), txid)) => {
+                let mut v = height.to_be_bytes().to_vec();
+                if let Some(txid) = txid {
+                    v.extend(serialize(*txid));
+                }
+                v
+            }
             _ => vec![],
         }
     }
@@ -81,7 +112,7 @@ impl MapKey<'_> {
     }
 }
 
-fn after(key: &[u8]) -> Vec<u8> {
+pub(crate) fn after(key: &[u8]) -> Vec<u8> {
     let mut key = key.to_owned();
     let mut idx = key.len();
     while idx > 0 {
@@ -110,7 +141,7 @@ fn after(key: &[u8]) -> Vec<u8> {
 /// [`database`]: crate::database
 #[derive(Debug, Default)]
 pub struct MemoryDatabase {
-    map: BTreeMap<Vec<u8>, Box<dyn std::any::Any>>,
+    map: BTreeMap<Vec<u8>, Box<dyn std::any::Any + Send + Sync>>,
     deleted_keys: Vec<Vec<u8>>,
 }
 
@@ -169,6 +200,14 @@ impl BatchOperations for MemoryDatabase {
         let mut transaction = transaction.clone();
         transaction.transaction = None;
 
+        // index the confirmed txs by height. Stale entries left by a change of height are skipped
+        // when iterating
+        if let Some(confirmation_time) = &transaction.confirmation_time {
+            let key = MapKey::TxHeight((Some(confirmation_time.height), Some(&transaction.txid)))
+                .as_map_key();
+            self.map.insert(key, Box::new(()));
+        }
+
         self.map.insert(key, Box::new(transaction));
 
         Ok(())
@@ -179,6 +218,30 @@ impl BatchOperations for MemoryDatabase {
 
         Ok(())
     }
+    fn set_used_mark(&mut self, keychain: KeychainKind, child: u32) -> Result<(), Error> {
+        let key = MapKey::UsedMark((keychain, Some(child))).as_map_key();
+        self.map.insert(key, Box::new(()));
+
+        Ok(())
+    }
+    fn set_watched_script(&mut self, script: &Script, history: &[WatchedTx]) -> Result<(), Error> {
+        let key = MapKey::WatchedScript(Some(script)).as_map_key();
+        self.map.insert(key, Box::new(history.to_vec()));
+
+        Ok(())
+    }
+    fn set_sweep_index(&mut self, keychain: KeychainKind, value: u32) -> Result<(), Error> {
+        let key = MapKey::SweepIndex(keychain).as_map_key();
+        self.map.insert(key, Box::new(value));
+
+        Ok(())
+    }
+    fn set_swept_utxo(&mut self, outpoint: &OutPoint) -> Result<(), Error> {
+        let key = MapKey::SweptUtxo(Some(outpoint)).as_map_key();
+        self.map.insert(key, Box::new(()));
+
+        Ok(())
+    }
 
     fn del_script_pubkey_from_path(
         &mut self,
@@ -255,6 +318,13 @@ impl BatchOperations for MemoryDatabase {
                 let mut val: TransactionDetails = b.downcast_ref().cloned().unwrap();
                 val.transaction = raw_tx;
 
+                if let Some(confirmation_time) = &val.confirmation_time {
+                    let key =
+                        MapKey::TxHeight((Some(confirmation_time.height), Some(txid))).as_map_key();
+                    self.map.remove(&key);
+                    self.deleted_keys.push(key);
+                }
+
                 Ok(Some(val))
             }
         }
@@ -269,6 +339,37 @@ impl BatchOperations for MemoryDatabase {
             Some(b) => Ok(Some(*b.downcast_ref().unwrap())),
         }
     }
+    fn del_used_mark(&mut self, keychain: KeychainKind, child: u32) -> Result<bool, Error> {
+        let key = MapKey::UsedMark((keychain, Some(child))).as_map_key();
+        let res = self.map.remove(&key);
+        self.deleted_keys.push(key);
+
+        Ok(res.is_some())
+    }
+    fn del_watched_script(&mut self, script: &Script) -> Result<Option<Vec<WatchedTx>>, Error> {
+        let key = MapKey::WatchedScript(Some(script)).as_map_key();
+        let res = self.map.remove(&key);
+        self.deleted_keys.push(key);
+
+        Ok(res.map(|x| x.downcast_ref().cloned().unwrap()))
+    }
+    fn del_sweep_index(&mut self, keychain: KeychainKind) -> Result<Option<u32>, Error> {
+        let key = MapKey::SweepIndex(keychain).as_map_key();
+        let res = self.map.remove(&key);
+        self.deleted_keys.push(key);
+
+        match res {
+            None => Ok(None),
+            Some(b) => Ok(Some(*b.downcast_ref().unwrap())),
+        }
+    }
+    fn del_swept_utxo(&mut self, outpoint: &OutPoint) -> Result<bool, Error> {
+        let key = MapKey::SweptUtxo(Some(outpoint)).as_map_key();
+        let res = self.map.remove(&key);
+        self.deleted_keys.push(key);
+
+        Ok(res.is_some())
+    }
 }
 
 impl Database for MemoryDatabase {
@@ -295,6 +396,14 @@ impl Database for MemoryDatabase {
         }
     }
 
+    fn get_descriptor_checksum(&self, keychain: KeychainKind) -> Result<Option<Vec<u8>>, Error> {
+        let key = MapKey::DescriptorChecksum(keychain).as_map_key();
+        Ok(self
+            .map
+            .get(&key)
+            .map(|b| b.downcast_ref().cloned().unwrap()))
+    }
+
     fn iter_script_pubkeys(&self, keychain: Option<KeychainKind>) -> Result<Vec<Script>, Error> {
         let key = MapKey::Path((keychain, None)).as_map_key();
         self.map
@@ -304,43 +413,95 @@ impl Database for MemoryDatabase {
     }
 
     fn iter_utxos(&self) -> Result<Vec<LocalUtxo>, Error> {
-        let key = MapKey::Utxo(None).as_map_key();
-        self.map
-            .range::<Vec<u8>, _>((Included(&key), Excluded(&after(&key))))
-            .map(|(k, v)| {
-                let outpoint = deserialize(&k[1..]).unwrap();
-                let (txout, keychain) = v.downcast_ref().cloned().unwrap();
-                Ok(LocalUtxo {
-                    outpoint,
-                    txout,
-                    keychain,
-                })
-            })
-            .collect()
+        self.utxos_iter(None).collect()
     }
 
     fn iter_raw_txs(&self) -> Result<Vec<Transaction>, Error> {
-        let key = MapKey::RawTx(None).as_map_key();
-        self.map
-            .range::<Vec<u8>, _>((Included(&key), Excluded(&after(&key))))
-            .map(|(_, v)| Ok(v.downcast_ref().cloned().unwrap()))
-            .collect()
+        self.raw_txs_iter().collect()
     }
 
     fn iter_txs(&self, include_raw: bool) -> Result<Vec<TransactionDetails>, Error> {
+        self.txs_iter(include_raw).collect()
+    }
+
+    fn utxos_iter(&self, keychain: Option<KeychainKind>) -> DatabaseIter<'_, LocalUtxo> {
+        let key = MapKey::Utxo(None).as_map_key();
+        Box::new(
+            self.map
+                .range::<Vec<u8>, _>((Included(&key), Excluded(&after(&key))))
+                .filter_map(move |(k, v)| {
+                    let (txout, utxo_keychain): &(TxOut, KeychainKind) = v.downcast_ref().unwrap();
+                    if keychain.is_some() && keychain != Some(*utxo_keychain) {
+                        return None;
+                    }
+
+                    let utxo = deserialize(&k[1..]).map(|outpoint| LocalUtxo {
+                        outpoint,
+                        txout: txout.clone(),
+                        keychain: *utxo_keychain,
+                    });
+                    Some(utxo.map_err(Error::from))
+                }),
+        )
+    }
+
+    fn raw_txs_iter(&self) -> DatabaseIter<'_, Transaction> {
+        let key = MapKey::RawTx(None).as_map_key();
+        Box::new(
+            self.map
+                .range::<Vec<u8>, _>((Included(&key), Excluded(&after(&key))))
+                .map(|(_, v)| Ok(v.downcast_ref().cloned().unwrap())),
+        )
+    }
+
+    fn txs_iter(&self, include_raw: bool) -> DatabaseIter<'_, TransactionDetails> {
         let key = MapKey::Transaction(None).as_map_key();
-        self.map
-            .range::<Vec<u8>, _>((Included(&key), Excluded(&after(&key))))
-            .map(|(k, v)| {
-                let mut txdetails: TransactionDetails = v.downcast_ref().cloned().unwrap();
-                if include_raw {
-                    let txid = deserialize(&k[1..])?;
-                    txdetails.transaction = self.get_raw_tx(&txid)?;
+        Box::new(
+            self.map
+                .range::<Vec<u8>, _>((Included(&key), Excluded(&after(&key))))
+                .map(move |(k, v)| {
+                    let mut txdetails: TransactionDetails = v.downcast_ref().cloned().unwrap();
+                    if include_raw {
+                        let txid = deserialize(&k[1..])?;
+                        txdetails.transaction = self.get_raw_tx(&txid)?;
+                    }
+
+                    Ok(txdetails)
+                }),
+        )
+    }
+
+    fn iter_txs_by_height(
+        &self,
+        from_height: u32,
+        to_height: u32,
+        include_raw: bool,
+    ) -> Result<Vec<TransactionDetails>, Error> {
+        if from_height > to_height {
+            return Ok(vec![]);
+        }
+
+        let from = MapKey::TxHeight((Some(from_height), None)).as_map_key();
+        let to = MapKey::TxHeight((Some(to_height), None)).as_map_key();
+        let mut txs = vec![];
+        for (k, _) in self
+            .map
+            .range::<Vec<u8>, _>((Included(&from), Excluded(&after(&to))))
+        {
+            let array: [u8; 4] = k[1..5]
+                .try_into()
+                .map_err(|_| Error::InvalidU32Bytes(k[1..5].to_vec()))?;
+            let height = u32::from_be_bytes(array);
+            let txid = deserialize(&k[5..])?;
+            match self.get_tx(&txid, include_raw)? {
+                Some(tx) if tx.confirmation_time.as_ref().map(|c| c.height) == Some(height) => {
+                    txs.push(tx)
                 }
+                _ => {}
+            }
+        }
 
-                Ok(txdetails)
-            })
-            .collect()
+        Ok(txs)
     }
 
     fn get_script_pubkey_from_path(
@@ -406,6 +567,53 @@ impl Database for MemoryDatabase {
         Ok(self.map.get(&key).map(|b| *b.downcast_ref().unwrap()))
     }
 
+    fn iter_used_marks(&self, keychain: KeychainKind) -> Result<Vec<u32>, Error> {
+        let key = MapKey::UsedMark((keychain, None)).as_map_key();
+        self.map
+            .range::<Vec<u8>, _>((Included(&key), Excluded(&after(&key))))
+            .map(|(k, _)| {
+                let array: [u8; 4] = k[2..]
+                    .try_into()
+                    .map_err(|_| Error::InvalidU32Bytes(k[2..].to_vec()))?;
+                Ok(u32::from_be_bytes(array))
+            })
+            .collect()
+    }
+
+    fn has_used_mark(&self, keychain: KeychainKind, child: u32) -> Result<bool, Error> {
+        let key = MapKey::UsedMark((keychain, Some(child))).as_map_key();
+        Ok(self.map.contains_key(&key))
+    }
+
+    fn iter_watched_scripts(&self) -> Result<Vec<Script>, Error> {
+        let key = MapKey::WatchedScript(None).as_map_key();
+        self.map
+            .range::<Vec<u8>, _>((Included(&key), Excluded(&after(&key))))
+            .map(|(k, _)| Ok(deserialize(&k[1..])?))
+            .collect()
+    }
+
+    fn get_watched_script(&self, script: &Script) -> Result<Option<Vec<WatchedTx>>, Error> {
+        let key = MapKey::WatchedScript(Some(script)).as_map_key();
+        Ok(self
+            .map
+            .get(&key)
+            .map(|b| b.downcast_ref().cloned().unwrap()))
+    }
+
+    fn get_sweep_index(&self, keychain: KeychainKind) -> Result<Option<u32>, Error> {
+        let key = MapKey::SweepIndex(keychain).as_map_key();
+        Ok(self.map.get(&key).map(|b| *b.downcast_ref().unwrap()))
+    }
+
+    fn iter_swept_utxos(&self) -> Result<Vec<OutPoint>, Error> {
+        let key = MapKey::SweptUtxo(None).as_map_key();
+        self.map
+            .range::<Vec<u8>, _>((Included(&key), Excluded(&after(&key))))
+            .map(|(k, _)| Ok(deserialize(&k[1..])?))
+            .collect()
+    }
+
     // inserts 0 if not present
     fn increment_last_index(&mut self, keychain: KeychainKind) -> Result<u32, Error> {
         let key = MapKey::LastIndex(keychain).as_map_key();
@@ -437,6 +645,14 @@ impl BatchDatabase for MemoryDatabase {
     }
 }
 
+#[maybe_async]
+impl PersistDatabase for MemoryDatabase {
+    fn persist(&self) -> Result<(), Error> {
+        // nothing to persist, the data only lives in memory
+        Ok(())
+    }
+}
+
 impl ConfigurableDatabase for MemoryDatabase {
     type Config = ();
 
@@ -486,6 +702,7 @@ macro_rules! populate_test_db {
             sent: 0,
             confirmation_time,
             verified: current_height.is_some(),
+            last_evicted: None,
         };
 
         db.set_tx(&tx_details).unwrap();
@@ -582,4 +799,29 @@ mod test {
     fn test_last_index() {
         crate::database::test::test_last_index(get_tree());
     }
+
+    #[test]
+    fn test_used_mark() {
+        crate::database::test::test_used_mark(get_tree());
+    }
+
+    #[test]
+    fn test_sweep_progress() {
+        crate::database::test::test_sweep_progress(get_tree());
+    }
+
+    #[test]
+    fn test_iterators() {
+        crate::database::test::test_iterators(get_tree());
+    }
+
+    #[test]
+    fn test_txs_by_height() {
+        crate::database::test::test_txs_by_height(get_tree());
+    }
+
+    #[test]
+    fn test_migrate() {
+        crate::database::test::test_migrate(get_tree());
+    }
 }
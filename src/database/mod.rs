@@ -24,6 +24,8 @@
 //!
 //! [`Wallet`]: crate::wallet::Wallet
 
+use std::collections::{HashMap, HashSet};
+
 use bitcoin::hash_types::Txid;
 use bitcoin::{OutPoint, Script, Transaction, TxOut};
 
@@ -59,6 +61,14 @@ pub trait BatchOperations {
     fn set_tx(&mut self, transaction: &TransactionDetails) -> Result<(), Error>;
     /// Store the last derivation index for a given keychain.
     fn set_last_index(&mut self, keychain: KeychainKind, value: u32) -> Result<(), Error>;
+    /// Mark the script_pubkey at a given keychain and child number as used.
+    fn set_used_mark(&mut self, keychain: KeychainKind, child: u32) -> Result<(), Error>;
+    /// Add a script_pubkey to the watchlist, or replace its history if already present
+    fn set_watched_script(&mut self, script: &Script, history: &[WatchedTx]) -> Result<(), Error>;
+    /// Store the next derivation index used as the destination of a sweep for a keychain
+    fn set_sweep_index(&mut self, keychain: KeychainKind, value: u32) -> Result<(), Error>;
+    /// Mark an [`OutPoint`] as already spent by a sweep
+    fn set_swept_utxo(&mut self, outpoint: &OutPoint) -> Result<(), Error>;
 
     /// Delete a script_pubkey given the keychain and its child number.
     fn del_script_pubkey_from_path(
@@ -84,8 +94,19 @@ pub trait BatchOperations {
     ) -> Result<Option<TransactionDetails>, Error>;
     /// Delete the last derivation index for a keychain.
     fn del_last_index(&mut self, keychain: KeychainKind) -> Result<Option<u32>, Error>;
+    /// Delete the "used" mark for a keychain and child number, returning whether it was present.
+    fn del_used_mark(&mut self, keychain: KeychainKind, child: u32) -> Result<bool, Error>;
+    /// Remove a script_pubkey from the watchlist, returning its history
+    fn del_watched_script(&mut self, script: &Script) -> Result<Option<Vec<WatchedTx>>, Error>;
+    /// Delete the next sweep derivation index for a keychain.
+    fn del_sweep_index(&mut self, keychain: KeychainKind) -> Result<Option<u32>, Error>;
+    /// Delete the "swept" mark of an [`OutPoint`], returning whether it was present.
+    fn del_swept_utxo(&mut self, outpoint: &OutPoint) -> Result<bool, Error>;
 }
 
+/// Boxed iterator over the items read from a database
+pub type DatabaseIter<'a, T> = Box<dyn Iterator<Item = Result<T, Error>> + 'a>;
+
 /// Trait for reading data from a database
 ///
 /// This traits defines the operations that can be used to read data out of a database
@@ -101,6 +122,9 @@ pub trait Database: BatchOperations {
         bytes: B,
     ) -> Result<(), Error>;
 
+    /// Return the descriptor checksum stored for a keychain
+    fn get_descriptor_checksum(&self, keychain: KeychainKind) -> Result<Option<Vec<u8>>, Error>;
+
     /// Return the list of script_pubkeys
     fn iter_script_pubkeys(&self, keychain: Option<KeychainKind>) -> Result<Vec<Script>, Error>;
     /// Return the list of [`LocalUtxo`]s
@@ -108,7 +132,27 @@ pub trait Database: BatchOperations {
     /// Return the list of raw transactions
     fn iter_raw_txs(&self) -> Result<Vec<Transaction>, Error>;
     /// Return the list of transactions metadata
+    ///
+    /// Transactions evicted from the mempool are included, with their
+    /// [`TransactionDetails::last_evicted`] field set.
     fn iter_txs(&self, include_raw: bool) -> Result<Vec<TransactionDetails>, Error>;
+    /// Iterate over the [`LocalUtxo`]s, optionally only the ones of `keychain`
+    ///
+    /// Unlike [`Database::iter_utxos`] the utxos are read one at a time, which avoids loading
+    /// all of them in memory at once.
+    fn utxos_iter(&self, keychain: Option<KeychainKind>) -> DatabaseIter<'_, LocalUtxo>;
+    /// Iterate over the raw transactions, reading them one at a time
+    fn raw_txs_iter(&self) -> DatabaseIter<'_, Transaction>;
+    /// Iterate over the transactions metadata, reading them one at a time
+    fn txs_iter(&self, include_raw: bool) -> DatabaseIter<'_, TransactionDetails>;
+    /// Return the metadata of the transactions confirmed between `from_height` and `to_height`,
+    /// both included, sorted by confirmation height
+    fn iter_txs_by_height(
+        &self,
+        from_height: u32,
+        to_height: u32,
+        include_raw: bool,
+    ) -> Result<Vec<TransactionDetails>, Error>;
 
     /// Fetch a script_pubkey given the child number of a keychain.
     fn get_script_pubkey_from_path(
@@ -126,9 +170,24 @@ pub trait Database: BatchOperations {
     /// Fetch a raw transaction given its [`Txid`]
     fn get_raw_tx(&self, txid: &Txid) -> Result<Option<Transaction>, Error>;
     /// Fetch the transaction metadata and optionally also the raw transaction
+    ///
+    /// Transactions evicted from the mempool are returned too: callers that need a transaction
+    /// that can still be mined must check [`TransactionDetails::last_evicted`].
     fn get_tx(&self, txid: &Txid, include_raw: bool) -> Result<Option<TransactionDetails>, Error>;
     /// Return the last defivation index for a keychain.
     fn get_last_index(&self, keychain: KeychainKind) -> Result<Option<u32>, Error>;
+    /// Return the child numbers that have been explicitly marked as used for a keychain
+    fn iter_used_marks(&self, keychain: KeychainKind) -> Result<Vec<u32>, Error>;
+    /// Return whether the child number of a keychain has been explicitly marked as used
+    fn has_used_mark(&self, keychain: KeychainKind, child: u32) -> Result<bool, Error>;
+    /// Return the script_pubkeys of the watchlist
+    fn iter_watched_scripts(&self) -> Result<Vec<Script>, Error>;
+    /// Fetch the history of a script_pubkey of the watchlist
+    fn get_watched_script(&self, script: &Script) -> Result<Option<Vec<WatchedTx>>, Error>;
+    /// Return the next sweep derivation index for a keychain.
+    fn get_sweep_index(&self, keychain: KeychainKind) -> Result<Option<u32>, Error>;
+    /// Return the [`OutPoint`]s that have been marked as already spent by a sweep
+    fn iter_swept_utxos(&self) -> Result<Vec<OutPoint>, Error>;
 
     /// Increment the last derivation index for a keychain and return it
     ///
@@ -149,6 +208,17 @@ pub trait BatchDatabase: Database {
     fn commit_batch(&mut self, batch: Self::Batch) -> Result<(), Error>;
 }
 
+/// Trait for [`Database`] types that buffer their writes and can explicitly persist them
+///
+/// When the `async-interface` feature is enabled (or when targeting `wasm32`) the method of this
+/// trait is `async`, so that applications running on an async runtime don't have to block while
+/// waiting for the data to be written to disk.
+#[maybe_async]
+pub trait PersistDatabase: Database {
+    /// Persist every pending write, returning once the data has been durably stored
+    fn persist(&self) -> Result<(), Error>;
+}
+
 /// Trait for [`Database`] types that can be created given a configuration
 pub trait ConfigurableDatabase: Database + Sized {
     /// Type that contains the configuration
@@ -189,6 +259,327 @@ pub(crate) trait DatabaseUtils: Database {
 
 impl<T: Database> DatabaseUtils for T {}
 
+/// Copy the content of the database `from` into the database `to`
+///
+/// This can be used to move an existing wallet to a different backend, for example from a
+/// [`MemoryDatabase`] to a [`sled::Tree`], without having to sync it again from scratch.
+///
+/// The descriptor checksums are copied too, so creating a [`Wallet`](crate::wallet::Wallet) with
+/// different descriptors on `to` fails with [`Error::ChecksumMismatch`], as it would on `from`.
+/// This also fails if `to` already stores the checksums of different descriptors.
+pub fn migrate<S: Database, D: BatchDatabase>(from: &S, to: &mut D) -> Result<(), Error> {
+    for keychain in &[KeychainKind::External, KeychainKind::Internal] {
+        if let Some(checksum) = from.get_descriptor_checksum(*keychain)? {
+            to.check_descriptor_checksum(*keychain, checksum)?;
+        }
+    }
+
+    let mut batch = to.begin_batch();
+
+    for script in from.iter_script_pubkeys(None)? {
+        if let Some((keychain, child)) = from.get_path_from_script_pubkey(&script)? {
+            batch.set_script_pubkey(&script, keychain, child)?;
+        }
+    }
+    for utxo in from.iter_utxos()? {
+        batch.set_utxo(&utxo)?;
+    }
+    for tx in from.iter_raw_txs()? {
+        batch.set_raw_tx(&tx)?;
+    }
+    for tx in from.iter_txs(false)? {
+        batch.set_tx(&tx)?;
+    }
+    for keychain in &[KeychainKind::External, KeychainKind::Internal] {
+        if let Some(index) = from.get_last_index(*keychain)? {
+            batch.set_last_index(*keychain, index)?;
+        }
+        for child in from.iter_used_marks(*keychain)? {
+            batch.set_used_mark(*keychain, child)?;
+        }
+        if let Some(index) = from.get_sweep_index(*keychain)? {
+            batch.set_sweep_index(*keychain, index)?;
+        }
+    }
+    for script in from.iter_watched_scripts()? {
+        let history = from.get_watched_script(&script)?.unwrap_or_default();
+        batch.set_watched_script(&script, &history)?;
+    }
+    for outpoint in from.iter_swept_utxos()? {
+        batch.set_swept_utxo(&outpoint)?;
+    }
+
+    to.commit_batch(batch)
+}
+
+// Mark the transactions in `txids` and their descendants as evicted from the mempool at
+// `timestamp`, returning the ids of the transactions that weren't already evicted
+//
+// The outputs of the evicted transactions are removed from the utxos, while the outputs of the
+// wallet they spent are restored, unless they are also spent by a transaction that wasn't evicted.
+pub(crate) fn evict_txs<D: BatchDatabase>(
+    db: &mut D,
+    txids: &[Txid],
+    timestamp: u64,
+) -> Result<Vec<Txid>, Error> {
+    let txs = db
+        .iter_txs(true)?
+        .into_iter()
+        .filter(|tx| tx.last_evicted.is_none())
+        .map(|tx| (tx.txid, tx))
+        .collect::<HashMap<_, _>>();
+
+    let mut evicted = HashSet::new();
+    let mut queue = txids.to_vec();
+    while let Some(txid) = queue.pop() {
+        if !txs.contains_key(&txid) || !evicted.insert(txid) {
+            continue;
+        }
+
+        // The descendants of an evicted transaction can't be valid either
+        queue.extend(
+            txs.values()
+                .filter(|tx| {
+                    tx.transaction.as_ref().map_or(false, |tx| {
+                        tx.input
+                            .iter()
+                            .any(|input| input.previous_output.txid == txid)
+                    })
+                })
+                .map(|tx| tx.txid),
+        );
+    }
+
+    let still_spent = txs
+        .values()
+        .filter(|tx| !evicted.contains(&tx.txid))
+        .filter_map(|tx| tx.transaction.as_ref())
+        .flat_map(|tx| tx.input.iter().map(|input| input.previous_output))
+        .collect::<HashSet<_>>();
+
+    let mut batch = db.begin_batch();
+    for utxo in db.iter_utxos()? {
+        if evicted.contains(&utxo.outpoint.txid) {
+            batch.del_utxo(&utxo.outpoint)?;
+        }
+    }
+    for txid in &evicted {
+        let mut details = txs[txid].clone();
+
+        for input in details.transaction.iter().flat_map(|tx| tx.input.iter()) {
+            let outpoint = input.previous_output;
+            if evicted.contains(&outpoint.txid)
+                || still_spent.contains(&outpoint)
+                || !txs.contains_key(&outpoint.txid)
+            {
+                continue;
+            }
+
+            if let Some(txout) = db.get_previous_output(&outpoint)? {
+                if let Some((keychain, _)) = db.get_path_from_script_pubkey(&txout.script_pubkey)? {
+                    batch.set_utxo(&LocalUtxo {
+                        outpoint,
+                        txout,
+                        keychain,
+                    })?;
+                }
+            }
+        }
+
+        details.confirmation_time = None;
+        details.last_evicted = Some(timestamp);
+        batch.set_tx(&details)?;
+    }
+    db.commit_batch(batch)?;
+
+    Ok(evicted.into_iter().collect())
+}
+
+/// Transactions affected by [`rollback_to`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RollbackReport {
+    /// Transactions confirmed in a disconnected block that are now unconfirmed
+    pub unconfirmed: Vec<Txid>,
+    /// Coinbase transactions of the disconnected blocks and their descendants, which can't be
+    /// valid anymore and have been marked as evicted
+    pub evicted: Vec<Txid>,
+}
+
+/// Disconnect the blocks above `height`, for example after a reorganization of the chain
+///
+/// The transactions confirmed in the disconnected blocks become unconfirmed, so that they are
+/// confirmed again by the next sync if they are included in the new chain. The coinbase
+/// transactions of the disconnected blocks, and their descendants, are marked as evicted at
+/// `timestamp` because they can't be included in a different block.
+pub fn rollback_to<D: BatchDatabase>(
+    db: &mut D,
+    height: u32,
+    timestamp: u64,
+) -> Result<RollbackReport, Error> {
+    let disconnected = db
+        .iter_txs(true)?
+        .into_iter()
+        .filter(|tx| tx.last_evicted.is_none())
+        .filter(|tx| {
+            tx.confirmation_time
+                .as_ref()
+                .map_or(false, |c| c.height > height)
+        })
+        .collect::<Vec<_>>();
+    let coinbases = disconnected
+        .iter()
+        .filter(|tx| {
+            tx.transaction
+                .as_ref()
+                .map_or(false, |tx| tx.is_coin_base())
+        })
+        .map(|tx| tx.txid)
+        .collect::<Vec<_>>();
+
+    let mut batch = db.begin_batch();
+    for tx in &disconnected {
+        let mut details = tx.clone();
+        details.transaction = None;
+        details.confirmation_time = None;
+        batch.set_tx(&details)?;
+    }
+    db.commit_batch(batch)?;
+
+    let evicted = evict_txs(db, &coinbases, timestamp)?;
+    let unconfirmed = disconnected
+        .into_iter()
+        .map(|tx| tx.txid)
+        .filter(|txid| !evicted.contains(txid))
+        .collect();
+
+    Ok(RollbackReport {
+        unconfirmed,
+        evicted,
+    })
+}
+
+/// Options for [`prune`]
+///
+/// By default nothing is removed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PruneOptions {
+    /// Remove the transactions evicted from the mempool before this timestamp
+    ///
+    /// See [`TransactionDetails::last_evicted`].
+    pub evicted_before: Option<u64>,
+    /// Remove the raw transactions confirmed at or below this height, if none of their outputs is
+    /// unspent and the transactions spending them are also confirmed at or below this height
+    ///
+    /// The metadata of the transactions is kept, but [`TransactionDetails::transaction`] will be
+    /// `None`.
+    pub spent_confirmed_at: Option<u32>,
+    /// Remove the raw transactions that were only stored to compute the fees of the wallet's
+    /// confirmed transactions
+    pub irrelevant_raw_txs: bool,
+}
+
+/// Transactions removed by [`prune`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PruneReport {
+    /// Transactions removed from the database, including their metadata
+    pub removed_txs: Vec<Txid>,
+    /// Transactions whose raw transaction was removed, while their metadata is kept
+    pub removed_raw_txs: Vec<Txid>,
+}
+
+/// Remove the transactions that are not needed anymore from the database
+///
+/// This can be used to keep the database of long-running wallets from growing without bounds. The
+/// utxos of the wallet are never affected.
+pub fn prune<D: BatchDatabase>(db: &mut D, options: &PruneOptions) -> Result<PruneReport, Error> {
+    let txs = db
+        .iter_txs(false)?
+        .into_iter()
+        .map(|tx| (tx.txid, tx))
+        .collect::<HashMap<_, _>>();
+    let raw_txs = db
+        .iter_raw_txs()?
+        .into_iter()
+        .map(|tx| (tx.txid(), tx))
+        .collect::<HashMap<_, _>>();
+    let utxo_txids = db
+        .iter_utxos()?
+        .into_iter()
+        .map(|utxo| utxo.outpoint.txid)
+        .collect::<HashSet<_>>();
+
+    let confirmed_at = |txid: &Txid, height: u32| {
+        txs.get(txid)
+            .and_then(|tx| tx.confirmation_time.as_ref())
+            .map_or(false, |confirmation_time| {
+                confirmation_time.height <= height
+            })
+    };
+    // Transactions of the wallet spending the outputs of every transaction
+    let mut spenders = HashMap::<Txid, Vec<Txid>>::new();
+    for (txid, tx) in &raw_txs {
+        if !txs.contains_key(txid) {
+            continue;
+        }
+        for input in &tx.input {
+            spenders
+                .entry(input.previous_output.txid)
+                .or_default()
+                .push(*txid);
+        }
+    }
+
+    let mut report = PruneReport::default();
+    let mut batch = db.begin_batch();
+    for (txid, tx) in &txs {
+        let evicted = match (tx.last_evicted, options.evicted_before) {
+            (Some(last_evicted), Some(before)) => last_evicted < before,
+            _ => false,
+        };
+        if evicted {
+            batch.del_tx(txid, true)?;
+            report.removed_txs.push(*txid);
+            continue;
+        }
+
+        if let Some(height) = options.spent_confirmed_at {
+            if raw_txs.contains_key(txid)
+                && confirmed_at(txid, height)
+                && !utxo_txids.contains(txid)
+                && spenders.get(txid).map_or(true, |spenders| {
+                    spenders.iter().all(|s| confirmed_at(s, height))
+                })
+            {
+                batch.del_raw_tx(txid)?;
+                report.removed_raw_txs.push(*txid);
+            }
+        }
+    }
+
+    if options.irrelevant_raw_txs {
+        // The previous transactions of the unconfirmed transactions are still needed to bump their
+        // fees
+        let needed = raw_txs
+            .iter()
+            .filter(|(txid, _)| {
+                txs.get(txid).map_or(false, |tx| {
+                    tx.confirmation_time.is_none() && tx.last_evicted.is_none()
+                })
+            })
+            .flat_map(|(_, tx)| tx.input.iter().map(|input| input.previous_output.txid))
+            .collect::<HashSet<_>>();
+        for txid in raw_txs.keys() {
+            if !txs.contains_key(txid) && !needed.contains(txid) {
+                batch.del_raw_tx(txid)?;
+                report.removed_raw_txs.push(*txid);
+            }
+        }
+    }
+    db.commit_batch(batch)?;
+
+    Ok(report)
+}
+
 #[cfg(test)]
 pub mod test {
     use std::str::FromStr;
@@ -322,6 +713,7 @@ pub mod test {
                 height: 1000,
             }),
             verified: true,
+            last_evicted: None,
         };
 
         tree.set_tx(&tx_details).unwrap();
@@ -369,5 +761,279 @@ pub mod test {
         );
     }
 
+    pub fn test_used_mark<D: Database>(mut tree: D) {
+        tree.set_used_mark(KeychainKind::External, 3).unwrap();
+        tree.set_used_mark(KeychainKind::External, 1).unwrap();
+
+        assert!(tree.has_used_mark(KeychainKind::External, 1).unwrap());
+        assert!(!tree.has_used_mark(KeychainKind::External, 2).unwrap());
+        assert!(!tree.has_used_mark(KeychainKind::Internal, 1).unwrap());
+        assert_eq!(
+            tree.iter_used_marks(KeychainKind::External).unwrap(),
+            vec![1, 3]
+        );
+        assert!(tree
+            .iter_used_marks(KeychainKind::Internal)
+            .unwrap()
+            .is_empty());
+
+        assert!(tree.del_used_mark(KeychainKind::External, 1).unwrap());
+        assert!(!tree.del_used_mark(KeychainKind::External, 1).unwrap());
+        assert_eq!(
+            tree.iter_used_marks(KeychainKind::External).unwrap(),
+            vec![3]
+        );
+    }
+
+    pub fn test_sweep_progress<D: Database>(mut tree: D) {
+        let outpoint_a = OutPoint::from_str(
+            "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:0",
+        )
+        .unwrap();
+        let outpoint_b = OutPoint::from_str(
+            "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:1",
+        )
+        .unwrap();
+
+        assert_eq!(tree.get_sweep_index(KeychainKind::External).unwrap(), None);
+        tree.set_sweep_index(KeychainKind::External, 2).unwrap();
+        assert_eq!(
+            tree.get_sweep_index(KeychainKind::External).unwrap(),
+            Some(2)
+        );
+        assert_eq!(tree.get_sweep_index(KeychainKind::Internal).unwrap(), None);
+
+        tree.set_swept_utxo(&outpoint_b).unwrap();
+        tree.set_swept_utxo(&outpoint_a).unwrap();
+        assert_eq!(
+            tree.iter_swept_utxos().unwrap(),
+            vec![outpoint_a, outpoint_b]
+        );
+
+        assert_eq!(
+            tree.del_sweep_index(KeychainKind::External).unwrap(),
+            Some(2)
+        );
+        assert_eq!(tree.get_sweep_index(KeychainKind::External).unwrap(), None);
+        assert!(tree.del_swept_utxo(&outpoint_a).unwrap());
+        assert!(!tree.del_swept_utxo(&outpoint_a).unwrap());
+        assert_eq!(tree.iter_swept_utxos().unwrap(), vec![outpoint_b]);
+    }
+
+    pub fn test_iterators<D: Database>(mut tree: D) {
+        let hex_tx = Vec::<u8>::from_hex("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap();
+        let tx: Transaction = deserialize(&hex_tx).unwrap();
+        let tx_details = TransactionDetails {
+            transaction: Some(tx.clone()),
+            txid: tx.txid(),
+            received: 1337,
+            sent: 0,
+            fee: Some(140),
+            confirmation_time: None,
+            verified: true,
+            last_evicted: None,
+        };
+        let utxo = |vout, keychain| LocalUtxo {
+            txout: tx.output[0].clone(),
+            outpoint: OutPoint::new(tx.txid(), vout),
+            keychain,
+        };
+
+        assert!(tree.txs_iter(true).next().is_none());
+
+        tree.set_tx(&tx_details).unwrap();
+        tree.set_utxo(&utxo(0, KeychainKind::External)).unwrap();
+        tree.set_utxo(&utxo(1, KeychainKind::Internal)).unwrap();
+        tree.set_utxo(&utxo(2, KeychainKind::External)).unwrap();
+
+        assert_eq!(
+            tree.txs_iter(true).collect::<Result<Vec<_>, _>>().unwrap(),
+            vec![tx_details.clone()]
+        );
+        assert_eq!(
+            tree.txs_iter(false).next().unwrap().unwrap().transaction,
+            None
+        );
+        assert_eq!(
+            tree.raw_txs_iter().collect::<Result<Vec<_>, _>>().unwrap(),
+            vec![tx]
+        );
+        assert_eq!(tree.utxos_iter(None).count(), 3);
+        assert_eq!(
+            tree.utxos_iter(Some(KeychainKind::External))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            vec![
+                utxo(0, KeychainKind::External),
+                utxo(2, KeychainKind::External)
+            ]
+        );
+        assert_eq!(
+            tree.utxos_iter(Some(KeychainKind::Internal))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            vec![utxo(1, KeychainKind::Internal)]
+        );
+    }
+
+    pub fn test_txs_by_height<D: Database>(mut tree: D) {
+        let hex_tx = Vec::<u8>::from_hex("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap();
+        let tx: Transaction = deserialize(&hex_tx).unwrap();
+        let details = |lock_time: u32, height: Option<u32>| {
+            let mut tx = tx.clone();
+            tx.lock_time = lock_time;
+            TransactionDetails {
+                txid: tx.txid(),
+                transaction: Some(tx),
+                received: 1337,
+                sent: 0,
+                fee: Some(140),
+                confirmation_time: height.map(|height| ConfirmationTime {
+                    timestamp: 123456,
+                    height,
+                }),
+                verified: true,
+                last_evicted: None,
+            }
+        };
+        let txids = |txs: Vec<TransactionDetails>| txs.iter().map(|tx| tx.txid).collect::<Vec<_>>();
+
+        let tx_a = details(1, Some(1000));
+        let tx_b = details(2, Some(1005));
+        let tx_c = details(3, Some(1010));
+        let tx_d = details(4, None);
+        for tx in &[&tx_c, &tx_a, &tx_b, &tx_d] {
+            tree.set_tx(tx).unwrap();
+        }
+
+        assert_eq!(
+            txids(tree.iter_txs_by_height(1000, 1005, false).unwrap()),
+            vec![tx_a.txid, tx_b.txid]
+        );
+        assert_eq!(
+            txids(tree.iter_txs_by_height(0, u32::MAX, false).unwrap()),
+            vec![tx_a.txid, tx_b.txid, tx_c.txid]
+        );
+        assert!(tree
+            .iter_txs_by_height(1011, 2000, false)
+            .unwrap()
+            .is_empty());
+        assert!(tree
+            .iter_txs_by_height(1005, 1000, false)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            tree.iter_txs_by_height(1010, 1010, true).unwrap(),
+            vec![tx_c.clone()]
+        );
+
+        // a reorg moves `tx_b` to a different height and `tx_c` back to the mempool
+        let tx_b = details(2, Some(1020));
+        tree.set_tx(&tx_b).unwrap();
+        tree.set_tx(&details(3, None)).unwrap();
+        assert_eq!(
+            txids(tree.iter_txs_by_height(1000, 1010, false).unwrap()),
+            vec![tx_a.txid]
+        );
+        assert_eq!(
+            txids(tree.iter_txs_by_height(1011, 1020, false).unwrap()),
+            vec![tx_b.txid]
+        );
+
+        tree.del_tx(&tx_a.txid, false).unwrap();
+        assert!(tree
+            .iter_txs_by_height(1000, 1010, false)
+            .unwrap()
+            .is_empty());
+    }
+
+    pub fn test_migrate<D: Database>(mut tree: D) {
+        let script = Script::from(
+            Vec::<u8>::from_hex("76a91402306a7c23f3e8010de41e9e591348bb83f11daa88ac").unwrap(),
+        );
+        let hex_tx = Vec::<u8>::from_hex("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap();
+        let tx: Transaction = deserialize(&hex_tx).unwrap();
+        let tx_details = TransactionDetails {
+            transaction: Some(tx.clone()),
+            txid: tx.txid(),
+            received: 1337,
+            sent: 0,
+            fee: Some(140),
+            confirmation_time: None,
+            verified: true,
+            last_evicted: None,
+        };
+        let utxo = LocalUtxo {
+            txout: tx.output[0].clone(),
+            outpoint: OutPoint::new(tx.txid(), 0),
+            keychain: KeychainKind::External,
+        };
+
+        tree.set_script_pubkey(&script, KeychainKind::External, 42)
+            .unwrap();
+        tree.set_tx(&tx_details).unwrap();
+        tree.set_utxo(&utxo).unwrap();
+        tree.set_last_index(KeychainKind::External, 42).unwrap();
+        tree.set_last_index(KeychainKind::Internal, 7).unwrap();
+        tree.set_used_mark(KeychainKind::Internal, 3).unwrap();
+        tree.set_sweep_index(KeychainKind::External, 5).unwrap();
+        tree.set_swept_utxo(&utxo.outpoint).unwrap();
+        tree.check_descriptor_checksum(KeychainKind::External, b"checksum")
+            .unwrap();
+
+        let mut migrated = MemoryDatabase::new();
+        migrate(&tree, &mut migrated).unwrap();
+
+        assert_eq!(
+            migrated.get_path_from_script_pubkey(&script).unwrap(),
+            Some((KeychainKind::External, 42))
+        );
+        assert_eq!(migrated.get_tx(&tx.txid(), true).unwrap(), Some(tx_details));
+        assert_eq!(migrated.iter_utxos().unwrap(), vec![utxo]);
+        assert_eq!(
+            migrated.get_last_index(KeychainKind::External).unwrap(),
+            Some(42)
+        );
+        assert_eq!(
+            migrated.get_last_index(KeychainKind::Internal).unwrap(),
+            Some(7)
+        );
+        assert_eq!(
+            migrated.iter_used_marks(KeychainKind::Internal).unwrap(),
+            vec![3]
+        );
+        assert_eq!(
+            migrated.get_sweep_index(KeychainKind::External).unwrap(),
+            Some(5)
+        );
+        assert_eq!(migrated.iter_swept_utxos().unwrap(), vec![utxo.outpoint]);
+        assert_eq!(
+            migrated
+                .get_descriptor_checksum(KeychainKind::External)
+                .unwrap(),
+            Some(b"checksum".to_vec())
+        );
+        assert_eq!(
+            migrated
+                .get_descriptor_checksum(KeychainKind::Internal)
+                .unwrap(),
+            None
+        );
+        assert!(matches!(
+            migrated.check_descriptor_checksum(KeychainKind::External, b"other"),
+            Err(Error::ChecksumMismatch)
+        ));
+
+        // the checksums of a different wallet can't be overwritten
+        let mut other = MemoryDatabase::new();
+        other
+            .check_descriptor_checksum(KeychainKind::External, b"other")
+            .unwrap();
+        assert!(matches!(
+            migrate(&tree, &mut other),
+            Err(Error::ChecksumMismatch)
+        ));
+    }
+
     // TODO: more tests...
 }
@@ -0,0 +1,319 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Policy compiler
+//!
+//! This module compiles a [concrete policy](miniscript::policy::Concrete), which describes the
+//! spending conditions of a wallet, into an optimized descriptor. The compiler picks the
+//! miniscript with the lowest expected cost, weighting every branch of an `or()` by the
+//! probability of it being used to spend: these weights can be written in the policy itself, as in
+//! `or(9@pk(A),1@pk(B))`, or changed afterwards with [`PolicyCompiler::set_branch_weight`] and
+//! [`PolicyCompiler::weigh_branches`].
+//!
+//! ```
+//! # use std::str::FromStr;
+//! # use bdk::bitcoin::Network;
+//! # use bdk::database::MemoryDatabase;
+//! # use bdk::descriptor::compiler::{CompiledScriptType, PolicyCompiler};
+//! # use bdk::Wallet;
+//! let policy = "or(pk(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/0/*),and(pk(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/0/*),older(52560)))";
+//!
+//! // The first branch is expected to be used nine times out of ten
+//! let descriptor = PolicyCompiler::<String>::from_str(policy)?
+//!     .set_branch_weight(&[0], 9)?
+//!     .compile(CompiledScriptType::Wsh)?;
+//!
+//! let wallet = Wallet::new_offline(
+//!     &descriptor.to_string(),
+//!     None,
+//!     Network::Testnet,
+//!     MemoryDatabase::new(),
+//! )?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use std::str::FromStr;
+
+use miniscript::policy::Concrete;
+use miniscript::{Descriptor, Legacy, MiniscriptKey, Segwitv0};
+
+use super::error::Error as DescriptorError;
+
+/// Script type used to embed the compiled miniscript
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CompiledScriptType {
+    /// Legacy P2SH (`sh()`)
+    Sh,
+    /// Native segwit P2WSH (`wsh()`)
+    Wsh,
+    /// P2WSH nested in P2SH (`sh(wsh())`)
+    ShWsh,
+}
+
+/// Compiler of concrete policies into descriptors
+///
+/// For a usage example see [this module](crate::descriptor::compiler)'s documentation.
+#[derive(Debug, Clone)]
+pub struct PolicyCompiler<Pk: MiniscriptKey> {
+    policy: Concrete<Pk>,
+}
+
+impl<Pk: MiniscriptKey> PolicyCompiler<Pk> {
+    /// Create a new compiler for `policy`
+    pub fn new(policy: Concrete<Pk>) -> Self {
+        PolicyCompiler { policy }
+    }
+
+    /// Return the policy, including the weights of its branches
+    pub fn policy(&self) -> &Concrete<Pk> {
+        &self.policy
+    }
+
+    /// Set the weight of a branch of an `or()`
+    ///
+    /// The branch is identified by its `path` from the root of the policy: every element is the
+    /// index of a child of `and()`, `or()` and `thresh()` nodes, and the last one must be the index
+    /// of a branch of an `or()`. The weights are relative to the other branches of the same
+    /// `or()`, and must be greater than zero.
+    pub fn set_branch_weight(
+        &mut self,
+        path: &[usize],
+        weight: usize,
+    ) -> Result<&mut Self, DescriptorError> {
+        if weight == 0 {
+            return Err(DescriptorError::InvalidBranchWeight);
+        }
+
+        let (last, parent_path) = path
+            .split_last()
+            .ok_or(DescriptorError::InvalidPolicyPath)?;
+        let mut node = &mut self.policy;
+        for index in parent_path {
+            node = child_mut(node, *index).ok_or(DescriptorError::InvalidPolicyPath)?;
+        }
+
+        match node {
+            Concrete::Or(branches) if *last < branches.len() => {
+                branches[*last].0 = weight;
+                Ok(self)
+            }
+            _ => Err(DescriptorError::InvalidPolicyPath),
+        }
+    }
+
+    /// Change the weights of every branch of the `or()`s in the policy
+    ///
+    /// `f` is called with the path of every branch, as defined in
+    /// [`set_branch_weight`](Self::set_branch_weight), the branch itself and its current weight,
+    /// and returns the new weight. Zero weights are not accepted.
+    pub fn weigh_branches<F>(&mut self, mut f: F) -> Result<&mut Self, DescriptorError>
+    where
+        F: FnMut(&[usize], &Concrete<Pk>, usize) -> usize,
+    {
+        fn visit<Pk: MiniscriptKey, F>(
+            node: &mut Concrete<Pk>,
+            path: &mut Vec<usize>,
+            f: &mut F,
+        ) -> Result<(), DescriptorError>
+        where
+            F: FnMut(&[usize], &Concrete<Pk>, usize) -> usize,
+        {
+            if let Concrete::Or(branches) = node {
+                for (index, (weight, branch)) in branches.iter_mut().enumerate() {
+                    path.push(index);
+                    *weight = f(path, branch, *weight);
+                    path.pop();
+
+                    if *weight == 0 {
+                        return Err(DescriptorError::InvalidBranchWeight);
+                    }
+                }
+            }
+
+            let mut index = 0;
+            while let Some(child) = child_mut(node, index) {
+                path.push(index);
+                visit(child, path, f)?;
+                path.pop();
+
+                index += 1;
+            }
+
+            Ok(())
+        }
+
+        visit(&mut self.policy, &mut vec![], &mut f)?;
+        Ok(self)
+    }
+
+    /// Compile the policy into a descriptor of type `script_type`
+    pub fn compile(
+        &self,
+        script_type: CompiledScriptType,
+    ) -> Result<Descriptor<Pk>, DescriptorError> {
+        let descriptor = match script_type {
+            CompiledScriptType::Sh => Descriptor::new_sh(
+                self.policy
+                    .compile::<Legacy>()
+                    .map_err(miniscript::Error::CompilerError)?,
+            )?,
+            CompiledScriptType::Wsh => Descriptor::new_wsh(
+                self.policy
+                    .compile::<Segwitv0>()
+                    .map_err(miniscript::Error::CompilerError)?,
+            )?,
+            CompiledScriptType::ShWsh => Descriptor::new_sh_wsh(
+                self.policy
+                    .compile::<Segwitv0>()
+                    .map_err(miniscript::Error::CompilerError)?,
+            )?,
+        };
+
+        Ok(descriptor)
+    }
+}
+
+impl<Pk> FromStr for PolicyCompiler<Pk>
+where
+    Pk: MiniscriptKey + FromStr,
+    Pk::Hash: FromStr,
+    <Pk as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash as FromStr>::Err: ToString,
+{
+    type Err = DescriptorError;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        Ok(PolicyCompiler::new(Concrete::from_str(policy)?))
+    }
+}
+
+impl<Pk: MiniscriptKey> From<Concrete<Pk>> for PolicyCompiler<Pk> {
+    fn from(policy: Concrete<Pk>) -> Self {
+        PolicyCompiler::new(policy)
+    }
+}
+
+// Return the child at `index` of `and()`, `or()` and `thresh()` nodes
+fn child_mut<Pk: MiniscriptKey>(
+    node: &mut Concrete<Pk>,
+    index: usize,
+) -> Option<&mut Concrete<Pk>> {
+    match node {
+        Concrete::And(children) | Concrete::Threshold(_, children) => children.get_mut(index),
+        Concrete::Or(branches) => branches.get_mut(index).map(|(_, branch)| branch),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn key_position(descriptor: &Descriptor<String>, key: &str) -> usize {
+        descriptor.to_string().find(key).unwrap()
+    }
+
+    #[test]
+    fn test_compile_script_types() {
+        let compiler = PolicyCompiler::<String>::from_str("pk(A)").unwrap();
+
+        assert!(compiler
+            .compile(CompiledScriptType::Sh)
+            .unwrap()
+            .to_string()
+            .starts_with("sh(pk(A))"));
+        assert!(compiler
+            .compile(CompiledScriptType::Wsh)
+            .unwrap()
+            .to_string()
+            .starts_with("wsh(pk(A))"));
+        assert!(compiler
+            .compile(CompiledScriptType::ShWsh)
+            .unwrap()
+            .to_string()
+            .starts_with("sh(wsh(pk(A)))"));
+    }
+
+    #[test]
+    fn test_branch_weights() {
+        let mut compiler = PolicyCompiler::<String>::from_str("or(99@pk(A),1@pk(B))").unwrap();
+        let descriptor = compiler.compile(CompiledScriptType::Wsh).unwrap();
+        assert!(key_position(&descriptor, "A") < key_position(&descriptor, "B"));
+
+        compiler
+            .set_branch_weight(&[0], 1)
+            .unwrap()
+            .set_branch_weight(&[1], 99)
+            .unwrap();
+        let descriptor = compiler.compile(CompiledScriptType::Wsh).unwrap();
+        assert!(key_position(&descriptor, "B") < key_position(&descriptor, "A"));
+    }
+
+    #[test]
+    fn test_weigh_branches() {
+        let mut compiler =
+            PolicyCompiler::<String>::from_str("and(pk(A),or(pk(B),or(pk(C),pk(D))))").unwrap();
+
+        let mut paths = vec![];
+        compiler
+            .weigh_branches(|path, branch, weight| {
+                assert_eq!(weight, 1);
+                paths.push(path.to_vec());
+                match branch {
+                    Concrete::Key(key) if key == "C" => 10,
+                    _ => weight,
+                }
+            })
+            .unwrap();
+        assert_eq!(
+            paths,
+            vec![vec![1, 0], vec![1, 1], vec![1, 1, 0], vec![1, 1, 1]]
+        );
+
+        match compiler.policy() {
+            Concrete::And(children) => match &children[1] {
+                Concrete::Or(branches) => match &branches[1].1 {
+                    Concrete::Or(branches) => {
+                        assert_eq!(branches[0].0, 10);
+                        assert_eq!(branches[1].0, 1);
+                    }
+                    _ => panic!("Unexpected policy"),
+                },
+                _ => panic!("Unexpected policy"),
+            },
+            _ => panic!("Unexpected policy"),
+        }
+
+        assert!(matches!(
+            compiler.weigh_branches(|_, _, _| 0),
+            Err(DescriptorError::InvalidBranchWeight)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_branch_path() {
+        let mut compiler =
+            PolicyCompiler::<String>::from_str("and(pk(A),or(pk(B),pk(C)))").unwrap();
+
+        assert!(compiler.set_branch_weight(&[1, 1], 5).is_ok());
+        for path in &[&[][..], &[0][..], &[1, 2][..], &[0, 0][..], &[2, 0][..]] {
+            assert!(matches!(
+                compiler.set_branch_weight(path, 5),
+                Err(DescriptorError::InvalidPolicyPath)
+            ));
+        }
+        assert!(matches!(
+            compiler.set_branch_weight(&[1, 0], 0),
+            Err(DescriptorError::InvalidBranchWeight)
+        ));
+    }
+}
@@ -22,6 +22,18 @@ pub enum Error {
     HardenedDerivationXpub,
     /// The descriptor contains multiple keys with the same BIP32 fingerprint
     DuplicatedKeys,
+    /// The multipath derivation steps of the descriptor don't contain exactly two different paths
+    InvalidMultipath,
+    /// A multipath descriptor was used together with a change descriptor
+    MultipathWithChangeDescriptor,
+    /// The relative timelock of a template must be between 1 and 65535 blocks
+    InvalidTimelock(u32),
+    /// The path doesn't identify a branch of an `or()` in the policy
+    #[cfg(feature = "compiler")]
+    InvalidPolicyPath,
+    /// The weight of a branch of the policy is zero
+    #[cfg(feature = "compiler")]
+    InvalidBranchWeight,
 
     /// Error thrown while working with [`keys`](crate::keys)
     Key(crate::keys::KeyError),
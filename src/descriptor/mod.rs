@@ -17,24 +17,32 @@
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ops::Deref;
 
+use bitcoin::hashes::{hash160, Hash};
 use bitcoin::util::bip32::{
     ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey, Fingerprint, KeySource,
 };
 use bitcoin::util::psbt;
 use bitcoin::{Network, PublicKey, Script, TxOut};
 
-use miniscript::descriptor::{DescriptorPublicKey, DescriptorType, DescriptorXKey, Wildcard};
+use miniscript::descriptor::{
+    DescriptorPublicKey, DescriptorType, DescriptorXKey, ShInner, Wildcard, WshInner,
+};
 pub use miniscript::{descriptor::KeyMap, Descriptor, Legacy, Miniscript, ScriptContext, Segwitv0};
-use miniscript::{DescriptorTrait, ForEachKey, TranslatePk};
+use miniscript::{DescriptorTrait, ForEachKey, Terminal, TranslatePk};
 
 use crate::descriptor::policy::BuildSatisfaction;
 
 pub mod checksum;
+#[cfg(feature = "compiler")]
+#[cfg_attr(docsrs, doc(cfg(feature = "compiler")))]
+pub mod compiler;
 pub(crate) mod derived;
 #[doc(hidden)]
 pub mod dsl;
 pub mod error;
+pub mod multipath;
 pub mod policy;
+pub mod spk_iter;
 pub mod template;
 
 pub use self::checksum::get_checksum;
@@ -42,9 +50,10 @@ use self::derived::AsDerived;
 pub use self::derived::DerivedDescriptorKey;
 pub use self::error::Error as DescriptorError;
 pub use self::policy::Policy;
+pub use self::spk_iter::SpkIterator;
 use self::template::DescriptorTemplateOut;
 use crate::keys::{IntoDescriptorKey, KeyError};
-use crate::wallet::signer::SignersContainer;
+use crate::wallet::signer::{SignerId, SignersContainer};
 use crate::wallet::utils::SecpCtx;
 
 /// Alias for a [`Descriptor`] that can contain extended keys using [`DescriptorPublicKey`]
@@ -68,6 +77,20 @@ pub trait IntoWalletDescriptor {
         secp: &SecpCtx,
         network: Network,
     ) -> Result<(ExtendedDescriptor, KeyMap), DescriptorError>;
+
+    /// Convert a [multipath](crate::descriptor::multipath) descriptor to the external and
+    /// internal wallet descriptors
+    ///
+    /// Returns `None` if this is not a multipath descriptor, which is the default.
+    #[allow(clippy::type_complexity)]
+    fn multipath_wallet_descriptors(
+        &self,
+        _secp: &SecpCtx,
+        _network: Network,
+    ) -> Result<Option<((ExtendedDescriptor, KeyMap), (ExtendedDescriptor, KeyMap))>, DescriptorError>
+    {
+        Ok(None)
+    }
 }
 
 impl IntoWalletDescriptor for &str {
@@ -76,24 +99,29 @@ impl IntoWalletDescriptor for &str {
         secp: &SecpCtx,
         network: Network,
     ) -> Result<(ExtendedDescriptor, KeyMap), DescriptorError> {
-        let descriptor = if self.contains('#') {
-            let parts: Vec<&str> = self.splitn(2, '#').collect();
-            if !get_checksum(parts[0])
-                .ok()
-                .map(|computed| computed == parts[1])
-                .unwrap_or(false)
-            {
-                return Err(DescriptorError::InvalidDescriptorChecksum);
-            }
-
-            parts[0]
-        } else {
-            self
-        };
+        let descriptor = multipath::strip_checksum(self)?;
+        if descriptor.contains('<') {
+            return Err(DescriptorError::InvalidMultipath);
+        }
 
         ExtendedDescriptor::parse_descriptor(secp, descriptor)?
             .into_wallet_descriptor(secp, network)
     }
+
+    fn multipath_wallet_descriptors(
+        &self,
+        secp: &SecpCtx,
+        network: Network,
+    ) -> Result<Option<((ExtendedDescriptor, KeyMap), (ExtendedDescriptor, KeyMap))>, DescriptorError>
+    {
+        match multipath::split_multipath(self)? {
+            Some((external, internal)) => Ok(Some((
+                external.as_str().into_wallet_descriptor(secp, network)?,
+                internal.as_str().into_wallet_descriptor(secp, network)?,
+            ))),
+            None => Ok(None),
+        }
+    }
 }
 
 impl IntoWalletDescriptor for &String {
@@ -104,6 +132,15 @@ impl IntoWalletDescriptor for &String {
     ) -> Result<(ExtendedDescriptor, KeyMap), DescriptorError> {
         self.as_str().into_wallet_descriptor(secp, network)
     }
+
+    fn multipath_wallet_descriptors(
+        &self,
+        secp: &SecpCtx,
+        network: Network,
+    ) -> Result<Option<((ExtendedDescriptor, KeyMap), (ExtendedDescriptor, KeyMap))>, DescriptorError>
+    {
+        self.as_str().multipath_wallet_descriptors(secp, network)
+    }
 }
 
 impl IntoWalletDescriptor for ExtendedDescriptor {
@@ -262,6 +299,30 @@ pub trait ExtractPolicy {
     ) -> Result<Option<Policy>, DescriptorError>;
 }
 
+/// Origin of a key in a descriptor, and whether the wallet can sign with it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyOriginInfo {
+    /// The key, as it appears in the descriptor
+    pub key: DescriptorPublicKey,
+    /// Fingerprint of the master key
+    ///
+    /// For keys without an origin this is the fingerprint of the key itself.
+    pub fingerprint: Fingerprint,
+    /// Derivation path from the master key to the key, excluding the derivation steps after
+    /// the key in the descriptor
+    pub origin_path: DerivationPath,
+    /// Whether a signer for this key is available
+    pub has_private_key: bool,
+}
+
+/// Trait implemented on [`Descriptor`]s to list the origins of their keys
+pub trait ExtractKeyOrigins {
+    /// Return the origin of every key in the descriptor, in the order they appear in it
+    ///
+    /// A key is marked as having a private key if `signers` contains a signer for it.
+    fn key_origins(&self, signers: &SignersContainer, secp: &SecpCtx) -> Vec<KeyOriginInfo>;
+}
+
 pub(crate) trait XKeyUtils {
     fn full_path(&self, append: &[ChildNumber]) -> DerivationPath;
     fn root_fingerprint(&self, secp: &SecpCtx) -> Fingerprint;
@@ -337,6 +398,7 @@ pub(crate) trait DerivedDescriptorMeta {
 
 pub(crate) trait DescriptorMeta {
     fn is_witness(&self) -> bool;
+    fn low_r_weight_savings(&self) -> usize;
     fn get_extended_keys(&self) -> Result<Vec<DescriptorXKey<ExtendedPubKey>>, DescriptorError>;
     fn derive_from_hd_keypaths<'s>(
         &self,
@@ -380,6 +442,34 @@ impl<'s> DescriptorScripts for DerivedDescriptor<'s> {
     }
 }
 
+// Minimum number of signatures in any satisfaction of `ms`
+fn min_signatures<Ctx: ScriptContext>(ms: &Miniscript<DescriptorPublicKey, Ctx>) -> usize {
+    match &ms.node {
+        Terminal::PkK(_) | Terminal::PkH(_) => 1,
+        Terminal::Multi(k, _) => *k,
+        Terminal::Alt(inner)
+        | Terminal::Swap(inner)
+        | Terminal::Check(inner)
+        | Terminal::DupIf(inner)
+        | Terminal::Verify(inner)
+        | Terminal::NonZero(inner)
+        | Terminal::ZeroNotEqual(inner) => min_signatures(inner),
+        Terminal::AndV(a, b) | Terminal::AndB(a, b) => min_signatures(a) + min_signatures(b),
+        Terminal::AndOr(a, b, c) => {
+            std::cmp::min(min_signatures(a) + min_signatures(b), min_signatures(c))
+        }
+        Terminal::OrB(a, b) | Terminal::OrD(a, b) | Terminal::OrC(a, b) | Terminal::OrI(a, b) => {
+            std::cmp::min(min_signatures(a), min_signatures(b))
+        }
+        Terminal::Thresh(k, subs) => {
+            let mut signatures = subs.iter().map(|s| min_signatures(s)).collect::<Vec<_>>();
+            signatures.sort_unstable();
+            signatures.into_iter().take(*k).sum()
+        }
+        _ => 0,
+    }
+}
+
 impl DescriptorMeta for ExtendedDescriptor {
     fn is_witness(&self) -> bool {
         matches!(
@@ -393,6 +483,36 @@ impl DescriptorMeta for ExtendedDescriptor {
         )
     }
 
+    // Weight saved by low-R signatures, which are one byte shorter than the ones assumed by
+    // `max_satisfaction_weight`. Every satisfaction contains at least the minimum number of
+    // signatures, so the weight of the satisfaction actually used is never underestimated
+    fn low_r_weight_savings(&self) -> usize {
+        let signatures = match self {
+            Descriptor::Bare(bare) => min_signatures(bare.as_inner()),
+            Descriptor::Pkh(_) | Descriptor::Wpkh(_) => 1,
+            Descriptor::Sh(sh) => match sh.as_inner() {
+                ShInner::Wpkh(_) => 1,
+                ShInner::SortedMulti(keys) => keys.k,
+                ShInner::Ms(ms) => min_signatures(ms),
+                ShInner::Wsh(wsh) => match wsh.as_inner() {
+                    WshInner::SortedMulti(keys) => keys.k,
+                    WshInner::Ms(ms) => min_signatures(ms),
+                },
+            },
+            Descriptor::Wsh(wsh) => match wsh.as_inner() {
+                WshInner::SortedMulti(keys) => keys.k,
+                WshInner::Ms(ms) => min_signatures(ms),
+            },
+        };
+
+        // the bytes of the script_sig count four times
+        if self.is_witness() {
+            signatures
+        } else {
+            signatures * 4
+        }
+    }
+
     fn get_extended_keys(&self) -> Result<Vec<DescriptorXKey<ExtendedPubKey>>, DescriptorError> {
         let mut answer = Vec::new();
 
@@ -507,6 +627,50 @@ impl DescriptorMeta for ExtendedDescriptor {
     }
 }
 
+impl ExtractKeyOrigins for ExtendedDescriptor {
+    fn key_origins(&self, signers: &SignersContainer, secp: &SecpCtx) -> Vec<KeyOriginInfo> {
+        let mut answer = Vec::new();
+
+        self.for_each_key(|key| {
+            let key = key.as_key().deref();
+            let (fingerprint, origin_path, id) = match key {
+                DescriptorPublicKey::SinglePub(single) => {
+                    let pubkey_hash = hash160::Hash::hash(&single.key.to_bytes());
+                    let (fingerprint, origin_path) = match &single.origin {
+                        Some((fingerprint, path)) => (*fingerprint, path.clone()),
+                        None => (
+                            Fingerprint::from(&pubkey_hash[..4]),
+                            DerivationPath::default(),
+                        ),
+                    };
+
+                    (fingerprint, origin_path, SignerId::from(pubkey_hash))
+                }
+                DescriptorPublicKey::XPub(xpub) => {
+                    let fingerprint = xpub.root_fingerprint(secp);
+                    let origin_path = match &xpub.origin {
+                        Some((_, path)) => path.clone(),
+                        None => DerivationPath::default(),
+                    };
+
+                    (fingerprint, origin_path, SignerId::from(fingerprint))
+                }
+            };
+
+            answer.push(KeyOriginInfo {
+                key: key.clone(),
+                fingerprint,
+                origin_path,
+                has_private_key: signers.find(id).is_some(),
+            });
+
+            true
+        });
+
+        answer
+    }
+}
+
 impl<'s> DerivedDescriptorMeta for DerivedDescriptor<'s> {
     fn get_hd_keypaths(&self, secp: &SecpCtx) -> Result<HdKeyPaths, DescriptorError> {
         let mut answer = BTreeMap::new();
@@ -0,0 +1,252 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Multipath descriptors
+//!
+//! This module converts between [BIP-389] multipath descriptors, which describe both keychains of
+//! a wallet with derivation steps like `<0;1>`, and the pair of external and internal descriptors
+//! used by the [`Wallet`](crate::Wallet).
+//!
+//! ```
+//! # use bdk::descriptor::multipath::{combine_multipath, split_multipath};
+//! let multipath = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/<0;1>/*)";
+//! let (external, internal) = split_multipath(multipath)?.unwrap();
+//! assert_eq!(external, "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/0/*)");
+//! assert_eq!(internal, "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/1/*)");
+//!
+//! let combined = combine_multipath(&external, &internal).unwrap();
+//! assert!(combined.starts_with(multipath));
+//! # Ok::<_, bdk::descriptor::DescriptorError>(())
+//! ```
+//!
+//! [BIP-389]: https://github.com/bitcoin/bips/blob/master/bip-0389.mediawiki
+
+use crate::descriptor::{get_checksum, DescriptorError};
+
+// Remove the checksum of `descriptor`, if present, after checking it
+pub(crate) fn strip_checksum(descriptor: &str) -> Result<&str, DescriptorError> {
+    let mut parts = descriptor.splitn(2, '#');
+    let descriptor = parts.next().unwrap_or_default();
+    match parts.next() {
+        Some(checksum) if get_checksum(descriptor).ok().as_deref() != Some(checksum) => {
+            Err(DescriptorError::InvalidDescriptorChecksum)
+        }
+        _ => Ok(descriptor),
+    }
+}
+
+/// Split a multipath descriptor into its external and internal descriptors
+///
+/// Every multipath derivation step must contain exactly two different paths, the first for the
+/// external descriptor and the second for the internal one. If present, the checksum of the
+/// descriptor is validated and removed. Returns `None` if the descriptor doesn't contain multipath
+/// steps.
+pub fn split_multipath(descriptor: &str) -> Result<Option<(String, String)>, DescriptorError> {
+    let mut rest = strip_checksum(descriptor)?;
+    if !rest.contains('<') {
+        return Ok(None);
+    }
+
+    let mut external = String::with_capacity(rest.len());
+    let mut internal = String::with_capacity(rest.len());
+    while let Some(start) = rest.find('<') {
+        let end = rest[start..]
+            .find('>')
+            .map(|end| start + end)
+            .ok_or(DescriptorError::InvalidMultipath)?;
+        let paths = rest[start + 1..end].split(';').collect::<Vec<_>>();
+        match paths[..] {
+            [first, second]
+                if rest[..start].ends_with('/')
+                    && !first.is_empty()
+                    && !second.is_empty()
+                    && first != second =>
+            {
+                external.push_str(&rest[..start]);
+                external.push_str(first);
+                internal.push_str(&rest[..start]);
+                internal.push_str(second);
+            }
+            _ => return Err(DescriptorError::InvalidMultipath),
+        }
+
+        rest = &rest[end + 1..];
+    }
+    external.push_str(rest);
+    internal.push_str(rest);
+
+    Ok(Some((external, internal)))
+}
+
+// Split a path element into its leading derivation step, if any, and the rest
+fn split_step(element: &str) -> (&str, &str) {
+    let digits = element
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or_else(|| element.len());
+    let len = match element[digits..].chars().next() {
+        Some('h') | Some('H') | Some('\'') if digits > 0 => digits + 1,
+        _ => digits,
+    };
+    element.split_at(len)
+}
+
+/// Combine an external and an internal descriptor into a multipath descriptor, with its checksum
+///
+/// Returns `None` if the descriptors are the same, or if they differ by anything other than the
+/// derivation steps after their keys.
+pub fn combine_multipath(external: &str, internal: &str) -> Option<String> {
+    let external = strip_checksum(external).ok()?;
+    let internal = strip_checksum(internal).ok()?;
+
+    let external_elements = external.split('/').collect::<Vec<_>>();
+    let internal_elements = internal.split('/').collect::<Vec<_>>();
+    if external_elements.len() != internal_elements.len() {
+        return None;
+    }
+
+    let mut combined = Vec::with_capacity(external_elements.len());
+    let mut in_origin = false;
+    let mut is_multipath = false;
+    for (i, (external, internal)) in external_elements
+        .into_iter()
+        .zip(internal_elements)
+        .enumerate()
+    {
+        if external == internal {
+            combined.push(external.to_string());
+        } else {
+            let (external_step, external_rest) = split_step(external);
+            let (internal_step, internal_rest) = split_step(internal);
+            // The first element comes before any derivation step, and the origin of the keys
+            // can't contain multipath steps
+            if i == 0 || in_origin || external_step.is_empty() || internal_step.is_empty() {
+                return None;
+            }
+            if external_rest != internal_rest {
+                return None;
+            }
+
+            combined.push(format!(
+                "<{};{}>{}",
+                external_step, internal_step, external_rest
+            ));
+            is_multipath = true;
+        }
+
+        if let Some(c) = external.chars().rev().find(|c| *c == '[' || *c == ']') {
+            in_origin = c == '[';
+        }
+    }
+
+    if !is_multipath {
+        return None;
+    }
+    let combined = combined.join("/");
+    let checksum = get_checksum(&combined).ok()?;
+    Some(format!("{}#{}", combined, checksum))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TPUB: &str = "tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq";
+
+    #[test]
+    fn test_split_multipath() {
+        let multipath = format!(
+            "wsh(multi(2,[d34db33f/48h/1h/0h/2h]{}/<0;1>/*,{}/7h/<2;3>/*))",
+            TPUB, TPUB
+        );
+        let (external, internal) = split_multipath(&multipath).unwrap().unwrap();
+        assert_eq!(
+            external,
+            format!(
+                "wsh(multi(2,[d34db33f/48h/1h/0h/2h]{}/0/*,{}/7h/2/*))",
+                TPUB, TPUB
+            )
+        );
+        assert_eq!(
+            internal,
+            format!(
+                "wsh(multi(2,[d34db33f/48h/1h/0h/2h]{}/1/*,{}/7h/3/*))",
+                TPUB, TPUB
+            )
+        );
+
+        let with_checksum = format!("{}#{}", multipath, get_checksum(&multipath).unwrap());
+        assert_eq!(
+            split_multipath(&with_checksum).unwrap(),
+            split_multipath(&multipath).unwrap()
+        );
+
+        let single = format!("wpkh({}/0/*)", TPUB);
+        assert_eq!(split_multipath(&single).unwrap(), None);
+    }
+
+    #[test]
+    fn test_split_invalid_multipath() {
+        for invalid in &[
+            format!("wpkh({}/<0;1;2>/*)", TPUB),
+            format!("wpkh({}/<0;0>/*)", TPUB),
+            format!("wpkh({}/<0>/*)", TPUB),
+            format!("wpkh({}/<0;1/*)", TPUB),
+            format!("wpkh({}<0;1>/*)", TPUB),
+        ] {
+            assert!(matches!(
+                split_multipath(invalid),
+                Err(DescriptorError::InvalidMultipath)
+            ));
+        }
+
+        assert!(matches!(
+            split_multipath(&format!("wpkh({}/<0;1>/*)#aaaaaaaa", TPUB)),
+            Err(DescriptorError::InvalidDescriptorChecksum)
+        ));
+    }
+
+    #[test]
+    fn test_combine_multipath() {
+        let external = format!("sh(wpkh([d34db33f/49h/1h/0h]{}/0/*))", TPUB);
+        let internal = format!("sh(wpkh([d34db33f/49h/1h/0h]{}/1/*))", TPUB);
+        let combined = combine_multipath(&external, &internal).unwrap();
+        assert!(combined.starts_with(&format!("sh(wpkh([d34db33f/49h/1h/0h]{}/<0;1>/*))#", TPUB)));
+        assert_eq!(
+            split_multipath(&combined).unwrap(),
+            Some((external.clone(), internal))
+        );
+
+        // Hardened steps keep their marker
+        let combined = combine_multipath(
+            &format!("wpkh({}/0h/*)", TPUB),
+            &format!("wpkh({}/1h/*)", TPUB),
+        )
+        .unwrap();
+        assert!(combined.starts_with(&format!("wpkh({}/<0h;1h>/*)#", TPUB)));
+
+        assert_eq!(combine_multipath(&external, &external), None);
+        // Different origins
+        assert_eq!(
+            combine_multipath(
+                &external,
+                &format!("sh(wpkh([d34db33f/49h/1h/1h]{}/1/*))", TPUB)
+            ),
+            None
+        );
+        // Different keys
+        assert_eq!(
+            combine_multipath(
+                &format!("wpkh({}/0/*)", TPUB),
+                "wpkh(03a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd)"
+            ),
+            None
+        );
+    }
+}
@@ -824,7 +824,7 @@ impl<Ctx: ScriptContext> ExtractPolicy for Miniscript<DescriptorPublicKey, Ctx>
                     ..
                 } = build_sat
                 {
-                    let after = After::new(Some(current_height), false);
+                    let after = After::new(Some(current_height), None, false);
                     let after_sat = Satisfier::<bitcoin::PublicKey>::check_after(&after, *value);
                     let inputs_sat = psbt_inputs_sat(psbt)
                         .all(|sat| Satisfier::<bitcoin::PublicKey>::check_after(&sat, *value));
@@ -0,0 +1,204 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Script pubkey iterator
+//!
+//! This module contains [`SpkIterator`], which derives the script pubkeys of a descriptor over a
+//! range of indexes, in ascending or descending order. Chain sources can use it to re-check only
+//! the most recently used script pubkeys of a wallet instead of starting from index 0.
+//!
+//! ```
+//! # use std::str::FromStr;
+//! # use bdk::bitcoin::secp256k1::Secp256k1;
+//! # use bdk::descriptor::{ExtendedDescriptor, SpkIterator};
+//! let secp = Secp256k1::new();
+//! let descriptor = ExtendedDescriptor::from_str("wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/0/*)")?;
+//!
+//! // The last 10 script pubkeys up to index 50, starting from the most recent one
+//! let indexes = SpkIterator::new_with_range(&descriptor, &secp, 41..=50)
+//!     .rev()
+//!     .map(|(index, _script)| index)
+//!     .collect::<Vec<_>>();
+//! assert_eq!(indexes, (41..=50).rev().collect::<Vec<_>>());
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use std::ops::{Bound, Range, RangeBounds};
+
+use bitcoin::Script;
+use miniscript::DescriptorTrait;
+
+use super::derived::AsDerived;
+use super::ExtendedDescriptor;
+use crate::wallet::utils::SecpCtx;
+
+/// Maximum non-hardened derivation index, as defined in BIP32
+pub const BIP32_MAX_INDEX: u32 = (1 << 31) - 1;
+
+/// Iterator over the script pubkeys of a descriptor, paired with their derivation index
+///
+/// Descriptors without wildcards only have the script pubkey at index 0. Indexes after
+/// [`BIP32_MAX_INDEX`] are never returned.
+///
+/// For a usage example see [this module](crate::descriptor::spk_iter)'s documentation.
+#[derive(Debug, Clone)]
+pub struct SpkIterator<'a> {
+    descriptor: &'a ExtendedDescriptor,
+    secp: &'a SecpCtx,
+    range: Range<u32>,
+}
+
+impl<'a> SpkIterator<'a> {
+    /// Iterate over all the script pubkeys of `descriptor`, starting from index 0
+    pub fn new(descriptor: &'a ExtendedDescriptor, secp: &'a SecpCtx) -> Self {
+        Self::new_with_range(descriptor, secp, ..)
+    }
+
+    /// Iterate over the script pubkeys of `descriptor` with an index in `range`
+    pub fn new_with_range<R: RangeBounds<u32>>(
+        descriptor: &'a ExtendedDescriptor,
+        secp: &'a SecpCtx,
+        range: R,
+    ) -> Self {
+        let max_index = match descriptor.is_deriveable() {
+            true => BIP32_MAX_INDEX,
+            false => 0,
+        };
+        let start = match range.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) => start.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => (*end).min(max_index) as u64 + 1,
+            Bound::Excluded(end) => (*end).min(max_index + 1) as u64,
+            Bound::Unbounded => max_index as u64 + 1,
+        };
+
+        SpkIterator {
+            descriptor,
+            secp,
+            // `end` is at most 2^31, so it always fits in a `u32`
+            range: start..(end as u32).max(start),
+        }
+    }
+
+    fn derive(&self, index: u32) -> (u32, Script) {
+        (
+            index,
+            self.descriptor.as_derived(index, self.secp).script_pubkey(),
+        )
+    }
+}
+
+impl<'a> Iterator for SpkIterator<'a> {
+    type Item = (u32, Script);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.range.next().map(|index| self.derive(index))
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.range.nth(n).map(|index| self.derive(index))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl<'a> DoubleEndedIterator for SpkIterator<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.range.next_back().map(|index| self.derive(index))
+    }
+}
+
+impl<'a> ExactSizeIterator for SpkIterator<'a> {}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::secp256k1::Secp256k1;
+
+    use super::*;
+
+    const DESCRIPTOR: &str = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/0/*)";
+
+    fn script_at(descriptor: &ExtendedDescriptor, secp: &SecpCtx, index: u32) -> Script {
+        descriptor.as_derived(index, secp).script_pubkey()
+    }
+
+    #[test]
+    fn test_spk_iter() {
+        let secp = Secp256k1::new();
+        let descriptor = ExtendedDescriptor::from_str(DESCRIPTOR).unwrap();
+
+        let mut iter = SpkIterator::new(&descriptor, &secp);
+        assert_eq!(iter.len(), BIP32_MAX_INDEX as usize + 1);
+        assert_eq!(iter.next(), Some((0, script_at(&descriptor, &secp, 0))));
+        assert_eq!(iter.nth(9), Some((10, script_at(&descriptor, &secp, 10))));
+        assert_eq!(
+            iter.next_back(),
+            Some((
+                BIP32_MAX_INDEX,
+                script_at(&descriptor, &secp, BIP32_MAX_INDEX)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_spk_iter_range() {
+        let secp = Secp256k1::new();
+        let descriptor = ExtendedDescriptor::from_str(DESCRIPTOR).unwrap();
+        let indexes = |iter: SpkIterator| iter.map(|(index, _)| index).collect::<Vec<_>>();
+
+        assert_eq!(
+            indexes(SpkIterator::new_with_range(&descriptor, &secp, 5..8)),
+            vec![5, 6, 7]
+        );
+        assert_eq!(
+            indexes(SpkIterator::new_with_range(&descriptor, &secp, 5..=8).rev()),
+            vec![8, 7, 6, 5]
+        );
+        assert_eq!(
+            indexes(SpkIterator::new_with_range(&descriptor, &secp, 8..5)),
+            Vec::<u32>::new()
+        );
+        assert_eq!(
+            indexes(SpkIterator::new_with_range(
+                &descriptor,
+                &secp,
+                BIP32_MAX_INDEX - 1..
+            )),
+            vec![BIP32_MAX_INDEX - 1, BIP32_MAX_INDEX]
+        );
+        assert_eq!(
+            SpkIterator::new_with_range(&descriptor, &secp, ..=u32::MAX).len(),
+            BIP32_MAX_INDEX as usize + 1
+        );
+    }
+
+    #[test]
+    fn test_spk_iter_non_wildcard() {
+        let secp = Secp256k1::new();
+        let descriptor = ExtendedDescriptor::from_str(
+            "wpkh(03a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd)",
+        )
+        .unwrap();
+
+        let spks = SpkIterator::new(&descriptor, &secp).collect::<Vec<_>>();
+        assert_eq!(spks, vec![(0, script_at(&descriptor, &secp, 0))]);
+        assert_eq!(
+            SpkIterator::new_with_range(&descriptor, &secp, 1..10).count(),
+            0
+        );
+    }
+}
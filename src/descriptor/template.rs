@@ -397,6 +397,128 @@ impl<K: DerivableKey<Segwitv0>> DescriptorTemplate for Bip84Public<K> {
     }
 }
 
+fn check_relative_timelock(timelock: u32) -> Result<(), DescriptorError> {
+    if timelock == 0 || timelock > 0xFFFF {
+        return Err(DescriptorError::InvalidTimelock(timelock));
+    }
+
+    Ok(())
+}
+
+/// Multisig with a timelocked recovery path. Expands to
+/// `wsh(or_d(multi(threshold,keys),and_v(v:multi(recovery_threshold,recovery_keys),older(timelock))))`
+///
+/// The funds can always be spent with `threshold` of the `keys`. If they haven't been moved for
+/// `timelock` blocks, `recovery_threshold` of the `recovery_keys` can spend them as well, for
+/// instance when some of the primary keys have been lost. Since the same key can't appear more
+/// than once in a wallet's descriptor, the recovery keys must be different from the primary
+/// ones: usually they are derived from the same seeds at a different path, or held by a
+/// third party.
+///
+/// ## Example
+///
+/// ```
+/// # use bdk::bitcoin::Network;
+/// # use bdk::database::MemoryDatabase;
+/// # use bdk::Wallet;
+/// use bdk::template::DegradingMultisig;
+///
+/// // 2-of-3 that degrades to 1-of-2 recovery keys after about a year
+/// let wallet = Wallet::new_offline(
+///     DegradingMultisig {
+///         threshold: 2,
+///         keys: vec![
+///             "tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/0/*",
+///             "tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/0/*",
+///             "tpubDDYkZojQFQjht8Tm4jsS3iuEmKjTiEGjG6KnuFNKKJb5A6ZUCUZKdvLdSDWofKi4ToRCwb9poe1XdqfUnP4jaJjCB2Zwv11ZLgSbnZSNecE/0/*",
+///         ],
+///         recovery_threshold: 1,
+///         recovery_keys: vec![
+///             "tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*",
+///             "tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/0/*",
+///         ],
+///         timelock: 52_560,
+///     },
+///     None,
+///     Network::Testnet,
+///     MemoryDatabase::default(),
+/// )?;
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+pub struct DegradingMultisig<K: IntoDescriptorKey<Segwitv0>> {
+    /// Number of primary keys required to spend
+    pub threshold: usize,
+    /// Primary keys
+    pub keys: Vec<K>,
+    /// Number of recovery keys required to spend after the timelock
+    pub recovery_threshold: usize,
+    /// Recovery keys
+    pub recovery_keys: Vec<K>,
+    /// Relative timelock, in blocks, after which the recovery keys can spend
+    pub timelock: u32,
+}
+
+impl<K: IntoDescriptorKey<Segwitv0>> DescriptorTemplate for DegradingMultisig<K> {
+    fn build(self) -> Result<DescriptorTemplateOut, DescriptorError> {
+        check_relative_timelock(self.timelock)?;
+
+        descriptor!(wsh(or_d(
+            multi_vec(self.threshold, self.keys),
+            and_v(
+                v: multi_vec(self.recovery_threshold, self.recovery_keys),
+                older(self.timelock)
+            )
+        )))
+    }
+}
+
+/// Collaborative custody with a co-signing service. Expands to
+/// `wsh(and_v(v:pk(user),or_d(pk(service),older(timelock))))`
+///
+/// Every transaction requires the signature of both the `user` and the co-signing `service`,
+/// which can enforce its own spending policies. If the service disappears, the `user` alone can
+/// spend the funds that haven't been moved for `timelock` blocks.
+///
+/// ## Example
+///
+/// ```
+/// # use bdk::bitcoin::Network;
+/// # use bdk::database::MemoryDatabase;
+/// # use bdk::Wallet;
+/// use bdk::template::CollaborativeCustody;
+///
+/// let wallet = Wallet::new_offline(
+///     CollaborativeCustody {
+///         user: "tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/0/*",
+///         service: "tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/0/*",
+///         timelock: 4_320,
+///     },
+///     None,
+///     Network::Testnet,
+///     MemoryDatabase::default(),
+/// )?;
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+pub struct CollaborativeCustody<K: IntoDescriptorKey<Segwitv0>> {
+    /// Key of the user, required by every spending path
+    pub user: K,
+    /// Key of the co-signing service
+    pub service: K,
+    /// Relative timelock, in blocks, after which the user can spend without the service
+    pub timelock: u32,
+}
+
+impl<K: IntoDescriptorKey<Segwitv0>> DescriptorTemplate for CollaborativeCustody<K> {
+    fn build(self) -> Result<DescriptorTemplateOut, DescriptorError> {
+        check_relative_timelock(self.timelock)?;
+
+        descriptor!(wsh(and_v(
+            v: pk(self.user),
+            or_d(pk(self.service), older(self.timelock))
+        )))
+    }
+}
+
 macro_rules! expand_make_bipxx {
     ( $mod_name:ident, $ctx:ty ) => {
         mod $mod_name {
@@ -723,4 +845,69 @@ mod test {
             ],
         );
     }
+
+    const TPUB_A: &str = "tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq";
+    const TPUB_B: &str = "tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK";
+    const TPUB_C: &str = "tpubDDYkZojQFQjht8Tm4jsS3iuEmKjTiEGjG6KnuFNKKJb5A6ZUCUZKdvLdSDWofKi4ToRCwb9poe1XdqfUnP4jaJjCB2Zwv11ZLgSbnZSNecE";
+
+    // Degrading multisig `wsh(or_d(multi(...),and_v(v:multi(...),older(...))))`
+    #[test]
+    fn test_degrading_multisig_template() {
+        let keys = vec![format!("{}/0/*", TPUB_A), format!("{}/0/*", TPUB_B)];
+        let recovery_keys = vec![format!("{}/0/*", TPUB_C)];
+        let template = DegradingMultisig {
+            threshold: 2,
+            keys: keys.iter().map(String::as_str).collect(),
+            recovery_threshold: 1,
+            recovery_keys: recovery_keys.iter().map(String::as_str).collect(),
+            timelock: 144,
+        };
+
+        let (descriptor, _, _) = template.build().unwrap();
+        assert!(descriptor.is_witness());
+        assert!(descriptor.to_string().starts_with(&format!(
+            "wsh(or_d(multi(2,{}/0/*,{}/0/*),and_v(v:multi(1,{}/0/*),older(144))))#",
+            TPUB_A, TPUB_B, TPUB_C
+        )));
+
+        let template = DegradingMultisig {
+            threshold: 2,
+            keys: keys.iter().map(String::as_str).collect(),
+            recovery_threshold: 1,
+            recovery_keys: recovery_keys.iter().map(String::as_str).collect(),
+            timelock: 0x10000,
+        };
+        assert!(matches!(
+            template.build(),
+            Err(DescriptorError::InvalidTimelock(0x10000))
+        ));
+    }
+
+    // Collaborative custody `wsh(and_v(v:pk(user),or_d(pk(service),older(...))))`
+    #[test]
+    fn test_collaborative_custody_template() {
+        let user = format!("{}/0/*", TPUB_A);
+        let service = format!("{}/0/*", TPUB_B);
+        let template = CollaborativeCustody {
+            user: user.as_str(),
+            service: service.as_str(),
+            timelock: 4320,
+        };
+
+        let (descriptor, _, _) = template.build().unwrap();
+        assert!(descriptor.to_string().starts_with(&format!(
+            "wsh(and_v(v:pk({}),or_d(pk({}),older(4320))))#",
+            user, service
+        )));
+
+        let template = CollaborativeCustody {
+            user: user.as_str(),
+            service: service.as_str(),
+            timelock: 0,
+        };
+        assert!(matches!(
+            template.build(),
+            Err(DescriptorError::InvalidTimelock(0))
+        ));
+    }
 }
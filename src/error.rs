@@ -13,7 +13,7 @@ use std::fmt;
 
 use crate::bitcoin::Network;
 use crate::{descriptor, wallet, wallet::address_validator};
-use bitcoin::OutPoint;
+use bitcoin::{Address, OutPoint};
 
 /// Errors that can be thrown by the [`Wallet`](crate::wallet::Wallet)
 #[derive(Debug)]
@@ -49,6 +49,10 @@ pub enum Error {
     TransactionNotFound,
     /// Happens when trying to bump a transaction that is already confirmed
     TransactionConfirmed,
+    /// Happens when trying to bump a transaction that has been evicted from the mempool
+    ///
+    /// The utxos it spent are available again, so a new transaction can be built from them.
+    TransactionEvicted,
     /// Trying to replace a tx that has a sequence >= `0xFFFFFFFE`
     IrreplaceableTransaction,
     /// When bumping a tx the fee rate requested is lower than required
@@ -61,6 +65,24 @@ pub enum Error {
         /// Required fee absolute value (satoshi)
         required: u64,
     },
+    /// The absolute fee is higher than the limit set with [`TxBuilder::max_absolute_fee`]
+    ///
+    /// [`TxBuilder::max_absolute_fee`]: crate::wallet::tx_builder::TxBuilder::max_absolute_fee
+    FeeTooHigh {
+        /// Fee of the transaction (satoshi)
+        fee: u64,
+        /// Maximum fee allowed (satoshi)
+        max: u64,
+    },
+    /// The fee rate is higher than the limit set with [`TxBuilder::max_fee_rate`]
+    ///
+    /// [`TxBuilder::max_fee_rate`]: crate::wallet::tx_builder::TxBuilder::max_fee_rate
+    FeeRateTooHigh {
+        /// Estimated fee rate of the transaction (satoshi/vbyte)
+        rate: crate::types::FeeRate,
+        /// Maximum fee rate allowed (satoshi/vbyte)
+        max: crate::types::FeeRate,
+    },
     /// Node doesn't have data to estimate a fee rate
     FeeRateUnavailable,
     /// In order to use the [`TxBuilder::add_global_xpubs`] option every extended
@@ -89,13 +111,53 @@ pub enum Error {
     #[cfg(feature = "verify")]
     /// Transaction verification error
     Verification(crate::wallet::verify::VerifyError),
+    #[cfg(feature = "bip47")]
+    /// BIP-47 payment code error
+    Bip47(crate::wallet::bip47::Bip47Error),
+    #[cfg(feature = "encrypted-keys")]
+    /// Error while encrypting or decrypting keys
+    EncryptedKey(crate::keys::encrypted::EncryptionError),
+    #[cfg(feature = "silent-payments")]
+    /// Silent payment error
+    SilentPayment(crate::wallet::silent_payments::SilentPaymentError),
 
     /// Progress value must be between `0.0` (included) and `100.0` (included)
     InvalidProgressValue(f32),
     /// Progress update error (maybe the channel has been closed)
     ProgressUpdateError,
+    /// The sync has been cancelled with a [`CancellableProgress`](crate::blockchain::CancellableProgress)
+    SyncCancelled,
     /// Requested outpoint doesn't exist in the tx (vout greater than available outputs)
     InvalidOutpoint(OutPoint),
+    /// The address is not valid for the network of the wallet
+    InvalidAddressNetwork(Address),
+    /// The data carrier outputs of the transaction wouldn't be relayed by default
+    ///
+    /// See [`TxBuilder::allow_nonstandard_data`](crate::wallet::tx_builder::TxBuilder::allow_nonstandard_data).
+    NonStandardData(String),
+    /// The absolute timelock required by the spending policy hasn't expired yet
+    TimelockNotSatisfied {
+        /// Block height required by the timelock
+        required: u32,
+        /// Current height of the chain known by the wallet
+        current_height: u32,
+    },
+    /// The time-based absolute timelock required by the spending policy hasn't expired yet
+    TimeTimelockNotSatisfied {
+        /// Timestamp required by the timelock
+        required: u32,
+        /// Median time past of the chain tip known by the wallet
+        median_time_past: u32,
+    },
+    /// The transaction doesn't respect the topology restrictions of TRUC (version 3) transactions
+    TrucViolation(String),
+    /// The PSBTs can't be combined because they describe different transactions or contain
+    /// conflicting data
+    IncompatiblePsbt(String),
+    /// The full previous transaction of this input is not available
+    ///
+    /// See [`TxBuilder::require_non_witness_utxo`](crate::wallet::tx_builder::TxBuilder::require_non_witness_utxo).
+    MissingNonWitnessUtxo(OutPoint),
 
     /// Error related to the parsing and usage of descriptors
     Descriptor(crate::descriptor::error::Error),
@@ -117,10 +179,11 @@ pub enum Error {
     Psbt(bitcoin::util::psbt::Error),
     /// Partially signed bitcoin transaction parseerror
     PsbtParse(bitcoin::util::psbt::PsbtParseError),
+    /// Invalid block header returned by the blockchain backend
+    Spv(crate::blockchain::spv::SpvError),
 
     //KeyMismatch(bitcoin::secp256k1::PublicKey, bitcoin::secp256k1::PublicKey),
     //MissingInputUTXO(usize),
-    //InvalidAddressNetwork(Address),
     //DifferentTransactions,
     //DifferentDescriptorStructure,
     //Uncapable(crate::blockchain::Capability),
@@ -128,6 +191,9 @@ pub enum Error {
     #[cfg(feature = "electrum")]
     /// Electrum client error
     Electrum(electrum_client::Error),
+    #[cfg(feature = "electrum")]
+    /// Electrum broadcast error
+    ElectrumBroadcast(crate::blockchain::electrum::BroadcastError),
     #[cfg(feature = "esplora")]
     /// Esplora client error
     Esplora(crate::blockchain::esplora::EsploraError),
@@ -187,15 +253,30 @@ impl_error!(serde_json::Error, Json);
 impl_error!(bitcoin::hashes::hex::Error, Hex);
 impl_error!(bitcoin::util::psbt::Error, Psbt);
 impl_error!(bitcoin::util::psbt::PsbtParseError, PsbtParse);
+impl_error!(crate::blockchain::spv::SpvError, Spv);
 
 #[cfg(feature = "electrum")]
 impl_error!(electrum_client::Error, Electrum);
+#[cfg(feature = "electrum")]
+impl_error!(
+    crate::blockchain::electrum::BroadcastError,
+    ElectrumBroadcast
+);
 #[cfg(feature = "esplora")]
 impl_error!(crate::blockchain::esplora::EsploraError, Esplora);
 #[cfg(feature = "key-value-db")]
 impl_error!(sled::Error, Sled);
 #[cfg(feature = "rpc")]
 impl_error!(bitcoincore_rpc::Error, Rpc);
+#[cfg(feature = "bip47")]
+impl_error!(crate::wallet::bip47::Bip47Error, Bip47);
+#[cfg(feature = "encrypted-keys")]
+impl_error!(crate::keys::encrypted::EncryptionError, EncryptedKey);
+#[cfg(feature = "silent-payments")]
+impl_error!(
+    crate::wallet::silent_payments::SilentPaymentError,
+    SilentPayment
+);
 
 #[cfg(feature = "compact_filters")]
 impl From<crate::blockchain::compact_filters::CompactFiltersError> for Error {
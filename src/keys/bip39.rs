@@ -10,21 +10,412 @@
 // licenses.
 
 //! BIP-0039
-
-// TODO: maybe write our own implementation of bip39? Seems stupid to have an extra dependency for
-// something that should be fairly simple to re-implement.
-
+//!
+//! Mnemonic codes for generating deterministic keys, implemented directly in this crate. All the
+//! wordlists defined in [BIP-39] are supported, and a [`Mnemonic`] can be turned into a
+//! descriptor key, either directly or through one of the [`descriptor
+//! templates`](crate::descriptor::template):
+//!
+//! ```
+//! # use bdk::bitcoin::Network;
+//! # use bdk::keys::bip39::{Language, Mnemonic};
+//! # use bdk::template::Bip84;
+//! # use bdk::{KeychainKind, Wallet};
+//! # use bdk::database::MemoryDatabase;
+//! let mnemonic = Mnemonic::from_phrase(
+//!     "aim bunker wash balance finish force paper analyst cabin spoon stable organ",
+//!     Language::English,
+//! )?;
+//! let wallet = Wallet::new_offline(
+//!     Bip84((mnemonic.clone(), Some("passphrase".to_string())), KeychainKind::External),
+//!     Some(Bip84(mnemonic, KeychainKind::Internal)),
+//!     Network::Testnet,
+//!     MemoryDatabase::default(),
+//! )?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Mnemonics and passphrases are NFKD-normalized as required by the standard, so the same seed is
+//! derived whatever the normalization form of the input.
+//!
+//! [BIP-39]: https://github.com/bitcoin/bips/blob/master/bip-0039.mediawiki
+
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use bitcoin::hashes::{sha256, sha512, Hash, HashEngine};
 use bitcoin::util::bip32;
 use bitcoin::Network;
 
 use miniscript::ScriptContext;
 
-pub use bip39::{Language, Mnemonic, MnemonicType, Seed};
+use unicode_normalization::UnicodeNormalization;
 
 use super::{
     any_network, DerivableKey, DescriptorKey, ExtendedKey, GeneratableKey, GeneratedKey, KeyError,
 };
 
+const PBKDF2_ROUNDS: usize = 2048;
+
+fn nfkd(s: &str) -> String {
+    s.nfkd().collect()
+}
+
+/// Errors related to BIP-39 mnemonics
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Bip39Error {
+    /// The mnemonic has an invalid number of words
+    BadWordCount(usize),
+    /// The word isn't in the wordlist
+    UnknownWord(String),
+    /// The entropy has an invalid length, in bits
+    BadEntropyBitCount(usize),
+    /// The checksum of the mnemonic is wrong
+    InvalidChecksum,
+    /// The words of the mnemonic don't belong to any of the supported languages
+    UnknownLanguage,
+    /// The mnemonic is valid in more than one language
+    AmbiguousLanguage(Vec<Language>),
+}
+
+impl fmt::Display for Bip39Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for Bip39Error {}
+
+/// Language of a BIP-39 wordlist
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Language {
+    /// English
+    English,
+    /// Simplified Chinese
+    ChineseSimplified,
+    /// Traditional Chinese
+    ChineseTraditional,
+    /// Czech
+    Czech,
+    /// French
+    French,
+    /// Italian
+    Italian,
+    /// Japanese
+    Japanese,
+    /// Korean
+    Korean,
+    /// Portuguese
+    Portuguese,
+    /// Spanish
+    Spanish,
+}
+
+impl Language {
+    /// Return all the supported languages
+    pub fn all() -> &'static [Language] {
+        &[
+            Language::English,
+            Language::ChineseSimplified,
+            Language::ChineseTraditional,
+            Language::Czech,
+            Language::French,
+            Language::Italian,
+            Language::Japanese,
+            Language::Korean,
+            Language::Portuguese,
+            Language::Spanish,
+        ]
+    }
+
+    fn raw_word_list(&self) -> &'static str {
+        match self {
+            Language::English => include_str!("bip39/english.txt"),
+            Language::ChineseSimplified => include_str!("bip39/chinese_simplified.txt"),
+            Language::ChineseTraditional => include_str!("bip39/chinese_traditional.txt"),
+            Language::Czech => include_str!("bip39/czech.txt"),
+            Language::French => include_str!("bip39/french.txt"),
+            Language::Italian => include_str!("bip39/italian.txt"),
+            Language::Japanese => include_str!("bip39/japanese.txt"),
+            Language::Korean => include_str!("bip39/korean.txt"),
+            Language::Portuguese => include_str!("bip39/portuguese.txt"),
+            Language::Spanish => include_str!("bip39/spanish.txt"),
+        }
+    }
+
+    /// Return the 2048 words of the wordlist, NFKD-normalized
+    pub fn word_list(&self) -> Vec<&'static str> {
+        self.raw_word_list().lines().collect()
+    }
+
+    /// Return the index of `word` in the wordlist, if present
+    pub fn find_word(&self, word: &str) -> Option<u16> {
+        let word = nfkd(word);
+        self.raw_word_list()
+            .lines()
+            .position(|w| w == word)
+            .map(|index| index as u16)
+    }
+
+    // Japanese mnemonics are joined with ideographic spaces
+    fn separator(&self) -> &'static str {
+        match self {
+            Language::Japanese => "\u{3000}",
+            _ => " ",
+        }
+    }
+}
+
+/// Number of words of a mnemonic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MnemonicType {
+    /// 12 words, 128 bits of entropy
+    Words12,
+    /// 15 words, 160 bits of entropy
+    Words15,
+    /// 18 words, 192 bits of entropy
+    Words18,
+    /// 21 words, 224 bits of entropy
+    Words21,
+    /// 24 words, 256 bits of entropy
+    Words24,
+}
+
+impl MnemonicType {
+    /// Return the type for a given number of words
+    pub fn for_word_count(count: usize) -> Result<Self, Bip39Error> {
+        match count {
+            12 => Ok(MnemonicType::Words12),
+            15 => Ok(MnemonicType::Words15),
+            18 => Ok(MnemonicType::Words18),
+            21 => Ok(MnemonicType::Words21),
+            24 => Ok(MnemonicType::Words24),
+            _ => Err(Bip39Error::BadWordCount(count)),
+        }
+    }
+
+    /// Return the number of words
+    pub fn word_count(&self) -> usize {
+        self.entropy_bits() * 3 / 32
+    }
+
+    /// Return the number of bits of entropy
+    pub fn entropy_bits(&self) -> usize {
+        match self {
+            MnemonicType::Words12 => 128,
+            MnemonicType::Words15 => 160,
+            MnemonicType::Words18 => 192,
+            MnemonicType::Words21 => 224,
+            MnemonicType::Words24 => 256,
+        }
+    }
+}
+
+/// A BIP-39 mnemonic
+#[derive(Clone, PartialEq, Eq)]
+pub struct Mnemonic {
+    language: Language,
+    entropy: Vec<u8>,
+    indexes: Vec<u16>,
+}
+
+impl Mnemonic {
+    /// Create a mnemonic from its entropy, which must be 16, 20, 24, 28 or 32 bytes long
+    pub fn from_entropy(entropy: &[u8], language: Language) -> Result<Self, Bip39Error> {
+        let bits = entropy.len() * 8;
+        if !(128..=256).contains(&bits) || bits % 32 != 0 {
+            return Err(Bip39Error::BadEntropyBitCount(bits));
+        }
+
+        let checksum = sha256::Hash::hash(entropy).into_inner();
+        let bit = |i: usize| {
+            let byte = if i < bits {
+                entropy[i / 8]
+            } else {
+                checksum[(i - bits) / 8]
+            };
+            (byte >> (7 - i % 8)) & 1 == 1
+        };
+        let indexes = (0..(bits + bits / 32) / 11)
+            .map(|word| (0..11).fold(0u16, |acc, i| (acc << 1) | bit(word * 11 + i) as u16))
+            .collect();
+
+        Ok(Mnemonic {
+            language,
+            entropy: entropy.to_vec(),
+            indexes,
+        })
+    }
+
+    /// Parse a mnemonic in the given language, verifying its checksum
+    ///
+    /// The words are matched case-insensitively, and can be separated by any whitespace,
+    /// including ideographic spaces.
+    pub fn from_phrase(phrase: &str, language: Language) -> Result<Self, Bip39Error> {
+        let phrase = nfkd(&phrase.to_lowercase());
+        let words = phrase.split_whitespace().collect::<Vec<_>>();
+        MnemonicType::for_word_count(words.len())?;
+
+        let indexes = words
+            .iter()
+            .map(|word| {
+                language
+                    .find_word(word)
+                    .ok_or_else(|| Bip39Error::UnknownWord(word.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let bits = indexes.len() * 11 * 32 / 33;
+        let mut entropy = vec![0u8; bits / 8];
+        for (i, index) in indexes.iter().enumerate() {
+            for j in 0..11 {
+                let bit = i * 11 + j;
+                if bit < bits && (index >> (10 - j)) & 1 == 1 {
+                    entropy[bit / 8] |= 1 << (7 - bit % 8);
+                }
+            }
+        }
+
+        let mnemonic = Mnemonic::from_entropy(&entropy, language)?;
+        if mnemonic.indexes != indexes {
+            return Err(Bip39Error::InvalidChecksum);
+        }
+
+        Ok(mnemonic)
+    }
+
+    /// Parse a mnemonic, detecting its language
+    pub fn from_phrase_any_language(phrase: &str) -> Result<Self, Bip39Error> {
+        let mut valid = Vec::new();
+        let mut error = Bip39Error::UnknownLanguage;
+        for language in Language::all() {
+            match Mnemonic::from_phrase(phrase, *language) {
+                Ok(mnemonic) => valid.push(mnemonic),
+                Err(e @ Bip39Error::BadWordCount(_)) => return Err(e),
+                Err(Bip39Error::InvalidChecksum) => error = Bip39Error::InvalidChecksum,
+                Err(_) => {}
+            }
+        }
+
+        match valid.len() {
+            0 => Err(error),
+            1 => Ok(valid.pop().unwrap()),
+            _ => Err(Bip39Error::AmbiguousLanguage(
+                valid.iter().map(|m| m.language).collect(),
+            )),
+        }
+    }
+
+    /// Return the language of the mnemonic
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    /// Return the entropy encoded by the mnemonic
+    pub fn entropy(&self) -> &[u8] {
+        &self.entropy
+    }
+
+    /// Return the words of the mnemonic
+    pub fn words(&self) -> Vec<&'static str> {
+        let word_list = self.language.word_list();
+        self.indexes
+            .iter()
+            .map(|index| word_list[*index as usize])
+            .collect()
+    }
+
+    /// Return the number of words of the mnemonic
+    pub fn word_count(&self) -> usize {
+        self.indexes.len()
+    }
+
+    /// Return the mnemonic phrase
+    pub fn phrase(&self) -> String {
+        self.words().join(self.language.separator())
+    }
+
+    /// Compute the seed of the mnemonic with the given passphrase
+    pub fn to_seed(&self, passphrase: &str) -> Seed {
+        Seed::new(self, passphrase)
+    }
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.phrase())
+    }
+}
+
+// Don't show the words in the logs
+impl fmt::Debug for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mnemonic")
+            .field("language", &self.language)
+            .field("word_count", &self.word_count())
+            .finish()
+    }
+}
+
+impl FromStr for Mnemonic {
+    type Err = Bip39Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Mnemonic::from_phrase_any_language(s)
+    }
+}
+
+/// The 64 bytes seed derived from a [`Mnemonic`] and a passphrase
+#[derive(Clone)]
+pub struct Seed([u8; 64]);
+
+// Arrays longer than 32 elements only implement `PartialEq` since Rust 1.47
+impl PartialEq for Seed {
+    fn eq(&self, other: &Self) -> bool {
+        self.0[..] == other.0[..]
+    }
+}
+
+impl Eq for Seed {}
+
+impl Seed {
+    /// Compute the seed of `mnemonic` with PBKDF2-HMAC-SHA512
+    pub fn new(mnemonic: &Mnemonic, passphrase: &str) -> Self {
+        let password = mnemonic.words().join(" ");
+        let salt = format!("mnemonic{}", nfkd(passphrase));
+
+        let prepared = HmacEngine::<sha512::Hash>::new(password.as_bytes());
+        let mut engine = prepared.clone();
+        engine.input(salt.as_bytes());
+        engine.input(&1u32.to_be_bytes());
+        let mut block = Hmac::from_engine(engine).into_inner();
+
+        let mut seed = block;
+        for _ in 1..PBKDF2_ROUNDS {
+            let mut engine = prepared.clone();
+            engine.input(&block);
+            block = Hmac::from_engine(engine).into_inner();
+            for (s, b) in seed.iter_mut().zip(block.iter()) {
+                *s ^= b;
+            }
+        }
+
+        Seed(seed)
+    }
+
+    /// Return the seed bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Seed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Seed(..)")
+    }
+}
+
 fn set_valid_on_any_network<Ctx: ScriptContext>(
     descriptor_key: DescriptorKey<Ctx>,
 ) -> DescriptorKey<Ctx> {
@@ -60,7 +451,7 @@ impl<Ctx: ScriptContext> DerivableKey<Ctx> for Seed {
 impl<Ctx: ScriptContext> DerivableKey<Ctx> for MnemonicWithPassphrase {
     fn into_extended_key(self) -> Result<ExtendedKey<Ctx>, KeyError> {
         let (mnemonic, passphrase) = self;
-        let seed = Seed::new(&mnemonic, passphrase.as_deref().unwrap_or(""));
+        let seed = mnemonic.to_seed(passphrase.as_deref().unwrap_or(""));
 
         seed.into_extended_key()
     }
@@ -102,14 +493,14 @@ impl<Ctx: ScriptContext> GeneratableKey<Ctx> for Mnemonic {
     type Entropy = [u8; 32];
 
     type Options = (MnemonicType, Language);
-    type Error = Option<bip39::ErrorKind>;
+    type Error = Bip39Error;
 
     fn generate_with_entropy(
         (mnemonic_type, language): Self::Options,
         entropy: Self::Entropy,
     ) -> Result<GeneratedKey<Self, Ctx>, Self::Error> {
         let entropy = &entropy.as_ref()[..(mnemonic_type.entropy_bits() / 8)];
-        let mnemonic = Mnemonic::from_entropy(entropy, language).map_err(|e| e.downcast().ok())?;
+        let mnemonic = Mnemonic::from_entropy(entropy, language)?;
 
         Ok(GeneratedKey::new(mnemonic, any_network()))
     }
@@ -119,9 +510,10 @@ impl<Ctx: ScriptContext> GeneratableKey<Ctx> for Mnemonic {
 mod test {
     use std::str::FromStr;
 
+    use bitcoin::hashes::hex::FromHex;
     use bitcoin::util::bip32;
 
-    use bip39::{Language, Mnemonic, MnemonicType};
+    use super::*;
 
     use crate::keys::{any_network, GeneratableKey, GeneratedKey};
 
@@ -187,4 +579,127 @@ mod test {
             Mnemonic::generate((MnemonicType::Words24, Language::English)).unwrap();
         assert_eq!(generated_mnemonic.valid_networks, any_network());
     }
+
+    #[test]
+    fn test_keys_bip39_vectors() {
+        let mnemonic = Mnemonic::from_entropy(&[0x00; 16], Language::English).unwrap();
+        assert_eq!(
+            mnemonic.phrase(),
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about"
+        );
+        assert_eq!(
+            mnemonic.to_seed("TREZOR").as_bytes().to_vec(),
+            Vec::<u8>::from_hex("c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04").unwrap()
+        );
+
+        let mnemonic = Mnemonic::from_entropy(&[0xff; 32], Language::English).unwrap();
+        assert_eq!(
+            mnemonic.phrase(),
+            "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo vote"
+        );
+        assert_eq!(
+            mnemonic.to_seed("TREZOR").as_bytes().to_vec(),
+            Vec::<u8>::from_hex("dd48c104698c30cfe2b6142103248622fb7bb0ff692eebb00089b32d22484e1613912f0a5b694407be899ffd31ed3992c456cdf60f5d4564b8ba3f05a69890ad").unwrap()
+        );
+
+        let parsed = Mnemonic::from_phrase(&mnemonic.phrase(), Language::English).unwrap();
+        assert_eq!(parsed.entropy(), &[0xff; 32][..]);
+    }
+
+    #[test]
+    fn test_keys_bip39_passphrase_normalization() {
+        let mnemonic = Mnemonic::from_entropy(&[0x00; 16], Language::English).unwrap();
+        // Cyrillic, a ligature, a fullwidth letter, Greek with tonos and Vietnamese
+        let passphrase = "\u{451}\u{fb01}\u{ff21}\u{3ac} vi\u{1ec7}t";
+        let expected = Vec::<u8>::from_hex("2031799149437a931205cc31491d4b419d255436ed458d945b9ef928b9317e66849496b7ac33aa9ea75f1e65963335d3658ae638d0de91fe86551312f9a5daa5").unwrap();
+
+        assert_eq!(mnemonic.to_seed(passphrase).as_bytes().to_vec(), expected);
+        // the same passphrase, already decomposed
+        assert_eq!(
+            mnemonic
+                .to_seed("\u{435}\u{308}fiA\u{3b1}\u{301} vie\u{323}\u{302}t")
+                .as_bytes()
+                .to_vec(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_keys_bip39_japanese() {
+        let mnemonic = Mnemonic::from_entropy(&[0x00; 16], Language::Japanese).unwrap();
+        assert_eq!(
+            mnemonic.phrase(),
+            format!(
+                "{}\u{3000}あおぞら",
+                vec!["あいこくしん"; 11].join("\u{3000}")
+            )
+        );
+        assert_eq!(
+            mnemonic.to_seed("TREZOR").as_bytes().to_vec(),
+            Vec::<u8>::from_hex("5a6c23b5abdd5c3e1f7d77ad25ecd715647bdafb44dab324c730a76a45d7421daccee1a4ff0739715a2c56a8a9f1e527a5e3496224d91293bfcd9b5393bfff83").unwrap()
+        );
+
+        // composed characters are normalized when parsing
+        let parsed = Mnemonic::from_phrase(&mnemonic.phrase(), Language::Japanese).unwrap();
+        assert_eq!(parsed.entropy(), &[0x00; 16][..]);
+        assert_eq!(parsed, Mnemonic::from_str(&mnemonic.phrase()).unwrap());
+    }
+
+    #[test]
+    fn test_keys_bip39_find_word() {
+        assert_eq!(Language::Spanish.find_word("\u{e1}baco"), Some(0));
+        assert_eq!(Language::Spanish.find_word("a\u{301}baco"), Some(0));
+        assert_eq!(Language::Korean.find_word("가격"), Some(0));
+        assert_eq!(Language::English.find_word("zoo"), Some(2047));
+        assert_eq!(Language::English.find_word("bitcoin"), None);
+
+        for language in Language::all() {
+            assert_eq!(language.word_list().len(), 2048);
+        }
+    }
+
+    #[test]
+    fn test_keys_bip39_invalid() {
+        assert_eq!(
+            Mnemonic::from_phrase("abandon abandon", Language::English),
+            Err(Bip39Error::BadWordCount(2))
+        );
+        assert_eq!(
+            Mnemonic::from_phrase(&["abandon"; 12].join(" "), Language::English),
+            Err(Bip39Error::InvalidChecksum)
+        );
+        assert_eq!(
+            Mnemonic::from_phrase(
+                "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon bitcoin",
+                Language::English
+            ),
+            Err(Bip39Error::UnknownWord("bitcoin".to_string()))
+        );
+        assert_eq!(
+            Mnemonic::from_entropy(&[0x00; 17], Language::English),
+            Err(Bip39Error::BadEntropyBitCount(136))
+        );
+        assert_eq!(
+            Mnemonic::from_phrase_any_language(&["bitcoin"; 12].join(" ")),
+            Err(Bip39Error::UnknownLanguage)
+        );
+    }
+
+    #[test]
+    fn test_keys_bip39_template() {
+        use crate::descriptor::template::{Bip84, DescriptorTemplate};
+        use crate::KeychainKind;
+
+        let mnemonic = Mnemonic::from_str(
+            "aim bunker wash balance finish force paper analyst cabin spoon stable organ",
+        )
+        .unwrap();
+        assert_eq!(mnemonic.language(), Language::English);
+        assert_eq!(mnemonic.word_count(), 12);
+
+        let (desc, keys, networks) = Bip84(mnemonic, KeychainKind::External).build().unwrap();
+        assert!(desc.to_string().starts_with("wpkh([be83839f/84'/0'/0']"));
+        assert_eq!(keys.len(), 1);
+        assert_eq!(networks.len(), 4);
+    }
 }
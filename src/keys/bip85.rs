@@ -0,0 +1,313 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! BIP-0085
+//!
+//! Deterministic entropy derived from a root key, as specified in [BIP-85]. A single backed up
+//! master key can be used to derive the mnemonics, WIF keys and xprvs of other applications or
+//! wallets, which can then be recovered from the master key alone.
+//!
+//! ```
+//! # use std::str::FromStr;
+//! # use bdk::bitcoin::secp256k1::Secp256k1;
+//! # use bdk::bitcoin::util::bip32::ExtendedPrivKey;
+//! # use bdk::keys::bip85::Bip85Root;
+//! let secp = Secp256k1::new();
+//! let root = Bip85Root::new(ExtendedPrivKey::from_str("xprv9s21ZrQH143K2LBWUUQRFXhucrQqBpKdRRxNVq2zBqsx8HVqFk2uYo8kmbaLLHRdqtQpUm98uKfu3vca1LqdGhUtyoFnCNkfmXRyPXLjbKb")?);
+//!
+//! // Derive the xprv of a new wallet, at index 0
+//! let child = root.derive_xprv(&secp, 0)?;
+//! assert_eq!(child.to_string(), "xprv9s21ZrQH143K2srSbCSg4m4kLvPMzcWydgmKEnMmoZUurYuBuYG46c6P71UGXMzmriLzCCBvKQWBUv3vPB3m1SATMhp3uEjXHJ42jFg7myX");
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! The root of a [`Wallet`](crate::Wallet) can be retrieved with
+//! [`Wallet::bip85_root`](crate::Wallet::bip85_root).
+//!
+//! [BIP-85]: https://github.com/bitcoin/bips/blob/master/bip-0085.mediawiki
+
+use bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use bitcoin::hashes::{sha512, Hash, HashEngine};
+use bitcoin::secp256k1::{self, Secp256k1, Signing};
+use bitcoin::util::bip32::{ChainCode, ChildNumber, DerivationPath, ExtendedPrivKey, Fingerprint};
+use bitcoin::PrivateKey;
+
+#[cfg(feature = "keys-bip39")]
+use super::bip39::{Language, Mnemonic, MnemonicType};
+use super::KeyError;
+
+const BIP85_PURPOSE: u32 = 83_696_968;
+const HMAC_KEY: &[u8] = b"bip-entropy-from-k";
+
+const APP_BIP39: u32 = 39;
+const APP_WIF: u32 = 2;
+const APP_XPRV: u32 = 32;
+const APP_HEX: u32 = 128_169;
+
+/// Root key used to derive BIP-85 child entropy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bip85Root(ExtendedPrivKey);
+
+impl Bip85Root {
+    /// Create a new root from an xprv, usually the master key of a wallet
+    pub fn new(xprv: ExtendedPrivKey) -> Self {
+        Bip85Root(xprv)
+    }
+
+    /// Return the root xprv
+    pub fn xprv(&self) -> &ExtendedPrivKey {
+        &self.0
+    }
+
+    /// Derive the 64 bytes of entropy at `path`, which must only contain hardened steps
+    pub fn derive_entropy<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        path: &DerivationPath,
+    ) -> Result<[u8; 64], KeyError> {
+        if path
+            .into_iter()
+            .any(|child| !matches!(child, ChildNumber::Hardened { .. }))
+        {
+            return Err(KeyError::Message(
+                "BIP-85 derivation paths must be hardened".to_string(),
+            ));
+        }
+
+        let derived = self.0.derive_priv(secp, path)?;
+        let mut engine = HmacEngine::<sha512::Hash>::new(HMAC_KEY);
+        engine.input(&derived.private_key.key[..]);
+
+        Ok(Hmac::from_engine(engine).into_inner())
+    }
+
+    fn derive_application<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        path: &[u32],
+    ) -> Result<[u8; 64], KeyError> {
+        let path = std::iter::once(BIP85_PURPOSE)
+            .chain(path.iter().copied())
+            .map(ChildNumber::from_hardened_idx)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.derive_entropy(secp, &path.into())
+    }
+
+    /// Derive the BIP-39 mnemonic at `index`, for the given language and number of words
+    #[cfg(feature = "keys-bip39")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "keys-bip39")))]
+    pub fn derive_mnemonic<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        mnemonic_type: MnemonicType,
+        language: Language,
+        index: u32,
+    ) -> Result<Mnemonic, KeyError> {
+        let language_code = match language {
+            Language::English => 0,
+            Language::Japanese => 1,
+            Language::Korean => 2,
+            Language::Spanish => 3,
+            Language::ChineseSimplified => 4,
+            Language::ChineseTraditional => 5,
+            Language::French => 6,
+            Language::Italian => 7,
+            Language::Czech => 8,
+            Language::Portuguese => {
+                return Err(KeyError::Message(
+                    "BIP-85 doesn't define a code for Portuguese".to_string(),
+                ))
+            }
+        };
+
+        let entropy = self.derive_application(
+            secp,
+            &[
+                APP_BIP39,
+                language_code,
+                mnemonic_type.word_count() as u32,
+                index,
+            ],
+        )?;
+        Mnemonic::from_entropy(&entropy[..mnemonic_type.entropy_bits() / 8], language)
+            .map_err(|e| KeyError::Message(e.to_string()))
+    }
+
+    /// Derive the private key at `index`, to be exported in the WIF format
+    pub fn derive_wif<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+    ) -> Result<PrivateKey, KeyError> {
+        let entropy = self.derive_application(secp, &[APP_WIF, index])?;
+        let key = secp256k1::SecretKey::from_slice(&entropy[..32])
+            .map_err(|e| KeyError::Message(e.to_string()))?;
+
+        Ok(PrivateKey {
+            compressed: true,
+            network: self.0.network,
+            key,
+        })
+    }
+
+    /// Derive the master xprv at `index`
+    pub fn derive_xprv<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+    ) -> Result<ExtendedPrivKey, KeyError> {
+        let entropy = self.derive_application(secp, &[APP_XPRV, index])?;
+        let key = secp256k1::SecretKey::from_slice(&entropy[32..])
+            .map_err(|e| KeyError::Message(e.to_string()))?;
+
+        Ok(ExtendedPrivKey {
+            network: self.0.network,
+            depth: 0,
+            parent_fingerprint: Fingerprint::default(),
+            child_number: ChildNumber::from_normal_idx(0)?,
+            private_key: PrivateKey {
+                compressed: true,
+                network: self.0.network,
+                key,
+            },
+            chain_code: ChainCode::from(&entropy[..32]),
+        })
+    }
+
+    /// Derive `num_bytes` of raw entropy at `index`, between 16 and 64 bytes
+    pub fn derive_hex<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        num_bytes: usize,
+        index: u32,
+    ) -> Result<Vec<u8>, KeyError> {
+        if !(16..=64).contains(&num_bytes) {
+            return Err(KeyError::Message(format!(
+                "Invalid number of bytes {}, must be between 16 and 64",
+                num_bytes
+            )));
+        }
+
+        let entropy = self.derive_application(secp, &[APP_HEX, num_bytes as u32, index])?;
+        Ok(entropy[..num_bytes].to_vec())
+    }
+}
+
+impl From<ExtendedPrivKey> for Bip85Root {
+    fn from(xprv: ExtendedPrivKey) -> Self {
+        Bip85Root::new(xprv)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::hashes::hex::{FromHex, ToHex};
+
+    use super::*;
+
+    fn test_root() -> Bip85Root {
+        Bip85Root::new(ExtendedPrivKey::from_str("xprv9s21ZrQH143K2LBWUUQRFXhucrQqBpKdRRxNVq2zBqsx8HVqFk2uYo8kmbaLLHRdqtQpUm98uKfu3vca1LqdGhUtyoFnCNkfmXRyPXLjbKb").unwrap())
+    }
+
+    #[test]
+    fn test_bip85_entropy() {
+        let secp = Secp256k1::new();
+        let root = test_root();
+
+        let entropy = root
+            .derive_entropy(
+                &secp,
+                &DerivationPath::from_str("m/83696968'/0'/0'").unwrap(),
+            )
+            .unwrap();
+        assert_eq!(entropy.to_hex(), "efecfbccffea313214232d29e71563d941229afb4338c21f9517c41aaa0d16f00b83d2a09ef747e7a64e8e2bd5a14869e693da66ce94ac2da570ab7ee48618f7");
+
+        let entropy = root
+            .derive_entropy(
+                &secp,
+                &DerivationPath::from_str("m/83696968'/0'/1'").unwrap(),
+            )
+            .unwrap();
+        assert_eq!(entropy.to_hex(), "70c6e3e8ebee8dc4c0dbba66076819bb8c09672527c4277ca8729532ad711872218f826919f6b67218adde99018a6df9095ab2b58d803b5b93ec9802085a690e");
+
+        assert!(matches!(
+            root.derive_entropy(
+                &secp,
+                &DerivationPath::from_str("m/83696968'/0'/0").unwrap()
+            ),
+            Err(KeyError::Message(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "keys-bip39")]
+    fn test_bip85_mnemonic() {
+        let secp = Secp256k1::new();
+        let root = test_root();
+
+        let mnemonic = root
+            .derive_mnemonic(&secp, MnemonicType::Words12, Language::English, 0)
+            .unwrap();
+        assert_eq!(
+            mnemonic.phrase(),
+            "girl mad pet galaxy egg matter matrix prison refuse sense ordinary nose"
+        );
+
+        let mnemonic = root
+            .derive_mnemonic(&secp, MnemonicType::Words18, Language::English, 0)
+            .unwrap();
+        assert_eq!(
+            mnemonic.phrase(),
+            "near account window bike charge season chef number sketch tomorrow excuse sniff circle vital hockey outdoor supply token"
+        );
+
+        let mnemonic = root
+            .derive_mnemonic(&secp, MnemonicType::Words24, Language::English, 0)
+            .unwrap();
+        assert_eq!(
+            mnemonic.phrase(),
+            "puppy ocean match cereal symbol another shed magic wrap hammer bulb intact gadget divorce twin tonight reason outdoor destroy simple truth cigar social volcano"
+        );
+    }
+
+    #[test]
+    fn test_bip85_wif() {
+        let secp = Secp256k1::new();
+        let key = test_root().derive_wif(&secp, 0).unwrap();
+        assert_eq!(
+            key.to_wif(),
+            "Kzyv4uF39d4Jrw2W7UryTHwZr1zQVNk4dAFyqE6BuMrMh1Za7uhp"
+        );
+    }
+
+    #[test]
+    fn test_bip85_xprv() {
+        let secp = Secp256k1::new();
+        let xprv = test_root().derive_xprv(&secp, 0).unwrap();
+        assert_eq!(xprv.to_string(), "xprv9s21ZrQH143K2srSbCSg4m4kLvPMzcWydgmKEnMmoZUurYuBuYG46c6P71UGXMzmriLzCCBvKQWBUv3vPB3m1SATMhp3uEjXHJ42jFg7myX");
+    }
+
+    #[test]
+    fn test_bip85_hex() {
+        let secp = Secp256k1::new();
+        let root = test_root();
+
+        let entropy = root.derive_hex(&secp, 64, 0).unwrap();
+        assert_eq!(entropy, Vec::<u8>::from_hex("492db4698cf3b73a5a24998aa3e9d7fa96275d85724a91e71aa2d645442f878555d078fd1f1f67e368976f04137b1f7a0d19232136ca50c44614af72b5582a5c").unwrap());
+
+        assert!(matches!(
+            root.derive_hex(&secp, 8, 0),
+            Err(KeyError::Message(_))
+        ));
+    }
+}
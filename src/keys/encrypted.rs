@@ -0,0 +1,525 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Encrypted secret keys
+//!
+//! This module implements [`EncryptedDescriptorSecretKey`], a secret key encrypted with
+//! AES-256-GCM under a key derived from a passphrase with PBKDF2-HMAC-SHA256, and the
+//! [`EncryptedSigner`] that decrypts it only while signing. The passphrase is requested to a
+//! [`KeyProvider`] every time the signer is used, so the plaintext key is never held by the
+//! [`Wallet`](crate::Wallet).
+//!
+//! The encrypted keys can be serialized with their [`Display`](std::fmt::Display) implementation
+//! and stored next to the wallet's database, to be loaded back with [`FromStr`].
+//!
+//! ```
+//! # use std::sync::Arc;
+//! # use bdk::bitcoin::Network;
+//! # use bdk::database::MemoryDatabase;
+//! # use bdk::keys::encrypted::{EncryptedSigner, Passphrase};
+//! # use bdk::signer::SignerOrdering;
+//! # use bdk::{KeychainKind, Wallet};
+//! let mut wallet = Wallet::new_offline(
+//!     "wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/0/*)",
+//!     None,
+//!     Network::Testnet,
+//!     MemoryDatabase::new(),
+//! )?;
+//!
+//! // Replace the xprv of the wallet with an encrypted copy
+//! let provider = Arc::new(Passphrase::new("correct horse battery staple"));
+//! let encrypted = wallet.encrypt_signers(
+//!     KeychainKind::External,
+//!     "correct horse battery staple",
+//!     provider.clone(),
+//! )?;
+//! let serialized = encrypted[0].to_string();
+//!
+//! // Later, load it back into a watch-only wallet
+//! let public_descriptor = wallet.public_descriptor(KeychainKind::External)?.unwrap();
+//! let mut wallet = Wallet::new_offline(
+//!     &public_descriptor.to_string(),
+//!     None,
+//!     Network::Testnet,
+//!     MemoryDatabase::new(),
+//! )?;
+//! let signer = EncryptedSigner::new(serialized.parse()?, provider);
+//! wallet.add_signer(KeychainKind::External, SignerOrdering::default(), Arc::new(signer));
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use std::fmt;
+use std::num::NonZeroU32;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::util::psbt;
+
+use miniscript::descriptor::{DescriptorPublicKey, DescriptorSecretKey};
+use miniscript::MiniscriptKey;
+
+use rand::RngCore;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::pbkdf2;
+
+use crate::descriptor::XKeyUtils;
+use crate::wallet::signer::{SignOptions, Signer, SignerError, SignerId};
+use crate::wallet::utils::SecpCtx;
+
+/// Default number of PBKDF2 iterations used to derive the encryption key from the passphrase
+pub const DEFAULT_ITERATIONS: u32 = 600_000;
+
+const SALT_LEN: usize = 16;
+
+/// Errors thrown while encrypting or decrypting keys
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum EncryptionError {
+    /// The passphrase is wrong, or the encrypted data has been tampered with
+    InvalidPassphrase,
+    /// The number of PBKDF2 iterations must be greater than zero
+    InvalidIterations,
+    /// The public key can't be derived from the secret key, or the decrypted key is invalid
+    InvalidKey,
+    /// The serialized encrypted key is malformed
+    InvalidFormat,
+}
+
+impl fmt::Display for EncryptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for EncryptionError {}
+
+/// A [`DescriptorSecretKey`] encrypted with a passphrase
+///
+/// The public key is stored in clear, so that the signer can be identified without decrypting
+/// the secret key, and is authenticated together with the ciphertext.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptedDescriptorSecretKey {
+    public_key: DescriptorPublicKey,
+    iterations: u32,
+    salt: [u8; SALT_LEN],
+    nonce: [u8; NONCE_LEN],
+    ciphertext: Vec<u8>,
+}
+
+impl EncryptedDescriptorSecretKey {
+    /// Encrypt `secret_key` with `passphrase`, using [`DEFAULT_ITERATIONS`]
+    pub fn encrypt(
+        secret_key: &DescriptorSecretKey,
+        passphrase: &str,
+        secp: &SecpCtx,
+    ) -> Result<Self, EncryptionError> {
+        Self::encrypt_with_iterations(secret_key, passphrase, DEFAULT_ITERATIONS, secp)
+    }
+
+    /// Encrypt `secret_key` with `passphrase`, using a custom number of PBKDF2 iterations
+    pub fn encrypt_with_iterations(
+        secret_key: &DescriptorSecretKey,
+        passphrase: &str,
+        iterations: u32,
+        secp: &SecpCtx,
+    ) -> Result<Self, EncryptionError> {
+        let public_key = secret_key
+            .as_public(secp)
+            .map_err(|_| EncryptionError::InvalidKey)?;
+
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let mut encrypted = EncryptedDescriptorSecretKey {
+            public_key,
+            iterations,
+            salt,
+            nonce,
+            ciphertext: secret_key.to_string().into_bytes(),
+        };
+        let key = encrypted.encryption_key(passphrase)?;
+        let aad = encrypted.public_key.to_string();
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(aad.as_bytes()),
+            &mut encrypted.ciphertext,
+        )
+        .map_err(|_| EncryptionError::InvalidKey)?;
+
+        Ok(encrypted)
+    }
+
+    /// Decrypt the secret key with `passphrase`
+    pub fn decrypt(&self, passphrase: &str) -> Result<DescriptorSecretKey, EncryptionError> {
+        let key = self.encryption_key(passphrase)?;
+        let aad = self.public_key.to_string();
+
+        let mut buffer = self.ciphertext.clone();
+        let result = key
+            .open_in_place(
+                Nonce::assume_unique_for_key(self.nonce),
+                Aad::from(aad.as_bytes()),
+                &mut buffer,
+            )
+            .map_err(|_| EncryptionError::InvalidPassphrase)
+            .and_then(|plaintext| {
+                std::str::from_utf8(plaintext)
+                    .ok()
+                    .and_then(|s| DescriptorSecretKey::from_str(s).ok())
+                    .ok_or(EncryptionError::InvalidKey)
+            });
+
+        // Don't leave the plaintext key around
+        for byte in buffer.iter_mut() {
+            *byte = 0;
+        }
+
+        result
+    }
+
+    /// Return the public key corresponding to the encrypted secret key
+    pub fn public_key(&self) -> &DescriptorPublicKey {
+        &self.public_key
+    }
+
+    /// Return the [`SignerId`] of the encrypted key
+    pub fn signer_id(&self, secp: &SecpCtx) -> SignerId {
+        match &self.public_key {
+            DescriptorPublicKey::SinglePub(single) => SignerId::from(single.key.to_pubkeyhash()),
+            DescriptorPublicKey::XPub(xpub) => SignerId::from(xpub.root_fingerprint(secp)),
+        }
+    }
+
+    fn encryption_key(&self, passphrase: &str) -> Result<LessSafeKey, EncryptionError> {
+        let iterations =
+            NonZeroU32::new(self.iterations).ok_or(EncryptionError::InvalidIterations)?;
+
+        let mut key = [0u8; 32];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            iterations,
+            &self.salt,
+            passphrase.as_bytes(),
+            &mut key,
+        );
+        let unbound = UnboundKey::new(&AES_256_GCM, &key).map_err(|_| EncryptionError::InvalidKey);
+        for byte in key.iter_mut() {
+            *byte = 0;
+        }
+
+        Ok(LessSafeKey::new(unbound?))
+    }
+}
+
+impl fmt::Display for EncryptedDescriptorSecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}:{}",
+            self.public_key,
+            self.iterations,
+            self.salt.to_hex(),
+            self.nonce.to_hex(),
+            self.ciphertext.to_hex()
+        )
+    }
+}
+
+impl FromStr for EncryptedDescriptorSecretKey {
+    type Err = EncryptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s.split(':').collect::<Vec<_>>();
+        let (public_key, iterations, salt, nonce, ciphertext) = match parts[..] {
+            [public_key, iterations, salt, nonce, ciphertext] => {
+                (public_key, iterations, salt, nonce, ciphertext)
+            }
+            _ => return Err(EncryptionError::InvalidFormat),
+        };
+
+        let salt = Vec::<u8>::from_hex(salt).map_err(|_| EncryptionError::InvalidFormat)?;
+        let nonce = Vec::<u8>::from_hex(nonce).map_err(|_| EncryptionError::InvalidFormat)?;
+        if salt.len() != SALT_LEN || nonce.len() != NONCE_LEN {
+            return Err(EncryptionError::InvalidFormat);
+        }
+
+        let mut encrypted = EncryptedDescriptorSecretKey {
+            public_key: DescriptorPublicKey::from_str(public_key)
+                .map_err(|_| EncryptionError::InvalidFormat)?,
+            iterations: iterations
+                .parse()
+                .map_err(|_| EncryptionError::InvalidFormat)?,
+            salt: [0u8; SALT_LEN],
+            nonce: [0u8; NONCE_LEN],
+            ciphertext: Vec::<u8>::from_hex(ciphertext)
+                .map_err(|_| EncryptionError::InvalidFormat)?,
+        };
+        encrypted.salt.copy_from_slice(&salt);
+        encrypted.nonce.copy_from_slice(&nonce);
+
+        Ok(encrypted)
+    }
+}
+
+/// Trait for types that provide the passphrase of an [`EncryptedDescriptorSecretKey`]
+///
+/// This is called every time an [`EncryptedSigner`] needs to sign, so implementations can prompt
+/// the user for the passphrase instead of keeping it in memory.
+pub trait KeyProvider: fmt::Debug + Send + Sync {
+    /// Return the passphrase for `key`, or [`SignerError::UserCanceled`] if it's not available
+    fn passphrase(&self, key: &EncryptedDescriptorSecretKey) -> Result<String, SignerError>;
+}
+
+/// A [`KeyProvider`] that always returns the same passphrase
+#[derive(Clone)]
+pub struct Passphrase(String);
+
+impl Passphrase {
+    /// Create a new provider for `passphrase`
+    pub fn new(passphrase: &str) -> Self {
+        Passphrase(passphrase.to_string())
+    }
+}
+
+impl fmt::Debug for Passphrase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Passphrase").field(&"<redacted>").finish()
+    }
+}
+
+impl KeyProvider for Passphrase {
+    fn passphrase(&self, _key: &EncryptedDescriptorSecretKey) -> Result<String, SignerError> {
+        Ok(self.0.clone())
+    }
+}
+
+/// Signer that decrypts its [`EncryptedDescriptorSecretKey`] only while signing
+#[derive(Debug)]
+pub struct EncryptedSigner {
+    key: EncryptedDescriptorSecretKey,
+    provider: Arc<dyn KeyProvider>,
+}
+
+impl EncryptedSigner {
+    /// Create a new signer for `key`, decrypted with the passphrase returned by `provider`
+    pub fn new(key: EncryptedDescriptorSecretKey, provider: Arc<dyn KeyProvider>) -> Self {
+        EncryptedSigner { key, provider }
+    }
+
+    /// Return the encrypted key
+    pub fn encrypted_key(&self) -> &EncryptedDescriptorSecretKey {
+        &self.key
+    }
+
+    // Whether the key may be able to sign one of the inputs, to avoid asking for the passphrase
+    // when it's not needed
+    fn can_sign(&self, psbt: &psbt::PartiallySignedTransaction, secp: &SecpCtx) -> bool {
+        psbt.inputs
+            .iter()
+            .filter(|input| {
+                input.final_script_sig.is_none() && input.final_script_witness.is_none()
+            })
+            .any(|input| match &self.key.public_key {
+                DescriptorPublicKey::XPub(xpub) => {
+                    input.bip32_derivation.values().any(|(fingerprint, path)| {
+                        xpub.matches(&(*fingerprint, path.clone()), secp).is_some()
+                    })
+                }
+                DescriptorPublicKey::SinglePub(_) => true,
+            })
+    }
+}
+
+impl Signer for EncryptedSigner {
+    fn sign(
+        &self,
+        psbt: &mut psbt::PartiallySignedTransaction,
+        input_index: Option<usize>,
+        secp: &SecpCtx,
+    ) -> Result<(), SignerError> {
+        self.sign_with_options(psbt, input_index, &SignOptions::default(), secp)
+    }
+
+    fn sign_with_options(
+        &self,
+        psbt: &mut psbt::PartiallySignedTransaction,
+        _input_index: Option<usize>,
+        sign_options: &SignOptions,
+        secp: &SecpCtx,
+    ) -> Result<(), SignerError> {
+        if !self.can_sign(psbt, secp) {
+            return Ok(());
+        }
+
+        let passphrase = self.provider.passphrase(&self.key)?;
+        let signer: Box<dyn Signer> = match self.key.decrypt(&passphrase) {
+            Ok(DescriptorSecretKey::XPrv(xprv)) => Box::new(xprv),
+            Ok(DescriptorSecretKey::SinglePriv(single)) => Box::new(single.key),
+            Err(_) => return Err(SignerError::InvalidPassphrase),
+        };
+
+        for index in 0..psbt.inputs.len() {
+            signer.sign_with_options(psbt, Some(index), sign_options, secp)?;
+        }
+
+        Ok(())
+    }
+
+    fn sign_whole_tx(&self) -> bool {
+        true
+    }
+
+    fn id(&self, secp: &SecpCtx) -> SignerId {
+        self.key.signer_id(secp)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use bitcoin::secp256k1::Secp256k1;
+
+    use super::*;
+    use crate::wallet::test::get_funded_wallet;
+    use crate::wallet::AddressIndex::New;
+
+    const XPRV: &str = "[c55b303f/84'/1'/0']tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/0/*";
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let secp = Secp256k1::new();
+        let secret_key = DescriptorSecretKey::from_str(XPRV).unwrap();
+
+        let encrypted = EncryptedDescriptorSecretKey::encrypt_with_iterations(
+            &secret_key,
+            "passphrase",
+            10,
+            &secp,
+        )
+        .unwrap();
+        assert_eq!(
+            encrypted.public_key(),
+            &secret_key.as_public(&secp).unwrap()
+        );
+        assert!(!encrypted.to_string().contains("tprv"));
+        assert_eq!(
+            encrypted.decrypt("passphrase").unwrap().to_string(),
+            secret_key.to_string()
+        );
+        assert!(matches!(
+            encrypted.decrypt("wrong passphrase"),
+            Err(EncryptionError::InvalidPassphrase)
+        ));
+        assert_eq!(
+            encrypted.signer_id(&secp),
+            SignerId::from(bitcoin::util::bip32::Fingerprint::from_hex("c55b303f").unwrap())
+        );
+
+        // Random salt and nonce
+        let other = EncryptedDescriptorSecretKey::encrypt_with_iterations(
+            &secret_key,
+            "passphrase",
+            10,
+            &secp,
+        )
+        .unwrap();
+        assert_ne!(encrypted.ciphertext, other.ciphertext);
+    }
+
+    #[test]
+    fn test_encrypted_key_roundtrip() {
+        let secp = Secp256k1::new();
+        let secret_key = DescriptorSecretKey::from_str(XPRV).unwrap();
+        let encrypted = EncryptedDescriptorSecretKey::encrypt_with_iterations(
+            &secret_key,
+            "passphrase",
+            10,
+            &secp,
+        )
+        .unwrap();
+
+        let parsed = EncryptedDescriptorSecretKey::from_str(&encrypted.to_string()).unwrap();
+        assert_eq!(parsed, encrypted);
+        assert_eq!(
+            parsed.decrypt("passphrase").unwrap().to_string(),
+            secret_key.to_string()
+        );
+
+        // The public key is authenticated
+        let other_key =
+            DescriptorSecretKey::from_str("cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW")
+                .unwrap();
+        let mut tampered = encrypted.clone();
+        tampered.public_key = other_key.as_public(&secp).unwrap();
+        assert!(matches!(
+            tampered.decrypt("passphrase"),
+            Err(EncryptionError::InvalidPassphrase)
+        ));
+
+        assert_eq!(
+            EncryptedDescriptorSecretKey::from_str("invalid"),
+            Err(EncryptionError::InvalidFormat)
+        );
+    }
+
+    #[derive(Debug, Default)]
+    struct CountingProvider(AtomicUsize);
+
+    impl KeyProvider for CountingProvider {
+        fn passphrase(&self, _key: &EncryptedDescriptorSecretKey) -> Result<String, SignerError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok("passphrase".to_string())
+        }
+    }
+
+    #[test]
+    fn test_encrypted_signer() {
+        let secp = Secp256k1::new();
+        let (wallet, _, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        let (mut psbt, _) = builder.finish().unwrap();
+
+        let secret_key = DescriptorSecretKey::from_str("tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*").unwrap();
+        let encrypted = EncryptedDescriptorSecretKey::encrypt_with_iterations(
+            &secret_key,
+            "passphrase",
+            10,
+            &secp,
+        )
+        .unwrap();
+
+        let wrong_signer =
+            EncryptedSigner::new(encrypted.clone(), Arc::new(Passphrase::new("wrong")));
+        assert_eq!(
+            wrong_signer.sign(&mut psbt, None, &secp),
+            Err(SignerError::InvalidPassphrase)
+        );
+
+        let provider = Arc::new(CountingProvider::default());
+        let signer = EncryptedSigner::new(encrypted, provider.clone());
+        match &secret_key {
+            DescriptorSecretKey::XPrv(xprv) => assert_eq!(signer.id(&secp), xprv.id(&secp)),
+            _ => unreachable!(),
+        }
+        signer.sign(&mut psbt, None, &secp).unwrap();
+        assert_eq!(psbt.inputs[0].partial_sigs.len(), 1);
+        assert_eq!(provider.0.load(Ordering::SeqCst), 1);
+
+        // Once the inputs are finalized the passphrase is not needed anymore
+        assert!(wallet.finalize_psbt(&mut psbt, Default::default()).unwrap());
+        signer.sign(&mut psbt, None, &secp).unwrap();
+        assert_eq!(provider.0.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,616 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Key import
+//!
+//! This module parses the extended public keys exported by hardware wallets and coordinators into
+//! [`DescriptorPublicKey`]s with their key origin, and assembles them into multisig descriptors
+//! with the [`MultisigDescriptorBuilder`]. The supported formats are:
+//!
+//! * Coldcard's generic and multisig JSON exports, with [`from_coldcard_json`]
+//! * Electrum wallet files, for both single-sig and multisig wallets, with [`from_electrum_wallet`]
+//! * Specter and Sparrow wallet backups, which contain the wallet descriptor, with
+//!   [`from_descriptor_backup`]
+//! * Keys with their origin, as in `[0f056943/48'/1'/0'/2']tpub...`, exported by Specter DIY and
+//!   Sparrow, with [`from_key_origin_str`]
+//!
+//! SLIP-132 encoded keys (`ypub`, `zpub`, `Vpub`, etc) are converted to `xpub`/`tpub`.
+//!
+//! ```
+//! # use bdk::bitcoin::Network;
+//! # use bdk::database::MemoryDatabase;
+//! # use bdk::keys::import::*;
+//! # use bdk::{KeychainKind, Wallet};
+//! let coldcard = r#"{
+//!     "p2wsh_deriv": "m/48'/1'/0'/2'",
+//!     "p2wsh": "tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3",
+//!     "xfp": "0F056943",
+//!     "account": "0"
+//! }"#;
+//!
+//! let builder = MultisigDescriptorBuilder::new(2)
+//!     .add_key(from_coldcard_json(coldcard, ScriptType::P2wsh)?)
+//!     .add_key(from_key_origin_str("[c55b303f/48'/1'/0'/2']tpubDDYkZojQFQjht8Tm4jsS3iuEmKjTiEGjG6KnuFNKKJb5A6ZUCUZKdvLdSDWofKi4ToRCwb9poe1XdqfUnP4jaJjCB2Zwv11ZLgSbnZSNecE")?)
+//!     .add_key(from_key_origin_str("[d34db33f/48'/1'/0'/2']tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9")?)
+//!     .script_type(ScriptType::P2wsh);
+//!
+//! let wallet = Wallet::new_offline(
+//!     builder.build(KeychainKind::External)?,
+//!     Some(builder.build(KeychainKind::Internal)?),
+//!     Network::Testnet,
+//!     MemoryDatabase::default(),
+//! )?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::util::base58;
+use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPubKey, Fingerprint};
+
+use miniscript::descriptor::{DescriptorPublicKey, DescriptorXKey, Wildcard};
+use miniscript::{Descriptor, ForEachKey, Miniscript, Segwitv0, Terminal};
+
+use serde_json::Value;
+
+use crate::descriptor::ExtendedDescriptor;
+use crate::types::KeychainKind;
+
+/// Errors thrown while importing keys
+#[derive(Debug)]
+pub enum ImportError {
+    /// The file is not valid JSON
+    Json(serde_json::Error),
+    /// A required field is missing from the file
+    MissingField(String),
+    /// The file doesn't contain a key for the requested script type
+    UnsupportedScriptType(ScriptType),
+    /// The extended public key can't be parsed
+    InvalidXpub(String),
+    /// The master key fingerprint can't be parsed
+    InvalidFingerprint(String),
+    /// The derivation path can't be parsed
+    InvalidDerivationPath(String),
+    /// The threshold of the multisig is zero or greater than the number of keys
+    InvalidThreshold(usize),
+    /// Miniscript error
+    Miniscript(miniscript::Error),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl_error!(serde_json::Error, Json, ImportError);
+impl_error!(miniscript::Error, Miniscript, ImportError);
+
+/// Script types of the keys exported by hardware wallets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScriptType {
+    /// Single-sig P2PKH (BIP44)
+    P2pkh,
+    /// Single-sig P2WPKH nested in P2SH (BIP49)
+    P2shP2wpkh,
+    /// Single-sig P2WPKH (BIP84)
+    P2wpkh,
+    /// Multisig P2SH (BIP45)
+    P2sh,
+    /// Multisig P2WSH nested in P2SH (BIP48, script type 1)
+    P2shP2wsh,
+    /// Multisig P2WSH (BIP48, script type 2)
+    P2wsh,
+}
+
+// SLIP-132 version bytes of the mainnet and testnet extended public keys
+const XPUB_VERSIONS: [[u8; 4]; 5] = [
+    [0x04, 0x88, 0xb2, 0x1e], // xpub
+    [0x04, 0x9d, 0x7c, 0xb2], // ypub
+    [0x02, 0x95, 0xb4, 0x3f], // Ypub
+    [0x04, 0xb2, 0x47, 0x46], // zpub
+    [0x02, 0xaa, 0x7e, 0xd3], // Zpub
+];
+const TPUB_VERSIONS: [[u8; 4]; 5] = [
+    [0x04, 0x35, 0x87, 0xcf], // tpub
+    [0x04, 0x4a, 0x52, 0x62], // upub
+    [0x02, 0x42, 0x89, 0xef], // Upub
+    [0x04, 0x5f, 0x1c, 0xf6], // vpub
+    [0x02, 0x57, 0x54, 0x83], // Vpub
+];
+
+/// Parse an extended public key, converting SLIP-132 encodings to `xpub`/`tpub`
+pub fn parse_xpub(s: &str) -> Result<ExtendedPubKey, ImportError> {
+    let invalid = || ImportError::InvalidXpub(s.to_string());
+
+    let mut data = base58::from_check(s).map_err(|_| invalid())?;
+    if data.len() != 78 {
+        return Err(invalid());
+    }
+
+    let version = [data[0], data[1], data[2], data[3]];
+    if XPUB_VERSIONS.contains(&version) {
+        data[..4].copy_from_slice(&XPUB_VERSIONS[0]);
+    } else if TPUB_VERSIONS.contains(&version) {
+        data[..4].copy_from_slice(&TPUB_VERSIONS[0]);
+    } else {
+        return Err(invalid());
+    }
+
+    ExtendedPubKey::from_str(&base58::check_encode_slice(&data)).map_err(|_| invalid())
+}
+
+fn parse_fingerprint(s: &str) -> Result<Fingerprint, ImportError> {
+    let invalid = || ImportError::InvalidFingerprint(s.to_string());
+
+    // Some exports store the fingerprint as an integer or without the leading zeros
+    let s = s.trim_start_matches("0x");
+    if s.is_empty() || s.len() > 8 {
+        return Err(invalid());
+    }
+    let value = u32::from_str_radix(s, 16).map_err(|_| invalid())?;
+
+    Ok(Fingerprint::from(&value.to_be_bytes()[..]))
+}
+
+fn parse_derivation_path(s: &str) -> Result<DerivationPath, ImportError> {
+    // Accept both `h` and `'` for hardened steps, and paths without the leading `m/`
+    let normalized = s.trim().replace('h', "'");
+    let normalized = if normalized.starts_with('m') {
+        normalized
+    } else {
+        format!("m/{}", normalized.trim_start_matches('/'))
+    };
+
+    DerivationPath::from_str(&normalized)
+        .map_err(|_| ImportError::InvalidDerivationPath(s.to_string()))
+}
+
+fn make_key(
+    xpub: ExtendedPubKey,
+    origin: Option<(Fingerprint, DerivationPath)>,
+) -> DescriptorPublicKey {
+    DescriptorPublicKey::XPub(DescriptorXKey {
+        origin,
+        xkey: xpub,
+        derivation_path: DerivationPath::default(),
+        wildcard: Wildcard::None,
+    })
+}
+
+fn get_str<'v>(value: &'v Value, field: &str) -> Result<&'v str, ImportError> {
+    match value.get(field) {
+        Some(Value::String(s)) => Ok(s),
+        _ => Err(ImportError::MissingField(field.to_string())),
+    }
+}
+
+/// Parse a key with its origin, as in `[0f056943/48'/1'/0'/2']tpub...`
+///
+/// The origin is optional, and the key can be SLIP-132 encoded. Derivation steps after the key
+/// are not allowed.
+pub fn from_key_origin_str(s: &str) -> Result<DescriptorPublicKey, ImportError> {
+    let s = s.trim();
+    if !s.starts_with('[') {
+        return Ok(make_key(parse_xpub(s)?, None));
+    }
+
+    let end = s
+        .find(']')
+        .ok_or_else(|| ImportError::InvalidDerivationPath(s.to_string()))?;
+    let mut origin = s[1..end].splitn(2, '/');
+    let fingerprint = parse_fingerprint(origin.next().unwrap_or_default())?;
+    let path = match origin.next() {
+        Some(path) => parse_derivation_path(path)?,
+        None => DerivationPath::default(),
+    };
+
+    Ok(make_key(
+        parse_xpub(&s[end + 1..])?,
+        Some((fingerprint, path)),
+    ))
+}
+
+/// Parse the key for `script_type` from a Coldcard JSON export
+///
+/// Both the generic export (`coldcard-export.json`) and the multisig export (`ccxp-*.json`)
+/// are supported. Single-sig script types are only available in the generic export.
+pub fn from_coldcard_json(
+    json: &str,
+    script_type: ScriptType,
+) -> Result<DescriptorPublicKey, ImportError> {
+    let value: Value = serde_json::from_str(json)?;
+    let fingerprint = parse_fingerprint(get_str(&value, "xfp")?)?;
+
+    let (section, multisig_field) = match script_type {
+        ScriptType::P2pkh => ("bip44", None),
+        ScriptType::P2shP2wpkh => ("bip49", None),
+        ScriptType::P2wpkh => ("bip84", None),
+        ScriptType::P2sh => ("bip45", Some("p2sh")),
+        ScriptType::P2shP2wsh => ("bip48_1", Some("p2sh_p2wsh")),
+        ScriptType::P2wsh => ("bip48_2", Some("p2wsh")),
+    };
+
+    let (xpub, path) = match (value.get(section), multisig_field) {
+        (Some(section), _) => (get_str(section, "xpub")?, get_str(section, "deriv")?),
+        (None, Some(field)) if value.get(field).is_some() => (
+            get_str(&value, field)?,
+            get_str(&value, &format!("{}_deriv", field))?,
+        ),
+        _ => return Err(ImportError::UnsupportedScriptType(script_type)),
+    };
+
+    Ok(make_key(
+        parse_xpub(xpub)?,
+        Some((fingerprint, parse_derivation_path(path)?)),
+    ))
+}
+
+fn from_electrum_keystore(keystore: &Value) -> Result<DescriptorPublicKey, ImportError> {
+    let xpub = parse_xpub(get_str(keystore, "xpub")?)?;
+
+    // Old keystores don't store the origin of the key
+    let origin = match (
+        get_str(keystore, "root_fingerprint"),
+        get_str(keystore, "derivation"),
+    ) {
+        (Ok(fingerprint), Ok(path)) => Some((
+            parse_fingerprint(fingerprint)?,
+            parse_derivation_path(path)?,
+        )),
+        _ => None,
+    };
+
+    Ok(make_key(xpub, origin))
+}
+
+/// Parse the keys from an unencrypted Electrum wallet file
+///
+/// Standard wallets contain a single key, multisig wallets contain one key for every cosigner,
+/// in the same order used by Electrum.
+pub fn from_electrum_wallet(json: &str) -> Result<Vec<DescriptorPublicKey>, ImportError> {
+    let value: Value = serde_json::from_str(json)?;
+
+    if let Some(keystore) = value.get("keystore") {
+        return Ok(vec![from_electrum_keystore(keystore)?]);
+    }
+
+    // Multisig keystores are stored as `x1/`, `x2/`, etc
+    let keystores = value
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(name, keystore)| {
+            name.strip_prefix('x')
+                .and_then(|name| name.strip_suffix('/'))
+                .and_then(|index| index.parse::<usize>().ok())
+                .map(|index| (index, keystore))
+        })
+        .collect::<BTreeMap<_, _>>();
+    if keystores.is_empty() {
+        return Err(ImportError::MissingField("keystore".to_string()));
+    }
+
+    keystores
+        .values()
+        .map(|k| from_electrum_keystore(k))
+        .collect()
+}
+
+/// Parse the keys from a Specter or Sparrow wallet backup, which contains the wallet descriptor
+///
+/// The derivation steps after the keys are removed, so that they can be assembled again with the
+/// [`MultisigDescriptorBuilder`].
+pub fn from_descriptor_backup(json: &str) -> Result<Vec<DescriptorPublicKey>, ImportError> {
+    let value: Value = serde_json::from_str(json)?;
+    let descriptor = get_str(&value, "descriptor")?;
+    let descriptor = descriptor.splitn(2, '#').next().unwrap_or_default();
+    let descriptor = ExtendedDescriptor::from_str(descriptor)?;
+
+    let mut keys = Vec::new();
+    descriptor.for_each_key(|key| {
+        let key = match key.as_key().clone() {
+            DescriptorPublicKey::XPub(xpub) => make_key(xpub.xkey, xpub.origin),
+            single => single,
+        };
+        keys.push(key);
+
+        true
+    });
+
+    Ok(keys)
+}
+
+/// Builder of multisig descriptors from imported keys
+///
+/// For a usage example see [this module](crate::keys::import)'s documentation.
+#[derive(Debug, Clone)]
+pub struct MultisigDescriptorBuilder {
+    threshold: usize,
+    keys: Vec<DescriptorPublicKey>,
+    script_type: ScriptType,
+    sorted: bool,
+}
+
+impl MultisigDescriptorBuilder {
+    /// Create a new builder for a multisig that requires `threshold` signatures
+    ///
+    /// By default the descriptor is a P2WSH `sortedmulti()`.
+    pub fn new(threshold: usize) -> Self {
+        MultisigDescriptorBuilder {
+            threshold,
+            keys: Vec::new(),
+            script_type: ScriptType::P2wsh,
+            sorted: true,
+        }
+    }
+
+    /// Add a key to the multisig
+    pub fn add_key(mut self, key: DescriptorPublicKey) -> Self {
+        self.keys.push(key);
+        self
+    }
+
+    /// Add multiple keys to the multisig
+    pub fn add_keys<I: IntoIterator<Item = DescriptorPublicKey>>(mut self, keys: I) -> Self {
+        self.keys.extend(keys);
+        self
+    }
+
+    /// Set the script type, which must be one of the multisig types
+    pub fn script_type(mut self, script_type: ScriptType) -> Self {
+        self.script_type = script_type;
+        self
+    }
+
+    /// Use `multi()` instead of `sortedmulti()`, keeping the keys in the order they were added
+    pub fn unsorted(mut self) -> Self {
+        self.sorted = false;
+        self
+    }
+
+    /// Build the descriptor for `keychain`
+    ///
+    /// Every extended key is derived at `/0/*` for the external keychain and `/1/*` for the
+    /// internal one.
+    pub fn build(&self, keychain: KeychainKind) -> Result<ExtendedDescriptor, ImportError> {
+        if self.threshold == 0 || self.threshold > self.keys.len() {
+            return Err(ImportError::InvalidThreshold(self.threshold));
+        }
+
+        let keychain_step = ChildNumber::from_normal_idx(match keychain {
+            KeychainKind::External => 0,
+            KeychainKind::Internal => 1,
+        })
+        .expect("Valid index");
+        let keys = self
+            .keys
+            .iter()
+            .cloned()
+            .map(|key| match key {
+                DescriptorPublicKey::XPub(mut xpub) => {
+                    xpub.derivation_path = xpub.derivation_path.child(keychain_step);
+                    xpub.wildcard = Wildcard::Unhardened;
+                    DescriptorPublicKey::XPub(xpub)
+                }
+                single => single,
+            })
+            .collect::<Vec<_>>();
+
+        let descriptor = match (self.script_type, self.sorted) {
+            (ScriptType::P2sh, true) => Descriptor::new_sh_sortedmulti(self.threshold, keys)?,
+            (ScriptType::P2shP2wsh, true) => {
+                Descriptor::new_sh_wsh_sortedmulti(self.threshold, keys)?
+            }
+            (ScriptType::P2wsh, true) => Descriptor::new_wsh_sortedmulti(self.threshold, keys)?,
+            (ScriptType::P2sh, false) => {
+                Descriptor::new_sh(Miniscript::from_ast(Terminal::Multi(self.threshold, keys))?)?
+            }
+            (ScriptType::P2shP2wsh, false) => Descriptor::new_sh_wsh(
+                Miniscript::<_, Segwitv0>::from_ast(Terminal::Multi(self.threshold, keys))?,
+            )?,
+            (ScriptType::P2wsh, false) => Descriptor::new_wsh(
+                Miniscript::<_, Segwitv0>::from_ast(Terminal::Multi(self.threshold, keys))?,
+            )?,
+            (script_type, _) => return Err(ImportError::UnsupportedScriptType(script_type)),
+        };
+
+        Ok(descriptor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TPUB: &str = "tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3";
+
+    fn slip132(xpub: &str, version: [u8; 4]) -> String {
+        let mut data = base58::from_check(xpub).unwrap();
+        data[..4].copy_from_slice(&version);
+        base58::check_encode_slice(&data)
+    }
+
+    #[test]
+    fn test_parse_slip132() {
+        let tpub = ExtendedPubKey::from_str(TPUB).unwrap();
+        for version in TPUB_VERSIONS.iter() {
+            let encoded = slip132(TPUB, *version);
+            assert_eq!(parse_xpub(&encoded).unwrap(), tpub);
+        }
+
+        let xpub = slip132(TPUB, XPUB_VERSIONS[0]);
+        let zpub = slip132(TPUB, XPUB_VERSIONS[3]);
+        assert_eq!(parse_xpub(&zpub).unwrap().to_string(), xpub);
+
+        assert!(matches!(
+            parse_xpub(&slip132(TPUB, [0, 0, 0, 0])),
+            Err(ImportError::InvalidXpub(_))
+        ));
+    }
+
+    #[test]
+    fn test_key_origin_str() {
+        let key = from_key_origin_str(&format!("[0f056943/48h/1h/0h/2h]{}", TPUB)).unwrap();
+        assert_eq!(key.to_string(), format!("[0f056943/48'/1'/0'/2']{}", TPUB));
+
+        let vpub = slip132(TPUB, TPUB_VERSIONS[4]);
+        let key = from_key_origin_str(&format!("[0F056943/48'/1'/0'/2']{}", vpub)).unwrap();
+        assert_eq!(key.to_string(), format!("[0f056943/48'/1'/0'/2']{}", TPUB));
+
+        assert_eq!(from_key_origin_str(TPUB).unwrap().to_string(), TPUB);
+        assert!(matches!(
+            from_key_origin_str(&format!("[zzzzzzzz/48'/1'/0'/2']{}", TPUB)),
+            Err(ImportError::InvalidFingerprint(_))
+        ));
+    }
+
+    #[test]
+    fn test_coldcard_json() {
+        let generic = format!(
+            r#"{{
+                "chain": "XTN",
+                "xfp": "0F056943",
+                "account": 0,
+                "xpub": "{tpub}",
+                "bip84": {{"name": "p2wpkh", "deriv": "m/84'/1'/0'", "xpub": "{tpub}", "_pub": "{vpub}"}},
+                "bip48_2": {{"name": "p2wsh", "deriv": "m/48'/1'/0'/2'", "xpub": "{tpub}", "_pub": "{vpub}"}}
+            }}"#,
+            tpub = TPUB,
+            vpub = slip132(TPUB, TPUB_VERSIONS[3])
+        );
+        assert_eq!(
+            from_coldcard_json(&generic, ScriptType::P2wpkh)
+                .unwrap()
+                .to_string(),
+            format!("[0f056943/84'/1'/0']{}", TPUB)
+        );
+        assert_eq!(
+            from_coldcard_json(&generic, ScriptType::P2wsh)
+                .unwrap()
+                .to_string(),
+            format!("[0f056943/48'/1'/0'/2']{}", TPUB)
+        );
+        assert!(matches!(
+            from_coldcard_json(&generic, ScriptType::P2pkh),
+            Err(ImportError::UnsupportedScriptType(ScriptType::P2pkh))
+        ));
+
+        let multisig = format!(
+            r#"{{"p2sh_p2wsh_deriv": "m/48'/1'/0'/1'", "p2sh_p2wsh": "{}", "xfp": "0F056943", "account": "0"}}"#,
+            slip132(TPUB, TPUB_VERSIONS[2])
+        );
+        assert_eq!(
+            from_coldcard_json(&multisig, ScriptType::P2shP2wsh)
+                .unwrap()
+                .to_string(),
+            format!("[0f056943/48'/1'/0'/1']{}", TPUB)
+        );
+        assert!(matches!(
+            from_coldcard_json(&multisig, ScriptType::P2wsh),
+            Err(ImportError::UnsupportedScriptType(ScriptType::P2wsh))
+        ));
+    }
+
+    #[test]
+    fn test_electrum_wallet() {
+        let vpub = slip132(TPUB, TPUB_VERSIONS[3]);
+        let standard = format!(
+            r#"{{"keystore": {{"type": "hardware", "hw_type": "coldcard", "xpub": "{}", "derivation": "m/84h/1h/0h", "root_fingerprint": "0f056943"}}, "wallet_type": "standard"}}"#,
+            vpub
+        );
+        let keys = from_electrum_wallet(&standard).unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].to_string(), format!("[0f056943/84'/1'/0']{}", TPUB));
+
+        let multisig = format!(
+            r#"{{
+                "x2/": {{"type": "bip32", "xpub": "{tpub}"}},
+                "x1/": {{"type": "hardware", "xpub": "{tpub}", "derivation": "m/48'/1'/0'/2'", "root_fingerprint": "0f056943"}},
+                "wallet_type": "2of2"
+            }}"#,
+            tpub = TPUB
+        );
+        let keys = from_electrum_wallet(&multisig).unwrap();
+        assert_eq!(
+            keys.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![format!("[0f056943/48'/1'/0'/2']{}", TPUB), TPUB.to_string()]
+        );
+
+        assert!(matches!(
+            from_electrum_wallet(r#"{"wallet_type": "standard"}"#),
+            Err(ImportError::MissingField(_))
+        ));
+    }
+
+    #[test]
+    fn test_descriptor_backup_and_builder() {
+        let keys = vec![
+            format!("[0f056943/48'/1'/0'/2']{}", TPUB),
+            "[c55b303f/48'/1'/0'/2']tpubDDYkZojQFQjht8Tm4jsS3iuEmKjTiEGjG6KnuFNKKJb5A6ZUCUZKdvLdSDWofKi4ToRCwb9poe1XdqfUnP4jaJjCB2Zwv11ZLgSbnZSNecE".to_string(),
+        ];
+        let builder = MultisigDescriptorBuilder::new(2)
+            .add_keys(keys.iter().map(|key| from_key_origin_str(key).unwrap()));
+
+        let external = builder.build(KeychainKind::External).unwrap();
+        let internal = builder.build(KeychainKind::Internal).unwrap();
+        assert_eq!(
+            external.to_string().splitn(2, '#').next().unwrap(),
+            format!("wsh(sortedmulti(2,{}/0/*,{}/0/*))", keys[0], keys[1])
+        );
+        assert_eq!(
+            internal.to_string().splitn(2, '#').next().unwrap(),
+            format!("wsh(sortedmulti(2,{}/1/*,{}/1/*))", keys[0], keys[1])
+        );
+
+        // A Specter backup of the same wallet gives back the same keys
+        let backup = format!(
+            r#"{{"label": "Multisig", "blockheight": 0, "descriptor": "{}"}}"#,
+            external
+        );
+        let imported = from_descriptor_backup(&backup).unwrap();
+        assert_eq!(
+            imported.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            keys
+        );
+
+        let unsorted = builder
+            .clone()
+            .unsorted()
+            .script_type(ScriptType::P2shP2wsh);
+        assert_eq!(
+            unsorted
+                .build(KeychainKind::External)
+                .unwrap()
+                .to_string()
+                .splitn(2, '#')
+                .next()
+                .unwrap(),
+            format!("sh(wsh(multi(2,{}/0/*,{}/0/*)))", keys[0], keys[1])
+        );
+
+        assert!(matches!(
+            MultisigDescriptorBuilder::new(3)
+                .add_keys(imported)
+                .build(KeychainKind::External),
+            Err(ImportError::InvalidThreshold(3))
+        ));
+        assert!(matches!(
+            builder
+                .script_type(ScriptType::P2wpkh)
+                .build(KeychainKind::External),
+            Err(ImportError::UnsupportedScriptType(ScriptType::P2wpkh))
+        ));
+    }
+}
@@ -36,6 +36,17 @@ use crate::wallet::utils::SecpCtx;
 #[cfg(feature = "keys-bip39")]
 #[cfg_attr(docsrs, doc(cfg(feature = "keys-bip39")))]
 pub mod bip39;
+pub mod bip85;
+#[cfg(feature = "encrypted-keys")]
+#[cfg_attr(docsrs, doc(cfg(feature = "encrypted-keys")))]
+pub mod encrypted;
+pub mod import;
+#[cfg(feature = "keys-slip39")]
+#[cfg_attr(docsrs, doc(cfg(feature = "keys-slip39")))]
+pub mod slip39;
+#[cfg(feature = "test-utils")]
+#[cfg_attr(docsrs, doc(cfg(feature = "test-utils")))]
+pub mod test_utils;
 
 /// Set of valid networks for a key
 pub type ValidNetworks = HashSet<Network>;
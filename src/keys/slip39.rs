@@ -0,0 +1,997 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! SLIP-0039
+//!
+//! Shamir's secret sharing of the master secret of a wallet, as specified in [SLIP-39]. The
+//! [`MasterSecret`] is split into groups of mnemonic [`Share`]s: recovering the secret requires
+//! a threshold of shares from a threshold of groups, which can be used to build social or sharded
+//! backups.
+//!
+//! The master secret is used directly as the BIP-32 seed, so it can be turned into descriptor
+//! keys like a BIP-39 mnemonic.
+//!
+//! ```
+//! # use bdk::keys::slip39::{GroupSpec, MasterSecret, Share};
+//! # use bdk::keys::{GeneratableKey, GeneratedKey};
+//! # use bdk::template::Bip84;
+//! # use bdk::miniscript::Segwitv0;
+//! # use bdk::KeychainKind;
+//! let secret: GeneratedKey<MasterSecret, Segwitv0> = MasterSecret::generate(Default::default())?;
+//!
+//! // Two of three shares are required to recover the secret
+//! let groups = secret.split("passphrase", 1, &[GroupSpec::new(2, 3)], 0)?;
+//! let mnemonics = groups[0]
+//!     .iter()
+//!     .map(|share| share.to_string())
+//!     .collect::<Vec<_>>();
+//!
+//! let shares: Vec<Share> = vec![mnemonics[0].parse()?, mnemonics[2].parse()?];
+//! let recovered = MasterSecret::combine(&shares, "passphrase")?;
+//! assert_eq!(recovered, *secret);
+//!
+//! let descriptor = Bip84(recovered, KeychainKind::External);
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [SLIP-39]: https://github.com/satoshilabs/slips/blob/master/slip-0039.md
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::util::bip32;
+use bitcoin::Network;
+
+use miniscript::ScriptContext;
+
+use rand::{thread_rng, CryptoRng, RngCore};
+
+use super::{
+    any_network, DerivableKey, DescriptorKey, ExtendedKey, GeneratableKey, GeneratedKey, KeyError,
+};
+
+const CUSTOMIZATION_STRING: &[u8] = b"shamir";
+const CUSTOMIZATION_STRING_EXTENDABLE: &[u8] = b"shamir_extendable";
+
+const RADIX_BITS: usize = 10;
+const CHECKSUM_WORDS: usize = 3;
+// Identifier, extendable flag and iteration exponent, then the group and member parameters
+const METADATA_WORDS: usize = 4 + CHECKSUM_WORDS;
+const MIN_SECRET_LEN: usize = 16;
+const MAX_SECRET_LEN: usize = 32;
+const MAX_SHARE_COUNT: u8 = 16;
+const DIGEST_LEN: usize = 4;
+const DIGEST_INDEX: u8 = 254;
+const SECRET_INDEX: u8 = 255;
+const BASE_ITERATION_COUNT: u32 = 10000;
+const ROUND_COUNT: u8 = 4;
+
+/// Errors related to SLIP-39 shares
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Slip39Error {
+    /// The mnemonic has an invalid number of words
+    BadWordCount(usize),
+    /// The word isn't in the wordlist
+    UnknownWord(String),
+    /// The checksum of the mnemonic is wrong
+    InvalidChecksum,
+    /// The padding bits of the share value are not zero
+    InvalidPadding,
+    /// The master secret must be between 16 and 32 bytes long, with an even length
+    InvalidSecretLength(usize),
+    /// The iteration exponent must fit in 4 bits
+    InvalidIterationExponent(u8),
+    /// The threshold is zero or higher than the number of shares, or there are more than 16
+    /// shares
+    InvalidThreshold {
+        /// Required number of shares
+        threshold: u8,
+        /// Total number of shares
+        count: u8,
+    },
+    /// The shares don't belong to the same set
+    MismatchedShares,
+    /// Two different shares have the same index
+    DuplicateShare,
+    /// Not enough shares or groups have been provided
+    InsufficientShares,
+    /// The recovered secret doesn't match its digest
+    InvalidDigest,
+}
+
+impl fmt::Display for Slip39Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for Slip39Error {}
+
+fn word_list() -> Vec<&'static str> {
+    include_str!("slip39/wordlist.txt").lines().collect()
+}
+
+fn find_word(word: &str) -> Option<u16> {
+    include_str!("slip39/wordlist.txt")
+        .lines()
+        .position(|w| w == word)
+        .map(|index| index as u16)
+}
+
+fn rs1024_polymod(values: impl IntoIterator<Item = u32>) -> u32 {
+    const GEN: [u32; 10] = [
+        0x00e0_e040,
+        0x01c1_c080,
+        0x0383_8100,
+        0x0707_0200,
+        0x0e0e_0009,
+        0x1c0c_2412,
+        0x3808_6c24,
+        0x3090_fc48,
+        0x21b1_f890,
+        0x03f3_f120,
+    ];
+
+    values.into_iter().fold(1, |chk, value| {
+        let b = chk >> 20;
+        let chk = ((chk & 0xf_ffff) << 10) ^ value;
+        GEN.iter()
+            .enumerate()
+            .filter(|(i, _)| (b >> i) & 1 == 1)
+            .fold(chk, |chk, (_, gen)| chk ^ gen)
+    })
+}
+
+fn customization_string(extendable: bool) -> &'static [u8] {
+    if extendable {
+        CUSTOMIZATION_STRING_EXTENDABLE
+    } else {
+        CUSTOMIZATION_STRING
+    }
+}
+
+fn rs1024_create_checksum(extendable: bool, data: &[u16]) -> [u16; CHECKSUM_WORDS] {
+    let values = customization_string(extendable)
+        .iter()
+        .map(|b| *b as u32)
+        .chain(data.iter().map(|w| *w as u32))
+        .chain(std::iter::repeat(0).take(CHECKSUM_WORDS));
+    let polymod = rs1024_polymod(values) ^ 1;
+
+    let mut checksum = [0; CHECKSUM_WORDS];
+    for (i, word) in checksum.iter_mut().enumerate() {
+        *word = ((polymod >> (RADIX_BITS * (CHECKSUM_WORDS - 1 - i))) & 0x3ff) as u16;
+    }
+    checksum
+}
+
+fn rs1024_verify_checksum(extendable: bool, data: &[u16]) -> bool {
+    let values = customization_string(extendable)
+        .iter()
+        .map(|b| *b as u32)
+        .chain(data.iter().map(|w| *w as u32));
+    rs1024_polymod(values) == 1
+}
+
+// Exponent and logarithm tables of GF(256) with the Rijndael polynomial x^8 + x^4 + x^3 + x + 1
+fn gf256_tables() -> ([u8; 255], [u8; 256]) {
+    let mut exp = [0u8; 255];
+    let mut log = [0u8; 256];
+
+    let mut poly: u16 = 1;
+    for (i, e) in exp.iter_mut().enumerate() {
+        *e = poly as u8;
+        log[poly as usize] = i as u8;
+
+        // Multiply by x + 1, a primitive element
+        poly = (poly << 1) ^ poly;
+        if poly & 0x100 != 0 {
+            poly ^= 0x11b;
+        }
+    }
+
+    (exp, log)
+}
+
+// Evaluate at `x` the polynomial of lowest degree going through the `shares` points
+fn interpolate(shares: &[(u8, Vec<u8>)], x: u8) -> Vec<u8> {
+    if let Some((_, value)) = shares.iter().find(|(index, _)| *index == x) {
+        return value.clone();
+    }
+
+    let (exp, log) = gf256_tables();
+    let log_prod: i64 = shares
+        .iter()
+        .map(|(index, _)| log[(index ^ x) as usize] as i64)
+        .sum();
+
+    let mut result = vec![0u8; shares[0].1.len()];
+    for (index, value) in shares {
+        let log_basis = (log_prod
+            - log[(index ^ x) as usize] as i64
+            - shares
+                .iter()
+                .map(|(other, _)| log[(index ^ other) as usize] as i64)
+                .sum::<i64>())
+        .rem_euclid(255);
+
+        for (r, v) in result.iter_mut().zip(value.iter()) {
+            if *v != 0 {
+                *r ^= exp[((log[*v as usize] as i64 + log_basis) % 255) as usize];
+            }
+        }
+    }
+
+    result
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut engine = HmacEngine::<sha256::Hash>::new(key);
+    engine.input(data);
+    Hmac::from_engine(engine).into_inner()
+}
+
+fn create_digest(random_data: &[u8], secret: &[u8]) -> [u8; DIGEST_LEN] {
+    let mut digest = [0u8; DIGEST_LEN];
+    digest.copy_from_slice(&hmac_sha256(random_data, secret)[..DIGEST_LEN]);
+    digest
+}
+
+fn split_secret<R: RngCore + CryptoRng>(
+    threshold: u8,
+    count: u8,
+    secret: &[u8],
+    rng: &mut R,
+) -> Result<Vec<(u8, Vec<u8>)>, Slip39Error> {
+    if threshold == 0 || threshold > count || count > MAX_SHARE_COUNT {
+        return Err(Slip39Error::InvalidThreshold { threshold, count });
+    }
+
+    if threshold == 1 {
+        return Ok((0..count).map(|index| (index, secret.to_vec())).collect());
+    }
+
+    let mut shares = (0..threshold - 2)
+        .map(|index| {
+            let mut value = vec![0u8; secret.len()];
+            rng.fill_bytes(&mut value);
+            (index, value)
+        })
+        .collect::<Vec<_>>();
+
+    let mut random_part = vec![0u8; secret.len() - DIGEST_LEN];
+    rng.fill_bytes(&mut random_part);
+    let mut digest = create_digest(&random_part, secret).to_vec();
+    digest.extend(random_part);
+
+    let mut base_shares = shares.clone();
+    base_shares.push((DIGEST_INDEX, digest));
+    base_shares.push((SECRET_INDEX, secret.to_vec()));
+
+    for index in threshold - 2..count {
+        shares.push((index, interpolate(&base_shares, index)));
+    }
+
+    Ok(shares)
+}
+
+fn recover_secret(threshold: u8, shares: &[(u8, Vec<u8>)]) -> Result<Vec<u8>, Slip39Error> {
+    if threshold == 1 {
+        return Ok(shares[0].1.clone());
+    }
+
+    let secret = interpolate(shares, SECRET_INDEX);
+    let digest = interpolate(shares, DIGEST_INDEX);
+    if digest[..DIGEST_LEN] != create_digest(&digest[DIGEST_LEN..], &secret) {
+        return Err(Slip39Error::InvalidDigest);
+    }
+
+    Ok(secret)
+}
+
+// Four rounds Feistel network, keyed with the passphrase
+fn feistel(
+    data: &[u8],
+    passphrase: &str,
+    iteration_exponent: u8,
+    identifier: u16,
+    extendable: bool,
+    encrypt: bool,
+) -> Vec<u8> {
+    let (left, right) = data.split_at(data.len() / 2);
+    let (mut left, mut right) = (left.to_vec(), right.to_vec());
+
+    let mut salt = Vec::new();
+    if !extendable {
+        salt.extend_from_slice(CUSTOMIZATION_STRING);
+        salt.extend_from_slice(&identifier.to_be_bytes());
+    }
+    let iterations = (BASE_ITERATION_COUNT << iteration_exponent) / ROUND_COUNT as u32;
+
+    for round in 0..ROUND_COUNT {
+        let round = if encrypt {
+            round
+        } else {
+            ROUND_COUNT - 1 - round
+        };
+
+        let mut password = vec![round];
+        password.extend_from_slice(passphrase.as_bytes());
+        let mut round_salt = salt.clone();
+        round_salt.extend_from_slice(&right);
+
+        // PBKDF2-HMAC-SHA256, the halves are never longer than one block
+        let prepared = HmacEngine::<sha256::Hash>::new(&password);
+        let mut engine = prepared.clone();
+        engine.input(&round_salt);
+        engine.input(&1u32.to_be_bytes());
+        let mut block = Hmac::from_engine(engine).into_inner();
+        let mut key = block;
+        for _ in 1..iterations {
+            let mut engine = prepared.clone();
+            engine.input(&block);
+            block = Hmac::from_engine(engine).into_inner();
+            for (k, b) in key.iter_mut().zip(block.iter()) {
+                *k ^= b;
+            }
+        }
+
+        let next = left
+            .iter()
+            .zip(key.iter())
+            .map(|(l, k)| l ^ k)
+            .collect::<Vec<_>>();
+        left = std::mem::replace(&mut right, next);
+    }
+
+    right.extend(left);
+    right
+}
+
+/// Threshold and number of shares of a group
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GroupSpec {
+    /// Number of shares required to recover the group secret
+    pub threshold: u8,
+    /// Total number of shares in the group
+    pub count: u8,
+}
+
+impl GroupSpec {
+    /// Create a new `threshold`-of-`count` group
+    pub fn new(threshold: u8, count: u8) -> Self {
+        GroupSpec { threshold, count }
+    }
+}
+
+/// A SLIP-39 share, encoded as a mnemonic of at least 20 words
+#[derive(Clone, PartialEq, Eq)]
+pub struct Share {
+    identifier: u16,
+    extendable: bool,
+    iteration_exponent: u8,
+    group_index: u8,
+    group_threshold: u8,
+    group_count: u8,
+    member_index: u8,
+    member_threshold: u8,
+    value: Vec<u8>,
+}
+
+impl Share {
+    /// Parse a share from its mnemonic, verifying its checksum
+    pub fn from_mnemonic(mnemonic: &str) -> Result<Self, Slip39Error> {
+        let mnemonic = mnemonic.to_lowercase();
+        let words = mnemonic
+            .split_whitespace()
+            .map(|word| find_word(word).ok_or_else(|| Slip39Error::UnknownWord(word.to_string())))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let value_words = words
+            .len()
+            .checked_sub(METADATA_WORDS)
+            .ok_or(Slip39Error::BadWordCount(words.len()))?;
+        let padding_bits = (RADIX_BITS * value_words) % 16;
+        if value_words * RADIX_BITS < MIN_SECRET_LEN * 8 || padding_bits > 8 {
+            return Err(Slip39Error::BadWordCount(words.len()));
+        }
+
+        let extendable = (words[1] >> 4) & 1 == 1;
+        if !rs1024_verify_checksum(extendable, &words) {
+            return Err(Slip39Error::InvalidChecksum);
+        }
+
+        let group_threshold = ((words[2] >> 2) & 0xf) as u8 + 1;
+        let group_count = (((words[2] & 0x3) << 2) | (words[3] >> 8)) as u8 + 1;
+        if group_threshold > group_count {
+            return Err(Slip39Error::InvalidThreshold {
+                threshold: group_threshold,
+                count: group_count,
+            });
+        }
+
+        let mut bits = Vec::with_capacity(value_words * RADIX_BITS);
+        for word in &words[4..4 + value_words] {
+            bits.extend((0..RADIX_BITS).rev().map(|i| (word >> i) & 1 == 1));
+        }
+        if bits[..padding_bits].iter().any(|bit| *bit) {
+            return Err(Slip39Error::InvalidPadding);
+        }
+        let value = bits[padding_bits..]
+            .chunks(8)
+            .map(|byte| byte.iter().fold(0u8, |acc, bit| (acc << 1) | *bit as u8))
+            .collect();
+
+        Ok(Share {
+            identifier: (words[0] << 5) | (words[1] >> 5),
+            extendable,
+            iteration_exponent: (words[1] & 0xf) as u8,
+            group_index: (words[2] >> 6) as u8,
+            group_threshold,
+            group_count,
+            member_index: ((words[3] >> 4) & 0xf) as u8,
+            member_threshold: (words[3] & 0xf) as u8 + 1,
+            value,
+        })
+    }
+
+    /// Encode the share as a mnemonic
+    pub fn to_mnemonic(&self) -> String {
+        let mut words = vec![
+            self.identifier >> 5,
+            ((self.identifier & 0x1f) << 5)
+                | ((self.extendable as u16) << 4)
+                | self.iteration_exponent as u16,
+            ((self.group_index as u16) << 6)
+                | ((self.group_threshold as u16 - 1) << 2)
+                | ((self.group_count as u16 - 1) >> 2),
+            (((self.group_count as u16 - 1) & 0x3) << 8)
+                | ((self.member_index as u16) << 4)
+                | (self.member_threshold as u16 - 1),
+        ];
+
+        let value_words = (self.value.len() * 8 + RADIX_BITS - 1) / RADIX_BITS;
+        let padding_bits = value_words * RADIX_BITS - self.value.len() * 8;
+        let bits = std::iter::repeat(false).take(padding_bits).chain(
+            self.value
+                .iter()
+                .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1 == 1)),
+        );
+        let bits = bits.collect::<Vec<_>>();
+        words.extend(
+            bits.chunks(RADIX_BITS)
+                .map(|word| word.iter().fold(0u16, |acc, bit| (acc << 1) | *bit as u16)),
+        );
+
+        let checksum = rs1024_create_checksum(self.extendable, &words);
+        words.extend_from_slice(&checksum);
+
+        let word_list = word_list();
+        words
+            .iter()
+            .map(|word| word_list[*word as usize])
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Return the random identifier shared by all the shares of a set
+    pub fn identifier(&self) -> u16 {
+        self.identifier
+    }
+
+    /// Return whether the set can be extended with more groups or shares
+    pub fn is_extendable(&self) -> bool {
+        self.extendable
+    }
+
+    /// Return the exponent of the number of PBKDF2 iterations used to encrypt the secret
+    pub fn iteration_exponent(&self) -> u8 {
+        self.iteration_exponent
+    }
+
+    /// Return the index of the group of this share
+    pub fn group_index(&self) -> u8 {
+        self.group_index
+    }
+
+    /// Return the number of groups required to recover the secret
+    pub fn group_threshold(&self) -> u8 {
+        self.group_threshold
+    }
+
+    /// Return the total number of groups
+    pub fn group_count(&self) -> u8 {
+        self.group_count
+    }
+
+    /// Return the index of this share in its group
+    pub fn member_index(&self) -> u8 {
+        self.member_index
+    }
+
+    /// Return the number of shares required to recover the secret of the group
+    pub fn member_threshold(&self) -> u8 {
+        self.member_threshold
+    }
+}
+
+impl fmt::Display for Share {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_mnemonic())
+    }
+}
+
+// Don't show the share value in the logs
+impl fmt::Debug for Share {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Share")
+            .field("identifier", &self.identifier)
+            .field("group_index", &self.group_index)
+            .field("member_index", &self.member_index)
+            .finish()
+    }
+}
+
+impl FromStr for Share {
+    type Err = Slip39Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Share::from_mnemonic(s)
+    }
+}
+
+/// Length of a generated [`MasterSecret`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecretLength {
+    /// 128 bits, encoded in shares of 20 words
+    Bits128,
+    /// 256 bits, encoded in shares of 33 words
+    Bits256,
+}
+
+impl Default for SecretLength {
+    fn default() -> Self {
+        SecretLength::Bits128
+    }
+}
+
+/// The master secret of a wallet, used as the BIP-32 seed
+#[derive(Clone, PartialEq, Eq)]
+pub struct MasterSecret(Vec<u8>);
+
+impl MasterSecret {
+    /// Create a master secret from its bytes, which must be between 16 and 32 bytes long, with
+    /// an even length
+    pub fn new(secret: Vec<u8>) -> Result<Self, Slip39Error> {
+        if !(MIN_SECRET_LEN..=MAX_SECRET_LEN).contains(&secret.len()) || secret.len() % 2 != 0 {
+            return Err(Slip39Error::InvalidSecretLength(secret.len()));
+        }
+
+        Ok(MasterSecret(secret))
+    }
+
+    /// Return the secret bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Split the secret into groups of shares, encrypted with `passphrase`
+    ///
+    /// `group_threshold` groups of the `groups` will be required to recover the secret. The
+    /// passphrase is stretched with `10000 * 2^iteration_exponent` PBKDF2 iterations.
+    pub fn split(
+        &self,
+        passphrase: &str,
+        group_threshold: u8,
+        groups: &[GroupSpec],
+        iteration_exponent: u8,
+    ) -> Result<Vec<Vec<Share>>, Slip39Error> {
+        self.split_with_rng(
+            passphrase,
+            group_threshold,
+            groups,
+            iteration_exponent,
+            &mut thread_rng(),
+        )
+    }
+
+    /// Split the secret like [`MasterSecret::split`], using a specific source of randomness
+    pub fn split_with_rng<R: RngCore + CryptoRng>(
+        &self,
+        passphrase: &str,
+        group_threshold: u8,
+        groups: &[GroupSpec],
+        iteration_exponent: u8,
+        rng: &mut R,
+    ) -> Result<Vec<Vec<Share>>, Slip39Error> {
+        if iteration_exponent > 0xf {
+            return Err(Slip39Error::InvalidIterationExponent(iteration_exponent));
+        }
+        if group_threshold == 0
+            || group_threshold as usize > groups.len()
+            || groups.len() > MAX_SHARE_COUNT as usize
+        {
+            return Err(Slip39Error::InvalidThreshold {
+                threshold: group_threshold,
+                count: groups.len() as u8,
+            });
+        }
+        // A 1-of-n group would just be the same share repeated n times
+        if let Some(group) = groups.iter().find(|group| {
+            group.threshold == 0
+                || group.threshold > group.count
+                || group.count > MAX_SHARE_COUNT
+                || (group.threshold == 1 && group.count > 1)
+        }) {
+            return Err(Slip39Error::InvalidThreshold {
+                threshold: group.threshold,
+                count: group.count,
+            });
+        }
+
+        let identifier = (rng.next_u32() & 0x7fff) as u16;
+        let extendable = true;
+        let encrypted = feistel(
+            &self.0,
+            passphrase,
+            iteration_exponent,
+            identifier,
+            extendable,
+            true,
+        );
+
+        let group_secrets = split_secret(group_threshold, groups.len() as u8, &encrypted, rng)?;
+        group_secrets
+            .into_iter()
+            .zip(groups)
+            .map(|((group_index, group_secret), group)| {
+                let members = split_secret(group.threshold, group.count, &group_secret, rng)?;
+                Ok(members
+                    .into_iter()
+                    .map(|(member_index, value)| Share {
+                        identifier,
+                        extendable,
+                        iteration_exponent,
+                        group_index,
+                        group_threshold,
+                        group_count: groups.len() as u8,
+                        member_index,
+                        member_threshold: group.threshold,
+                        value,
+                    })
+                    .collect::<Vec<_>>())
+            })
+            .collect()
+    }
+
+    /// Recover the secret from a set of shares, decrypting it with `passphrase`
+    ///
+    /// Any passphrase will recover a secret, but only the one used when splitting it will recover
+    /// the original one.
+    pub fn combine(shares: &[Share], passphrase: &str) -> Result<Self, Slip39Error> {
+        let first = shares.first().ok_or(Slip39Error::InsufficientShares)?;
+        if shares.iter().any(|share| {
+            share.identifier != first.identifier
+                || share.extendable != first.extendable
+                || share.iteration_exponent != first.iteration_exponent
+                || share.group_threshold != first.group_threshold
+                || share.group_count != first.group_count
+                || share.value.len() != first.value.len()
+        }) {
+            return Err(Slip39Error::MismatchedShares);
+        }
+
+        let mut groups: BTreeMap<u8, Vec<&Share>> = BTreeMap::new();
+        for share in shares {
+            if share.group_index >= share.group_count {
+                return Err(Slip39Error::MismatchedShares);
+            }
+
+            let group = groups.entry(share.group_index).or_default();
+            if group
+                .iter()
+                .any(|other| other.member_threshold != share.member_threshold)
+            {
+                return Err(Slip39Error::MismatchedShares);
+            }
+            match group
+                .iter()
+                .find(|other| other.member_index == share.member_index)
+            {
+                Some(other) if other.value != share.value => {
+                    return Err(Slip39Error::DuplicateShare)
+                }
+                Some(_) => {}
+                None => group.push(share),
+            }
+        }
+
+        let group_secrets = groups
+            .into_iter()
+            .filter(|(_, members)| members.len() >= members[0].member_threshold as usize)
+            .take(first.group_threshold as usize)
+            .map(|(group_index, members)| {
+                let threshold = members[0].member_threshold;
+                let members = members
+                    .iter()
+                    .take(threshold as usize)
+                    .map(|share| (share.member_index, share.value.clone()))
+                    .collect::<Vec<_>>();
+                Ok((group_index, recover_secret(threshold, &members)?))
+            })
+            .collect::<Result<Vec<_>, Slip39Error>>()?;
+        if group_secrets.len() < first.group_threshold as usize {
+            return Err(Slip39Error::InsufficientShares);
+        }
+
+        let encrypted = recover_secret(first.group_threshold, &group_secrets)?;
+        MasterSecret::new(feistel(
+            &encrypted,
+            passphrase,
+            first.iteration_exponent,
+            first.identifier,
+            first.extendable,
+            false,
+        ))
+    }
+}
+
+// Don't show the secret in the logs
+impl fmt::Debug for MasterSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MasterSecret(..)")
+    }
+}
+
+#[cfg_attr(docsrs, doc(cfg(feature = "keys-slip39")))]
+impl<Ctx: ScriptContext> DerivableKey<Ctx> for MasterSecret {
+    fn into_extended_key(self) -> Result<ExtendedKey<Ctx>, KeyError> {
+        Ok(bip32::ExtendedPrivKey::new_master(Network::Bitcoin, &self.0)?.into())
+    }
+
+    fn into_descriptor_key(
+        self,
+        source: Option<bip32::KeySource>,
+        derivation_path: bip32::DerivationPath,
+    ) -> Result<DescriptorKey<Ctx>, KeyError> {
+        let descriptor_key = self
+            .into_extended_key()?
+            .into_descriptor_key(source, derivation_path)?;
+
+        // Like BIP-39, SLIP-39 doesn't encode the network, so the key is valid everywhere
+        Ok(descriptor_key.override_valid_networks(any_network()))
+    }
+}
+
+#[cfg_attr(docsrs, doc(cfg(feature = "keys-slip39")))]
+impl<Ctx: ScriptContext> GeneratableKey<Ctx> for MasterSecret {
+    type Entropy = [u8; 32];
+
+    type Options = SecretLength;
+    type Error = Slip39Error;
+
+    fn generate_with_entropy(
+        length: Self::Options,
+        entropy: Self::Entropy,
+    ) -> Result<GeneratedKey<Self, Ctx>, Self::Error> {
+        let length = match length {
+            SecretLength::Bits128 => 16,
+            SecretLength::Bits256 => 32,
+        };
+        let secret = MasterSecret::new(entropy[..length].to_vec())?;
+
+        Ok(GeneratedKey::new(secret, any_network()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use bitcoin::hashes::hex::FromHex;
+
+    use super::*;
+
+    use crate::descriptor::template::{Bip84, DescriptorTemplate};
+    use crate::keys::{any_network, GeneratableKey, GeneratedKey};
+    use crate::KeychainKind;
+
+    fn parse_shares(mnemonics: &[&str]) -> Vec<Share> {
+        mnemonics.iter().map(|m| m.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_keys_slip39_vectors() {
+        let mnemonic = "duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision keyboard";
+        let shares = parse_shares(&[mnemonic]);
+        assert_eq!(shares[0].to_mnemonic(), mnemonic);
+        let secret = MasterSecret::combine(&shares, "TREZOR").unwrap();
+        assert_eq!(
+            secret.as_bytes(),
+            &Vec::<u8>::from_hex("bb54aac4b89dc868ba37d9cc21b2cece").unwrap()[..]
+        );
+
+        let shares = parse_shares(&[
+            "shadow pistol academic always adequate wildlife fancy gross oasis cylinder mustang wrist rescue view short owner flip making coding armed",
+            "shadow pistol academic acid actress prayer class unknown daughter sweater depict flip twice unkind craft early superior advocate guest smoking",
+        ]);
+        let secret = MasterSecret::combine(&shares, "TREZOR").unwrap();
+        assert_eq!(
+            secret.as_bytes(),
+            &Vec::<u8>::from_hex("b43ceb7e57a0ea8766221624d01b0864").unwrap()[..]
+        );
+        // Only one of the two required shares
+        assert_eq!(
+            MasterSecret::combine(&shares[..1], "TREZOR"),
+            Err(Slip39Error::InsufficientShares)
+        );
+
+        let shares = parse_shares(&["theory painting academic academic armed sweater year military elder discuss acne wildlife boring employer fused large satoshi bundle carbon diagnose anatomy hamster leaves tracks paces beyond phantom capital marvel lips brave detect luck"]);
+        let secret = MasterSecret::combine(&shares, "TREZOR").unwrap();
+        assert_eq!(
+            secret.as_bytes(),
+            &Vec::<u8>::from_hex(
+                "989baf9dcaad5b10ca33dfd8cc75e42477025dce88ae83e75a230086a0e00e92"
+            )
+            .unwrap()[..]
+        );
+    }
+
+    #[test]
+    fn test_keys_slip39_invalid_mnemonic() {
+        assert_eq!(
+            Share::from_mnemonic("duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision kidney"),
+            Err(Slip39Error::InvalidChecksum)
+        );
+        assert_eq!(
+            Share::from_mnemonic("duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision bitcoin"),
+            Err(Slip39Error::UnknownWord("bitcoin".to_string()))
+        );
+        assert_eq!(
+            Share::from_mnemonic("duckling enlarge academic academic agency result"),
+            Err(Slip39Error::BadWordCount(6))
+        );
+    }
+
+    #[test]
+    fn test_keys_slip39_split_combine() {
+        let mut rng = StdRng::from_seed([42; 32]);
+        let secret = MasterSecret::new(vec![0x42; 32]).unwrap();
+
+        // 2 of 3 groups: a 1-of-1, a 2-of-3 and a 3-of-5
+        let groups = [
+            GroupSpec::new(1, 1),
+            GroupSpec::new(2, 3),
+            GroupSpec::new(3, 5),
+        ];
+        let shares = secret
+            .split_with_rng("passphrase", 2, &groups, 0, &mut rng)
+            .unwrap();
+        assert_eq!(
+            shares.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![1, 3, 5]
+        );
+
+        // Round-trip through the mnemonics
+        let shares = shares
+            .iter()
+            .map(|group| {
+                group
+                    .iter()
+                    .map(|share| share.to_mnemonic().parse::<Share>().unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(shares[2][0].to_mnemonic().split(' ').count(), 33);
+
+        let selected = vec![
+            shares[0][0].clone(),
+            shares[2][4].clone(),
+            shares[2][0].clone(),
+            shares[2][2].clone(),
+        ];
+        assert_eq!(
+            MasterSecret::combine(&selected, "passphrase").unwrap(),
+            secret
+        );
+        assert_ne!(MasterSecret::combine(&selected, "wrong").unwrap(), secret);
+
+        let selected = vec![
+            shares[1][1].clone(),
+            shares[1][2].clone(),
+            shares[2][1].clone(),
+            shares[2][3].clone(),
+            shares[2][4].clone(),
+        ];
+        assert_eq!(
+            MasterSecret::combine(&selected, "passphrase").unwrap(),
+            secret
+        );
+
+        // Not enough shares in the second group
+        let selected = vec![
+            shares[0][0].clone(),
+            shares[2][0].clone(),
+            shares[2][1].clone(),
+        ];
+        assert_eq!(
+            MasterSecret::combine(&selected, "passphrase"),
+            Err(Slip39Error::InsufficientShares)
+        );
+
+        let other = secret
+            .split_with_rng("passphrase", 1, &[GroupSpec::new(2, 2)], 0, &mut rng)
+            .unwrap();
+        assert_eq!(
+            MasterSecret::combine(&[shares[0][0].clone(), other[0][0].clone()], "passphrase"),
+            Err(Slip39Error::MismatchedShares)
+        );
+    }
+
+    #[test]
+    fn test_keys_slip39_invalid_parameters() {
+        let secret = MasterSecret::new(vec![0x42; 16]).unwrap();
+        assert_eq!(
+            secret.split("", 3, &[GroupSpec::new(2, 3), GroupSpec::new(2, 3)], 0),
+            Err(Slip39Error::InvalidThreshold {
+                threshold: 3,
+                count: 2
+            })
+        );
+        assert_eq!(
+            secret.split("", 1, &[GroupSpec::new(1, 3)], 0),
+            Err(Slip39Error::InvalidThreshold {
+                threshold: 1,
+                count: 3
+            })
+        );
+        assert_eq!(
+            secret.split("", 1, &[GroupSpec::new(2, 17)], 0),
+            Err(Slip39Error::InvalidThreshold {
+                threshold: 2,
+                count: 17
+            })
+        );
+        assert_eq!(
+            secret.split("", 1, &[GroupSpec::new(2, 3)], 16),
+            Err(Slip39Error::InvalidIterationExponent(16))
+        );
+        assert_eq!(
+            MasterSecret::new(vec![0x42; 17]),
+            Err(Slip39Error::InvalidSecretLength(17))
+        );
+    }
+
+    #[test]
+    fn test_keys_slip39_descriptor() {
+        let secret =
+            MasterSecret::new(Vec::<u8>::from_hex("bb54aac4b89dc868ba37d9cc21b2cece").unwrap())
+                .unwrap();
+        let (desc, keys, networks) = Bip84(secret, KeychainKind::External).build().unwrap();
+        assert!(desc.to_string().starts_with("wpkh([828ecab2/84'/0'/0']"));
+        assert_eq!(keys.len(), 1);
+        assert_eq!(networks.len(), 4);
+    }
+
+    #[test]
+    fn test_keys_generate_slip39() {
+        let generated: GeneratedKey<_, miniscript::Segwitv0> = MasterSecret::generate_with_entropy(
+            SecretLength::Bits256,
+            crate::keys::test::TEST_ENTROPY,
+        )
+        .unwrap();
+        assert_eq!(generated.valid_networks, any_network());
+        assert_eq!(generated.as_bytes(), &crate::keys::test::TEST_ENTROPY[..]);
+    }
+}
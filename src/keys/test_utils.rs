@@ -0,0 +1,276 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Deterministic test keys
+//!
+//! This module derives keys and descriptors from a short seed string, so that tests and examples
+//! don't need to hard-code extended private keys. The same seed always gives the same keys, and
+//! different seeds give unrelated keys.
+//!
+//! **These keys are not secret: never use them to receive real funds.**
+//!
+//! ```
+//! # use bdk::bitcoin::Network;
+//! # use bdk::database::MemoryDatabase;
+//! # use bdk::keys::test_utils::{test_multisig, test_wpkh};
+//! # use bdk::wallet::AddressIndex;
+//! # use bdk::{KeychainKind, Wallet};
+//! let alice = test_wpkh("alice", Network::Regtest);
+//! let wallet = Wallet::new_offline(
+//!     &alice.external,
+//!     Some(&alice.internal),
+//!     Network::Regtest,
+//!     MemoryDatabase::default(),
+//! )?;
+//! assert_eq!(
+//!     wallet.get_address(AddressIndex::New)?.address,
+//!     alice.address(KeychainKind::External, 0)
+//! );
+//!
+//! let multisig = test_multisig(2, &["alice", "bob", "carol"], Network::Regtest);
+//! # Ok::<_, bdk::Error>(())
+//! ```
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey, Fingerprint};
+use bitcoin::{Address, Network};
+
+use miniscript::descriptor::KeyMap;
+use miniscript::DescriptorTrait;
+
+use crate::descriptor::derived::AsDerived;
+use crate::descriptor::{ExtendedDescriptor, IntoWalletDescriptor};
+use crate::testutils::{TestIncomingOutput, TestIncomingTx};
+use crate::types::KeychainKind;
+
+/// Derive the master key for `seed`
+pub fn test_xprv(seed: &str, network: Network) -> ExtendedPrivKey {
+    let entropy = sha256::Hash::hash(format!("bdk test key: {}", seed).as_bytes());
+    ExtendedPrivKey::new_master(network, &entropy[..]).expect("Valid entropy")
+}
+
+/// Deterministic descriptors for both keychains of a test wallet
+#[derive(Debug, Clone)]
+pub struct TestDescriptors {
+    /// External descriptor, including the private keys
+    pub external: String,
+    /// Internal descriptor, including the private keys
+    pub internal: String,
+    /// External descriptor without the private keys
+    pub public_external: ExtendedDescriptor,
+    /// Internal descriptor without the private keys
+    pub public_internal: ExtendedDescriptor,
+    /// Private keys of both descriptors
+    pub keymap: KeyMap,
+    /// Network of the keys
+    pub network: Network,
+}
+
+impl TestDescriptors {
+    fn new(external: String, internal: String, network: Network) -> Self {
+        let secp = Secp256k1::new();
+        let (public_external, mut keymap) = external
+            .as_str()
+            .into_wallet_descriptor(&secp, network)
+            .expect("Valid descriptor");
+        let (public_internal, internal_keymap) = internal
+            .as_str()
+            .into_wallet_descriptor(&secp, network)
+            .expect("Valid descriptor");
+        keymap.extend(internal_keymap);
+
+        TestDescriptors {
+            external,
+            internal,
+            public_external,
+            public_internal,
+            keymap,
+            network,
+        }
+    }
+
+    /// Return the address at `index` of `keychain`
+    pub fn address(&self, keychain: KeychainKind, index: u32) -> Address {
+        let secp = Secp256k1::new();
+        let descriptor = match keychain {
+            KeychainKind::External => &self.public_external,
+            KeychainKind::Internal => &self.public_internal,
+        };
+
+        descriptor
+            .as_derived(index, &secp)
+            .address(self.network)
+            .expect("Descriptor with an address")
+    }
+
+    /// Build a transaction fixture that pays `amounts` to the first external addresses, in order
+    ///
+    /// The transaction is unconfirmed: the `min_confirmations` of the returned [`TestIncomingTx`]
+    /// can be changed before sending it.
+    pub fn funding_tx(&self, amounts: &[u64]) -> TestIncomingTx {
+        let outputs = amounts
+            .iter()
+            .enumerate()
+            .map(|(index, amount)| {
+                TestIncomingOutput::new(*amount, self.address(KeychainKind::External, index as u32))
+            })
+            .collect();
+
+        TestIncomingTx::new(outputs, None, None, None)
+    }
+}
+
+// Master fingerprint and account key of `seed` at `path`
+fn account_key(seed: &str, path: &str, network: Network) -> (Fingerprint, ExtendedPrivKey) {
+    let secp = Secp256k1::new();
+    let master = test_xprv(seed, network);
+    let path = path.parse::<DerivationPath>().expect("Valid path");
+
+    (
+        master.fingerprint(&secp),
+        master.derive_priv(&secp, &path).expect("Valid derivation"),
+    )
+}
+
+fn coin_type(network: Network) -> u32 {
+    match network {
+        Network::Bitcoin => 0,
+        _ => 1,
+    }
+}
+
+/// Return single-sig P2WPKH descriptors derived from `seed`, following BIP84
+pub fn test_wpkh(seed: &str, network: Network) -> TestDescriptors {
+    let origin = format!("84'/{}'/0'", coin_type(network));
+    let (fingerprint, xprv) = account_key(seed, &format!("m/{}", origin), network);
+    let descriptor =
+        |keychain: u32| format!("wpkh([{}/{}]{}/{}/*)", fingerprint, origin, xprv, keychain);
+
+    TestDescriptors::new(descriptor(0), descriptor(1), network)
+}
+
+/// Return `threshold`-of-n P2WSH multisig descriptors, with one key derived from every seed in
+/// `seeds` following BIP48
+///
+/// The descriptors include the private keys of every cosigner: use the public descriptors to
+/// build watch-only wallets.
+pub fn test_multisig(threshold: usize, seeds: &[&str], network: Network) -> TestDescriptors {
+    let origin = format!("48'/{}'/0'/2'", coin_type(network));
+    let keys = seeds
+        .iter()
+        .map(|seed| account_key(seed, &format!("m/{}", origin), network))
+        .collect::<Vec<_>>();
+    let descriptor = |keychain: u32| {
+        let keys = keys
+            .iter()
+            .map(|(fingerprint, xprv)| {
+                format!("[{}/{}]{}/{}/*", fingerprint, origin, xprv, keychain)
+            })
+            .collect::<Vec<_>>();
+        format!("wsh(sortedmulti({},{}))", threshold, keys.join(","))
+    };
+
+    TestDescriptors::new(descriptor(0), descriptor(1), network)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::MemoryDatabase;
+    use crate::wallet::AddressIndex;
+    use crate::Wallet;
+
+    #[test]
+    fn test_deterministic_keys() {
+        assert_eq!(
+            test_xprv("alice", Network::Regtest),
+            test_xprv("alice", Network::Regtest)
+        );
+        assert_ne!(
+            test_xprv("alice", Network::Regtest),
+            test_xprv("bob", Network::Regtest)
+        );
+
+        let alice = test_wpkh("alice", Network::Testnet);
+        assert_eq!(
+            alice.external,
+            test_wpkh("alice", Network::Testnet).external
+        );
+        assert!(alice.external.starts_with(&format!(
+            "wpkh([{}/84'/1'/0']tprv",
+            test_xprv("alice", Network::Testnet).fingerprint(&Secp256k1::new())
+        )));
+        assert!(alice.external.ends_with("/0/*)"));
+        assert!(alice.internal.ends_with("/1/*)"));
+        assert_eq!(alice.keymap.len(), 2);
+    }
+
+    #[test]
+    fn test_wallet_from_test_descriptors() {
+        let alice = test_wpkh("alice", Network::Regtest);
+        let wallet = Wallet::new_offline(
+            &alice.external,
+            Some(&alice.internal),
+            Network::Regtest,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+
+        for index in 0..3 {
+            assert_eq!(
+                wallet
+                    .get_address(AddressIndex::Peek(index))
+                    .unwrap()
+                    .address,
+                alice.address(KeychainKind::External, index)
+            );
+        }
+        assert_eq!(
+            wallet.public_descriptor(KeychainKind::Internal).unwrap(),
+            Some(alice.public_internal.clone())
+        );
+        assert!(wallet
+            .key_origins(KeychainKind::External)
+            .iter()
+            .all(|origin| origin.has_private_key));
+
+        let tx = alice.funding_tx(&[50_000, 20_000]);
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(tx.output[0].value, 50_000);
+        assert_eq!(
+            tx.output[1].to_address,
+            alice.address(KeychainKind::External, 1).to_string()
+        );
+    }
+
+    #[test]
+    fn test_multisig_descriptors() {
+        let multisig = test_multisig(2, &["alice", "bob", "carol"], Network::Regtest);
+        assert!(multisig.external.starts_with("wsh(sortedmulti(2,["));
+        assert_eq!(multisig.keymap.len(), 6);
+
+        let wallet = Wallet::new_offline(
+            &multisig.public_external.to_string(),
+            Some(&multisig.public_internal.to_string()),
+            Network::Regtest,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            wallet.get_address(AddressIndex::Peek(0)).unwrap().address,
+            multisig.address(KeychainKind::External, 0)
+        );
+        assert!(wallet
+            .key_origins(KeychainKind::External)
+            .iter()
+            .all(|origin| !origin.has_private_key));
+    }
+}
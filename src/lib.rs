@@ -186,7 +186,15 @@ fn main() -> Result<(), bdk::Error> {
 //!
 //! * `all-keys`: all features for working with bitcoin keys
 //! * `async-interface`: async functions in bdk traits
+//! * `bip47`: [BIP-47](https://github.com/bitcoin/bips/blob/master/bip-0047.mediawiki) reusable payment codes, see [`wallet::bip47`]
+//! * `bsms`: [BIP-129](https://github.com/bitcoin/bips/blob/master/bip-0129.mediawiki) secure multisig setup, see [`wallet::bsms`]
+//! * `compiler`: compile spending policies into descriptors, see [`descriptor::compiler`]
+//! * `encrypted-keys`: secret keys encrypted with a passphrase and only decrypted while signing, see [`keys::encrypted`]
 //! * `keys-bip39`: [BIP-39](https://github.com/bitcoin/bips/blob/master/bip-0039.mediawiki) mnemonic codes for generating deterministic keys
+//! * `keys-slip39`: [SLIP-39](https://github.com/satoshilabs/slips/blob/master/slip-0039.md) Shamir backups of the master secret, see [`keys::slip39`]
+//! * `qr`: animated QR codes (BC-UR and BBQr) to exchange PSBTs with air-gapped signers, see [`psbt::qr`]
+//! * `silent-payments`: [BIP-352](https://github.com/bitcoin/bips/blob/master/bip-0352.mediawiki) silent payments, see [`wallet::silent_payments`]
+//! * `test-utils`: deterministic keys and descriptors for tests and examples, see [`keys::test_utils`]
 //!
 //! ## Internal features
 //!
@@ -210,9 +218,6 @@ compile_error!(
     "Features async-interface and electrum are mutually exclusive and cannot be enabled together"
 );
 
-#[cfg(feature = "keys-bip39")]
-extern crate bip39;
-
 #[cfg(any(target_arch = "wasm32", feature = "async-interface"))]
 #[macro_use]
 extern crate async_trait;
@@ -243,7 +248,7 @@ pub mod descriptor;
 #[cfg(feature = "test-md-docs")]
 mod doctest;
 pub mod keys;
-pub(crate) mod psbt;
+pub mod psbt;
 pub(crate) mod types;
 pub mod wallet;
 
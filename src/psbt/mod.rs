@@ -9,10 +9,27 @@
 // You may not use this file except in accordance with one or both of these
 // licenses.
 
+//! Additional functions on PSBTs
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Cursor;
+
+use bitcoin::consensus::encode::{serialize, Decodable, VarInt};
+use bitcoin::util::bip32::{DerivationPath, Fingerprint};
+use bitcoin::util::psbt::raw;
 use bitcoin::util::psbt::PartiallySignedTransaction as Psbt;
-use bitcoin::TxOut;
+use bitcoin::{Address, OutPoint, PublicKey, Script, TxOut};
+
+use crate::types::{FeeRate, KeychainKind};
 
+#[cfg(feature = "qr")]
+#[cfg_attr(docsrs, doc(cfg(feature = "qr")))]
+pub mod qr;
+pub mod v2;
+
+/// Trait to add functions to extract utxos and calculate fees
 pub trait PsbtUtils {
+    /// Get the `TxOut` for the specified input index, if it doesn't exist in the PSBT `None` is returned
     fn get_utxo_for(&self, input_index: usize) -> Option<TxOut>;
 }
 
@@ -39,8 +56,329 @@ impl PsbtUtils for Psbt {
     }
 }
 
+/// Key type of the proprietary PSBT fields, as defined in BIP-174
+pub const PSBT_PROPRIETARY_TYPE: u8 = 0xFC;
+
+/// Key of a proprietary PSBT field
+///
+/// Proprietary fields can be used by custom protocols to carry their own metadata in a PSBT. They
+/// are stored among the `unknown` fields of the global, input and output maps, and they are
+/// preserved when signing, combining, finalizing or converting PSBTs to version 2.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProprietaryKey {
+    /// Identifier of the protocol or vendor that defined the field
+    pub prefix: Vec<u8>,
+    /// Type of the field within the protocol
+    pub subtype: u8,
+    /// Additional key data
+    pub key: Vec<u8>,
+}
+
+impl ProprietaryKey {
+    /// Create a new proprietary key
+    pub fn new(prefix: &[u8], subtype: u8, key: &[u8]) -> Self {
+        ProprietaryKey {
+            prefix: prefix.to_vec(),
+            subtype,
+            key: key.to_vec(),
+        }
+    }
+
+    /// Convert to the raw key stored among the `unknown` fields of a PSBT map
+    pub fn to_raw_key(&self) -> raw::Key {
+        let mut key = serialize(&self.prefix);
+        key.extend(serialize(&VarInt(self.subtype as u64)));
+        key.extend_from_slice(&self.key);
+
+        raw::Key {
+            type_value: PSBT_PROPRIETARY_TYPE,
+            key,
+        }
+    }
+
+    /// Parse a raw key, returning `None` if it's not a valid proprietary key
+    pub fn from_raw_key(raw_key: &raw::Key) -> Option<Self> {
+        if raw_key.type_value != PSBT_PROPRIETARY_TYPE {
+            return None;
+        }
+
+        let mut cursor = Cursor::new(&raw_key.key[..]);
+        let prefix = Vec::<u8>::consensus_decode(&mut cursor).ok()?;
+        let VarInt(subtype) = VarInt::consensus_decode(&mut cursor).ok()?;
+        if subtype > u8::MAX as u64 {
+            return None;
+        }
+        let key = raw_key.key[cursor.position() as usize..].to_vec();
+
+        Some(ProprietaryKey {
+            prefix,
+            subtype: subtype as u8,
+            key,
+        })
+    }
+}
+
+/// Return the proprietary fields with the given `prefix` found among the `unknown` fields of a
+/// PSBT map
+pub fn get_proprietary_fields<'a>(
+    unknown: &'a BTreeMap<raw::Key, Vec<u8>>,
+    prefix: &'a [u8],
+) -> impl Iterator<Item = (ProprietaryKey, &'a Vec<u8>)> + 'a {
+    unknown.iter().filter_map(move |(raw_key, value)| {
+        ProprietaryKey::from_raw_key(raw_key)
+            .filter(|key| key.prefix == prefix)
+            .map(|key| (key, value))
+    })
+}
+
+/// Result of [`Wallet::combine_psbts`](crate::wallet::Wallet::combine_psbts)
+#[derive(Debug, Clone, PartialEq)]
+pub struct CombinedPsbt {
+    /// The PSBT containing the data of every PSBT that was combined
+    pub psbt: Psbt,
+    /// For every input, the public keys of the partial signatures that were added on top of
+    /// the ones already present in the first PSBT
+    pub added_signatures: Vec<BTreeSet<PublicKey>>,
+}
+
+/// Options for [`Wallet::check_psbt`](crate::wallet::Wallet::check_psbt)
+#[derive(Debug, Clone, Default)]
+pub struct PsbtCheckOptions {
+    /// Maximum fee rate the transaction is expected to pay
+    pub max_fee_rate: Option<FeeRate>,
+    /// Maximum absolute fee (in satoshi) the transaction is expected to pay
+    pub max_fee: Option<u64>,
+    /// The outputs the user expects to send to
+    ///
+    /// When set, every output that doesn't belong to the wallet must appear in this list, and
+    /// every entry of the list must appear in the transaction.
+    pub expected_outputs: Option<Vec<TxOut>>,
+}
+
+/// A problem found by [`Wallet::check_psbt`](crate::wallet::Wallet::check_psbt)
+#[derive(Debug, Clone, PartialEq)]
+pub enum PsbtIssue {
+    /// The input at this index has neither a `witness_utxo` nor a `non_witness_utxo`
+    MissingUtxo(usize),
+    /// The `witness_utxo` and the `non_witness_utxo` of the input at this index don't agree, or
+    /// the `non_witness_utxo` isn't the transaction being spent
+    InconsistentUtxo(usize),
+    /// The outputs spend more than the inputs
+    OutputsExceedInputs {
+        /// Total value of the inputs
+        input_value: u64,
+        /// Total value of the outputs
+        output_value: u64,
+    },
+    /// The fee is higher than [`PsbtCheckOptions::max_fee`]
+    FeeTooHigh {
+        /// Fee of the transaction
+        fee: u64,
+        /// Maximum fee allowed
+        max: u64,
+    },
+    /// The estimated fee rate is higher than [`PsbtCheckOptions::max_fee_rate`]
+    FeeRateTooHigh {
+        /// Estimated fee rate of the transaction
+        rate: FeeRate,
+        /// Maximum fee rate allowed
+        max: FeeRate,
+    },
+    /// The output at this index carries derivation paths for the wallet keys, but its script
+    /// doesn't belong to the wallet
+    ForeignChange(usize),
+    /// The output at this index doesn't belong to the wallet and isn't one of the
+    /// [`PsbtCheckOptions::expected_outputs`]
+    UnexpectedOutput(usize),
+    /// This entry of [`PsbtCheckOptions::expected_outputs`] doesn't appear in the transaction
+    MissingOutput(TxOut),
+}
+
+/// An output of the transaction checked by [`Wallet::check_psbt`](crate::wallet::Wallet::check_psbt)
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckedOutput {
+    /// Script of the output
+    pub script_pubkey: Script,
+    /// Value of the output
+    pub value: u64,
+    /// Keychain of the wallet the output belongs to, `None` if the output is not ours
+    pub keychain: Option<KeychainKind>,
+}
+
+/// Report returned by [`Wallet::check_psbt`](crate::wallet::Wallet::check_psbt)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PsbtCheckReport {
+    /// Fee paid by the transaction, `None` if some input utxos are missing
+    pub fee: Option<u64>,
+    /// Estimated fee rate of the signed transaction
+    ///
+    /// The weight of inputs that are not finalized and don't belong to the wallet can't be
+    /// estimated, so in that case this value is an upper bound.
+    pub fee_rate: Option<FeeRate>,
+    /// Value of the wallet utxos spent by the transaction
+    pub sent: u64,
+    /// Value of the outputs that belong to the wallet
+    pub received: u64,
+    /// The outputs of the transaction, in order
+    pub outputs: Vec<CheckedOutput>,
+    /// The problems found, empty if the PSBT looks safe to sign
+    pub issues: Vec<PsbtIssue>,
+}
+
+impl PsbtCheckReport {
+    /// Whether no problems were found
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// An input of the PSBT described by [`Wallet::describe_psbt`](crate::wallet::Wallet::describe_psbt)
+#[derive(Debug, Clone, PartialEq)]
+pub struct DescribedInput {
+    /// Outpoint spent by the input
+    pub previous_output: OutPoint,
+    /// Output spent by the input, if available in the PSBT
+    pub utxo: Option<TxOut>,
+    /// Keychain of the wallet the spent output belongs to, `None` if it's not ours
+    pub keychain: Option<KeychainKind>,
+    /// Key fingerprints and derivation paths declared in the PSBT input
+    pub derivation_paths: Vec<(Fingerprint, DerivationPath)>,
+    /// Whether the input is already finalized
+    pub finalized: bool,
+}
+
+/// An output of the PSBT described by [`Wallet::describe_psbt`](crate::wallet::Wallet::describe_psbt)
+#[derive(Debug, Clone, PartialEq)]
+pub struct DescribedOutput {
+    /// Script of the output
+    pub script_pubkey: Script,
+    /// Address of the output, if its script can be represented as one
+    pub address: Option<Address>,
+    /// Value of the output
+    pub value: u64,
+    /// Keychain of the wallet the output belongs to, `None` if it's not ours
+    pub keychain: Option<KeychainKind>,
+    /// Key fingerprints and derivation paths declared in the PSBT output
+    pub derivation_paths: Vec<(Fingerprint, DerivationPath)>,
+}
+
+/// A suspicious pattern flagged by [`Wallet::describe_psbt`](crate::wallet::Wallet::describe_psbt)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PsbtWarning {
+    /// The output at this index sends to one of our addresses that has already been used
+    AddressReuse(usize),
+    /// The output at this index sends to a foreign address the wallet has already seen in its
+    /// transaction history
+    PreviouslySeenAddress(usize),
+    /// The output at this index carries derivation paths for the wallet keys, but its script
+    /// doesn't belong to the wallet
+    ForeignChange(usize),
+}
+
+/// Human-reviewable summary returned by [`Wallet::describe_psbt`](crate::wallet::Wallet::describe_psbt)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PsbtDescription {
+    /// The inputs of the transaction, in order
+    pub inputs: Vec<DescribedInput>,
+    /// The outputs of the transaction, in order
+    pub outputs: Vec<DescribedOutput>,
+    /// Fee paid by the transaction, `None` if some input utxos are missing
+    pub fee: Option<u64>,
+    /// Estimated fee rate of the signed transaction
+    pub fee_rate: Option<FeeRate>,
+    /// The suspicious patterns found
+    pub warnings: Vec<PsbtWarning>,
+}
+
+/// Outcome of the finalization of a single input, see [`FinalizeReport`]
+#[derive(Debug)]
+pub enum InputFinalization {
+    /// The input was already finalized
+    AlreadyFinalized,
+    /// The input has been finalized
+    Finalized,
+    /// The wallet doesn't know which descriptor the input is spending
+    UnknownDescriptor,
+    /// The input can't be satisfied yet, either because some signatures are missing or because
+    /// its timelocks haven't expired
+    MissingSignatures {
+        /// The keys of the descriptor that haven't signed the input yet, with the fingerprint of
+        /// their master key when known. Depending on the policy not all of them are required.
+        keys: Vec<(PublicKey, Option<Fingerprint>)>,
+    },
+    /// Miniscript failed to satisfy the input for a different reason
+    SatisfactionFailed(miniscript::Error),
+}
+
+/// Report returned by [`Wallet::finalize_psbt_with_report`](crate::wallet::Wallet::finalize_psbt_with_report)
+#[derive(Debug)]
+pub struct FinalizeReport {
+    /// The outcome for every input, in order
+    pub inputs: Vec<InputFinalization>,
+}
+
+impl FinalizeReport {
+    /// Whether every input is finalized
+    pub fn is_finalized(&self) -> bool {
+        self.inputs.iter().all(|input| {
+            matches!(
+                input,
+                InputFinalization::AlreadyFinalized | InputFinalization::Finalized
+            )
+        })
+    }
+
+    /// Indexes of the inputs that are still missing signatures
+    pub fn missing_signatures(&self) -> Vec<usize> {
+        self.inputs
+            .iter()
+            .enumerate()
+            .filter(|(_, input)| matches!(input, InputFinalization::MissingSignatures { .. }))
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+/// A signature added to an input by
+/// [`Wallet::sign_with_report`](crate::wallet::Wallet::sign_with_report)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddedSignature {
+    /// The key that produced the signature
+    pub public_key: PublicKey,
+    /// Fingerprint of the master key and derivation path of the key, when present in the PSBT
+    pub origin: Option<(Fingerprint, DerivationPath)>,
+}
+
+/// Report returned by [`Wallet::sign_with_report`](crate::wallet::Wallet::sign_with_report)
+#[derive(Debug)]
+pub struct SigningReport {
+    /// The signatures added to every input, in order
+    pub added_signatures: Vec<Vec<AddedSignature>>,
+    /// The outcome of the finalization attempted after signing, which also lists the signatures
+    /// that are still missing
+    pub finalization: FinalizeReport,
+}
+
+impl SigningReport {
+    /// Whether every input is finalized
+    pub fn is_finalized(&self) -> bool {
+        self.finalization.is_finalized()
+    }
+
+    /// Indexes of the inputs that received at least one new signature
+    pub fn signed_inputs(&self) -> Vec<usize> {
+        self.added_signatures
+            .iter()
+            .enumerate()
+            .filter(|(_, sigs)| !sigs.is_empty())
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use super::*;
     use crate::bitcoin::TxIn;
     use crate::psbt::Psbt;
     use crate::wallet::test::{get_funded_wallet, get_test_wpkh};
@@ -119,4 +457,67 @@ mod test {
 
         let _ = wallet.sign(&mut psbt, SignOptions::default()).unwrap();
     }
+
+    #[test]
+    fn test_proprietary_key() {
+        let key = ProprietaryKey::new(b"bdk", 0x01, &[0xde, 0xad]);
+        let raw_key = key.to_raw_key();
+        assert_eq!(raw_key.type_value, PSBT_PROPRIETARY_TYPE);
+        assert_eq!(raw_key.key, vec![0x03, b'b', b'd', b'k', 0x01, 0xde, 0xad]);
+        assert_eq!(ProprietaryKey::from_raw_key(&raw_key), Some(key));
+
+        let other = raw::Key {
+            type_value: 0x01,
+            key: raw_key.key,
+        };
+        assert_eq!(ProprietaryKey::from_raw_key(&other), None);
+    }
+
+    #[test]
+    fn test_psbt_proprietary_fields() {
+        let (wallet, _, txid) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_address(AddressIndex::New).unwrap();
+        let key = ProprietaryKey::new(b"bdk", 0x01, &[]);
+
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .add_proprietary_global(key.clone(), vec![0x00])
+            .add_proprietary_input(
+                crate::bitcoin::OutPoint { txid, vout: 0 },
+                key.clone(),
+                vec![0x01],
+            )
+            .add_proprietary_output(addr.script_pubkey(), key.clone(), vec![0x02]);
+        let (psbt, _) = builder.finish().unwrap();
+
+        let mut signed = v2::deserialize(&v2::serialize(&psbt)).unwrap();
+        let finalized = wallet.sign(&mut signed, SignOptions::default()).unwrap();
+        assert!(finalized);
+        let combined = wallet.combine_psbts(vec![psbt, signed]).unwrap().psbt;
+
+        let output_index = combined
+            .global
+            .unsigned_tx
+            .output
+            .iter()
+            .position(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap();
+        for (unknown, value) in &[
+            (&combined.global.unknown, vec![0x00]),
+            (&combined.inputs[0].unknown, vec![0x01]),
+            (&combined.outputs[output_index].unknown, vec![0x02]),
+        ] {
+            assert_eq!(
+                get_proprietary_fields(unknown, b"bdk").collect::<Vec<_>>(),
+                vec![(key.clone(), value)]
+            );
+        }
+
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .add_proprietary_output(Script::new(), key, vec![]);
+        assert!(matches!(builder.finish(), Err(crate::Error::Generic(_))));
+    }
 }
@@ -0,0 +1,348 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! BBQr encoding
+//!
+//! Every [BBQr] part starts with an 8 characters header: the `B$` marker, the encoding of the
+//! data, the type of file, the total number of parts and the index of the part, both in base 36.
+//! PSBTs are encoded as hex or base32 files of type `P`. Zlib-compressed parts are not supported.
+//!
+//! ```
+//! # use bdk::*;
+//! # use bdk::psbt::qr::bbqr::{self, BbqrDecoder, BbqrEncoding};
+//! # let wallet = doctest_wallet!();
+//! # let mut builder = wallet.build_tx();
+//! # builder.drain_wallet().drain_to(wallet.get_address(wallet::AddressIndex::New)?.script_pubkey());
+//! # let (psbt, _) = builder.finish()?;
+//! let parts = bbqr::encode(&psbt, BbqrEncoding::Base32, 100)?;
+//!
+//! let mut decoder = BbqrDecoder::default();
+//! for part in &parts {
+//!     decoder.receive(part)?;
+//! }
+//! assert_eq!(decoder.psbt()?, psbt);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [BBQr]: https://bbqr.org/BBQr.html
+
+use std::collections::BTreeMap;
+
+use bitcoin::util::psbt::PartiallySignedTransaction as Psbt;
+
+use super::QrError;
+use crate::psbt::v2;
+
+const HEADER_LEN: usize = 8;
+const MAX_PARTS: usize = 36 * 36 - 1;
+const FILE_TYPE_PSBT: char = 'P';
+
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const BASE36_ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/// Encoding of the data in the BBQr parts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BbqrEncoding {
+    /// Uppercase hex, `H` in the header
+    Hex,
+    /// RFC 4648 base32 without padding, `2` in the header. More compact than hex in the
+    /// alphanumeric mode of QR codes
+    Base32,
+}
+
+impl BbqrEncoding {
+    fn from_char(c: char) -> Result<Self, QrError> {
+        match c {
+            'H' => Ok(BbqrEncoding::Hex),
+            '2' => Ok(BbqrEncoding::Base32),
+            'Z' => Err(QrError::Unsupported(
+                "Zlib compressed BBQr are not supported".into(),
+            )),
+            _ => Err(QrError::InvalidFormat(format!(
+                "Unknown BBQr encoding `{}`",
+                c
+            ))),
+        }
+    }
+
+    fn as_char(&self) -> char {
+        match self {
+            BbqrEncoding::Hex => 'H',
+            BbqrEncoding::Base32 => '2',
+        }
+    }
+
+    // Number of characters that always encode a whole number of bytes
+    fn unit_len(&self) -> usize {
+        match self {
+            BbqrEncoding::Hex => 2,
+            BbqrEncoding::Base32 => 8,
+        }
+    }
+
+    fn encode(&self, data: &[u8]) -> String {
+        match self {
+            BbqrEncoding::Hex => data.iter().map(|b| format!("{:02X}", b)).collect(),
+            BbqrEncoding::Base32 => {
+                let mut encoded = String::with_capacity((data.len() * 8 + 4) / 5);
+                for chunk in data.chunks(5) {
+                    let mut buf = [0u8; 5];
+                    buf[..chunk.len()].copy_from_slice(chunk);
+                    let value = buf.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+                    let chars = (chunk.len() * 8 + 4) / 5;
+                    for i in 0..chars {
+                        let index = (value >> (35 - i * 5)) & 0x1F;
+                        encoded.push(BASE32_ALPHABET[index as usize] as char);
+                    }
+                }
+                encoded
+            }
+        }
+    }
+
+    fn decode(&self, data: &str) -> Result<Vec<u8>, QrError> {
+        let invalid = || QrError::InvalidFormat("Invalid BBQr data".into());
+
+        match self {
+            BbqrEncoding::Hex => {
+                if data.len() % 2 != 0 || !data.is_ascii() {
+                    return Err(invalid());
+                }
+                (0..data.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&data[i..i + 2], 16).map_err(|_| invalid()))
+                    .collect()
+            }
+            BbqrEncoding::Base32 => {
+                let mut decoded = Vec::with_capacity(data.len() * 5 / 8);
+                for chunk in data.as_bytes().chunks(8) {
+                    let bytes = chunk.len() * 5 / 8;
+                    if ![2, 4, 5, 7, 8].contains(&chunk.len()) {
+                        return Err(invalid());
+                    }
+
+                    let mut value = 0u64;
+                    for (i, c) in chunk.iter().enumerate() {
+                        let index = BASE32_ALPHABET
+                            .iter()
+                            .position(|a| a == c)
+                            .ok_or_else(invalid)?;
+                        value |= (index as u64) << (35 - i * 5);
+                    }
+                    decoded.extend_from_slice(&value.to_be_bytes()[3..3 + bytes]);
+                }
+                Ok(decoded)
+            }
+        }
+    }
+}
+
+fn encode_base36(value: usize) -> String {
+    [value / 36, value % 36]
+        .iter()
+        .map(|i| BASE36_ALPHABET[*i] as char)
+        .collect()
+}
+
+fn decode_base36(s: &str) -> Result<usize, QrError> {
+    usize::from_str_radix(s, 36).map_err(|_| QrError::InvalidFormat("Invalid BBQr header".into()))
+}
+
+/// Split `psbt` in BBQr parts of at most `max_part_len` characters, header included
+///
+/// The data is split evenly between the parts, so that all of them except the last have the
+/// same length.
+pub fn encode(
+    psbt: &Psbt,
+    encoding: BbqrEncoding,
+    max_part_len: usize,
+) -> Result<Vec<String>, QrError> {
+    let unit_len = encoding.unit_len();
+    let max_data_len = max_part_len.saturating_sub(HEADER_LEN) / unit_len * unit_len;
+    if max_data_len == 0 {
+        return Err(QrError::InvalidFormat(format!(
+            "Parts must be longer than {} characters",
+            HEADER_LEN + unit_len - 1
+        )));
+    }
+
+    let data = encoding.encode(&bitcoin::consensus::serialize(psbt));
+    let count = (data.len() + max_data_len - 1) / max_data_len;
+    if count > MAX_PARTS {
+        return Err(QrError::TooManyParts);
+    }
+    let units = (data.len() + unit_len - 1) / unit_len;
+    let part_len = (units + count - 1) / count * unit_len;
+
+    Ok(data
+        .as_bytes()
+        .chunks(part_len)
+        .enumerate()
+        .map(|(index, chunk)| {
+            format!(
+                "B${}{}{}{}{}",
+                encoding.as_char(),
+                FILE_TYPE_PSBT,
+                encode_base36(count),
+                encode_base36(index),
+                // the encoded data is always ASCII
+                std::str::from_utf8(chunk).unwrap()
+            )
+        })
+        .collect())
+}
+
+/// Decoder for BBQr parts
+///
+/// Parts can be received in any order, and duplicated parts are ignored. Unlike BC-UR, every part
+/// has to be received to decode the PSBT.
+#[derive(Debug, Clone, Default)]
+pub struct BbqrDecoder {
+    header: Option<(BbqrEncoding, usize)>,
+    parts: BTreeMap<usize, Vec<u8>>,
+}
+
+impl BbqrDecoder {
+    /// Receive a scanned part
+    pub fn receive(&mut self, part: &str) -> Result<(), QrError> {
+        let part = part.trim();
+        if !part.is_ascii() || part.len() < HEADER_LEN || !part.starts_with("B$") {
+            return Err(QrError::InvalidFormat("Missing BBQr header".into()));
+        }
+
+        let encoding = BbqrEncoding::from_char(part[2..3].chars().next().unwrap())?;
+        if &part[3..4] != "P" {
+            return Err(QrError::Unsupported(format!(
+                "Unsupported BBQr file type `{}`",
+                &part[3..4]
+            )));
+        }
+        let count = decode_base36(&part[4..6])?;
+        let index = decode_base36(&part[6..8])?;
+        if count == 0 || index >= count {
+            return Err(QrError::InvalidFormat("Invalid BBQr header".into()));
+        }
+
+        match self.header {
+            Some(header) if header != (encoding, count) => return Err(QrError::InconsistentPart),
+            Some(_) => {}
+            None => self.header = Some((encoding, count)),
+        }
+
+        let data = encoding.decode(&part[HEADER_LEN..])?;
+        self.parts.insert(index, data);
+
+        Ok(())
+    }
+
+    /// Whether all the parts have been received
+    pub fn is_complete(&self) -> bool {
+        match self.header {
+            Some((_, count)) => self.parts.len() == count,
+            None => false,
+        }
+    }
+
+    /// Fraction of the parts received so far, between `0.0` and `1.0`
+    pub fn progress(&self) -> f32 {
+        match self.header {
+            Some((_, count)) => self.parts.len() as f32 / count as f32,
+            None => 0.0,
+        }
+    }
+
+    /// Return the decoded PSBT, or [`QrError::Incomplete`] if some parts are missing
+    ///
+    /// Both version 0 and version 2 PSBTs are accepted, see [`v2::deserialize`].
+    pub fn psbt(&self) -> Result<Psbt, QrError> {
+        if !self.is_complete() {
+            return Err(QrError::Incomplete);
+        }
+
+        let data = self.parts.values().flatten().copied().collect::<Vec<_>>();
+        Ok(v2::deserialize(&data)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::wallet::test::{get_funded_wallet, get_test_wpkh};
+
+    #[test]
+    fn test_bbqr_encodings() {
+        let data = b"foobar";
+        assert_eq!(BbqrEncoding::Hex.encode(data), "666F6F626172");
+        assert_eq!(BbqrEncoding::Base32.encode(data), "MZXW6YTBOI");
+        for encoding in &[BbqrEncoding::Hex, BbqrEncoding::Base32] {
+            for len in 0..data.len() {
+                let encoded = encoding.encode(&data[..len]);
+                assert_eq!(encoding.decode(&encoded).unwrap(), &data[..len]);
+            }
+        }
+        assert!(BbqrEncoding::Base32.decode("MZXW6YTBO").is_err());
+
+        assert_eq!(encode_base36(1295), "ZZ");
+        assert_eq!(decode_base36("0Z").unwrap(), 35);
+    }
+
+    #[test]
+    fn test_bbqr_round_trip() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let mut builder = wallet.build_tx();
+        builder.drain_wallet().drain_to(
+            wallet
+                .get_address(crate::wallet::AddressIndex::New)
+                .unwrap()
+                .script_pubkey(),
+        );
+        let (psbt, _) = builder.finish().unwrap();
+
+        for encoding in &[BbqrEncoding::Hex, BbqrEncoding::Base32] {
+            let parts = encode(&psbt, *encoding, 60).unwrap();
+            assert!(parts.len() > 1);
+            assert!(parts.iter().all(|p| p.len() <= 60));
+            assert!(parts[..parts.len() - 1]
+                .iter()
+                .all(|p| p.len() == parts[0].len()));
+            assert_eq!(
+                &parts[1][..HEADER_LEN],
+                format!("B${}P{}01", encoding.as_char(), encode_base36(parts.len()))
+            );
+
+            // in reverse order, with a duplicate
+            let mut decoder = BbqrDecoder::default();
+            decoder.receive(&parts[1]).unwrap();
+            for part in parts.iter().rev() {
+                assert!(matches!(decoder.psbt(), Err(QrError::Incomplete)));
+                decoder.receive(part).unwrap();
+            }
+            assert!(decoder.is_complete());
+            assert_eq!(decoder.psbt().unwrap(), psbt);
+        }
+
+        let mut decoder = BbqrDecoder::default();
+        decoder
+            .receive(&encode(&psbt, BbqrEncoding::Hex, 60).unwrap()[0])
+            .unwrap();
+        assert!(matches!(
+            decoder.receive(&encode(&psbt, BbqrEncoding::Base32, 60).unwrap()[0]),
+            Err(QrError::InconsistentPart)
+        ));
+        assert!(matches!(
+            decoder.receive("B$ZP0100"),
+            Err(QrError::Unsupported(_))
+        ));
+        assert!(matches!(
+            encode(&psbt, BbqrEncoding::Base32, 15),
+            Err(QrError::InvalidFormat(_))
+        ));
+    }
+}
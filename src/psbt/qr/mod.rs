@@ -0,0 +1,78 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Animated QR codes for PSBTs
+//!
+//! PSBTs are usually too large to fit in a single QR code, so air-gapped signers split them into
+//! a sequence of fragments that are displayed as an animated QR code. This module implements the
+//! two formats used by the most common devices:
+//!
+//! - [`ur`]: the [BC-UR] `crypto-psbt` format, used for instance by SeedSigner and Keystone.
+//!   Fragments are fountain-encoded, so the receiver can decode the PSBT from any large enough
+//!   subset of them.
+//! - [`bbqr`]: the [BBQr] format, used for instance by Coldcard Q. Every fragment has to be
+//!   scanned.
+//!
+//! The encoders produce the strings that should be shown as QR codes, and the decoders are fed
+//! the strings scanned by the camera until they are complete.
+//!
+//! ```
+//! # use bdk::*;
+//! # use bdk::psbt::qr::ur::{UrDecoder, UrEncoder};
+//! # let wallet = doctest_wallet!();
+//! let mut builder = wallet.build_tx();
+//! builder.drain_wallet().drain_to(wallet.get_address(wallet::AddressIndex::New)?.script_pubkey());
+//! let (psbt, _) = builder.finish()?;
+//!
+//! let mut encoder = UrEncoder::new(&psbt, 100);
+//! let mut decoder = UrDecoder::default();
+//! while !decoder.is_complete() {
+//!     decoder.receive(&encoder.next_part())?;
+//! }
+//! assert_eq!(decoder.psbt()?, psbt);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [BC-UR]: https://github.com/BlockchainCommons/Research/blob/master/papers/bcr-2020-005-ur.md
+//! [BBQr]: https://bbqr.org/BBQr.html
+
+use std::fmt;
+
+pub mod bbqr;
+pub mod ur;
+
+/// Errors related to the encoding and decoding of animated QR codes
+#[derive(Debug)]
+pub enum QrError {
+    /// The scanned string is not a valid fragment
+    InvalidFormat(String),
+    /// The fragment doesn't contain a PSBT, or uses an unsupported encoding
+    Unsupported(String),
+    /// The checksum of the fragment or of the decoded data is wrong
+    InvalidChecksum,
+    /// The fragment doesn't belong to the same PSBT as the ones received before
+    InconsistentPart,
+    /// The PSBT is too large to be split in the maximum number of fragments
+    TooManyParts,
+    /// Not enough fragments have been received yet
+    Incomplete,
+    /// The decoded data is not a valid PSBT
+    Psbt(crate::Error),
+}
+
+impl_error!(crate::Error, Psbt, QrError);
+
+impl fmt::Display for QrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for QrError {}
@@ -0,0 +1,667 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! BC-UR `crypto-psbt` encoding
+//!
+//! PSBTs are wrapped in a CBOR byte string and encoded as [uniform resources]. When they don't
+//! fit in a single fragment they are split with the [fountain encoding] of multi-part URs: the
+//! first fragments contain the message in order, and the following ones are XOR combinations of
+//! pseudo-randomly chosen fragments, which let the [`UrDecoder`] recover the missing ones.
+//!
+//! [uniform resources]: https://github.com/BlockchainCommons/Research/blob/master/papers/bcr-2020-005-ur.md
+//! [fountain encoding]: https://github.com/BlockchainCommons/Research/blob/master/papers/bcr-2024-001-multipart-ur.md
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::util::psbt::PartiallySignedTransaction as Psbt;
+
+use super::QrError;
+use crate::psbt::v2;
+
+/// UR type produced by the [`UrEncoder`]
+pub const UR_TYPE: &str = "crypto-psbt";
+// More recent type name of the same format, accepted by the decoder
+const UR_TYPE_PSBT: &str = "psbt";
+
+const MIN_FRAGMENT_LEN: usize = 10;
+
+const BYTEWORDS: &str = "ableacidalsoapexaquaarchatomauntawayaxisbackbaldbarnbeltbetabiasbluebodybragbrewbulbbuzzcalmcashcatschefcityclawcodecolacookcostcruxcurlcuspcyandarkdatadaysdelidicedietdoordowndrawdropdrumdulldutyeacheasyechoedgeepicevenexamexiteyesfactfairfernfigsfilmfishfizzflapflewfluxfoxyfreefrogfuelfundgalagamegeargemsgiftgirlglowgoodgraygrimgurugushgyrohalfhanghardhawkheathelphighhillholyhopehornhutsicedideaidleinchinkyintoirisironitemjadejazzjoinjoltjowljudojugsjumpjunkjurykeepkenokeptkeyskickkilnkingkitekiwiknoblamblavalazyleaflegsliarlimplionlistlogoloudloveluaulucklungmainmanymathmazememomenumeowmildmintmissmonknailnavyneednewsnextnoonnotenumbobeyoboeomitonyxopenovalowlspaidpartpeckplaypluspoempoolposepuffpumapurrquadquizraceramprealredorichroadrockroofrubyruinrunsrustsafesagascarsetssilkskewslotsoapsolosongstubsurfswantacotasktaxitenttiedtimetinytoiltombtoystriptunatwinuglyundouniturgeuservastveryvetovialvibeviewvisavoidvowswallwandwarmwaspwavewaxywebswhatwhenwhizwolfworkyankyawnyellyogayurtzapszerozestzinczonezoom";
+
+const CBOR_UNSIGNED: u8 = 0;
+const CBOR_BYTES: u8 = 2;
+const CBOR_ARRAY: u8 = 4;
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Encode `data` and its checksum as minimal bytewords (first and last letter of every word)
+fn encode_bytewords(data: &[u8]) -> String {
+    let words = BYTEWORDS.as_bytes();
+    data.iter()
+        .chain(crc32(data).to_be_bytes().iter())
+        .flat_map(|byte| {
+            let word = &words[*byte as usize * 4..*byte as usize * 4 + 4];
+            vec![word[0] as char, word[3] as char]
+        })
+        .collect()
+}
+
+fn decode_bytewords(s: &str) -> Result<Vec<u8>, QrError> {
+    let words = BYTEWORDS.as_bytes();
+    let s = s.as_bytes();
+    if s.len() % 2 != 0 || s.len() < 10 {
+        return Err(QrError::InvalidFormat("Invalid bytewords length".into()));
+    }
+
+    let mut data = s
+        .chunks(2)
+        .map(|pair| {
+            (0..256)
+                .find(|i| words[i * 4] == pair[0] && words[i * 4 + 3] == pair[1])
+                .map(|i| i as u8)
+                .ok_or_else(|| QrError::InvalidFormat("Invalid byteword".into()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let checksum = data.split_off(data.len() - 4);
+    if checksum[..] != crc32(&data).to_be_bytes()[..] {
+        return Err(QrError::InvalidChecksum);
+    }
+
+    Ok(data)
+}
+
+fn write_cbor_header(major: u8, value: u64, out: &mut Vec<u8>) {
+    let major = major << 5;
+    match value {
+        0..=23 => out.push(major | value as u8),
+        24..=0xFF => {
+            out.push(major | 24);
+            out.push(value as u8);
+        }
+        0x100..=0xFFFF => {
+            out.push(major | 25);
+            out.extend_from_slice(&(value as u16).to_be_bytes());
+        }
+        0x1_0000..=0xFFFF_FFFF => {
+            out.push(major | 26);
+            out.extend_from_slice(&(value as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+}
+
+fn read_cbor_header(data: &[u8], pos: &mut usize, major: u8) -> Result<u64, QrError> {
+    let invalid = || QrError::InvalidFormat("Invalid CBOR data".into());
+
+    let first = *data.get(*pos).ok_or_else(invalid)?;
+    *pos += 1;
+    if first >> 5 != major {
+        return Err(invalid());
+    }
+
+    let len = match first & 0x1F {
+        value @ 0..=23 => return Ok(value as u64),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        _ => return Err(invalid()),
+    };
+    let bytes = data.get(*pos..*pos + len).ok_or_else(invalid)?;
+    *pos += len;
+
+    Ok(bytes.iter().fold(0, |acc, b| (acc << 8) | *b as u64))
+}
+
+fn read_cbor_bytes<'a>(data: &'a [u8], pos: &mut usize) -> Result<&'a [u8], QrError> {
+    let len = read_cbor_header(data, pos, CBOR_BYTES)? as usize;
+    let bytes = data
+        .get(*pos..*pos + len)
+        .ok_or_else(|| QrError::InvalidFormat("Invalid CBOR data".into()))?;
+    *pos += len;
+
+    Ok(bytes)
+}
+
+// Xoshiro256** PRNG, seeded as described by the multi-part UR specification
+struct Xoshiro256 {
+    s: [u64; 4],
+}
+
+impl Xoshiro256 {
+    fn new(seed: &[u8]) -> Self {
+        let hash = sha256::Hash::hash(seed).into_inner();
+        let mut s = [0u64; 4];
+        for (i, chunk) in hash.chunks(8).enumerate() {
+            s[i] = chunk.iter().fold(0, |acc, b| (acc << 8) | *b as u64);
+        }
+
+        Xoshiro256 { s }
+    }
+
+    fn next(&mut self) -> u64 {
+        let result = self.s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = self.s[1] << 17;
+
+        self.s[2] ^= self.s[0];
+        self.s[3] ^= self.s[1];
+        self.s[1] ^= self.s[2];
+        self.s[0] ^= self.s[3];
+        self.s[2] ^= t;
+        self.s[3] = self.s[3].rotate_left(45);
+
+        result
+    }
+
+    fn next_double(&mut self) -> f64 {
+        self.next() as f64 / (u64::MAX as f64 + 1.0)
+    }
+
+    fn next_int(&mut self, low: u64, high: u64) -> u64 {
+        (self.next_double() * (high - low + 1) as f64) as u64 + low
+    }
+}
+
+// Walker-Vose alias method to sample from a discrete distribution
+struct RandomSampler {
+    probs: Vec<f64>,
+    aliases: Vec<usize>,
+}
+
+impl RandomSampler {
+    fn new(weights: &[f64]) -> Self {
+        let n = weights.len();
+        let sum: f64 = weights.iter().sum();
+        let mut p: Vec<f64> = weights.iter().map(|w| w * n as f64 / sum).collect();
+
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) =
+            (0..n).rev().partition(|j| p[*j] < 1.0);
+        let mut probs = vec![0.0; n];
+        let mut aliases = vec![0; n];
+
+        while !small.is_empty() && !large.is_empty() {
+            let a = small.pop().unwrap();
+            let g = large.pop().unwrap();
+            probs[a] = p[a];
+            aliases[a] = g;
+            p[g] += p[a] - 1.0;
+            if p[g] < 1.0 {
+                small.push(g);
+            } else {
+                large.push(g);
+            }
+        }
+        for i in large.into_iter().chain(small.into_iter()) {
+            probs[i] = 1.0;
+        }
+
+        RandomSampler { probs, aliases }
+    }
+
+    fn next(&self, rng: &mut Xoshiro256) -> usize {
+        let r1 = rng.next_double();
+        let r2 = rng.next_double();
+        let i = (self.probs.len() as f64 * r1) as usize;
+        if r2 < self.probs[i] {
+            i
+        } else {
+            self.aliases[i]
+        }
+    }
+}
+
+/// Return the indexes of the fragments combined in the part with sequence number `seq_num`
+fn choose_fragments(seq_num: u32, seq_len: usize, checksum: u32) -> BTreeSet<usize> {
+    if seq_num as usize <= seq_len {
+        return std::iter::once(seq_num as usize - 1).collect();
+    }
+
+    let mut seed = seq_num.to_be_bytes().to_vec();
+    seed.extend_from_slice(&checksum.to_be_bytes());
+    let mut rng = Xoshiro256::new(&seed);
+
+    let weights: Vec<f64> = (1..=seq_len).map(|i| 1.0 / i as f64).collect();
+    let degree = RandomSampler::new(&weights).next(&mut rng) + 1;
+
+    shuffle((0..seq_len).collect(), &mut rng)
+        .into_iter()
+        .take(degree)
+        .collect()
+}
+
+fn shuffle<T>(mut items: Vec<T>, rng: &mut Xoshiro256) -> Vec<T> {
+    let mut shuffled = Vec::with_capacity(items.len());
+    while !items.is_empty() {
+        let index = rng.next_int(0, items.len() as u64 - 1) as usize;
+        shuffled.push(items.remove(index));
+    }
+
+    shuffled
+}
+
+fn fragment_length(message_len: usize, max_fragment_len: usize) -> usize {
+    let max_fragment_count = std::cmp::max(1, message_len / MIN_FRAGMENT_LEN);
+    let mut fragment_len = 0;
+    for fragment_count in 1..=max_fragment_count {
+        fragment_len = (message_len + fragment_count - 1) / fragment_count;
+        if fragment_len <= max_fragment_len {
+            break;
+        }
+    }
+
+    fragment_len
+}
+
+fn xor_into(target: &mut [u8], other: &[u8]) {
+    for (t, o) in target.iter_mut().zip(other) {
+        *t ^= *o;
+    }
+}
+
+/// Encoder producing the parts of an animated `crypto-psbt` QR code
+///
+/// The parts returned by [`UrEncoder::next_part`] should be displayed in a loop: after the first
+/// [`UrEncoder::fragment_count`] parts the encoder keeps producing new fountain-encoded parts,
+/// which help the receiver recover the fragments it missed.
+#[derive(Debug, Clone)]
+pub struct UrEncoder {
+    message: Vec<u8>,
+    fragments: Vec<Vec<u8>>,
+    checksum: u32,
+    seq_num: u32,
+}
+
+impl UrEncoder {
+    /// Create an encoder for `psbt`, with at most `max_fragment_len` bytes of the PSBT in every
+    /// part
+    pub fn new(psbt: &Psbt, max_fragment_len: usize) -> Self {
+        let psbt = bitcoin::consensus::serialize(psbt);
+        let mut message = Vec::with_capacity(psbt.len() + 9);
+        write_cbor_header(CBOR_BYTES, psbt.len() as u64, &mut message);
+        message.extend(psbt);
+
+        let fragment_len = fragment_length(message.len(), max_fragment_len);
+        let fragments = message
+            .chunks(fragment_len)
+            .map(|chunk| {
+                let mut fragment = chunk.to_vec();
+                fragment.resize(fragment_len, 0);
+                fragment
+            })
+            .collect();
+
+        UrEncoder {
+            checksum: crc32(&message),
+            message,
+            fragments,
+            seq_num: 0,
+        }
+    }
+
+    /// Number of fragments the PSBT has been split into, which is the minimum number of parts the
+    /// receiver needs to scan
+    pub fn fragment_count(&self) -> usize {
+        self.fragments.len()
+    }
+
+    /// Return the next part to display
+    ///
+    /// If the PSBT fits in a single fragment, the same single-part UR is always returned.
+    pub fn next_part(&mut self) -> String {
+        if self.fragments.len() == 1 {
+            return format!("ur:{}/{}", UR_TYPE, encode_bytewords(&self.message));
+        }
+
+        self.seq_num += 1;
+        let seq_len = self.fragments.len();
+        let mut data = vec![0; self.fragments[0].len()];
+        for index in choose_fragments(self.seq_num, seq_len, self.checksum) {
+            xor_into(&mut data, &self.fragments[index]);
+        }
+
+        let mut part = Vec::with_capacity(data.len() + 32);
+        write_cbor_header(CBOR_ARRAY, 5, &mut part);
+        write_cbor_header(CBOR_UNSIGNED, self.seq_num as u64, &mut part);
+        write_cbor_header(CBOR_UNSIGNED, seq_len as u64, &mut part);
+        write_cbor_header(CBOR_UNSIGNED, self.message.len() as u64, &mut part);
+        write_cbor_header(CBOR_UNSIGNED, self.checksum as u64, &mut part);
+        write_cbor_header(CBOR_BYTES, data.len() as u64, &mut part);
+        part.extend(data);
+
+        format!(
+            "ur:{}/{}-{}/{}",
+            UR_TYPE,
+            self.seq_num,
+            seq_len,
+            encode_bytewords(&part)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct PartInfo {
+    seq_len: usize,
+    message_len: usize,
+    checksum: u32,
+    fragment_len: usize,
+}
+
+/// Decoder for animated `crypto-psbt` QR codes
+///
+/// Parts can be received in any order, and duplicated parts are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct UrDecoder {
+    info: Option<PartInfo>,
+    simple: BTreeMap<usize, Vec<u8>>,
+    mixed: Vec<(BTreeSet<usize>, Vec<u8>)>,
+    message: Option<Vec<u8>>,
+}
+
+impl UrDecoder {
+    /// Receive a scanned part. Both single-part and multi-part URs are accepted, case
+    /// insensitively.
+    pub fn receive(&mut self, part: &str) -> Result<(), QrError> {
+        if self.message.is_some() {
+            return Ok(());
+        }
+
+        let part = part.trim().to_lowercase();
+        let components = part
+            .strip_prefix("ur:")
+            .ok_or_else(|| QrError::InvalidFormat("Missing `ur:` prefix".into()))?
+            .split('/')
+            .collect::<Vec<_>>();
+        if components[0] != UR_TYPE && components[0] != UR_TYPE_PSBT {
+            return Err(QrError::Unsupported(format!(
+                "Unsupported UR type `{}`",
+                components[0]
+            )));
+        }
+
+        match components[1..] {
+            [body] => {
+                self.message = Some(decode_bytewords(body)?);
+                Ok(())
+            }
+            [sequence, body] => self.receive_fragment(sequence, body),
+            _ => Err(QrError::InvalidFormat("Invalid UR path".into())),
+        }
+    }
+
+    fn receive_fragment(&mut self, sequence: &str, body: &str) -> Result<(), QrError> {
+        let invalid = || QrError::InvalidFormat("Invalid multi-part UR sequence".into());
+
+        let mut sequence = sequence.splitn(2, '-').map(|s| s.parse::<usize>());
+        let (seq_num, seq_len) = match (sequence.next(), sequence.next()) {
+            (Some(Ok(seq_num)), Some(Ok(seq_len))) => (seq_num, seq_len),
+            _ => return Err(invalid()),
+        };
+
+        let part = decode_bytewords(body)?;
+        let mut pos = 0;
+        if read_cbor_header(&part, &mut pos, CBOR_ARRAY)? != 5 {
+            return Err(invalid());
+        }
+        let mut fields = [0u64; 4];
+        for field in fields.iter_mut() {
+            *field = read_cbor_header(&part, &mut pos, CBOR_UNSIGNED)?;
+        }
+        let data = read_cbor_bytes(&part, &mut pos)?.to_vec();
+        if fields[0] as usize != seq_num
+            || fields[1] as usize != seq_len
+            || seq_num == 0
+            || seq_num > u32::MAX as usize
+            || seq_len == 0
+            || fields[3] > u32::MAX as u64
+            || data.is_empty()
+        {
+            return Err(invalid());
+        }
+
+        let info = PartInfo {
+            seq_len,
+            message_len: fields[2] as usize,
+            checksum: fields[3] as u32,
+            fragment_len: data.len(),
+        };
+        if info.message_len > info.seq_len * info.fragment_len {
+            return Err(invalid());
+        }
+        match &self.info {
+            Some(current) if current != &info => return Err(QrError::InconsistentPart),
+            Some(_) => {}
+            None => self.info = Some(info.clone()),
+        }
+
+        let indexes = choose_fragments(seq_num as u32, seq_len, info.checksum);
+        self.process_part(indexes, data);
+
+        if self.simple.len() == seq_len {
+            let mut message = self.simple.values().flatten().copied().collect::<Vec<_>>();
+            message.truncate(info.message_len);
+            if crc32(&message) != info.checksum {
+                return Err(QrError::InvalidChecksum);
+            }
+            self.message = Some(message);
+        }
+
+        Ok(())
+    }
+
+    fn process_part(&mut self, indexes: BTreeSet<usize>, data: Vec<u8>) {
+        let mut queue = vec![(indexes, data)];
+        while let Some((mut indexes, mut data)) = queue.pop() {
+            // remove the fragments we already know from the part
+            for (index, fragment) in &self.simple {
+                if indexes.len() > 1 && indexes.remove(index) {
+                    xor_into(&mut data, fragment);
+                }
+            }
+
+            if indexes.len() == 1 {
+                let index = *indexes.iter().next().unwrap();
+                if self.simple.contains_key(&index) {
+                    continue;
+                }
+
+                // the new fragment may reduce the mixed parts
+                for (mixed_indexes, mixed_data) in self.mixed.drain(..) {
+                    queue.push((mixed_indexes, mixed_data));
+                }
+                self.simple.insert(index, data);
+            } else if !self.mixed.iter().any(|(i, _)| i == &indexes) {
+                self.mixed.push((indexes, data));
+            }
+        }
+    }
+
+    /// Whether enough parts have been received to decode the PSBT
+    pub fn is_complete(&self) -> bool {
+        self.message.is_some()
+    }
+
+    /// Estimated progress of the decoding, between `0.0` and `1.0`
+    pub fn progress(&self) -> f32 {
+        match (&self.message, &self.info) {
+            (Some(_), _) => 1.0,
+            (None, Some(info)) => self.simple.len() as f32 / info.seq_len as f32,
+            (None, None) => 0.0,
+        }
+    }
+
+    /// Return the decoded PSBT, or [`QrError::Incomplete`] if more parts are needed
+    ///
+    /// Both version 0 and version 2 PSBTs are accepted, see [`v2::deserialize`].
+    pub fn psbt(&self) -> Result<Psbt, QrError> {
+        let message = self.message.as_ref().ok_or(QrError::Incomplete)?;
+        let mut pos = 0;
+        let psbt = read_cbor_bytes(message, &mut pos)?;
+        if pos != message.len() {
+            return Err(QrError::InvalidFormat(
+                "Trailing data after the PSBT".into(),
+            ));
+        }
+
+        Ok(v2::deserialize(psbt)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::wallet::test::{get_funded_wallet, get_test_wpkh};
+
+    fn get_test_psbt() -> Psbt {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(
+                wallet
+                    .get_address(crate::wallet::AddressIndex::New)
+                    .unwrap()
+                    .script_pubkey(),
+                25_000,
+            )
+            .add_recipient(
+                wallet
+                    .get_address(crate::wallet::AddressIndex::New)
+                    .unwrap()
+                    .script_pubkey(),
+                10_000,
+            );
+        builder.finish().unwrap().0
+    }
+
+    #[test]
+    fn test_bytewords() {
+        let data = [0x00, 0x01, 0x02, 0x80, 0xFF];
+        assert_eq!(encode_bytewords(&data), "aeadaolazmjendeoti");
+        assert_eq!(decode_bytewords("aeadaolazmjendeoti").unwrap(), data);
+        assert!(matches!(
+            decode_bytewords("aeadaolazmjendeota"),
+            Err(QrError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            decode_bytewords("aeadaolazmjendeoto"),
+            Err(QrError::InvalidChecksum)
+        ));
+    }
+
+    #[test]
+    fn test_xoshiro256() {
+        let mut rng = Xoshiro256::new(b"Wolf");
+        let numbers = (0..10).map(|_| rng.next() % 100).collect::<Vec<_>>();
+        assert_eq!(numbers, vec![42, 81, 85, 8, 82, 84, 76, 73, 70, 88]);
+    }
+
+    #[test]
+    fn test_shuffle() {
+        let mut rng = Xoshiro256::new(b"Wolf");
+        let shuffled = shuffle((1..=10).collect(), &mut rng);
+        assert_eq!(shuffled, vec![6, 4, 9, 3, 10, 5, 7, 8, 1, 2]);
+    }
+
+    #[test]
+    fn test_random_sampler() {
+        let mut rng = Xoshiro256::new(b"Wolf");
+        let sampler = RandomSampler::new(&[1.0, 2.0, 4.0, 8.0]);
+        let samples = (0..15).map(|_| sampler.next(&mut rng)).collect::<Vec<_>>();
+        assert_eq!(samples, vec![3, 3, 3, 3, 3, 3, 3, 0, 2, 3, 3, 3, 3, 1, 2]);
+    }
+
+    #[test]
+    fn test_ur_single_part() {
+        let psbt = get_test_psbt();
+        let mut encoder = UrEncoder::new(&psbt, 10_000);
+        assert_eq!(encoder.fragment_count(), 1);
+
+        let part = encoder.next_part();
+        assert!(part.starts_with("ur:crypto-psbt/"));
+
+        let mut decoder = UrDecoder::default();
+        assert!(matches!(decoder.psbt(), Err(QrError::Incomplete)));
+        decoder.receive(&part.to_uppercase()).unwrap();
+        assert!(decoder.is_complete());
+        assert_eq!(decoder.psbt().unwrap(), psbt);
+    }
+
+    #[test]
+    fn test_ur_multi_part() {
+        let psbt = get_test_psbt();
+        let mut encoder = UrEncoder::new(&psbt, 30);
+        let parts = (0..encoder.fragment_count() * 3)
+            .map(|_| encoder.next_part())
+            .collect::<Vec<_>>();
+        assert!(parts[0].starts_with(&format!("ur:crypto-psbt/1-{}/", encoder.fragment_count())));
+
+        // in order
+        let mut decoder = UrDecoder::default();
+        for part in &parts[..encoder.fragment_count()] {
+            assert!(!decoder.is_complete());
+            decoder.receive(part).unwrap();
+        }
+        assert!(decoder.is_complete());
+        assert_eq!(decoder.psbt().unwrap(), psbt);
+
+        // skipping every other pure fragment, the fountain-encoded parts fill the gaps
+        let mut decoder = UrDecoder::default();
+        for part in parts.iter().skip(1).step_by(2) {
+            decoder.receive(part).unwrap();
+        }
+        for part in parts.iter().skip(encoder.fragment_count()) {
+            if decoder.is_complete() {
+                break;
+            }
+            decoder.receive(part).unwrap();
+        }
+        assert!(decoder.is_complete());
+        assert_eq!(decoder.psbt().unwrap(), psbt);
+    }
+
+    #[test]
+    fn test_ur_invalid() {
+        let psbt = get_test_psbt();
+        let mut decoder = UrDecoder::default();
+        assert!(matches!(
+            decoder.receive("ur:crypto-output/aeadaolazmjendeoti"),
+            Err(QrError::Unsupported(_))
+        ));
+        assert!(matches!(
+            decoder.receive("crypto-psbt/aeadaolazmjendeoti"),
+            Err(QrError::InvalidFormat(_))
+        ));
+
+        decoder
+            .receive(&UrEncoder::new(&psbt, 30).next_part())
+            .unwrap();
+        let other = UrEncoder::new(&psbt, 40).next_part();
+        assert!(matches!(
+            decoder.receive(&other),
+            Err(QrError::InconsistentPart)
+        ));
+    }
+}
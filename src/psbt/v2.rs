@@ -0,0 +1,474 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! PSBT version 2
+//!
+//! This module converts between the version 0 PSBTs used by the rest of the library and the
+//! version 2 serialization defined in [BIP-370], where the unsigned transaction is replaced by
+//! per-input and per-output fields.
+//!
+//! PSBTs are always handled as version 0 internally: to produce a version 2 PSBT [`serialize`]
+//! the one returned by the [`TxBuilder`](crate::TxBuilder), and to sign or finalize a version 2
+//! PSBT [`deserialize`] it first, and then [`serialize`] it again to send it back.
+//!
+//! ```
+//! # use bdk::*;
+//! # use bdk::psbt::v2;
+//! # let wallet = doctest_wallet!();
+//! let mut builder = wallet.build_tx();
+//! builder.drain_wallet().drain_to(wallet.get_address(wallet::AddressIndex::New)?.script_pubkey());
+//! let (psbt, _) = builder.finish()?;
+//!
+//! let psbt_v2 = v2::serialize(&psbt);
+//! // ... send it to a coordinator, which sends it back ...
+//! let mut psbt = v2::deserialize(&psbt_v2)?;
+//! wallet.sign(&mut psbt, SignOptions::default())?;
+//! # Ok::<(), bdk::Error>(())
+//! ```
+//!
+//! [BIP-370]: https://github.com/bitcoin/bips/blob/master/bip-0370.mediawiki
+
+use std::io::Cursor;
+
+use bitcoin::consensus::encode::{self, deserialize as consensus_deserialize, Decodable, VarInt};
+use bitcoin::consensus::{serialize as consensus_serialize, Encodable};
+use bitcoin::util::psbt::PartiallySignedTransaction as Psbt;
+use bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut, Txid};
+
+use crate::error::Error;
+
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
+
+const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
+const PSBT_GLOBAL_TX_VERSION: u8 = 0x02;
+const PSBT_GLOBAL_FALLBACK_LOCKTIME: u8 = 0x03;
+const PSBT_GLOBAL_INPUT_COUNT: u8 = 0x04;
+const PSBT_GLOBAL_OUTPUT_COUNT: u8 = 0x05;
+const PSBT_GLOBAL_TX_MODIFIABLE: u8 = 0x06;
+const PSBT_GLOBAL_VERSION: u8 = 0xFB;
+
+const PSBT_IN_PREVIOUS_TXID: u8 = 0x0e;
+const PSBT_IN_OUTPUT_INDEX: u8 = 0x0f;
+const PSBT_IN_SEQUENCE: u8 = 0x10;
+const PSBT_IN_REQUIRED_TIME_LOCKTIME: u8 = 0x11;
+const PSBT_IN_REQUIRED_HEIGHT_LOCKTIME: u8 = 0x12;
+
+const PSBT_OUT_AMOUNT: u8 = 0x03;
+const PSBT_OUT_SCRIPT: u8 = 0x04;
+
+// A key-value map of a PSBT, keys include their type
+type RawMap = Vec<(Vec<u8>, Vec<u8>)>;
+
+#[derive(Debug, Default)]
+struct RawPsbt {
+    global: RawMap,
+    inputs: Vec<RawMap>,
+    outputs: Vec<RawMap>,
+}
+
+fn parse_failed(msg: &'static str) -> Error {
+    Error::Encode(encode::Error::ParseFailed(msg))
+}
+
+fn get<'a>(map: &'a [(Vec<u8>, Vec<u8>)], key_type: u8) -> Option<&'a [u8]> {
+    map.iter()
+        .find(|(key, _)| key.as_slice() == [key_type])
+        .map(|(_, value)| value.as_slice())
+}
+
+fn decode<T: Decodable>(value: &[u8]) -> Result<T, Error> {
+    Ok(consensus_deserialize(value)?)
+}
+
+fn remove(map: &mut RawMap, key_types: &[u8]) {
+    map.retain(|(key, _)| !(key.len() == 1 && key_types.contains(&key[0])));
+}
+
+fn read_map(cursor: &mut Cursor<&[u8]>) -> Result<RawMap, Error> {
+    let mut map = RawMap::new();
+    loop {
+        let key = Vec::<u8>::consensus_decode(&mut *cursor)?;
+        if key.is_empty() {
+            return Ok(map);
+        }
+        if map.iter().any(|(k, _)| k == &key) {
+            return Err(parse_failed("Duplicated key in PSBT map"));
+        }
+        let value = Vec::<u8>::consensus_decode(&mut *cursor)?;
+        map.push((key, value));
+    }
+}
+
+fn write_map(map: &[(Vec<u8>, Vec<u8>)], buf: &mut Vec<u8>) {
+    for (key, value) in map {
+        key.consensus_encode(&mut *buf)
+            .expect("in-memory writers don't error");
+        value
+            .consensus_encode(&mut *buf)
+            .expect("in-memory writers don't error");
+    }
+    buf.push(0x00);
+}
+
+impl RawPsbt {
+    fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        if !bytes.starts_with(PSBT_MAGIC) {
+            return Err(parse_failed("Invalid PSBT magic bytes"));
+        }
+
+        let mut cursor = Cursor::new(&bytes[PSBT_MAGIC.len()..]);
+        let global = read_map(&mut cursor)?;
+
+        let (input_count, output_count) = match get(&global, PSBT_GLOBAL_UNSIGNED_TX) {
+            Some(tx) => {
+                let tx: Transaction = decode(tx)?;
+                (tx.input.len() as u64, tx.output.len() as u64)
+            }
+            None => (
+                decode::<VarInt>(
+                    get(&global, PSBT_GLOBAL_INPUT_COUNT)
+                        .ok_or_else(|| parse_failed("Missing PSBT input count"))?,
+                )?
+                .0,
+                decode::<VarInt>(
+                    get(&global, PSBT_GLOBAL_OUTPUT_COUNT)
+                        .ok_or_else(|| parse_failed("Missing PSBT output count"))?,
+                )?
+                .0,
+            ),
+        };
+
+        let inputs = (0..input_count)
+            .map(|_| read_map(&mut cursor))
+            .collect::<Result<Vec<_>, _>>()?;
+        let outputs = (0..output_count)
+            .map(|_| read_map(&mut cursor))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if cursor.position() as usize != cursor.get_ref().len() {
+            return Err(parse_failed("Trailing data after the PSBT"));
+        }
+
+        Ok(RawPsbt {
+            global,
+            inputs,
+            outputs,
+        })
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut buf = PSBT_MAGIC.to_vec();
+        write_map(&self.global, &mut buf);
+        for map in self.inputs.iter().chain(self.outputs.iter()) {
+            write_map(map, &mut buf);
+        }
+        buf
+    }
+
+    fn version(&self) -> Result<u32, Error> {
+        get(&self.global, PSBT_GLOBAL_VERSION)
+            .map(decode::<u32>)
+            .unwrap_or(Ok(0))
+    }
+}
+
+/// Serialize a PSBT as version 2
+pub fn serialize(psbt: &Psbt) -> Vec<u8> {
+    let mut raw =
+        RawPsbt::parse(&consensus_serialize(psbt)).expect("Valid PSBTs can always be parsed");
+    let tx = &psbt.global.unsigned_tx;
+
+    remove(
+        &mut raw.global,
+        &[PSBT_GLOBAL_UNSIGNED_TX, PSBT_GLOBAL_VERSION],
+    );
+    raw.global.extend(vec![
+        (
+            vec![PSBT_GLOBAL_TX_VERSION],
+            consensus_serialize(&tx.version),
+        ),
+        (
+            vec![PSBT_GLOBAL_FALLBACK_LOCKTIME],
+            consensus_serialize(&tx.lock_time),
+        ),
+        (
+            vec![PSBT_GLOBAL_INPUT_COUNT],
+            consensus_serialize(&VarInt(tx.input.len() as u64)),
+        ),
+        (
+            vec![PSBT_GLOBAL_OUTPUT_COUNT],
+            consensus_serialize(&VarInt(tx.output.len() as u64)),
+        ),
+        (vec![PSBT_GLOBAL_VERSION], consensus_serialize(&2u32)),
+    ]);
+
+    for (map, txin) in raw.inputs.iter_mut().zip(tx.input.iter()) {
+        map.extend(vec![
+            (
+                vec![PSBT_IN_PREVIOUS_TXID],
+                consensus_serialize(&txin.previous_output.txid),
+            ),
+            (
+                vec![PSBT_IN_OUTPUT_INDEX],
+                consensus_serialize(&txin.previous_output.vout),
+            ),
+            (vec![PSBT_IN_SEQUENCE], consensus_serialize(&txin.sequence)),
+        ]);
+    }
+    for (map, txout) in raw.outputs.iter_mut().zip(tx.output.iter()) {
+        map.extend(vec![
+            (vec![PSBT_OUT_AMOUNT], consensus_serialize(&txout.value)),
+            (vec![PSBT_OUT_SCRIPT], txout.script_pubkey.to_bytes()),
+        ]);
+    }
+
+    raw.serialize()
+}
+
+/// Deserialize a PSBT, converting it to version 0 if it's a version 2 PSBT
+///
+/// The nLockTime of the transaction is computed from the locktimes required by the inputs as
+/// described in BIP-370, or taken from the fallback locktime if no input requires one.
+pub fn deserialize(bytes: &[u8]) -> Result<Psbt, Error> {
+    let mut raw = RawPsbt::parse(bytes)?;
+    match raw.version()? {
+        0 => return Ok(consensus_deserialize(bytes)?),
+        2 => {}
+        _ => return Err(parse_failed("Unsupported PSBT version")),
+    }
+    if get(&raw.global, PSBT_GLOBAL_UNSIGNED_TX).is_some() {
+        return Err(parse_failed(
+            "PSBT version 2 can't contain an unsigned transaction",
+        ));
+    }
+
+    let version = decode(
+        get(&raw.global, PSBT_GLOBAL_TX_VERSION)
+            .ok_or_else(|| parse_failed("Missing PSBT transaction version"))?,
+    )?;
+    let fallback_locktime = get(&raw.global, PSBT_GLOBAL_FALLBACK_LOCKTIME)
+        .map(decode)
+        .transpose()?
+        .unwrap_or(0);
+
+    let input = raw
+        .inputs
+        .iter()
+        .map(|map| {
+            let txid: Txid = decode(
+                get(map, PSBT_IN_PREVIOUS_TXID)
+                    .ok_or_else(|| parse_failed("Missing PSBT input previous txid"))?,
+            )?;
+            let vout = decode(
+                get(map, PSBT_IN_OUTPUT_INDEX)
+                    .ok_or_else(|| parse_failed("Missing PSBT input output index"))?,
+            )?;
+            let sequence = get(map, PSBT_IN_SEQUENCE)
+                .map(decode)
+                .transpose()?
+                .unwrap_or(0xFFFFFFFF);
+
+            Ok(TxIn {
+                previous_output: OutPoint { txid, vout },
+                script_sig: Script::new(),
+                sequence,
+                witness: vec![],
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let output = raw
+        .outputs
+        .iter()
+        .map(|map| {
+            let value = decode(
+                get(map, PSBT_OUT_AMOUNT)
+                    .ok_or_else(|| parse_failed("Missing PSBT output amount"))?,
+            )?;
+            let script_pubkey = Script::from(
+                get(map, PSBT_OUT_SCRIPT)
+                    .ok_or_else(|| parse_failed("Missing PSBT output script"))?
+                    .to_vec(),
+            );
+
+            Ok(TxOut {
+                value,
+                script_pubkey,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let required_locktimes = raw
+        .inputs
+        .iter()
+        .map(|map| {
+            Ok((
+                get(map, PSBT_IN_REQUIRED_TIME_LOCKTIME)
+                    .map(decode::<u32>)
+                    .transpose()?,
+                get(map, PSBT_IN_REQUIRED_HEIGHT_LOCKTIME)
+                    .map(decode::<u32>)
+                    .transpose()?,
+            ))
+        })
+        .filter(|locktimes: &Result<_, Error>| !matches!(locktimes, Ok((None, None))))
+        .collect::<Result<Vec<_>, Error>>()?;
+    // prefer height-based locktimes if every input supports them
+    let lock_time = if required_locktimes.is_empty() {
+        fallback_locktime
+    } else if required_locktimes
+        .iter()
+        .all(|(_, height)| height.is_some())
+    {
+        required_locktimes
+            .iter()
+            .filter_map(|(_, height)| *height)
+            .max()
+            .unwrap_or(0)
+    } else if required_locktimes.iter().all(|(time, _)| time.is_some()) {
+        required_locktimes
+            .iter()
+            .filter_map(|(time, _)| *time)
+            .max()
+            .unwrap_or(0)
+    } else {
+        return Err(parse_failed(
+            "The PSBT inputs require incompatible locktime types",
+        ));
+    };
+
+    let tx = Transaction {
+        version,
+        lock_time,
+        input,
+        output,
+    };
+
+    remove(
+        &mut raw.global,
+        &[
+            PSBT_GLOBAL_TX_VERSION,
+            PSBT_GLOBAL_FALLBACK_LOCKTIME,
+            PSBT_GLOBAL_INPUT_COUNT,
+            PSBT_GLOBAL_OUTPUT_COUNT,
+            PSBT_GLOBAL_TX_MODIFIABLE,
+            PSBT_GLOBAL_VERSION,
+        ],
+    );
+    raw.global
+        .insert(0, (vec![PSBT_GLOBAL_UNSIGNED_TX], consensus_serialize(&tx)));
+    for map in raw.inputs.iter_mut() {
+        remove(
+            map,
+            &[
+                PSBT_IN_PREVIOUS_TXID,
+                PSBT_IN_OUTPUT_INDEX,
+                PSBT_IN_SEQUENCE,
+                PSBT_IN_REQUIRED_TIME_LOCKTIME,
+                PSBT_IN_REQUIRED_HEIGHT_LOCKTIME,
+            ],
+        );
+    }
+    for map in raw.outputs.iter_mut() {
+        remove(map, &[PSBT_OUT_AMOUNT, PSBT_OUT_SCRIPT]);
+    }
+
+    Ok(consensus_deserialize(&raw.serialize())?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::consensus::serialize as consensus_serialize;
+
+    use super::*;
+    use crate::wallet::test::{get_funded_wallet, get_test_wpkh};
+    use crate::wallet::AddressIndex;
+
+    #[test]
+    fn test_psbt_v2_roundtrip() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = bitcoin::Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .nlocktime(42)
+            .enable_rbf();
+        let (psbt, _) = builder.finish().unwrap();
+
+        let psbt_v2 = serialize(&psbt);
+        let raw = RawPsbt::parse(&psbt_v2).unwrap();
+        assert_eq!(raw.version().unwrap(), 2);
+        assert!(get(&raw.global, PSBT_GLOBAL_UNSIGNED_TX).is_none());
+        assert_eq!(raw.inputs.len(), psbt.inputs.len());
+        assert_eq!(raw.outputs.len(), psbt.outputs.len());
+
+        let decoded = deserialize(&psbt_v2).unwrap();
+        assert_eq!(decoded, psbt);
+        assert_eq!(decoded.global.unsigned_tx.lock_time, 42);
+
+        // version 0 PSBTs are parsed as they are
+        assert_eq!(deserialize(&consensus_serialize(&psbt)).unwrap(), psbt);
+    }
+
+    #[test]
+    fn test_psbt_v2_sign() {
+        let (wallet, _, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+        let addr = wallet.get_address(AddressIndex::New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.drain_wallet().drain_to(addr.script_pubkey());
+        let (psbt, _) = builder.finish().unwrap();
+
+        let mut psbt = deserialize(&serialize(&psbt)).unwrap();
+        let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
+        assert!(finalized);
+    }
+
+    #[test]
+    fn test_psbt_v2_required_locktime() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_address(AddressIndex::New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.drain_wallet().drain_to(addr.script_pubkey());
+        let (psbt, _) = builder.finish().unwrap();
+
+        let mut raw = RawPsbt::parse(&serialize(&psbt)).unwrap();
+        raw.inputs[0].push((
+            vec![PSBT_IN_REQUIRED_HEIGHT_LOCKTIME],
+            consensus_serialize(&100u32),
+        ));
+        let decoded = deserialize(&raw.serialize()).unwrap();
+        assert_eq!(decoded.global.unsigned_tx.lock_time, 100);
+
+        // only a time-based locktime is supported by this input now
+        raw.inputs[0].retain(|(key, _)| key != &[PSBT_IN_REQUIRED_HEIGHT_LOCKTIME]);
+        raw.inputs[0].push((
+            vec![PSBT_IN_REQUIRED_TIME_LOCKTIME],
+            consensus_serialize(&500_000_042u32),
+        ));
+        let decoded = deserialize(&raw.serialize()).unwrap();
+        assert_eq!(decoded.global.unsigned_tx.lock_time, 500_000_042);
+    }
+
+    #[test]
+    fn test_psbt_v2_invalid() {
+        assert!(deserialize(b"not a psbt").is_err());
+
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_address(AddressIndex::New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.drain_wallet().drain_to(addr.script_pubkey());
+        let (psbt, _) = builder.finish().unwrap();
+
+        let mut raw = RawPsbt::parse(&serialize(&psbt)).unwrap();
+        raw.outputs[0].retain(|(key, _)| key != &[PSBT_OUT_SCRIPT]);
+        assert!(deserialize(&raw.serialize()).is_err());
+    }
+}
@@ -526,10 +526,14 @@ macro_rules! bdk_blockchain_tests {
                 wallet.sync(noop_progress(), None).unwrap();
 
                 assert_eq!(wallet.get_balance().unwrap(), 50_000, "incorrect balance after bump");
-                assert_eq!(wallet.list_transactions(false).unwrap().len(), 1, "incorrect number of txs after bump");
+                assert_eq!(wallet.list_transactions(false).unwrap().len(), 2, "incorrect number of txs after bump");
                 assert_eq!(wallet.list_unspent().unwrap().len(), 1, "incorrect unspent after bump");
 
-                let list_tx_item = &wallet.list_transactions(false).unwrap()[0];
+                let (evicted, list_tx_items): (Vec<_>, Vec<_>) = wallet.list_transactions(false).unwrap().into_iter().partition(|tx| tx.last_evicted.is_some());
+                assert_eq!(evicted.len(), 1, "incorrect number of evicted txs after bump");
+                assert_eq!(evicted[0].txid, txid, "incorrect evicted txid after bump");
+
+                let list_tx_item = &list_tx_items[0];
                 assert_eq!(list_tx_item.txid, new_txid, "incorrect txid after bump");
                 assert_eq!(list_tx_item.received, 50_000, "incorrect received after bump");
                 assert_eq!(list_tx_item.sent, 0, "incorrect sent after bump");
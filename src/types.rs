@@ -9,6 +9,7 @@
 // You may not use this file except in accordance with one or both of these
 // licenses.
 
+use std::collections::BTreeMap;
 use std::convert::AsRef;
 
 use bitcoin::blockdata::transaction::{OutPoint, Transaction, TxOut};
@@ -177,6 +178,12 @@ pub struct TransactionDetails {
     /// The check is only perfomed when the `verify` feature is enabled.
     #[serde(default = "bool::default")] // default to `false` if not specified
     pub verified: bool,
+    /// Unix timestamp of the last time the transaction was reported as no longer in the mempool
+    ///
+    /// Evicted transactions are kept in the database, but their outputs are not counted in the
+    /// balance of the wallet and can't be spent. This is cleared if the transaction is seen again.
+    #[serde(default)]
+    pub last_evicted: Option<u64>,
 }
 
 /// Block height and timestamp of the block containing the confirmed transaction
@@ -198,6 +205,154 @@ impl ConfirmationTime {
     }
 }
 
+/// Balance of a [`Wallet`](crate::Wallet), split by spendability
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Balance {
+    /// Value of the coinbase outputs that haven't reached maturity yet, which can't be spent
+    pub immature: u64,
+    /// Value of the confirmed outputs
+    pub confirmed: u64,
+    /// Value of the unconfirmed outputs
+    pub unconfirmed: u64,
+}
+
+impl Balance {
+    /// Value that can currently be spent, meaning every output except the immature coinbases
+    pub fn spendable(&self) -> u64 {
+        self.confirmed + self.unconfirmed
+    }
+
+    /// Total value of the unspent outputs, including the immature ones
+    pub fn total(&self) -> u64 {
+        self.immature + self.confirmed + self.unconfirmed
+    }
+}
+
+/// Transactions of a [`Wallet`](crate::Wallet) that spend the same output
+///
+/// Only one of them can be confirmed: the others have been, or will be, replaced.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TxConflict {
+    /// Output spent by all the transactions
+    pub outpoint: OutPoint,
+    /// Ids of the conflicting transactions, sorted
+    pub txids: Vec<Txid>,
+    /// Transaction that is currently valid, if known
+    ///
+    /// This is the confirmed transaction, if any, or else the only one that hasn't been evicted
+    /// from the mempool. It's `None` if the transactions haven't been confirmed or evicted yet, for
+    /// example because the wallet hasn't been synced after broadcasting a replacement.
+    pub canonical: Option<Txid>,
+}
+
+/// Set of unconfirmed transactions of a [`Wallet`](crate::Wallet) that are relayed and mined
+/// together, such as a transaction and its unconfirmed ancestors
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct TxPackage {
+    /// Ids of the transactions in the package
+    pub txids: Vec<Txid>,
+    /// Sum of the fees of the transactions, or `None` if the fee or the raw transaction of any of
+    /// them is unknown
+    pub fee: Option<u64>,
+    /// Sum of the weights of the transactions
+    pub weight: usize,
+}
+
+impl TxPackage {
+    pub(crate) fn add(&mut self, details: &TransactionDetails) {
+        self.txids.push(details.txid);
+        match (&details.transaction, details.fee) {
+            (Some(tx), Some(fee)) => {
+                self.fee = self.fee.map(|total| total + fee);
+                self.weight += tx.get_weight();
+            }
+            _ => self.fee = None,
+        }
+    }
+
+    /// Virtual size of the package
+    pub fn vsize(&self) -> f32 {
+        self.weight as f32 / 4.0
+    }
+
+    /// Fee rate of the whole package, if its fee is known
+    pub fn fee_rate(&self) -> Option<FeeRate> {
+        match (self.fee, self.weight) {
+            (Some(_), 0) => Some(FeeRate::from_sat_per_vb(0.0)),
+            (Some(fee), _) => Some(FeeRate::from_sat_per_vb(fee as f32 / self.vsize())),
+            (None, _) => None,
+        }
+    }
+}
+
+/// Transaction that spends from or sends to a script of the watchlist of a
+/// [`Wallet`](crate::Wallet)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WatchedTx {
+    /// Transaction id
+    pub txid: Txid,
+    /// Height of the block the transaction is confirmed in, or `None` if it's unconfirmed
+    pub height: Option<u32>,
+}
+
+/// Fee rates required to confirm a transaction within a number of blocks, as estimated by a
+/// [`FeeEstimator`](crate::blockchain::FeeEstimator)
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FeeEstimates {
+    estimates: BTreeMap<usize, FeeRate>,
+    timestamp: u64,
+}
+
+impl FeeEstimates {
+    /// Create an empty set of estimates, made at `timestamp`
+    pub fn new(timestamp: u64) -> Self {
+        FeeEstimates {
+            estimates: BTreeMap::new(),
+            timestamp,
+        }
+    }
+
+    /// Set the estimated fee rate for a confirmation `target`, in blocks
+    pub fn insert(&mut self, target: usize, fee_rate: FeeRate) {
+        self.estimates.insert(target, fee_rate);
+    }
+
+    /// Return the fee rate to confirm a transaction within `target` blocks
+    ///
+    /// If there's no estimate for exactly `target`, the one of the highest target below it is
+    /// used, or the one of the lowest target if `target` is below all of them. Returns `None` if
+    /// there are no estimates at all.
+    pub fn get(&self, target: usize) -> Option<FeeRate> {
+        self.estimates
+            .range(..=target)
+            .next_back()
+            .or_else(|| self.estimates.iter().next())
+            .map(|(_, fee_rate)| *fee_rate)
+    }
+
+    /// Iterate over the targets and their estimated fee rate, sorted by target
+    pub fn iter(&self) -> impl Iterator<Item = (usize, FeeRate)> + '_ {
+        self.estimates
+            .iter()
+            .map(|(target, fee_rate)| (*target, *fee_rate))
+    }
+
+    /// Return whether there are no estimates
+    pub fn is_empty(&self) -> bool {
+        self.estimates.is_empty()
+    }
+
+    /// Return the timestamp of the estimates, in seconds
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// Return whether the estimates were made more than `max_age` seconds before `now`
+    pub fn is_stale(&self, now: u64, max_age: u64) -> bool {
+        now.saturating_sub(self.timestamp) > max_age
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -207,4 +362,30 @@ mod tests {
         const _MY_RATE: FeeRate = FeeRate::from_sat_per_vb(10.0);
         const _MIN_RELAY: FeeRate = FeeRate::default_min_relay_fee();
     }
+
+    #[test]
+    fn test_fee_estimates() {
+        let mut estimates = FeeEstimates::new(1_600_000_000);
+        assert_eq!(estimates.get(6), None);
+
+        estimates.insert(2, FeeRate::from_sat_per_vb(20.0));
+        estimates.insert(6, FeeRate::from_sat_per_vb(10.0));
+        estimates.insert(144, FeeRate::from_sat_per_vb(2.0));
+
+        assert_eq!(estimates.get(6), Some(FeeRate::from_sat_per_vb(10.0)));
+        assert_eq!(estimates.get(12), Some(FeeRate::from_sat_per_vb(10.0)));
+        assert_eq!(estimates.get(1), Some(FeeRate::from_sat_per_vb(20.0)));
+        assert_eq!(estimates.get(1008), Some(FeeRate::from_sat_per_vb(2.0)));
+        assert_eq!(
+            estimates
+                .iter()
+                .map(|(target, _)| target)
+                .collect::<Vec<_>>(),
+            vec![2, 6, 144]
+        );
+        assert_eq!(estimates.timestamp(), 1_600_000_000);
+        assert!(!estimates.is_stale(1_600_000_060, 60));
+        assert!(estimates.is_stale(1_600_000_061, 60));
+        assert!(!estimates.is_stale(1_500_000_000, 60));
+    }
 }
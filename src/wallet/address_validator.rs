@@ -22,7 +22,8 @@
 //! whenever a new address is generated (either explicitly by the user with
 //! [`Wallet::get_address`](super::Wallet::get_address) or internally to create a change
 //! address) all the attached validators will be polled, in sequence. All of them must complete
-//! successfully to continue.
+//! successfully to continue. The validators can also be polled again for an address that has
+//! already been generated with [`Wallet::verify_address`](super::Wallet::verify_address).
 //!
 //! ## Example
 //!
@@ -116,7 +117,7 @@ mod test {
 
     use super::*;
     use crate::wallet::test::{get_funded_wallet, get_test_wpkh};
-    use crate::wallet::AddressIndex::New;
+    use crate::wallet::AddressIndex::{New, Peek};
 
     #[derive(Debug)]
     struct TestValidator;
@@ -140,6 +141,48 @@ mod test {
         wallet.get_address(New).unwrap();
     }
 
+    #[derive(Debug, Default)]
+    struct RecordingValidator(std::sync::Mutex<Vec<(KeychainKind, bitcoin::Script)>>);
+    impl AddressValidator for RecordingValidator {
+        fn validate(
+            &self,
+            keychain: KeychainKind,
+            _hd_keypaths: &HdKeyPaths,
+            script: &bitcoin::Script,
+        ) -> Result<(), AddressValidatorError> {
+            self.0.lock().unwrap().push((keychain, script.clone()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_verify_address() {
+        let (mut wallet, _, _) = get_funded_wallet("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)");
+        let validator = Arc::new(RecordingValidator::default());
+        wallet.add_address_validator(validator.clone());
+
+        wallet.verify_address(KeychainKind::External, 5).unwrap();
+        // without a change descriptor the external one is used
+        wallet.verify_address(KeychainKind::Internal, 1).unwrap();
+
+        assert_eq!(
+            *validator.0.lock().unwrap(),
+            vec![
+                (
+                    KeychainKind::External,
+                    wallet.get_address(Peek(5)).unwrap().script_pubkey()
+                ),
+                (
+                    KeychainKind::External,
+                    wallet.get_address(Peek(1)).unwrap().script_pubkey()
+                ),
+            ]
+        );
+
+        wallet.add_address_validator(Arc::new(TestValidator));
+        assert!(wallet.verify_address(KeychainKind::External, 0).is_err());
+    }
+
     #[test]
     #[should_panic(expected = "InvalidScript")]
     fn test_address_validator_internal() {
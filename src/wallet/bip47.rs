@@ -0,0 +1,712 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Reusable payment codes
+//!
+//! This module implements version 1 of [BIP-47](https://github.com/bitcoin/bips/blob/master/bip-0047.mediawiki),
+//! which lets a wallet publish a [`PaymentCode`] that other wallets can pay to without reusing
+//! addresses.
+//!
+//! 1. Alice sends a notification transaction to the notification address of Bob, built with
+//!    [`TxBuilder::add_bip47_notification`](crate::wallet::tx_builder::TxBuilder::add_bip47_notification).
+//!    It contains Alice's payment code, blinded so that only Bob can read it.
+//! 2. Bob finds the notification with [`Bip47Account::process_notification`], and learns
+//!    Alice's payment code.
+//! 3. Alice pays Bob to the scripts returned by [`Bip47Account::send_script`], and Bob watches the
+//!    scripts returned by [`Bip47Account::receive_script`] and spends them with the keys returned
+//!    by [`Bip47Account::receive_key`].
+//!
+//! The scripts derived from payment codes don't belong to the descriptors of the wallet, so they
+//! are not synced automatically: the keys returned by [`Bip47Account::receive_key`] can be added to
+//! a wallet of their own, or as signers with [`Wallet::add_signer`](crate::Wallet::add_signer).
+//!
+//! ## Example
+//!
+//! ```
+//! # use std::str::FromStr;
+//! # use bitcoin::secp256k1::Secp256k1;
+//! # use bitcoin::util::bip32::ExtendedPrivKey;
+//! # use bdk::wallet::bip47::*;
+//! let secp = Secp256k1::new();
+//! let master = ExtendedPrivKey::from_str("xprv9s21ZrQH143K2Ajn1HM67qGeNCp8GXztNyEnc9ebYQ3ipQ5hRiWgzVtmHVYpRvWCFF65j45uj6yAWPrJWVsVgtCJLtWAAvrQB6F8T9xK4C2")?;
+//! let alice = Bip47Account::new(&master, 0, &secp)?;
+//!
+//! let bob = PaymentCode::from_str("PM8TJS2JxQ5ztXUpBBRnpTbcUXbUHy2T1abfrb3KkAAtMEGNbey4oumH7Hc578WgQJhPjBxteQ5GHHToTYHE3A1w6p7tU6KSoFmWBVbFGjKPisZDbP97")?;
+//! assert_eq!(bob.notification_address(bitcoin::Network::Bitcoin, &secp)?.to_string(), "1ChvUUvht2hUQufHBXF8NgLhW8SwE2ecGV");
+//!
+//! // Once Bob has been notified, the first payment goes to this script
+//! let script = alice.send_script(&bob, 0, &secp)?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::blockdata::opcodes::all::OP_RETURN;
+use bitcoin::blockdata::script::{Builder, Instruction};
+use bitcoin::consensus::encode::serialize;
+use bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use bitcoin::hashes::{sha256, sha512, Hash, HashEngine};
+use bitcoin::secp256k1::{self, SecretKey};
+use bitcoin::util::base58;
+use bitcoin::util::bip32::{
+    self, ChainCode, ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey, Fingerprint,
+};
+use bitcoin::util::psbt::PartiallySignedTransaction as Psbt;
+use bitcoin::{Address, Network, OutPoint, PrivateKey, PublicKey, Script, Transaction, TxIn};
+
+use crate::database::BatchDatabase;
+use crate::wallet::utils::SecpCtx;
+use crate::wallet::Wallet;
+use crate::Error;
+
+/// Version of the payment codes supported by this module
+pub const PAYMENT_CODE_VERSION: u8 = 0x01;
+/// Amount sent to the notification address of the recipient in a notification transaction
+pub const NOTIFICATION_AMOUNT: u64 = 546;
+
+const PAYMENT_CODE_PREFIX: u8 = 0x47;
+const PAYMENT_CODE_LEN: usize = 80;
+const BIP47_PURPOSE: u32 = 47;
+
+/// Errors related to BIP-47 payment codes
+#[derive(Debug)]
+pub enum Bip47Error {
+    /// The payment code is malformed or has an unsupported version
+    InvalidPaymentCode(String),
+    /// The first input of the notification transaction doesn't expose its public key, or its
+    /// private key is not available
+    MissingDesignatedKey,
+    /// The secret shared with the other payment code is not a valid private key, a different
+    /// index has to be used
+    InvalidSharedSecret,
+
+    /// BIP32 error
+    Bip32(bip32::Error),
+    /// Secp256k1 error
+    Secp256k1(secp256k1::Error),
+}
+
+impl_error!(bip32::Error, Bip32, Bip47Error);
+impl_error!(secp256k1::Error, Secp256k1, Bip47Error);
+
+impl fmt::Display for Bip47Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for Bip47Error {}
+
+/// A version 1 payment code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PaymentCode {
+    public_key: secp256k1::PublicKey,
+    chain_code: [u8; 32],
+}
+
+impl PaymentCode {
+    /// Parse a payment code from its 80 bytes binary serialization
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Bip47Error> {
+        if bytes.len() != PAYMENT_CODE_LEN {
+            return Err(Bip47Error::InvalidPaymentCode(format!(
+                "Invalid length {}",
+                bytes.len()
+            )));
+        }
+        if bytes[0] != PAYMENT_CODE_VERSION {
+            return Err(Bip47Error::InvalidPaymentCode(format!(
+                "Unsupported version {}",
+                bytes[0]
+            )));
+        }
+
+        let public_key = secp256k1::PublicKey::from_slice(&bytes[2..35])
+            .map_err(|e| Bip47Error::InvalidPaymentCode(e.to_string()))?;
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&bytes[35..67]);
+
+        Ok(PaymentCode {
+            public_key,
+            chain_code,
+        })
+    }
+
+    /// Serialize the payment code to its 80 bytes binary form
+    pub fn to_bytes(&self) -> [u8; PAYMENT_CODE_LEN] {
+        let mut bytes = [0u8; PAYMENT_CODE_LEN];
+        bytes[0] = PAYMENT_CODE_VERSION;
+        bytes[2..35].copy_from_slice(&self.public_key.serialize());
+        bytes[35..67].copy_from_slice(&self.chain_code);
+        bytes
+    }
+
+    /// Return the public key of the payment code
+    pub fn public_key(&self) -> secp256k1::PublicKey {
+        self.public_key
+    }
+
+    fn xpub(&self) -> ExtendedPubKey {
+        ExtendedPubKey {
+            network: Network::Bitcoin,
+            depth: 3,
+            parent_fingerprint: Fingerprint::default(),
+            child_number: ChildNumber::Hardened { index: 0 },
+            public_key: PublicKey {
+                compressed: true,
+                key: self.public_key,
+            },
+            chain_code: ChainCode::from(&self.chain_code[..]),
+        }
+    }
+
+    /// Derive the public key at `index`
+    pub fn derive_public_key(
+        &self,
+        index: u32,
+        secp: &SecpCtx,
+    ) -> Result<secp256k1::PublicKey, Bip47Error> {
+        let child = self
+            .xpub()
+            .ckd_pub(secp, ChildNumber::from_normal_idx(index)?)?;
+        Ok(child.public_key.key)
+    }
+
+    /// Return the address that receives the notification transactions for this payment code
+    pub fn notification_address(
+        &self,
+        network: Network,
+        secp: &SecpCtx,
+    ) -> Result<Address, Bip47Error> {
+        Ok(p2pkh_address(self.derive_public_key(0, secp)?, network))
+    }
+}
+
+impl fmt::Display for PaymentCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut data = vec![PAYMENT_CODE_PREFIX];
+        data.extend_from_slice(&self.to_bytes());
+        write!(f, "{}", base58::check_encode_slice(&data))
+    }
+}
+
+impl FromStr for PaymentCode {
+    type Err = Bip47Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let data =
+            base58::from_check(s).map_err(|e| Bip47Error::InvalidPaymentCode(e.to_string()))?;
+        match data.split_first() {
+            Some((&PAYMENT_CODE_PREFIX, bytes)) => PaymentCode::from_bytes(bytes),
+            _ => Err(Bip47Error::InvalidPaymentCode("Invalid prefix".to_string())),
+        }
+    }
+}
+
+fn p2pkh_address(key: secp256k1::PublicKey, network: Network) -> Address {
+    Address::p2pkh(
+        &PublicKey {
+            compressed: true,
+            key,
+        },
+        network,
+    )
+}
+
+// x coordinate of `secret * point`
+fn shared_secret(
+    secret: &SecretKey,
+    point: &secp256k1::PublicKey,
+    secp: &SecpCtx,
+) -> Result<[u8; 32], Bip47Error> {
+    let mut shared = *point;
+    shared.mul_assign(secp, &secret[..])?;
+
+    let mut x = [0u8; 32];
+    x.copy_from_slice(&shared.serialize()[1..]);
+    Ok(x)
+}
+
+// Tweak applied to the keys of the `index`-th payment between two payment codes
+fn payment_tweak(
+    secret: &SecretKey,
+    point: &secp256k1::PublicKey,
+    secp: &SecpCtx,
+) -> Result<SecretKey, Bip47Error> {
+    let hash = sha256::Hash::hash(&shared_secret(secret, point, secp)?);
+    SecretKey::from_slice(&hash[..]).map_err(|_| Bip47Error::InvalidSharedSecret)
+}
+
+// XOR the public key and chain code of a serialized payment code with the mask derived from the
+// designated input
+fn blind(
+    payload: &mut [u8; PAYMENT_CODE_LEN],
+    secret: &SecretKey,
+    point: &secp256k1::PublicKey,
+    outpoint: &OutPoint,
+    secp: &SecpCtx,
+) -> Result<(), Bip47Error> {
+    let mut engine = HmacEngine::<sha512::Hash>::new(&serialize(outpoint));
+    engine.input(&shared_secret(secret, point, secp)?);
+    let mask = Hmac::from_engine(engine).into_inner();
+
+    for (byte, mask) in payload[3..67].iter_mut().zip(mask.iter()) {
+        *byte ^= mask;
+    }
+    Ok(())
+}
+
+// The public key revealed by the scriptSig or witness of a P2PKH, P2WPKH or P2SH-P2WPKH input
+fn exposed_public_key(input: &TxIn) -> Option<secp256k1::PublicKey> {
+    if let Some(key) = input.witness.last() {
+        return secp256k1::PublicKey::from_slice(key).ok();
+    }
+
+    input
+        .script_sig
+        .instructions()
+        .filter_map(|instruction| match instruction {
+            Ok(Instruction::PushBytes(bytes)) => secp256k1::PublicKey::from_slice(bytes).ok(),
+            _ => None,
+        })
+        .last()
+}
+
+// Placeholder for the blinded payment code, replaced once the designated input is known
+pub(crate) fn notification_placeholder() -> Script {
+    Builder::new()
+        .push_opcode(OP_RETURN)
+        .push_slice(&[0u8; PAYMENT_CODE_LEN])
+        .into_script()
+}
+
+/// The BIP-47 account of a wallet, with its payment code and the private keys required to send and
+/// receive payments
+#[derive(Clone)]
+pub struct Bip47Account {
+    xprv: ExtendedPrivKey,
+    payment_code: PaymentCode,
+}
+
+impl Bip47Account {
+    /// Derive the account `m/47'/coin_type'/account'` from the master key of a wallet
+    pub fn new(master: &ExtendedPrivKey, account: u32, secp: &SecpCtx) -> Result<Self, Bip47Error> {
+        let coin_type = match master.network {
+            Network::Bitcoin => 0,
+            _ => 1,
+        };
+        let path: DerivationPath = vec![
+            ChildNumber::from_hardened_idx(BIP47_PURPOSE)?,
+            ChildNumber::from_hardened_idx(coin_type)?,
+            ChildNumber::from_hardened_idx(account)?,
+        ]
+        .into();
+        let xprv = master.derive_priv(secp, &path)?;
+
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&xprv.chain_code[..]);
+        let payment_code = PaymentCode {
+            public_key: secp256k1::PublicKey::from_secret_key(secp, &xprv.private_key.key),
+            chain_code,
+        };
+
+        Ok(Bip47Account { xprv, payment_code })
+    }
+
+    /// Return the payment code of the account
+    pub fn payment_code(&self) -> &PaymentCode {
+        &self.payment_code
+    }
+
+    /// Return the address that receives the notification transactions for this account
+    pub fn notification_address(&self, secp: &SecpCtx) -> Result<Address, Bip47Error> {
+        self.payment_code
+            .notification_address(self.xprv.network, secp)
+    }
+
+    fn derive_secret_key(&self, index: u32, secp: &SecpCtx) -> Result<SecretKey, Bip47Error> {
+        let child = self
+            .xprv
+            .ckd_priv(secp, ChildNumber::from_normal_idx(index)?)?;
+        Ok(child.private_key.key)
+    }
+
+    /// Return the payment code of the sender if `tx` is a notification transaction for this
+    /// account
+    pub fn process_notification(&self, tx: &Transaction, secp: &SecpCtx) -> Option<PaymentCode> {
+        let notification_script = self.notification_address(secp).ok()?.script_pubkey();
+        if !tx
+            .output
+            .iter()
+            .any(|output| output.script_pubkey == notification_script)
+        {
+            return None;
+        }
+
+        let mut payload = tx.output.iter().find_map(|output| {
+            let mut instructions = output.script_pubkey.instructions();
+            match (
+                instructions.next(),
+                instructions.next(),
+                instructions.next(),
+            ) {
+                (
+                    Some(Ok(Instruction::Op(OP_RETURN))),
+                    Some(Ok(Instruction::PushBytes(bytes))),
+                    None,
+                ) if bytes.len() == PAYMENT_CODE_LEN => {
+                    let mut payload = [0u8; PAYMENT_CODE_LEN];
+                    payload.copy_from_slice(bytes);
+                    Some(payload)
+                }
+                _ => None,
+            }
+        })?;
+
+        // The designated input is the first one that exposes its public key
+        let (designated_key, outpoint) = tx
+            .input
+            .iter()
+            .find_map(|input| exposed_public_key(input).map(|key| (key, input.previous_output)))?;
+
+        let notification_key = self.derive_secret_key(0, secp).ok()?;
+        blind(
+            &mut payload,
+            &notification_key,
+            &designated_key,
+            &outpoint,
+            secp,
+        )
+        .ok()?;
+
+        PaymentCode::from_bytes(&payload).ok()
+    }
+
+    /// Build the blinded payment code of this account sent in a notification transaction to
+    /// `recipient`
+    ///
+    /// `designated_key` is the private key of the input spending `outpoint`, which must be the
+    /// first input of the transaction exposing its public key.
+    pub fn notification_payload(
+        &self,
+        recipient: &PaymentCode,
+        designated_key: &SecretKey,
+        outpoint: &OutPoint,
+        secp: &SecpCtx,
+    ) -> Result<[u8; PAYMENT_CODE_LEN], Bip47Error> {
+        let mut payload = self.payment_code.to_bytes();
+        blind(
+            &mut payload,
+            designated_key,
+            &recipient.derive_public_key(0, secp)?,
+            outpoint,
+            secp,
+        )?;
+
+        Ok(payload)
+    }
+
+    /// Return the script of the `index`-th payment to `recipient`
+    pub fn send_script(
+        &self,
+        recipient: &PaymentCode,
+        index: u32,
+        secp: &SecpCtx,
+    ) -> Result<Script, Bip47Error> {
+        let mut key = recipient.derive_public_key(index, secp)?;
+        let tweak = payment_tweak(&self.derive_secret_key(0, secp)?, &key, secp)?;
+        key.add_exp_assign(secp, &tweak[..])?;
+
+        Ok(p2pkh_address(key, self.xprv.network).script_pubkey())
+    }
+
+    /// Return the private key that can spend the `index`-th payment received from `sender`
+    pub fn receive_key(
+        &self,
+        sender: &PaymentCode,
+        index: u32,
+        secp: &SecpCtx,
+    ) -> Result<PrivateKey, Bip47Error> {
+        let mut key = self.derive_secret_key(index, secp)?;
+        let tweak = payment_tweak(&key, &sender.derive_public_key(0, secp)?, secp)?;
+        key.add_assign(&tweak[..])?;
+
+        Ok(PrivateKey {
+            compressed: true,
+            network: self.xprv.network,
+            key,
+        })
+    }
+
+    /// Return the script of the `index`-th payment received from `sender`
+    pub fn receive_script(
+        &self,
+        sender: &PaymentCode,
+        index: u32,
+        secp: &SecpCtx,
+    ) -> Result<Script, Bip47Error> {
+        let key = self.receive_key(sender, index, secp)?;
+        Ok(Address::p2pkh(&key.public_key(secp), self.xprv.network).script_pubkey())
+    }
+}
+
+// Don't show the private key in the logs
+impl fmt::Debug for Bip47Account {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bip47Account")
+            .field("payment_code", &self.payment_code)
+            .finish()
+    }
+}
+
+impl<B, D> Wallet<B, D>
+where
+    D: BatchDatabase,
+{
+    // Replace the placeholder added by `TxBuilder::add_bip47_notification` with the blinded
+    // payment code
+    pub(crate) fn fill_bip47_notification(
+        &self,
+        mut psbt: Psbt,
+        sender: &PaymentCode,
+        recipient: &PaymentCode,
+    ) -> Result<Psbt, Error> {
+        let designated_key = self
+            .psbt_input_secret_key(&psbt, 0)
+            .ok_or(Bip47Error::MissingDesignatedKey)?
+            .key;
+        let outpoint = psbt.global.unsigned_tx.input[0].previous_output;
+
+        let mut payload = sender.to_bytes();
+        blind(
+            &mut payload,
+            &designated_key,
+            &recipient.derive_public_key(0, &self.secp)?,
+            &outpoint,
+            &self.secp,
+        )?;
+        let script = Builder::new()
+            .push_opcode(OP_RETURN)
+            .push_slice(&payload)
+            .into_script();
+
+        let placeholder = notification_placeholder();
+        let output = psbt
+            .global
+            .unsigned_tx
+            .output
+            .iter_mut()
+            .find(|output| output.script_pubkey == placeholder)
+            .expect("The placeholder is added by the TxBuilder");
+        output.script_pubkey = script;
+
+        Ok(psbt)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::consensus::deserialize;
+    use bitcoin::hashes::hex::{FromHex, ToHex};
+    use bitcoin::secp256k1::Secp256k1;
+    use bitcoin::TxOut;
+
+    use super::*;
+    use crate::wallet::test::get_funded_wallet;
+    use crate::SignOptions;
+
+    const ALICE: &str = "xprv9s21ZrQH143K2Ajn1HM67qGeNCp8GXztNyEnc9ebYQ3ipQ5hRiWgzVtmHVYpRvWCFF65j45uj6yAWPrJWVsVgtCJLtWAAvrQB6F8T9xK4C2";
+    const BOB: &str = "xprv9s21ZrQH143K4WqdBSH6cNgoTz8U9UJ1cAwwZmJHw6LyXJT8BGe4gmEmc8wQSCT9Uz6wos96fkPsshRzXzWRz6koJWq3mkJcYMSrCecqTgT";
+
+    fn account(xprv: &str, secp: &SecpCtx) -> Bip47Account {
+        Bip47Account::new(&ExtendedPrivKey::from_str(xprv).unwrap(), 0, secp).unwrap()
+    }
+
+    #[test]
+    fn test_payment_code() {
+        let secp = Secp256k1::new();
+        let alice = account(ALICE, &secp);
+        let bob = account(BOB, &secp);
+
+        assert_eq!(alice.payment_code().to_string(), "PM8TJTLJbPRGxSbc8EJi42Wrr6QbNSaSSVJ5Y3E4pbCYiTHUskHg13935Ubb7q8tx9GVbh2UuRnBc3WSyJHhUrw8KhprKnn9eDznYGieTzFcwQRya4GA");
+        assert_eq!(bob.payment_code().to_string(), "PM8TJS2JxQ5ztXUpBBRnpTbcUXbUHy2T1abfrb3KkAAtMEGNbey4oumH7Hc578WgQJhPjBxteQ5GHHToTYHE3A1w6p7tU6KSoFmWBVbFGjKPisZDbP97");
+        assert_eq!(
+            PaymentCode::from_str(&alice.payment_code().to_string()).unwrap(),
+            *alice.payment_code()
+        );
+
+        assert_eq!(
+            alice.notification_address(&secp).unwrap().to_string(),
+            "1JDdmqFLhpzcUwPeinhJbUPw4Co3aWLyzW"
+        );
+        assert_eq!(
+            bob.notification_address(&secp).unwrap().to_string(),
+            "1ChvUUvht2hUQufHBXF8NgLhW8SwE2ecGV"
+        );
+
+        assert!(matches!(
+            PaymentCode::from_str("1ChvUUvht2hUQufHBXF8NgLhW8SwE2ecGV"),
+            Err(Bip47Error::InvalidPaymentCode(_))
+        ));
+    }
+
+    #[test]
+    fn test_notification() {
+        let secp = Secp256k1::new();
+        let alice = account(ALICE, &secp);
+        let bob = account(BOB, &secp);
+
+        let designated_key = SecretKey::from_slice(
+            &Vec::<u8>::from_hex(
+                "1b7a10f45118e2519a8dd46ef81591c1ae501d082b6610fdda3de7a3c932880d",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let outpoint: OutPoint = deserialize(
+            &Vec::<u8>::from_hex(
+                "86f411ab1c8e70ae8a0795ab7a6757aea6e4d5ae1826fc7b8f00c597d500609c01000000",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let payload = alice
+            .notification_payload(bob.payment_code(), &designated_key, &outpoint, &secp)
+            .unwrap();
+        assert_eq!(payload.to_hex(), "010002063e4eb95e62791b06c50e1a3a942e1ecaaa9afbbeb324d16ae6821e091611fa96c0cf048f607fe51a0327f5e2528979311c78cb2de0d682c61e1180fc3d543b00000000000000000000000000");
+
+        let designated_public_key = PublicKey {
+            compressed: true,
+            key: secp256k1::PublicKey::from_secret_key(&secp, &designated_key),
+        };
+        let tx = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: outpoint,
+                script_sig: Builder::new()
+                    .push_slice(&[0x30; 71])
+                    .push_key(&designated_public_key)
+                    .into_script(),
+                sequence: 0xFFFFFFFF,
+                witness: vec![],
+            }],
+            output: vec![
+                TxOut {
+                    script_pubkey: bob.notification_address(&secp).unwrap().script_pubkey(),
+                    value: NOTIFICATION_AMOUNT,
+                },
+                TxOut {
+                    script_pubkey: Builder::new()
+                        .push_opcode(OP_RETURN)
+                        .push_slice(&payload)
+                        .into_script(),
+                    value: 0,
+                },
+            ],
+        };
+        assert_eq!(
+            bob.process_notification(&tx, &secp),
+            Some(*alice.payment_code())
+        );
+        // The notification is only readable by Bob
+        assert_eq!(alice.process_notification(&tx, &secp), None);
+    }
+
+    #[test]
+    fn test_payment_scripts() {
+        let secp = Secp256k1::new();
+        let alice = account(ALICE, &secp);
+        let bob = account(BOB, &secp);
+
+        let expected = [
+            (
+                "141fi7TY3h936vRUKh1qfUZr8rSBuYbVBK",
+                "d687f6b820e6e3d47296b01f3b73ccdc930eded39d559921a7dd8ed81b2c8f82",
+            ),
+            (
+                "12u3Uued2fuko2nY4SoSFGCoGLCBUGPkk6",
+                "c7a376a4ddc5ca6ecc3822fd06f6c5009911e71ce38e9b1e52bd2aaf735fd505",
+            ),
+            (
+                "1FsBVhT5dQutGwaPePTYMe5qvYqqjxyftc",
+                "72ab5f58870e5b24e13c1bedf674e2419bdba319bedeccf5eb4851050fc6feed",
+            ),
+        ];
+        for (index, (address, key)) in expected.iter().enumerate() {
+            let index = index as u32;
+            let script = Address::from_str(address).unwrap().script_pubkey();
+            assert_eq!(
+                alice.send_script(bob.payment_code(), index, &secp).unwrap(),
+                script
+            );
+            assert_eq!(
+                bob.receive_script(alice.payment_code(), index, &secp)
+                    .unwrap(),
+                script
+            );
+            assert_eq!(
+                bob.receive_key(alice.payment_code(), index, &secp)
+                    .unwrap()
+                    .key[..]
+                    .to_hex(),
+                *key
+            );
+        }
+    }
+
+    #[test]
+    fn test_notification_tx_builder() {
+        let secp = Secp256k1::new();
+        let xprv = "tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS";
+        let (wallet, _, _) = get_funded_wallet(&format!("wpkh({}/*)", xprv));
+        let sender = account(xprv, &secp);
+        let bob = account(BOB, &secp);
+
+        let mut builder = wallet.build_tx();
+        builder
+            .add_bip47_notification(sender.payment_code(), bob.payment_code())
+            .unwrap();
+        let (mut psbt, details) = builder.finish().unwrap();
+        assert_eq!(details.txid, psbt.global.unsigned_tx.txid());
+        assert!(psbt
+            .global
+            .unsigned_tx
+            .output
+            .iter()
+            .all(|output| output.script_pubkey != notification_placeholder()));
+
+        assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+        let tx = psbt.extract_tx();
+        assert!(tx.output.iter().any(|output| output.script_pubkey
+            == bob
+                .payment_code()
+                .notification_address(Network::Testnet, &secp)
+                .unwrap()
+                .script_pubkey()
+            && output.value == NOTIFICATION_AMOUNT));
+        assert_eq!(
+            bob.process_notification(&tx, &secp),
+            Some(*sender.payment_code())
+        );
+
+        // A wallet without the private key of the designated input can't build the notification
+        let (wallet, _, _) = get_funded_wallet("wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)");
+        let mut builder = wallet.build_tx();
+        builder
+            .add_bip47_notification(sender.payment_code(), bob.payment_code())
+            .unwrap();
+        assert!(matches!(
+            builder.finish(),
+            Err(Error::Bip47(Bip47Error::MissingDesignatedKey))
+        ));
+    }
+}
@@ -0,0 +1,603 @@
+// Bitcoin Dev Kit
+// Written in 2021 by the Bitcoin Dev Kit Developers
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Bitcoin Secure Multisig Setup
+//!
+//! This module implements the unencrypted flavor of [BIP-129](https://github.com/bitcoin/bips/blob/master/bip-0129.mediawiki),
+//! a protocol that lets a coordinator and a set of signers agree on a multisig descriptor.
+//!
+//! 1. Every signer creates a signed [`KeyRecord`] containing one of its extended public keys and
+//!    sends it to the coordinator.
+//! 2. The coordinator verifies the key records and combines them into a [`DescriptorRecord`],
+//!    which is sent back to every signer.
+//! 3. Every signer checks that the [`DescriptorRecord`] contains its key and that the first
+//!    address matches, and then creates its wallet using [`DescriptorRecord::descriptors`].
+//!
+//! Only the "no encryption" mode (token `00`) is supported: the records of the encrypted modes
+//! are rejected with [`BsmsError::EncryptedRecord`].
+//!
+//! ## Example
+//!
+//! ```
+//! # use std::str::FromStr;
+//! # use bitcoin::secp256k1::Secp256k1;
+//! # use bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey};
+//! # use bitcoin::Network;
+//! # use bdk::database::MemoryDatabase;
+//! # use bdk::wallet::bsms::*;
+//! # use bdk::Wallet;
+//! let secp = Secp256k1::new();
+//! let path = DerivationPath::from_str("m/48'/1'/0'/2'").unwrap();
+//!
+//! // round 1: every signer creates its key record
+//! let records = ["tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS", "tprv8ZgxMBicQKsPegBHHnq7YEgM815dG24M2Jk5RVqipgDxF1HJ1tsnT815X5Fd5FRfMVUs8NZs9XCb6y9an8hRPThnhfwfXJ36intaekySHGF"]
+//!     .iter()
+//!     .enumerate()
+//!     .map(|(i, xprv)| {
+//!         let master = ExtendedPrivKey::from_str(xprv).unwrap();
+//!         let account = master.derive_priv(&secp, &path).unwrap();
+//!         KeyRecord::new(
+//!             &account,
+//!             (master.fingerprint(&secp), path.clone()),
+//!             format!("Signer {}", i),
+//!             &secp,
+//!         )
+//!         .map(|record| record.to_string())
+//!     })
+//!     .collect::<Result<Vec<_>, _>>()?;
+//!
+//! // round 2: the coordinator combines them
+//! let records = records
+//!     .iter()
+//!     .map(|r| KeyRecord::from_str(r))
+//!     .collect::<Result<Vec<_>, _>>()?;
+//! let descriptor_record = DescriptorRecord::new(2, &records, Network::Testnet, &secp)?;
+//!
+//! // every signer verifies the result and creates its wallet
+//! let descriptor_record = DescriptorRecord::from_str(&descriptor_record.to_string())?;
+//! descriptor_record.verify(&records[0].key, Network::Testnet, &secp)?;
+//! let (descriptor, change_descriptor) = descriptor_record.descriptors()?;
+//! let wallet = Wallet::new_offline(
+//!     &descriptor,
+//!     Some(&change_descriptor),
+//!     Network::Testnet,
+//!     MemoryDatabase::default(),
+//! )
+//! .unwrap();
+//! # Ok::<_, BsmsError>(())
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::secp256k1::{self, Message};
+use bitcoin::util::bip32::{ExtendedPrivKey, ExtendedPubKey, KeySource};
+use bitcoin::util::misc::{signed_msg_hash, MessageSignature};
+use bitcoin::{Address, Network};
+
+use miniscript::descriptor::{DescriptorPublicKey, DescriptorXKey, Wildcard};
+
+use crate::descriptor::derived::AsDerived;
+use crate::descriptor::{get_checksum, DescriptorError, ExtendedDescriptor};
+use crate::wallet::utils::SecpCtx;
+
+/// Version header of every BSMS record
+pub const BSMS_VERSION: &str = "BSMS 1.0";
+/// Token used in the "no encryption" mode
+pub const NO_ENCRYPTION_TOKEN: &str = "00";
+/// Path restrictions of the descriptor templates created by this module
+pub const DEFAULT_PATH_RESTRICTIONS: &str = "/0/*,/1/*";
+
+/// Errors related to the BSMS protocol
+#[derive(Debug)]
+pub enum BsmsError {
+    /// The record doesn't start with [`BSMS_VERSION`]
+    InvalidVersion,
+    /// The record uses one of the encrypted modes, which are not supported
+    ///
+    /// Returned for the key records whose token is not [`NO_ENCRYPTION_TOKEN`], and for the
+    /// records that are fully encrypted, which are hex encoded. The coordinator has to be asked
+    /// to use the "no encryption" mode instead.
+    EncryptedRecord,
+    /// The record is malformed
+    InvalidRecord(String),
+    /// The key must be an extended public key with origin and without derivation steps
+    InvalidKey,
+    /// The signature of a key record doesn't match its key
+    InvalidSignature,
+    /// The path restrictions are not [`DEFAULT_PATH_RESTRICTIONS`]
+    UnsupportedPathRestrictions(String),
+    /// The threshold is zero or larger than the number of keys
+    InvalidThreshold,
+    /// The descriptor record doesn't contain the signer's key
+    KeyNotFound,
+    /// The first address doesn't match the descriptor
+    AddressMismatch,
+
+    /// Error while working with the descriptor
+    Descriptor(DescriptorError),
+    /// Secp256k1 error
+    Secp256k1(secp256k1::Error),
+}
+
+impl_error!(DescriptorError, Descriptor, BsmsError);
+impl_error!(secp256k1::Error, Secp256k1, BsmsError);
+
+impl From<miniscript::Error> for BsmsError {
+    fn from(err: miniscript::Error) -> Self {
+        BsmsError::Descriptor(err.into())
+    }
+}
+
+impl fmt::Display for BsmsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for BsmsError {}
+
+fn check_header<'a, I: Iterator<Item = &'a str>>(lines: &mut I) -> Result<(), BsmsError> {
+    match lines.next() {
+        Some(BSMS_VERSION) => Ok(()),
+        Some(line) if is_hex(line) => Err(BsmsError::EncryptedRecord),
+        _ => Err(BsmsError::InvalidVersion),
+    }
+}
+
+fn is_hex(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn next_line<'a, I: Iterator<Item = &'a str>>(
+    lines: &mut I,
+    name: &str,
+) -> Result<&'a str, BsmsError> {
+    lines
+        .next()
+        .ok_or_else(|| BsmsError::InvalidRecord(format!("Missing {}", name)))
+}
+
+/// Key record sent by a signer to the coordinator (round 1)
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyRecord {
+    /// Extended public key of the signer, with its origin
+    pub key: DescriptorPublicKey,
+    /// Free-form description of the signer
+    pub description: String,
+    signature: MessageSignature,
+}
+
+impl KeyRecord {
+    /// Create a signed key record for `xprv`, whose origin is `source`
+    pub fn new(
+        xprv: &ExtendedPrivKey,
+        source: KeySource,
+        description: String,
+        secp: &SecpCtx,
+    ) -> Result<Self, BsmsError> {
+        if description.contains('\n') {
+            return Err(BsmsError::InvalidRecord(
+                "The description can't contain new lines".into(),
+            ));
+        }
+
+        let key = DescriptorPublicKey::XPub(DescriptorXKey {
+            origin: Some(source),
+            xkey: ExtendedPubKey::from_private(secp, xprv),
+            derivation_path: Default::default(),
+            wildcard: Wildcard::None,
+        });
+        let message = Self::message(&key, &description);
+        let msg_hash = Message::from_slice(&signed_msg_hash(&message))?;
+        let signature = MessageSignature::new(
+            secp.sign_recoverable(&msg_hash, &xprv.private_key.key),
+            true,
+        );
+
+        Ok(KeyRecord {
+            key,
+            description,
+            signature,
+        })
+    }
+
+    fn message(key: &DescriptorPublicKey, description: &str) -> String {
+        format!(
+            "{}\n{}\n{}\n{}",
+            BSMS_VERSION, NO_ENCRYPTION_TOKEN, key, description
+        )
+    }
+
+    fn xpub(&self) -> Result<&ExtendedPubKey, BsmsError> {
+        match &self.key {
+            DescriptorPublicKey::XPub(xkey)
+                if xkey.origin.is_some()
+                    && xkey.derivation_path.as_ref().is_empty()
+                    && xkey.wildcard == Wildcard::None =>
+            {
+                Ok(&xkey.xkey)
+            }
+            _ => Err(BsmsError::InvalidKey),
+        }
+    }
+
+    /// Verify that the record has been signed by the private key of its extended public key
+    pub fn verify(&self, secp: &SecpCtx) -> Result<(), BsmsError> {
+        let xpub = self.xpub()?;
+        let msg_hash = signed_msg_hash(&Self::message(&self.key, &self.description));
+        let public_key = self
+            .signature
+            .recover_pubkey(secp, msg_hash)
+            .map_err(|_| BsmsError::InvalidSignature)?;
+
+        if public_key.key == xpub.public_key.key {
+            Ok(())
+        } else {
+            Err(BsmsError::InvalidSignature)
+        }
+    }
+}
+
+impl fmt::Display for KeyRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\n{}",
+            Self::message(&self.key, &self.description),
+            self.signature.to_base64()
+        )
+    }
+}
+
+impl FromStr for KeyRecord {
+    type Err = BsmsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.trim().lines().map(str::trim);
+        check_header(&mut lines)?;
+
+        // The tokens of the encrypted modes are 8 or 16 bytes, hex encoded
+        match next_line(&mut lines, "token")? {
+            NO_ENCRYPTION_TOKEN => {}
+            token if (token.len() == 16 || token.len() == 32) && is_hex(token) => {
+                return Err(BsmsError::EncryptedRecord)
+            }
+            token => return Err(BsmsError::InvalidRecord(format!("Invalid token {}", token))),
+        }
+        let key = DescriptorPublicKey::from_str(next_line(&mut lines, "key")?)
+            .map_err(|_| BsmsError::InvalidKey)?;
+        let description = next_line(&mut lines, "description")?.to_string();
+        let signature = MessageSignature::from_base64(next_line(&mut lines, "signature")?)
+            .map_err(|_| BsmsError::InvalidSignature)?;
+
+        let record = KeyRecord {
+            key,
+            description,
+            signature,
+        };
+        record.xpub()?;
+
+        Ok(record)
+    }
+}
+
+/// Descriptor record sent by the coordinator to the signers (round 2)
+#[derive(Debug, Clone, PartialEq)]
+pub struct DescriptorRecord {
+    /// Descriptor template, using `/**` to denote the [`DEFAULT_PATH_RESTRICTIONS`]
+    pub template: String,
+    /// First external address of the wallet
+    pub first_address: Address,
+}
+
+impl DescriptorRecord {
+    /// Combine the key records of the signers into a `threshold`-of-n `wsh(sortedmulti(...))`
+    /// descriptor record
+    ///
+    /// The signatures of all the key records are verified.
+    pub fn new(
+        threshold: usize,
+        records: &[KeyRecord],
+        network: Network,
+        secp: &SecpCtx,
+    ) -> Result<Self, BsmsError> {
+        if threshold == 0 || threshold > records.len() {
+            return Err(BsmsError::InvalidThreshold);
+        }
+        for record in records {
+            record.verify(secp)?;
+        }
+
+        let keys = records
+            .iter()
+            .map(|r| format!("{}/**", r.key))
+            .collect::<Vec<_>>()
+            .join(",");
+        let template = format!("wsh(sortedmulti({},{}))", threshold, keys);
+
+        let first_address = Self::derive_first_address(&template, network, secp)?;
+        let template = format!("{}#{}", template, get_checksum(&template)?);
+
+        Ok(DescriptorRecord {
+            template,
+            first_address,
+        })
+    }
+
+    fn strip_checksum(template: &str) -> &str {
+        template.splitn(2, '#').next().unwrap()
+    }
+
+    fn expand(template: &str, branch: u32) -> Result<String, BsmsError> {
+        let expanded = template.replace("/**", &format!("/{}/*", branch));
+        let checksum = get_checksum(&expanded)?;
+        Ok(format!("{}#{}", expanded, checksum))
+    }
+
+    fn derive_first_address(
+        template: &str,
+        network: Network,
+        secp: &SecpCtx,
+    ) -> Result<Address, BsmsError> {
+        let descriptor = ExtendedDescriptor::from_str(&Self::expand(template, 0)?)?;
+        descriptor
+            .as_derived(0, secp)
+            .address(network)
+            .map_err(|_| BsmsError::AddressMismatch)
+    }
+
+    /// Return the external and internal descriptors described by this record
+    pub fn descriptors(&self) -> Result<(String, String), BsmsError> {
+        let template = Self::strip_checksum(&self.template);
+        Ok((Self::expand(template, 0)?, Self::expand(template, 1)?))
+    }
+
+    /// Verify the record from the point of view of the signer that owns `key`
+    ///
+    /// Checks that the descriptor contains `key` and that the first address is correct.
+    pub fn verify(
+        &self,
+        key: &DescriptorPublicKey,
+        network: Network,
+        secp: &SecpCtx,
+    ) -> Result<(), BsmsError> {
+        let template = Self::strip_checksum(&self.template);
+        if !template.contains(&format!("{}/**", key)) {
+            return Err(BsmsError::KeyNotFound);
+        }
+
+        if Self::derive_first_address(template, network, secp)? != self.first_address {
+            return Err(BsmsError::AddressMismatch);
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for DescriptorRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\n{}\n{}\n{}",
+            BSMS_VERSION, self.template, DEFAULT_PATH_RESTRICTIONS, self.first_address
+        )
+    }
+}
+
+impl FromStr for DescriptorRecord {
+    type Err = BsmsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.trim().lines().map(str::trim);
+        check_header(&mut lines)?;
+
+        let template = next_line(&mut lines, "descriptor template")?;
+        // also rejects the characters that can't appear in a descriptor when there's no checksum
+        let expected_checksum = get_checksum(Self::strip_checksum(template))?;
+        if let Some(checksum) = template.splitn(2, '#').nth(1) {
+            if expected_checksum != checksum {
+                return Err(BsmsError::Descriptor(
+                    DescriptorError::InvalidDescriptorChecksum,
+                ));
+            }
+        }
+        let path_restrictions = next_line(&mut lines, "path restrictions")?;
+        if path_restrictions != DEFAULT_PATH_RESTRICTIONS {
+            return Err(BsmsError::UnsupportedPathRestrictions(
+                path_restrictions.into(),
+            ));
+        }
+        let first_address = Address::from_str(next_line(&mut lines, "first address")?)
+            .map_err(|e| BsmsError::InvalidRecord(e.to_string()))?;
+
+        Ok(DescriptorRecord {
+            template: template.into(),
+            first_address,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::secp256k1::Secp256k1;
+    use bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey};
+    use bitcoin::Network;
+
+    use super::*;
+
+    const XPRVS: [&str; 3] = [
+        "tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS",
+        "tprv8ZgxMBicQKsPegBHHnq7YEgM815dG24M2Jk5RVqipgDxF1HJ1tsnT815X5Fd5FRfMVUs8NZs9XCb6y9an8hRPThnhfwfXJ36intaekySHGF",
+        "tprv8ZgxMBicQKsPdZXrcHNLf5JAJWFAoJ2TrstMRdSKtEggz6PddbuSkvHKM9oKJyFgZV1B7rw8oChspxyYbtmEXYyg1AjfWbL3ho3XHDpHRZf",
+    ];
+
+    fn get_key_records() -> Vec<KeyRecord> {
+        let secp = Secp256k1::new();
+        let path = DerivationPath::from_str("m/48'/1'/0'/2'").unwrap();
+
+        XPRVS
+            .iter()
+            .enumerate()
+            .map(|(i, xprv)| {
+                let master = ExtendedPrivKey::from_str(xprv).unwrap();
+                let account = master.derive_priv(&secp, &path).unwrap();
+                KeyRecord::new(
+                    &account,
+                    (master.fingerprint(&secp), path.clone()),
+                    format!("Signer {}", i),
+                    &secp,
+                )
+                .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_key_record_roundtrip() {
+        let secp = Secp256k1::new();
+
+        for record in get_key_records() {
+            let parsed = KeyRecord::from_str(&record.to_string()).unwrap();
+            assert_eq!(parsed, record);
+            parsed.verify(&secp).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_key_record_tampered() {
+        let secp = Secp256k1::new();
+        let records = get_key_records();
+
+        let mut tampered = records[0].clone();
+        tampered.description = "Evil signer".into();
+        assert!(matches!(
+            tampered.verify(&secp),
+            Err(BsmsError::InvalidSignature)
+        ));
+
+        let mut tampered = records[0].clone();
+        tampered.key = records[1].key.clone();
+        assert!(matches!(
+            tampered.verify(&secp),
+            Err(BsmsError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_key_record_encrypted() {
+        let record = get_key_records()[0].to_string();
+        for token in &["a54044308ceac9b7", "a54044308ceac9b7c4c3b1d95ceb3fd4"] {
+            let encrypted = record.replacen("\n00\n", &format!("\n{}\n", token), 1);
+            assert!(matches!(
+                KeyRecord::from_str(&encrypted),
+                Err(BsmsError::EncryptedRecord)
+            ));
+        }
+
+        let invalid = record.replacen("\n00\n", "\n1234\n", 1);
+        assert!(matches!(
+            KeyRecord::from_str(&invalid),
+            Err(BsmsError::InvalidRecord(_))
+        ));
+
+        // fully encrypted records are hex encoded, without the header
+        let encrypted = "5f1d4a2a38d3a0c0ad5e7e1ff7c7fdb38c1b1d18a6e4ce3d2f1d4a7a8eb0d7c1";
+        assert!(matches!(
+            KeyRecord::from_str(encrypted),
+            Err(BsmsError::EncryptedRecord)
+        ));
+        assert!(matches!(
+            DescriptorRecord::from_str(encrypted),
+            Err(BsmsError::EncryptedRecord)
+        ));
+    }
+
+    #[test]
+    fn test_descriptor_record() {
+        let secp = Secp256k1::new();
+        let records = get_key_records();
+
+        let descriptor_record =
+            DescriptorRecord::new(2, &records, Network::Testnet, &secp).unwrap();
+        let parsed = DescriptorRecord::from_str(&descriptor_record.to_string()).unwrap();
+        assert_eq!(parsed, descriptor_record);
+
+        for record in &records {
+            parsed.verify(&record.key, Network::Testnet, &secp).unwrap();
+        }
+
+        let (descriptor, change_descriptor) = parsed.descriptors().unwrap();
+        assert!(descriptor.starts_with("wsh(sortedmulti(2,"));
+        assert!(descriptor.contains("/0/*"));
+        assert!(change_descriptor.contains("/1/*"));
+
+        let wallet = crate::Wallet::new_offline(
+            &descriptor,
+            Some(&change_descriptor),
+            Network::Testnet,
+            crate::database::MemoryDatabase::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            wallet
+                .get_address(crate::wallet::AddressIndex::Peek(0))
+                .unwrap()
+                .address,
+            parsed.first_address
+        );
+    }
+
+    #[test]
+    fn test_descriptor_record_errors() {
+        let secp = Secp256k1::new();
+        let records = get_key_records();
+
+        assert!(matches!(
+            DescriptorRecord::new(4, &records, Network::Testnet, &secp),
+            Err(BsmsError::InvalidThreshold)
+        ));
+
+        let descriptor_record =
+            DescriptorRecord::new(2, &records[..2], Network::Testnet, &secp).unwrap();
+        assert!(matches!(
+            descriptor_record.verify(&records[2].key, Network::Testnet, &secp),
+            Err(BsmsError::KeyNotFound)
+        ));
+
+        let invalid_template = format!(
+            "{}\nwsh(é)/**\n{}\n{}",
+            BSMS_VERSION, DEFAULT_PATH_RESTRICTIONS, descriptor_record.first_address
+        );
+        assert!(matches!(
+            DescriptorRecord::from_str(&invalid_template),
+            Err(BsmsError::Descriptor(_))
+        ));
+        let mut invalid_record = descriptor_record.clone();
+        invalid_record.template = "wsh(é)/**".into();
+        assert!(matches!(
+            invalid_record.descriptors(),
+            Err(BsmsError::Descriptor(_))
+        ));
+
+        let mut wrong_address = descriptor_record.clone();
+        wrong_address.first_address =
+            DescriptorRecord::new(1, &records[..2], Network::Testnet, &secp)
+                .unwrap()
+                .first_address;
+        assert!(matches!(
+            wrong_address.verify(&records[0].key, Network::Testnet, &secp),
+            Err(BsmsError::AddressMismatch)
+        ));
+    }
+}
@@ -167,6 +167,33 @@ pub trait CoinSelectionAlgorithm<D: Database>: std::fmt::Debug {
         amount_needed: u64,
         fee_amount: f32,
     ) -> Result<CoinSelectionResult, Error>;
+
+    /// Perform the coin selection, given the long-term fee rate set with
+    /// [`TxBuilder::long_term_fee_rate`](super::tx_builder::TxBuilder::long_term_fee_rate)
+    ///
+    /// This is what the wallet calls. The default implementation ignores `long_term_fee_rate` and
+    /// calls [`coin_select`](Self::coin_select): algorithms that can use it, like
+    /// [`BranchAndBoundCoinSelection`], override it.
+    #[allow(clippy::too_many_arguments)]
+    fn coin_select_with_long_term_fee_rate(
+        &self,
+        database: &D,
+        required_utxos: Vec<WeightedUtxo>,
+        optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        _long_term_fee_rate: Option<FeeRate>,
+        amount_needed: u64,
+        fee_amount: f32,
+    ) -> Result<CoinSelectionResult, Error> {
+        self.coin_select(
+            database,
+            required_utxos,
+            optional_utxos,
+            fee_rate,
+            amount_needed,
+            fee_amount,
+        )
+    }
 }
 
 /// Simple and dumb coin selection
@@ -267,14 +294,72 @@ impl OutputGroup {
             effective_value,
         }
     }
+
+    // The waste of spending this UTXO now rather than at `long_term_fee_rate`
+    fn waste(&self, long_term_fee_rate: FeeRate) -> f32 {
+        let long_term_fee = (TXIN_BASE_WEIGHT + self.weighted_utxo.satisfaction_weight).vbytes()
+            * long_term_fee_rate.as_sat_vb();
+        self.fee - long_term_fee
+    }
+}
+
+/// Compute the "waste" of a selection, as defined by Bitcoin Core
+///
+/// The waste is the sum of the difference between the fee paid for each input at `fee_rate` and
+/// the fee it would pay at `long_term_fee_rate`, plus either the `cost_of_change` if a change
+/// output is created or the excess over `target` (which is dropped to fees) otherwise.
+///
+/// - `selected`: the utxos selected with their weight cost
+/// - `target`: the amount in satoshi that the selected utxos need to cover, including the fees
+///             that don't depend on the inputs
+/// - `fee_rate`: fee rate used for the transaction
+/// - `long_term_fee_rate`: fee rate the utxos are expected to be spent at in the future
+/// - `cost_of_change`: the cost in satoshi of creating and later spending a change output, or
+///                     `None` if the selection doesn't create one
+///
+/// Lower is better: a negative waste means that spending the inputs now is cheaper than it's
+/// expected to be in the future.
+pub fn calculate_waste(
+    selected: &[WeightedUtxo],
+    target: u64,
+    fee_rate: FeeRate,
+    long_term_fee_rate: FeeRate,
+    cost_of_change: Option<f32>,
+) -> f32 {
+    let groups = selected
+        .iter()
+        .cloned()
+        .map(|u| OutputGroup::new(u, fee_rate))
+        .collect::<Vec<_>>();
+
+    let inputs_waste = groups
+        .iter()
+        .map(|u| u.waste(long_term_fee_rate))
+        .sum::<f32>();
+
+    match cost_of_change {
+        Some(cost_of_change) => inputs_waste + cost_of_change,
+        None => {
+            let selected_effective_value = groups.iter().map(|u| u.effective_value).sum::<i64>();
+            inputs_waste + (selected_effective_value - target as i64) as f32
+        }
+    }
 }
 
 /// Branch and bound coin selection
 ///
 /// Code adapted from Bitcoin Core's implementation and from Mark Erhardt Master's Thesis: <http://murch.one/wp-content/uploads/2016/11/erhardt2016coinselection.pdf>
+///
+/// When a long-term fee rate is set with [`long_term_fee_rate`](Self::long_term_fee_rate), or
+/// with [`TxBuilder::long_term_fee_rate`](super::tx_builder::TxBuilder::long_term_fee_rate) which
+/// takes precedence, the candidate selections are compared using their [waste](calculate_waste):
+/// at fee rates higher than the long-term one fewer inputs are preferred, while at lower fee rates
+/// the algorithm will consolidate more UTXOs. Without it, the selection with the smallest excess
+/// is picked.
 #[derive(Debug)]
 pub struct BranchAndBoundCoinSelection {
     size_of_change: u64,
+    long_term_fee_rate: Option<FeeRate>,
 }
 
 impl Default for BranchAndBoundCoinSelection {
@@ -282,6 +367,7 @@ impl Default for BranchAndBoundCoinSelection {
         Self {
             // P2WPKH cost of change -> value (8 bytes) + script len (1 bytes) + script (22 bytes)
             size_of_change: 8 + 1 + 22,
+            long_term_fee_rate: None,
         }
     }
 }
@@ -289,7 +375,17 @@ impl Default for BranchAndBoundCoinSelection {
 impl BranchAndBoundCoinSelection {
     /// Create new instance with target size for change output
     pub fn new(size_of_change: u64) -> Self {
-        Self { size_of_change }
+        Self {
+            size_of_change,
+            long_term_fee_rate: None,
+        }
+    }
+
+    /// Set the fee rate the UTXOs are expected to be spent at in the long term, used to compute
+    /// the waste of the candidate selections
+    pub fn long_term_fee_rate(mut self, long_term_fee_rate: FeeRate) -> Self {
+        self.long_term_fee_rate = Some(long_term_fee_rate);
+        self
     }
 }
 
@@ -365,6 +461,30 @@ impl<D: Database> CoinSelectionAlgorithm<D> for BranchAndBoundCoinSelection {
                 )
             }))
     }
+
+    fn coin_select_with_long_term_fee_rate(
+        &self,
+        database: &D,
+        required_utxos: Vec<WeightedUtxo>,
+        optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        long_term_fee_rate: Option<FeeRate>,
+        amount_needed: u64,
+        fee_amount: f32,
+    ) -> Result<CoinSelectionResult, Error> {
+        let coin_selection = BranchAndBoundCoinSelection {
+            size_of_change: self.size_of_change,
+            long_term_fee_rate: long_term_fee_rate.or(self.long_term_fee_rate),
+        };
+        coin_selection.coin_select(
+            database,
+            required_utxos,
+            optional_utxos,
+            fee_rate,
+            amount_needed,
+            fee_amount,
+        )
+    }
 }
 
 impl BranchAndBoundCoinSelection {
@@ -391,9 +511,22 @@ impl BranchAndBoundCoinSelection {
         optional_utxos.sort_unstable_by_key(|a| a.effective_value);
         optional_utxos.reverse();
 
+        // Without a long-term fee rate every input is considered to have no waste, which means
+        // that the selection with the smallest excess is the best one
+        let utxo_waste = |u: &OutputGroup| {
+            self.long_term_fee_rate
+                .map(|long_term_fee_rate| u.waste(long_term_fee_rate))
+                .unwrap_or(0.0)
+        };
+        let optional_waste: Vec<f32> = optional_utxos.iter().map(utxo_waste).collect();
+        // If adding inputs can only increase the waste we can stop exploring a branch as soon as
+        // it's worse than the best selection
+        let waste_only_increases = optional_waste.iter().all(|w| *w >= 0.0);
+        let mut curr_waste: f32 = required_utxos.iter().map(utxo_waste).sum();
+
         // Contains the best selection we found
         let mut best_selection = Vec::new();
-        let mut best_selection_value = None;
+        let mut best_selection_waste = None;
 
         // Depth First search loop for choosing the UTXOs
         for _ in 0..BNB_TOTAL_TRIES {
@@ -404,6 +537,8 @@ impl BranchAndBoundCoinSelection {
             // Go back and try other branch
             if curr_value + curr_available_value < actual_target
                 || curr_value > actual_target + cost_of_change as i64
+                || (waste_only_increases
+                    && best_selection_waste.map_or(false, |best| curr_waste > best))
             {
                 backtrack = true;
             } else if curr_value >= actual_target {
@@ -411,15 +546,19 @@ impl BranchAndBoundCoinSelection {
                 // backtracking
                 backtrack = true;
 
+                // The excess is dropped to fees, so it counts towards the waste
+                let waste = curr_waste + (curr_value - actual_target) as f32;
+
                 // If we found a solution better than the previous one, or if there wasn't previous
                 // solution, update the best solution
-                if best_selection_value.is_none() || curr_value < best_selection_value.unwrap() {
+                if best_selection_waste.map_or(true, |best| waste < best) {
                     best_selection = current_selection.clone();
-                    best_selection_value = Some(curr_value);
+                    best_selection_waste = Some(waste);
                 }
 
-                // If we found a perfect match, break here
-                if curr_value == actual_target {
+                // If we found a perfect match and the inputs don't contribute to the waste, we
+                // can't do any better: break here
+                if curr_value == actual_target && self.long_term_fee_rate.is_none() {
                     break;
                 }
             }
@@ -448,6 +587,7 @@ impl BranchAndBoundCoinSelection {
 
                 let utxo = &optional_utxos[current_selection.len() - 1];
                 curr_value -= utxo.effective_value;
+                curr_waste -= optional_waste[current_selection.len() - 1];
             } else {
                 // Moving forwards, continuing down this branch
                 let utxo = &optional_utxos[current_selection.len()];
@@ -456,6 +596,7 @@ impl BranchAndBoundCoinSelection {
                 curr_available_value -= utxo.effective_value;
 
                 // Inclusion branch first (Largest First Exploration)
+                curr_waste += optional_waste[current_selection.len()];
                 current_selection.push(true);
                 curr_value += utxo.effective_value;
             }
@@ -1017,6 +1158,132 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_bnb_function_waste() {
+        let fee_rate = FeeRate::from_sat_per_vb(10.0);
+        let size_of_change = 100;
+        let cost_of_change = size_of_change as f32 * fee_rate.as_sat_vb();
+
+        // Two utxos that exactly match the target, and a larger one that overshoots it by 400
+        // sats. Every input costs 680 sats at 10 sat/vbyte
+        let mut utxos = generate_same_value_utxos(30_000, 2);
+        utxos.extend(generate_same_value_utxos(59_720, 1));
+        let utxos: Vec<OutputGroup> = utxos
+            .into_iter()
+            .map(|u| OutputGroup::new(u, fee_rate))
+            .collect();
+        let curr_available_value = utxos.iter().fold(0, |acc, x| acc + x.effective_value);
+        let target_amount = 2 * (30_000 - 680);
+
+        // Without a long-term fee rate the exact match wins
+        let result = BranchAndBoundCoinSelection::new(size_of_change)
+            .bnb(
+                vec![],
+                utxos.clone(),
+                0,
+                curr_available_value,
+                target_amount,
+                FEE_AMOUNT,
+                cost_of_change,
+            )
+            .unwrap();
+        assert_eq!(result.selected.len(), 2);
+        assert_eq!(result.selected_amount(), 60_000);
+
+        // With a long-term fee rate of 1 sat/vbyte every input wastes 612 sats, so spending a
+        // single input and dropping the 400 sats excess to fees is cheaper
+        let result = BranchAndBoundCoinSelection::new(size_of_change)
+            .long_term_fee_rate(FeeRate::from_sat_per_vb(1.0))
+            .bnb(
+                vec![],
+                utxos,
+                0,
+                curr_available_value,
+                target_amount,
+                FEE_AMOUNT,
+                cost_of_change,
+            )
+            .unwrap();
+        assert_eq!(result.selected.len(), 1);
+        assert_eq!(result.selected_amount(), 59_720);
+    }
+
+    #[test]
+    fn test_bnb_coin_selection_long_term_fee_rate() {
+        let fee_rate = FeeRate::from_sat_per_vb(10.0);
+        let database = MemoryDatabase::default();
+
+        // Same utxos as `test_bnb_function_waste`
+        let mut utxos = generate_same_value_utxos(30_000, 2);
+        utxos.extend(generate_same_value_utxos(59_720, 1));
+        let amount_needed = 2 * (30_000 - 680) - FEE_AMOUNT as u64;
+
+        let result = BranchAndBoundCoinSelection::new(100)
+            .coin_select_with_long_term_fee_rate(
+                &database,
+                vec![],
+                utxos.clone(),
+                fee_rate,
+                None,
+                amount_needed,
+                FEE_AMOUNT,
+            )
+            .unwrap();
+        assert_eq!(result.selected.len(), 2);
+        assert_eq!(result.selected_amount(), 60_000);
+
+        let result = BranchAndBoundCoinSelection::new(100)
+            .coin_select_with_long_term_fee_rate(
+                &database,
+                vec![],
+                utxos,
+                fee_rate,
+                Some(FeeRate::from_sat_per_vb(1.0)),
+                amount_needed,
+                FEE_AMOUNT,
+            )
+            .unwrap();
+        assert_eq!(result.selected.len(), 1);
+        assert_eq!(result.selected_amount(), 59_720);
+    }
+
+    #[test]
+    fn test_calculate_waste() {
+        let fee_rate = FeeRate::from_sat_per_vb(10.0);
+        let utxos = generate_same_value_utxos(30_000, 2);
+        let target = 2 * (30_000 - 680) - 100;
+
+        // Each input is 68 vbytes: (680 - 68) * 2 + 100 of excess
+        let waste = calculate_waste(
+            &utxos,
+            target,
+            fee_rate,
+            FeeRate::from_sat_per_vb(1.0),
+            None,
+        );
+        assert!((waste - 1324.0).abs() < f32::EPSILON);
+
+        // With change the excess is replaced by the cost of change
+        let waste = calculate_waste(
+            &utxos,
+            target,
+            fee_rate,
+            FeeRate::from_sat_per_vb(1.0),
+            Some(500.0),
+        );
+        assert!((waste - 1724.0).abs() < f32::EPSILON);
+
+        // Spending at a fee rate lower than the long-term one has a negative waste
+        let waste = calculate_waste(
+            &utxos,
+            2 * (30_000 - 68),
+            FeeRate::from_sat_per_vb(1.0),
+            fee_rate,
+            None,
+        );
+        assert!((waste + 1224.0).abs() < f32::EPSILON);
+    }
+
     #[test]
     fn test_single_random_draw_function_success() {
         let seed = [0; 32];
@@ -0,0 +1,265 @@
+// Bitcoin Dev Kit
+// Written in 2021 by the Bitcoin Dev Kit Developers
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Wallet events
+//!
+//! A [`Wallet`](super::Wallet) emits a [`WalletEvent`] every time something relevant changes in
+//! its state: new addresses are revealed with [`Wallet::get_address`](super::Wallet::get_address),
+//! and transactions are added, confirmed, reorganized or removed by
+//! [`Wallet::sync`](super::Wallet::sync).
+//!
+//! Events can be received either by attaching a [`WalletEventListener`] with
+//! [`Wallet::add_event_listener`](super::Wallet::add_event_listener), or through a channel returned
+//! by [`Wallet::subscribe`](super::Wallet::subscribe).
+//!
+//! ## Example
+//!
+//! ```
+//! # use bitcoin::*;
+//! # use bdk::database::*;
+//! # use bdk::wallet::event::*;
+//! # use bdk::wallet::AddressIndex::New;
+//! # use bdk::*;
+//! let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+//! let mut wallet = Wallet::new_offline(descriptor, None, Network::Testnet, MemoryDatabase::default())?;
+//! let events = wallet.subscribe();
+//!
+//! wallet.get_address(New)?;
+//! assert_eq!(
+//!     events.try_recv().unwrap(),
+//!     WalletEvent::AddressRevealed { keychain: KeychainKind::External, index: 0 }
+//! );
+//! # Ok::<(), bdk::Error>(())
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+
+use bitcoin::Txid;
+
+use crate::types::{ConfirmationTime, KeychainKind, TransactionDetails};
+
+/// Event emitted by a [`Wallet`](super::Wallet)
+#[derive(Debug, Clone, PartialEq)]
+pub enum WalletEvent {
+    /// A new address has been revealed
+    AddressRevealed {
+        /// Keychain of the address
+        keychain: KeychainKind,
+        /// Child number of the address
+        index: u32,
+    },
+    /// A transaction relevant to the wallet has been seen for the first time
+    TxSeen {
+        /// Id of the transaction
+        txid: Txid,
+    },
+    /// A transaction has been confirmed
+    TxConfirmed {
+        /// Id of the transaction
+        txid: Txid,
+        /// Block height and timestamp of the confirmation
+        confirmation_time: ConfirmationTime,
+    },
+    /// A transaction that was confirmed has been reorganized out of the chain
+    ///
+    /// If it has been included in a different block, a [`WalletEvent::TxConfirmed`] event follows.
+    ReorgDetected {
+        /// Id of the transaction
+        txid: Txid,
+        /// Confirmation that is not valid anymore
+        previous: ConfirmationTime,
+    },
+    /// A transaction is not relevant to the wallet anymore, because it has been replaced or
+    /// evicted from the mempool
+    TxRemoved {
+        /// Id of the transaction
+        txid: Txid,
+    },
+}
+
+/// Trait to build wallet event listeners
+///
+/// All the listeners attached to a wallet with
+/// [`Wallet::add_event_listener`](super::Wallet::add_event_listener) are notified, in sequence,
+/// of every [`WalletEvent`].
+///
+/// For a usage example see [this module](crate::wallet::event)'s documentation.
+pub trait WalletEventListener: Send + Sync + fmt::Debug {
+    /// Handle an event
+    fn on_event(&self, event: &WalletEvent);
+}
+
+impl WalletEventListener for Mutex<Sender<WalletEvent>> {
+    fn on_event(&self, event: &WalletEvent) {
+        // the receiver might have been dropped, nothing to do in that case
+        if let Ok(sender) = self.lock() {
+            let _ = sender.send(event.clone());
+        }
+    }
+}
+
+/// Compute the events caused by going from the `before` to the `after` list of transactions
+pub(crate) fn diff_transactions(
+    before: &[TransactionDetails],
+    after: &[TransactionDetails],
+) -> Vec<WalletEvent> {
+    // evicted transactions are treated as if they weren't in the database
+    let before = before
+        .iter()
+        .filter(|tx| tx.last_evicted.is_none())
+        .map(|tx| (tx.txid, tx.confirmation_time.as_ref()))
+        .collect::<HashMap<_, _>>();
+    let after = after
+        .iter()
+        .filter(|tx| tx.last_evicted.is_none())
+        .collect::<Vec<_>>();
+    let mut events = vec![];
+
+    for tx in &after {
+        let txid = tx.txid;
+        match (before.get(&txid), &tx.confirmation_time) {
+            (None, confirmation_time) => {
+                events.push(WalletEvent::TxSeen { txid });
+                if let Some(confirmation_time) = confirmation_time {
+                    events.push(WalletEvent::TxConfirmed {
+                        txid,
+                        confirmation_time: confirmation_time.clone(),
+                    });
+                }
+            }
+            (Some(None), Some(confirmation_time)) => events.push(WalletEvent::TxConfirmed {
+                txid,
+                confirmation_time: confirmation_time.clone(),
+            }),
+            (Some(Some(previous)), confirmation_time)
+                if confirmation_time.as_ref() != Some(*previous) =>
+            {
+                events.push(WalletEvent::ReorgDetected {
+                    txid,
+                    previous: (*previous).clone(),
+                });
+                if let Some(confirmation_time) = confirmation_time {
+                    events.push(WalletEvent::TxConfirmed {
+                        txid,
+                        confirmation_time: confirmation_time.clone(),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let after = after.iter().map(|tx| tx.txid).collect::<Vec<_>>();
+    for txid in before.keys() {
+        if !after.contains(txid) {
+            events.push(WalletEvent::TxRemoved { txid: *txid });
+        }
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn get_tx(txid: &str, height: Option<u32>) -> TransactionDetails {
+        TransactionDetails {
+            transaction: None,
+            txid: Txid::from_str(txid).unwrap(),
+            received: 0,
+            sent: 0,
+            fee: None,
+            confirmation_time: height.map(|height| ConfirmationTime {
+                height,
+                timestamp: 0,
+            }),
+            verified: true,
+            last_evicted: None,
+        }
+    }
+
+    const TXID_A: &str = "4ddff1fa33af17f377f62b72357b43107c19110a8009b36fb832af505efed98a";
+    const TXID_B: &str = "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456";
+
+    #[test]
+    fn test_diff_transactions_new_and_confirmed() {
+        let before = vec![get_tx(TXID_A, None)];
+        let after = vec![get_tx(TXID_A, Some(100)), get_tx(TXID_B, None)];
+
+        let events = diff_transactions(&before, &after);
+        assert_eq!(
+            events,
+            vec![
+                WalletEvent::TxConfirmed {
+                    txid: after[0].txid,
+                    confirmation_time: after[0].confirmation_time.clone().unwrap(),
+                },
+                WalletEvent::TxSeen {
+                    txid: after[1].txid
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_transactions_reorg_and_removed() {
+        let before = vec![get_tx(TXID_A, Some(100)), get_tx(TXID_B, None)];
+        let after = vec![get_tx(TXID_A, Some(101))];
+
+        let events = diff_transactions(&before, &after);
+        assert_eq!(
+            events,
+            vec![
+                WalletEvent::ReorgDetected {
+                    txid: after[0].txid,
+                    previous: before[0].confirmation_time.clone().unwrap(),
+                },
+                WalletEvent::TxConfirmed {
+                    txid: after[0].txid,
+                    confirmation_time: after[0].confirmation_time.clone().unwrap(),
+                },
+                WalletEvent::TxRemoved {
+                    txid: before[1].txid
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_transactions_evicted() {
+        let mut evicted_b = get_tx(TXID_B, None);
+        evicted_b.last_evicted = Some(1_000);
+        let before = vec![get_tx(TXID_A, None), evicted_b];
+
+        let mut evicted_a = get_tx(TXID_A, None);
+        evicted_a.last_evicted = Some(2_000);
+        let after = vec![evicted_a, get_tx(TXID_B, None)];
+
+        let events = diff_transactions(&before, &after);
+        assert_eq!(
+            events,
+            vec![
+                WalletEvent::TxSeen {
+                    txid: after[1].txid
+                },
+                WalletEvent::TxRemoved {
+                    txid: after[0].txid
+                },
+            ]
+        );
+    }
+}
@@ -58,15 +58,37 @@
 //! println!("Exported: {}", export.to_string());
 //! # Ok::<_, bdk::Error>(())
 //! ```
+//!
+//! ### Move a `Wallet` to a different database
+//!
+//! A [`WalletSnapshot`] contains everything the wallet has stored in its database, so it can be
+//! used to move to a different database type without having to sync again from scratch.
+//!
+//! ```
+//! # use bitcoin::*;
+//! # use bdk::database::*;
+//! # use bdk::*;
+//! let descriptor = "wpkh([c258d2e4/84h/1h/0h]tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/0/*)";
+//! let wallet = Wallet::new_offline(descriptor, None, Network::Testnet, MemoryDatabase::default())?;
+//! let snapshot = wallet.export_state()?;
+//!
+//! let migrated = Wallet::from_snapshot(descriptor, None, Network::Testnet, MemoryDatabase::default(), &snapshot)?;
+//! assert_eq!(migrated.get_balance()?, wallet.get_balance()?);
+//! # Ok::<_, bdk::Error>(())
+//! ```
 
 use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 
+use bitcoin::{Script, Transaction};
+
 use miniscript::descriptor::{ShInner, WshInner};
 use miniscript::{Descriptor, DescriptorPublicKey, ScriptContext, Terminal};
 
 use crate::database::BatchDatabase;
+use crate::descriptor::multipath::combine_multipath;
+use crate::types::{KeychainKind, LocalUtxo, TransactionDetails};
 use crate::wallet::Wallet;
 
 /// Structure that contains the export of a wallet
@@ -122,7 +144,7 @@ impl WalletExport {
         let descriptor = remove_checksum(descriptor);
         Self::is_compatible_with_core(&descriptor)?;
 
-        let blockheight = match wallet.database.borrow().iter_txs(false) {
+        let blockheight = match wallet.database.read().unwrap().iter_txs(false) {
             _ if !include_blockheight => 0,
             Err(_) => 0,
             Ok(txs) => {
@@ -200,6 +222,53 @@ impl WalletExport {
             None
         }
     }
+
+    /// Return the external and internal descriptors combined into a [BIP-389] multipath
+    /// descriptor, if the internal descriptor is present
+    ///
+    /// [BIP-389]: https://github.com/bitcoin/bips/blob/master/bip-0389.mediawiki
+    pub fn multipath_descriptor(&self) -> Option<String> {
+        combine_multipath(&self.descriptor, &self.change_descriptor()?)
+    }
+}
+
+/// Snapshot of the whole state stored in a wallet's database
+///
+/// Created with [`Wallet::export_state`] and loaded back with [`Wallet::import_state`] or
+/// [`Wallet::from_snapshot`]. For a usage example see [this module](crate::wallet::export)'s
+/// documentation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WalletSnapshot {
+    /// Checksum of the external descriptor
+    pub descriptor_checksum: String,
+    /// Checksum of the internal descriptor, if present
+    pub change_descriptor_checksum: Option<String>,
+    /// Cached script_pubkeys along with their keychain and child number
+    pub script_pubkeys: Vec<(Script, KeychainKind, u32)>,
+    /// Last derivation index of each keychain
+    pub last_indexes: Vec<(KeychainKind, u32)>,
+    /// Addresses explicitly marked as used
+    pub used_marks: Vec<(KeychainKind, u32)>,
+    /// Unspent outputs
+    pub utxos: Vec<LocalUtxo>,
+    /// Raw transactions
+    pub raw_txs: Vec<Transaction>,
+    /// Transactions metadata, without the raw transactions
+    pub transactions: Vec<TransactionDetails>,
+}
+
+impl ToString for WalletSnapshot {
+    fn to_string(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+impl FromStr for WalletSnapshot {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
 }
 
 #[cfg(test)]
@@ -210,7 +279,7 @@ mod test {
 
     use super::*;
     use crate::database::{memory::MemoryDatabase, BatchOperations};
-    use crate::types::TransactionDetails;
+    use crate::types::{KeychainKind, TransactionDetails};
     use crate::wallet::Wallet;
     use crate::ConfirmationTime;
 
@@ -231,6 +300,7 @@ mod test {
                 height: 5000,
             }),
             verified: true,
+            last_evicted: None,
         })
         .unwrap();
 
@@ -257,6 +327,25 @@ mod test {
         assert_eq!(export.label, "Test Label");
     }
 
+    #[test]
+    fn test_export_multipath() {
+        let descriptor = "wpkh(xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44'/0'/0'/<0;1>/*)";
+
+        let wallet =
+            Wallet::new_offline(descriptor, None, Network::Bitcoin, get_test_db()).unwrap();
+        let export = WalletExport::export_wallet(&wallet, "Test Label", true).unwrap();
+
+        assert_eq!(export.descriptor(), descriptor.replace("<0;1>", "0"));
+        assert_eq!(
+            export.change_descriptor(),
+            Some(descriptor.replace("<0;1>", "1"))
+        );
+        assert!(export
+            .multipath_descriptor()
+            .unwrap()
+            .starts_with(&format!("{}#", descriptor)));
+    }
+
     #[test]
     #[should_panic(expected = "Incompatible change descriptor")]
     fn test_export_no_change() {
@@ -348,4 +437,60 @@ mod test {
         assert_eq!(export.blockheight, 5000);
         assert_eq!(export.label, "Test Label");
     }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let descriptor = "wpkh(xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44'/0'/0'/0/*)";
+        let change_descriptor = "wpkh(xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44'/0'/0'/1/*)";
+
+        let wallet = Wallet::new_offline(
+            descriptor,
+            Some(change_descriptor),
+            Network::Bitcoin,
+            get_test_db(),
+        )
+        .unwrap();
+        wallet
+            .get_address(crate::wallet::AddressIndex::New)
+            .unwrap();
+        wallet.mark_used(KeychainKind::External, 0).unwrap();
+
+        let snapshot = wallet.export_state().unwrap();
+        let snapshot = WalletSnapshot::from_str(&snapshot.to_string()).unwrap();
+
+        let migrated = Wallet::from_snapshot(
+            descriptor,
+            Some(change_descriptor),
+            Network::Bitcoin,
+            MemoryDatabase::new(),
+            &snapshot,
+        )
+        .unwrap();
+
+        assert_eq!(migrated.export_state().unwrap(), snapshot);
+        assert_eq!(
+            migrated.list_transactions(false).unwrap(),
+            wallet.list_transactions(false).unwrap()
+        );
+        assert!(migrated.is_used(KeychainKind::External, 0).unwrap());
+    }
+
+    #[test]
+    fn test_snapshot_different_descriptor() {
+        let descriptor = "wpkh(xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44'/0'/0'/0/*)";
+        let other_descriptor = "wpkh(xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/84'/0'/0'/0/*)";
+
+        let wallet =
+            Wallet::new_offline(descriptor, None, Network::Bitcoin, get_test_db()).unwrap();
+        let snapshot = wallet.export_state().unwrap();
+
+        let res = Wallet::from_snapshot(
+            other_descriptor,
+            None,
+            Network::Bitcoin,
+            MemoryDatabase::new(),
+            &snapshot,
+        );
+        assert!(matches!(res, Err(crate::Error::ChecksumMismatch)));
+    }
 }
@@ -0,0 +1,152 @@
+// Bitcoin Dev Kit
+// Written in 2021 by the Bitcoin Dev Kit Developers
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Thread-safe wallet handle
+//!
+//! This module defines [`WalletHandle`], a cheaply clonable handle that can be used to share a
+//! [`Wallet`] between multiple threads.
+//!
+//! Most of the methods of a [`Wallet`] only need a shared reference, so many threads can hold a
+//! [`WalletHandle::read`] guard at the same time to query the balance, list transactions or
+//! generate addresses, while writes to the internal database are serialized by the wallet
+//! itself. A [`WalletHandle::write`] guard is only required for the few methods that take
+//! `&mut self`, like [`Wallet::add_signer`].
+//!
+//! ## Example
+//!
+//! ```
+//! # use std::thread;
+//! # use bdk::bitcoin::Network;
+//! # use bdk::database::MemoryDatabase;
+//! # use bdk::wallet::{AddressIndex, WalletHandle};
+//! # use bdk::Wallet;
+//! let wallet = Wallet::new_offline(
+//!     "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)",
+//!     None,
+//!     Network::Testnet,
+//!     MemoryDatabase::default(),
+//! )?;
+//! let handle = WalletHandle::new(wallet);
+//!
+//! let reader = handle.clone();
+//! let balance = thread::spawn(move || reader.read().get_balance())
+//!     .join()
+//!     .unwrap()?;
+//! let address = handle.read().get_address(AddressIndex::New)?;
+//!
+//! println!("balance: {}, address: {}", balance, address);
+//! # Ok::<_, bdk::Error>(())
+//! ```
+
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::wallet::Wallet;
+
+/// Clonable handle to a [`Wallet`] shared between threads
+///
+/// For a usage example see [this module](crate::wallet::handle)'s documentation.
+#[derive(Debug)]
+pub struct WalletHandle<B, D> {
+    inner: Arc<RwLock<Wallet<B, D>>>,
+}
+
+impl<B, D> Clone for WalletHandle<B, D> {
+    fn clone(&self) -> Self {
+        WalletHandle {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<B, D> From<Wallet<B, D>> for WalletHandle<B, D> {
+    fn from(wallet: Wallet<B, D>) -> Self {
+        WalletHandle::new(wallet)
+    }
+}
+
+impl<B, D> WalletHandle<B, D> {
+    /// Create a new handle that takes ownership of `wallet`
+    pub fn new(wallet: Wallet<B, D>) -> Self {
+        WalletHandle {
+            inner: Arc::new(RwLock::new(wallet)),
+        }
+    }
+
+    /// Acquire shared access to the wallet
+    ///
+    /// Any number of threads can hold a read guard at the same time. This blocks while another
+    /// thread holds a [`WalletHandle::write`] guard.
+    ///
+    /// ## Panics
+    ///
+    /// This method panics if a thread panicked while holding a write guard.
+    pub fn read(&self) -> RwLockReadGuard<'_, Wallet<B, D>> {
+        self.inner.read().expect("The wallet lock is poisoned")
+    }
+
+    /// Acquire exclusive access to the wallet
+    ///
+    /// This blocks until every other read or write guard has been released.
+    ///
+    /// ## Panics
+    ///
+    /// This method panics if a thread panicked while holding a write guard.
+    pub fn write(&self) -> RwLockWriteGuard<'_, Wallet<B, D>> {
+        self.inner.write().expect("The wallet lock is poisoned")
+    }
+
+    /// Take back ownership of the wallet
+    ///
+    /// Returns the handle unchanged if it has been cloned and some of the clones are still alive.
+    pub fn try_into_inner(self) -> Result<Wallet<B, D>, Self> {
+        match Arc::try_unwrap(self.inner) {
+            Ok(lock) => Ok(lock.into_inner().expect("The wallet lock is poisoned")),
+            Err(inner) => Err(WalletHandle { inner }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::WalletHandle;
+    use crate::wallet::test::get_funded_wallet;
+    use crate::wallet::AddressIndex;
+
+    #[test]
+    fn test_wallet_handle_concurrent_readers() {
+        let (wallet, _, _) = get_funded_wallet("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)");
+        let handle = WalletHandle::new(wallet);
+
+        let threads = (0..4)
+            .map(|_| {
+                let handle = handle.clone();
+                thread::spawn(move || {
+                    let wallet = handle.read();
+                    wallet.get_address(AddressIndex::New).unwrap();
+                    wallet.get_balance().unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for t in threads {
+            assert_eq!(t.join().unwrap(), 50_000);
+        }
+
+        // every thread got a different address
+        assert_eq!(
+            handle.read().get_address(AddressIndex::New).unwrap().index,
+            4
+        );
+        assert!(handle.try_into_inner().is_ok());
+    }
+}
@@ -13,32 +13,47 @@
 //!
 //! This module defines the [`Wallet`] structure.
 
-use std::cell::RefCell;
 use std::collections::HashMap;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fmt;
-use std::ops::{Deref, DerefMut};
-use std::sync::Arc;
+use std::ops::{Deref, DerefMut, RangeBounds};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex, RwLock};
 
 use bitcoin::secp256k1::Secp256k1;
 
 use bitcoin::consensus::encode::serialize;
 use bitcoin::util::base58;
+use bitcoin::util::bip32::{DerivationPath, Fingerprint};
 use bitcoin::util::psbt::raw::Key as PsbtKey;
 use bitcoin::util::psbt::Input;
 use bitcoin::util::psbt::PartiallySignedTransaction as Psbt;
-use bitcoin::{Address, Network, OutPoint, Script, SigHashType, Transaction, TxOut, Txid};
+use bitcoin::{
+    Address, Network, OutPoint, PublicKey, Script, SigHashType, Transaction, TxOut, Txid,
+};
 
-use miniscript::descriptor::DescriptorTrait;
+use miniscript::descriptor::{DescriptorPublicKey, DescriptorSecretKey, DescriptorTrait};
 use miniscript::psbt::PsbtInputSatisfier;
+use miniscript::{ForEachKey, ToPublicKey};
 
 #[allow(unused_imports)]
 use log::{debug, error, info, trace};
 
 pub mod address_validator;
+#[cfg(feature = "bip47")]
+#[cfg_attr(docsrs, doc(cfg(feature = "bip47")))]
+pub mod bip47;
+#[cfg(feature = "bsms")]
+#[cfg_attr(docsrs, doc(cfg(feature = "bsms")))]
+pub mod bsms;
 pub mod coin_selection;
+pub mod event;
 pub mod export;
+pub mod handle;
 pub mod signer;
+#[cfg(feature = "silent-payments")]
+#[cfg_attr(docsrs, doc(cfg(feature = "silent-payments")))]
+pub mod silent_payments;
 pub mod time;
 pub mod tx_builder;
 pub(crate) mod utils;
@@ -46,30 +61,61 @@ pub(crate) mod utils;
 #[cfg_attr(docsrs, doc(cfg(feature = "verify")))]
 pub mod verify;
 
+pub use handle::WalletHandle;
 pub use utils::IsDust;
 
 use address_validator::AddressValidator;
-use coin_selection::DefaultCoinSelectionAlgorithm;
-use signer::{SignOptions, Signer, SignerOrdering, SignersContainer};
+use coin_selection::{DefaultCoinSelectionAlgorithm, TXIN_BASE_WEIGHT};
+use event::{WalletEvent, WalletEventListener};
+use export::WalletSnapshot;
+use signer::{
+    RemoteSigner, RemoteSigningReport, SignOptions, Signer, SignerOrdering, SignerRegistry,
+    SignersContainer,
+};
 use tx_builder::{BumpFee, CreateTx, FeePolicy, TxBuilder, TxParams};
-use utils::{check_nlocktime, check_nsequence_rbf, After, Older, SecpCtx, DUST_LIMIT_SATOSHI};
+use utils::{
+    check_data_carrier_scripts, check_nlocktime, check_nsequence_rbf, is_address_valid_for_network,
+    After, Older, SecpCtx, BLOCKS_TIMELOCK_THRESHOLD, DUST_LIMIT_SATOSHI, SEQUENCE_LOCKTIME_MASK,
+    SEQUENCE_LOCKTIME_TYPE_FLAG,
+};
 
 use crate::blockchain::{Blockchain, Progress};
-use crate::database::{BatchDatabase, BatchOperations, DatabaseUtils};
+use crate::database::{
+    self, BatchDatabase, BatchOperations, DatabaseUtils, PersistDatabase, PruneOptions,
+    PruneReport, RollbackReport,
+};
 use crate::descriptor::derived::AsDerived;
+use crate::descriptor::multipath::combine_multipath;
 use crate::descriptor::policy::BuildSatisfaction;
 use crate::descriptor::{
     get_checksum, into_wallet_descriptor_checked, DerivedDescriptor, DerivedDescriptorMeta,
-    DescriptorMeta, DescriptorScripts, ExtendedDescriptor, ExtractPolicy, IntoWalletDescriptor,
-    Policy, XKeyUtils,
+    DescriptorError, DescriptorMeta, DescriptorScripts, ExtendedDescriptor, ExtractKeyOrigins,
+    ExtractPolicy, IntoWalletDescriptor, KeyOriginInfo, Policy, SpkIterator, XKeyUtils,
 };
 use crate::error::Error;
-use crate::psbt::PsbtUtils;
+use crate::keys::bip85::Bip85Root;
+use crate::psbt::{
+    AddedSignature, CheckedOutput, CombinedPsbt, DescribedInput, DescribedOutput, FinalizeReport,
+    InputFinalization, PsbtCheckOptions, PsbtCheckReport, PsbtDescription, PsbtIssue, PsbtUtils,
+    PsbtWarning, SigningReport,
+};
 use crate::signer::SignerError;
 use crate::types::*;
 
 const CACHE_ADDR_BATCH_SIZE: u32 = 100;
 
+/// Number of confirmations required before the outputs of a coinbase transaction can be spent
+pub const COINBASE_MATURITY: u32 = 100;
+
+/// Maximum virtual size of a TRUC (version 3) transaction
+pub const TRUC_MAX_VSIZE: usize = 10_000;
+
+/// Maximum virtual size of a TRUC (version 3) transaction spending an unconfirmed TRUC transaction
+pub const TRUC_CHILD_MAX_VSIZE: usize = 1_000;
+
+// Pay-to-anchor output script: `OP_1 OP_PUSHBYTES_2 4e73`
+const P2A_SCRIPT: [u8; 4] = [0x51, 0x02, 0x4e, 0x73];
+
 /// A Bitcoin wallet
 ///
 /// A wallet takes descriptors, a [`database`](trait@crate::database::Database) and a
@@ -87,15 +133,18 @@ pub struct Wallet<B, D> {
 
     signers: Arc<SignersContainer>,
     change_signers: Arc<SignersContainer>,
+    signer_registry: SignerRegistry,
 
     address_validators: Vec<Arc<dyn AddressValidator>>,
+    event_listeners: Vec<Arc<dyn WalletEventListener>>,
 
     network: Network,
 
-    current_height: Option<u32>,
+    current_height: RwLock<Option<u32>>,
+    median_time_past: RwLock<Option<u32>>,
 
     client: B,
-    database: RefCell<D>,
+    database: RwLock<D>,
 
     secp: SecpCtx,
 }
@@ -113,6 +162,22 @@ where
     ) -> Result<Self, Error> {
         Self::_new(descriptor, change_descriptor, network, database, (), None)
     }
+
+    /// Create a new "offline" wallet and load its state from a [`WalletSnapshot`]
+    ///
+    /// See [`Wallet::import_state`] for more details.
+    pub fn from_snapshot<E: IntoWalletDescriptor>(
+        descriptor: E,
+        change_descriptor: Option<E>,
+        network: Network,
+        database: D,
+        snapshot: &WalletSnapshot,
+    ) -> Result<Self, Error> {
+        let wallet = Self::new_offline(descriptor, change_descriptor, network, database)?;
+        wallet.import_state(snapshot)?;
+
+        Ok(wallet)
+    }
 }
 
 impl<B, D> Wallet<B, D>
@@ -129,16 +194,32 @@ where
     ) -> Result<Self, Error> {
         let secp = Secp256k1::new();
 
-        let (descriptor, keymap) = into_wallet_descriptor_checked(descriptor, &secp, network)?;
+        // Multipath descriptors contain both keychains
+        let (descriptor, change_descriptor) =
+            match descriptor.multipath_wallet_descriptors(&secp, network)? {
+                Some(_) if change_descriptor.is_some() => {
+                    return Err(DescriptorError::MultipathWithChangeDescriptor.into())
+                }
+                Some((external, internal)) => (
+                    into_wallet_descriptor_checked(external, &secp, network)?,
+                    Some(into_wallet_descriptor_checked(internal, &secp, network)?),
+                ),
+                None => (
+                    into_wallet_descriptor_checked(descriptor, &secp, network)?,
+                    change_descriptor
+                        .map(|desc| into_wallet_descriptor_checked(desc, &secp, network))
+                        .transpose()?,
+                ),
+            };
+
+        let (descriptor, keymap) = descriptor;
         database.check_descriptor_checksum(
             KeychainKind::External,
             get_checksum(&descriptor.to_string())?.as_bytes(),
         )?;
         let signers = Arc::new(SignersContainer::from(keymap));
         let (change_descriptor, change_signers) = match change_descriptor {
-            Some(desc) => {
-                let (change_descriptor, change_keymap) =
-                    into_wallet_descriptor_checked(desc, &secp, network)?;
+            Some((change_descriptor, change_keymap)) => {
                 database.check_descriptor_checksum(
                     KeychainKind::Internal,
                     get_checksum(&change_descriptor.to_string())?.as_bytes(),
@@ -159,11 +240,14 @@ where
             change_descriptor,
             signers,
             change_signers,
+            signer_registry: SignerRegistry::new(),
             address_validators: Vec::new(),
+            event_listeners: Vec::new(),
             network,
-            current_height,
+            current_height: RwLock::new(current_height),
+            median_time_past: RwLock::new(None),
             client,
-            database: RefCell::new(database),
+            database: RwLock::new(database),
             secp,
         })
     }
@@ -250,22 +334,18 @@ where
     // transaction. Otherwise return a new address using [`Wallet::get_new_address`].
     fn get_unused_address(&self) -> Result<AddressInfo, Error> {
         let current_index = self.fetch_index(KeychainKind::External)?;
+        let script_pubkey = self
+            .descriptor
+            .as_derived(current_index, &self.secp)
+            .script_pubkey();
 
-        let derived_key = self.descriptor.as_derived(current_index, &self.secp);
-
-        let script_pubkey = derived_key.script_pubkey();
-
-        let found_used = self
-            .list_transactions(true)?
-            .iter()
-            .flat_map(|tx_details| tx_details.transaction.as_ref())
-            .flat_map(|tx| tx.output.iter())
-            .any(|o| o.script_pubkey == script_pubkey);
-
-        if found_used {
+        if self.is_used(KeychainKind::External, current_index)?
+            || self.has_received(&script_pubkey)?
+        {
             self.get_new_address()
         } else {
-            derived_key
+            self.descriptor
+                .as_derived(current_index, &self.secp)
                 .address(self.network)
                 .map(|address| AddressInfo {
                     address,
@@ -308,9 +388,253 @@ where
         }
     }
 
+    /// Return whether the address at `index` of a `keychain` has been used
+    ///
+    /// An address is considered used once it has been marked, either by [`Wallet::sync`] when it
+    /// first receives funds or explicitly with [`Wallet::mark_used`].
+    ///
+    /// Note that this methods only operate on the internal database, which first needs to be
+    /// [`Wallet::sync`] manually. The transactions synced by a previous version of the library
+    /// are only marked by the next [`Wallet::full_scan`].
+    pub fn is_used(&self, keychain: KeychainKind, index: u32) -> Result<bool, Error> {
+        let (_, keychain) = self._get_descriptor_for_keychain(keychain);
+        self.database.read().unwrap().has_used_mark(keychain, index)
+    }
+
+    /// Return the addresses of a `keychain` that have been revealed but never used
+    ///
+    /// See [`Wallet::is_used`] for what makes an address "used".
+    pub fn list_unused_addresses(&self, keychain: KeychainKind) -> Result<Vec<AddressInfo>, Error> {
+        let (descriptor, keychain) = self._get_descriptor_for_keychain(keychain);
+        let last_index = match descriptor.is_deriveable() {
+            false => Some(0),
+            true => self.database.read().unwrap().get_last_index(keychain)?,
+        };
+        let last_index = match last_index {
+            Some(i) => i,
+            None => return Ok(vec![]),
+        };
+
+        let marked = self
+            .database
+            .read()
+            .unwrap()
+            .iter_used_marks(keychain)?
+            .into_iter()
+            .collect::<HashSet<_>>();
+
+        (0..=last_index)
+            .filter(|index| !marked.contains(index))
+            .map(|index| {
+                descriptor
+                    .as_derived(index, &self.secp)
+                    .address(self.network)
+                    .map(|address| AddressInfo { index, address })
+                    .map_err(|_| Error::ScriptDoesntHaveAddressForm)
+            })
+            .collect()
+    }
+
+    /// Mark the address at `index` of a `keychain` as used
+    ///
+    /// This is useful to prevent an address that has been handed out, but hasn't received any
+    /// funds yet, from being returned again by [`AddressIndex::LastUnused`] or
+    /// [`Wallet::list_unused_addresses`]. The mark is stored in the database.
+    pub fn mark_used(&self, keychain: KeychainKind, index: u32) -> Result<(), Error> {
+        let (_, keychain) = self._get_descriptor_for_keychain(keychain);
+        self.database
+            .write()
+            .unwrap()
+            .set_used_mark(keychain, index)
+    }
+
+    /// Undo the effect of [`Wallet::mark_used`]
+    ///
+    /// Returns whether the address is now considered unused. This has no effect if the address
+    /// appears in one of the wallet's transactions, since the mark set by [`Wallet::sync`] can't
+    /// be undone.
+    pub fn unmark_used(&self, keychain: KeychainKind, index: u32) -> Result<bool, Error> {
+        let (descriptor, keychain) = self._get_descriptor_for_keychain(keychain);
+        if !self
+            .database
+            .write()
+            .unwrap()
+            .del_used_mark(keychain, index)?
+        {
+            return Ok(false);
+        }
+
+        let script_pubkey = descriptor.as_derived(index, &self.secp).script_pubkey();
+        if self.has_received(&script_pubkey)? {
+            self.database
+                .write()
+                .unwrap()
+                .set_used_mark(keychain, index)?;
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    /// Export the whole state stored in the wallet's database
+    ///
+    /// The returned [`WalletSnapshot`] can be serialized and later loaded with
+    /// [`Wallet::import_state`], for example to move the wallet to a different database type
+    /// without having to sync it again from scratch.
+    pub fn export_state(&self) -> Result<WalletSnapshot, Error> {
+        let database = self.database.read().unwrap();
+
+        let script_pubkeys = database
+            .iter_script_pubkeys(None)?
+            .into_iter()
+            .filter_map(|script| {
+                database
+                    .get_path_from_script_pubkey(&script)
+                    .map(|path| path.map(|(keychain, child)| (script, keychain, child)))
+                    .transpose()
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let mut last_indexes = vec![];
+        let mut used_marks = vec![];
+        for keychain in &[KeychainKind::External, KeychainKind::Internal] {
+            if let Some(index) = database.get_last_index(*keychain)? {
+                last_indexes.push((*keychain, index));
+            }
+            used_marks.extend(
+                database
+                    .iter_used_marks(*keychain)?
+                    .into_iter()
+                    .map(|child| (*keychain, child)),
+            );
+        }
+
+        Ok(WalletSnapshot {
+            descriptor_checksum: get_checksum(&self.descriptor.to_string())?,
+            change_descriptor_checksum: self
+                .change_descriptor
+                .as_ref()
+                .map(|desc| get_checksum(&desc.to_string()))
+                .transpose()?,
+            script_pubkeys,
+            last_indexes,
+            used_marks,
+            utxos: database.iter_utxos()?,
+            raw_txs: database.iter_raw_txs()?,
+            transactions: database.iter_txs(false)?,
+        })
+    }
+
+    /// Load a [`WalletSnapshot`] created with [`Wallet::export_state`] into the wallet's database
+    ///
+    /// The data is written on top of what's already stored in the database. Returns
+    /// [`Error::ChecksumMismatch`] if the snapshot was taken from a wallet with different
+    /// descriptors.
+    pub fn import_state(&self, snapshot: &WalletSnapshot) -> Result<(), Error> {
+        let change_descriptor_checksum = self
+            .change_descriptor
+            .as_ref()
+            .map(|desc| get_checksum(&desc.to_string()))
+            .transpose()?;
+        if snapshot.descriptor_checksum != get_checksum(&self.descriptor.to_string())?
+            || snapshot.change_descriptor_checksum != change_descriptor_checksum
+        {
+            return Err(Error::ChecksumMismatch);
+        }
+
+        let mut batch = self.database.read().unwrap().begin_batch();
+        for (script, keychain, child) in &snapshot.script_pubkeys {
+            batch.set_script_pubkey(script, *keychain, *child)?;
+        }
+        for (keychain, index) in &snapshot.last_indexes {
+            batch.set_last_index(*keychain, *index)?;
+        }
+        for (keychain, child) in &snapshot.used_marks {
+            batch.set_used_mark(*keychain, *child)?;
+        }
+        for utxo in &snapshot.utxos {
+            batch.set_utxo(utxo)?;
+        }
+        for tx in &snapshot.raw_txs {
+            batch.set_raw_tx(tx)?;
+        }
+        for details in &snapshot.transactions {
+            batch.set_tx(details)?;
+        }
+
+        self.database.write().unwrap().commit_batch(batch)
+    }
+
     /// Return whether or not a `script` is part of this wallet (either internal or external)
     pub fn is_mine(&self, script: &Script) -> Result<bool, Error> {
-        self.database.borrow().is_mine(script)
+        self.database.read().unwrap().is_mine(script)
+    }
+
+    /// Return the script pubkeys that are checked during [`Wallet::sync`]: the cached script
+    /// pubkeys of both keychains, followed by the scripts in the watchlist
+    pub fn list_script_pubkeys(&self) -> Result<Vec<Script>, Error> {
+        let database = self.database.read().unwrap();
+        let mut scripts = database.iter_script_pubkeys(None)?;
+        scripts.extend(database.iter_watched_scripts()?);
+
+        Ok(scripts)
+    }
+
+    /// Check that `address` is valid for the network of this wallet
+    ///
+    /// Returns [`Error::InvalidAddressNetwork`] if the address belongs to a different network.
+    /// Testnet addresses are also accepted on signet, and on regtest if they don't use bech32.
+    pub fn check_address_network(&self, address: &Address) -> Result<(), Error> {
+        if is_address_valid_for_network(address, self.network) {
+            Ok(())
+        } else {
+            Err(Error::InvalidAddressNetwork(address.clone()))
+        }
+    }
+
+    /// Add a script that isn't derived from the wallet's descriptors to the watchlist
+    ///
+    /// The history of the scripts in the watchlist is downloaded during [`Wallet::sync`] by the
+    /// Electrum and Esplora backends, and can be read with [`Wallet::watched_script_history`].
+    /// Their transactions and outputs are not part of the wallet, so they are never counted in
+    /// the balance or spent.
+    pub fn watch_script(&self, script: Script) -> Result<(), Error> {
+        let mut database = self.database.write().unwrap();
+        if database.get_watched_script(&script)?.is_none() {
+            database.set_watched_script(&script, &[])?;
+        }
+
+        Ok(())
+    }
+
+    /// Add an address to the watchlist, see [`Wallet::watch_script`]
+    ///
+    /// Fails with [`Error::InvalidAddressNetwork`] if the address is for a different network.
+    pub fn watch_address(&self, address: &Address) -> Result<(), Error> {
+        self.check_address_network(address)?;
+        self.watch_script(address.script_pubkey())
+    }
+
+    /// Remove a script from the watchlist, returning whether it was present
+    pub fn unwatch_script(&self, script: &Script) -> Result<bool, Error> {
+        Ok(self
+            .database
+            .write()
+            .unwrap()
+            .del_watched_script(script)?
+            .is_some())
+    }
+
+    /// Return the scripts in the watchlist
+    pub fn list_watched_scripts(&self) -> Result<Vec<Script>, Error> {
+        self.database.read().unwrap().iter_watched_scripts()
+    }
+
+    /// Return the transactions of a script in the watchlist, as of the last [`Wallet::sync`]
+    ///
+    /// Returns `None` if the script is not in the watchlist.
+    pub fn watched_script_history(&self, script: &Script) -> Result<Option<Vec<WatchedTx>>, Error> {
+        self.database.read().unwrap().get_watched_script(script)
     }
 
     /// Return the list of unspent outputs of this wallet
@@ -318,13 +642,13 @@ where
     /// Note that this methods only operate on the internal database, which first needs to be
     /// [`Wallet::sync`] manually.
     pub fn list_unspent(&self) -> Result<Vec<LocalUtxo>, Error> {
-        self.database.borrow().iter_utxos()
+        self.database.read().unwrap().iter_utxos()
     }
 
     /// Returns the `UTXO` owned by this wallet corresponding to `outpoint` if it exists in the
     /// wallet's database.
     pub fn get_utxo(&self, outpoint: OutPoint) -> Result<Option<LocalUtxo>, Error> {
-        self.database.borrow().get_utxo(&outpoint)
+        self.database.read().unwrap().get_utxo(&outpoint)
     }
 
     /// Return the list of transactions made and received by the wallet
@@ -332,10 +656,240 @@ where
     /// Optionally fill the [`TransactionDetails::transaction`] field with the raw transaction if
     /// `include_raw` is `true`.
     ///
+    /// Transactions that have been evicted from the mempool are included, with their
+    /// [`TransactionDetails::last_evicted`] field set. Their outputs are not part of the utxos,
+    /// and they can't be bumped with [`Wallet::build_fee_bump`].
+    ///
     /// Note that this methods only operate on the internal database, which first needs to be
     /// [`Wallet::sync`] manually.
     pub fn list_transactions(&self, include_raw: bool) -> Result<Vec<TransactionDetails>, Error> {
-        self.database.borrow().iter_txs(include_raw)
+        self.database.read().unwrap().iter_txs(include_raw)
+    }
+
+    /// Return the transactions of the wallet confirmed between `from_height` and `to_height`, both
+    /// included, sorted by confirmation height
+    ///
+    /// The transactions are looked up in an index of the database, so this is cheaper than
+    /// filtering the result of [`Wallet::list_transactions`] for wallets with a long history.
+    pub fn list_transactions_confirmed_between(
+        &self,
+        from_height: u32,
+        to_height: u32,
+        include_raw: bool,
+    ) -> Result<Vec<TransactionDetails>, Error> {
+        self.database
+            .read()
+            .unwrap()
+            .iter_txs_by_height(from_height, to_height, include_raw)
+    }
+
+    /// Return the transactions of the wallet that spend the same outputs
+    ///
+    /// Conflicts between unconfirmed transactions, for example after a transaction has been
+    /// replaced with RBF, and between an unconfirmed and a confirmed transaction are reported,
+    /// including the evicted transactions. See [`TxConflict::canonical`] for the transaction
+    /// that is currently valid.
+    pub fn list_conflicts(&self) -> Result<Vec<TxConflict>, Error> {
+        let txs = self.database.read().unwrap().iter_txs(true)?;
+
+        let mut spent_by = BTreeMap::<OutPoint, Vec<&TransactionDetails>>::new();
+        for details in &txs {
+            let tx = match &details.transaction {
+                Some(tx) if !tx.is_coin_base() => tx,
+                _ => continue,
+            };
+            for input in &tx.input {
+                spent_by
+                    .entry(input.previous_output)
+                    .or_default()
+                    .push(details);
+            }
+        }
+
+        Ok(spent_by
+            .into_iter()
+            .filter(|(_, spenders)| spenders.len() > 1)
+            .map(|(outpoint, spenders)| {
+                let confirmed = spenders
+                    .iter()
+                    .find(|tx| tx.confirmation_time.is_some())
+                    .map(|tx| tx.txid);
+                let active = spenders
+                    .iter()
+                    .filter(|tx| tx.last_evicted.is_none())
+                    .map(|tx| tx.txid)
+                    .collect::<Vec<_>>();
+                let canonical = match (confirmed, active.as_slice()) {
+                    (Some(txid), _) => Some(txid),
+                    (None, [txid]) => Some(*txid),
+                    _ => None,
+                };
+
+                let mut txids = spenders.iter().map(|tx| tx.txid).collect::<Vec<_>>();
+                txids.sort();
+                TxConflict {
+                    outpoint,
+                    txids,
+                    canonical,
+                }
+            })
+            .collect())
+    }
+
+    /// Return the ids of the transactions of the wallet that spend any of the outputs spent by
+    /// `txid`
+    ///
+    /// See [`Wallet::list_conflicts`].
+    pub fn conflicts_of(&self, txid: &Txid) -> Result<Vec<Txid>, Error> {
+        let conflicts = self
+            .list_conflicts()?
+            .into_iter()
+            .filter(|conflict| conflict.txids.contains(txid))
+            .flat_map(|conflict| conflict.txids)
+            .filter(|other| other != txid)
+            .collect::<BTreeSet<_>>();
+
+        Ok(conflicts.into_iter().collect())
+    }
+
+    /// Return the unconfirmed transaction `txid` and its unconfirmed ancestors, with their total
+    /// fee and weight
+    ///
+    /// The package is empty if `txid` is confirmed, evicted or unknown. This is the package that
+    /// a transaction spending an output of `txid` has to pay for with CPFP.
+    pub fn unconfirmed_ancestors(&self, txid: &Txid) -> Result<TxPackage, Error> {
+        let database = self.database.read().unwrap();
+
+        let mut visited = HashSet::new();
+        let mut to_visit = vec![*txid];
+        let mut package = TxPackage {
+            fee: Some(0),
+            ..Default::default()
+        };
+        while let Some(txid) = to_visit.pop() {
+            if !visited.insert(txid) {
+                continue;
+            }
+
+            let details = match database.get_tx(&txid, true)? {
+                Some(details)
+                    if details.confirmation_time.is_none() && details.last_evicted.is_none() =>
+                {
+                    details
+                }
+                _ => continue,
+            };
+            package.add(&details);
+            if let Some(tx) = &details.transaction {
+                to_visit.extend(tx.input.iter().map(|txin| txin.previous_output.txid));
+            }
+        }
+
+        Ok(package)
+    }
+
+    /// Return the unconfirmed transaction `txid` and its unconfirmed descendants in the wallet,
+    /// with their total fee and weight
+    ///
+    /// The package is empty if `txid` is confirmed, evicted or unknown. These are the
+    /// transactions that are replaced when `txid` is replaced with RBF.
+    pub fn unconfirmed_descendants(&self, txid: &Txid) -> Result<TxPackage, Error> {
+        let txs = self
+            .database
+            .read()
+            .unwrap()
+            .iter_txs(true)?
+            .into_iter()
+            .filter(|tx| tx.confirmation_time.is_none() && tx.last_evicted.is_none())
+            .map(|tx| (tx.txid, tx))
+            .collect::<HashMap<_, _>>();
+
+        let mut visited = HashSet::new();
+        let mut to_visit = vec![*txid];
+        let mut package = TxPackage {
+            fee: Some(0),
+            ..Default::default()
+        };
+        while let Some(txid) = to_visit.pop() {
+            if !visited.insert(txid) {
+                continue;
+            }
+
+            if let Some(details) = txs.get(&txid) {
+                package.add(details);
+                to_visit.extend(
+                    txs.values()
+                        .filter(|tx| {
+                            tx.transaction.as_ref().map_or(false, |tx| {
+                                tx.input
+                                    .iter()
+                                    .any(|txin| txin.previous_output.txid == txid)
+                            })
+                        })
+                        .map(|tx| tx.txid),
+                );
+            }
+        }
+
+        Ok(package)
+    }
+
+    /// Mark the transaction `txid` as evicted from the mempool at `timestamp`
+    ///
+    /// This can be used to report evictions seen by chain sources other than the wallet's
+    /// blockchain, which already detects them while syncing. The transaction and all its
+    /// unconfirmed descendants stop counting toward the balance of the wallet: their outputs are
+    /// removed from the utxos, and the utxos they spent become spendable again unless they are
+    /// spent by a different transaction. A [`WalletEvent::TxRemoved`] is emitted for every evicted
+    /// transaction.
+    ///
+    /// Returns the ids of the evicted transactions, which is empty if the transaction is unknown or
+    /// was already evicted.
+    pub fn mark_evicted(&self, txid: &Txid, timestamp: u64) -> Result<Vec<Txid>, Error> {
+        let evicted = database::evict_txs(
+            self.database.write().unwrap().deref_mut(),
+            &[*txid],
+            timestamp,
+        )?;
+        for txid in &evicted {
+            self.emit_event(WalletEvent::TxRemoved { txid: *txid });
+        }
+
+        Ok(evicted)
+    }
+
+    /// Disconnect the blocks above `height` after a reorganization of the chain
+    ///
+    /// The transactions confirmed in the disconnected blocks become unconfirmed until the next
+    /// [`Wallet::sync`] finds them in the new chain, and the coinbase transactions of those blocks
+    /// are marked as evicted. See [`database::rollback_to`] for the details.
+    ///
+    /// A [`WalletEvent::ReorgDetected`] is emitted for every unconfirmed transaction and a
+    /// [`WalletEvent::TxRemoved`] for every evicted one.
+    pub fn rollback_to(&self, height: u32) -> Result<RollbackReport, Error> {
+        let mut database = self.database.write().unwrap();
+        let txs_before = match self.event_listeners.is_empty() {
+            true => vec![],
+            false => database.iter_txs(false)?,
+        };
+        let report = database::rollback_to(database.deref_mut(), height, time::get_timestamp())?;
+
+        if !self.event_listeners.is_empty() {
+            let txs_after = database.iter_txs(false)?;
+            drop(database);
+            for event in event::diff_transactions(&txs_before, &txs_after) {
+                self.emit_event(event);
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Remove the transactions that are not needed anymore from the database
+    ///
+    /// See [`PruneOptions`] for the transactions that can be removed.
+    pub fn prune(&self, options: &PruneOptions) -> Result<PruneReport, Error> {
+        database::prune(self.database.write().unwrap().deref_mut(), options)
     }
 
     /// Return the balance, meaning the sum of this wallet's unspent outputs' values
@@ -349,58 +903,403 @@ where
             .fold(0, |sum, i| sum + i.txout.value))
     }
 
-    /// Add an external signer
+    /// Return the balance the wallet had at the block `height`
     ///
-    /// See [the `signer` module](signer) for an example.
-    pub fn add_signer(
-        &mut self,
-        keychain: KeychainKind,
-        ordering: SignerOrdering,
-        signer: Arc<dyn Signer>,
-    ) {
-        let signers = match keychain {
-            KeychainKind::External => Arc::make_mut(&mut self.signers),
-            KeychainKind::Internal => Arc::make_mut(&mut self.change_signers),
-        };
+    /// This is computed by replaying the transactions confirmed at or before `height`, so it only
+    /// takes into account transactions whose raw data is stored in the database. Unconfirmed
+    /// transactions are ignored.
+    ///
+    /// Note that this methods only operate on the internal database, which first needs to be
+    /// [`Wallet::sync`] manually.
+    pub fn balance_at(&self, height: u32) -> Result<u64, Error> {
+        let database = self.database.read().unwrap();
+        let txs = database
+            .iter_txs(true)?
+            .into_iter()
+            .filter(|tx| matches!(&tx.confirmation_time, Some(c) if c.height <= height))
+            .filter_map(|tx| tx.transaction)
+            .collect::<Vec<_>>();
+        let spent = txs
+            .iter()
+            .flat_map(|tx| tx.input.iter().map(|input| input.previous_output))
+            .collect::<HashSet<_>>();
+
+        let mut balance = 0;
+        for tx in &txs {
+            let txid = tx.txid();
+            for (vout, output) in tx.output.iter().enumerate() {
+                if !spent.contains(&OutPoint::new(txid, vout as u32))
+                    && database.is_mine(&output.script_pubkey)?
+                {
+                    balance += output.value;
+                }
+            }
+        }
 
-        signers.add_external(signer.id(&self.secp), ordering, signer);
+        Ok(balance)
     }
 
-    /// Add an address validator
+    /// Return the height of the chain tip, as reported by the blockchain when the wallet was
+    /// created or last synced
     ///
-    /// See [the `address_validator` module](address_validator) for an example.
-    pub fn add_address_validator(&mut self, validator: Arc<dyn AddressValidator>) {
-        self.address_validators.push(validator);
+    /// The confirmations of the transactions and the maturity of the coinbase outputs are counted
+    /// from it. It's `None` for offline wallets.
+    pub fn current_height(&self) -> Option<u32> {
+        *self.current_height.read().unwrap()
     }
 
-    /// Start building a transaction.
-    ///
-    /// This returns a blank [`TxBuilder`] from which you can specify the parameters for the transaction.
+    /// Return the median time past (BIP113) of the chain tip, as reported by the blockchain when
+    /// the wallet was created or last synced
     ///
-    /// ## Example
+    /// Time-based timelocks are checked against it when building and finalizing transactions.
+    /// It's `None` for offline wallets and for backends that can't compute it.
+    pub fn median_time_past(&self) -> Option<u32> {
+        *self.median_time_past.read().unwrap()
+    }
+
+    /// Return the balance split into confirmed, unconfirmed and immature coinbase outputs
     ///
-    /// ```
-    /// # use std::str::FromStr;
-    /// # use bitcoin::*;
-    /// # use bdk::*;
-    /// # use bdk::database::*;
-    /// # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
-    /// # let wallet = doctest_wallet!();
-    /// # let to_address = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt").unwrap();
-    /// let (psbt, details) = {
-    ///    let mut builder =  wallet.build_tx();
-    ///    builder
-    ///        .add_recipient(to_address.script_pubkey(), 50_000);
-    ///    builder.finish()?
-    /// };
+    /// Immature coinbase outputs can't be spent until they reach [`COINBASE_MATURITY`]
+    /// confirmations, so they are excluded from [`Balance::spendable`].
     ///
-    /// // sign and broadcast ...
-    /// # Ok::<(), bdk::Error>(())
-    /// ```
+    /// Note that this methods only operate on the internal database, which first needs to be
+    /// [`Wallet::sync`] manually.
+    pub fn get_balance_details(&self) -> Result<Balance, Error> {
+        let database = self.database.read().unwrap();
+        let mut balance = Balance::default();
+
+        for utxo in database.iter_utxos()? {
+            let tx = database.get_tx(&utxo.outpoint.txid, true)?;
+            if self.is_immature_coinbase(tx.as_ref()) {
+                balance.immature += utxo.txout.value;
+            } else if tx.map(|tx| tx.confirmation_time.is_some()) == Some(true) {
+                balance.confirmed += utxo.txout.value;
+            } else {
+                balance.unconfirmed += utxo.txout.value;
+            }
+        }
+
+        Ok(balance)
+    }
+
+    /// Return whether `utxo` is the output of a coinbase transaction that hasn't reached
+    /// [`COINBASE_MATURITY`] yet
     ///
-    /// [`TxBuilder`]: crate::TxBuilder
-    pub fn build_tx(&self) -> TxBuilder<'_, B, D, DefaultCoinSelectionAlgorithm, CreateTx> {
-        TxBuilder {
+    /// Immature outputs are never picked by the coin selection. If the current height is unknown,
+    /// as in offline wallets, confirmed coinbase outputs are assumed to be mature.
+    pub fn is_immature(&self, utxo: &LocalUtxo) -> Result<bool, Error> {
+        let tx = self
+            .database
+            .read()
+            .unwrap()
+            .get_tx(&utxo.outpoint.txid, true)?;
+        Ok(self.is_immature_coinbase(tx.as_ref()))
+    }
+
+    // If the current height is unknown confirmed transactions are assumed to have exactly one
+    // confirmation
+    fn get_confirmations(&self, tx: Option<&TransactionDetails>) -> u32 {
+        match (
+            tx.and_then(|tx| tx.confirmation_time.as_ref()),
+            self.current_height(),
+        ) {
+            (None, _) => 0,
+            (Some(confirmation_time), Some(current_height)) => {
+                current_height.saturating_sub(confirmation_time.height) + 1
+            }
+            (Some(_), None) => 1,
+        }
+    }
+
+    // Number of confirmations a utxo needs for the relative timelock `csv` to be satisfied. Only
+    // timelocks expressed in blocks can be checked, and only if we know the current height
+    fn csv_min_confirmations(&self, csv: Option<u32>) -> u32 {
+        match (csv, self.current_height()) {
+            (Some(csv), Some(_)) if csv & SEQUENCE_LOCKTIME_TYPE_FLAG == 0 => {
+                csv & SEQUENCE_LOCKTIME_MASK
+            }
+            _ => 0,
+        }
+    }
+
+    // Returns the fees, in satoshi, that a transaction spending an output of `txid` has to pay on
+    // top of its own for the unconfirmed ancestors to reach `fee_rate`
+    fn get_ancestors_fee_deficit(&self, txid: &Txid, fee_rate: FeeRate) -> Result<u64, Error> {
+        let ancestors = self.unconfirmed_ancestors(txid)?;
+
+        match ancestors.fee {
+            Some(fee) => {
+                let required = (ancestors.weight.vbytes() * fee_rate.as_sat_vb()).ceil() as u64;
+                Ok(required.saturating_sub(fee))
+            }
+            // without the fee we can't tell how much the ancestors are paying
+            None => Ok(0),
+        }
+    }
+
+    // Check that `tx` respects the topology restrictions of TRUC (version 3) transactions, see
+    // BIP-431. The restrictions also apply to non-TRUC transactions, which can't spend unconfirmed
+    // TRUC outputs.
+    fn check_truc_policy(&self, tx: &Transaction, signed_weight: usize) -> Result<(), Error> {
+        let is_truc = tx.version == 3;
+        let vsize = signed_weight.vbytes().ceil() as usize;
+        if is_truc && vsize > TRUC_MAX_VSIZE {
+            return Err(Error::TrucViolation(format!(
+                "the transaction is {} vbytes, more than the maximum of {}",
+                vsize, TRUC_MAX_VSIZE
+            )));
+        }
+
+        let database = self.database.read().unwrap();
+        let mut unconfirmed_parents = HashSet::new();
+        for txin in &tx.input {
+            let parent = match database.get_tx(&txin.previous_output.txid, true)? {
+                Some(details) if details.confirmation_time.is_none() => details,
+                _ => continue,
+            };
+            let parent_tx = match parent.transaction {
+                Some(tx) => tx,
+                None => continue,
+            };
+
+            match (is_truc, parent_tx.version == 3) {
+                (true, false) => {
+                    return Err(Error::TrucViolation(format!(
+                        "a TRUC transaction can't spend the unconfirmed non-TRUC transaction {}",
+                        parent.txid
+                    )))
+                }
+                (false, true) => {
+                    return Err(Error::TrucViolation(format!(
+                        "a non-TRUC transaction can't spend the unconfirmed TRUC transaction {}",
+                        parent.txid
+                    )))
+                }
+                (false, false) => continue,
+                (true, true) => {}
+            }
+
+            // the parent can't have unconfirmed ancestors itself
+            for parent_txin in &parent_tx.input {
+                if let Some(details) = database.get_tx(&parent_txin.previous_output.txid, false)? {
+                    if details.confirmation_time.is_none() {
+                        return Err(Error::TrucViolation(format!(
+                            "the unconfirmed TRUC transaction {} has unconfirmed ancestors",
+                            parent.txid
+                        )));
+                    }
+                }
+            }
+
+            unconfirmed_parents.insert(parent.txid);
+        }
+
+        if unconfirmed_parents.len() > 1 {
+            return Err(Error::TrucViolation(
+                "a TRUC transaction can have at most one unconfirmed parent".into(),
+            ));
+        }
+        if !unconfirmed_parents.is_empty() && vsize > TRUC_CHILD_MAX_VSIZE {
+            return Err(Error::TrucViolation(format!(
+                "the transaction spends an unconfirmed TRUC transaction and is {} vbytes, more than the maximum of {}",
+                vsize, TRUC_CHILD_MAX_VSIZE
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn is_immature_coinbase(&self, tx: Option<&TransactionDetails>) -> bool {
+        let tx = match tx {
+            Some(tx) => tx,
+            None => return false,
+        };
+        if !tx
+            .transaction
+            .as_ref()
+            .map_or(false, |tx| tx.is_coin_base())
+        {
+            return false;
+        }
+
+        // Coinbase transactions are only evicted when their block is disconnected, which also
+        // clears their confirmation time, so they are never mature. Without knowing the current
+        // height we can't tell, so we don't hold the output back
+        match (&tx.confirmation_time, self.current_height()) {
+            (None, _) => true,
+            (Some(confirmation_time), Some(current_height)) => {
+                current_height.saturating_sub(confirmation_time.height) + 1 < COINBASE_MATURITY
+            }
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Add an external signer
+    ///
+    /// See [the `signer` module](signer) for an example.
+    pub fn add_signer(
+        &mut self,
+        keychain: KeychainKind,
+        ordering: SignerOrdering,
+        signer: Arc<dyn Signer>,
+    ) {
+        let signers = match keychain {
+            KeychainKind::External => Arc::make_mut(&mut self.signers),
+            KeychainKind::Internal => Arc::make_mut(&mut self.change_signers),
+        };
+
+        signers.add_external(signer.id(&self.secp), ordering, signer);
+    }
+
+    /// Replace the signers of `keychain` that hold a secret key with
+    /// [`EncryptedSigner`](crate::keys::encrypted::EncryptedSigner)s, encrypting their keys with
+    /// `passphrase`
+    ///
+    /// After this call the secret keys are only decrypted while signing, with the passphrase
+    /// returned by `provider`. The encrypted keys are returned so that they can be stored and
+    /// added back to the wallet with [`Wallet::add_signer`] when it's loaded again. For an
+    /// example see the [`keys::encrypted`](crate::keys::encrypted) module.
+    #[cfg(feature = "encrypted-keys")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "encrypted-keys")))]
+    pub fn encrypt_signers(
+        &mut self,
+        keychain: KeychainKind,
+        passphrase: &str,
+        provider: Arc<dyn crate::keys::encrypted::KeyProvider>,
+    ) -> Result<Vec<crate::keys::encrypted::EncryptedDescriptorSecretKey>, Error> {
+        let signers = match keychain {
+            KeychainKind::External => Arc::make_mut(&mut self.signers),
+            KeychainKind::Internal => Arc::make_mut(&mut self.change_signers),
+        };
+
+        Ok(signers.encrypt(passphrase, provider, &self.secp)?)
+    }
+
+    /// Register a signer for the keys derived from `origin`, the fingerprint of a master key and a
+    /// derivation path
+    ///
+    /// Registered signers are only asked to sign the inputs with a key derived from their origin,
+    /// after the signers added with [`Wallet::add_signer`]. Since this only takes a shared
+    /// reference, signers can be added to a wallet at runtime, for instance when a hardware
+    /// device is connected. Optionally returns the signer that was previously registered for the
+    /// same origin, if any.
+    ///
+    /// See [`SignerRegistry`] for more details.
+    pub fn register_signer(
+        &self,
+        origin: (Fingerprint, DerivationPath),
+        signer: Arc<dyn Signer>,
+    ) -> Option<Arc<dyn Signer>> {
+        self.signer_registry.register(origin, signer)
+    }
+
+    /// Remove the signer registered for `origin` with [`Wallet::register_signer`] and return it
+    pub fn unregister_signer(
+        &self,
+        origin: &(Fingerprint, DerivationPath),
+    ) -> Option<Arc<dyn Signer>> {
+        self.signer_registry.unregister(origin)
+    }
+
+    /// Return the registry of signers keyed by key origin
+    ///
+    /// This can be used to find which registered signers can sign an input, see
+    /// [`SignerRegistry::signers_for_input`].
+    pub fn signer_registry(&self) -> &SignerRegistry {
+        &self.signer_registry
+    }
+
+    /// Return the [BIP-85](crate::keys::bip85) root of the wallet, the first master xprv found in
+    /// its signers
+    ///
+    /// Returns `None` if the wallet doesn't contain the private key of a master xprv (with a
+    /// depth of zero and no origin path), for instance if it's watch-only or if its descriptors
+    /// only contain the xprvs of accounts.
+    pub fn bip85_root(&self) -> Option<Bip85Root> {
+        self.signers
+            .signers()
+            .into_iter()
+            .chain(self.change_signers.signers())
+            .filter_map(|signer| match signer.descriptor_secret_key() {
+                Some(DescriptorSecretKey::XPrv(xkey))
+                    if xkey.xkey.depth == 0
+                        && xkey
+                            .origin
+                            .as_ref()
+                            .map_or(true, |(_, path)| path.as_ref().is_empty()) =>
+                {
+                    Some(Bip85Root::new(xkey.xkey))
+                }
+                _ => None,
+            })
+            .next()
+    }
+
+    /// Add an address validator
+    ///
+    /// See [the `address_validator` module](address_validator) for an example.
+    pub fn add_address_validator(&mut self, validator: Arc<dyn AddressValidator>) {
+        self.address_validators.push(validator);
+    }
+
+    /// Run all the attached address validators on the address at `index` of a `keychain`
+    ///
+    /// Validators are normally only polled when a new address is generated. This method can be
+    /// used to show again an address that has already been revealed, for instance one returned by
+    /// [`AddressIndex::LastUnused`] or [`AddressIndex::Peek`], on a hardware wallet before handing
+    /// it out.
+    ///
+    /// See [the `address_validator` module](address_validator) for an example of a validator.
+    pub fn verify_address(&self, keychain: KeychainKind, index: u32) -> Result<(), Error> {
+        let (_, keychain) = self._get_descriptor_for_keychain(keychain);
+        self.validate_address(keychain, index)
+    }
+
+    /// Add a listener that will be notified of every [`WalletEvent`]
+    ///
+    /// See [the `event` module](event) for more details.
+    pub fn add_event_listener(&mut self, listener: Arc<dyn WalletEventListener>) {
+        self.event_listeners.push(listener);
+    }
+
+    /// Subscribe to the wallet's events, returning the receiving end of a channel
+    ///
+    /// See [the `event` module](event) for an example.
+    pub fn subscribe(&mut self) -> Receiver<WalletEvent> {
+        let (sender, receiver) = channel();
+        self.add_event_listener(Arc::new(Mutex::new(sender)));
+
+        receiver
+    }
+
+    /// Start building a transaction.
+    ///
+    /// This returns a blank [`TxBuilder`] from which you can specify the parameters for the transaction.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use std::str::FromStr;
+    /// # use bitcoin::*;
+    /// # use bdk::*;
+    /// # use bdk::database::*;
+    /// # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+    /// # let wallet = doctest_wallet!();
+    /// # let to_address = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt").unwrap();
+    /// let (psbt, details) = {
+    ///    let mut builder =  wallet.build_tx();
+    ///    builder
+    ///        .add_recipient(to_address.script_pubkey(), 50_000);
+    ///    builder.finish()?
+    /// };
+    ///
+    /// // sign and broadcast ...
+    /// # Ok::<(), bdk::Error>(())
+    /// ```
+    ///
+    /// [`TxBuilder`]: crate::TxBuilder
+    pub fn build_tx(&self) -> TxBuilder<'_, B, D, DefaultCoinSelectionAlgorithm, CreateTx> {
+        TxBuilder {
             wallet: self,
             params: TxParams::default(),
             coin_selection: DefaultCoinSelectionAlgorithm::default(),
@@ -408,6 +1307,162 @@ where
         }
     }
 
+    /// Build the transactions required to move all the funds of this wallet to `descriptor` and
+    /// `change_descriptor`
+    ///
+    /// This is meant to be used when rotating the wallet to a new pair of descriptors: the
+    /// spendable unspent outputs are split into batches of at most `max_inputs` inputs, and every
+    /// batch is swept with a transaction that pays `fee_rate` and drains to the next address of
+    /// the new descriptor of the same keychain. Building fails with [`Error::FeeRateTooHigh`] if
+    /// the fee rate of any of the transactions ends up above `max_fee_rate`.
+    ///
+    /// The progress of the sweep is saved in the database: the outputs spent by the returned
+    /// transactions are not swept again, and the next call derives its destinations after the
+    /// ones already used. Immature coinbase outputs are skipped, so running the sweep again after
+    /// they mature (and after a [`Wallet::sync`]) moves what is left. The rotation is complete
+    /// once [`Wallet::list_unspent`] returns an empty list.
+    ///
+    /// The returned transactions are independent from each other and can be signed and broadcast
+    /// in any order. If some of them are abandoned, or to sweep to a different pair of
+    /// descriptors, call [`Wallet::reset_sweep`] first.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use bdk::*;
+    /// # let wallet = doctest_wallet!();
+    /// let new_descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/0/*)";
+    /// let new_change_descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/1/*)";
+    /// let sweep = wallet.build_sweep(
+    ///     new_descriptor,
+    ///     new_change_descriptor,
+    ///     FeeRate::from_sat_per_vb(5.0),
+    ///     FeeRate::from_sat_per_vb(10.0),
+    ///     50,
+    /// )?;
+    /// for (_psbt, _details) in sweep {
+    ///     // sign and broadcast ...
+    /// }
+    /// # Ok::<(), bdk::Error>(())
+    /// ```
+    pub fn build_sweep<E: IntoWalletDescriptor>(
+        &self,
+        descriptor: E,
+        change_descriptor: E,
+        fee_rate: FeeRate,
+        max_fee_rate: FeeRate,
+        max_inputs: usize,
+    ) -> Result<Vec<(Psbt, TransactionDetails)>, Error> {
+        if max_inputs == 0 {
+            return Err(Error::Generic(
+                "A sweep transaction must have at least one input".into(),
+            ));
+        }
+
+        let (descriptor, _) = into_wallet_descriptor_checked(descriptor, &self.secp, self.network)?;
+        let (change_descriptor, _) =
+            into_wallet_descriptor_checked(change_descriptor, &self.secp, self.network)?;
+
+        let swept = self
+            .database
+            .read()
+            .unwrap()
+            .iter_swept_utxos()?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let mut utxos = vec![];
+        for utxo in self.list_unspent()? {
+            if !swept.contains(&utxo.outpoint) && !self.is_immature(&utxo)? {
+                utxos.push(utxo);
+            }
+        }
+
+        let mut sweep = vec![];
+        let mut next_indexes = vec![];
+        for (keychain, descriptor) in &[
+            (KeychainKind::External, &descriptor),
+            (KeychainKind::Internal, &change_descriptor),
+        ] {
+            let outpoints = utxos
+                .iter()
+                .filter(|utxo| utxo.keychain == *keychain)
+                .map(|utxo| utxo.outpoint)
+                .collect::<Vec<_>>();
+            if outpoints.is_empty() {
+                continue;
+            }
+
+            let mut index = self
+                .database
+                .read()
+                .unwrap()
+                .get_sweep_index(*keychain)?
+                .unwrap_or(0);
+            for outpoints in outpoints.chunks(max_inputs) {
+                let script_pubkey = descriptor.as_derived(index, &self.secp).script_pubkey();
+
+                let mut builder = self.build_tx();
+                builder
+                    .add_utxos(outpoints)?
+                    .manually_selected_only()
+                    .drain_to(script_pubkey)
+                    .fee_rate(fee_rate)
+                    .max_fee_rate(max_fee_rate);
+                sweep.push(builder.finish()?);
+
+                index += 1;
+            }
+            next_indexes.push((*keychain, index));
+        }
+
+        // only save the progress once all the transactions have been built
+        let mut database = self.database.write().unwrap();
+        let mut batch = database.begin_batch();
+        for utxo in &utxos {
+            batch.set_swept_utxo(&utxo.outpoint)?;
+        }
+        for (keychain, index) in next_indexes {
+            batch.set_sweep_index(keychain, index)?;
+        }
+        database.commit_batch(batch)?;
+
+        Ok(sweep)
+    }
+
+    /// Forget the progress of the sweeps built with [`Wallet::build_sweep`]
+    ///
+    /// After this call the outputs already swept can be swept again, and the destinations are
+    /// derived again starting from index `0`. This should be used if the sweep transactions are
+    /// abandoned before being broadcast, or before sweeping to a different pair of descriptors.
+    pub fn reset_sweep(&self) -> Result<(), Error> {
+        let mut database = self.database.write().unwrap();
+        let mut batch = database.begin_batch();
+        for outpoint in database.iter_swept_utxos()? {
+            batch.del_swept_utxo(&outpoint)?;
+        }
+        for keychain in &[KeychainKind::External, KeychainKind::Internal] {
+            batch.del_sweep_index(*keychain)?;
+        }
+        database.commit_batch(batch)
+    }
+
+    // Like Bitcoin Core, use the current height, as refreshed by the last sync, as nLockTime so
+    // that the transaction can't be mined in a block that reorgs the tip, and occasionally go
+    // back up to 100 blocks so that transactions that have been delayed don't stand out.
+    fn anti_fee_sniping_locktime(&self, params: &TxParams) -> Option<u32> {
+        use rand::Rng;
+
+        if params.disable_anti_fee_sniping {
+            return None;
+        }
+
+        let mut rng = rand::thread_rng();
+        self.current_height().map(|height| match rng.gen_bool(0.1) {
+            true => height.saturating_sub(rng.gen_range(0, 100)),
+            false => height,
+        })
+    }
+
     pub(crate) fn create_tx<Cs: coin_selection::CoinSelectionAlgorithm<D>>(
         &self,
         coin_selection: Cs,
@@ -484,9 +1539,38 @@ where
             _ => 1,
         };
 
+        // Don't build transactions that can't be mined until the absolute timelock required by
+        // the policy expires. Timelocks expressed as timestamps are compared to the median time
+        // past, which must be strictly greater than the nLockTime
+        if let (Some(required), Some(current_height)) =
+            (requirements.timelock, self.current_height())
+        {
+            if required < BLOCKS_TIMELOCK_THRESHOLD && required > current_height {
+                return Err(Error::TimelockNotSatisfied {
+                    required,
+                    current_height,
+                });
+            }
+        }
+        if let (Some(required), Some(median_time_past)) =
+            (requirements.timelock, self.median_time_past())
+        {
+            if required >= BLOCKS_TIMELOCK_THRESHOLD && required >= median_time_past {
+                return Err(Error::TimeTimelockNotSatisfied {
+                    required,
+                    median_time_past,
+                });
+            }
+        }
+
         let lock_time = match params.locktime {
-            // No nLockTime, default to 0
-            None => requirements.timelock.unwrap_or(0),
+            // No nLockTime, use the current height to discourage fee sniping if it's compatible
+            // with the constraints, otherwise default to the required value or 0
+            None => match (requirements.timelock, self.anti_fee_sniping_locktime(&params)) {
+                (None, Some(x)) => x,
+                (Some(required), Some(x)) if check_nlocktime(x, required) => x,
+                (required, _) => required.unwrap_or(0),
+            },
             // Specific nLockTime required and we have no constraints, so just set to that value
             Some(x) if requirements.timelock.is_none() => x,
             // Specific nLockTime required and it's compatible with the constraints
@@ -529,34 +1613,37 @@ where
             (Some(rbf), _) => rbf.get_value(),
         };
 
-        let (fee_rate, mut fee_amount) = match params
-            .fee_policy
-            .as_ref()
-            .unwrap_or(&FeePolicy::FeeRate(FeeRate::default()))
-        {
-            //FIXME: see https://github.com/bitcoindevkit/bdk/issues/256
-            FeePolicy::FeeAmount(fee) => {
-                if let Some(previous_fee) = params.bumping_fee {
-                    if *fee < previous_fee.absolute {
-                        return Err(Error::FeeTooLow {
-                            required: previous_fee.absolute,
-                        });
+        // transactions with an ephemeral anchor pay zero fees, the fees are paid by their child
+        let default_fee_policy = match params.ephemeral_anchor {
+            true => FeePolicy::FeeAmount(0),
+            false => FeePolicy::FeeRate(FeeRate::default()),
+        };
+        let (fee_rate, mut fee_amount) =
+            match params.fee_policy.as_ref().unwrap_or(&default_fee_policy) {
+                //FIXME: see https://github.com/bitcoindevkit/bdk/issues/256
+                FeePolicy::FeeAmount(fee) => {
+                    if let Some(previous_fee) = params.bumping_fee {
+                        if *fee < previous_fee.absolute {
+                            return Err(Error::FeeTooLow {
+                                required: previous_fee.absolute,
+                            });
+                        }
                     }
+                    (FeeRate::from_sat_per_vb(0.0), *fee as f32)
                 }
-                (FeeRate::from_sat_per_vb(0.0), *fee as f32)
-            }
-            FeePolicy::FeeRate(rate) => {
-                if let Some(previous_fee) = params.bumping_fee {
-                    let required_feerate = FeeRate::from_sat_per_vb(previous_fee.rate + 1.0);
-                    if *rate < required_feerate {
-                        return Err(Error::FeeRateTooLow {
-                            required: required_feerate,
-                        });
+                FeePolicy::FeeRate(rate) => {
+                    if let Some(previous_fee) = params.bumping_fee {
+                        let required_feerate = FeeRate::from_sat_per_vb(previous_fee.rate + 1.0);
+                        if *rate < required_feerate {
+                            return Err(Error::FeeRateTooLow {
+                                required: required_feerate,
+                            });
+                        }
                     }
+                    (*rate, 0.0)
                 }
-                (*rate, 0.0)
-            }
-        };
+                FeePolicy::Unavailable => return Err(Error::FeeRateUnavailable),
+            };
 
         let mut tx = Transaction {
             version,
@@ -569,6 +1656,23 @@ where
             return Err(Error::NoUtxosSelected);
         }
 
+        if !params.drain_to_multiple.is_empty() {
+            if params.drain_to.is_some() {
+                return Err(Error::Generic(
+                    "`drain_to` and `drain_to_multiple` can't be used together".into(),
+                ));
+            }
+            if params
+                .drain_to_multiple
+                .iter()
+                .any(|(_, weight)| *weight == 0)
+            {
+                return Err(Error::Generic(
+                    "The weights of `drain_to_multiple` must be greater than zero".into(),
+                ));
+            }
+        }
+
         // we keep it as a float while we accumulate it, and only round it at the end
         let mut outgoing: u64 = 0;
         let mut received: u64 = 0;
@@ -598,6 +1702,33 @@ where
             outgoing += value;
         }
 
+        if params.ephemeral_anchor {
+            let anchor = TxOut {
+                script_pubkey: Script::from(P2A_SCRIPT.to_vec()),
+                value: 0,
+            };
+            fee_amount += calc_fee_bytes(serialize(&anchor).len() * 4);
+            tx.output.push(anchor);
+        }
+
+        if let Some(script) = params.data.iter().find(|script| !script.is_op_return()) {
+            return Err(Error::NonStandardData(format!(
+                "The script `{}` is not a data carrier script",
+                script
+            )));
+        }
+        if !params.allow_nonstandard_data {
+            check_data_carrier_scripts(&params.data).map_err(Error::NonStandardData)?;
+        }
+        for script_pubkey in &params.data {
+            let data_output = TxOut {
+                script_pubkey: script_pubkey.clone(),
+                value: 0,
+            };
+            fee_amount += calc_fee_bytes(serialize(&data_output).len() * 4);
+            tx.output.push(data_output);
+        }
+
         if params.change_policy != tx_builder::ChangeSpendPolicy::ChangeAllowed
             && self.change_descriptor.is_none()
         {
@@ -606,20 +1737,102 @@ where
             ));
         }
 
-        let (required_utxos, optional_utxos) = self.preselect_utxos(
+        let (mut required_utxos, mut optional_utxos) = self.preselect_utxos(
             params.change_policy,
             &params.unspendable,
             params.utxos.clone(),
             params.drain_wallet,
             params.manually_selected_only,
-            params.bumping_fee.is_some(), // we mandate confirmed transactions if we're bumping the fee
+            std::cmp::max(
+                match params.bumping_fee {
+                    // we mandate confirmed transactions if we're bumping the fee
+                    Some(_) => std::cmp::max(params.min_confirmations, 1),
+                    None => params.min_confirmations,
+                },
+                self.csv_min_confirmations(requirements.csv),
+            ),
         )?;
 
-        let coin_selection = coin_selection.coin_select(
-            self.database.borrow().deref(),
+        if !params.disable_grinding {
+            for u in required_utxos.iter_mut().chain(optional_utxos.iter_mut()) {
+                if let Utxo::Local(local) = &u.utxo {
+                    let savings = self
+                        .get_descriptor_for_keychain(local.keychain)
+                        .low_r_weight_savings();
+                    u.satisfaction_weight = u.satisfaction_weight.saturating_sub(savings);
+                }
+            }
+        }
+
+        if let Some(max_input_weight) = params.consolidate {
+            // spend the smallest utxos first, skipping the ones that are not worth spending at
+            // this fee rate
+            optional_utxos.retain(|u| {
+                let fee = calc_fee_bytes(TXIN_BASE_WEIGHT + u.satisfaction_weight).ceil() as u64;
+                u.utxo.txout().value > fee
+            });
+            optional_utxos.sort_unstable_by_key(|u| u.utxo.txout().value);
+
+            let mut input_weight = required_utxos
+                .iter()
+                .map(|u| TXIN_BASE_WEIGHT + u.satisfaction_weight)
+                .sum::<usize>();
+            for utxo in optional_utxos.drain(..) {
+                input_weight += TXIN_BASE_WEIGHT + utxo.satisfaction_weight;
+                if input_weight > max_input_weight {
+                    break;
+                }
+                required_utxos.push(utxo);
+            }
+        }
+
+        let satisfaction_weights = required_utxos
+            .iter()
+            .chain(optional_utxos.iter())
+            .map(|u| (u.utxo.outpoint(), u.satisfaction_weight))
+            .collect::<HashMap<_, _>>();
+
+        // utxos created by unconfirmed transactions that pay less than the target fee rate also
+        // have to pay for their ancestors, so that the whole package reaches the target
+        if params.bumping_fee.is_none() && fee_rate > FeeRate::from_sat_per_vb(0.0) {
+            let mut deficits = HashMap::new();
+            for u in required_utxos.iter().chain(optional_utxos.iter()) {
+                let outpoint = u.utxo.outpoint();
+                let deficit = self.get_ancestors_fee_deficit(&outpoint.txid, fee_rate)?;
+                if deficit > 0 {
+                    deficits.insert(outpoint, deficit);
+                }
+            }
+
+            if params.exclude_low_feerate_ancestors {
+                // manually selected utxos are always spent
+                let is_allowed = |u: &WeightedUtxo| {
+                    !deficits.contains_key(&u.utxo.outpoint())
+                        || params
+                            .utxos
+                            .iter()
+                            .any(|m| m.utxo.outpoint() == u.utxo.outpoint())
+                };
+                required_utxos.retain(is_allowed);
+                optional_utxos.retain(is_allowed);
+            }
+
+            // the missing fees are accounted for as additional weight at the target fee rate.
+            // Utxos sharing the same ancestors each pay for them, so we might slightly overpay
+            for u in required_utxos.iter_mut().chain(optional_utxos.iter_mut()) {
+                if let Some(deficit) = deficits.get(&u.utxo.outpoint()) {
+                    u.satisfaction_weight +=
+                        (*deficit as f32 * 4.0 / fee_rate.as_sat_vb()).ceil() as usize;
+                }
+            }
+        }
+
+        let coin_selection = coin_selection.coin_select_with_long_term_fee_rate(
+            self.database.read().unwrap().deref(),
             required_utxos,
             optional_utxos,
             fee_rate,
+            params.long_term_fee_rate,
             outgoing,
             fee_amount,
         )?;
@@ -636,26 +1849,48 @@ where
             })
             .collect();
 
-        // prepare the drain output
-        let mut drain_output = {
+        // prepare the drain outputs, each one with the weight of its share of the drained value
+        let mut drain_outputs = if params.drain_to_multiple.is_empty() {
             let script_pubkey = match params.drain_to {
                 Some(ref drain_recipient) => drain_recipient.clone(),
                 None => self.get_change_address()?,
             };
 
-            TxOut {
-                script_pubkey,
-                value: 0,
-            }
+            vec![(
+                TxOut {
+                    script_pubkey,
+                    value: 0,
+                },
+                1,
+            )]
+        } else {
+            params
+                .drain_to_multiple
+                .iter()
+                .map(|(script_pubkey, weight)| {
+                    (
+                        TxOut {
+                            script_pubkey: script_pubkey.clone(),
+                            value: 0,
+                        },
+                        *weight,
+                    )
+                })
+                .collect()
         };
 
-        fee_amount += calc_fee_bytes(serialize(&drain_output).len() * 4);
+        for (drain_output, _) in &drain_outputs {
+            fee_amount += calc_fee_bytes(serialize(drain_output).len() * 4);
+        }
 
         let mut fee_amount = fee_amount.ceil() as u64;
         let drain_val = (coin_selection.selected_amount() - outgoing).saturating_sub(fee_amount);
 
         if tx.output.is_empty() {
-            if params.drain_to.is_some() {
+            if params.drain_to.is_some()
+                || !params.drain_to_multiple.is_empty()
+                || params.consolidate.is_some()
+            {
                 if drain_val.is_dust() {
                     return Err(Error::InsufficientFunds {
                         needed: DUST_LIMIT_SATOSHI,
@@ -670,19 +1905,91 @@ where
         if drain_val.is_dust() {
             fee_amount += drain_val;
         } else {
-            drain_output.value = drain_val;
-            if self.is_mine(&drain_output.script_pubkey)? {
-                received += drain_val;
+            // split the drained value proportionally to the weights, the remainder of the
+            // division goes to the first output
+            let total_weight = drain_outputs.iter().map(|(_, w)| *w as u64).sum::<u64>();
+            for (drain_output, weight) in drain_outputs.iter_mut() {
+                drain_output.value =
+                    (drain_val as u128 * *weight as u128 / total_weight as u128) as u64;
+            }
+            let remainder = drain_val - drain_outputs.iter().map(|(o, _)| o.value).sum::<u64>();
+            drain_outputs[0].0.value += remainder;
+
+            if drain_outputs.iter().any(|(o, _)| o.value.is_dust()) {
+                let min_weight = drain_outputs.iter().map(|(_, w)| *w as u64).min().unwrap();
+                return Err(Error::InsufficientFunds {
+                    needed: DUST_LIMIT_SATOSHI * total_weight / min_weight,
+                    available: drain_val,
+                });
+            }
+
+            for (drain_output, _) in drain_outputs {
+                if self.is_mine(&drain_output.script_pubkey)? {
+                    received += drain_output.value;
+                }
+                tx.output.push(drain_output);
             }
-            tx.output.push(drain_output);
         }
 
+        if let Some(max) = params.max_absolute_fee {
+            if fee_amount > max {
+                return Err(Error::FeeTooHigh {
+                    fee: fee_amount,
+                    max,
+                });
+            }
+        }
+        // estimate the size of the signed transaction
+        let signed_weight = tx.get_weight()
+            + tx.input
+                .iter()
+                .map(|txin| satisfaction_weights[&txin.previous_output])
+                .sum::<usize>();
+
+        if let Some(max) = params.max_fee_rate {
+            let vbytes = signed_weight.vbytes();
+            if fee_amount as f32 > (max.as_sat_vb() * vbytes).ceil() {
+                return Err(Error::FeeRateTooHigh {
+                    rate: FeeRate::from_sat_per_vb(fee_amount as f32 / vbytes),
+                    max,
+                });
+            }
+        }
+
+        if params.ephemeral_anchor && fee_amount > 0 {
+            return Err(Error::Generic(format!(
+                "Transactions with an ephemeral anchor must pay zero fees, but this one would pay {} sats",
+                fee_amount
+            )));
+        }
+
+        self.check_truc_policy(&tx, signed_weight)?;
+
         // sort input/outputs according to the chosen algorithm
         params.ordering.sort_tx(&mut tx);
 
-        let txid = tx.txid();
         let sent = coin_selection.local_selected_amount();
+        #[cfg(feature = "bip47")]
+        let bip47_notification = params.bip47_notification;
+        #[cfg(feature = "silent-payments")]
+        let (silent_payments, ordering) = (params.silent_payments.clone(), params.ordering.clone());
         let psbt = self.complete_transaction(tx, coin_selection.selected, params)?;
+        #[cfg(feature = "bip47")]
+        let psbt = match bip47_notification {
+            Some((sender, recipient)) => self.fill_bip47_notification(psbt, &sender, &recipient)?,
+            None => psbt,
+        };
+        #[cfg(feature = "silent-payments")]
+        let psbt = if silent_payments.is_empty() {
+            psbt
+        } else {
+            // The scripts of the silent payments depend on the inputs, so they are only derived
+            // once the psbt is complete, and the outputs have to be sorted again
+            let mut psbt = self.fill_silent_payment_outputs(psbt, &silent_payments)?;
+            ordering.sort_psbt_outputs(&mut psbt);
+            psbt
+        };
+        let txid = psbt.global.unsigned_tx.txid();
 
         let transaction_details = TransactionDetails {
             transaction: None,
@@ -692,6 +1999,7 @@ where
             sent,
             fee: Some(fee_amount),
             verified: true,
+            last_evicted: None,
         };
 
         Ok((psbt, transaction_details))
@@ -699,8 +2007,8 @@ where
 
     /// Bump the fee of a transaction previously created with this wallet.
     ///
-    /// Returns an error if the transaction is already confirmed or doesn't explicitly signal
-    /// *repalce by fee* (RBF). If the transaction can be fee bumped then it returns a [`TxBuilder`]
+    /// Returns an error if the transaction is already confirmed, has been evicted from the mempool
+    /// or doesn't explicitly signal *repalce by fee* (RBF). If the transaction can be fee bumped then it returns a [`TxBuilder`]
     /// pre-populated with the inputs and outputs of the original transaction.
     ///
     /// ## Example
@@ -743,72 +2051,156 @@ where
         &self,
         txid: Txid,
     ) -> Result<TxBuilder<'_, B, D, DefaultCoinSelectionAlgorithm, BumpFee>, Error> {
-        let mut details = match self.database.borrow().get_tx(&txid, true)? {
+        let mut details = match self.database.read().unwrap().get_tx(&txid, true)? {
             None => return Err(Error::TransactionNotFound),
             Some(tx) if tx.transaction.is_none() => return Err(Error::TransactionNotFound),
             Some(tx) if tx.confirmation_time.is_some() => return Err(Error::TransactionConfirmed),
+            Some(tx) if tx.last_evicted.is_some() => return Err(Error::TransactionEvicted),
             Some(tx) => tx,
         };
-        let mut tx = details.transaction.take().unwrap();
+
+        self.fee_bump_builder(details.transaction.take().unwrap(), &[])
+    }
+
+    /// Bump the fee of a transaction that spends some of the wallet's utxos, even if it wasn't
+    /// created by this wallet.
+    ///
+    /// This can be used to bump transactions created by an older wallet or by another signer of
+    /// the same descriptor, which may not be in the database. The recipients are reconstructed from
+    /// the outputs of `tx`, except for the ones sent to the wallet's change keychain, which are
+    /// treated as change like in [`build_fee_bump`](Self::build_fee_bump).
+    ///
+    /// `prev_txs` must contain the transactions spent by `tx` that are not in the wallet's
+    /// database. The inputs of `tx` that don't belong to the wallet are added as foreign utxos,
+    /// and will have to be signed again by their owners.
+    ///
+    /// Returns an error if the transaction is known to be confirmed or evicted from the mempool,
+    /// doesn't explicitly signal *replace by fee* (RBF) or doesn't spend any utxo of the wallet.
+    pub fn build_fee_bump_from_tx(
+        &self,
+        tx: Transaction,
+        prev_txs: &[Transaction],
+    ) -> Result<TxBuilder<'_, B, D, DefaultCoinSelectionAlgorithm, BumpFee>, Error> {
+        if let Some(details) = self.database.read().unwrap().get_tx(&tx.txid(), false)? {
+            if details.confirmation_time.is_some() {
+                return Err(Error::TransactionConfirmed);
+            }
+            if details.last_evicted.is_some() {
+                return Err(Error::TransactionEvicted);
+            }
+        }
+
+        self.fee_bump_builder(tx, prev_txs)
+    }
+
+    fn fee_bump_builder(
+        &self,
+        mut tx: Transaction,
+        prev_txs: &[Transaction],
+    ) -> Result<TxBuilder<'_, B, D, DefaultCoinSelectionAlgorithm, BumpFee>, Error> {
         if !tx.input.iter().any(|txin| txin.sequence <= 0xFFFFFFFD) {
             return Err(Error::IrreplaceableTransaction);
         }
 
         let vbytes = tx.get_weight().vbytes();
-        let feerate = details.fee.ok_or(Error::FeeRateUnavailable)? as f32 / vbytes;
+
+        let prev_txs = prev_txs
+            .iter()
+            .map(|prev_tx| (prev_tx.txid(), prev_tx))
+            .collect::<HashMap<_, _>>();
 
         // remove the inputs from the tx and process them
         let original_txin = tx.input.drain(..).collect::<Vec<_>>();
         let original_utxos = original_txin
             .iter()
             .map(|txin| -> Result<_, Error> {
-                let txout = self
-                    .database
-                    .borrow()
-                    .get_previous_output(&txin.previous_output)?
-                    .ok_or(Error::UnknownUtxo)?;
+                let prev_tx = match prev_txs.get(&txin.previous_output.txid) {
+                    Some(prev_tx) => (*prev_tx).clone(),
+                    None => self
+                        .database
+                        .read()
+                        .unwrap()
+                        .get_raw_tx(&txin.previous_output.txid)?
+                        .ok_or(Error::UnknownUtxo)?,
+                };
+                let txout = prev_tx
+                    .output
+                    .get(txin.previous_output.vout as usize)
+                    .cloned()
+                    .ok_or(Error::InvalidOutpoint(txin.previous_output))?;
 
-                let (weight, keychain) = match self
+                match self
                     .database
-                    .borrow()
+                    .read()
+                    .unwrap()
                     .get_path_from_script_pubkey(&txout.script_pubkey)?
                 {
-                    Some((keychain, _)) => (
-                        self._get_descriptor_for_keychain(keychain)
+                    Some((keychain, _)) => Ok(WeightedUtxo {
+                        satisfaction_weight: self
+                            ._get_descriptor_for_keychain(keychain)
                             .0
                             .max_satisfaction_weight()
                             .unwrap(),
-                        keychain,
-                    ),
+                        utxo: Utxo::Local(LocalUtxo {
+                            outpoint: txin.previous_output,
+                            txout,
+                            keychain,
+                        }),
+                    }),
                     None => {
                         // estimate the weight based on the scriptsig/witness size present in the
                         // original transaction
                         let weight =
                             serialize(&txin.script_sig).len() * 4 + serialize(&txin.witness).len();
-                        (weight, KeychainKind::External)
+                        let psbt_input = Input {
+                            witness_utxo: match txin.witness.is_empty() {
+                                true => None,
+                                false => Some(txout),
+                            },
+                            non_witness_utxo: Some(prev_tx),
+                            ..Default::default()
+                        };
+
+                        Ok(WeightedUtxo {
+                            satisfaction_weight: weight,
+                            utxo: Utxo::Foreign {
+                                outpoint: txin.previous_output,
+                                psbt_input: Box::new(psbt_input),
+                            },
+                        })
                     }
-                };
-
-                let utxo = LocalUtxo {
-                    outpoint: txin.previous_output,
-                    txout,
-                    keychain,
-                };
-
-                Ok(WeightedUtxo {
-                    satisfaction_weight: weight,
-                    utxo: Utxo::Local(utxo),
-                })
+                }
             })
             .collect::<Result<Vec<_>, _>>()?;
 
+        if !original_utxos
+            .iter()
+            .any(|u| matches!(u.utxo, Utxo::Local(_)))
+        {
+            return Err(Error::Generic(
+                "The transaction doesn't spend any utxo of this wallet".into(),
+            ));
+        }
+
+        let input_value = original_utxos
+            .iter()
+            .map(|u| u.utxo.txout().value)
+            .sum::<u64>();
+        let output_value = tx.output.iter().map(|txout| txout.value).sum::<u64>();
+        let fee = input_value
+            .checked_sub(output_value)
+            .ok_or_else(|| Error::Generic("The transaction spends more than its inputs".into()))?;
+
+        let feerate = fee as f32 / vbytes;
+
         if tx.output.len() > 1 {
             let mut change_index = None;
             for (index, txout) in tx.output.iter().enumerate() {
                 let (_, change_type) = self._get_descriptor_for_keychain(KeychainKind::Internal);
                 match self
                     .database
-                    .borrow()
+                    .read()
+                    .unwrap()
                     .get_path_from_script_pubkey(&txout.script_pubkey)?
                 {
                     Some((keychain, _)) if keychain == change_type => change_index = Some(index),
@@ -831,7 +2223,7 @@ where
                 .collect(),
             utxos: original_utxos,
             bumping_fee: Some(tx_builder::PreviousFee {
-                absolute: details.fee.ok_or(Error::FeeRateUnavailable)?,
+                absolute: fee,
                 rate: feerate,
             }),
             ..Default::default()
@@ -853,6 +2245,9 @@ where
     /// signers will follow the options, but the "software signers" (WIF keys and `xprv`) defined
     /// in this library will.
     ///
+    /// See [`Wallet::sign_with_report`] to know which signatures have been added and which ones
+    /// are still missing.
+    ///
     /// ## Example
     ///
     /// ```
@@ -872,6 +2267,120 @@ where
     /// assert!(finalized, "we should have signed all the inputs");
     /// # Ok::<(), bdk::Error>(())
     pub fn sign(&self, psbt: &mut Psbt, sign_options: SignOptions) -> Result<bool, Error> {
+        Ok(self.sign_with_report(psbt, sign_options)?.is_finalized())
+    }
+
+    /// Sign a transaction with all the wallet's signers, reporting the signatures added to every
+    /// input
+    ///
+    /// This behaves like [`Wallet::sign`], but the returned [`SigningReport`] lists the keys that
+    /// signed every input, with their fingerprint and derivation path when known, and includes
+    /// the [`FinalizeReport`] of the finalization attempted at the end, which tells which
+    /// signatures are still missing. This lets UIs show the progress of multi-signer setups.
+    pub fn sign_with_report(
+        &self,
+        psbt: &mut Psbt,
+        sign_options: SignOptions,
+    ) -> Result<SigningReport, Error> {
+        self.prepare_psbt_for_signing(psbt, &sign_options)?;
+
+        let previous_keys = psbt
+            .inputs
+            .iter()
+            .map(|i| i.partial_sigs.keys().copied().collect::<BTreeSet<_>>())
+            .collect::<Vec<_>>();
+
+        self.sign_with_local_signers(psbt, &sign_options)?;
+
+        let added_signatures = psbt
+            .inputs
+            .iter()
+            .zip(previous_keys)
+            .map(|(psbt_input, previous_keys)| {
+                psbt_input
+                    .partial_sigs
+                    .keys()
+                    .filter(|key| !previous_keys.contains(key))
+                    .map(|key| AddedSignature {
+                        public_key: *key,
+                        origin: psbt_input.bip32_derivation.get(key).cloned(),
+                    })
+                    .collect()
+            })
+            .collect();
+
+        // attempt to finalize
+        let finalization = self.finalize_psbt_with_report(psbt, sign_options)?;
+
+        Ok(SigningReport {
+            added_signatures,
+            finalization,
+        })
+    }
+
+    /// Sign a transaction with all the wallet's signers and then with `remote_signers`, in order
+    ///
+    /// Every remote signer receives the PSBT with the signatures added by the previous ones, and
+    /// the copy it returns is merged back into `psbt`. The returned [`RemoteSigningReport`] lists
+    /// the inputs that received new signatures from every remote signer, and whether the
+    /// transaction could be finalized at the end.
+    ///
+    /// When the `async-interface` feature is enabled this method is `async`, so that it doesn't
+    /// block the runtime while waiting for the remote signers.
+    #[maybe_async]
+    pub fn sign_with_remote_signers(
+        &self,
+        psbt: &mut Psbt,
+        remote_signers: &[Arc<dyn RemoteSigner>],
+        sign_options: SignOptions,
+    ) -> Result<RemoteSigningReport, Error> {
+        self.prepare_psbt_for_signing(psbt, &sign_options)?;
+        self.sign_with_local_signers(psbt, &sign_options)?;
+
+        let signature_state = |psbt: &Psbt| {
+            psbt.inputs
+                .iter()
+                .map(|i| {
+                    (
+                        i.partial_sigs.len(),
+                        i.final_script_sig.is_some() || i.final_script_witness.is_some(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut report = RemoteSigningReport::default();
+        for remote_signer in remote_signers {
+            let before = signature_state(psbt);
+            let signed = maybe_await!(remote_signer.sign_psbt(psbt))?;
+            if signed.global.unsigned_tx != psbt.global.unsigned_tx {
+                return Err(Error::Signer(signer::SignerError::Remote(
+                    "The signed PSBT spends a different transaction".into(),
+                )));
+            }
+            psbt.merge(signed)?;
+
+            let signed_inputs = before
+                .into_iter()
+                .zip(signature_state(psbt))
+                .enumerate()
+                .filter(|(_, (before, after))| before != after)
+                .map(|(index, _)| index)
+                .collect();
+            report
+                .signed_inputs
+                .insert(maybe_await!(remote_signer.id()), signed_inputs);
+        }
+
+        report.finalized = self.finalize_psbt(psbt, sign_options)?;
+        Ok(report)
+    }
+
+    fn prepare_psbt_for_signing(
+        &self,
+        psbt: &mut Psbt,
+        sign_options: &SignOptions,
+    ) -> Result<(), Error> {
         // this helps us doing our job later
         self.add_input_hd_keypaths(psbt)?;
 
@@ -887,17 +2396,39 @@ where
             return Err(Error::Signer(signer::SignerError::MissingNonWitnessUtxo));
         }
 
+        for (index, sighash) in &sign_options.input_sighashes {
+            psbt.inputs
+                .get_mut(*index)
+                .ok_or(Error::Signer(signer::SignerError::InputIndexOutOfRange))?
+                .sighash_type = Some(*sighash);
+        }
+
         // If the user hasn't explicitly opted-in, refuse to sign the transaction unless every input
-        // is using `SIGHASH_ALL`
+        // is using `SIGHASH_ALL` or the sighash set for it in the options
         if !sign_options.allow_all_sighashes
-            && !psbt
-                .inputs
-                .iter()
-                .all(|i| i.sighash_type.is_none() || i.sighash_type == Some(SigHashType::All))
+            && !psbt.inputs.iter().enumerate().all(|(index, i)| {
+                i.sighash_type.is_none()
+                    || i.sighash_type == Some(SigHashType::All)
+                    || sign_options.input_sighashes.contains_key(&index)
+            })
         {
             return Err(Error::Signer(signer::SignerError::NonStandardSighash));
         }
 
+        for (index, psbt_input) in psbt.inputs.iter().enumerate() {
+            if let Some(sighash) = psbt_input.sighash_type {
+                signer::check_sighash(&psbt.global.unsigned_tx, index, sighash)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn sign_with_local_signers(
+        &self,
+        psbt: &mut Psbt,
+        sign_options: &SignOptions,
+    ) -> Result<(), Error> {
         for signer in self
             .signers
             .signers()
@@ -905,16 +2436,30 @@ where
             .chain(self.change_signers.signers().iter())
         {
             if signer.sign_whole_tx() {
-                signer.sign(psbt, None, &self.secp)?;
+                signer.sign_with_options(psbt, None, sign_options, &self.secp)?;
             } else {
                 for index in 0..psbt.inputs.len() {
-                    signer.sign(psbt, Some(index), &self.secp)?;
+                    signer.sign_with_options(psbt, Some(index), sign_options, &self.secp)?;
                 }
             }
         }
 
-        // attempt to finalize
-        self.finalize_psbt(psbt, sign_options)
+        // registered signers only sign the inputs that contain one of their keys
+        let mut whole_tx_signers: Vec<Arc<dyn Signer>> = Vec::new();
+        for index in 0..psbt.inputs.len() {
+            for (_, signer) in self.signer_registry.signers_for_input(&psbt.inputs[index]) {
+                if !signer.sign_whole_tx() {
+                    signer.sign_with_options(psbt, Some(index), sign_options, &self.secp)?;
+                } else if !whole_tx_signers.iter().any(|s| Arc::ptr_eq(s, &signer)) {
+                    whole_tx_signers.push(signer);
+                }
+            }
+        }
+        for signer in whole_tx_signers {
+            signer.sign_with_options(psbt, None, sign_options, &self.secp)?;
+        }
+
+        Ok(())
     }
 
     /// Return the spending policies for the wallet's descriptor
@@ -934,6 +2479,22 @@ where
         }
     }
 
+    /// Return the origin of every key in the descriptor of `keychain`, and whether the wallet holds
+    /// a signer for it
+    ///
+    /// This can be used to show which keys can sign for the wallet, or to match the fingerprints
+    /// of connected hardware wallets. If the wallet doesn't have a change descriptor the keys of
+    /// the external one are returned for [`KeychainKind::Internal`].
+    pub fn key_origins(&self, keychain: KeychainKind) -> Vec<KeyOriginInfo> {
+        let (descriptor, keychain) = self._get_descriptor_for_keychain(keychain);
+        let signers = match keychain {
+            KeychainKind::External => &self.signers,
+            KeychainKind::Internal => &self.change_signers,
+        };
+
+        descriptor.key_origins(signers, &self.secp)
+    }
+
     /// Return the "public" version of the wallet's descriptor, meaning a new descriptor that has
     /// the same structure but with every secret key removed
     ///
@@ -949,12 +2510,353 @@ where
         }
     }
 
+    /// Return the "public" descriptors of both keychains combined into a single [BIP-389]
+    /// multipath descriptor, with its checksum
+    ///
+    /// Returns `None` if the wallet doesn't have a change descriptor, or if the two descriptors
+    /// differ by anything other than their derivation steps.
+    ///
+    /// [BIP-389]: https://github.com/bitcoin/bips/blob/master/bip-0389.mediawiki
+    pub fn public_multipath_descriptor(&self) -> Option<String> {
+        let change_descriptor = self.change_descriptor.as_ref()?;
+        combine_multipath(&self.descriptor.to_string(), &change_descriptor.to_string())
+    }
+
+    /// Combine multiple PSBTs for the same transaction into a single one
+    ///
+    /// This is the step a coordinator runs after every signer has returned its copy of the PSBT:
+    /// all the partial signatures and the other metadata are merged into the first PSBT of the
+    /// list, and the returned [`CombinedPsbt`] reports which signatures were added to every input.
+    ///
+    /// An [`Error::IncompatiblePsbt`] is returned if the PSBTs don't spend the same inputs into
+    /// the same outputs, or if they contain conflicting signatures, utxos or final scripts for the
+    /// same input.
+    pub fn combine_psbts(&self, psbts: Vec<Psbt>) -> Result<CombinedPsbt, Error> {
+        let mut psbts = psbts.into_iter();
+        let mut combined = psbts
+            .next()
+            .ok_or_else(|| Error::Generic("No PSBTs to combine".into()))?;
+        let mut added_signatures = vec![BTreeSet::new(); combined.inputs.len()];
+
+        for (n, psbt) in psbts.enumerate() {
+            let unsigned_tx = &combined.global.unsigned_tx;
+            if psbt.global.unsigned_tx.txid() != unsigned_tx.txid()
+                || psbt.inputs.len() != combined.inputs.len()
+                || psbt.outputs.len() != combined.outputs.len()
+            {
+                return Err(Error::IncompatiblePsbt(format!(
+                    "PSBT #{} has a different unsigned transaction ({} instead of {})",
+                    n + 1,
+                    psbt.global.unsigned_tx.txid(),
+                    unsigned_tx.txid()
+                )));
+            }
+
+            for (index, (ours, theirs)) in combined.inputs.iter().zip(&psbt.inputs).enumerate() {
+                let conflict = |what: &str| {
+                    Error::IncompatiblePsbt(format!(
+                        "PSBT #{} has a different {} for input #{}",
+                        n + 1,
+                        what,
+                        index
+                    ))
+                };
+
+                match (&ours.witness_utxo, &theirs.witness_utxo) {
+                    (Some(a), Some(b)) if a != b => return Err(conflict("witness utxo")),
+                    _ => {}
+                }
+                match (&ours.non_witness_utxo, &theirs.non_witness_utxo) {
+                    (Some(a), Some(b)) if a.txid() != b.txid() => {
+                        return Err(conflict("non-witness utxo"))
+                    }
+                    _ => {}
+                }
+                match (&ours.final_script_sig, &theirs.final_script_sig) {
+                    (Some(a), Some(b)) if a != b => return Err(conflict("final script sig")),
+                    _ => {}
+                }
+                match (&ours.final_script_witness, &theirs.final_script_witness) {
+                    (Some(a), Some(b)) if a != b => return Err(conflict("final script witness")),
+                    _ => {}
+                }
+
+                for (pk, sig) in &theirs.partial_sigs {
+                    match ours.partial_sigs.get(pk) {
+                        Some(our_sig) if our_sig != sig => {
+                            return Err(conflict(&format!("signature for key {}", pk)))
+                        }
+                        Some(_) => {}
+                        None => {
+                            added_signatures[index].insert(*pk);
+                        }
+                    }
+                }
+            }
+
+            combined.merge(psbt)?;
+        }
+
+        Ok(CombinedPsbt {
+            psbt: combined,
+            added_signatures,
+        })
+    }
+
+    /// Verify a PSBT before signing it
+    ///
+    /// The returned [`PsbtCheckReport`] contains the fee, the value moved in and out of the
+    /// wallet, the owner of every output and the list of [`PsbtIssue`]s found, so that it can be
+    /// shown to the user before asking for a signature. The checks are:
+    ///
+    /// - every input must have a utxo, and the `witness_utxo` must match the `non_witness_utxo`
+    /// - the fee must stay within [`PsbtCheckOptions::max_fee`] and
+    ///   [`PsbtCheckOptions::max_fee_rate`]
+    /// - outputs carrying derivation paths for our keys must actually belong to the wallet
+    /// - when [`PsbtCheckOptions::expected_outputs`] is set, every output that isn't ours must be
+    ///   expected, and every expected output must be present
+    pub fn check_psbt(
+        &self,
+        psbt: &Psbt,
+        options: PsbtCheckOptions,
+    ) -> Result<PsbtCheckReport, Error> {
+        let tx = &psbt.global.unsigned_tx;
+        let mut issues = Vec::new();
+
+        let mut input_value = Some(0);
+        let mut sent = 0;
+        let mut weight = tx.get_weight();
+        for (n, txin) in tx.input.iter().enumerate() {
+            let psbt_input = psbt
+                .inputs
+                .get(n)
+                .ok_or(Error::Signer(SignerError::InputIndexOutOfRange))?;
+            let vout = txin.previous_output.vout as usize;
+
+            if let Some(prev_tx) = &psbt_input.non_witness_utxo {
+                let consistent = prev_tx.txid() == txin.previous_output.txid
+                    && match (&psbt_input.witness_utxo, prev_tx.output.get(vout)) {
+                        (Some(witness_utxo), Some(txout)) => witness_utxo == txout,
+                        (_, txout) => txout.is_some(),
+                    };
+                if !consistent {
+                    issues.push(PsbtIssue::InconsistentUtxo(n));
+                }
+            }
+
+            let utxo = match get_psbt_input_utxo(psbt_input, txin) {
+                Some(utxo) => utxo,
+                None => {
+                    issues.push(PsbtIssue::MissingUtxo(n));
+                    input_value = None;
+                    continue;
+                }
+            };
+
+            input_value = input_value.map(|v| v + utxo.value);
+            if self.is_mine(&utxo.script_pubkey)? {
+                sent += utxo.value;
+            }
+
+            weight += self.get_psbt_input_satisfaction_weight(psbt_input, &utxo)?;
+        }
+
+        let mut expected_outputs = options.expected_outputs.clone();
+        let mut output_value = 0;
+        let mut received = 0;
+        let mut outputs = Vec::with_capacity(tx.output.len());
+        for (n, txout) in tx.output.iter().enumerate() {
+            output_value += txout.value;
+
+            let (keychain, foreign_change) = self.get_psbt_output_keychain(psbt, n)?;
+            if foreign_change {
+                issues.push(PsbtIssue::ForeignChange(n));
+            }
+
+            let expected = expected_outputs
+                .as_mut()
+                .and_then(|expected| {
+                    expected
+                        .iter()
+                        .position(|o| o == txout)
+                        .map(|pos| expected.remove(pos))
+                })
+                .is_some();
+            if keychain.is_some() {
+                received += txout.value;
+            } else if options.expected_outputs.is_some() && !expected {
+                issues.push(PsbtIssue::UnexpectedOutput(n));
+            }
+
+            outputs.push(CheckedOutput {
+                script_pubkey: txout.script_pubkey.clone(),
+                value: txout.value,
+                keychain,
+            });
+        }
+        issues.extend(
+            expected_outputs
+                .into_iter()
+                .flatten()
+                .map(PsbtIssue::MissingOutput),
+        );
+
+        let fee = match input_value {
+            Some(input_value) if input_value < output_value => {
+                issues.push(PsbtIssue::OutputsExceedInputs {
+                    input_value,
+                    output_value,
+                });
+                None
+            }
+            Some(input_value) => Some(input_value - output_value),
+            None => None,
+        };
+        let fee_rate = fee.map(|fee| FeeRate::from_sat_per_vb(fee as f32 / weight.vbytes()));
+
+        if let (Some(fee), Some(max)) = (fee, options.max_fee) {
+            if fee > max {
+                issues.push(PsbtIssue::FeeTooHigh { fee, max });
+            }
+        }
+        if let (Some(rate), Some(max)) = (fee_rate, options.max_fee_rate) {
+            if rate > max {
+                issues.push(PsbtIssue::FeeRateTooHigh { rate, max });
+            }
+        }
+
+        Ok(PsbtCheckReport {
+            fee,
+            fee_rate,
+            sent,
+            received,
+            outputs,
+            issues,
+        })
+    }
+
+    /// Describe the inputs and outputs of a PSBT for a review screen
+    ///
+    /// Every input and output is labeled as ours or foreign, together with the derivation paths
+    /// declared in the PSBT. The fee and the estimated fee rate are computed when all the input
+    /// utxos are available, and the [`PsbtWarning`]s list suspicious outputs, like address reuse
+    /// or payments to a foreign address seen before.
+    ///
+    /// Unlike [`Wallet::check_psbt`] this method doesn't judge whether the PSBT should be signed,
+    /// it only reports what it would do.
+    pub fn describe_psbt(&self, psbt: &Psbt) -> Result<PsbtDescription, Error> {
+        let tx = &psbt.global.unsigned_tx;
+        let mut warnings = Vec::new();
+
+        let mut input_value = Some(0);
+        let mut weight = tx.get_weight();
+        let mut inputs = Vec::with_capacity(tx.input.len());
+        for (n, txin) in tx.input.iter().enumerate() {
+            let psbt_input = psbt
+                .inputs
+                .get(n)
+                .ok_or(Error::Signer(SignerError::InputIndexOutOfRange))?;
+            let utxo = get_psbt_input_utxo(psbt_input, txin);
+
+            let mut keychain = None;
+            match &utxo {
+                Some(utxo) => {
+                    input_value = input_value.map(|v| v + utxo.value);
+                    weight += self.get_psbt_input_satisfaction_weight(psbt_input, utxo)?;
+                    keychain = self
+                        .database
+                        .read()
+                        .unwrap()
+                        .get_path_from_script_pubkey(&utxo.script_pubkey)?
+                        .map(|(keychain, _)| keychain);
+                }
+                None => input_value = None,
+            }
+
+            inputs.push(DescribedInput {
+                previous_output: txin.previous_output,
+                utxo,
+                keychain,
+                derivation_paths: psbt_input.bip32_derivation.values().cloned().collect(),
+                finalized: psbt_input.final_script_sig.is_some()
+                    || psbt_input.final_script_witness.is_some(),
+            });
+        }
+
+        let txid = tx.txid();
+        let seen_scripts = self
+            .list_transactions(true)?
+            .into_iter()
+            .filter(|details| details.txid != txid)
+            .flat_map(|details| details.transaction)
+            .flat_map(|tx| tx.output.into_iter())
+            .map(|txout| txout.script_pubkey)
+            .collect::<HashSet<_>>();
+
+        let mut outputs = Vec::with_capacity(tx.output.len());
+        for (n, txout) in tx.output.iter().enumerate() {
+            let (keychain, foreign_change) = self.get_psbt_output_keychain(psbt, n)?;
+            if foreign_change {
+                warnings.push(PsbtWarning::ForeignChange(n));
+            }
+            if seen_scripts.contains(&txout.script_pubkey) {
+                warnings.push(match keychain {
+                    Some(_) => PsbtWarning::AddressReuse(n),
+                    None => PsbtWarning::PreviouslySeenAddress(n),
+                });
+            }
+
+            outputs.push(DescribedOutput {
+                script_pubkey: txout.script_pubkey.clone(),
+                address: Address::from_script(&txout.script_pubkey, self.network),
+                value: txout.value,
+                keychain,
+                derivation_paths: psbt
+                    .outputs
+                    .get(n)
+                    .map(|o| o.bip32_derivation.values().cloned().collect())
+                    .unwrap_or_default(),
+            });
+        }
+
+        let output_value: u64 = tx.output.iter().map(|txout| txout.value).sum();
+        let fee = input_value.and_then(|input_value| input_value.checked_sub(output_value));
+        let fee_rate = fee.map(|fee| FeeRate::from_sat_per_vb(fee as f32 / weight.vbytes()));
+
+        Ok(PsbtDescription {
+            inputs,
+            outputs,
+            fee,
+            fee_rate,
+            warnings,
+        })
+    }
+
     /// Try to finalize a PSBT
     ///
     /// The [`SignOptions`] can be used to tweak the behavior of the finalizer.
+    ///
+    /// Returns `true` if every input is finalized. See [`Wallet::finalize_psbt_with_report`] to
+    /// know what's still missing for the inputs that couldn't be finalized.
     pub fn finalize_psbt(&self, psbt: &mut Psbt, sign_options: SignOptions) -> Result<bool, Error> {
+        Ok(self
+            .finalize_psbt_with_report(psbt, sign_options)?
+            .is_finalized())
+    }
+
+    /// Try to finalize a PSBT, reporting the outcome for every input
+    ///
+    /// This behaves like [`Wallet::finalize_psbt`], but the returned [`FinalizeReport`] tells
+    /// which inputs have been finalized, which ones are still missing signatures (and from which
+    /// keys), and which ones failed for a different reason, so that a coordinator can tell the
+    /// users exactly what's still needed.
+    pub fn finalize_psbt_with_report(
+        &self,
+        psbt: &mut Psbt,
+        sign_options: SignOptions,
+    ) -> Result<FinalizeReport, Error> {
         let tx = &psbt.global.unsigned_tx;
-        let mut finished = true;
+        let mut inputs = Vec::with_capacity(tx.input.len());
 
         for (n, input) in tx.input.iter().enumerate() {
             let psbt_input = &psbt
@@ -962,16 +2864,18 @@ where
                 .get(n)
                 .ok_or(Error::Signer(SignerError::InputIndexOutOfRange))?;
             if psbt_input.final_script_sig.is_some() || psbt_input.final_script_witness.is_some() {
+                inputs.push(InputFinalization::AlreadyFinalized);
                 continue;
             }
             // if the height is None in the database it means it's still unconfirmed, so consider
             // that as a very high value
             let create_height = self
                 .database
-                .borrow()
+                .read()
+                .unwrap()
                 .get_tx(&input.previous_output.txid, false)?
                 .map(|tx| tx.confirmation_time.map(|c| c.height).unwrap_or(u32::MAX));
-            let current_height = sign_options.assume_height.or(self.current_height);
+            let current_height = sign_options.assume_height.or(self.current_height());
 
             debug!(
                 "Input #{} - {}, using `create_height` = {:?}, `current_height` = {:?}",
@@ -1002,14 +2906,14 @@ where
                     })
                 });
 
-            match desc {
+            let outcome = match desc {
                 Some(desc) => {
                     let mut tmp_input = bitcoin::TxIn::default();
                     match desc.satisfy(
                         &mut tmp_input,
                         (
                             PsbtInputSatisfier::new(psbt, n),
-                            After::new(current_height, false),
+                            After::new(current_height, self.median_time_past(), false),
                             Older::new(current_height, create_height, false),
                         ),
                     ) {
@@ -1017,18 +2921,26 @@ where
                             let psbt_input = &mut psbt.inputs[n];
                             psbt_input.final_script_sig = Some(tmp_input.script_sig);
                             psbt_input.final_script_witness = Some(tmp_input.witness);
+                            InputFinalization::Finalized
+                        }
+                        Err(miniscript::Error::CouldNotSatisfy) => {
+                            debug!("input {} can't be satisfied yet", n);
+                            InputFinalization::MissingSignatures {
+                                keys: self.get_missing_signatures(&desc, psbt_input),
+                            }
                         }
                         Err(e) => {
                             debug!("satisfy error {:?} for input {}", e, n);
-                            finished = false
+                            InputFinalization::SatisfactionFailed(e)
                         }
                     }
                 }
-                None => finished = false,
-            }
+                None => InputFinalization::UnknownDescriptor,
+            };
+            inputs.push(outcome);
         }
 
-        Ok(finished)
+        Ok(FinalizeReport { inputs })
     }
 
     /// Return the secp256k1 context used for all signing operations
@@ -1042,6 +2954,18 @@ where
         descriptor
     }
 
+    /// Return an iterator over the script pubkeys of `keychain` with an index in `range`
+    ///
+    /// This includes script pubkeys that haven't been revealed yet. Use `..` to iterate over all
+    /// of them, or [`DoubleEndedIterator::rev`] to start from the most recent ones.
+    pub fn spk_iter<R: RangeBounds<u32>>(&self, keychain: KeychainKind, range: R) -> SpkIterator {
+        SpkIterator::new_with_range(
+            self.get_descriptor_for_keychain(keychain),
+            &self.secp,
+            range,
+        )
+    }
+
     // Internals
 
     fn _get_descriptor_for_keychain(
@@ -1057,18 +2981,188 @@ where
         }
     }
 
+    // Private key spending the `index`-th input of `psbt`, if it's a P2PKH, P2WPKH or P2SH-P2WPKH
+    // output of a key in the signers of the wallet
+    #[cfg(any(feature = "bip47", feature = "silent-payments"))]
+    pub(crate) fn psbt_input_secret_key(
+        &self,
+        psbt: &Psbt,
+        index: usize,
+    ) -> Option<bitcoin::PrivateKey> {
+        use bitcoin::blockdata::opcodes::all::{
+            OP_CHECKSIG, OP_DUP, OP_EQUAL, OP_EQUALVERIFY, OP_HASH160,
+        };
+        use bitcoin::blockdata::script::Builder;
+        use bitcoin::hashes::{hash160, Hash};
+
+        let input = psbt.inputs.get(index)?;
+        let script_pubkey = psbt.get_utxo_for(index)?.script_pubkey;
+
+        self.signers
+            .signers()
+            .into_iter()
+            .chain(self.change_signers.signers())
+            .filter_map(|signer| signer.descriptor_secret_key())
+            .flat_map(|secret| match secret {
+                DescriptorSecretKey::SinglePriv(single) => vec![single.key],
+                DescriptorSecretKey::XPrv(xkey) => input
+                    .bip32_derivation
+                    .values()
+                    .filter_map(|(fingerprint, full_path)| {
+                        xkey.matches(&(*fingerprint, full_path.clone()), &self.secp)?;
+                        let path = match &xkey.origin {
+                            Some((_, origin_path)) => DerivationPath::from(
+                                &full_path.as_ref()[origin_path.as_ref().len()..],
+                            ),
+                            None => full_path.clone(),
+                        };
+                        xkey.xkey
+                            .derive_priv(&self.secp, &path)
+                            .ok()
+                            .map(|derived| derived.private_key)
+                    })
+                    .collect(),
+            })
+            .find(|key| {
+                let hash = hash160::Hash::hash(&key.public_key(&self.secp).to_bytes());
+                let p2pkh = Builder::new()
+                    .push_opcode(OP_DUP)
+                    .push_opcode(OP_HASH160)
+                    .push_slice(&hash[..])
+                    .push_opcode(OP_EQUALVERIFY)
+                    .push_opcode(OP_CHECKSIG)
+                    .into_script();
+                let p2wpkh = Builder::new()
+                    .push_int(0)
+                    .push_slice(&hash[..])
+                    .into_script();
+                let p2shwpkh = Builder::new()
+                    .push_opcode(OP_HASH160)
+                    .push_slice(&hash160::Hash::hash(p2wpkh.as_bytes())[..])
+                    .push_opcode(OP_EQUAL)
+                    .into_script();
+                script_pubkey == p2pkh || script_pubkey == p2wpkh || script_pubkey == p2shwpkh
+            })
+    }
+
+    fn get_psbt_input_satisfaction_weight(
+        &self,
+        psbt_input: &Input,
+        utxo: &TxOut,
+    ) -> Result<usize, Error> {
+        match (
+            &psbt_input.final_script_sig,
+            &psbt_input.final_script_witness,
+        ) {
+            (None, None) => Ok(self
+                .get_descriptor_for_txout(utxo)?
+                .map(|desc| desc.max_satisfaction_weight())
+                .transpose()?
+                .unwrap_or(0)),
+            (script_sig, witness) => Ok(script_sig.as_ref().map(|s| s.len() * 4).unwrap_or(0)
+                + witness.as_ref().map(|w| serialize(w).len()).unwrap_or(0)),
+        }
+    }
+
+    /// Return the keys of `desc` that haven't signed `psbt_input` yet, with the fingerprint of
+    /// their master key when known
+    fn get_missing_signatures(
+        &self,
+        desc: &DerivedDescriptor<'_>,
+        psbt_input: &Input,
+    ) -> Vec<(PublicKey, Option<Fingerprint>)> {
+        let mut missing = Vec::new();
+        desc.for_each_key(|key| {
+            let pubkey = key.as_key().to_public_key();
+            if psbt_input.partial_sigs.contains_key(&pubkey)
+                || missing.iter().any(|(pk, _)| *pk == pubkey)
+            {
+                return true;
+            }
+
+            let fingerprint = match (
+                psbt_input.bip32_derivation.get(&pubkey),
+                key.as_key().deref(),
+            ) {
+                (Some((fingerprint, _)), _) => Some(*fingerprint),
+                (None, DescriptorPublicKey::XPub(xpub)) => Some(xpub.root_fingerprint(&self.secp)),
+                (None, DescriptorPublicKey::SinglePub(single)) => {
+                    single.origin.as_ref().map(|(fingerprint, _)| *fingerprint)
+                }
+            };
+            missing.push((pubkey, fingerprint));
+
+            true
+        });
+
+        missing
+    }
+
+    /// Return the keychain an output of `psbt` belongs to, looking first in the database and then
+    /// at the derivation paths of the PSBT output. The second element is `true` if the PSBT output
+    /// carries derivation paths for our keys that don't match its script.
+    fn get_psbt_output_keychain(
+        &self,
+        psbt: &Psbt,
+        index: usize,
+    ) -> Result<(Option<KeychainKind>, bool), Error> {
+        let txout = &psbt.global.unsigned_tx.output[index];
+        let keychain = self
+            .database
+            .read()
+            .unwrap()
+            .get_path_from_script_pubkey(&txout.script_pubkey)?
+            .map(|(keychain, _)| keychain);
+
+        match psbt.outputs.get(index) {
+            Some(psbt_output) if keychain.is_none() && !psbt_output.bip32_derivation.is_empty() => {
+                let derived = [KeychainKind::External, KeychainKind::Internal]
+                    .iter()
+                    .filter_map(|keychain| {
+                        let (desc, keychain) = self._get_descriptor_for_keychain(*keychain);
+                        desc.derive_from_hd_keypaths(&psbt_output.bip32_derivation, &self.secp)
+                            .map(|desc| (desc.script_pubkey(), keychain))
+                    })
+                    .collect::<Vec<_>>();
+
+                let keychain = derived
+                    .iter()
+                    .find(|(script, _)| script == &txout.script_pubkey)
+                    .map(|(_, keychain)| *keychain);
+                Ok((keychain, keychain.is_none() && !derived.is_empty()))
+            }
+            _ => Ok((keychain, false)),
+        }
+    }
+
     fn get_descriptor_for_txout(
         &self,
         txout: &TxOut,
     ) -> Result<Option<DerivedDescriptor<'_>>, Error> {
         Ok(self
             .database
-            .borrow()
+            .read()
+            .unwrap()
             .get_path_from_script_pubkey(&txout.script_pubkey)?
             .map(|(keychain, child)| (self.get_descriptor_for_keychain(keychain), child))
             .map(|(desc, child)| desc.as_derived(child, &self.secp)))
     }
 
+    fn emit_event(&self, event: WalletEvent) {
+        for listener in &self.event_listeners {
+            listener.on_event(&event);
+        }
+    }
+
+    fn has_received(&self, script_pubkey: &Script) -> Result<bool, Error> {
+        Ok(self
+            .list_transactions(true)?
+            .iter()
+            .flat_map(|tx_details| tx_details.transaction.as_ref())
+            .flat_map(|tx| tx.output.iter())
+            .any(|o| &o.script_pubkey == script_pubkey))
+    }
+
     fn get_change_address(&self) -> Result<Script, Error> {
         let (desc, keychain) = self._get_descriptor_for_keychain(KeychainKind::Internal);
         let index = self.fetch_and_increment_index(keychain)?;
@@ -1080,19 +3174,34 @@ where
         let (descriptor, keychain) = self._get_descriptor_for_keychain(keychain);
         let index = match descriptor.is_deriveable() {
             false => 0,
-            true => self.database.borrow_mut().increment_last_index(keychain)?,
+            true => self
+                .database
+                .write()
+                .unwrap()
+                .increment_last_index(keychain)?,
         };
 
         if self
             .database
-            .borrow()
+            .read()
+            .unwrap()
             .get_script_pubkey_from_path(keychain, index)?
             .is_none()
         {
             self.cache_addresses(keychain, index, CACHE_ADDR_BATCH_SIZE)?;
         }
 
-        let derived_descriptor = descriptor.as_derived(index, &self.secp);
+        self.validate_address(keychain, index)?;
+
+        self.emit_event(WalletEvent::AddressRevealed { keychain, index });
+
+        Ok(index)
+    }
+
+    fn validate_address(&self, keychain: KeychainKind, index: u32) -> Result<(), Error> {
+        let derived_descriptor = self
+            .get_descriptor_for_keychain(keychain)
+            .as_derived(index, &self.secp);
 
         let hd_keypaths = derived_descriptor.get_hd_keypaths(&self.secp)?;
         let script = derived_descriptor.script_pubkey();
@@ -1101,14 +3210,14 @@ where
             validator.validate(keychain, &hd_keypaths, &script)?;
         }
 
-        Ok(index)
+        Ok(())
     }
 
     fn fetch_index(&self, keychain: KeychainKind) -> Result<u32, Error> {
         let (descriptor, keychain) = self._get_descriptor_for_keychain(keychain);
         let index = match descriptor.is_deriveable() {
             false => Some(0),
-            true => self.database.borrow_mut().get_last_index(keychain)?,
+            true => self.database.write().unwrap().get_last_index(keychain)?,
         };
 
         if let Some(i) = index {
@@ -1119,7 +3228,10 @@ where
     }
 
     fn set_index(&self, keychain: KeychainKind, index: u32) -> Result<(), Error> {
-        self.database.borrow_mut().set_last_index(keychain, index)?;
+        self.database
+            .write()
+            .unwrap()
+            .set_last_index(keychain, index)?;
         Ok(())
     }
 
@@ -1138,7 +3250,7 @@ where
             count = 1;
         }
 
-        let mut address_batch = self.database.borrow().begin_batch();
+        let mut address_batch = self.database.read().unwrap().begin_batch();
 
         let start_time = time::Instant::new();
         for i in from..(from + count) {
@@ -1156,7 +3268,7 @@ where
             start_time.elapsed().as_millis()
         );
 
-        self.database.borrow_mut().commit_batch(address_batch)?;
+        self.database.write().unwrap().commit_batch(address_batch)?;
 
         Ok(())
     }
@@ -1187,7 +3299,7 @@ where
         manually_selected: Vec<WeightedUtxo>,
         must_use_all_available: bool,
         manual_only: bool,
-        must_only_use_confirmed_tx: bool,
+        min_confirmations: u32,
     ) -> Result<(Vec<WeightedUtxo>, Vec<WeightedUtxo>), Error> {
         //    must_spend <- manually selected utxos
         //    may_spend  <- all other available utxos
@@ -1206,29 +3318,23 @@ where
             return Ok((must_spend, vec![]));
         }
 
-        let satisfies_confirmed = match must_only_use_confirmed_tx {
-            true => {
-                let database = self.database.borrow();
-                may_spend
-                    .iter()
-                    .map(|u| {
-                        database
-                            .get_tx(&u.0.outpoint.txid, true)
-                            .map(|tx| match tx {
-                                None => false,
-                                Some(tx) => tx.confirmation_time.is_some(),
-                            })
-                    })
-                    .collect::<Result<Vec<_>, _>>()?
-            }
-            false => vec![true; may_spend.len()],
-        };
+        let database = self.database.read().unwrap();
+        let is_spendable = may_spend
+            .iter()
+            .map(|u| {
+                database.get_tx(&u.0.outpoint.txid, true).map(|tx| {
+                    !self.is_immature_coinbase(tx.as_ref())
+                        && self.get_confirmations(tx.as_ref()) >= min_confirmations
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        drop(database);
 
         let mut i = 0;
         may_spend.retain(|u| {
             let retain = change_policy.is_satisfied_by(&u.0)
                 && !unspendable.contains(&u.0.outpoint)
-                && satisfies_confirmed[i];
+                && is_spendable[i];
             i += 1;
             retain
         });
@@ -1289,30 +3395,55 @@ where
             .map(|utxo| (utxo.outpoint(), utxo))
             .collect::<HashMap<_, _>>();
 
+        if let Some(outpoint) = params.input_sighashes.keys().find(|outpoint| {
+            !psbt
+                .global
+                .unsigned_tx
+                .input
+                .iter()
+                .any(|txin| txin.previous_output == **outpoint)
+        }) {
+            return Err(Error::Generic(format!(
+                "A sighash was set for {}, which is not spent by the transaction",
+                outpoint
+            )));
+        }
+
         // add metadata for the inputs
-        for (psbt_input, input) in psbt
+        for (index, (psbt_input, input)) in psbt
             .inputs
             .iter_mut()
             .zip(psbt.global.unsigned_tx.input.iter())
+            .enumerate()
         {
             let utxo = match lookup_output.remove(&input.previous_output) {
                 Some(utxo) => utxo,
                 None => continue,
             };
 
+            let sighash = params
+                .input_sighashes
+                .get(&input.previous_output)
+                .copied()
+                .or(params.sighash);
+            if let Some(sighash) = sighash {
+                signer::check_sighash(&psbt.global.unsigned_tx, index, sighash)?;
+            }
+
             match utxo {
                 Utxo::Local(utxo) => {
-                    *psbt_input =
-                        match self.get_psbt_input(utxo, params.sighash, params.only_witness_utxo) {
-                            Ok(psbt_input) => psbt_input,
-                            Err(e) => match e {
-                                Error::UnknownUtxo => Input {
-                                    sighash_type: params.sighash,
-                                    ..Input::default()
-                                },
-                                _ => return Err(e),
+                    let only_witness_utxo =
+                        params.only_witness_utxo && !params.require_non_witness_utxo;
+                    *psbt_input = match self.get_psbt_input(utxo, sighash, only_witness_utxo) {
+                        Ok(psbt_input) => psbt_input,
+                        Err(e) => match e {
+                            Error::UnknownUtxo => Input {
+                                sighash_type: sighash,
+                                ..Input::default()
                             },
-                        }
+                            _ => return Err(e),
+                        },
+                    }
                 }
                 Utxo::Foreign {
                     psbt_input: foreign_psbt_input,
@@ -1325,8 +3456,15 @@ where
                         )));
                     }
                     *psbt_input = *foreign_psbt_input;
+                    if let Some(sighash) = params.input_sighashes.get(&outpoint) {
+                        psbt_input.sighash_type = Some(*sighash);
+                    }
                 }
             }
+
+            if params.require_non_witness_utxo && psbt_input.non_witness_utxo.is_none() {
+                return Err(Error::MissingNonWitnessUtxo(input.previous_output));
+            }
         }
 
         // probably redundant but it doesn't hurt...
@@ -1340,7 +3478,8 @@ where
         {
             if let Some((keychain, child)) = self
                 .database
-                .borrow()
+                .read()
+                .unwrap()
                 .get_path_from_script_pubkey(&tx_output.script_pubkey)?
             {
                 let (desc, _) = self._get_descriptor_for_keychain(keychain);
@@ -1354,6 +3493,44 @@ where
             }
         }
 
+        // add the proprietary fields
+        for (key, value) in params.proprietary_global {
+            psbt.global.unknown.insert(key.to_raw_key(), value);
+        }
+        for (outpoint, key, value) in params.proprietary_inputs {
+            let index = psbt
+                .global
+                .unsigned_tx
+                .input
+                .iter()
+                .position(|txin| txin.previous_output == outpoint)
+                .ok_or_else(|| {
+                    Error::Generic(format!(
+                        "A proprietary field was set for {}, which is not spent by the transaction",
+                        outpoint
+                    ))
+                })?;
+            psbt.inputs[index].unknown.insert(key.to_raw_key(), value);
+        }
+        for (script_pubkey, key, value) in params.proprietary_outputs {
+            let mut found = false;
+            for (psbt_output, _) in psbt
+                .outputs
+                .iter_mut()
+                .zip(psbt.global.unsigned_tx.output.iter())
+                .filter(|(_, tx_output)| tx_output.script_pubkey == script_pubkey)
+            {
+                psbt_output.unknown.insert(key.to_raw_key(), value.clone());
+                found = true;
+            }
+            if !found {
+                return Err(Error::Generic(format!(
+                    "A proprietary field was set for the script {}, which is not an output of the transaction",
+                    script_pubkey
+                )));
+            }
+        }
+
         Ok(psbt)
     }
 
@@ -1368,7 +3545,8 @@ where
         // and the derivation index
         let (keychain, child) = self
             .database
-            .borrow()
+            .read()
+            .unwrap()
             .get_path_from_script_pubkey(&utxo.txout.script_pubkey)?
             .ok_or(Error::UnknownUtxo)?;
 
@@ -1385,7 +3563,12 @@ where
         psbt_input.witness_script = derived_descriptor.psbt_witness_script();
 
         let prev_output = utxo.outpoint;
-        if let Some(prev_tx) = self.database.borrow().get_raw_tx(&prev_output.txid)? {
+        if let Some(prev_tx) = self
+            .database
+            .read()
+            .unwrap()
+            .get_raw_tx(&prev_output.txid)?
+        {
             if desc.is_witness() {
                 psbt_input.witness_utxo = Some(prev_tx.output[prev_output.vout as usize].clone());
             }
@@ -1407,7 +3590,8 @@ where
             if let Some(out) = out {
                 if let Some((keychain, child)) = self
                     .database
-                    .borrow()
+                    .read()
+                    .unwrap()
                     .get_path_from_script_pubkey(&out.script_pubkey)?
                 {
                     debug!("Found descriptor {:?}/{}", keychain, child);
@@ -1426,6 +3610,24 @@ where
     }
 }
 
+impl<B, D> Wallet<B, D>
+where
+    D: BatchDatabase + PersistDatabase,
+{
+    /// Persist every pending write of the internal database
+    ///
+    /// Some databases, like `sled`, buffer their writes in memory and only flush
+    /// them to disk periodically. This method can be used to make sure everything has been durably
+    /// stored, for instance after [`Wallet::sync`] or after generating new addresses.
+    ///
+    /// When the `async-interface` feature is enabled this method is `async`, so that it doesn't
+    /// block the runtime while the data is being written.
+    #[maybe_async]
+    pub fn persist(&self) -> Result<(), Error> {
+        maybe_await!(self.database.read().unwrap().persist())
+    }
+}
+
 impl<B, D> Wallet<B, D>
 where
     B: Blockchain,
@@ -1441,26 +3643,59 @@ where
         client: B,
     ) -> Result<Self, Error> {
         let current_height = Some(maybe_await!(client.get_height())? as u32);
-        Self::_new(
+        let median_time_past = maybe_await!(Self::fetch_median_time_past(&client));
+        let wallet = Self::_new(
             descriptor,
             change_descriptor,
             network,
             database,
             client,
             current_height,
-        )
+        )?;
+        *wallet.median_time_past.write().unwrap() = median_time_past;
+
+        Ok(wallet)
     }
 
     /// Sync the internal database with the blockchain
+    ///
+    /// The first sync, and the syncs that follow the caching of new addresses, run a full scan
+    /// with [`Blockchain::setup`]. The others only refresh the script pubkeys that are already
+    /// known to be used, and look for new ones after them, with [`Blockchain::sync`]. Use
+    /// [`Wallet::full_scan`] to force a full scan.
+    ///
+    /// The progress of the sync is reported to `progress_update`.
     #[maybe_async]
     pub fn sync<P: 'static + Progress>(
         &self,
         progress_update: P,
         max_address_param: Option<u32>,
+    ) -> Result<(), Error> {
+        maybe_await!(self.sync_inner(progress_update, max_address_param, false))
+    }
+
+    /// Sync the internal database with the blockchain, always running a full scan
+    ///
+    /// See [`Wallet::sync`].
+    #[maybe_async]
+    pub fn full_scan<P: 'static + Progress>(
+        &self,
+        progress_update: P,
+        max_address_param: Option<u32>,
+    ) -> Result<(), Error> {
+        maybe_await!(self.sync_inner(progress_update, max_address_param, true))
+    }
+
+    #[maybe_async]
+    fn sync_inner<P: 'static + Progress>(
+        &self,
+        progress_update: P,
+        max_address_param: Option<u32>,
+        full_scan: bool,
     ) -> Result<(), Error> {
         debug!("Begin sync...");
 
-        let mut run_setup = false;
+        let mut run_setup = full_scan;
 
         let max_address = match self.descriptor.is_deriveable() {
             false => 0,
@@ -1469,7 +3704,8 @@ where
         debug!("max_address {}", max_address);
         if self
             .database
-            .borrow()
+            .read()
+            .unwrap()
             .get_script_pubkey_from_path(KeychainKind::External, max_address.saturating_sub(1))?
             .is_none()
         {
@@ -1486,7 +3722,8 @@ where
 
             if self
                 .database
-                .borrow()
+                .read()
+                .unwrap()
                 .get_script_pubkey_from_path(KeychainKind::Internal, max_address.saturating_sub(1))?
                 .is_none()
             {
@@ -1496,39 +3733,91 @@ where
             }
         }
 
+        let txs_before = self.database.read().unwrap().iter_txs(false)?;
+
         debug!("run_setup: {}", run_setup);
         // TODO: what if i generate an address first and cache some addresses?
         // TODO: we should sync if generating an address triggers a new batch to be stored
         if run_setup {
             maybe_await!(self
                 .client
-                .setup(self.database.borrow_mut().deref_mut(), progress_update,))?;
+                .setup(self.database.write().unwrap().deref_mut(), progress_update,))?;
         } else {
             maybe_await!(self
                 .client
-                .sync(self.database.borrow_mut().deref_mut(), progress_update,))?;
+                .sync(self.database.write().unwrap().deref_mut(), progress_update,))?;
+        }
+
+        // Refresh the tip after the sync, so that the confirmations of the transactions found are
+        // never counted from an older height
+        let current_height = maybe_await!(self.client.get_height())?;
+        *self.current_height.write().unwrap() = Some(current_height);
+        if let Some(median_time_past) = maybe_await!(Self::fetch_median_time_past(&self.client)) {
+            *self.median_time_past.write().unwrap() = Some(median_time_past);
         }
 
         #[cfg(feature = "verify")]
         {
             debug!("Verifying transactions...");
-            for mut tx in self.database.borrow().iter_txs(true)? {
+            let txs = self.database.read().unwrap().iter_txs(true)?;
+            for mut tx in txs {
                 if !tx.verified {
                     verify::verify_tx(
                         tx.transaction.as_ref().ok_or(Error::TransactionNotFound)?,
-                        self.database.borrow().deref(),
+                        self.database.read().unwrap().deref(),
                         &self.client,
                     )?;
 
                     tx.verified = true;
-                    self.database.borrow_mut().set_tx(&tx)?;
+                    self.database.write().unwrap().set_tx(&tx)?;
+                }
+            }
+        }
+
+        // Mark the addresses that received funds in the new transactions. A setup goes through all
+        // of them, so that the transactions synced before the marks existed are covered as well.
+        {
+            let known_txids = txs_before.iter().map(|tx| tx.txid).collect::<HashSet<_>>();
+            let mut database = self.database.write().unwrap();
+            let mut batch = database.begin_batch();
+            for tx in database.iter_txs(true)? {
+                if !run_setup && known_txids.contains(&tx.txid) {
+                    continue;
+                }
+                for output in tx.transaction.iter().flat_map(|tx| tx.output.iter()) {
+                    if let Some((keychain, child)) =
+                        database.get_path_from_script_pubkey(&output.script_pubkey)?
+                    {
+                        batch.set_used_mark(keychain, child)?;
+                    }
                 }
             }
+            database.commit_batch(batch)?;
+        }
+
+        if !self.event_listeners.is_empty() {
+            let txs_after = self.database.read().unwrap().iter_txs(false)?;
+            for event in event::diff_transactions(&txs_before, &txs_after) {
+                self.emit_event(event);
+            }
         }
 
         Ok(())
     }
 
+    // The median time past is only needed to check time-based timelocks, so failing to fetch it
+    // doesn't prevent creating or syncing the wallet
+    #[maybe_async]
+    fn fetch_median_time_past(client: &B) -> Option<u32> {
+        match maybe_await!(client.get_median_time_past()) {
+            Ok(median_time_past) => median_time_past,
+            Err(e) => {
+                info!("Can't fetch the median time past: {}", e);
+                None
+            }
+        }
+    }
+
     /// Return a reference to the internal blockchain client
     pub fn client(&self) -> &B {
         &self.client
@@ -1546,6 +3835,78 @@ where
 
         Ok(tx.txid())
     }
+
+    /// Fill the [`non_witness_utxo`](bitcoin::util::psbt::Input::non_witness_utxo) of every
+    /// input of `psbt` that doesn't have one yet
+    ///
+    /// The previous transactions are taken from the database when available, otherwise they are
+    /// requested to the blockchain backend. Returns [`Error::MissingNonWitnessUtxo`] if one of
+    /// them can't be found.
+    #[maybe_async]
+    pub fn populate_non_witness_utxos(&self, psbt: &mut Psbt) -> Result<(), Error> {
+        let tx = &psbt.global.unsigned_tx;
+        let needed = tx
+            .input
+            .iter()
+            .zip(psbt.inputs.iter())
+            .filter(|(_, psbt_input)| psbt_input.non_witness_utxo.is_none())
+            .map(|(txin, _)| txin.previous_output.txid)
+            .collect::<BTreeSet<_>>();
+
+        let mut prev_txs = HashMap::new();
+        let mut to_download = vec![];
+        {
+            let database = self.database.read().unwrap();
+            for txid in needed {
+                match database.get_raw_tx(&txid)? {
+                    Some(prev_tx) => {
+                        prev_txs.insert(txid, prev_tx);
+                    }
+                    None => to_download.push(txid),
+                }
+            }
+        }
+        // fetch all the missing transactions at once, so that the backends can batch the requests
+        let downloaded = maybe_await!(self.client.get_txs(&to_download))?;
+        prev_txs.extend(
+            to_download
+                .into_iter()
+                .zip(downloaded)
+                .filter_map(|(txid, prev_tx)| prev_tx.map(|prev_tx| (txid, prev_tx))),
+        );
+
+        for (txin, psbt_input) in tx.input.iter().zip(psbt.inputs.iter_mut()) {
+            if psbt_input.non_witness_utxo.is_some() {
+                continue;
+            }
+
+            let txid = &txin.previous_output.txid;
+            match prev_txs.get(txid) {
+                Some(prev_tx)
+                    if prev_tx.txid() == *txid
+                        && (txin.previous_output.vout as usize) < prev_tx.output.len() =>
+                {
+                    psbt_input.non_witness_utxo = Some(prev_tx.clone())
+                }
+                _ => return Err(Error::MissingNonWitnessUtxo(txin.previous_output)),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Return the utxo spent by `txin` from the PSBT input, without trusting its `non_witness_utxo`
+/// to have enough outputs
+fn get_psbt_input_utxo(psbt_input: &Input, txin: &bitcoin::TxIn) -> Option<TxOut> {
+    psbt_input.witness_utxo.clone().or_else(|| {
+        psbt_input.non_witness_utxo.as_ref().and_then(|prev_tx| {
+            prev_tx
+                .output
+                .get(txin.previous_output.vout as usize)
+                .cloned()
+        })
+    })
 }
 
 /// Trait implemented by types that can be used to measure weight units.
@@ -1575,6 +3936,132 @@ pub(crate) mod test {
     use crate::testutils;
     use crate::wallet::AddressIndex::{LastUnused, New, Peek, Reset};
 
+    #[test]
+    fn test_multipath_descriptor() {
+        let multipath = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/<0;1>/*)";
+        let external = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/0/*)";
+        let internal = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/1/*)";
+
+        let multipath_wallet =
+            Wallet::new_offline(multipath, None, Network::Testnet, MemoryDatabase::new()).unwrap();
+        let wallet = Wallet::new_offline(
+            external,
+            Some(internal),
+            Network::Testnet,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+
+        for index in 0..3 {
+            assert_eq!(
+                multipath_wallet.get_address(Peek(index)).unwrap(),
+                wallet.get_address(Peek(index)).unwrap()
+            );
+        }
+        assert_eq!(
+            multipath_wallet
+                .public_descriptor(KeychainKind::Internal)
+                .unwrap(),
+            wallet.public_descriptor(KeychainKind::Internal).unwrap()
+        );
+
+        let combined = wallet.public_multipath_descriptor().unwrap();
+        assert!(combined.starts_with(&format!("{}#", multipath)));
+        assert_eq!(
+            multipath_wallet.public_multipath_descriptor(),
+            Some(combined)
+        );
+    }
+
+    #[test]
+    fn test_key_origins() {
+        use bitcoin::util::bip32::ExtendedPubKey;
+
+        let tpub = "tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq";
+        let single = "03a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd";
+        let descriptor = format!(
+            "wsh(multi(2,[d34db33f/48'/1'/0'/2']tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/0/*,{}/0/*,{}))",
+            tpub, single
+        );
+        let wallet =
+            Wallet::new_offline(&descriptor, None, Network::Testnet, MemoryDatabase::new())
+                .unwrap();
+
+        let origins = wallet.key_origins(KeychainKind::External);
+        assert_eq!(origins.len(), 3);
+
+        assert_eq!(origins[0].fingerprint.to_string(), "d34db33f");
+        assert_eq!(
+            origins[0].origin_path,
+            DerivationPath::from_str("m/48'/1'/0'/2'").unwrap()
+        );
+        assert!(origins[0].has_private_key);
+
+        assert_eq!(
+            origins[1].fingerprint,
+            ExtendedPubKey::from_str(tpub).unwrap().fingerprint()
+        );
+        assert_eq!(origins[1].origin_path, DerivationPath::default());
+        assert!(!origins[1].has_private_key);
+
+        assert_eq!(origins[2].key.to_string(), single);
+        assert!(!origins[2].has_private_key);
+
+        // Without a change descriptor the internal keychain uses the external keys
+        assert_eq!(wallet.key_origins(KeychainKind::Internal), origins);
+    }
+
+    #[test]
+    fn test_spk_iter() {
+        let multipath = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/<0;1>/*)";
+        let wallet =
+            Wallet::new_offline(multipath, None, Network::Testnet, MemoryDatabase::new()).unwrap();
+
+        let recent = wallet
+            .spk_iter(KeychainKind::External, 3..6)
+            .rev()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            recent.iter().map(|(index, _)| *index).collect::<Vec<_>>(),
+            vec![5, 4, 3]
+        );
+        for (index, script) in recent {
+            assert_eq!(
+                wallet
+                    .get_address(AddressIndex::Peek(index))
+                    .unwrap()
+                    .script_pubkey(),
+                script
+            );
+        }
+
+        // The internal keychain uses the change paths of the multipath descriptor
+        let (_, external_script) = wallet.spk_iter(KeychainKind::External, ..).next().unwrap();
+        let (_, change_script) = wallet.spk_iter(KeychainKind::Internal, ..).next().unwrap();
+        assert_ne!(external_script, change_script);
+    }
+
+    #[test]
+    fn test_multipath_descriptor_with_change() {
+        let multipath = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/<0;1>/*)";
+        let internal = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/1/*)";
+
+        assert!(matches!(
+            Wallet::new_offline(
+                multipath,
+                Some(internal),
+                Network::Testnet,
+                MemoryDatabase::new()
+            ),
+            Err(Error::Descriptor(
+                DescriptorError::MultipathWithChangeDescriptor
+            ))
+        ));
+
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        assert_eq!(wallet.public_multipath_descriptor(), None);
+    }
+
     #[test]
     fn test_cache_addresses_fixed() {
         let db = MemoryDatabase::new();
@@ -1597,13 +4084,15 @@ pub(crate) mod test {
 
         assert!(wallet
             .database
-            .borrow_mut()
+            .write()
+            .unwrap()
             .get_script_pubkey_from_path(KeychainKind::External, 0)
             .unwrap()
             .is_some());
         assert!(wallet
             .database
-            .borrow_mut()
+            .write()
+            .unwrap()
             .get_script_pubkey_from_path(KeychainKind::Internal, 0)
             .unwrap()
             .is_none());
@@ -1625,13 +4114,15 @@ pub(crate) mod test {
 
         assert!(wallet
             .database
-            .borrow_mut()
+            .write()
+            .unwrap()
             .get_script_pubkey_from_path(KeychainKind::External, CACHE_ADDR_BATCH_SIZE - 1)
             .unwrap()
             .is_some());
         assert!(wallet
             .database
-            .borrow_mut()
+            .write()
+            .unwrap()
             .get_script_pubkey_from_path(KeychainKind::External, CACHE_ADDR_BATCH_SIZE)
             .unwrap()
             .is_none());
@@ -1648,7 +4139,8 @@ pub(crate) mod test {
         );
         assert!(wallet
             .database
-            .borrow_mut()
+            .write()
+            .unwrap()
             .get_script_pubkey_from_path(KeychainKind::External, CACHE_ADDR_BATCH_SIZE - 1)
             .unwrap()
             .is_some());
@@ -1659,7 +4151,8 @@ pub(crate) mod test {
 
         assert!(wallet
             .database
-            .borrow_mut()
+            .write()
+            .unwrap()
             .get_script_pubkey_from_path(KeychainKind::External, CACHE_ADDR_BATCH_SIZE * 2 - 1)
             .unwrap()
             .is_some());
@@ -1684,6 +4177,35 @@ pub(crate) mod test {
         "wsh(and_v(v:pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW),after(100000)))"
     }
 
+    // Like `get_funded_wallet`, but the wallet knows the current height of the chain
+    pub(crate) fn get_funded_wallet_at_height(
+        descriptor: &str,
+        current_height: u32,
+    ) -> (
+        Wallet<(), MemoryDatabase>,
+        (String, Option<String>),
+        bitcoin::Txid,
+    ) {
+        let descriptors = testutils!(@descriptors (descriptor));
+        let wallet = Wallet::_new(
+            &descriptors.0,
+            None,
+            Network::Regtest,
+            MemoryDatabase::new(),
+            (),
+            Some(current_height),
+        )
+        .unwrap();
+
+        let txid = crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 50_000 ) (@confirmations 1)),
+            Some(current_height),
+        );
+
+        (wallet, descriptors, txid)
+    }
+
     pub(crate) fn get_funded_wallet(
         descriptor: &str,
     ) -> (
@@ -1708,7 +4230,8 @@ pub(crate) mod test {
 
         wallet
             .database
-            .borrow_mut()
+            .write()
+            .unwrap()
             .set_script_pubkey(
                 &bitcoin::Address::from_str(&tx_meta.output.get(0).unwrap().to_address)
                     .unwrap()
@@ -1719,11 +4242,12 @@ pub(crate) mod test {
             .unwrap();
         wallet
             .database
-            .borrow_mut()
+            .write()
+            .unwrap()
             .set_last_index(KeychainKind::External, funding_address_kix)
             .unwrap();
 
-        let txid = crate::populate_test_db!(wallet.database.borrow_mut(), tx_meta, Some(100));
+        let txid = crate::populate_test_db!(wallet.database.write().unwrap(), tx_meta, Some(100));
 
         (wallet, descriptors, txid)
     }
@@ -1816,2013 +4340,4310 @@ pub(crate) mod test {
     }
 
     #[test]
-    fn test_create_tx_default_locktime() {
+    fn test_create_tx_recipient_address_network() {
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_address(New).unwrap();
+        let mainnet_addr = Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap();
+        let regtest_addr =
+            Address::from_str("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080").unwrap();
+
         let mut builder = wallet.build_tx();
-        builder.add_recipient(addr.script_pubkey(), 25_000);
+        assert!(matches!(
+            builder.add_recipient_address(&mainnet_addr, 25_000),
+            Err(Error::InvalidAddressNetwork(_))
+        ));
+        assert!(matches!(
+            builder.drain_to_address(&mainnet_addr),
+            Err(Error::InvalidAddressNetwork(_))
+        ));
+
+        builder
+            .add_recipient_address(&regtest_addr, 25_000)
+            .unwrap()
+            .drain_to_address(&regtest_addr)
+            .unwrap();
         let (psbt, _) = builder.finish().unwrap();
 
-        assert_eq!(psbt.global.unsigned_tx.lock_time, 0);
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 2);
+        assert!(psbt
+            .global
+            .unsigned_tx
+            .output
+            .iter()
+            .all(|txout| txout.script_pubkey == regtest_addr.script_pubkey()));
     }
 
     #[test]
-    fn test_create_tx_default_locktime_cltv() {
-        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_cltv());
+    fn test_create_tx_default_locktime() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
         builder.add_recipient(addr.script_pubkey(), 25_000);
         let (psbt, _) = builder.finish().unwrap();
 
-        assert_eq!(psbt.global.unsigned_tx.lock_time, 100_000);
+        assert_eq!(psbt.global.unsigned_tx.lock_time, 0);
     }
 
     #[test]
-    fn test_create_tx_custom_locktime() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_address(New).unwrap();
+    fn test_create_tx_anti_fee_sniping_locktime() {
+        let (wallet, _, _) = get_funded_wallet_at_height("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)", 700_000);
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+
+        for _ in 0..20 {
+            let mut builder = wallet.build_tx();
+            builder.add_recipient(addr.script_pubkey(), 25_000);
+            let (psbt, _) = builder.finish().unwrap();
+
+            let lock_time = psbt.global.unsigned_tx.lock_time;
+            assert!(lock_time > 700_000 - 100 && lock_time <= 700_000);
+            assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 0xFFFFFFFE);
+        }
+
         let mut builder = wallet.build_tx();
         builder
             .add_recipient(addr.script_pubkey(), 25_000)
-            .nlocktime(630_000);
+            .disable_anti_fee_sniping();
         let (psbt, _) = builder.finish().unwrap();
+        assert_eq!(psbt.global.unsigned_tx.lock_time, 0);
 
-        assert_eq!(psbt.global.unsigned_tx.lock_time, 630_000);
-    }
-
-    #[test]
-    fn test_create_tx_custom_locktime_compatible_with_cltv() {
-        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_cltv());
-        let addr = wallet.get_address(New).unwrap();
+        // an explicit nLockTime takes precedence
         let mut builder = wallet.build_tx();
         builder
             .add_recipient(addr.script_pubkey(), 25_000)
             .nlocktime(630_000);
         let (psbt, _) = builder.finish().unwrap();
-
         assert_eq!(psbt.global.unsigned_tx.lock_time, 630_000);
     }
 
     #[test]
-    #[should_panic(
-        expected = "TxBuilder requested timelock of `50000`, but at least `100000` is required to spend from this script"
-    )]
-    fn test_create_tx_custom_locktime_incompatible_with_cltv() {
-        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_cltv());
-        let addr = wallet.get_address(New).unwrap();
+    fn test_create_tx_min_confirmations() {
+        let (wallet, descriptors, _) = get_funded_wallet_at_height(get_test_wpkh(), 1_000);
+        let unconfirmed_txid = crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 10_000 )),
+            Some(1_000),
+        );
+        let deep_txid = crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 30_000 ) (@confirmations 9)),
+            Some(1_000),
+        );
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+
+        let get_inputs = |builder: TxBuilder<_, _, _, _>| {
+            let (psbt, _) = builder.finish().unwrap();
+            psbt.global
+                .unsigned_tx
+                .input
+                .iter()
+                .map(|txin| txin.previous_output.txid)
+                .collect::<HashSet<_>>()
+        };
+
         let mut builder = wallet.build_tx();
         builder
-            .add_recipient(addr.script_pubkey(), 25_000)
-            .nlocktime(50000);
-        builder.finish().unwrap();
-    }
+            .drain_to(addr.script_pubkey())
+            .drain_wallet()
+            .exclude_unconfirmed();
+        let inputs = get_inputs(builder);
+        assert_eq!(inputs.len(), 2);
+        assert!(!inputs.contains(&unconfirmed_txid));
 
-    #[test]
-    fn test_create_tx_no_rbf_csv() {
-        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_csv());
-        let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
-        builder.add_recipient(addr.script_pubkey(), 25_000);
-        let (psbt, _) = builder.finish().unwrap();
-
-        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 6);
-    }
+        builder
+            .drain_to(addr.script_pubkey())
+            .drain_wallet()
+            .min_confirmations(10);
+        let inputs = get_inputs(builder);
+        assert_eq!(inputs.into_iter().collect::<Vec<_>>(), vec![deep_txid]);
 
-    #[test]
-    fn test_create_tx_with_default_rbf_csv() {
-        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_csv());
-        let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
         builder
-            .add_recipient(addr.script_pubkey(), 25_000)
-            .enable_rbf();
-        let (psbt, _) = builder.finish().unwrap();
-        // When CSV is enabled it takes precedence over the rbf value (unless forced by the user).
-        // It will be set to the OP_CSV value, in this case 6
-        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 6);
+            .add_recipient(addr.script_pubkey(), 40_000)
+            .min_confirmations(10);
+        assert!(matches!(
+            builder.finish(),
+            Err(Error::InsufficientFunds { .. })
+        ));
     }
 
     #[test]
-    #[should_panic(
-        expected = "Cannot enable RBF with nSequence `3` given a required OP_CSV of `6`"
-    )]
-    fn test_create_tx_with_custom_rbf_csv() {
-        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_csv());
-        let addr = wallet.get_address(New).unwrap();
+    fn test_create_tx_consolidate() {
+        let (wallet, descriptors, _) = get_funded_wallet_at_height(get_test_wpkh(), 1_000);
+        let mut small_txids = HashSet::new();
+        for value in &[1_000, 2_000] {
+            small_txids.insert(crate::populate_test_db!(
+                wallet.database.write().unwrap(),
+                testutils! (@tx ( (@external descriptors, 0) => *value ) (@confirmations 1)),
+                Some(1_000),
+            ));
+        }
+        // a p2wpkh input costs 340 sats at 5 sat/vbyte, not worth spending
+        crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 300 ) (@confirmations 1)),
+            Some(1_000),
+        );
+
+        // only two p2wpkh inputs (~272 wu each) fit in the weight limit
         let mut builder = wallet.build_tx();
         builder
-            .add_recipient(addr.script_pubkey(), 25_000)
-            .enable_rbf_with_sequence(3);
-        builder.finish().unwrap();
+            .consolidate(700)
+            .fee_rate(FeeRate::from_sat_per_vb(5.0));
+        let (psbt, details) = builder.finish().unwrap();
+
+        let inputs = psbt
+            .global
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output.txid)
+            .collect::<HashSet<_>>();
+        assert_eq!(inputs, small_txids);
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
+        assert!(wallet
+            .is_mine(&psbt.global.unsigned_tx.output[0].script_pubkey)
+            .unwrap());
+        assert_eq!(details.sent, 3_000);
+        assert_eq!(details.received, 3_000 - details.fee.unwrap_or(0));
+    }
+
+    // Store a transaction created by the wallet and update the utxos, as a sync would
+    fn store_tx<B>(
+        wallet: &Wallet<B, MemoryDatabase>,
+        tx: Transaction,
+        details: TransactionDetails,
+    ) {
+        let mut db = wallet.database.write().unwrap();
+        for input in &tx.input {
+            db.del_utxo(&input.previous_output).unwrap();
+        }
+        for (vout, txout) in tx.output.iter().enumerate() {
+            if let Some((keychain, _)) = db
+                .get_path_from_script_pubkey(&txout.script_pubkey)
+                .unwrap()
+            {
+                db.set_utxo(&LocalUtxo {
+                    outpoint: OutPoint::new(tx.txid(), vout as u32),
+                    txout: txout.clone(),
+                    keychain,
+                })
+                .unwrap();
+            }
+        }
+        db.set_tx(&TransactionDetails {
+            transaction: Some(tx),
+            ..details
+        })
+        .unwrap();
     }
 
     #[test]
-    fn test_create_tx_no_rbf_cltv() {
-        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_cltv());
+    fn test_mark_evicted() {
+        let (wallet, _, funding_txid) = get_funded_wallet(get_test_wpkh());
         let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
         builder.add_recipient(addr.script_pubkey(), 25_000);
-        let (psbt, _) = builder.finish().unwrap();
+        let (mut psbt, details) = builder.finish().unwrap();
+        assert!(wallet.sign(&mut psbt, Default::default()).unwrap());
+        let tx = psbt.extract_tx();
+        let txid = tx.txid();
+        let fee = details.fee.unwrap();
 
-        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 0xFFFFFFFE);
-    }
+        store_tx(&wallet, tx, details);
+        assert_eq!(wallet.get_balance().unwrap(), 50_000 - fee);
 
-    #[test]
-    #[should_panic(expected = "Cannot enable RBF with a nSequence >= 0xFFFFFFFE")]
-    fn test_create_tx_invalid_rbf_sequence() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_address(New).unwrap();
-        let mut builder = wallet.build_tx();
-        builder
-            .add_recipient(addr.script_pubkey(), 25_000)
-            .enable_rbf_with_sequence(0xFFFFFFFE);
-        builder.finish().unwrap();
-    }
+        assert_eq!(wallet.mark_evicted(&txid, 1_000).unwrap(), vec![txid]);
+        assert_eq!(wallet.get_balance().unwrap(), 50_000);
+        assert_eq!(
+            wallet
+                .list_unspent()
+                .unwrap()
+                .into_iter()
+                .map(|utxo| utxo.outpoint)
+                .collect::<Vec<_>>(),
+            vec![OutPoint::new(funding_txid, 0)]
+        );
 
-    #[test]
-    fn test_create_tx_custom_rbf_sequence() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_address(New).unwrap();
-        let mut builder = wallet.build_tx();
-        builder
-            .add_recipient(addr.script_pubkey(), 25_000)
-            .enable_rbf_with_sequence(0xDEADBEEF);
-        let (psbt, _) = builder.finish().unwrap();
+        let evicted = wallet
+            .list_transactions(false)
+            .unwrap()
+            .into_iter()
+            .find(|tx| tx.txid == txid)
+            .unwrap();
+        assert_eq!(evicted.last_evicted, Some(1_000));
+        assert_eq!(evicted.confirmation_time, None);
 
-        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 0xDEADBEEF);
+        // already evicted or unknown transactions are ignored
+        assert!(wallet.mark_evicted(&txid, 2_000).unwrap().is_empty());
+        assert!(wallet
+            .mark_evicted(&Txid::default(), 2_000)
+            .unwrap()
+            .is_empty());
     }
 
     #[test]
-    fn test_create_tx_default_sequence() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+    fn test_rollback_to() {
+        let (mut wallet, _, funding_txid) = get_funded_wallet(get_test_wpkh());
+        let events = wallet.subscribe();
         let addr = wallet.get_address(New).unwrap();
+
+        // the funding tx is confirmed at height 99, spend it at height 100
         let mut builder = wallet.build_tx();
         builder.add_recipient(addr.script_pubkey(), 25_000);
-        let (psbt, _) = builder.finish().unwrap();
+        let (mut psbt, details) = builder.finish().unwrap();
+        assert!(wallet.sign(&mut psbt, Default::default()).unwrap());
+        let tx = psbt.extract_tx();
+        let spend_txid = tx.txid();
+        store_tx(
+            &wallet,
+            tx,
+            TransactionDetails {
+                confirmation_time: ConfirmationTime::new(Some(100), Some(0)),
+                ..details
+            },
+        );
+        // a coinbase paying to the wallet, mined at height 101
+        let coinbase = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![bitcoin::TxIn::default()],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: addr.script_pubkey(),
+            }],
+        };
+        let coinbase_txid = coinbase.txid();
+        store_tx(
+            &wallet,
+            coinbase,
+            TransactionDetails {
+                txid: coinbase_txid,
+                transaction: None,
+                received: 10_000,
+                sent: 0,
+                fee: Some(0),
+                confirmation_time: ConfirmationTime::new(Some(101), Some(0)),
+                verified: false,
+                last_evicted: None,
+            },
+        );
+        let balance = wallet.get_balance().unwrap();
+        while events.try_recv().is_ok() {}
+
+        let report = wallet.rollback_to(99).unwrap();
+        assert_eq!(report.unconfirmed, vec![spend_txid]);
+        assert_eq!(report.evicted, vec![coinbase_txid]);
+        assert_eq!(wallet.get_balance().unwrap(), balance - 10_000);
+
+        let txs = wallet.list_transactions(false).unwrap();
+        let confirmation_time = |txid| {
+            txs.iter()
+                .find(|tx| tx.txid == txid)
+                .unwrap()
+                .confirmation_time
+                .clone()
+        };
+        assert_eq!(confirmation_time(funding_txid).map(|c| c.height), Some(99));
+        assert_eq!(confirmation_time(spend_txid), None);
+        assert_eq!(confirmation_time(coinbase_txid), None);
 
-        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 0xFFFFFFFF);
-    }
+        let events = events.try_iter().collect::<Vec<_>>();
+        assert_eq!(events.len(), 2);
+        assert!(events.contains(&WalletEvent::ReorgDetected {
+            txid: spend_txid,
+            previous: ConfirmationTime::new(Some(100), Some(0)).unwrap(),
+        }));
+        assert!(events.contains(&WalletEvent::TxRemoved {
+            txid: coinbase_txid
+        }));
 
-    #[test]
-    #[should_panic(
-        expected = "The `change_policy` can be set only if the wallet has a change_descriptor"
-    )]
-    fn test_create_tx_change_policy_no_internal() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_address(New).unwrap();
-        let mut builder = wallet.build_tx();
-        builder
-            .add_recipient(addr.script_pubkey(), 25_000)
-            .do_not_spend_change();
-        builder.finish().unwrap();
+        // nothing left to disconnect
+        assert_eq!(wallet.rollback_to(99).unwrap(), RollbackReport::default());
     }
 
     #[test]
-    fn test_create_tx_drain_wallet_and_drain_to() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_address(New).unwrap();
-        let mut builder = wallet.build_tx();
-        builder.drain_to(addr.script_pubkey()).drain_wallet();
-        let (psbt, details) = builder.finish().unwrap();
-
-        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
+    fn test_watchlist() {
+        let (wallet, _, txid) = get_funded_wallet(get_test_wpkh());
+        let balance = wallet.get_balance().unwrap();
+        let address = Address::from_str("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080").unwrap();
+        let script = address.script_pubkey();
+
+        wallet.watch_address(&address).unwrap();
+        assert_eq!(wallet.list_watched_scripts().unwrap(), vec![script.clone()]);
         assert_eq!(
-            psbt.global.unsigned_tx.output[0].value,
-            50_000 - details.fee.unwrap_or(0)
+            wallet.watched_script_history(&script).unwrap(),
+            Some(vec![])
         );
-    }
-
-    #[test]
-    fn test_create_tx_drain_wallet_and_drain_to_and_with_recipient() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt").unwrap();
-        let drain_addr = wallet.get_address(New).unwrap();
-        let mut builder = wallet.build_tx();
-        builder
-            .add_recipient(addr.script_pubkey(), 20_000)
-            .drain_to(drain_addr.script_pubkey())
-            .drain_wallet();
-        let (psbt, details) = builder.finish().unwrap();
-        dbg!(&psbt);
-        let outputs = psbt.global.unsigned_tx.output;
+        assert!(matches!(
+            wallet.watch_address(
+                &Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap()
+            ),
+            Err(Error::InvalidAddressNetwork(_))
+        ));
 
-        assert_eq!(outputs.len(), 2);
-        let main_output = outputs
-            .iter()
-            .find(|x| x.script_pubkey == addr.script_pubkey())
-            .unwrap();
-        let drain_output = outputs
-            .iter()
-            .find(|x| x.script_pubkey == drain_addr.script_pubkey())
+        // the history is stored during sync
+        let history = vec![WatchedTx {
+            txid,
+            height: Some(100),
+        }];
+        wallet
+            .database
+            .write()
+            .unwrap()
+            .set_watched_script(&script, &history)
             .unwrap();
-        assert_eq!(main_output.value, 20_000,);
-        assert_eq!(drain_output.value, 30_000 - details.fee.unwrap_or(0));
+        // watching again doesn't reset the history
+        wallet.watch_script(script.clone()).unwrap();
+        assert_eq!(
+            wallet.watched_script_history(&script).unwrap(),
+            Some(history)
+        );
+        assert_eq!(wallet.get_balance().unwrap(), balance);
+
+        assert!(wallet.unwatch_script(&script).unwrap());
+        assert!(!wallet.unwatch_script(&script).unwrap());
+        assert!(wallet.list_watched_scripts().unwrap().is_empty());
+        assert_eq!(wallet.watched_script_history(&script).unwrap(), None);
     }
 
     #[test]
-    fn test_create_tx_default_fee_rate() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+    fn test_list_conflicts() {
+        let (wallet, _, funding_txid) = get_funded_wallet(get_test_wpkh());
         let addr = wallet.get_address(New).unwrap();
-        let mut builder = wallet.build_tx();
-        builder.add_recipient(addr.script_pubkey(), 25_000);
-        let (psbt, details) = builder.finish().unwrap();
+        let spend = |amount| {
+            let mut builder = wallet.build_tx();
+            builder.add_recipient(addr.script_pubkey(), amount);
+            let (mut psbt, details) = builder.finish().unwrap();
+            assert!(wallet.sign(&mut psbt, Default::default()).unwrap());
+            (psbt.extract_tx(), details)
+        };
 
-        assert_fee_rate!(psbt.extract_tx(), details.fee.unwrap_or(0), FeeRate::default(), @add_signature);
-    }
+        // both transactions spend the funding utxo
+        let (tx, details) = spend(25_000);
+        let first_txid = tx.txid();
+        let (replacement, replacement_details) = spend(20_000);
+        let replacement_txid = replacement.txid();
+        store_tx(&wallet, tx, details);
+        assert!(wallet.list_conflicts().unwrap().is_empty());
+        store_tx(&wallet, replacement, replacement_details.clone());
+
+        let mut txids = vec![first_txid, replacement_txid];
+        txids.sort();
+        assert_eq!(
+            wallet.list_conflicts().unwrap(),
+            vec![TxConflict {
+                outpoint: OutPoint::new(funding_txid, 0),
+                txids: txids.clone(),
+                canonical: None,
+            }]
+        );
+        assert_eq!(
+            wallet.conflicts_of(&first_txid).unwrap(),
+            vec![replacement_txid]
+        );
+        assert!(wallet.conflicts_of(&funding_txid).unwrap().is_empty());
 
-    #[test]
-    fn test_create_tx_custom_fee_rate() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_address(New).unwrap();
-        let mut builder = wallet.build_tx();
-        builder
-            .add_recipient(addr.script_pubkey(), 25_000)
-            .fee_rate(FeeRate::from_sat_per_vb(5.0));
-        let (psbt, details) = builder.finish().unwrap();
+        wallet.mark_evicted(&first_txid, 1_000).unwrap();
+        assert_eq!(
+            wallet.list_conflicts().unwrap()[0].canonical,
+            Some(replacement_txid)
+        );
 
-        assert_fee_rate!(psbt.extract_tx(), details.fee.unwrap_or(0), FeeRate::from_sat_per_vb(5.0), @add_signature);
+        // the replacement is confirmed, the evicted transaction is still reported
+        wallet
+            .database
+            .write()
+            .unwrap()
+            .set_tx(&TransactionDetails {
+                confirmation_time: ConfirmationTime::new(Some(100), Some(0)),
+                ..replacement_details
+            })
+            .unwrap();
+        let conflicts = wallet.list_conflicts().unwrap();
+        assert_eq!(conflicts[0].txids, txids);
+        assert_eq!(conflicts[0].canonical, Some(replacement_txid));
     }
 
     #[test]
-    fn test_create_tx_absolute_fee() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+    fn test_prune() {
+        let (wallet, _, funding_txid) = get_funded_wallet(get_test_wpkh());
         let addr = wallet.get_address(New).unwrap();
-        let mut builder = wallet.build_tx();
-        builder
-            .drain_to(addr.script_pubkey())
-            .drain_wallet()
-            .fee_absolute(100);
-        let (psbt, details) = builder.finish().unwrap();
+        let spend = |amount| {
+            let mut builder = wallet.build_tx();
+            builder.add_recipient(addr.script_pubkey(), amount);
+            let (mut psbt, details) = builder.finish().unwrap();
+            assert!(wallet.sign(&mut psbt, Default::default()).unwrap());
+            (psbt.extract_tx(), details)
+        };
 
-        assert_eq!(details.fee.unwrap_or(0), 100);
-        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
+        // the funding tx is confirmed at height 99, spend it at height 100
+        let (tx, details) = spend(25_000);
+        let confirmed_txid = tx.txid();
+        store_tx(
+            &wallet,
+            tx,
+            TransactionDetails {
+                confirmation_time: ConfirmationTime::new(Some(100), Some(0)),
+                ..details
+            },
+        );
+        // a transaction stored only to compute the fees of the wallet's transactions
+        let irrelevant = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut::default()],
+        };
+        wallet
+            .database
+            .write()
+            .unwrap()
+            .set_raw_tx(&irrelevant)
+            .unwrap();
+        // an unconfirmed transaction evicted from the mempool
+        let (tx, details) = spend(10_000);
+        let evicted_txid = tx.txid();
+        store_tx(&wallet, tx, details);
+        wallet.mark_evicted(&evicted_txid, 1_000).unwrap();
+
+        let balance = wallet.get_balance().unwrap();
         assert_eq!(
-            psbt.global.unsigned_tx.output[0].value,
-            50_000 - details.fee.unwrap_or(0)
+            wallet.prune(&PruneOptions::default()).unwrap(),
+            PruneReport::default()
         );
+
+        // the spending transaction is confirmed after height 99
+        let report = wallet
+            .prune(&PruneOptions {
+                spent_confirmed_at: Some(99),
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(report.removed_raw_txs.is_empty());
+
+        let report = wallet
+            .prune(&PruneOptions {
+                evicted_before: Some(2_000),
+                spent_confirmed_at: Some(100),
+                irrelevant_raw_txs: true,
+            })
+            .unwrap();
+        assert_eq!(report.removed_txs, vec![evicted_txid]);
+        let mut removed_raw_txs = report.removed_raw_txs;
+        removed_raw_txs.sort();
+        let mut expected = vec![funding_txid, irrelevant.txid()];
+        expected.sort();
+        assert_eq!(removed_raw_txs, expected);
+
+        assert_eq!(wallet.get_balance().unwrap(), balance);
+        let txs = wallet.list_transactions(true).unwrap();
+        assert_eq!(txs.len(), 2);
+        let funding = txs.iter().find(|tx| tx.txid == funding_txid).unwrap();
+        assert!(funding.transaction.is_none());
+        let confirmed = txs.iter().find(|tx| tx.txid == confirmed_txid).unwrap();
+        assert!(confirmed.transaction.is_some());
     }
 
     #[test]
-    fn test_create_tx_absolute_zero_fee() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_address(New).unwrap();
+    fn test_create_tx_unconfirmed_ancestors_fee() {
+        let (wallet, descriptors, confirmed_txid) = get_funded_wallet(get_test_wpkh());
+        let unconfirmed_txid = crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 50_000 )),
+            Some(100),
+        );
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+
+        let spend = |txid| {
+            let mut builder = wallet.build_tx();
+            builder
+                .add_utxo(OutPoint { txid, vout: 0 })
+                .unwrap()
+                .manually_selected_only()
+                .drain_to(addr.script_pubkey())
+                .fee_rate(FeeRate::from_sat_per_vb(5.0));
+            builder.finish().unwrap().1.fee.unwrap()
+        };
+
+        // the parent has no inputs and a single p2wpkh output, 41 vbytes with no fees
+        assert_eq!(spend(unconfirmed_txid), spend(confirmed_txid) + 41 * 5);
+
         let mut builder = wallet.build_tx();
         builder
             .drain_to(addr.script_pubkey())
             .drain_wallet()
-            .fee_absolute(0);
-        let (psbt, details) = builder.finish().unwrap();
-
-        assert_eq!(details.fee.unwrap_or(0), 0);
-        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
+            .exclude_low_feerate_ancestors()
+            .fee_rate(FeeRate::from_sat_per_vb(5.0));
+        let (psbt, _) = builder.finish().unwrap();
+        assert_eq!(psbt.global.unsigned_tx.input.len(), 1);
         assert_eq!(
-            psbt.global.unsigned_tx.output[0].value,
-            50_000 - details.fee.unwrap_or(0)
+            psbt.global.unsigned_tx.input[0].previous_output.txid,
+            confirmed_txid
         );
     }
 
     #[test]
-    #[should_panic(expected = "InsufficientFunds")]
-    fn test_create_tx_absolute_high_fee() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_address(New).unwrap();
+    fn test_unconfirmed_packages() {
+        let (wallet, descriptors, confirmed_txid) = get_funded_wallet(get_test_wpkh());
+        let parent_txid = crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 40_000 )),
+            Some(100),
+        );
+        let parent = wallet
+            .database
+            .read()
+            .unwrap()
+            .get_tx(&parent_txid, true)
+            .unwrap()
+            .unwrap();
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+
         let mut builder = wallet.build_tx();
         builder
-            .drain_to(addr.script_pubkey())
-            .drain_wallet()
-            .fee_absolute(60_000);
-        let (_psbt, _details) = builder.finish().unwrap();
+            .add_utxo(OutPoint::new(parent_txid, 0))
+            .unwrap()
+            .manually_selected_only()
+            .add_recipient(addr.script_pubkey(), 25_000);
+        let (mut psbt, details) = builder.finish().unwrap();
+        assert!(wallet.sign(&mut psbt, Default::default()).unwrap());
+        let child = psbt.extract_tx();
+        let child_txid = child.txid();
+        let child_fee = details.fee.unwrap();
+        let weight = child.get_weight() + parent.transaction.as_ref().unwrap().get_weight();
+        store_tx(&wallet, child, details);
+
+        let sorted = |mut package: TxPackage| {
+            package.txids.sort();
+            package
+        };
+        let mut txids = vec![parent_txid, child_txid];
+        txids.sort();
+        let expected = TxPackage {
+            txids,
+            fee: Some(child_fee + parent.fee.unwrap()),
+            weight,
+        };
+        assert_eq!(
+            sorted(wallet.unconfirmed_ancestors(&child_txid).unwrap()),
+            expected
+        );
+        assert_eq!(
+            sorted(wallet.unconfirmed_descendants(&parent_txid).unwrap()),
+            expected
+        );
+        assert_eq!(
+            expected.fee_rate().unwrap().as_sat_vb(),
+            expected.fee.unwrap() as f32 / expected.vsize()
+        );
+
+        let child_only = wallet.unconfirmed_descendants(&child_txid).unwrap();
+        assert_eq!(child_only.txids, vec![child_txid]);
+        assert_eq!(child_only.fee, Some(child_fee));
+
+        let empty = TxPackage {
+            fee: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            wallet.unconfirmed_ancestors(&confirmed_txid).unwrap(),
+            empty
+        );
+        assert_eq!(
+            wallet.unconfirmed_descendants(&confirmed_txid).unwrap(),
+            empty
+        );
     }
 
     #[test]
-    fn test_create_tx_add_change() {
-        use super::tx_builder::TxOrdering;
-
+    fn test_create_tx_add_data() {
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_address(New).unwrap();
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
         let mut builder = wallet.build_tx();
         builder
             .add_recipient(addr.script_pubkey(), 25_000)
-            .ordering(TxOrdering::Untouched);
-        let (psbt, details) = builder.finish().unwrap();
+            .add_data(b"hello")
+            .add_data(b"world")
+            .ordering(super::tx_builder::TxOrdering::Untouched);
+        let (psbt, _) = builder.finish().unwrap();
 
-        assert_eq!(psbt.global.unsigned_tx.output.len(), 2);
-        assert_eq!(psbt.global.unsigned_tx.output[0].value, 25_000);
-        assert_eq!(
-            psbt.global.unsigned_tx.output[1].value,
-            25_000 - details.fee.unwrap_or(0)
-        );
-    }
+        let data_outputs = psbt
+            .global
+            .unsigned_tx
+            .output
+            .iter()
+            .filter(|o| o.script_pubkey.is_op_return())
+            .collect::<Vec<_>>();
+        assert_eq!(data_outputs.len(), 2);
+        assert!(data_outputs.iter().all(|o| o.value == 0));
+        assert_eq!(&data_outputs[0].script_pubkey.as_bytes()[2..], b"hello");
+        assert_eq!(&data_outputs[1].script_pubkey.as_bytes()[2..], b"world");
 
-    #[test]
-    fn test_create_tx_skip_change_dust() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
-        builder.add_recipient(addr.script_pubkey(), 49_800);
-        let (psbt, details) = builder.finish().unwrap();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .add_data(&[0x42; 50])
+            .add_data(&[0x42; 50]);
+        assert!(matches!(builder.finish(), Err(Error::NonStandardData(_))));
 
-        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
-        assert_eq!(psbt.global.unsigned_tx.output[0].value, 49_800);
-        assert_eq!(details.fee.unwrap_or(0), 200);
-    }
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .add_data(&[0x42; 50])
+            .add_data(&[0x42; 50])
+            .allow_nonstandard_data();
+        assert!(builder.finish().is_ok());
 
-    #[test]
-    #[should_panic(expected = "InsufficientFunds")]
-    fn test_create_tx_drain_to_dust_amount() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_address(New).unwrap();
-        // very high fee rate, so that the only output would be below dust
         let mut builder = wallet.build_tx();
         builder
-            .drain_to(addr.script_pubkey())
-            .drain_wallet()
-            .fee_rate(FeeRate::from_sat_per_vb(453.0));
-        builder.finish().unwrap();
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .add_data_script(addr.script_pubkey())
+            .allow_nonstandard_data();
+        assert!(matches!(builder.finish(), Err(Error::NonStandardData(_))));
     }
 
     #[test]
-    fn test_create_tx_ordering_respected() {
+    fn test_create_tx_ephemeral_anchor() {
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_address(New).unwrap();
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
         let mut builder = wallet.build_tx();
         builder
-            .add_recipient(addr.script_pubkey(), 30_000)
-            .add_recipient(addr.script_pubkey(), 10_000)
-            .ordering(super::tx_builder::TxOrdering::Bip69Lexicographic);
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .version(3)
+            .add_ephemeral_anchor();
         let (psbt, details) = builder.finish().unwrap();
 
-        assert_eq!(psbt.global.unsigned_tx.output.len(), 3);
-        assert_eq!(
-            psbt.global.unsigned_tx.output[0].value,
-            10_000 - details.fee.unwrap_or(0)
-        );
-        assert_eq!(psbt.global.unsigned_tx.output[1].value, 10_000);
-        assert_eq!(psbt.global.unsigned_tx.output[2].value, 30_000);
-    }
+        assert_eq!(details.fee, Some(0));
+        assert_eq!(psbt.global.unsigned_tx.version, 3);
+        assert!(psbt
+            .global
+            .unsigned_tx
+            .output
+            .iter()
+            .any(|o| o.value == 0 && o.script_pubkey == Script::from(P2A_SCRIPT.to_vec())));
 
-    #[test]
-    fn test_create_tx_default_sighash() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
-        builder.add_recipient(addr.script_pubkey(), 30_000);
-        let (psbt, _) = builder.finish().unwrap();
-
-        assert_eq!(psbt.inputs[0].sighash_type, None);
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .version(3)
+            .add_ephemeral_anchor()
+            .fee_rate(FeeRate::from_sat_per_vb(5.0));
+        assert!(matches!(builder.finish(), Err(Error::Generic(_))));
     }
 
     #[test]
-    fn test_create_tx_custom_sighash() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_address(New).unwrap();
-        let mut builder = wallet.build_tx();
-        builder
-            .add_recipient(addr.script_pubkey(), 30_000)
-            .sighash(bitcoin::SigHashType::Single);
-        let (psbt, _) = builder.finish().unwrap();
+    fn test_create_tx_truc_policy() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let spend = |outpoint, version| {
+            let mut builder = wallet.build_tx();
+            builder
+                .add_utxo(outpoint)
+                .unwrap()
+                .manually_selected_only()
+                .drain_to(addr.script_pubkey())
+                .version(version);
+            builder.finish()
+        };
 
-        assert_eq!(
-            psbt.inputs[0].sighash_type,
-            Some(bitcoin::SigHashType::Single)
+        // a TRUC transaction can't spend an unconfirmed non-TRUC one
+        let txid = crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 )),
+            Some(100),
         );
+        assert!(matches!(
+            spend(OutPoint { txid, vout: 0 }, 3),
+            Err(Error::TrucViolation(_))
+        ));
+
+        // only TRUC transactions can spend an unconfirmed TRUC one
+        let truc_parent = Transaction {
+            version: 3,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut {
+                value: 25_000,
+                script_pubkey: wallet.get_address(Peek(0)).unwrap().script_pubkey(),
+            }],
+        };
+        let outpoint = OutPoint {
+            txid: truc_parent.txid(),
+            vout: 0,
+        };
+        {
+            let mut database = wallet.database.write().unwrap();
+            database
+                .set_tx(&TransactionDetails {
+                    transaction: Some(truc_parent.clone()),
+                    txid: truc_parent.txid(),
+                    received: 25_000,
+                    sent: 0,
+                    fee: Some(0),
+                    confirmation_time: None,
+                    verified: true,
+                    last_evicted: None,
+                })
+                .unwrap();
+            database
+                .set_utxo(&LocalUtxo {
+                    outpoint,
+                    txout: truc_parent.output[0].clone(),
+                    keychain: KeychainKind::External,
+                })
+                .unwrap();
+        }
+        assert!(matches!(spend(outpoint, 2), Err(Error::TrucViolation(_))));
+        assert!(spend(outpoint, 3).is_ok());
     }
 
     #[test]
-    fn test_create_tx_input_hd_keypaths() {
-        use bitcoin::util::bip32::{DerivationPath, Fingerprint};
-        use std::str::FromStr;
+    fn test_create_tx_csv_unsatisfied_utxos() {
+        let (wallet, descriptors, _) =
+            get_funded_wallet_at_height(get_test_single_sig_csv(), 1_000);
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
 
-        let (wallet, _, _) = get_funded_wallet("wpkh([d34db33f/44'/0'/0']tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)");
-        let addr = wallet.get_address(New).unwrap();
+        // the only utxo has two confirmations, but the descriptor requires 6
         let mut builder = wallet.build_tx();
-        builder.drain_to(addr.script_pubkey()).drain_wallet();
-        let (psbt, _) = builder.finish().unwrap();
-
-        assert_eq!(psbt.inputs[0].bip32_derivation.len(), 1);
-        assert_eq!(
-            psbt.inputs[0].bip32_derivation.values().next().unwrap(),
-            &(
-                Fingerprint::from_str("d34db33f").unwrap(),
-                DerivationPath::from_str("m/44'/0'/0'/0/0").unwrap()
-            )
+        builder.add_recipient(addr.script_pubkey(), 25_000);
+        assert!(matches!(
+            builder.finish(),
+            Err(Error::InsufficientFunds { .. })
+        ));
+
+        let txid = crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 30_000 ) (@confirmations 5)),
+            Some(1_000),
         );
+        let mut builder = wallet.build_tx();
+        builder.add_recipient(addr.script_pubkey(), 25_000);
+        let (psbt, _) = builder.finish().unwrap();
+        assert_eq!(psbt.global.unsigned_tx.input.len(), 1);
+        assert_eq!(psbt.global.unsigned_tx.input[0].previous_output.txid, txid);
+        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 6);
     }
 
     #[test]
-    fn test_create_tx_output_hd_keypaths() {
-        use bitcoin::util::bip32::{DerivationPath, Fingerprint};
-        use std::str::FromStr;
+    fn test_create_tx_cltv_not_satisfied() {
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
 
-        let (wallet, descriptors, _) = get_funded_wallet("wpkh([d34db33f/44'/0'/0']tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)");
-        // cache some addresses
-        wallet.get_address(New).unwrap();
+        let (wallet, _, _) = get_funded_wallet_at_height(get_test_single_sig_cltv(), 99_998);
+        let mut builder = wallet.build_tx();
+        builder.add_recipient(addr.script_pubkey(), 25_000);
+        assert!(matches!(
+            builder.finish(),
+            Err(Error::TimelockNotSatisfied {
+                required: 100_000,
+                current_height: 99_998
+            })
+        ));
 
-        let addr = testutils!(@external descriptors, 5);
+        let (wallet, _, _) = get_funded_wallet_at_height(get_test_single_sig_cltv(), 100_000);
         let mut builder = wallet.build_tx();
-        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        builder.add_recipient(addr.script_pubkey(), 25_000);
         let (psbt, _) = builder.finish().unwrap();
-
-        assert_eq!(psbt.outputs[0].bip32_derivation.len(), 1);
-        assert_eq!(
-            psbt.outputs[0].bip32_derivation.values().next().unwrap(),
-            &(
-                Fingerprint::from_str("d34db33f").unwrap(),
-                DerivationPath::from_str("m/44'/0'/0'/0/5").unwrap()
-            )
-        );
+        assert_eq!(psbt.global.unsigned_tx.lock_time, 100_000);
     }
 
     #[test]
-    fn test_create_tx_set_redeem_script_p2sh() {
-        use bitcoin::hashes::hex::FromHex;
+    fn test_create_tx_time_cltv() {
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        // and(pk(Alice),after(1600000000))
+        let descriptor =
+            "wsh(and_v(v:pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW),after(1600000000)))";
 
-        let (wallet, _, _) =
-            get_funded_wallet("sh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
-        let addr = wallet.get_address(New).unwrap();
+        let (wallet, _, _) = get_funded_wallet_at_height(descriptor, 1_000);
+        *wallet.median_time_past.write().unwrap() = Some(1_600_000_000);
         let mut builder = wallet.build_tx();
-        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        builder.add_recipient(addr.script_pubkey(), 25_000);
+        assert!(matches!(
+            builder.finish(),
+            Err(Error::TimeTimelockNotSatisfied {
+                required: 1_600_000_000,
+                median_time_past: 1_600_000_000
+            })
+        ));
+
+        *wallet.median_time_past.write().unwrap() = Some(1_600_000_001);
+        let mut builder = wallet.build_tx();
+        builder.add_recipient(addr.script_pubkey(), 25_000);
         let (psbt, _) = builder.finish().unwrap();
+        assert_eq!(psbt.global.unsigned_tx.lock_time, 1_600_000_000);
 
-        assert_eq!(
-            psbt.inputs[0].redeem_script,
-            Some(Script::from(
-                Vec::<u8>::from_hex(
-                    "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac"
-                )
-                .unwrap()
-            ))
-        );
-        assert_eq!(psbt.inputs[0].witness_script, None);
+        let mut signed = psbt.clone();
+        assert!(wallet.sign(&mut signed, Default::default()).unwrap());
+
+        // without the median time past the timelock can't be checked
+        *wallet.median_time_past.write().unwrap() = None;
+        let mut signed = psbt;
+        assert!(!wallet.sign(&mut signed, Default::default()).unwrap());
     }
 
     #[test]
-    fn test_create_tx_set_witness_script_p2wsh() {
-        use bitcoin::hashes::hex::FromHex;
-
-        let (wallet, _, _) =
-            get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    fn test_create_tx_default_locktime_cltv() {
+        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_cltv());
         let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
-        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        builder.add_recipient(addr.script_pubkey(), 25_000);
         let (psbt, _) = builder.finish().unwrap();
 
-        assert_eq!(psbt.inputs[0].redeem_script, None);
-        assert_eq!(
-            psbt.inputs[0].witness_script,
-            Some(Script::from(
-                Vec::<u8>::from_hex(
-                    "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac"
-                )
-                .unwrap()
-            ))
-        );
+        assert_eq!(psbt.global.unsigned_tx.lock_time, 100_000);
     }
 
     #[test]
-    fn test_create_tx_set_redeem_witness_script_p2wsh_p2sh() {
-        use bitcoin::hashes::hex::FromHex;
-
-        let (wallet, _, _) =
-            get_funded_wallet("sh(wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)))");
+    fn test_create_tx_custom_locktime() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
-        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .nlocktime(630_000);
         let (psbt, _) = builder.finish().unwrap();
 
-        let script = Script::from(
-            Vec::<u8>::from_hex(
-                "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac",
-            )
-            .unwrap(),
-        );
-
-        assert_eq!(psbt.inputs[0].redeem_script, Some(script.to_v0_p2wsh()));
-        assert_eq!(psbt.inputs[0].witness_script, Some(script));
+        assert_eq!(psbt.global.unsigned_tx.lock_time, 630_000);
     }
 
     #[test]
-    fn test_create_tx_non_witness_utxo() {
-        let (wallet, _, _) =
-            get_funded_wallet("sh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    fn test_create_tx_custom_locktime_compatible_with_cltv() {
+        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_cltv());
         let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
-        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .nlocktime(630_000);
         let (psbt, _) = builder.finish().unwrap();
 
-        assert!(psbt.inputs[0].non_witness_utxo.is_some());
-        assert!(psbt.inputs[0].witness_utxo.is_none());
+        assert_eq!(psbt.global.unsigned_tx.lock_time, 630_000);
     }
 
     #[test]
-    fn test_create_tx_only_witness_utxo() {
-        let (wallet, _, _) =
-            get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    #[should_panic(
+        expected = "TxBuilder requested timelock of `50000`, but at least `100000` is required to spend from this script"
+    )]
+    fn test_create_tx_custom_locktime_incompatible_with_cltv() {
+        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_cltv());
         let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
         builder
-            .drain_to(addr.script_pubkey())
-            .only_witness_utxo()
-            .drain_wallet();
-        let (psbt, _) = builder.finish().unwrap();
-
-        assert!(psbt.inputs[0].non_witness_utxo.is_none());
-        assert!(psbt.inputs[0].witness_utxo.is_some());
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .nlocktime(50000);
+        builder.finish().unwrap();
     }
 
     #[test]
-    fn test_create_tx_shwpkh_has_witness_utxo() {
-        let (wallet, _, _) =
-            get_funded_wallet("sh(wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    fn test_create_tx_no_rbf_csv() {
+        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_csv());
         let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
-        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        builder.add_recipient(addr.script_pubkey(), 25_000);
         let (psbt, _) = builder.finish().unwrap();
 
-        assert!(psbt.inputs[0].witness_utxo.is_some());
+        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 6);
     }
 
     #[test]
-    fn test_create_tx_both_non_witness_utxo_and_witness_utxo_default() {
-        let (wallet, _, _) =
-            get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    fn test_create_tx_with_default_rbf_csv() {
+        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_csv());
         let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
-        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .enable_rbf();
         let (psbt, _) = builder.finish().unwrap();
-
-        assert!(psbt.inputs[0].non_witness_utxo.is_some());
-        assert!(psbt.inputs[0].witness_utxo.is_some());
+        // When CSV is enabled it takes precedence over the rbf value (unless forced by the user).
+        // It will be set to the OP_CSV value, in this case 6
+        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 6);
     }
 
     #[test]
-    fn test_create_tx_add_utxo() {
-        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
-        let small_output_txid = crate::populate_test_db!(
-            wallet.database.borrow_mut(),
-            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
-            Some(100),
-        );
-
-        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+    #[should_panic(
+        expected = "Cannot enable RBF with nSequence `3` given a required OP_CSV of `6`"
+    )]
+    fn test_create_tx_with_custom_rbf_csv() {
+        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_csv());
+        let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
         builder
-            .add_recipient(addr.script_pubkey(), 30_000)
-            .add_utxo(OutPoint {
-                txid: small_output_txid,
-                vout: 0,
-            })
-            .unwrap();
-        let (psbt, details) = builder.finish().unwrap();
-
-        assert_eq!(
-            psbt.global.unsigned_tx.input.len(),
-            2,
-            "should add an additional input since 25_000 < 30_000"
-        );
-        assert_eq!(details.sent, 75_000, "total should be sum of both inputs");
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .enable_rbf_with_sequence(3);
+        builder.finish().unwrap();
     }
 
     #[test]
-    #[should_panic(expected = "InsufficientFunds")]
-    fn test_create_tx_manually_selected_insufficient() {
-        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
-        let small_output_txid = crate::populate_test_db!(
-            wallet.database.borrow_mut(),
-            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
-            Some(100),
-        );
-
-        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+    fn test_create_tx_no_rbf_cltv() {
+        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_cltv());
+        let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
-        builder
-            .add_recipient(addr.script_pubkey(), 30_000)
-            .add_utxo(OutPoint {
-                txid: small_output_txid,
-                vout: 0,
-            })
-            .unwrap()
-            .manually_selected_only();
-        builder.finish().unwrap();
+        builder.add_recipient(addr.script_pubkey(), 25_000);
+        let (psbt, _) = builder.finish().unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 0xFFFFFFFE);
     }
 
     #[test]
-    #[should_panic(expected = "SpendingPolicyRequired(External)")]
-    fn test_create_tx_policy_path_required() {
-        let (wallet, _, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
-
-        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+    #[should_panic(expected = "Cannot enable RBF with a nSequence >= 0xFFFFFFFE")]
+    fn test_create_tx_invalid_rbf_sequence() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
-        builder.add_recipient(addr.script_pubkey(), 30_000);
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .enable_rbf_with_sequence(0xFFFFFFFE);
         builder.finish().unwrap();
     }
 
     #[test]
-    fn test_create_tx_policy_path_no_csv() {
-        let (wallet, _, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
-
-        let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
-        let root_id = external_policy.id;
-        // child #0 is just the key "A"
-        let path = vec![(root_id, vec![0])].into_iter().collect();
-
-        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+    fn test_create_tx_custom_rbf_sequence() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
         builder
-            .add_recipient(addr.script_pubkey(), 30_000)
-            .policy_path(path, KeychainKind::External);
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .enable_rbf_with_sequence(0xDEADBEEF);
         let (psbt, _) = builder.finish().unwrap();
 
-        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 0xFFFFFFFF);
+        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 0xDEADBEEF);
     }
 
     #[test]
-    fn test_create_tx_policy_path_use_csv() {
-        let (wallet, _, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
-
-        let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
-        let root_id = external_policy.id;
-        // child #1 is or(pk(B),older(144))
-        let path = vec![(root_id, vec![1])].into_iter().collect();
-
-        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+    fn test_create_tx_default_sequence() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
-        builder
-            .add_recipient(addr.script_pubkey(), 30_000)
-            .policy_path(path, KeychainKind::External);
+        builder.add_recipient(addr.script_pubkey(), 25_000);
         let (psbt, _) = builder.finish().unwrap();
 
-        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 144);
+        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 0xFFFFFFFF);
     }
 
     #[test]
-    fn test_create_tx_global_xpubs_with_origin() {
-        use bitcoin::hashes::hex::FromHex;
-        use bitcoin::util::base58;
-        use bitcoin::util::psbt::raw::Key;
-
-        let (wallet, _, _) = get_funded_wallet("wpkh([73756c7f/48'/0'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*)");
+    #[should_panic(
+        expected = "The `change_policy` can be set only if the wallet has a change_descriptor"
+    )]
+    fn test_create_tx_change_policy_no_internal() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
         builder
             .add_recipient(addr.script_pubkey(), 25_000)
-            .add_global_xpubs();
-        let (psbt, _) = builder.finish().unwrap();
-
-        let type_value = 0x01;
-        let key = base58::from_check("tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3").unwrap();
-
-        let psbt_key = Key { type_value, key };
-
-        // This key has an explicit origin, so it will be encoded here
-        let value_bytes = Vec::<u8>::from_hex("73756c7f30000080000000800000008002000080").unwrap();
-
-        assert_eq!(psbt.global.unknown.len(), 1);
-        assert_eq!(psbt.global.unknown.get(&psbt_key), Some(&value_bytes));
+            .do_not_spend_change();
+        builder.finish().unwrap();
     }
 
     #[test]
-    fn test_add_foreign_utxo() {
-        let (wallet1, _, _) = get_funded_wallet(get_test_wpkh());
-        let (wallet2, _, _) =
-            get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
-
-        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
-        let utxo = wallet2.list_unspent().unwrap().remove(0);
-        let foreign_utxo_satisfaction = wallet2
-            .get_descriptor_for_keychain(KeychainKind::External)
-            .max_satisfaction_weight()
-            .unwrap();
-
-        let psbt_input = psbt::Input {
-            witness_utxo: Some(utxo.txout.clone()),
-            ..Default::default()
-        };
-
-        let mut builder = wallet1.build_tx();
-        builder
-            .add_recipient(addr.script_pubkey(), 60_000)
-            .only_witness_utxo()
-            .add_foreign_utxo(utxo.outpoint, psbt_input, foreign_utxo_satisfaction)
-            .unwrap();
-        let (mut psbt, details) = builder.finish().unwrap();
+    fn test_create_tx_drain_wallet_and_drain_to() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        let (psbt, details) = builder.finish().unwrap();
 
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
         assert_eq!(
-            details.sent - details.received,
-            10_000 + details.fee.unwrap_or(0),
-            "we should have only net spent ~10_000"
+            psbt.global.unsigned_tx.output[0].value,
+            50_000 - details.fee.unwrap_or(0)
         );
-
-        assert!(
-            psbt.global
-                .unsigned_tx
-                .input
-                .iter()
-                .any(|input| input.previous_output == utxo.outpoint),
-            "foreign_utxo should be in there"
-        );
-
-        let finished = wallet1
-            .sign(
-                &mut psbt,
-                SignOptions {
-                    trust_witness_utxo: true,
-                    ..Default::default()
-                },
-            )
-            .unwrap();
-
-        assert!(
-            !finished,
-            "only one of the inputs should have been signed so far"
-        );
-
-        let finished = wallet2
-            .sign(
-                &mut psbt,
-                SignOptions {
-                    trust_witness_utxo: true,
-                    ..Default::default()
-                },
-            )
-            .unwrap();
-        assert!(finished, "all the inputs should have been signed now");
-    }
+    }
 
     #[test]
-    #[should_panic(expected = "Generic(\"Foreign utxo missing witness_utxo or non_witness_utxo\")")]
-    fn test_add_foreign_utxo_invalid_psbt_input() {
+    fn test_create_tx_drain_wallet_and_drain_to_and_with_recipient() {
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt").unwrap();
+        let drain_addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
-        let outpoint = wallet.list_unspent().unwrap()[0].outpoint;
-        let foreign_utxo_satisfaction = wallet
-            .get_descriptor_for_keychain(KeychainKind::External)
-            .max_satisfaction_weight()
-            .unwrap();
         builder
-            .add_foreign_utxo(outpoint, psbt::Input::default(), foreign_utxo_satisfaction)
-            .unwrap();
-    }
-
-    #[test]
-    fn test_add_foreign_utxo_where_outpoint_doesnt_match_psbt_input() {
-        let (wallet1, _, txid1) = get_funded_wallet(get_test_wpkh());
-        let (wallet2, _, txid2) =
-            get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+            .add_recipient(addr.script_pubkey(), 20_000)
+            .drain_to(drain_addr.script_pubkey())
+            .drain_wallet();
+        let (psbt, details) = builder.finish().unwrap();
+        dbg!(&psbt);
+        let outputs = psbt.global.unsigned_tx.output;
 
-        let utxo2 = wallet2.list_unspent().unwrap().remove(0);
-        let tx1 = wallet1
-            .database
-            .borrow()
-            .get_tx(&txid1, true)
-            .unwrap()
-            .unwrap()
-            .transaction
-            .unwrap();
-        let tx2 = wallet2
-            .database
-            .borrow()
-            .get_tx(&txid2, true)
-            .unwrap()
-            .unwrap()
-            .transaction
+        assert_eq!(outputs.len(), 2);
+        let main_output = outputs
+            .iter()
+            .find(|x| x.script_pubkey == addr.script_pubkey())
             .unwrap();
-
-        let satisfaction_weight = wallet2
-            .get_descriptor_for_keychain(KeychainKind::External)
-            .max_satisfaction_weight()
+        let drain_output = outputs
+            .iter()
+            .find(|x| x.script_pubkey == drain_addr.script_pubkey())
             .unwrap();
-
-        let mut builder = wallet1.build_tx();
-        assert!(
-            builder
-                .add_foreign_utxo(
-                    utxo2.outpoint,
-                    psbt::Input {
-                        non_witness_utxo: Some(tx1),
-                        ..Default::default()
-                    },
-                    satisfaction_weight
-                )
-                .is_err(),
-            "should fail when outpoint doesn't match psbt_input"
-        );
-        assert!(
-            builder
-                .add_foreign_utxo(
-                    utxo2.outpoint,
-                    psbt::Input {
-                        non_witness_utxo: Some(tx2),
-                        ..Default::default()
-                    },
-                    satisfaction_weight
-                )
-                .is_ok(),
-            "shoulld be ok when outpoint does match psbt_input"
-        );
+        assert_eq!(main_output.value, 20_000,);
+        assert_eq!(drain_output.value, 30_000 - details.fee.unwrap_or(0));
     }
 
     #[test]
-    fn test_add_foreign_utxo_only_witness_utxo() {
-        let (wallet1, _, _) = get_funded_wallet(get_test_wpkh());
-        let (wallet2, _, txid2) =
-            get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
-        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
-        let utxo2 = wallet2.list_unspent().unwrap().remove(0);
-
-        let satisfaction_weight = wallet2
-            .get_descriptor_for_keychain(KeychainKind::External)
-            .max_satisfaction_weight()
-            .unwrap();
-
-        let mut builder = wallet1.build_tx();
-        builder.add_recipient(addr.script_pubkey(), 60_000);
-
-        {
-            let mut builder = builder.clone();
-            let psbt_input = psbt::Input {
-                witness_utxo: Some(utxo2.txout.clone()),
-                ..Default::default()
-            };
-            builder
-                .add_foreign_utxo(utxo2.outpoint, psbt_input, satisfaction_weight)
-                .unwrap();
-            assert!(
-                builder.finish().is_err(),
-                "psbt_input with witness_utxo should fail with only witness_utxo"
-            );
-        }
-
-        {
-            let mut builder = builder.clone();
-            let psbt_input = psbt::Input {
-                witness_utxo: Some(utxo2.txout.clone()),
-                ..Default::default()
-            };
-            builder
-                .only_witness_utxo()
-                .add_foreign_utxo(utxo2.outpoint, psbt_input, satisfaction_weight)
-                .unwrap();
-            assert!(
-                builder.finish().is_ok(),
-                "psbt_input with just witness_utxo should succeed when `only_witness_utxo` is enabled"
-            );
-        }
+    fn test_create_tx_default_fee_rate() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.add_recipient(addr.script_pubkey(), 25_000);
+        let (psbt, details) = builder.finish().unwrap();
 
-        {
-            let mut builder = builder.clone();
-            let tx2 = wallet2
-                .database
-                .borrow()
-                .get_tx(&txid2, true)
-                .unwrap()
-                .unwrap()
-                .transaction
-                .unwrap();
-            let psbt_input = psbt::Input {
-                non_witness_utxo: Some(tx2),
-                ..Default::default()
-            };
-            builder
-                .add_foreign_utxo(utxo2.outpoint, psbt_input, satisfaction_weight)
-                .unwrap();
-            assert!(
-                builder.finish().is_ok(),
-                "psbt_input with non_witness_utxo should succeed by default"
-            );
-        }
+        assert_fee_rate!(psbt.extract_tx(), details.fee.unwrap_or(0), FeeRate::default(), @add_signature);
     }
 
     #[test]
-    fn test_get_psbt_input() {
-        // this should grab a known good utxo and set the input
+    fn test_create_tx_custom_fee_rate() {
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        for utxo in wallet.list_unspent().unwrap() {
-            let psbt_input = wallet.get_psbt_input(utxo, None, false).unwrap();
-            assert!(psbt_input.witness_utxo.is_some() || psbt_input.non_witness_utxo.is_some());
-        }
-    }
-
-    #[test]
-    #[should_panic(
-        expected = "MissingKeyOrigin(\"tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3\")"
-    )]
-    fn test_create_tx_global_xpubs_origin_missing() {
-        let (wallet, _, _) = get_funded_wallet("wpkh(tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*)");
         let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
         builder
             .add_recipient(addr.script_pubkey(), 25_000)
-            .add_global_xpubs();
-        builder.finish().unwrap();
+            .fee_rate(FeeRate::from_sat_per_vb(5.0));
+        let (psbt, details) = builder.finish().unwrap();
+
+        assert_fee_rate!(psbt.extract_tx(), details.fee.unwrap_or(0), FeeRate::from_sat_per_vb(5.0), @add_signature);
     }
 
     #[test]
-    fn test_create_tx_global_xpubs_master_without_origin() {
-        use bitcoin::hashes::hex::FromHex;
-        use bitcoin::util::base58;
-        use bitcoin::util::psbt::raw::Key;
-
-        let (wallet, _, _) = get_funded_wallet("wpkh(tpubD6NzVbkrYhZ4Y55A58Gv9RSNF5hy84b5AJqYy7sCcjFrkcLpPre8kmgfit6kY1Zs3BLgeypTDBZJM222guPpdz7Cup5yzaMu62u7mYGbwFL/0/*)");
+    fn test_create_tx_fee_estimate() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = wallet.get_address(New).unwrap();
+        let mut estimates = FeeEstimates::new(0);
+        estimates.insert(1, FeeRate::from_sat_per_vb(20.0));
+        estimates.insert(6, FeeRate::from_sat_per_vb(5.0));
         let mut builder = wallet.build_tx();
         builder
             .add_recipient(addr.script_pubkey(), 25_000)
-            .add_global_xpubs();
-        let (psbt, _) = builder.finish().unwrap();
-
-        let type_value = 0x01;
-        let key = base58::from_check("tpubD6NzVbkrYhZ4Y55A58Gv9RSNF5hy84b5AJqYy7sCcjFrkcLpPre8kmgfit6kY1Zs3BLgeypTDBZJM222guPpdz7Cup5yzaMu62u7mYGbwFL").unwrap();
-
-        let psbt_key = Key { type_value, key };
+            .fee_estimate(&estimates, 12);
+        let (psbt, details) = builder.finish().unwrap();
 
-        // This key doesn't have an explicit origin, but it's a master key (depth = 0). So we encode
-        // its fingerprint directly and an empty path
-        let value_bytes = Vec::<u8>::from_hex("997a323b").unwrap();
+        assert_fee_rate!(psbt.extract_tx(), details.fee.unwrap_or(0), FeeRate::from_sat_per_vb(5.0), @add_signature);
 
-        assert_eq!(psbt.global.unknown.len(), 1);
-        assert_eq!(psbt.global.unknown.get(&psbt_key), Some(&value_bytes));
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .fee_estimate(&FeeEstimates::new(0), 6);
+        assert!(matches!(builder.finish(), Err(Error::FeeRateUnavailable)));
     }
 
     #[test]
-    #[should_panic(expected = "IrreplaceableTransaction")]
-    fn test_bump_fee_irreplaceable_tx() {
+    fn test_create_tx_absolute_fee() {
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
-        builder.add_recipient(addr.script_pubkey(), 25_000);
-        let (psbt, mut details) = builder.finish().unwrap();
-
-        let tx = psbt.extract_tx();
-        let txid = tx.txid();
-        // skip saving the utxos, we know they can't be used anyways
-        details.transaction = Some(tx);
-        wallet.database.borrow_mut().set_tx(&details).unwrap();
+        builder
+            .drain_to(addr.script_pubkey())
+            .drain_wallet()
+            .fee_absolute(100);
+        let (psbt, details) = builder.finish().unwrap();
 
-        wallet.build_fee_bump(txid).unwrap().finish().unwrap();
+        assert_eq!(details.fee.unwrap_or(0), 100);
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
+        assert_eq!(
+            psbt.global.unsigned_tx.output[0].value,
+            50_000 - details.fee.unwrap_or(0)
+        );
     }
 
     #[test]
-    #[should_panic(expected = "TransactionConfirmed")]
-    fn test_bump_fee_confirmed_tx() {
+    fn test_create_tx_max_absolute_fee() {
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = wallet.get_address(New).unwrap();
-        let mut builder = wallet.build_tx();
-        builder.add_recipient(addr.script_pubkey(), 25_000);
-        let (psbt, mut details) = builder.finish().unwrap();
 
-        let tx = psbt.extract_tx();
-        let txid = tx.txid();
-        // skip saving the utxos, we know they can't be used anyways
-        details.transaction = Some(tx);
-        details.confirmation_time = Some(ConfirmationTime {
-            timestamp: 12345678,
-            height: 42,
-        });
-        wallet.database.borrow_mut().set_tx(&details).unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .drain_to(addr.script_pubkey())
+            .drain_wallet()
+            .fee_absolute(1_000)
+            .max_absolute_fee(500);
+        assert!(matches!(
+            builder.finish(),
+            Err(Error::FeeTooHigh {
+                fee: 1_000,
+                max: 500
+            })
+        ));
 
-        wallet.build_fee_bump(txid).unwrap().finish().unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .drain_to(addr.script_pubkey())
+            .drain_wallet()
+            .fee_absolute(500)
+            .max_absolute_fee(500);
+        let (_, details) = builder.finish().unwrap();
+        assert_eq!(details.fee, Some(500));
     }
 
     #[test]
-    #[should_panic(expected = "FeeRateTooLow")]
-    fn test_bump_fee_low_fee_rate() {
+    fn test_create_tx_max_fee_rate() {
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_address(New).unwrap();
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+
         let mut builder = wallet.build_tx();
         builder
             .add_recipient(addr.script_pubkey(), 25_000)
-            .enable_rbf();
-        let (psbt, mut details) = builder.finish().unwrap();
-
-        let tx = psbt.extract_tx();
-        let txid = tx.txid();
-        // skip saving the utxos, we know they can't be used anyways
-        details.transaction = Some(tx);
-        wallet.database.borrow_mut().set_tx(&details).unwrap();
-
-        let mut builder = wallet.build_fee_bump(txid).unwrap();
-        builder.fee_rate(FeeRate::from_sat_per_vb(1.0));
+            .fee_rate(FeeRate::from_sat_per_vb(50.0))
+            .max_fee_rate(FeeRate::from_sat_per_vb(10.0));
+        assert!(matches!(
+            builder.finish(),
+            Err(Error::FeeRateTooHigh { .. })
+        ));
+
+        // the limit is inclusive
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .fee_rate(FeeRate::from_sat_per_vb(10.0))
+            .max_fee_rate(FeeRate::from_sat_per_vb(10.0));
         builder.finish().unwrap();
+
+        // dust change that would be added to the fee is also taken into account
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 49_500)
+            .fee_rate(FeeRate::from_sat_per_vb(1.0))
+            .max_fee_rate(FeeRate::from_sat_per_vb(2.0));
+        assert!(matches!(
+            builder.finish(),
+            Err(Error::FeeRateTooHigh { .. })
+        ));
     }
 
     #[test]
-    #[should_panic(expected = "FeeTooLow")]
-    fn test_bump_fee_low_abs() {
+    fn test_create_tx_absolute_zero_fee() {
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
         builder
-            .add_recipient(addr.script_pubkey(), 25_000)
-            .enable_rbf();
-        let (psbt, mut details) = builder.finish().unwrap();
-
-        let tx = psbt.extract_tx();
-        let txid = tx.txid();
-        // skip saving the utxos, we know they can't be used anyways
-        details.transaction = Some(tx);
-        wallet.database.borrow_mut().set_tx(&details).unwrap();
+            .drain_to(addr.script_pubkey())
+            .drain_wallet()
+            .fee_absolute(0);
+        let (psbt, details) = builder.finish().unwrap();
 
-        let mut builder = wallet.build_fee_bump(txid).unwrap();
-        builder.fee_absolute(10);
-        builder.finish().unwrap();
+        assert_eq!(details.fee.unwrap_or(0), 0);
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
+        assert_eq!(
+            psbt.global.unsigned_tx.output[0].value,
+            50_000 - details.fee.unwrap_or(0)
+        );
     }
 
     #[test]
-    #[should_panic(expected = "FeeTooLow")]
-    fn test_bump_fee_zero_abs() {
+    #[should_panic(expected = "InsufficientFunds")]
+    fn test_create_tx_absolute_high_fee() {
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
         builder
-            .add_recipient(addr.script_pubkey(), 25_000)
-            .enable_rbf();
-        let (psbt, mut details) = builder.finish().unwrap();
-
-        let tx = psbt.extract_tx();
-        let txid = tx.txid();
-        // skip saving the utxos, we know they can't be used anyways
-        details.transaction = Some(tx);
-        wallet.database.borrow_mut().set_tx(&details).unwrap();
-
-        let mut builder = wallet.build_fee_bump(txid).unwrap();
-        builder.fee_absolute(0);
-        builder.finish().unwrap();
+            .drain_to(addr.script_pubkey())
+            .drain_wallet()
+            .fee_absolute(60_000);
+        let (_psbt, _details) = builder.finish().unwrap();
     }
 
     #[test]
-    fn test_bump_fee_reduce_change() {
+    fn test_create_tx_add_change() {
+        use super::tx_builder::TxOrdering;
+
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
         builder
             .add_recipient(addr.script_pubkey(), 25_000)
-            .enable_rbf();
-        let (psbt, mut original_details) = builder.finish().unwrap();
-        let mut tx = psbt.extract_tx();
-        let txid = tx.txid();
-        // skip saving the new utxos, we know they can't be used anyways
-        for txin in &mut tx.input {
-            txin.witness.push([0x00; 108].to_vec()); // fake signature
-            wallet
-                .database
-                .borrow_mut()
-                .del_utxo(&txin.previous_output)
-                .unwrap();
-        }
-        original_details.transaction = Some(tx);
-        wallet
-            .database
-            .borrow_mut()
-            .set_tx(&original_details)
-            .unwrap();
-
-        let mut builder = wallet.build_fee_bump(txid).unwrap();
-        builder.fee_rate(FeeRate::from_sat_per_vb(2.5)).enable_rbf();
+            .ordering(TxOrdering::Untouched);
         let (psbt, details) = builder.finish().unwrap();
 
-        assert_eq!(details.sent, original_details.sent);
-        assert_eq!(
-            details.received + details.fee.unwrap_or(0),
-            original_details.received + original_details.fee.unwrap_or(0)
-        );
-        assert!(details.fee.unwrap_or(0) > original_details.fee.unwrap_or(0));
-
-        let tx = &psbt.global.unsigned_tx;
-        assert_eq!(tx.output.len(), 2);
-        assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey == addr.script_pubkey())
-                .unwrap()
-                .value,
-            25_000
-        );
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 2);
+        assert_eq!(psbt.global.unsigned_tx.output[0].value, 25_000);
         assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey != addr.script_pubkey())
-                .unwrap()
-                .value,
-            details.received
+            psbt.global.unsigned_tx.output[1].value,
+            25_000 - details.fee.unwrap_or(0)
         );
-
-        assert_fee_rate!(psbt.extract_tx(), details.fee.unwrap_or(0), FeeRate::from_sat_per_vb(2.5), @add_signature);
     }
 
     #[test]
-    fn test_bump_fee_absolute_reduce_change() {
+    fn test_create_tx_skip_change_dust() {
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let addr = wallet.get_address(New).unwrap();
         let mut builder = wallet.build_tx();
-        builder
-            .add_recipient(addr.script_pubkey(), 25_000)
-            .enable_rbf();
-        let (psbt, mut original_details) = builder.finish().unwrap();
-        let mut tx = psbt.extract_tx();
-        let txid = tx.txid();
-        // skip saving the new utxos, we know they can't be used anyways
-        for txin in &mut tx.input {
-            txin.witness.push([0x00; 108].to_vec()); // fake signature
-            wallet
-                .database
-                .borrow_mut()
-                .del_utxo(&txin.previous_output)
-                .unwrap();
-        }
-        original_details.transaction = Some(tx);
-        wallet
-            .database
-            .borrow_mut()
-            .set_tx(&original_details)
-            .unwrap();
-
-        let mut builder = wallet.build_fee_bump(txid).unwrap();
-        builder.fee_absolute(200);
-        builder.enable_rbf();
+        builder.add_recipient(addr.script_pubkey(), 49_800);
         let (psbt, details) = builder.finish().unwrap();
 
-        assert_eq!(details.sent, original_details.sent);
-        assert_eq!(
-            details.received + details.fee.unwrap_or(0),
-            original_details.received + original_details.fee.unwrap_or(0)
-        );
-        assert!(
-            details.fee.unwrap_or(0) > original_details.fee.unwrap_or(0),
-            "{} > {}",
-            details.fee.unwrap_or(0),
-            original_details.fee.unwrap_or(0)
-        );
-
-        let tx = &psbt.global.unsigned_tx;
-        assert_eq!(tx.output.len(), 2);
-        assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey == addr.script_pubkey())
-                .unwrap()
-                .value,
-            25_000
-        );
-        assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey != addr.script_pubkey())
-                .unwrap()
-                .value,
-            details.received
-        );
-
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
+        assert_eq!(psbt.global.unsigned_tx.output[0].value, 49_800);
         assert_eq!(details.fee.unwrap_or(0), 200);
     }
 
     #[test]
-    fn test_bump_fee_reduce_single_recipient() {
+    #[should_panic(expected = "InsufficientFunds")]
+    fn test_create_tx_drain_to_dust_amount() {
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let addr = wallet.get_address(New).unwrap();
+        // very high fee rate, so that the only output would be below dust
         let mut builder = wallet.build_tx();
         builder
             .drain_to(addr.script_pubkey())
             .drain_wallet()
-            .enable_rbf();
-        let (psbt, mut original_details) = builder.finish().unwrap();
-        let mut tx = psbt.extract_tx();
-        let txid = tx.txid();
-        for txin in &mut tx.input {
-            txin.witness.push([0x00; 108].to_vec()); // fake signature
-            wallet
-                .database
-                .borrow_mut()
-                .del_utxo(&txin.previous_output)
-                .unwrap();
-        }
-        original_details.transaction = Some(tx);
-        wallet
-            .database
-            .borrow_mut()
-            .set_tx(&original_details)
-            .unwrap();
+            .fee_rate(FeeRate::from_sat_per_vb(453.0));
+        builder.finish().unwrap();
+    }
 
-        let mut builder = wallet.build_fee_bump(txid).unwrap();
+    #[test]
+    fn test_create_tx_drain_to_multiple() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr1 = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let addr2 = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt").unwrap();
+        let mut builder = wallet.build_tx();
         builder
-            .fee_rate(FeeRate::from_sat_per_vb(2.5))
-            .allow_shrinking(addr.script_pubkey())
-            .unwrap();
+            .drain_to_multiple(vec![
+                (addr1.script_pubkey(), 70),
+                (addr2.script_pubkey(), 30),
+            ])
+            .drain_wallet();
         let (psbt, details) = builder.finish().unwrap();
 
-        assert_eq!(details.sent, original_details.sent);
-        assert!(details.fee.unwrap_or(0) > original_details.fee.unwrap_or(0));
-
-        let tx = &psbt.global.unsigned_tx;
-        assert_eq!(tx.output.len(), 1);
-        assert_eq!(tx.output[0].value + details.fee.unwrap_or(0), details.sent);
-
-        assert_fee_rate!(psbt.extract_tx(), details.fee.unwrap_or(0), FeeRate::from_sat_per_vb(2.5), @add_signature);
+        let drain_val = 50_000 - details.fee.unwrap_or(0);
+        let outputs = &psbt.global.unsigned_tx.output;
+        assert_eq!(outputs.len(), 2);
+        let value_of = |addr: &Address| {
+            outputs
+                .iter()
+                .find(|o| o.script_pubkey == addr.script_pubkey())
+                .unwrap()
+                .value
+        };
+        assert_eq!(value_of(&addr2), drain_val * 30 / 100);
+        assert_eq!(value_of(&addr1), drain_val - drain_val * 30 / 100);
     }
 
     #[test]
-    fn test_bump_fee_absolute_reduce_single_recipient() {
+    fn test_create_tx_drain_to_multiple_invalid() {
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let addr1 = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let addr2 = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt").unwrap();
+
         let mut builder = wallet.build_tx();
         builder
-            .drain_to(addr.script_pubkey())
-            .drain_wallet()
-            .enable_rbf();
-        let (psbt, mut original_details) = builder.finish().unwrap();
+            .drain_to_multiple(vec![(addr1.script_pubkey(), 1), (addr2.script_pubkey(), 0)])
+            .drain_wallet();
+        assert!(matches!(builder.finish(), Err(Error::Generic(_))));
+
+        let mut builder = wallet.build_tx();
+        builder
+            .drain_to_multiple(vec![(addr1.script_pubkey(), 1)])
+            .drain_to(addr2.script_pubkey())
+            .drain_wallet();
+        assert!(matches!(builder.finish(), Err(Error::Generic(_))));
+
+        // the 1% share would be below the dust limit
+        let mut builder = wallet.build_tx();
+        builder
+            .drain_to_multiple(vec![
+                (addr1.script_pubkey(), 999),
+                (addr2.script_pubkey(), 1),
+            ])
+            .drain_wallet();
+        assert!(matches!(
+            builder.finish(),
+            Err(Error::InsufficientFunds { .. })
+        ));
+    }
+
+    #[test]
+    fn test_create_tx_ordering_respected() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 30_000)
+            .add_recipient(addr.script_pubkey(), 10_000)
+            .ordering(super::tx_builder::TxOrdering::Bip69Lexicographic);
+        let (psbt, details) = builder.finish().unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 3);
+        assert_eq!(
+            psbt.global.unsigned_tx.output[0].value,
+            10_000 - details.fee.unwrap_or(0)
+        );
+        assert_eq!(psbt.global.unsigned_tx.output[1].value, 10_000);
+        assert_eq!(psbt.global.unsigned_tx.output[2].value, 30_000);
+    }
+
+    #[test]
+    fn test_create_tx_default_sighash() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.add_recipient(addr.script_pubkey(), 30_000);
+        let (psbt, _) = builder.finish().unwrap();
+
+        assert_eq!(psbt.inputs[0].sighash_type, None);
+    }
+
+    #[test]
+    fn test_create_tx_custom_sighash() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 30_000)
+            .sighash(bitcoin::SigHashType::Single);
+        let (psbt, _) = builder.finish().unwrap();
+
+        assert_eq!(
+            psbt.inputs[0].sighash_type,
+            Some(bitcoin::SigHashType::Single)
+        );
+    }
+
+    #[test]
+    fn test_create_tx_input_hd_keypaths() {
+        use bitcoin::util::bip32::{DerivationPath, Fingerprint};
+        use std::str::FromStr;
+
+        let (wallet, _, _) = get_funded_wallet("wpkh([d34db33f/44'/0'/0']tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)");
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        let (psbt, _) = builder.finish().unwrap();
+
+        assert_eq!(psbt.inputs[0].bip32_derivation.len(), 1);
+        assert_eq!(
+            psbt.inputs[0].bip32_derivation.values().next().unwrap(),
+            &(
+                Fingerprint::from_str("d34db33f").unwrap(),
+                DerivationPath::from_str("m/44'/0'/0'/0/0").unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn test_create_tx_output_hd_keypaths() {
+        use bitcoin::util::bip32::{DerivationPath, Fingerprint};
+        use std::str::FromStr;
+
+        let (wallet, descriptors, _) = get_funded_wallet("wpkh([d34db33f/44'/0'/0']tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)");
+        // cache some addresses
+        wallet.get_address(New).unwrap();
+
+        let addr = testutils!(@external descriptors, 5);
+        let mut builder = wallet.build_tx();
+        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        let (psbt, _) = builder.finish().unwrap();
+
+        assert_eq!(psbt.outputs[0].bip32_derivation.len(), 1);
+        assert_eq!(
+            psbt.outputs[0].bip32_derivation.values().next().unwrap(),
+            &(
+                Fingerprint::from_str("d34db33f").unwrap(),
+                DerivationPath::from_str("m/44'/0'/0'/0/5").unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn test_create_tx_set_redeem_script_p2sh() {
+        use bitcoin::hashes::hex::FromHex;
+
+        let (wallet, _, _) =
+            get_funded_wallet("sh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        let (psbt, _) = builder.finish().unwrap();
+
+        assert_eq!(
+            psbt.inputs[0].redeem_script,
+            Some(Script::from(
+                Vec::<u8>::from_hex(
+                    "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac"
+                )
+                .unwrap()
+            ))
+        );
+        assert_eq!(psbt.inputs[0].witness_script, None);
+    }
+
+    #[test]
+    fn test_create_tx_set_witness_script_p2wsh() {
+        use bitcoin::hashes::hex::FromHex;
+
+        let (wallet, _, _) =
+            get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        let (psbt, _) = builder.finish().unwrap();
+
+        assert_eq!(psbt.inputs[0].redeem_script, None);
+        assert_eq!(
+            psbt.inputs[0].witness_script,
+            Some(Script::from(
+                Vec::<u8>::from_hex(
+                    "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac"
+                )
+                .unwrap()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_create_tx_set_redeem_witness_script_p2wsh_p2sh() {
+        use bitcoin::hashes::hex::FromHex;
+
+        let (wallet, _, _) =
+            get_funded_wallet("sh(wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)))");
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        let (psbt, _) = builder.finish().unwrap();
+
+        let script = Script::from(
+            Vec::<u8>::from_hex(
+                "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac",
+            )
+            .unwrap(),
+        );
+
+        assert_eq!(psbt.inputs[0].redeem_script, Some(script.to_v0_p2wsh()));
+        assert_eq!(psbt.inputs[0].witness_script, Some(script));
+    }
+
+    #[test]
+    fn test_create_tx_non_witness_utxo() {
+        let (wallet, _, _) =
+            get_funded_wallet("sh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        let (psbt, _) = builder.finish().unwrap();
+
+        assert!(psbt.inputs[0].non_witness_utxo.is_some());
+        assert!(psbt.inputs[0].witness_utxo.is_none());
+    }
+
+    #[test]
+    fn test_create_tx_only_witness_utxo() {
+        let (wallet, _, _) =
+            get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .drain_to(addr.script_pubkey())
+            .only_witness_utxo()
+            .drain_wallet();
+        let (psbt, _) = builder.finish().unwrap();
+
+        assert!(psbt.inputs[0].non_witness_utxo.is_none());
+        assert!(psbt.inputs[0].witness_utxo.is_some());
+    }
+
+    #[test]
+    fn test_create_tx_require_non_witness_utxo() {
+        let (wallet, _, txid) =
+            get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .drain_to(addr.script_pubkey())
+            .only_witness_utxo()
+            .require_non_witness_utxo()
+            .drain_wallet();
+        let (psbt, _) = builder.finish().unwrap();
+        assert!(psbt.inputs[0].non_witness_utxo.is_some());
+
+        wallet.database.write().unwrap().del_raw_tx(&txid).unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .drain_to(addr.script_pubkey())
+            .require_non_witness_utxo()
+            .drain_wallet();
+        assert!(matches!(
+            builder.finish(),
+            Err(Error::MissingNonWitnessUtxo(outpoint)) if outpoint.txid == txid
+        ));
+    }
+
+    #[test]
+    fn test_create_tx_shwpkh_has_witness_utxo() {
+        let (wallet, _, _) =
+            get_funded_wallet("sh(wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        let (psbt, _) = builder.finish().unwrap();
+
+        assert!(psbt.inputs[0].witness_utxo.is_some());
+    }
+
+    #[test]
+    fn test_create_tx_both_non_witness_utxo_and_witness_utxo_default() {
+        let (wallet, _, _) =
+            get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        let (psbt, _) = builder.finish().unwrap();
+
+        assert!(psbt.inputs[0].non_witness_utxo.is_some());
+        assert!(psbt.inputs[0].witness_utxo.is_some());
+    }
+
+    #[test]
+    fn test_create_tx_add_utxo() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        let small_output_txid = crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 30_000)
+            .add_utxo(OutPoint {
+                txid: small_output_txid,
+                vout: 0,
+            })
+            .unwrap();
+        let (psbt, details) = builder.finish().unwrap();
+
+        assert_eq!(
+            psbt.global.unsigned_tx.input.len(),
+            2,
+            "should add an additional input since 25_000 < 30_000"
+        );
+        assert_eq!(details.sent, 75_000, "total should be sum of both inputs");
+    }
+
+    #[test]
+    #[should_panic(expected = "InsufficientFunds")]
+    fn test_create_tx_manually_selected_insufficient() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        let small_output_txid = crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 30_000)
+            .add_utxo(OutPoint {
+                txid: small_output_txid,
+                vout: 0,
+            })
+            .unwrap()
+            .manually_selected_only();
+        builder.finish().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "SpendingPolicyRequired(External)")]
+    fn test_create_tx_policy_path_required() {
+        let (wallet, _, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let mut builder = wallet.build_tx();
+        builder.add_recipient(addr.script_pubkey(), 30_000);
+        builder.finish().unwrap();
+    }
+
+    #[test]
+    fn test_create_tx_policy_path_no_csv() {
+        let (wallet, _, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
+
+        let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
+        let root_id = external_policy.id;
+        // child #0 is just the key "A"
+        let path = vec![(root_id, vec![0])].into_iter().collect();
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 30_000)
+            .policy_path(path, KeychainKind::External);
+        let (psbt, _) = builder.finish().unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 0xFFFFFFFF);
+    }
+
+    #[test]
+    fn test_create_tx_policy_path_use_csv() {
+        let (wallet, _, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
+
+        let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
+        let root_id = external_policy.id;
+        // child #1 is or(pk(B),older(144))
+        let path = vec![(root_id, vec![1])].into_iter().collect();
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 30_000)
+            .policy_path(path, KeychainKind::External);
+        let (psbt, _) = builder.finish().unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 144);
+    }
+
+    #[test]
+    fn test_create_tx_global_xpubs_with_origin() {
+        use bitcoin::hashes::hex::FromHex;
+        use bitcoin::util::base58;
+        use bitcoin::util::psbt::raw::Key;
+
+        let (wallet, _, _) = get_funded_wallet("wpkh([73756c7f/48'/0'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*)");
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .add_global_xpubs();
+        let (psbt, _) = builder.finish().unwrap();
+
+        let type_value = 0x01;
+        let key = base58::from_check("tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3").unwrap();
+
+        let psbt_key = Key { type_value, key };
+
+        // This key has an explicit origin, so it will be encoded here
+        let value_bytes = Vec::<u8>::from_hex("73756c7f30000080000000800000008002000080").unwrap();
+
+        assert_eq!(psbt.global.unknown.len(), 1);
+        assert_eq!(psbt.global.unknown.get(&psbt_key), Some(&value_bytes));
+    }
+
+    #[test]
+    fn test_add_foreign_utxo() {
+        let (wallet1, _, _) = get_funded_wallet(get_test_wpkh());
+        let (wallet2, _, _) =
+            get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let utxo = wallet2.list_unspent().unwrap().remove(0);
+        let foreign_utxo_satisfaction = wallet2
+            .get_descriptor_for_keychain(KeychainKind::External)
+            .max_satisfaction_weight()
+            .unwrap();
+
+        let psbt_input = psbt::Input {
+            witness_utxo: Some(utxo.txout.clone()),
+            ..Default::default()
+        };
+
+        let mut builder = wallet1.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 60_000)
+            .only_witness_utxo()
+            .add_foreign_utxo(utxo.outpoint, psbt_input, foreign_utxo_satisfaction)
+            .unwrap();
+        let (mut psbt, details) = builder.finish().unwrap();
+
+        assert_eq!(
+            details.sent - details.received,
+            10_000 + details.fee.unwrap_or(0),
+            "we should have only net spent ~10_000"
+        );
+
+        assert!(
+            psbt.global
+                .unsigned_tx
+                .input
+                .iter()
+                .any(|input| input.previous_output == utxo.outpoint),
+            "foreign_utxo should be in there"
+        );
+
+        let finished = wallet1
+            .sign(
+                &mut psbt,
+                SignOptions {
+                    trust_witness_utxo: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        assert!(
+            !finished,
+            "only one of the inputs should have been signed so far"
+        );
+
+        let finished = wallet2
+            .sign(
+                &mut psbt,
+                SignOptions {
+                    trust_witness_utxo: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert!(finished, "all the inputs should have been signed now");
+    }
+
+    #[test]
+    fn test_add_foreign_utxo_with_descriptor() {
+        let (wallet1, _, _) = get_funded_wallet(get_test_wpkh());
+        let (wallet2, _, _) =
+            get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let utxo = wallet2.list_unspent().unwrap().remove(0);
+        let psbt_input = psbt::Input {
+            witness_utxo: Some(utxo.txout.clone()),
+            ..Default::default()
+        };
+
+        let mut builder = wallet1.build_tx();
+        let err = builder
+            .add_foreign_utxo_with_descriptor(
+                utxo.outpoint,
+                psbt_input.clone(),
+                wallet1.get_descriptor_for_keychain(KeychainKind::External),
+                0,
+            )
+            .unwrap_err();
+        assert!(matches!(err, Error::Generic(_)));
+
+        builder
+            .add_recipient(addr.script_pubkey(), 60_000)
+            .only_witness_utxo()
+            .add_foreign_utxo_with_descriptor(
+                utxo.outpoint,
+                psbt_input,
+                wallet2.get_descriptor_for_keychain(KeychainKind::External),
+                0,
+            )
+            .unwrap();
+        let (psbt, details) = builder.finish().unwrap();
+
+        assert_eq!(
+            details.sent - details.received,
+            10_000 + details.fee.unwrap_or(0),
+            "we should have only net spent ~10_000"
+        );
+        assert!(psbt
+            .global
+            .unsigned_tx
+            .input
+            .iter()
+            .any(|input| input.previous_output == utxo.outpoint));
+    }
+
+    #[test]
+    #[should_panic(expected = "Generic(\"Foreign utxo missing witness_utxo or non_witness_utxo\")")]
+    fn test_add_foreign_utxo_invalid_psbt_input() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let mut builder = wallet.build_tx();
+        let outpoint = wallet.list_unspent().unwrap()[0].outpoint;
+        let foreign_utxo_satisfaction = wallet
+            .get_descriptor_for_keychain(KeychainKind::External)
+            .max_satisfaction_weight()
+            .unwrap();
+        builder
+            .add_foreign_utxo(outpoint, psbt::Input::default(), foreign_utxo_satisfaction)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_add_foreign_utxo_where_outpoint_doesnt_match_psbt_input() {
+        let (wallet1, _, txid1) = get_funded_wallet(get_test_wpkh());
+        let (wallet2, _, txid2) =
+            get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+
+        let utxo2 = wallet2.list_unspent().unwrap().remove(0);
+        let tx1 = wallet1
+            .database
+            .read()
+            .unwrap()
+            .get_tx(&txid1, true)
+            .unwrap()
+            .unwrap()
+            .transaction
+            .unwrap();
+        let tx2 = wallet2
+            .database
+            .read()
+            .unwrap()
+            .get_tx(&txid2, true)
+            .unwrap()
+            .unwrap()
+            .transaction
+            .unwrap();
+
+        let satisfaction_weight = wallet2
+            .get_descriptor_for_keychain(KeychainKind::External)
+            .max_satisfaction_weight()
+            .unwrap();
+
+        let mut builder = wallet1.build_tx();
+        assert!(
+            builder
+                .add_foreign_utxo(
+                    utxo2.outpoint,
+                    psbt::Input {
+                        non_witness_utxo: Some(tx1),
+                        ..Default::default()
+                    },
+                    satisfaction_weight
+                )
+                .is_err(),
+            "should fail when outpoint doesn't match psbt_input"
+        );
+        assert!(
+            builder
+                .add_foreign_utxo(
+                    utxo2.outpoint,
+                    psbt::Input {
+                        non_witness_utxo: Some(tx2),
+                        ..Default::default()
+                    },
+                    satisfaction_weight
+                )
+                .is_ok(),
+            "shoulld be ok when outpoint does match psbt_input"
+        );
+    }
+
+    #[test]
+    fn test_add_foreign_utxo_only_witness_utxo() {
+        let (wallet1, _, _) = get_funded_wallet(get_test_wpkh());
+        let (wallet2, _, txid2) =
+            get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let utxo2 = wallet2.list_unspent().unwrap().remove(0);
+
+        let satisfaction_weight = wallet2
+            .get_descriptor_for_keychain(KeychainKind::External)
+            .max_satisfaction_weight()
+            .unwrap();
+
+        let mut builder = wallet1.build_tx();
+        builder.add_recipient(addr.script_pubkey(), 60_000);
+
+        {
+            let mut builder = builder.clone();
+            let psbt_input = psbt::Input {
+                witness_utxo: Some(utxo2.txout.clone()),
+                ..Default::default()
+            };
+            builder
+                .add_foreign_utxo(utxo2.outpoint, psbt_input, satisfaction_weight)
+                .unwrap();
+            assert!(
+                builder.finish().is_err(),
+                "psbt_input with witness_utxo should fail with only witness_utxo"
+            );
+        }
+
+        {
+            let mut builder = builder.clone();
+            let psbt_input = psbt::Input {
+                witness_utxo: Some(utxo2.txout.clone()),
+                ..Default::default()
+            };
+            builder
+                .only_witness_utxo()
+                .add_foreign_utxo(utxo2.outpoint, psbt_input, satisfaction_weight)
+                .unwrap();
+            assert!(
+                builder.finish().is_ok(),
+                "psbt_input with just witness_utxo should succeed when `only_witness_utxo` is enabled"
+            );
+        }
+
+        {
+            let mut builder = builder.clone();
+            let tx2 = wallet2
+                .database
+                .read()
+                .unwrap()
+                .get_tx(&txid2, true)
+                .unwrap()
+                .unwrap()
+                .transaction
+                .unwrap();
+            let psbt_input = psbt::Input {
+                non_witness_utxo: Some(tx2),
+                ..Default::default()
+            };
+            builder
+                .add_foreign_utxo(utxo2.outpoint, psbt_input, satisfaction_weight)
+                .unwrap();
+            assert!(
+                builder.finish().is_ok(),
+                "psbt_input with non_witness_utxo should succeed by default"
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_psbt_input() {
+        // this should grab a known good utxo and set the input
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        for utxo in wallet.list_unspent().unwrap() {
+            let psbt_input = wallet.get_psbt_input(utxo, None, false).unwrap();
+            assert!(psbt_input.witness_utxo.is_some() || psbt_input.non_witness_utxo.is_some());
+        }
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "MissingKeyOrigin(\"tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3\")"
+    )]
+    fn test_create_tx_global_xpubs_origin_missing() {
+        let (wallet, _, _) = get_funded_wallet("wpkh(tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*)");
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .add_global_xpubs();
+        builder.finish().unwrap();
+    }
+
+    #[test]
+    fn test_create_tx_global_xpubs_master_without_origin() {
+        use bitcoin::hashes::hex::FromHex;
+        use bitcoin::util::base58;
+        use bitcoin::util::psbt::raw::Key;
+
+        let (wallet, _, _) = get_funded_wallet("wpkh(tpubD6NzVbkrYhZ4Y55A58Gv9RSNF5hy84b5AJqYy7sCcjFrkcLpPre8kmgfit6kY1Zs3BLgeypTDBZJM222guPpdz7Cup5yzaMu62u7mYGbwFL/0/*)");
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .add_global_xpubs();
+        let (psbt, _) = builder.finish().unwrap();
+
+        let type_value = 0x01;
+        let key = base58::from_check("tpubD6NzVbkrYhZ4Y55A58Gv9RSNF5hy84b5AJqYy7sCcjFrkcLpPre8kmgfit6kY1Zs3BLgeypTDBZJM222guPpdz7Cup5yzaMu62u7mYGbwFL").unwrap();
+
+        let psbt_key = Key { type_value, key };
+
+        // This key doesn't have an explicit origin, but it's a master key (depth = 0). So we encode
+        // its fingerprint directly and an empty path
+        let value_bytes = Vec::<u8>::from_hex("997a323b").unwrap();
+
+        assert_eq!(psbt.global.unknown.len(), 1);
+        assert_eq!(psbt.global.unknown.get(&psbt_key), Some(&value_bytes));
+    }
+
+    #[test]
+    #[should_panic(expected = "IrreplaceableTransaction")]
+    fn test_bump_fee_irreplaceable_tx() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.add_recipient(addr.script_pubkey(), 25_000);
+        let (psbt, mut details) = builder.finish().unwrap();
+
+        let tx = psbt.extract_tx();
+        let txid = tx.txid();
+        // skip saving the utxos, we know they can't be used anyways
+        details.transaction = Some(tx);
+        wallet.database.write().unwrap().set_tx(&details).unwrap();
+
+        wallet.build_fee_bump(txid).unwrap().finish().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "TransactionConfirmed")]
+    fn test_bump_fee_confirmed_tx() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.add_recipient(addr.script_pubkey(), 25_000);
+        let (psbt, mut details) = builder.finish().unwrap();
+
+        let tx = psbt.extract_tx();
+        let txid = tx.txid();
+        // skip saving the utxos, we know they can't be used anyways
+        details.transaction = Some(tx);
+        details.confirmation_time = Some(ConfirmationTime {
+            timestamp: 12345678,
+            height: 42,
+        });
+        wallet.database.write().unwrap().set_tx(&details).unwrap();
+
+        wallet.build_fee_bump(txid).unwrap().finish().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "TransactionEvicted")]
+    fn test_bump_fee_evicted_tx() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .enable_rbf();
+        let (psbt, details) = builder.finish().unwrap();
+
+        let tx = psbt.extract_tx();
+        let txid = tx.txid();
+        store_tx(&wallet, tx, details);
+        assert_eq!(wallet.mark_evicted(&txid, 1_000).unwrap(), vec![txid]);
+
+        wallet.build_fee_bump(txid).unwrap().finish().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "FeeRateTooLow")]
+    fn test_bump_fee_low_fee_rate() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .enable_rbf();
+        let (psbt, mut details) = builder.finish().unwrap();
+
+        let tx = psbt.extract_tx();
+        let txid = tx.txid();
+        // skip saving the utxos, we know they can't be used anyways
+        details.transaction = Some(tx);
+        wallet.database.write().unwrap().set_tx(&details).unwrap();
+
+        let mut builder = wallet.build_fee_bump(txid).unwrap();
+        builder.fee_rate(FeeRate::from_sat_per_vb(1.0));
+        builder.finish().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "FeeTooLow")]
+    fn test_bump_fee_low_abs() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .enable_rbf();
+        let (psbt, mut details) = builder.finish().unwrap();
+
+        let tx = psbt.extract_tx();
+        let txid = tx.txid();
+        // skip saving the utxos, we know they can't be used anyways
+        details.transaction = Some(tx);
+        wallet.database.write().unwrap().set_tx(&details).unwrap();
+
+        let mut builder = wallet.build_fee_bump(txid).unwrap();
+        builder.fee_absolute(10);
+        builder.finish().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "FeeTooLow")]
+    fn test_bump_fee_zero_abs() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .enable_rbf();
+        let (psbt, mut details) = builder.finish().unwrap();
+
+        let tx = psbt.extract_tx();
+        let txid = tx.txid();
+        // skip saving the utxos, we know they can't be used anyways
+        details.transaction = Some(tx);
+        wallet.database.write().unwrap().set_tx(&details).unwrap();
+
+        let mut builder = wallet.build_fee_bump(txid).unwrap();
+        builder.fee_absolute(0);
+        builder.finish().unwrap();
+    }
+
+    #[test]
+    fn test_bump_fee_from_tx() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let foreign_addr = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt").unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .enable_rbf();
+        let (psbt, original_details) = builder.finish().unwrap();
+        let mut tx = psbt.extract_tx();
+        for txin in &mut tx.input {
+            txin.witness.push([0x00; 108].to_vec()); // fake signature
+        }
+
+        // another party added an input and an output of the same value, and the transaction was
+        // never stored in the wallet's database
+        let foreign_prev_tx = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: foreign_addr.script_pubkey(),
+            }],
+        };
+        let foreign_outpoint = OutPoint {
+            txid: foreign_prev_tx.txid(),
+            vout: 0,
+        };
+        tx.input.push(bitcoin::TxIn {
+            previous_output: foreign_outpoint,
+            script_sig: Script::default(),
+            sequence: 0xFFFFFFFD,
+            witness: vec![[0x00; 108].to_vec()],
+        });
+        tx.output.push(TxOut {
+            value: 10_000,
+            script_pubkey: foreign_addr.script_pubkey(),
+        });
+
+        assert!(matches!(
+            wallet.build_fee_bump_from_tx(tx.clone(), &[]),
+            Err(Error::UnknownUtxo)
+        ));
+
+        let mut builder = wallet
+            .build_fee_bump_from_tx(tx, &[foreign_prev_tx])
+            .unwrap();
+        builder.fee_rate(FeeRate::from_sat_per_vb(10.0));
+        let (psbt, details) = builder.finish().unwrap();
+
+        assert_eq!(details.sent, original_details.sent);
+        assert!(details.fee.unwrap_or(0) > original_details.fee.unwrap_or(0));
+
+        let unsigned_tx = &psbt.global.unsigned_tx;
+        let foreign_index = unsigned_tx
+            .input
+            .iter()
+            .position(|txin| txin.previous_output == foreign_outpoint)
+            .unwrap();
+        assert!(psbt.inputs[foreign_index].non_witness_utxo.is_some());
+        assert!(unsigned_tx
+            .output
+            .iter()
+            .any(|o| o.script_pubkey == addr.script_pubkey() && o.value == 25_000));
+        assert!(unsigned_tx
+            .output
+            .iter()
+            .any(|o| o.script_pubkey == foreign_addr.script_pubkey() && o.value == 10_000));
+    }
+
+    #[test]
+    fn test_bump_fee_reduce_change() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .enable_rbf();
+        let (psbt, mut original_details) = builder.finish().unwrap();
+        let mut tx = psbt.extract_tx();
+        let txid = tx.txid();
+        // skip saving the new utxos, we know they can't be used anyways
+        for txin in &mut tx.input {
+            txin.witness.push([0x00; 108].to_vec()); // fake signature
+            wallet
+                .database
+                .write()
+                .unwrap()
+                .del_utxo(&txin.previous_output)
+                .unwrap();
+        }
+        original_details.transaction = Some(tx);
+        wallet
+            .database
+            .write()
+            .unwrap()
+            .set_tx(&original_details)
+            .unwrap();
+
+        let mut builder = wallet.build_fee_bump(txid).unwrap();
+        builder.fee_rate(FeeRate::from_sat_per_vb(2.5)).enable_rbf();
+        let (psbt, details) = builder.finish().unwrap();
+
+        assert_eq!(details.sent, original_details.sent);
+        assert_eq!(
+            details.received + details.fee.unwrap_or(0),
+            original_details.received + original_details.fee.unwrap_or(0)
+        );
+        assert!(details.fee.unwrap_or(0) > original_details.fee.unwrap_or(0));
+
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey == addr.script_pubkey())
+                .unwrap()
+                .value,
+            25_000
+        );
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey != addr.script_pubkey())
+                .unwrap()
+                .value,
+            details.received
+        );
+
+        assert_fee_rate!(psbt.extract_tx(), details.fee.unwrap_or(0), FeeRate::from_sat_per_vb(2.5), @add_signature);
+    }
+
+    #[test]
+    fn test_bump_fee_absolute_reduce_change() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .enable_rbf();
+        let (psbt, mut original_details) = builder.finish().unwrap();
+        let mut tx = psbt.extract_tx();
+        let txid = tx.txid();
+        // skip saving the new utxos, we know they can't be used anyways
+        for txin in &mut tx.input {
+            txin.witness.push([0x00; 108].to_vec()); // fake signature
+            wallet
+                .database
+                .write()
+                .unwrap()
+                .del_utxo(&txin.previous_output)
+                .unwrap();
+        }
+        original_details.transaction = Some(tx);
+        wallet
+            .database
+            .write()
+            .unwrap()
+            .set_tx(&original_details)
+            .unwrap();
+
+        let mut builder = wallet.build_fee_bump(txid).unwrap();
+        builder.fee_absolute(200);
+        builder.enable_rbf();
+        let (psbt, details) = builder.finish().unwrap();
+
+        assert_eq!(details.sent, original_details.sent);
+        assert_eq!(
+            details.received + details.fee.unwrap_or(0),
+            original_details.received + original_details.fee.unwrap_or(0)
+        );
+        assert!(
+            details.fee.unwrap_or(0) > original_details.fee.unwrap_or(0),
+            "{} > {}",
+            details.fee.unwrap_or(0),
+            original_details.fee.unwrap_or(0)
+        );
+
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey == addr.script_pubkey())
+                .unwrap()
+                .value,
+            25_000
+        );
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey != addr.script_pubkey())
+                .unwrap()
+                .value,
+            details.received
+        );
+
+        assert_eq!(details.fee.unwrap_or(0), 200);
+    }
+
+    #[test]
+    fn test_bump_fee_reduce_single_recipient() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .drain_to(addr.script_pubkey())
+            .drain_wallet()
+            .enable_rbf();
+        let (psbt, mut original_details) = builder.finish().unwrap();
+        let mut tx = psbt.extract_tx();
+        let txid = tx.txid();
+        for txin in &mut tx.input {
+            txin.witness.push([0x00; 108].to_vec()); // fake signature
+            wallet
+                .database
+                .write()
+                .unwrap()
+                .del_utxo(&txin.previous_output)
+                .unwrap();
+        }
+        original_details.transaction = Some(tx);
+        wallet
+            .database
+            .write()
+            .unwrap()
+            .set_tx(&original_details)
+            .unwrap();
+
+        let mut builder = wallet.build_fee_bump(txid).unwrap();
+        builder
+            .fee_rate(FeeRate::from_sat_per_vb(2.5))
+            .allow_shrinking(addr.script_pubkey())
+            .unwrap();
+        let (psbt, details) = builder.finish().unwrap();
+
+        assert_eq!(details.sent, original_details.sent);
+        assert!(details.fee.unwrap_or(0) > original_details.fee.unwrap_or(0));
+
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(tx.output[0].value + details.fee.unwrap_or(0), details.sent);
+
+        assert_fee_rate!(psbt.extract_tx(), details.fee.unwrap_or(0), FeeRate::from_sat_per_vb(2.5), @add_signature);
+    }
+
+    #[test]
+    fn test_bump_fee_absolute_reduce_single_recipient() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .drain_to(addr.script_pubkey())
+            .drain_wallet()
+            .enable_rbf();
+        let (psbt, mut original_details) = builder.finish().unwrap();
+        let mut tx = psbt.extract_tx();
+        let txid = tx.txid();
+        for txin in &mut tx.input {
+            txin.witness.push([0x00; 108].to_vec()); // fake signature
+            wallet
+                .database
+                .write()
+                .unwrap()
+                .del_utxo(&txin.previous_output)
+                .unwrap();
+        }
+        original_details.transaction = Some(tx);
+        wallet
+            .database
+            .write()
+            .unwrap()
+            .set_tx(&original_details)
+            .unwrap();
+
+        let mut builder = wallet.build_fee_bump(txid).unwrap();
+        builder
+            .allow_shrinking(addr.script_pubkey())
+            .unwrap()
+            .fee_absolute(300);
+        let (psbt, details) = builder.finish().unwrap();
+
+        assert_eq!(details.sent, original_details.sent);
+        assert!(details.fee.unwrap_or(0) > original_details.fee.unwrap_or(0));
+
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(tx.output[0].value + details.fee.unwrap_or(0), details.sent);
+
+        assert_eq!(details.fee.unwrap_or(0), 300);
+    }
+
+    #[test]
+    fn test_bump_fee_drain_wallet() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        // receive an extra tx so that our wallet has two utxos.
+        let incoming_txid = crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+        let outpoint = OutPoint {
+            txid: incoming_txid,
+            vout: 0,
+        };
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .drain_to(addr.script_pubkey())
+            .add_utxo(outpoint)
+            .unwrap()
+            .manually_selected_only()
+            .enable_rbf();
+        let (psbt, mut original_details) = builder.finish().unwrap();
+        let mut tx = psbt.extract_tx();
+        let txid = tx.txid();
+        for txin in &mut tx.input {
+            txin.witness.push([0x00; 108].to_vec()); // fake signature
+            wallet
+                .database
+                .write()
+                .unwrap()
+                .del_utxo(&txin.previous_output)
+                .unwrap();
+        }
+        original_details.transaction = Some(tx);
+        wallet
+            .database
+            .write()
+            .unwrap()
+            .set_tx(&original_details)
+            .unwrap();
+        assert_eq!(original_details.sent, 25_000);
+
+        // for the new feerate, it should be enough to reduce the output, but since we specify
+        // `drain_wallet` we expect to spend everything
+        let mut builder = wallet.build_fee_bump(txid).unwrap();
+        builder
+            .drain_wallet()
+            .allow_shrinking(addr.script_pubkey())
+            .unwrap()
+            .fee_rate(FeeRate::from_sat_per_vb(5.0));
+        let (_, details) = builder.finish().unwrap();
+        assert_eq!(details.sent, 75_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "InsufficientFunds")]
+    fn test_bump_fee_remove_output_manually_selected_only() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        // receive an extra tx so that our wallet has two utxos. then we manually pick only one of
+        // them, and make sure that `bump_fee` doesn't try to add more. This fails because we've
+        // told the wallet it's not allowed to add more inputs AND it can't reduce the value of the
+        // existing output. In other words, bump_fee + manually_selected_only is always an error
+        // unless you've also set "allow_shrinking" OR there is a change output.
+        let incoming_txid = crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+        let outpoint = OutPoint {
+            txid: incoming_txid,
+            vout: 0,
+        };
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .drain_to(addr.script_pubkey())
+            .add_utxo(outpoint)
+            .unwrap()
+            .manually_selected_only()
+            .enable_rbf();
+        let (psbt, mut original_details) = builder.finish().unwrap();
+        let mut tx = psbt.extract_tx();
+        let txid = tx.txid();
+        for txin in &mut tx.input {
+            txin.witness.push([0x00; 108].to_vec()); // fake signature
+            wallet
+                .database
+                .write()
+                .unwrap()
+                .del_utxo(&txin.previous_output)
+                .unwrap();
+        }
+        original_details.transaction = Some(tx);
+        wallet
+            .database
+            .write()
+            .unwrap()
+            .set_tx(&original_details)
+            .unwrap();
+        assert_eq!(original_details.sent, 25_000);
+
+        let mut builder = wallet.build_fee_bump(txid).unwrap();
+        builder
+            .manually_selected_only()
+            .fee_rate(FeeRate::from_sat_per_vb(255.0));
+        builder.finish().unwrap();
+    }
+
+    #[test]
+    fn test_bump_fee_add_input() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 45_000)
+            .enable_rbf();
+        let (psbt, mut original_details) = builder.finish().unwrap();
+        let mut tx = psbt.extract_tx();
+        let txid = tx.txid();
+        // skip saving the new utxos, we know they can't be used anyways
+        for txin in &mut tx.input {
+            txin.witness.push([0x00; 108].to_vec()); // fake signature
+            wallet
+                .database
+                .write()
+                .unwrap()
+                .del_utxo(&txin.previous_output)
+                .unwrap();
+        }
+        original_details.transaction = Some(tx);
+        wallet
+            .database
+            .write()
+            .unwrap()
+            .set_tx(&original_details)
+            .unwrap();
+
+        let mut builder = wallet.build_fee_bump(txid).unwrap();
+        builder.fee_rate(FeeRate::from_sat_per_vb(50.0));
+        let (psbt, details) = builder.finish().unwrap();
+
+        assert_eq!(details.sent, original_details.sent + 25_000);
+        assert_eq!(details.fee.unwrap_or(0) + details.received, 30_000);
+
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey == addr.script_pubkey())
+                .unwrap()
+                .value,
+            45_000
+        );
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey != addr.script_pubkey())
+                .unwrap()
+                .value,
+            details.received
+        );
+
+        assert_fee_rate!(psbt.extract_tx(), details.fee.unwrap_or(0), FeeRate::from_sat_per_vb(50.0), @add_signature);
+    }
+
+    #[test]
+    fn test_bump_fee_absolute_add_input() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 45_000)
+            .enable_rbf();
+        let (psbt, mut original_details) = builder.finish().unwrap();
+        let mut tx = psbt.extract_tx();
+        let txid = tx.txid();
+        // skip saving the new utxos, we know they can't be used anyways
+        for txin in &mut tx.input {
+            txin.witness.push([0x00; 108].to_vec()); // fake signature
+            wallet
+                .database
+                .write()
+                .unwrap()
+                .del_utxo(&txin.previous_output)
+                .unwrap();
+        }
+        original_details.transaction = Some(tx);
+        wallet
+            .database
+            .write()
+            .unwrap()
+            .set_tx(&original_details)
+            .unwrap();
+
+        let mut builder = wallet.build_fee_bump(txid).unwrap();
+        builder.fee_absolute(6_000);
+        let (psbt, details) = builder.finish().unwrap();
+
+        assert_eq!(details.sent, original_details.sent + 25_000);
+        assert_eq!(details.fee.unwrap_or(0) + details.received, 30_000);
+
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey == addr.script_pubkey())
+                .unwrap()
+                .value,
+            45_000
+        );
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey != addr.script_pubkey())
+                .unwrap()
+                .value,
+            details.received
+        );
+
+        assert_eq!(details.fee.unwrap_or(0), 6_000);
+    }
+
+    #[test]
+    fn test_bump_fee_no_change_add_input_and_change() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        let incoming_txid = crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+
+        // initially make a tx without change by using `drain_to`
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .drain_to(addr.script_pubkey())
+            .add_utxo(OutPoint {
+                txid: incoming_txid,
+                vout: 0,
+            })
+            .unwrap()
+            .manually_selected_only()
+            .enable_rbf();
+        let (psbt, mut original_details) = builder.finish().unwrap();
+
+        let mut tx = psbt.extract_tx();
+        let txid = tx.txid();
+        // skip saving the new utxos, we know they can't be used anyways
+        for txin in &mut tx.input {
+            txin.witness.push([0x00; 108].to_vec()); // fake signature
+            wallet
+                .database
+                .write()
+                .unwrap()
+                .del_utxo(&txin.previous_output)
+                .unwrap();
+        }
+        original_details.transaction = Some(tx);
+        wallet
+            .database
+            .write()
+            .unwrap()
+            .set_tx(&original_details)
+            .unwrap();
+
+        // now bump the fees without using `allow_shrinking`. the wallet should add an
+        // extra input and a change output, and leave the original output untouched
+        let mut builder = wallet.build_fee_bump(txid).unwrap();
+        builder.fee_rate(FeeRate::from_sat_per_vb(50.0));
+        let (psbt, details) = builder.finish().unwrap();
+
+        let original_send_all_amount = original_details.sent - original_details.fee.unwrap_or(0);
+        assert_eq!(details.sent, original_details.sent + 50_000);
+        assert_eq!(
+            details.received,
+            75_000 - original_send_all_amount - details.fee.unwrap_or(0)
+        );
+
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey == addr.script_pubkey())
+                .unwrap()
+                .value,
+            original_send_all_amount
+        );
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey != addr.script_pubkey())
+                .unwrap()
+                .value,
+            75_000 - original_send_all_amount - details.fee.unwrap_or(0)
+        );
+
+        assert_fee_rate!(psbt.extract_tx(), details.fee.unwrap_or(0), FeeRate::from_sat_per_vb(50.0), @add_signature);
+    }
+
+    #[test]
+    fn test_bump_fee_add_input_change_dust() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 45_000)
+            .enable_rbf();
+        let (psbt, mut original_details) = builder.finish().unwrap();
+        let mut tx = psbt.extract_tx();
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.output.len(), 2);
+        let txid = tx.txid();
+        // skip saving the new utxos, we know they can't be used anyways
+        for txin in &mut tx.input {
+            txin.witness.push([0x00; 108].to_vec()); // fake signature
+            wallet
+                .database
+                .write()
+                .unwrap()
+                .del_utxo(&txin.previous_output)
+                .unwrap();
+        }
+        original_details.transaction = Some(tx);
+        wallet
+            .database
+            .write()
+            .unwrap()
+            .set_tx(&original_details)
+            .unwrap();
+
+        let mut builder = wallet.build_fee_bump(txid).unwrap();
+        builder.fee_rate(FeeRate::from_sat_per_vb(140.0));
+        let (psbt, details) = builder.finish().unwrap();
+
+        assert_eq!(
+            original_details.received,
+            5_000 - original_details.fee.unwrap_or(0)
+        );
+
+        assert_eq!(details.sent, original_details.sent + 25_000);
+        assert_eq!(details.fee.unwrap_or(0), 30_000);
+        assert_eq!(details.received, 0);
+
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey == addr.script_pubkey())
+                .unwrap()
+                .value,
+            45_000
+        );
+
+        assert_fee_rate!(psbt.extract_tx(), details.fee.unwrap_or(0), FeeRate::from_sat_per_vb(140.0), @dust_change, @add_signature);
+    }
+
+    #[test]
+    fn test_bump_fee_force_add_input() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        let incoming_txid = crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 45_000)
+            .enable_rbf();
+        let (psbt, mut original_details) = builder.finish().unwrap();
+        let mut tx = psbt.extract_tx();
+        let txid = tx.txid();
+        // skip saving the new utxos, we know they can't be used anyways
+        for txin in &mut tx.input {
+            txin.witness.push([0x00; 108].to_vec()); // fake signature
+            wallet
+                .database
+                .write()
+                .unwrap()
+                .del_utxo(&txin.previous_output)
+                .unwrap();
+        }
+        original_details.transaction = Some(tx);
+        wallet
+            .database
+            .write()
+            .unwrap()
+            .set_tx(&original_details)
+            .unwrap();
+
+        // the new fee_rate is low enough that just reducing the change would be fine, but we force
+        // the addition of an extra input with `add_utxo()`
+        let mut builder = wallet.build_fee_bump(txid).unwrap();
+        builder
+            .add_utxo(OutPoint {
+                txid: incoming_txid,
+                vout: 0,
+            })
+            .unwrap()
+            .fee_rate(FeeRate::from_sat_per_vb(5.0));
+        let (psbt, details) = builder.finish().unwrap();
+
+        assert_eq!(details.sent, original_details.sent + 25_000);
+        assert_eq!(details.fee.unwrap_or(0) + details.received, 30_000);
+
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey == addr.script_pubkey())
+                .unwrap()
+                .value,
+            45_000
+        );
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey != addr.script_pubkey())
+                .unwrap()
+                .value,
+            details.received
+        );
+
+        assert_fee_rate!(psbt.extract_tx(), details.fee.unwrap_or(0), FeeRate::from_sat_per_vb(5.0), @add_signature);
+    }
+
+    #[test]
+    fn test_bump_fee_absolute_force_add_input() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        let incoming_txid = crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 45_000)
+            .enable_rbf();
+        let (psbt, mut original_details) = builder.finish().unwrap();
         let mut tx = psbt.extract_tx();
         let txid = tx.txid();
+        // skip saving the new utxos, we know they can't be used anyways
         for txin in &mut tx.input {
             txin.witness.push([0x00; 108].to_vec()); // fake signature
             wallet
                 .database
-                .borrow_mut()
+                .write()
+                .unwrap()
                 .del_utxo(&txin.previous_output)
                 .unwrap();
         }
         original_details.transaction = Some(tx);
         wallet
             .database
-            .borrow_mut()
+            .write()
+            .unwrap()
             .set_tx(&original_details)
             .unwrap();
 
-        let mut builder = wallet.build_fee_bump(txid).unwrap();
-        builder
-            .allow_shrinking(addr.script_pubkey())
-            .unwrap()
-            .fee_absolute(300);
-        let (psbt, details) = builder.finish().unwrap();
+        // the new fee_rate is low enough that just reducing the change would be fine, but we force
+        // the addition of an extra input with `add_utxo()`
+        let mut builder = wallet.build_fee_bump(txid).unwrap();
+        builder
+            .add_utxo(OutPoint {
+                txid: incoming_txid,
+                vout: 0,
+            })
+            .unwrap()
+            .fee_absolute(250);
+        let (psbt, details) = builder.finish().unwrap();
+
+        assert_eq!(details.sent, original_details.sent + 25_000);
+        assert_eq!(details.fee.unwrap_or(0) + details.received, 30_000);
+
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey == addr.script_pubkey())
+                .unwrap()
+                .value,
+            45_000
+        );
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey != addr.script_pubkey())
+                .unwrap()
+                .value,
+            details.received
+        );
+
+        assert_eq!(details.fee.unwrap_or(0), 250);
+    }
+
+    #[test]
+    fn test_sign_single_xprv() {
+        let (wallet, _, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        let (mut psbt, _) = builder.finish().unwrap();
+
+        let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
+        assert!(finalized);
+
+        let extracted = psbt.extract_tx();
+        assert_eq!(extracted.input[0].witness.len(), 2);
+    }
+
+    #[test]
+    fn test_sign_single_xprv_with_master_fingerprint_and_path() {
+        let (wallet, _, _) = get_funded_wallet("wpkh([d34db33f/84h/1h/0h]tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        let (mut psbt, _) = builder.finish().unwrap();
+
+        let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
+        assert!(finalized);
+
+        let extracted = psbt.extract_tx();
+        assert_eq!(extracted.input[0].witness.len(), 2);
+    }
+
+    #[test]
+    fn test_sign_single_xprv_bip44_path() {
+        let (wallet, _, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/44'/0'/0'/0/*)");
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        let (mut psbt, _) = builder.finish().unwrap();
+
+        let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
+        assert!(finalized);
+
+        let extracted = psbt.extract_tx();
+        assert_eq!(extracted.input[0].witness.len(), 2);
+    }
+
+    #[test]
+    fn test_sign_single_xprv_sh_wpkh() {
+        let (wallet, _, _) = get_funded_wallet("sh(wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*))");
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        let (mut psbt, _) = builder.finish().unwrap();
+
+        let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
+        assert!(finalized);
+
+        let extracted = psbt.extract_tx();
+        assert_eq!(extracted.input[0].witness.len(), 2);
+    }
+
+    #[test]
+    fn test_sign_single_wif() {
+        let (wallet, _, _) =
+            get_funded_wallet("wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)");
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        let (mut psbt, _) = builder.finish().unwrap();
+
+        let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
+        assert!(finalized);
+
+        let extracted = psbt.extract_tx();
+        assert_eq!(extracted.input[0].witness.len(), 2);
+    }
+
+    #[test]
+    fn test_sign_single_xprv_no_hd_keypaths() {
+        let (wallet, _, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        let (mut psbt, _) = builder.finish().unwrap();
+
+        psbt.inputs[0].bip32_derivation.clear();
+        assert_eq!(psbt.inputs[0].bip32_derivation.len(), 0);
+
+        let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
+        assert!(finalized);
 
-        assert_eq!(details.sent, original_details.sent);
-        assert!(details.fee.unwrap_or(0) > original_details.fee.unwrap_or(0));
+        let extracted = psbt.extract_tx();
+        assert_eq!(extracted.input[0].witness.len(), 2);
+    }
 
-        let tx = &psbt.global.unsigned_tx;
-        assert_eq!(tx.output.len(), 1);
-        assert_eq!(tx.output[0].value + details.fee.unwrap_or(0), details.sent);
+    #[test]
+    fn test_include_output_redeem_witness_script() {
+        let (wallet, _, _) = get_funded_wallet("sh(wsh(multi(1,cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW,cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu)))");
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 45_000)
+            .include_output_redeem_witness_script();
+        let (psbt, _) = builder.finish().unwrap();
 
-        assert_eq!(details.fee.unwrap_or(0), 300);
+        // p2sh-p2wsh transaction should contain both witness and redeem scripts
+        assert!(psbt
+            .outputs
+            .iter()
+            .any(|output| output.redeem_script.is_some() && output.witness_script.is_some()));
     }
 
     #[test]
-    fn test_bump_fee_drain_wallet() {
-        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
-        // receive an extra tx so that our wallet has two utxos.
-        let incoming_txid = crate::populate_test_db!(
-            wallet.database.borrow_mut(),
-            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
-            Some(100),
-        );
-        let outpoint = OutPoint {
-            txid: incoming_txid,
-            vout: 0,
-        };
+    fn test_signing_only_one_of_multiple_inputs() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
         let mut builder = wallet.build_tx();
         builder
-            .drain_to(addr.script_pubkey())
-            .add_utxo(outpoint)
-            .unwrap()
-            .manually_selected_only()
-            .enable_rbf();
-        let (psbt, mut original_details) = builder.finish().unwrap();
-        let mut tx = psbt.extract_tx();
-        let txid = tx.txid();
-        for txin in &mut tx.input {
-            txin.witness.push([0x00; 108].to_vec()); // fake signature
-            wallet
-                .database
-                .borrow_mut()
-                .del_utxo(&txin.previous_output)
-                .unwrap();
-        }
-        original_details.transaction = Some(tx);
-        wallet
-            .database
-            .borrow_mut()
-            .set_tx(&original_details)
+            .add_recipient(addr.script_pubkey(), 45_000)
+            .include_output_redeem_witness_script();
+        let (mut psbt, _) = builder.finish().unwrap();
+
+        // add another input to the psbt that is at least passable.
+        let dud_input = bitcoin::util::psbt::Input {
+            witness_utxo: Some(TxOut {
+                value: 100_000,
+                script_pubkey: miniscript::Descriptor::<bitcoin::PublicKey>::from_str(
+                    "wpkh(025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357)",
+                )
+                .unwrap()
+                .script_pubkey(),
+            }),
+            ..Default::default()
+        };
+
+        psbt.inputs.push(dud_input);
+        psbt.global.unsigned_tx.input.push(bitcoin::TxIn::default());
+        let is_final = wallet
+            .sign(
+                &mut psbt,
+                SignOptions {
+                    trust_witness_utxo: true,
+                    ..Default::default()
+                },
+            )
             .unwrap();
-        assert_eq!(original_details.sent, 25_000);
+        assert!(
+            !is_final,
+            "shouldn't be final since we can't sign one of the inputs"
+        );
+        assert!(
+            psbt.inputs[0].final_script_witness.is_some(),
+            "should finalized input it signed"
+        )
+    }
 
-        // for the new feerate, it should be enough to reduce the output, but since we specify
-        // `drain_wallet` we expect to spend everything
-        let mut builder = wallet.build_fee_bump(txid).unwrap();
+    #[test]
+    fn test_sign_nonstandard_sighash() {
+        let sighash = SigHashType::NonePlusAnyoneCanPay;
+
+        let (wallet, _, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+        let addr = wallet.get_address(New).unwrap();
+        let mut builder = wallet.build_tx();
         builder
-            .drain_wallet()
-            .allow_shrinking(addr.script_pubkey())
-            .unwrap()
-            .fee_rate(FeeRate::from_sat_per_vb(5.0));
-        let (_, details) = builder.finish().unwrap();
-        assert_eq!(details.sent, 75_000);
+            .drain_to(addr.script_pubkey())
+            .sighash(sighash)
+            .drain_wallet();
+        let (mut psbt, _) = builder.finish().unwrap();
+
+        let result = wallet.sign(&mut psbt, Default::default());
+        assert!(
+            result.is_err(),
+            "Signing should have failed because the TX uses non-standard sighashes"
+        );
+        assert!(
+            matches!(
+                result.unwrap_err(),
+                Error::Signer(SignerError::NonStandardSighash)
+            ),
+            "Signing failed with the wrong error type"
+        );
+
+        // try again after opting-in
+        let result = wallet.sign(
+            &mut psbt,
+            SignOptions {
+                allow_all_sighashes: true,
+                ..Default::default()
+            },
+        );
+        assert!(result.is_ok(), "Signing should have worked");
+        assert!(
+            result.unwrap(),
+            "Should finalize the input since we can produce signatures"
+        );
+
+        let extracted = psbt.extract_tx();
+        assert_eq!(
+            *extracted.input[0].witness[0].last().unwrap(),
+            sighash.as_u32() as u8,
+            "The signature should have been made with the right sighash"
+        );
     }
 
     #[test]
-    #[should_panic(expected = "InsufficientFunds")]
-    fn test_bump_fee_remove_output_manually_selected_only() {
-        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
-        // receive an extra tx so that our wallet has two utxos. then we manually pick only one of
-        // them, and make sure that `bump_fee` doesn't try to add more. This fails because we've
-        // told the wallet it's not allowed to add more inputs AND it can't reduce the value of the
-        // existing output. In other words, bump_fee + manually_selected_only is always an error
-        // unless you've also set "allow_shrinking" OR there is a change output.
-        let incoming_txid = crate::populate_test_db!(
-            wallet.database.borrow_mut(),
-            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
-            Some(100),
-        );
-        let outpoint = OutPoint {
-            txid: incoming_txid,
-            vout: 0,
-        };
+    fn test_sign_input_sighash() {
+        let sighash = SigHashType::AllPlusAnyoneCanPay;
+
+        let (wallet, _, txid) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
         let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let outpoint = OutPoint { txid, vout: 0 };
+
         let mut builder = wallet.build_tx();
         builder
-            .drain_to(addr.script_pubkey())
-            .add_utxo(outpoint)
-            .unwrap()
-            .manually_selected_only()
-            .enable_rbf();
-        let (psbt, mut original_details) = builder.finish().unwrap();
-        let mut tx = psbt.extract_tx();
-        let txid = tx.txid();
-        for txin in &mut tx.input {
-            txin.witness.push([0x00; 108].to_vec()); // fake signature
-            wallet
-                .database
-                .borrow_mut()
-                .del_utxo(&txin.previous_output)
-                .unwrap();
-        }
-        original_details.transaction = Some(tx);
-        wallet
-            .database
-            .borrow_mut()
-            .set_tx(&original_details)
-            .unwrap();
-        assert_eq!(original_details.sent, 25_000);
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .input_sighash(OutPoint { txid, vout: 1 }, sighash);
+        assert!(matches!(builder.finish(), Err(Error::Generic(_))));
 
-        let mut builder = wallet.build_fee_bump(txid).unwrap();
+        let mut builder = wallet.build_tx();
         builder
-            .manually_selected_only()
-            .fee_rate(FeeRate::from_sat_per_vb(255.0));
-        builder.finish().unwrap();
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .input_sighash(outpoint, sighash);
+        let (mut psbt, _) = builder.finish().unwrap();
+        assert_eq!(psbt.inputs[0].sighash_type, Some(sighash));
+
+        let result = wallet.sign(&mut psbt, Default::default());
+        assert!(matches!(
+            result,
+            Err(Error::Signer(SignerError::NonStandardSighash))
+        ));
+
+        let mut input_sighashes = BTreeMap::new();
+        input_sighashes.insert(0, sighash);
+        let finalized = wallet
+            .sign(
+                &mut psbt,
+                SignOptions {
+                    input_sighashes,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert!(finalized);
+
+        let extracted = psbt.extract_tx();
+        assert_eq!(
+            *extracted.input[0].witness[0].last().unwrap(),
+            sighash.as_u32() as u8,
+            "The signature should have been made with the right sighash"
+        );
+    }
+
+    #[test]
+    fn test_combine_psbts() {
+        let (wallet_a, _, _) = get_funded_wallet("wsh(multi(2,cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW,03024f83fc3c1abc4faf227df351b5bc319c26ab2e2ec90e549cdb446744c32a48))");
+        let wallet_b = Wallet::new_offline(
+            "wsh(multi(2,032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3,cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu))",
+            None,
+            Network::Regtest,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+
+        let mut builder = wallet_a.build_tx();
+        builder.add_recipient(addr.script_pubkey(), 25_000);
+        let (psbt, _) = builder.finish().unwrap();
+
+        let mut psbt_a = psbt.clone();
+        assert!(!wallet_a.sign(&mut psbt_a, Default::default()).unwrap());
+        let mut psbt_b = psbt;
+        assert!(!wallet_b.sign(&mut psbt_b, Default::default()).unwrap());
+        let key_b = *psbt_b.inputs[0].partial_sigs.keys().next().unwrap();
+
+        let combined = wallet_a
+            .combine_psbts(vec![psbt_a.clone(), psbt_b.clone(), psbt_b.clone()])
+            .unwrap();
+        assert_eq!(combined.added_signatures.len(), 1);
+        assert_eq!(
+            combined.added_signatures[0].iter().collect::<Vec<_>>(),
+            vec![&key_b]
+        );
+        assert_eq!(combined.psbt.inputs[0].partial_sigs.len(), 2);
+
+        let mut psbt = combined.psbt;
+        assert!(wallet_a
+            .finalize_psbt(&mut psbt, Default::default())
+            .unwrap());
+
+        // different transaction
+        let mut builder = wallet_a.build_tx();
+        builder.add_recipient(addr.script_pubkey(), 20_000);
+        let (other_psbt, _) = builder.finish().unwrap();
+        assert!(matches!(
+            wallet_a.combine_psbts(vec![psbt_a, other_psbt]),
+            Err(Error::IncompatiblePsbt(_))
+        ));
+
+        // conflicting signature for the same key
+        let mut conflicting = psbt_b.clone();
+        conflicting.inputs[0]
+            .partial_sigs
+            .get_mut(&key_b)
+            .unwrap()
+            .push(0x00);
+        assert!(matches!(
+            wallet_a.combine_psbts(vec![psbt_b, conflicting]),
+            Err(Error::IncompatiblePsbt(_))
+        ));
+
+        assert!(matches!(
+            wallet_a.combine_psbts(vec![]),
+            Err(Error::Generic(_))
+        ));
+    }
+
+    #[test]
+    fn test_finalize_psbt_with_report() {
+        let (wallet_a, _, _) = get_funded_wallet("wsh(multi(2,cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW,03024f83fc3c1abc4faf227df351b5bc319c26ab2e2ec90e549cdb446744c32a48))");
+        let wallet_b = Wallet::new_offline(
+            "wsh(multi(2,032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3,cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu))",
+            None,
+            Network::Regtest,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+
+        let mut builder = wallet_a.build_tx();
+        builder.add_recipient(addr.script_pubkey(), 25_000);
+        let (mut psbt, _) = builder.finish().unwrap();
+
+        let report = wallet_a
+            .finalize_psbt_with_report(&mut psbt, Default::default())
+            .unwrap();
+        assert!(!report.is_finalized());
+        assert!(matches!(
+            &report.inputs[0],
+            InputFinalization::MissingSignatures { keys } if keys.len() == 2
+        ));
+
+        assert!(!wallet_a.sign(&mut psbt, Default::default()).unwrap());
+        let report = wallet_a
+            .finalize_psbt_with_report(&mut psbt, Default::default())
+            .unwrap();
+        assert_eq!(report.missing_signatures(), vec![0]);
+        match &report.inputs[0] {
+            InputFinalization::MissingSignatures { keys } => assert_eq!(
+                keys,
+                &vec![(
+                    PublicKey::from_str(
+                        "03024f83fc3c1abc4faf227df351b5bc319c26ab2e2ec90e549cdb446744c32a48"
+                    )
+                    .unwrap(),
+                    None
+                )]
+            ),
+            other => panic!("unexpected outcome {:?}", other),
+        }
+
+        assert!(wallet_b.sign(&mut psbt, Default::default()).unwrap());
+        let report = wallet_a
+            .finalize_psbt_with_report(&mut psbt, Default::default())
+            .unwrap();
+        assert!(report.is_finalized());
+        assert!(matches!(
+            report.inputs[0],
+            InputFinalization::AlreadyFinalized
+        ));
     }
 
     #[test]
-    fn test_bump_fee_add_input() {
-        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
-        crate::populate_test_db!(
-            wallet.database.borrow_mut(),
-            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
-            Some(100),
-        );
+    fn test_sign_with_report() {
+        use bitcoin::util::bip32;
 
+        let (wallet, _, _) = get_funded_wallet("wsh(multi(2,tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*,03024f83fc3c1abc4faf227df351b5bc319c26ab2e2ec90e549cdb446744c32a48))");
         let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let xprv = bip32::ExtendedPrivKey::from_str("tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS").unwrap();
+
         let mut builder = wallet.build_tx();
-        builder
-            .add_recipient(addr.script_pubkey(), 45_000)
-            .enable_rbf();
-        let (psbt, mut original_details) = builder.finish().unwrap();
-        let mut tx = psbt.extract_tx();
-        let txid = tx.txid();
-        // skip saving the new utxos, we know they can't be used anyways
-        for txin in &mut tx.input {
-            txin.witness.push([0x00; 108].to_vec()); // fake signature
-            wallet
-                .database
-                .borrow_mut()
-                .del_utxo(&txin.previous_output)
-                .unwrap();
-        }
-        original_details.transaction = Some(tx);
-        wallet
-            .database
-            .borrow_mut()
-            .set_tx(&original_details)
+        builder.add_recipient(addr.script_pubkey(), 25_000);
+        let (mut psbt, _) = builder.finish().unwrap();
+
+        let report = wallet
+            .sign_with_report(&mut psbt, Default::default())
             .unwrap();
+        assert!(!report.is_finalized());
+        assert_eq!(report.signed_inputs(), vec![0]);
+        assert_eq!(report.added_signatures[0].len(), 1);
+        let added = &report.added_signatures[0][0];
+        assert!(psbt.inputs[0].partial_sigs.contains_key(&added.public_key));
+        assert_eq!(
+            added.origin,
+            Some((
+                xprv.fingerprint(wallet.secp_ctx()),
+                bip32::DerivationPath::from_str("m/0").unwrap()
+            ))
+        );
+        assert_eq!(report.finalization.missing_signatures(), vec![0]);
 
-        let mut builder = wallet.build_fee_bump(txid).unwrap();
-        builder.fee_rate(FeeRate::from_sat_per_vb(50.0));
-        let (psbt, details) = builder.finish().unwrap();
+        // signing again doesn't add anything
+        let report = wallet
+            .sign_with_report(&mut psbt, Default::default())
+            .unwrap();
+        assert!(report.signed_inputs().is_empty());
+        assert_eq!(report.added_signatures, vec![vec![]]);
+    }
 
-        assert_eq!(details.sent, original_details.sent + 25_000);
-        assert_eq!(details.fee.unwrap_or(0) + details.received, 30_000);
+    #[test]
+    fn test_sign_low_r() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_address(New).unwrap();
+        let fee_rate = FeeRate::from_sat_per_vb(10.0);
+
+        // about half of the signatures produced without grinding have a high `r`, sign a few
+        // different transactions to find some
+        let mut high_r = false;
+        for amount in 0..16 {
+            let mut fees = vec![];
+            for allow_grinding in &[true, false] {
+                let mut builder = wallet.build_tx();
+                builder
+                    .add_recipient(addr.script_pubkey(), 10_000 + amount)
+                    .fee_rate(fee_rate)
+                    .allow_grinding(*allow_grinding);
+                let (mut psbt, details) = builder.finish().unwrap();
+
+                let sign_options = SignOptions {
+                    allow_grinding: *allow_grinding,
+                    ..Default::default()
+                };
+                let finalized = wallet.sign(&mut psbt, sign_options).unwrap();
+                assert!(finalized);
+                let tx = psbt.extract_tx();
+
+                // DER: 0x30 <len> 0x02 <r len> <r> ..., followed by the sighash byte
+                let signature = &tx.input[0].witness[0];
+                if *allow_grinding {
+                    assert!(signature[3] <= 32);
+                    assert!(signature.len() <= 71);
+                } else {
+                    assert!(signature.len() <= 72);
+                    high_r |= signature[3] == 33;
+                }
 
-        let tx = &psbt.global.unsigned_tx;
-        assert_eq!(tx.input.len(), 2);
-        assert_eq!(tx.output.len(), 2);
-        assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey == addr.script_pubkey())
-                .unwrap()
-                .value,
-            45_000
-        );
-        assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey != addr.script_pubkey())
-                .unwrap()
-                .value,
-            details.received
-        );
+                // the fee rate is never lower than the target, and with grinding the estimated
+                // weight is within one vbyte of the signed one
+                let fee = details.fee.unwrap();
+                let min_fee = (fee_rate.as_sat_vb() * tx.get_weight().vbytes()).ceil() as u64;
+                assert!(fee >= min_fee, "{} < {}", fee, min_fee);
+                if *allow_grinding {
+                    assert!(
+                        fee <= min_fee + fee_rate.as_sat_vb() as u64,
+                        "{} > {}",
+                        fee,
+                        min_fee
+                    );
+                }
+                fees.push(fee);
+            }
 
-        assert_fee_rate!(psbt.extract_tx(), details.fee.unwrap_or(0), FeeRate::from_sat_per_vb(50.0), @add_signature);
+            // without grinding the estimate assumes one more byte for the signature
+            assert!(fees[1] > fees[0]);
+        }
+        assert!(high_r);
     }
 
     #[test]
-    fn test_bump_fee_absolute_add_input() {
-        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
-        crate::populate_test_db!(
-            wallet.database.borrow_mut(),
-            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
-            Some(100),
-        );
+    fn test_signer_registry() {
+        use bitcoin::util::bip32;
+        use miniscript::descriptor::{DescriptorXKey, Wildcard};
+
+        let secp = Secp256k1::new();
+        let xprv = bip32::ExtendedPrivKey::from_str("tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS").unwrap();
+        let xpub = bip32::ExtendedPubKey::from_private(&secp, &xprv);
+        let (wallet, _, _) = get_funded_wallet(&format!("wpkh({}/0/*)", xpub));
+        let addr = wallet.get_address(New).unwrap();
 
-        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
         let mut builder = wallet.build_tx();
-        builder
-            .add_recipient(addr.script_pubkey(), 45_000)
-            .enable_rbf();
-        let (psbt, mut original_details) = builder.finish().unwrap();
-        let mut tx = psbt.extract_tx();
-        let txid = tx.txid();
-        // skip saving the new utxos, we know they can't be used anyways
-        for txin in &mut tx.input {
-            txin.witness.push([0x00; 108].to_vec()); // fake signature
-            wallet
-                .database
-                .borrow_mut()
-                .del_utxo(&txin.previous_output)
-                .unwrap();
-        }
-        original_details.transaction = Some(tx);
-        wallet
-            .database
-            .borrow_mut()
-            .set_tx(&original_details)
-            .unwrap();
+        builder.drain_wallet().drain_to(addr.script_pubkey());
+        let (mut psbt, _) = builder.finish().unwrap();
 
-        let mut builder = wallet.build_fee_bump(txid).unwrap();
-        builder.fee_absolute(6_000);
-        let (psbt, details) = builder.finish().unwrap();
+        // watch-only
+        assert!(!wallet.sign(&mut psbt.clone(), Default::default()).unwrap());
 
-        assert_eq!(details.sent, original_details.sent + 25_000);
-        assert_eq!(details.fee.unwrap_or(0) + details.received, 30_000);
+        let signer: Arc<dyn Signer> = Arc::new(DescriptorXKey {
+            origin: None,
+            xkey: xprv,
+            derivation_path: bip32::DerivationPath::default(),
+            wildcard: Wildcard::Unhardened,
+        });
+        let origin = (
+            xprv.fingerprint(&secp),
+            bip32::DerivationPath::from_str("m/0").unwrap(),
+        );
 
-        let tx = &psbt.global.unsigned_tx;
-        assert_eq!(tx.input.len(), 2);
-        assert_eq!(tx.output.len(), 2);
-        assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey == addr.script_pubkey())
-                .unwrap()
-                .value,
-            45_000
+        // a signer registered for a different path doesn't match
+        wallet.register_signer(
+            (origin.0, bip32::DerivationPath::from_str("m/1").unwrap()),
+            Arc::clone(&signer),
         );
+        assert!(wallet
+            .signer_registry()
+            .signers_for_input(&psbt.inputs[0])
+            .is_empty());
+
+        assert!(wallet.register_signer(origin.clone(), signer).is_none());
+        let matching = wallet.signer_registry().signers_for_input(&psbt.inputs[0]);
+        assert_eq!(matching.len(), 1);
+        assert!(psbt.inputs[0].bip32_derivation.contains_key(&matching[0].0));
+        assert!(wallet.sign(&mut psbt, Default::default()).unwrap());
+
+        assert!(wallet.unregister_signer(&origin).is_some());
+        assert_eq!(wallet.signer_registry().origins().len(), 1);
+    }
+
+    #[test]
+    fn test_bip85_root() {
+        let xprv = "tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS";
+        let (wallet, _, _) = get_funded_wallet(&format!("wpkh({}/84'/1'/0'/0/*)", xprv));
+        let root = wallet.bip85_root().unwrap();
+        assert_eq!(root.xprv().to_string(), xprv);
         assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            root.derive_xprv(wallet.secp_ctx(), 0).unwrap(),
+            Bip85Root::new(bitcoin::util::bip32::ExtendedPrivKey::from_str(xprv).unwrap())
+                .derive_xprv(wallet.secp_ctx(), 0)
                 .unwrap()
-                .value,
-            details.received
         );
 
-        assert_eq!(details.fee.unwrap_or(0), 6_000);
+        // The key of an account isn't a root, even if it was serialized with a depth of zero
+        let (wallet, _, _) = get_funded_wallet(&format!("wpkh([d34db33f/84h/1h/0h]{}/*)", xprv));
+        assert!(wallet.bip85_root().is_none());
+
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        assert!(wallet.bip85_root().is_none());
     }
 
     #[test]
-    fn test_bump_fee_no_change_add_input_and_change() {
-        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
-        let incoming_txid = crate::populate_test_db!(
-            wallet.database.borrow_mut(),
-            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
-            Some(100),
-        );
-
-        // initially make a tx without change by using `drain_to`
+    fn test_check_psbt() {
+        let (wallet, _, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
         let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let recipient = TxOut {
+            script_pubkey: addr.script_pubkey(),
+            value: 25_000,
+        };
+
         let mut builder = wallet.build_tx();
         builder
-            .drain_to(addr.script_pubkey())
-            .add_utxo(OutPoint {
-                txid: incoming_txid,
-                vout: 0,
-            })
-            .unwrap()
-            .manually_selected_only()
-            .enable_rbf();
-        let (psbt, mut original_details) = builder.finish().unwrap();
-
-        let mut tx = psbt.extract_tx();
-        let txid = tx.txid();
-        // skip saving the new utxos, we know they can't be used anyways
-        for txin in &mut tx.input {
-            txin.witness.push([0x00; 108].to_vec()); // fake signature
-            wallet
-                .database
-                .borrow_mut()
-                .del_utxo(&txin.previous_output)
-                .unwrap();
-        }
-        original_details.transaction = Some(tx);
-        wallet
-            .database
-            .borrow_mut()
-            .set_tx(&original_details)
-            .unwrap();
-
-        // now bump the fees without using `allow_shrinking`. the wallet should add an
-        // extra input and a change output, and leave the original output untouched
-        let mut builder = wallet.build_fee_bump(txid).unwrap();
-        builder.fee_rate(FeeRate::from_sat_per_vb(50.0));
+            .add_recipient(recipient.script_pubkey.clone(), recipient.value)
+            .ordering(super::tx_builder::TxOrdering::Untouched);
         let (psbt, details) = builder.finish().unwrap();
 
-        let original_send_all_amount = original_details.sent - original_details.fee.unwrap_or(0);
-        assert_eq!(details.sent, original_details.sent + 50_000);
+        let report = wallet
+            .check_psbt(
+                &psbt,
+                PsbtCheckOptions {
+                    expected_outputs: Some(vec![recipient.clone()]),
+                    max_fee_rate: Some(FeeRate::from_sat_per_vb(2.0)),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+        assert_eq!(report.fee, details.fee);
+        assert!((report.fee_rate.unwrap().as_sat_vb() - 1.0).abs() < 0.1);
+        assert_eq!(report.sent, 50_000);
+        assert_eq!(report.received, details.received);
+        assert_eq!(report.outputs[0].keychain, None);
+        assert_eq!(report.outputs[1].keychain, Some(KeychainKind::External));
+
+        let report = wallet
+            .check_psbt(
+                &psbt,
+                PsbtCheckOptions {
+                    expected_outputs: Some(vec![TxOut {
+                        value: 20_000,
+                        ..recipient.clone()
+                    }]),
+                    max_fee: Some(1),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
         assert_eq!(
-            details.received,
-            75_000 - original_send_all_amount - details.fee.unwrap_or(0)
+            report.issues,
+            vec![
+                PsbtIssue::UnexpectedOutput(0),
+                PsbtIssue::MissingOutput(TxOut {
+                    value: 20_000,
+                    ..recipient
+                }),
+                PsbtIssue::FeeTooHigh {
+                    fee: details.fee.unwrap(),
+                    max: 1
+                },
+            ]
         );
 
-        let tx = &psbt.global.unsigned_tx;
-        assert_eq!(tx.input.len(), 2);
-        assert_eq!(tx.output.len(), 2);
-        assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey == addr.script_pubkey())
-                .unwrap()
-                .value,
-            original_send_all_amount
-        );
+        let mut tampered = psbt.clone();
+        tampered.inputs[0].witness_utxo.as_mut().unwrap().value = 60_000;
+        tampered.outputs[0].bip32_derivation = tampered.outputs[1].bip32_derivation.clone();
+        let report = wallet.check_psbt(&tampered, Default::default()).unwrap();
         assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey != addr.script_pubkey())
-                .unwrap()
-                .value,
-            75_000 - original_send_all_amount - details.fee.unwrap_or(0)
+            report.issues,
+            vec![PsbtIssue::InconsistentUtxo(0), PsbtIssue::ForeignChange(0)]
         );
 
-        assert_fee_rate!(psbt.extract_tx(), details.fee.unwrap_or(0), FeeRate::from_sat_per_vb(50.0), @add_signature);
+        let mut tampered = psbt;
+        tampered.inputs[0].witness_utxo = None;
+        tampered.inputs[0].non_witness_utxo = None;
+        let report = wallet.check_psbt(&tampered, Default::default()).unwrap();
+        assert_eq!(report.issues, vec![PsbtIssue::MissingUtxo(0)]);
+        assert_eq!(report.fee, None);
     }
 
     #[test]
-    fn test_bump_fee_add_input_change_dust() {
-        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
-        crate::populate_test_db!(
-            wallet.database.borrow_mut(),
-            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
-            Some(100),
-        );
-
-        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
-        let mut builder = wallet.build_tx();
-        builder
-            .add_recipient(addr.script_pubkey(), 45_000)
-            .enable_rbf();
-        let (psbt, mut original_details) = builder.finish().unwrap();
-        let mut tx = psbt.extract_tx();
-        assert_eq!(tx.input.len(), 1);
-        assert_eq!(tx.output.len(), 2);
-        let txid = tx.txid();
-        // skip saving the new utxos, we know they can't be used anyways
-        for txin in &mut tx.input {
-            txin.witness.push([0x00; 108].to_vec()); // fake signature
-            wallet
-                .database
-                .borrow_mut()
-                .del_utxo(&txin.previous_output)
-                .unwrap();
-        }
-        original_details.transaction = Some(tx);
+    fn test_describe_psbt() {
+        let (wallet, _, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+        let seen_addr = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt").unwrap();
+        let new_addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        // reveal the funded address, so that the change doesn't reuse it
+        let reused_addr = wallet.get_address(New).unwrap();
+
+        let past_tx = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: seen_addr.script_pubkey(),
+            }],
+        };
         wallet
             .database
-            .borrow_mut()
-            .set_tx(&original_details)
+            .write()
+            .unwrap()
+            .set_tx(&TransactionDetails {
+                txid: past_tx.txid(),
+                transaction: Some(past_tx),
+                ..Default::default()
+            })
             .unwrap();
 
-        let mut builder = wallet.build_fee_bump(txid).unwrap();
-        builder.fee_rate(FeeRate::from_sat_per_vb(140.0));
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(seen_addr.script_pubkey(), 10_000)
+            .add_recipient(reused_addr.script_pubkey(), 5_000)
+            .add_recipient(new_addr.script_pubkey(), 5_000)
+            .ordering(super::tx_builder::TxOrdering::Untouched);
         let (psbt, details) = builder.finish().unwrap();
 
+        let description = wallet.describe_psbt(&psbt).unwrap();
+        assert_eq!(description.fee, details.fee);
+        assert!((description.fee_rate.unwrap().as_sat_vb() - 1.0).abs() < 0.1);
+
+        assert_eq!(description.inputs.len(), 1);
+        assert_eq!(description.inputs[0].keychain, Some(KeychainKind::External));
+        assert_eq!(description.inputs[0].derivation_paths.len(), 1);
+        assert!(!description.inputs[0].finalized);
+
+        assert_eq!(description.outputs.len(), 4);
+        assert_eq!(description.outputs[0].address, Some(seen_addr));
+        assert_eq!(description.outputs[0].keychain, None);
+        assert_eq!(
+            description.outputs[1].keychain,
+            Some(KeychainKind::External)
+        );
+        assert_eq!(description.outputs[2].keychain, None);
+        assert!(description.outputs[2].derivation_paths.is_empty());
+        assert_eq!(
+            description.outputs[3].keychain,
+            Some(KeychainKind::External)
+        );
+        assert_eq!(description.outputs[3].derivation_paths.len(), 1);
+
+        assert_eq!(
+            description.warnings,
+            vec![
+                PsbtWarning::PreviouslySeenAddress(0),
+                PsbtWarning::AddressReuse(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unused_address() {
+        let db = MemoryDatabase::new();
+        let wallet = Wallet::new_offline("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)",
+                                         None, Network::Testnet, db).unwrap();
+
         assert_eq!(
-            original_details.received,
-            5_000 - original_details.fee.unwrap_or(0)
+            wallet.get_address(LastUnused).unwrap().to_string(),
+            "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+        );
+        assert_eq!(
+            wallet.get_address(LastUnused).unwrap().to_string(),
+            "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
         );
+    }
 
-        assert_eq!(details.sent, original_details.sent + 25_000);
-        assert_eq!(details.fee.unwrap_or(0), 30_000);
-        assert_eq!(details.received, 0);
+    #[test]
+    fn test_next_unused_address() {
+        let descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
+        let descriptors = testutils!(@descriptors (descriptor));
+        let wallet = Wallet::new_offline(
+            &descriptors.0,
+            None,
+            Network::Testnet,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
 
-        let tx = &psbt.global.unsigned_tx;
-        assert_eq!(tx.input.len(), 2);
-        assert_eq!(tx.output.len(), 1);
         assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey == addr.script_pubkey())
-                .unwrap()
-                .value,
-            45_000
+            wallet.get_address(LastUnused).unwrap().to_string(),
+            "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
         );
 
-        assert_fee_rate!(psbt.extract_tx(), details.fee.unwrap_or(0), FeeRate::from_sat_per_vb(140.0), @dust_change, @add_signature);
+        // use the above address
+        crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+
+        assert_eq!(
+            wallet.get_address(LastUnused).unwrap().to_string(),
+            "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
+        );
     }
 
     #[test]
-    fn test_bump_fee_force_add_input() {
+    fn test_build_sweep() {
         let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
-        let incoming_txid = crate::populate_test_db!(
-            wallet.database.borrow_mut(),
+        let txid = crate::populate_test_db!(
+            wallet.database.write().unwrap(),
             testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
             Some(100),
         );
-
-        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
-        let mut builder = wallet.build_tx();
-        builder
-            .add_recipient(addr.script_pubkey(), 45_000)
-            .enable_rbf();
-        let (psbt, mut original_details) = builder.finish().unwrap();
-        let mut tx = psbt.extract_tx();
-        let txid = tx.txid();
-        // skip saving the new utxos, we know they can't be used anyways
-        for txin in &mut tx.input {
-            txin.witness.push([0x00; 108].to_vec()); // fake signature
-            wallet
-                .database
-                .borrow_mut()
-                .del_utxo(&txin.previous_output)
-                .unwrap();
-        }
-        original_details.transaction = Some(tx);
-        wallet
-            .database
-            .borrow_mut()
-            .set_tx(&original_details)
+        let new_descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
+        let new_change_descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/1/*)";
+        let change_wallet = Wallet::new_offline(
+            new_change_descriptor,
+            None,
+            Network::Regtest,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+        let fee_rate = FeeRate::from_sat_per_vb(1.0);
+        let max_fee_rate = FeeRate::from_sat_per_vb(5.0);
+
+        let sweep = wallet
+            .build_sweep(
+                new_descriptor,
+                new_change_descriptor,
+                fee_rate,
+                max_fee_rate,
+                1,
+            )
             .unwrap();
-
-        // the new fee_rate is low enough that just reducing the change would be fine, but we force
-        // the addition of an extra input with `add_utxo()`
-        let mut builder = wallet.build_fee_bump(txid).unwrap();
-        builder
-            .add_utxo(OutPoint {
-                txid: incoming_txid,
-                vout: 0,
-            })
-            .unwrap()
-            .fee_rate(FeeRate::from_sat_per_vb(5.0));
-        let (psbt, details) = builder.finish().unwrap();
-
-        assert_eq!(details.sent, original_details.sent + 25_000);
-        assert_eq!(details.fee.unwrap_or(0) + details.received, 30_000);
-
-        let tx = &psbt.global.unsigned_tx;
-        assert_eq!(tx.input.len(), 2);
-        assert_eq!(tx.output.len(), 2);
+        assert_eq!(sweep.len(), 2);
         assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            sweep[0].0.global.unsigned_tx.output[0].script_pubkey,
+            Address::from_str("tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a")
                 .unwrap()
-                .value,
-            45_000
+                .script_pubkey()
         );
         assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            sweep[1].0.global.unsigned_tx.output[0].script_pubkey,
+            Address::from_str("tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7")
                 .unwrap()
-                .value,
-            details.received
+                .script_pubkey()
         );
+        assert_eq!(sweep[0].1.sent + sweep[1].1.sent, 75_000);
+        {
+            let database = wallet.database.read().unwrap();
+            assert_eq!(
+                database.get_sweep_index(KeychainKind::External).unwrap(),
+                Some(2)
+            );
+            assert_eq!(
+                database.get_sweep_index(KeychainKind::Internal).unwrap(),
+                None
+            );
+            assert_eq!(database.iter_swept_utxos().unwrap().len(), 2);
+        }
 
-        assert_fee_rate!(psbt.extract_tx(), details.fee.unwrap_or(0), FeeRate::from_sat_per_vb(5.0), @add_signature);
-    }
+        // the outputs already swept are skipped
+        assert!(wallet
+            .build_sweep(
+                new_descriptor,
+                new_change_descriptor,
+                fee_rate,
+                max_fee_rate,
+                1,
+            )
+            .unwrap()
+            .is_empty());
 
-    #[test]
-    fn test_bump_fee_absolute_force_add_input() {
-        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
-        let incoming_txid = crate::populate_test_db!(
-            wallet.database.borrow_mut(),
-            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+        // new outputs are swept to the next address
+        crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 10_000 ) (@confirmations 1)),
             Some(100),
         );
-
-        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
-        let mut builder = wallet.build_tx();
-        builder
-            .add_recipient(addr.script_pubkey(), 45_000)
-            .enable_rbf();
-        let (psbt, mut original_details) = builder.finish().unwrap();
-        let mut tx = psbt.extract_tx();
-        let txid = tx.txid();
-        // skip saving the new utxos, we know they can't be used anyways
-        for txin in &mut tx.input {
-            txin.witness.push([0x00; 108].to_vec()); // fake signature
+        let sweep = wallet
+            .build_sweep(
+                new_descriptor,
+                new_change_descriptor,
+                fee_rate,
+                max_fee_rate,
+                1,
+            )
+            .unwrap();
+        assert_eq!(sweep.len(), 1);
+        assert_eq!(sweep[0].1.sent, 10_000);
+        assert_eq!(
             wallet
                 .database
-                .borrow_mut()
-                .del_utxo(&txin.previous_output)
-                .unwrap();
+                .read()
+                .unwrap()
+                .get_sweep_index(KeychainKind::External)
+                .unwrap(),
+            Some(3)
+        );
+
+        // after a reset everything is swept again, internal outputs to the change descriptor
+        wallet.reset_sweep().unwrap();
+        {
+            let database = wallet.database.read().unwrap();
+            assert_eq!(
+                database.get_sweep_index(KeychainKind::External).unwrap(),
+                None
+            );
+            assert!(database.iter_swept_utxos().unwrap().is_empty());
         }
-        original_details.transaction = Some(tx);
-        wallet
+        let mut utxo = wallet
             .database
-            .borrow_mut()
-            .set_tx(&original_details)
-            .unwrap();
-
-        // the new fee_rate is low enough that just reducing the change would be fine, but we force
-        // the addition of an extra input with `add_utxo()`
-        let mut builder = wallet.build_fee_bump(txid).unwrap();
-        builder
-            .add_utxo(OutPoint {
-                txid: incoming_txid,
-                vout: 0,
-            })
+            .read()
             .unwrap()
-            .fee_absolute(250);
-        let (psbt, details) = builder.finish().unwrap();
-
-        assert_eq!(details.sent, original_details.sent + 25_000);
-        assert_eq!(details.fee.unwrap_or(0) + details.received, 30_000);
-
-        let tx = &psbt.global.unsigned_tx;
-        assert_eq!(tx.input.len(), 2);
-        assert_eq!(tx.output.len(), 2);
+            .get_utxo(&OutPoint::new(txid, 0))
+            .unwrap()
+            .unwrap();
+        utxo.keychain = KeychainKind::Internal;
+        wallet.database.write().unwrap().set_utxo(&utxo).unwrap();
+
+        let sweep = wallet
+            .build_sweep(
+                new_descriptor,
+                new_change_descriptor,
+                fee_rate,
+                max_fee_rate,
+                10,
+            )
+            .unwrap();
+        assert_eq!(sweep.len(), 2);
+        let (psbt, details) = &sweep[0];
+        assert_eq!(psbt.global.unsigned_tx.input.len(), 2);
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
         assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            psbt.global.unsigned_tx.output[0].script_pubkey,
+            Address::from_str("tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a")
                 .unwrap()
-                .value,
-            45_000
+                .script_pubkey()
         );
+        assert_eq!(details.sent, 60_000);
+        assert_fee_rate!(psbt.clone().extract_tx(), details.fee.unwrap_or(0), fee_rate, @add_signature);
+        let (psbt, details) = &sweep[1];
         assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey != addr.script_pubkey())
-                .unwrap()
-                .value,
-            details.received
+            psbt.global.unsigned_tx.output[0].script_pubkey,
+            change_wallet.get_address(Peek(0)).unwrap().script_pubkey()
         );
+        assert_eq!(details.sent, 25_000);
+        {
+            let database = wallet.database.read().unwrap();
+            assert_eq!(
+                database.get_sweep_index(KeychainKind::External).unwrap(),
+                Some(1)
+            );
+            assert_eq!(
+                database.get_sweep_index(KeychainKind::Internal).unwrap(),
+                Some(1)
+            );
+        }
 
-        assert_eq!(details.fee.unwrap_or(0), 250);
+        // nothing is saved if one of the transactions fails
+        wallet.reset_sweep().unwrap();
+        assert!(matches!(
+            wallet.build_sweep(
+                new_descriptor,
+                new_change_descriptor,
+                FeeRate::from_sat_per_vb(10.0),
+                max_fee_rate,
+                10,
+            ),
+            Err(Error::FeeRateTooHigh { .. })
+        ));
+        assert!(wallet
+            .database
+            .read()
+            .unwrap()
+            .iter_swept_utxos()
+            .unwrap()
+            .is_empty());
+
+        assert!(wallet
+            .build_sweep(
+                new_descriptor,
+                new_change_descriptor,
+                fee_rate,
+                max_fee_rate,
+                0,
+            )
+            .is_err());
     }
 
     #[test]
-    fn test_sign_single_xprv() {
-        let (wallet, _, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
-        let addr = wallet.get_address(New).unwrap();
-        let mut builder = wallet.build_tx();
-        builder.drain_to(addr.script_pubkey()).drain_wallet();
-        let (mut psbt, _) = builder.finish().unwrap();
+    fn test_migrate_descriptor_checksum() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
 
-        let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
-        assert!(finalized);
+        let mut migrated = MemoryDatabase::new();
+        crate::database::migrate(&*wallet.database.read().unwrap(), &mut migrated).unwrap();
+        let other_wallet =
+            Wallet::new_offline(get_test_single_sig_csv(), None, Network::Regtest, migrated);
+        assert!(matches!(other_wallet, Err(Error::ChecksumMismatch)));
 
-        let extracted = psbt.extract_tx();
-        assert_eq!(extracted.input[0].witness.len(), 2);
+        let mut migrated = MemoryDatabase::new();
+        crate::database::migrate(&*wallet.database.read().unwrap(), &mut migrated).unwrap();
+        let migrated_wallet =
+            Wallet::new_offline(get_test_wpkh(), None, Network::Regtest, migrated).unwrap();
+        assert_eq!(
+            migrated_wallet.get_balance().unwrap(),
+            wallet.get_balance().unwrap()
+        );
     }
 
     #[test]
-    fn test_sign_single_xprv_with_master_fingerprint_and_path() {
-        let (wallet, _, _) = get_funded_wallet("wpkh([d34db33f/84h/1h/0h]tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
-        let addr = wallet.get_address(New).unwrap();
-        let mut builder = wallet.build_tx();
-        builder.drain_to(addr.script_pubkey()).drain_wallet();
-        let (mut psbt, _) = builder.finish().unwrap();
+    fn test_balance_at() {
+        use bitcoin::TxIn;
 
-        let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
-        assert!(finalized);
+        let (wallet, _, txid) = get_funded_wallet(get_test_wpkh());
+        let script_pubkey = wallet.get_address(Peek(0)).unwrap().script_pubkey();
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
 
-        let extracted = psbt.extract_tx();
-        assert_eq!(extracted.input[0].witness.len(), 2);
+        let spend = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(txid, 0),
+                script_sig: Script::new(),
+                sequence: 0xFFFFFFFF,
+                witness: vec![],
+            }],
+            output: vec![
+                TxOut {
+                    value: 20_000,
+                    script_pubkey,
+                },
+                TxOut {
+                    value: 29_000,
+                    script_pubkey: addr.script_pubkey(),
+                },
+            ],
+        };
+        wallet
+            .database
+            .write()
+            .unwrap()
+            .set_tx(&TransactionDetails {
+                txid: spend.txid(),
+                transaction: Some(spend),
+                received: 20_000,
+                sent: 50_000,
+                fee: Some(1_000),
+                confirmation_time: Some(ConfirmationTime {
+                    height: 120,
+                    timestamp: 0,
+                }),
+                verified: true,
+                last_evicted: None,
+            })
+            .unwrap();
+
+        // the funding transaction is confirmed at height 99
+        assert_eq!(wallet.balance_at(98).unwrap(), 0);
+        assert_eq!(wallet.balance_at(99).unwrap(), 50_000);
+        assert_eq!(wallet.balance_at(119).unwrap(), 50_000);
+        assert_eq!(wallet.balance_at(120).unwrap(), 20_000);
     }
 
     #[test]
-    fn test_sign_single_xprv_bip44_path() {
-        let (wallet, _, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/44'/0'/0'/0/*)");
-        let addr = wallet.get_address(New).unwrap();
-        let mut builder = wallet.build_tx();
-        builder.drain_to(addr.script_pubkey()).drain_wallet();
-        let (mut psbt, _) = builder.finish().unwrap();
+    fn test_coinbase_maturity() {
+        use bitcoin::TxIn;
+
+        let get_wallet = |current_height| {
+            let descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
+            let wallet = Wallet::_new(
+                descriptor,
+                None,
+                Network::Regtest,
+                MemoryDatabase::new(),
+                (),
+                current_height,
+            )
+            .unwrap();
 
-        let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
-        assert!(finalized);
+            let coinbase = Transaction {
+                version: 1,
+                lock_time: 0,
+                input: vec![TxIn {
+                    previous_output: OutPoint::null(),
+                    script_sig: Script::new(),
+                    sequence: 0xFFFFFFFF,
+                    witness: vec![],
+                }],
+                output: vec![TxOut {
+                    value: 50_000,
+                    script_pubkey: wallet.get_address(Peek(0)).unwrap().script_pubkey(),
+                }],
+            };
+            let utxo = LocalUtxo {
+                outpoint: OutPoint::new(coinbase.txid(), 0),
+                txout: coinbase.output[0].clone(),
+                keychain: KeychainKind::External,
+            };
 
-        let extracted = psbt.extract_tx();
-        assert_eq!(extracted.input[0].witness.len(), 2);
+            let mut database = wallet.database.write().unwrap();
+            database
+                .set_tx(&TransactionDetails {
+                    txid: coinbase.txid(),
+                    transaction: Some(coinbase),
+                    received: 50_000,
+                    sent: 0,
+                    fee: Some(0),
+                    confirmation_time: Some(ConfirmationTime {
+                        height: 150,
+                        timestamp: 0,
+                    }),
+                    verified: true,
+                    last_evicted: None,
+                })
+                .unwrap();
+            database.set_utxo(&utxo).unwrap();
+            drop(database);
+
+            (wallet, utxo)
+        };
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+
+        // 200 - 150 + 1 = 51 confirmations
+        let (wallet, utxo) = get_wallet(Some(200));
+        assert!(wallet.is_immature(&utxo).unwrap());
+        assert_eq!(
+            wallet.get_balance_details().unwrap(),
+            Balance {
+                immature: 50_000,
+                confirmed: 0,
+                unconfirmed: 0,
+            }
+        );
+        let mut builder = wallet.build_tx();
+        builder.add_recipient(addr.script_pubkey(), 25_000);
+        assert!(matches!(
+            builder.finish(),
+            Err(Error::InsufficientFunds { .. })
+        ));
+
+        // without the current height the output is assumed to be mature
+        let (wallet, utxo) = get_wallet(None);
+        assert!(!wallet.is_immature(&utxo).unwrap());
+
+        // 249 - 150 + 1 = 100 confirmations
+        let (wallet, utxo) = get_wallet(Some(249));
+        assert!(!wallet.is_immature(&utxo).unwrap());
+        assert_eq!(wallet.get_balance_details().unwrap().spendable(), 50_000);
+        let mut builder = wallet.build_tx();
+        builder.add_recipient(addr.script_pubkey(), 25_000);
+        builder.finish().unwrap();
+    }
+
+    // A blockchain without transactions, whose tip can be moved between syncs. It fails to
+    // compute the median time past until one is set
+    #[cfg(not(any(target_arch = "wasm32", feature = "async-interface")))]
+    struct TipBlockchain {
+        height: std::sync::atomic::AtomicU32,
+        median_time_past: Mutex<Option<u32>>,
     }
 
-    #[test]
-    fn test_sign_single_xprv_sh_wpkh() {
-        let (wallet, _, _) = get_funded_wallet("sh(wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*))");
-        let addr = wallet.get_address(New).unwrap();
-        let mut builder = wallet.build_tx();
-        builder.drain_to(addr.script_pubkey()).drain_wallet();
-        let (mut psbt, _) = builder.finish().unwrap();
+    #[cfg(not(any(target_arch = "wasm32", feature = "async-interface")))]
+    impl TipBlockchain {
+        fn at_height(height: u32) -> Self {
+            TipBlockchain {
+                height: height.into(),
+                median_time_past: Mutex::new(None),
+            }
+        }
 
-        let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
-        assert!(finalized);
+        fn set_median_time_past(&self, median_time_past: u32) {
+            *self.median_time_past.lock().unwrap() = Some(median_time_past);
+        }
 
-        let extracted = psbt.extract_tx();
-        assert_eq!(extracted.input[0].witness.len(), 2);
+        fn set_height(&self, height: u32) {
+            self.height
+                .store(height, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    #[cfg(not(any(target_arch = "wasm32", feature = "async-interface")))]
+    impl Blockchain for TipBlockchain {
+        fn get_capabilities(&self) -> HashSet<crate::blockchain::Capability> {
+            Default::default()
+        }
+        fn setup<D: BatchDatabase, P: 'static + Progress>(
+            &self,
+            _database: &mut D,
+            _progress_update: P,
+        ) -> Result<(), Error> {
+            Ok(())
+        }
+        fn get_tx(&self, _txid: &Txid) -> Result<Option<Transaction>, Error> {
+            Ok(None)
+        }
+        fn broadcast(&self, _tx: &Transaction) -> Result<(), Error> {
+            Ok(())
+        }
+        fn get_height(&self) -> Result<u32, Error> {
+            Ok(self.height.load(std::sync::atomic::Ordering::SeqCst))
+        }
+        fn estimate_fee(&self, _target: usize) -> Result<FeeRate, Error> {
+            Ok(FeeRate::default_min_relay_fee())
+        }
+        fn get_median_time_past(&self) -> Result<Option<u32>, Error> {
+            self.median_time_past
+                .lock()
+                .unwrap()
+                .map(Some)
+                .ok_or_else(|| Error::Generic("median time past unavailable".into()))
+        }
     }
 
+    #[cfg(not(any(target_arch = "wasm32", feature = "async-interface")))]
     #[test]
-    fn test_sign_single_wif() {
-        let (wallet, _, _) =
-            get_funded_wallet("wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)");
-        let addr = wallet.get_address(New).unwrap();
-        let mut builder = wallet.build_tx();
-        builder.drain_to(addr.script_pubkey()).drain_wallet();
-        let (mut psbt, _) = builder.finish().unwrap();
+    fn test_sync_refreshes_current_height() {
+        use crate::blockchain::noop_progress;
 
-        let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
-        assert!(finalized);
+        let descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
+        let wallet = Wallet::new(
+            descriptor,
+            None,
+            Network::Regtest,
+            MemoryDatabase::new(),
+            TipBlockchain::at_height(200),
+        )
+        .unwrap();
+        assert_eq!(wallet.current_height(), Some(200));
+
+        let coinbase = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![bitcoin::TxIn::default()],
+            output: vec![TxOut {
+                value: 50_000,
+                script_pubkey: wallet.get_address(New).unwrap().script_pubkey(),
+            }],
+        };
+        let coinbase_txid = coinbase.txid();
+        store_tx(
+            &wallet,
+            coinbase,
+            TransactionDetails {
+                txid: coinbase_txid,
+                transaction: None,
+                received: 50_000,
+                sent: 0,
+                fee: Some(0),
+                confirmation_time: ConfirmationTime::new(Some(150), Some(0)),
+                verified: true,
+                last_evicted: None,
+            },
+        );
+        let utxo = wallet.list_unspent().unwrap().pop().unwrap();
+        assert!(wallet.is_immature(&utxo).unwrap());
 
-        let extracted = psbt.extract_tx();
-        assert_eq!(extracted.input[0].witness.len(), 2);
+        // 249 - 150 + 1 = 100 confirmations once the wallet learns about the new tip
+        wallet.client().set_height(249);
+        assert!(wallet.is_immature(&utxo).unwrap());
+        wallet.sync(noop_progress(), None).unwrap();
+        assert_eq!(wallet.current_height(), Some(249));
+        assert!(!wallet.is_immature(&utxo).unwrap());
     }
 
+    #[cfg(not(any(target_arch = "wasm32", feature = "async-interface")))]
     #[test]
-    fn test_sign_single_xprv_no_hd_keypaths() {
-        let (wallet, _, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
-        let addr = wallet.get_address(New).unwrap();
-        let mut builder = wallet.build_tx();
-        builder.drain_to(addr.script_pubkey()).drain_wallet();
-        let (mut psbt, _) = builder.finish().unwrap();
+    fn test_sync_refreshes_median_time_past() {
+        use crate::blockchain::noop_progress;
 
-        psbt.inputs[0].bip32_derivation.clear();
-        assert_eq!(psbt.inputs[0].bip32_derivation.len(), 0);
+        // failing to fetch the median time past doesn't prevent creating the wallet
+        let wallet = Wallet::new(
+            get_test_wpkh(),
+            None,
+            Network::Regtest,
+            MemoryDatabase::new(),
+            TipBlockchain::at_height(1_000),
+        )
+        .unwrap();
+        assert_eq!(wallet.median_time_past(), None);
 
-        let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
-        assert!(finalized);
+        wallet.client().set_median_time_past(1_600_000_000);
+        wallet.sync(noop_progress(), None).unwrap();
+        assert_eq!(wallet.median_time_past(), Some(1_600_000_000));
 
-        let extracted = psbt.extract_tx();
-        assert_eq!(extracted.input[0].witness.len(), 2);
+        // a failure during the sync keeps the last value known
+        *wallet.client().median_time_past.lock().unwrap() = None;
+        wallet.sync(noop_progress(), None).unwrap();
+        assert_eq!(wallet.median_time_past(), Some(1_600_000_000));
     }
 
+    #[cfg(not(any(target_arch = "wasm32", feature = "async-interface")))]
     #[test]
-    fn test_include_output_redeem_witness_script() {
-        let (wallet, _, _) = get_funded_wallet("sh(wsh(multi(1,cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW,cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu)))");
+    fn test_sync_refreshes_timelocks() {
+        use crate::blockchain::noop_progress;
+
+        let descriptors = testutils!(@descriptors (get_test_single_sig_cltv()));
+        let wallet = Wallet::new(
+            &descriptors.0,
+            None,
+            Network::Regtest,
+            MemoryDatabase::new(),
+            TipBlockchain::at_height(99_999),
+        )
+        .unwrap();
+        crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 50_000 ) (@confirmations 1)),
+            Some(99_999),
+        );
         let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
-        let mut builder = wallet.build_tx();
-        builder
-            .add_recipient(addr.script_pubkey(), 45_000)
-            .include_output_redeem_witness_script();
-        let (psbt, _) = builder.finish().unwrap();
 
-        // p2sh-p2wsh transaction should contain both witness and redeem scripts
-        assert!(psbt
-            .outputs
-            .iter()
-            .any(|output| output.redeem_script.is_some() && output.witness_script.is_some()));
-    }
+        let mut builder = wallet.build_tx();
+        builder.add_recipient(addr.script_pubkey(), 25_000);
+        assert!(matches!(
+            builder.finish(),
+            Err(Error::TimelockNotSatisfied {
+                required: 100_000,
+                current_height: 99_999
+            })
+        ));
 
-    #[test]
-    fn test_signing_only_one_of_multiple_inputs() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        // the timelock expires and the anti-fee-sniping locktime follows the new tip
+        wallet.client().set_height(100_500);
+        wallet.sync(noop_progress(), None).unwrap();
         let mut builder = wallet.build_tx();
-        builder
-            .add_recipient(addr.script_pubkey(), 45_000)
-            .include_output_redeem_witness_script();
+        builder.add_recipient(addr.script_pubkey(), 25_000);
         let (mut psbt, _) = builder.finish().unwrap();
-
-        // add another input to the psbt that is at least passable.
-        let dud_input = bitcoin::util::psbt::Input {
-            witness_utxo: Some(TxOut {
-                value: 100_000,
-                script_pubkey: miniscript::Descriptor::<bitcoin::PublicKey>::from_str(
-                    "wpkh(025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357)",
-                )
-                .unwrap()
-                .script_pubkey(),
-            }),
-            ..Default::default()
-        };
-
-        psbt.inputs.push(dud_input);
-        psbt.global.unsigned_tx.input.push(bitcoin::TxIn::default());
-        let is_final = wallet
-            .sign(
-                &mut psbt,
-                SignOptions {
-                    trust_witness_utxo: true,
-                    ..Default::default()
-                },
-            )
-            .unwrap();
-        assert!(
-            !is_final,
-            "shouldn't be final since we can't sign one of the inputs"
-        );
-        assert!(
-            psbt.inputs[0].final_script_witness.is_some(),
-            "should finalized input it signed"
-        )
+        let lock_time = psbt.global.unsigned_tx.lock_time;
+        assert!(lock_time > 100_500 - 100 && lock_time <= 100_500);
+        assert!(wallet.sign(&mut psbt, Default::default()).unwrap());
     }
 
+    #[cfg(not(any(target_arch = "wasm32", feature = "async-interface")))]
     #[test]
-    fn test_sign_nonstandard_sighash() {
-        let sighash = SigHashType::NonePlusAnyoneCanPay;
-
-        let (wallet, _, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
-        let addr = wallet.get_address(New).unwrap();
-        let mut builder = wallet.build_tx();
-        builder
-            .drain_to(addr.script_pubkey())
-            .sighash(sighash)
-            .drain_wallet();
-        let (mut psbt, _) = builder.finish().unwrap();
-
-        let result = wallet.sign(&mut psbt, Default::default());
-        assert!(
-            result.is_err(),
-            "Signing should have failed because the TX uses non-standard sighashes"
-        );
-        assert!(
-            matches!(
-                result.unwrap_err(),
-                Error::Signer(SignerError::NonStandardSighash)
-            ),
-            "Signing failed with the wrong error type"
-        );
+    fn test_sync_refreshes_confirmations() {
+        use crate::blockchain::noop_progress;
 
-        // try again after opting-in
-        let result = wallet.sign(
-            &mut psbt,
-            SignOptions {
-                allow_all_sighashes: true,
-                ..Default::default()
-            },
-        );
-        assert!(result.is_ok(), "Signing should have worked");
-        assert!(
-            result.unwrap(),
-            "Should finalize the input since we can produce signatures"
+        let descriptors = testutils!(@descriptors (get_test_wpkh()));
+        let wallet = Wallet::new(
+            &descriptors.0,
+            None,
+            Network::Regtest,
+            MemoryDatabase::new(),
+            TipBlockchain::at_height(1_000),
+        )
+        .unwrap();
+        // confirmed at height 999
+        crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 0) => 50_000 ) (@confirmations 1)),
+            Some(1_000),
         );
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
 
-        let extracted = psbt.extract_tx();
-        assert_eq!(
-            *extracted.input[0].witness[0].last().unwrap(),
-            sighash.as_u32() as u8,
-            "The signature should have been made with the right sighash"
-        );
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .min_confirmations(10);
+        assert!(matches!(
+            builder.finish(),
+            Err(Error::InsufficientFunds { .. })
+        ));
+
+        // 1_008 - 999 + 1 = 10 confirmations once the wallet learns about the new tip
+        wallet.client().set_height(1_008);
+        wallet.sync(noop_progress(), None).unwrap();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 25_000)
+            .min_confirmations(10);
+        builder.finish().unwrap();
     }
 
+    #[cfg(not(any(target_arch = "wasm32", feature = "async-interface")))]
     #[test]
-    fn test_unused_address() {
-        let db = MemoryDatabase::new();
-        let wallet = Wallet::new_offline("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)",
-                                         None, Network::Testnet, db).unwrap();
-
-        assert_eq!(
-            wallet.get_address(LastUnused).unwrap().to_string(),
-            "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
-        );
-        assert_eq!(
-            wallet.get_address(LastUnused).unwrap().to_string(),
-            "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
-        );
-    }
+    fn test_list_unused_addresses_and_mark_used() {
+        use crate::blockchain::noop_progress;
 
-    #[test]
-    fn test_next_unused_address() {
         let descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
         let descriptors = testutils!(@descriptors (descriptor));
-        let wallet = Wallet::new_offline(
+        let wallet = Wallet::new(
             &descriptors.0,
             None,
             Network::Testnet,
             MemoryDatabase::new(),
+            TipBlockchain::at_height(100),
         )
         .unwrap();
 
-        assert_eq!(
-            wallet.get_address(LastUnused).unwrap().to_string(),
-            "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
-        );
+        assert!(wallet
+            .list_unused_addresses(KeychainKind::External)
+            .unwrap()
+            .is_empty());
 
-        // use the above address
+        for _ in 0..3 {
+            wallet.get_address(New).unwrap();
+        }
+
+        // receive on index 0
         crate::populate_test_db!(
-            wallet.database.borrow_mut(),
+            wallet.database.write().unwrap(),
             testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
             Some(100),
         );
 
-        assert_eq!(
-            wallet.get_address(LastUnused).unwrap().to_string(),
-            "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
+        // the mark is only set once the transaction is synced
+        assert!(!wallet.is_used(KeychainKind::External, 0).unwrap());
+        wallet.sync(noop_progress(), None).unwrap();
+        assert!(wallet.is_used(KeychainKind::External, 0).unwrap());
+        assert!(!wallet.is_used(KeychainKind::External, 1).unwrap());
+        let unused = wallet
+            .list_unused_addresses(KeychainKind::External)
+            .unwrap()
+            .into_iter()
+            .map(|a| a.index)
+            .collect::<Vec<_>>();
+        assert_eq!(unused, vec![1, 2]);
+
+        wallet.mark_used(KeychainKind::External, 1).unwrap();
+        assert!(wallet.is_used(KeychainKind::External, 1).unwrap());
+        let unused = wallet
+            .list_unused_addresses(KeychainKind::External)
+            .unwrap()
+            .into_iter()
+            .map(|a| a.index)
+            .collect::<Vec<_>>();
+        assert_eq!(unused, vec![2]);
+
+        assert!(wallet.unmark_used(KeychainKind::External, 1).unwrap());
+        assert!(!wallet.is_used(KeychainKind::External, 1).unwrap());
+        // a real use can't be undone
+        assert!(!wallet.unmark_used(KeychainKind::External, 0).unwrap());
+        assert!(wallet.is_used(KeychainKind::External, 0).unwrap());
+
+        // the following syncs only look at the new transactions
+        crate::populate_test_db!(
+            wallet.database.write().unwrap(),
+            testutils! (@tx ( (@external descriptors, 2) => 25_000 ) (@confirmations 1)),
+            Some(100),
         );
+        wallet.sync(noop_progress(), None).unwrap();
+        assert!(wallet.is_used(KeychainKind::External, 2).unwrap());
+        let unused = wallet
+            .list_unused_addresses(KeychainKind::External)
+            .unwrap()
+            .into_iter()
+            .map(|a| a.index)
+            .collect::<Vec<_>>();
+        assert_eq!(unused, vec![1]);
     }
 
     #[test]
@@ -3977,4 +8798,77 @@ pub(crate) mod test {
             }
         );
     }
+
+    #[derive(Debug)]
+    struct TestRemoteSigner {
+        wallet: Wallet<(), MemoryDatabase>,
+        tamper: bool,
+    }
+
+    #[maybe_async]
+    impl RemoteSigner for TestRemoteSigner {
+        fn id(&self) -> signer::SignerId {
+            signer::SignerId::Dummy(42)
+        }
+
+        fn sign_psbt(&self, psbt: &Psbt) -> Result<Psbt, SignerError> {
+            let mut psbt = psbt.clone();
+            if self.tamper {
+                psbt.global.unsigned_tx.lock_time += 1;
+            }
+            self.wallet
+                .sign(&mut psbt, Default::default())
+                .map_err(|e| SignerError::Remote(e.to_string()))?;
+            Ok(psbt)
+        }
+    }
+
+    #[cfg(not(any(target_arch = "wasm32", feature = "async-interface")))]
+    #[test]
+    fn test_sign_with_remote_signers() {
+        let (wallet, _, _) = get_funded_wallet("wsh(multi(2,cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW,03024f83fc3c1abc4faf227df351b5bc319c26ab2e2ec90e549cdb446744c32a48))");
+        let remote_wallet = Wallet::new_offline(
+            "wsh(multi(2,032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3,cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu))",
+            None,
+            Network::Regtest,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+        let remote_signer: Arc<dyn RemoteSigner> = Arc::new(TestRemoteSigner {
+            wallet: remote_wallet,
+            tamper: false,
+        });
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+
+        let mut builder = wallet.build_tx();
+        builder.add_recipient(addr.script_pubkey(), 25_000);
+        let (mut psbt, _) = builder.finish().unwrap();
+        let original_psbt = psbt.clone();
+
+        let report = wallet
+            .sign_with_remote_signers(&mut psbt, &[remote_signer], Default::default())
+            .unwrap();
+        assert!(report.finalized);
+        assert_eq!(
+            report.signed_inputs.get(&signer::SignerId::Dummy(42)),
+            Some(&vec![0])
+        );
+        assert!(psbt.inputs[0].final_script_witness.is_some());
+
+        let tampering_signer: Arc<dyn RemoteSigner> = Arc::new(TestRemoteSigner {
+            wallet: Wallet::new_offline(
+                "wsh(multi(2,032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3,cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu))",
+                None,
+                Network::Regtest,
+                MemoryDatabase::new(),
+            )
+            .unwrap(),
+            tamper: true,
+        });
+        let mut psbt = original_psbt;
+        assert!(matches!(
+            wallet.sign_with_remote_signers(&mut psbt, &[tampering_signer], Default::default()),
+            Err(Error::Signer(SignerError::Remote(_)))
+        ));
+    }
 }
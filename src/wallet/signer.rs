@@ -86,15 +86,15 @@ use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::fmt;
 use std::ops::Bound::Included;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 use bitcoin::blockdata::opcodes;
 use bitcoin::blockdata::script::Builder as ScriptBuilder;
-use bitcoin::hashes::{hash160, Hash};
-use bitcoin::secp256k1::{Message, Secp256k1};
+use bitcoin::hashes::{hash160, sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{self, Message, Secp256k1, Signature};
 use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, Fingerprint};
 use bitcoin::util::{bip143, psbt};
-use bitcoin::{PrivateKey, Script, SigHash, SigHashType};
+use bitcoin::{PrivateKey, PublicKey, Script, SigHash, SigHashType};
 
 use miniscript::descriptor::{DescriptorSecretKey, DescriptorSinglePriv, DescriptorXKey, KeyMap};
 use miniscript::{Legacy, MiniscriptKey, Segwitv0};
@@ -153,6 +153,20 @@ pub enum SignerError {
     /// To enable signing transactions with non-standard sighashes set
     /// [`SignOptions::allow_all_sighashes`] to `true`.
     NonStandardSighash,
+    /// An input uses `SIGHASH_SINGLE` but the transaction doesn't have an output with the same
+    /// index
+    MissingSighashSingleOutput(usize),
+    /// A [`RemoteSigner`] couldn't be reached or refused to sign
+    Remote(String),
+    /// The signature returned by an external signer is not valid for the input
+    InvalidSignature,
+    /// The nonce of the signature returned by an [`AntiExfilDevice`] doesn't match the commitment
+    /// it sent, the device may be trying to leak its private key
+    InvalidNonceCommitment,
+    /// The passphrase returned by the [`KeyProvider`](crate::keys::encrypted::KeyProvider) can't
+    /// decrypt the key
+    #[cfg(feature = "encrypted-keys")]
+    InvalidPassphrase,
 }
 
 impl fmt::Display for SignerError {
@@ -180,6 +194,21 @@ pub trait Signer: fmt::Debug + Send + Sync {
         secp: &SecpCtx,
     ) -> Result<(), SignerError>;
 
+    /// Sign a PSBT following the [`SignOptions`] passed to [`Wallet::sign`](crate::Wallet::sign)
+    ///
+    /// This is what the wallet calls. The default implementation ignores the options and calls
+    /// [`Signer::sign`]: signers that support some of them, like the software signers with
+    /// [`SignOptions::allow_grinding`], can override it.
+    fn sign_with_options(
+        &self,
+        psbt: &mut psbt::PartiallySignedTransaction,
+        input_index: Option<usize>,
+        _sign_options: &SignOptions,
+        secp: &SecpCtx,
+    ) -> Result<(), SignerError> {
+        self.sign(psbt, input_index, secp)
+    }
+
     /// Return whether or not the signer signs the whole transaction in one go instead of every
     /// input individually
     fn sign_whole_tx(&self) -> bool;
@@ -206,6 +235,16 @@ impl Signer for DescriptorXKey<ExtendedPrivKey> {
         psbt: &mut psbt::PartiallySignedTransaction,
         input_index: Option<usize>,
         secp: &SecpCtx,
+    ) -> Result<(), SignerError> {
+        self.sign_with_options(psbt, input_index, &SignOptions::default(), secp)
+    }
+
+    fn sign_with_options(
+        &self,
+        psbt: &mut psbt::PartiallySignedTransaction,
+        input_index: Option<usize>,
+        sign_options: &SignOptions,
+        secp: &SecpCtx,
     ) -> Result<(), SignerError> {
         let input_index = input_index.unwrap();
         if input_index >= psbt.inputs.len() {
@@ -248,7 +287,9 @@ impl Signer for DescriptorXKey<ExtendedPrivKey> {
         if &derived_key.private_key.public_key(secp) != public_key {
             Err(SignerError::InvalidKey)
         } else {
-            derived_key.private_key.sign(psbt, Some(input_index), secp)
+            derived_key
+                .private_key
+                .sign_with_options(psbt, Some(input_index), sign_options, secp)
         }
     }
 
@@ -271,6 +312,16 @@ impl Signer for PrivateKey {
         psbt: &mut psbt::PartiallySignedTransaction,
         input_index: Option<usize>,
         secp: &SecpCtx,
+    ) -> Result<(), SignerError> {
+        self.sign_with_options(psbt, input_index, &SignOptions::default(), secp)
+    }
+
+    fn sign_with_options(
+        &self,
+        psbt: &mut psbt::PartiallySignedTransaction,
+        input_index: Option<usize>,
+        sign_options: &SignOptions,
+        secp: &SecpCtx,
     ) -> Result<(), SignerError> {
         let input_index = input_index.unwrap();
         if input_index >= psbt.inputs.len() || input_index >= psbt.global.unsigned_tx.input.len() {
@@ -297,10 +348,12 @@ impl Signer for PrivateKey {
             None => Legacy::sighash(psbt, input_index)?,
         };
 
-        let signature = secp.sign(
-            &Message::from_slice(&hash.into_inner()[..]).unwrap(),
-            &self.key,
-        );
+        let message = Message::from_slice(&hash.into_inner()[..]).unwrap();
+        let signature = if sign_options.allow_grinding {
+            secp.sign_low_r(&message, &self.key)
+        } else {
+            secp.sign(&message, &self.key)
+        };
 
         let mut final_signature = Vec::with_capacity(75);
         final_signature.extend_from_slice(&signature.serialize_der());
@@ -329,6 +382,237 @@ impl Signer for PrivateKey {
     }
 }
 
+const S2C_DATA_TAG: &[u8] = b"s2c/ecdsa/data";
+const S2C_POINT_TAG: &[u8] = b"s2c/ecdsa/point";
+
+fn tagged_hash(tag: &[u8], data: &[&[u8]]) -> [u8; 32] {
+    let tag_hash = sha256::Hash::hash(tag);
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag_hash[..]);
+    engine.input(&tag_hash[..]);
+    for d in data {
+        engine.input(d);
+    }
+
+    sha256::Hash::from_engine(engine).into_inner()
+}
+
+/// Host side of the ECDSA anti-exfil protocol
+///
+/// A malicious hardware signer could choose the nonces of its signatures to leak its private key
+/// to an attacker watching the blockchain. With the anti-exfil (or "anti-klepto") protocol the
+/// host contributes some randomness to every nonce, and can verify afterwards that the device
+/// actually used it:
+///
+/// 1. the host sends the [`commitment`](AntiExfilHost::commitment) to its random data to the
+///    device;
+/// 2. the device replies with a commitment to its own nonce;
+/// 3. the host reveals its [`host_data`](AntiExfilHost::host_data), and the device signs with its
+///    nonce tweaked by it;
+/// 4. the host [`verify`](AntiExfilHost::verify)es the signature against the device's
+///    commitment.
+///
+/// This follows the `ecdsa_s2c` module of libsecp256k1-zkp, implemented by devices like the
+/// Blockstream Jade.
+#[derive(Debug, Clone)]
+pub struct AntiExfilHost {
+    host_data: [u8; 32],
+}
+
+impl AntiExfilHost {
+    /// Create a host session with fresh random data
+    pub fn new() -> Self {
+        AntiExfilHost {
+            host_data: rand::random(),
+        }
+    }
+
+    /// Create a host session with the given random data
+    pub fn from_host_data(host_data: [u8; 32]) -> Self {
+        AntiExfilHost { host_data }
+    }
+
+    /// Return the random data of the host, to reveal to the device once it has committed to its
+    /// nonce
+    pub fn host_data(&self) -> [u8; 32] {
+        self.host_data
+    }
+
+    /// Return the commitment to the host data, to send to the device first
+    pub fn commitment(&self) -> [u8; 32] {
+        tagged_hash(S2C_DATA_TAG, &[&self.host_data])
+    }
+
+    /// Verify that `signature` is valid for `message` and `public_key`, and that its nonce is the
+    /// one committed to in `signer_commitment` tweaked with the host data
+    pub fn verify(
+        &self,
+        secp: &SecpCtx,
+        message: &Message,
+        public_key: &PublicKey,
+        signer_commitment: &secp256k1::PublicKey,
+        signature: &Signature,
+    ) -> Result<(), SignerError> {
+        secp.verify(message, signature, &public_key.key)
+            .map_err(|_| SignerError::InvalidSignature)?;
+
+        let tweak = tagged_hash(
+            S2C_POINT_TAG,
+            &[&signer_commitment.serialize(), &self.host_data],
+        );
+        let mut nonce = *signer_commitment;
+        nonce
+            .add_exp_assign(secp, &tweak)
+            .map_err(|_| SignerError::InvalidNonceCommitment)?;
+
+        // `r` is the x coordinate of the nonce reduced modulo the curve order, the two only differ
+        // with a negligible probability
+        if nonce.serialize()[1..] != signature.serialize_compact()[..32] {
+            return Err(SignerError::InvalidNonceCommitment);
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for AntiExfilHost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Trait for hardware signers that implement the ECDSA anti-exfil protocol
+///
+/// Wrap the device in an [`AntiExfilSigner`] to add it to a [`Wallet`](crate::Wallet): the
+/// signer drives the protocol for every input, and refuses signatures that don't match the
+/// device's commitment. See [`AntiExfilHost`] for the details.
+pub trait AntiExfilDevice: fmt::Debug + Send + Sync {
+    /// Return the [`SignerId`] for this device
+    fn id(&self, secp: &SecpCtx) -> SignerId;
+
+    /// Start signing an input, given the commitment to the host data
+    ///
+    /// Return the key that will sign the input and the device's commitment to its nonce (the
+    /// nonce point before the host tweak), or `None` if the device can't sign this input.
+    fn commit_nonce(
+        &self,
+        psbt: &psbt::PartiallySignedTransaction,
+        input_index: usize,
+        host_commitment: &[u8; 32],
+    ) -> Result<Option<(PublicKey, secp256k1::PublicKey)>, SignerError>;
+
+    /// Sign the input with the nonce committed to in [`AntiExfilDevice::commit_nonce`], tweaked
+    /// with the host data
+    fn sign_with_host_data(
+        &self,
+        psbt: &psbt::PartiallySignedTransaction,
+        input_index: usize,
+        host_data: &[u8; 32],
+    ) -> Result<Signature, SignerError>;
+}
+
+/// [`Signer`] for devices that implement the anti-exfil protocol
+#[derive(Debug)]
+pub struct AntiExfilSigner<T: AntiExfilDevice>(pub T);
+
+impl<T: AntiExfilDevice> Signer for AntiExfilSigner<T> {
+    fn sign(
+        &self,
+        psbt: &mut psbt::PartiallySignedTransaction,
+        input_index: Option<usize>,
+        secp: &SecpCtx,
+    ) -> Result<(), SignerError> {
+        let input_index = input_index.unwrap();
+        if input_index >= psbt.inputs.len() || input_index >= psbt.global.unsigned_tx.input.len() {
+            return Err(SignerError::InputIndexOutOfRange);
+        }
+
+        if psbt.inputs[input_index].final_script_sig.is_some()
+            || psbt.inputs[input_index].final_script_witness.is_some()
+        {
+            return Ok(());
+        }
+
+        let host = AntiExfilHost::new();
+        let (pubkey, signer_commitment) =
+            match self.0.commit_nonce(psbt, input_index, &host.commitment())? {
+                Some(commitment) => commitment,
+                None => return Ok(()),
+            };
+        if psbt.inputs[input_index].partial_sigs.contains_key(&pubkey) {
+            return Ok(());
+        }
+
+        // same logic as the software signers to pick the sighash algorithm
+        let (hash, sighash) = match psbt.inputs[input_index].witness_utxo {
+            Some(_) => Segwitv0::sighash(psbt, input_index)?,
+            None => Legacy::sighash(psbt, input_index)?,
+        };
+
+        let signature = self
+            .0
+            .sign_with_host_data(psbt, input_index, &host.host_data())?;
+        host.verify(
+            secp,
+            &Message::from_slice(&hash.into_inner()[..]).unwrap(),
+            &pubkey,
+            &signer_commitment,
+            &signature,
+        )?;
+
+        let mut final_signature = Vec::with_capacity(75);
+        final_signature.extend_from_slice(&signature.serialize_der());
+        final_signature.push(sighash.as_u32() as u8);
+
+        psbt.inputs[input_index]
+            .partial_sigs
+            .insert(pubkey, final_signature);
+
+        Ok(())
+    }
+
+    fn sign_whole_tx(&self) -> bool {
+        false
+    }
+
+    fn id(&self, secp: &SecpCtx) -> SignerId {
+        self.0.id(secp)
+    }
+}
+
+/// Trait for signers that live outside of the process, like signing servers, HSMs or MPC
+/// services
+///
+/// Unlike a [`Signer`], a remote signer receives the whole PSBT and returns a signed copy of it,
+/// which the wallet then merges into its own. See
+/// [`Wallet::sign_with_remote_signers`](crate::Wallet::sign_with_remote_signers).
+///
+/// When the `async-interface` feature is enabled (or when targeting `wasm32`) the methods of this
+/// trait are `async`, so that network requests don't block the runtime.
+#[maybe_async]
+pub trait RemoteSigner: fmt::Debug {
+    /// Return the [`SignerId`] for this signer
+    fn id(&self) -> SignerId;
+
+    /// Sign a PSBT, returning a copy of it with the signatures added
+    ///
+    /// The unsigned transaction of the returned PSBT must be the same as the original one.
+    fn sign_psbt(
+        &self,
+        psbt: &psbt::PartiallySignedTransaction,
+    ) -> Result<psbt::PartiallySignedTransaction, SignerError>;
+}
+
+/// Inputs signed by the remote signers in
+/// [`Wallet::sign_with_remote_signers`](crate::Wallet::sign_with_remote_signers)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemoteSigningReport {
+    /// Indexes of the inputs that received new signatures, for every remote signer
+    pub signed_inputs: BTreeMap<SignerId, Vec<usize>>,
+    /// Whether the PSBT has been finalized
+    pub finalized: bool,
+}
+
 /// Defines the order in which signers are called
 ///
 /// The default value is `100`. Signers with an ordering above that will be called later,
@@ -432,6 +716,40 @@ impl SignersContainer {
         self.0.values().collect()
     }
 
+    /// Replace every signer that holds a secret key with an
+    /// [`EncryptedSigner`](crate::keys::encrypted::EncryptedSigner) for the same key, encrypted
+    /// with `passphrase`
+    ///
+    /// Returns the encrypted keys, which should be stored to load the signers back later.
+    #[cfg(feature = "encrypted-keys")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "encrypted-keys")))]
+    pub fn encrypt(
+        &mut self,
+        passphrase: &str,
+        provider: Arc<dyn crate::keys::encrypted::KeyProvider>,
+        secp: &SecpCtx,
+    ) -> Result<
+        Vec<crate::keys::encrypted::EncryptedDescriptorSecretKey>,
+        crate::keys::encrypted::EncryptionError,
+    > {
+        use crate::keys::encrypted::{EncryptedDescriptorSecretKey, EncryptedSigner};
+
+        let mut encrypted_keys = Vec::new();
+        for signer in self.0.values_mut() {
+            if let Some(secret_key) = signer.descriptor_secret_key() {
+                let encrypted =
+                    EncryptedDescriptorSecretKey::encrypt(&secret_key, passphrase, secp)?;
+                *signer = Arc::new(EncryptedSigner::new(
+                    encrypted.clone(),
+                    Arc::clone(&provider),
+                ));
+                encrypted_keys.push(encrypted);
+            }
+        }
+
+        Ok(encrypted_keys)
+    }
+
     /// Finds the signer with lowest ordering for a given id in the container.
     pub fn find(&self, id: SignerId) -> Option<&Arc<dyn Signer>> {
         self.0
@@ -445,6 +763,74 @@ impl SignersContainer {
     }
 }
 
+/// Registry of signers keyed by the origin of the keys they control
+///
+/// Unlike the [`SignersContainer`], where signers are called in the order given by their
+/// [`SignerOrdering`] and try to sign every input, signers in the registry are registered for a
+/// key origin, i.e. the fingerprint of a master key and a derivation path (usually the one of an
+/// account, like `m/84'/0'/0'`), and are only asked to sign the inputs that contain a key derived
+/// from that origin in their `bip32_derivation`.
+///
+/// Signers can be registered and unregistered through a shared reference, which allows adding or
+/// removing hardware devices at runtime to a watch-only [`Wallet`](crate::Wallet), see
+/// [`Wallet::register_signer`](crate::Wallet::register_signer).
+#[derive(Debug, Default)]
+pub struct SignerRegistry(RwLock<BTreeMap<(Fingerprint, DerivationPath), Arc<dyn Signer>>>);
+
+impl SignerRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        SignerRegistry::default()
+    }
+
+    /// Register a signer for the keys derived from `origin`. Optionally returns the signer that
+    /// was previously registered for the same origin, if any
+    pub fn register(
+        &self,
+        origin: (Fingerprint, DerivationPath),
+        signer: Arc<dyn Signer>,
+    ) -> Option<Arc<dyn Signer>> {
+        self.0.write().unwrap().insert(origin, signer)
+    }
+
+    /// Remove the signer registered for `origin` and return it
+    pub fn unregister(&self, origin: &(Fingerprint, DerivationPath)) -> Option<Arc<dyn Signer>> {
+        self.0.write().unwrap().remove(origin)
+    }
+
+    /// Return the origins of all the registered signers
+    pub fn origins(&self) -> Vec<(Fingerprint, DerivationPath)> {
+        self.0.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Whether the registry is empty
+    pub fn is_empty(&self) -> bool {
+        self.0.read().unwrap().is_empty()
+    }
+
+    /// Return the registered signers that can sign `psbt_input`, with the key they would sign
+    /// with
+    ///
+    /// A signer matches a key of the input if the key's fingerprint is the one of the signer's
+    /// origin, and the signer's derivation path is a prefix of the key's one.
+    pub fn signers_for_input(&self, psbt_input: &psbt::Input) -> Vec<(PublicKey, Arc<dyn Signer>)> {
+        let registry = self.0.read().unwrap();
+        psbt_input
+            .bip32_derivation
+            .iter()
+            .filter_map(|(pk, (fingerprint, path))| {
+                registry
+                    .iter()
+                    .find(|((origin_fingerprint, origin_path), _)| {
+                        origin_fingerprint == fingerprint
+                            && path.as_ref().starts_with(origin_path.as_ref())
+                    })
+                    .map(|(_, signer)| (*pk, Arc::clone(signer)))
+            })
+            .collect()
+    }
+}
+
 /// Options for a software signer
 ///
 /// Adjust the behavior of our software signers and the way a transaction is finalized
@@ -477,6 +863,28 @@ pub struct SignOptions {
     ///
     /// Defaults to `false` which will only allow signing using `SIGHASH_ALL`.
     pub allow_all_sighashes: bool,
+
+    /// Sighash types to set on specific inputs, by index, before signing
+    ///
+    /// The sighashes set here override the ones in the PSBT, and are allowed even if
+    /// [`allow_all_sighashes`](Self::allow_all_sighashes) is `false`.
+    ///
+    /// Defaults to an empty map.
+    pub input_sighashes: BTreeMap<usize, SigHashType>,
+
+    /// Whether the software signers should grind the ECDSA nonces to produce signatures with a
+    /// low `r` value
+    ///
+    /// Low-R signatures are always at most 70 bytes long (DER encoded, without the sighash byte)
+    /// instead of 71, which saves one byte for about half of the signatures. By default the
+    /// [`TxBuilder`](crate::TxBuilder) estimates the fees assuming low-R signatures, so if this is
+    /// disabled, or if the transaction is signed by signers that don't grind their nonces, the
+    /// transaction should be built with
+    /// [`TxBuilder::allow_grinding(false)`](crate::TxBuilder::allow_grinding) to avoid paying a
+    /// fee rate slightly lower than the target.
+    ///
+    /// Defaults to `true`.
+    pub allow_grinding: bool,
 }
 
 impl Default for SignOptions {
@@ -485,7 +893,26 @@ impl Default for SignOptions {
             trust_witness_utxo: false,
             assume_height: None,
             allow_all_sighashes: false,
+            input_sighashes: BTreeMap::new(),
+            allow_grinding: true,
+        }
+    }
+}
+
+// Check that `sighash` can be used to sign the input `input_index` of `tx`: `SIGHASH_SINGLE`
+// requires an output with the same index, otherwise the signature would commit to nothing
+pub(crate) fn check_sighash(
+    tx: &bitcoin::Transaction,
+    input_index: usize,
+    sighash: SigHashType,
+) -> Result<(), SignerError> {
+    match sighash {
+        SigHashType::Single | SigHashType::SinglePlusAnyoneCanPay
+            if input_index >= tx.output.len() =>
+        {
+            Err(SignerError::MissingSighashSingleOutput(input_index))
         }
+        _ => Ok(()),
     }
 }
 
@@ -764,3 +1191,176 @@ mod signers_container_tests {
         (prvkey, pubkey, fingerprint)
     }
 }
+
+#[cfg(test)]
+mod anti_exfil_tests {
+    use super::*;
+    use crate::wallet::test::get_funded_wallet;
+    use crate::wallet::AddressIndex;
+    use crate::KeychainKind;
+    use bitcoin::secp256k1::SecretKey;
+    use std::str::FromStr;
+    use std::sync::Mutex;
+
+    const N_MINUS_2: [u8; 32] = [
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xfe, 0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36,
+        0x41, 0x3f,
+    ];
+
+    // modular inverse as `k^(n-2)`, since secp256k1 doesn't expose it
+    fn invert(k: &SecretKey) -> SecretKey {
+        let mut one = [0u8; 32];
+        one[31] = 1;
+        let mut result = SecretKey::from_slice(&one).unwrap();
+        for byte in N_MINUS_2.iter() {
+            for bit in (0..8).rev() {
+                let square = result;
+                result.mul_assign(&square[..]).unwrap();
+                if (byte >> bit) & 1 == 1 {
+                    result.mul_assign(&k[..]).unwrap();
+                }
+            }
+        }
+        result
+    }
+
+    // device that implements the signer side of the protocol with raw ECDSA
+    #[derive(Debug)]
+    struct TestDevice {
+        key: PrivateKey,
+        honest: bool,
+        nonce: Mutex<Option<SecretKey>>,
+    }
+
+    impl AntiExfilDevice for TestDevice {
+        fn id(&self, secp: &SecpCtx) -> SignerId {
+            SignerId::from(self.key.public_key(secp).to_pubkeyhash())
+        }
+
+        fn commit_nonce(
+            &self,
+            _psbt: &psbt::PartiallySignedTransaction,
+            input_index: usize,
+            host_commitment: &[u8; 32],
+        ) -> Result<Option<(PublicKey, secp256k1::PublicKey)>, SignerError> {
+            let secp = Secp256k1::new();
+            let nonce = tagged_hash(
+                b"test/nonce",
+                &[&self.key.key[..], host_commitment, &[input_index as u8]],
+            );
+            let nonce = SecretKey::from_slice(&nonce).unwrap();
+            *self.nonce.lock().unwrap() = Some(nonce);
+
+            Ok(Some((
+                self.key.public_key(&secp),
+                secp256k1::PublicKey::from_secret_key(&secp, &nonce),
+            )))
+        }
+
+        fn sign_with_host_data(
+            &self,
+            psbt: &psbt::PartiallySignedTransaction,
+            input_index: usize,
+            host_data: &[u8; 32],
+        ) -> Result<Signature, SignerError> {
+            let secp = Secp256k1::new();
+            let mut k = self.nonce.lock().unwrap().take().unwrap();
+            if self.honest {
+                let commitment = secp256k1::PublicKey::from_secret_key(&secp, &k);
+                let tweak = tagged_hash(S2C_POINT_TAG, &[&commitment.serialize(), host_data]);
+                k.add_assign(&tweak).unwrap();
+            }
+
+            let (hash, _) = Segwitv0::sighash(psbt, input_index)?;
+            let r = secp256k1::PublicKey::from_secret_key(&secp, &k).serialize();
+            let mut s = self.key.key;
+            s.mul_assign(&r[1..]).unwrap();
+            s.add_assign(&hash.into_inner()).unwrap();
+            s.mul_assign(&invert(&k)[..]).unwrap();
+
+            let mut signature = Signature::from_compact(&[&r[1..], &s[..]].concat()).unwrap();
+            signature.normalize_s();
+            Ok(signature)
+        }
+    }
+
+    #[test]
+    fn test_anti_exfil_host_commitment() {
+        let host = AntiExfilHost::from_host_data([0x42; 32]);
+        assert_eq!(host.host_data(), [0x42; 32]);
+        assert_eq!(
+            host.commitment(),
+            tagged_hash(b"s2c/ecdsa/data", &[&[0x42u8; 32]])
+        );
+        assert_ne!(
+            AntiExfilHost::new().commitment(),
+            AntiExfilHost::new().commitment()
+        );
+    }
+
+    #[test]
+    fn test_anti_exfil_signer() {
+        let key =
+            PrivateKey::from_wif("cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW").unwrap();
+        let build_psbt = |honest: bool| {
+            let (mut wallet, _, _) = get_funded_wallet(
+                "wpkh(032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3)",
+            );
+            wallet.add_signer(
+                KeychainKind::External,
+                SignerOrdering::default(),
+                Arc::new(AntiExfilSigner(TestDevice {
+                    key,
+                    honest,
+                    nonce: Mutex::new(None),
+                })),
+            );
+            let addr = wallet.get_address(AddressIndex::New).unwrap();
+            let mut builder = wallet.build_tx();
+            builder.drain_wallet().drain_to(addr.script_pubkey());
+            let (psbt, _) = builder.finish().unwrap();
+            (wallet, psbt)
+        };
+
+        let (wallet, mut psbt) = build_psbt(true);
+        assert!(wallet.sign(&mut psbt, Default::default()).unwrap());
+
+        let (wallet, mut psbt) = build_psbt(false);
+        assert!(matches!(
+            wallet.sign(&mut psbt, Default::default()),
+            Err(crate::Error::Signer(SignerError::InvalidNonceCommitment))
+        ));
+        assert!(psbt.inputs[0].partial_sigs.is_empty());
+
+        // a wrong signature is caught before the nonce check
+        let host = AntiExfilHost::new();
+        let secp = Secp256k1::new();
+        let message = Message::from_slice(&[0x01; 32]).unwrap();
+        let signature = secp.sign(&message, &key.key);
+        let commitment = secp256k1::PublicKey::from_str(
+            "03024f83fc3c1abc4faf227df351b5bc319c26ab2e2ec90e549cdb446744c32a48",
+        )
+        .unwrap();
+        assert_eq!(
+            host.verify(
+                &secp,
+                &Message::from_slice(&[0x02; 32]).unwrap(),
+                &key.public_key(&secp),
+                &commitment,
+                &signature
+            ),
+            Err(SignerError::InvalidSignature)
+        );
+        assert_eq!(
+            host.verify(
+                &secp,
+                &message,
+                &key.public_key(&secp),
+                &commitment,
+                &signature
+            ),
+            Err(SignerError::InvalidNonceCommitment)
+        );
+    }
+}
@@ -0,0 +1,1132 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! BIP-0352 silent payments
+//!
+//! A [silent payment address](SilentPaymentAddress) is a static address, defined in [BIP-352],
+//! that can be reused without linking the payments it receives. Every payment goes to a different
+//! taproot output, obtained by tweaking the spend key of the recipient with a secret shared
+//! between the inputs of the transaction and the scan key of the recipient.
+//!
+//! Silent payments are added to a transaction with
+//! [`TxBuilder::add_silent_payment_recipient`](crate::wallet::tx_builder::TxBuilder::add_silent_payment_recipient).
+//! Since the outputs depend on the inputs, they are only derived once the coins have been
+//! selected, and the wallet needs the private key of every P2PKH, P2WPKH and P2SH-P2WPKH input.
+//! Transactions spending taproot outputs are not supported.
+//!
+//! ```no_run
+//! # use std::str::FromStr;
+//! # use bitcoin::*;
+//! # use bdk::database::*;
+//! # use bdk::wallet::silent_payments::SilentPaymentAddress;
+//! # use bdk::*;
+//! # let descriptor = "wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/84'/1'/0'/0/*)";
+//! # let wallet = Wallet::new_offline(descriptor, None, Network::Testnet, MemoryDatabase::default())?;
+//! let address = SilentPaymentAddress::from_str("tsp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc3wk4yh")?;
+//!
+//! let mut builder = wallet.build_tx();
+//! builder.add_silent_payment_recipient(&address, 50_000)?;
+//! let (psbt, details) = builder.finish()?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! A [`SilentPaymentScanner`] finds the payments received by an address in a transaction, given
+//! its tweak data, which is computed from the outputs it spends with [`compute_tweak`] or fetched
+//! from a BIP-352 index. Backends implementing [`SilentPaymentSource`] serve the tweak data of the
+//! transactions of a block.
+//!
+//! The outputs found are not added to the wallet: they are taproot outputs, which the wallet
+//! can't sign for. They can be spent by a taproot signer with the private key returned by
+//! [`SilentPaymentOutput::secret_key`].
+//!
+//! [BIP-352]: https://github.com/bitcoin/bips/blob/master/bip-0352.mediawiki
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::blockdata::script::{Builder, Instruction};
+use bitcoin::consensus::encode::serialize;
+use bitcoin::hashes::{hash160, sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{self, PublicKey, SecretKey};
+use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey};
+use bitcoin::util::psbt::PartiallySignedTransaction as Psbt;
+use bitcoin::{Network, OutPoint, Script, Transaction, TxIn, TxOut};
+
+use crate::database::BatchDatabase;
+use crate::psbt::PsbtUtils;
+use crate::wallet::utils::SecpCtx;
+use crate::wallet::Wallet;
+use crate::Error;
+
+const INPUTS_TAG: &str = "BIP0352/Inputs";
+const SHARED_SECRET_TAG: &str = "BIP0352/SharedSecret";
+// x coordinate of the point `H` of BIP-341, used as internal key when the key path is unspendable
+const NUMS_INTERNAL_KEY: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+// Silent payment addresses don't have the 90 characters limit of segwit addresses
+const MAX_ADDRESS_LEN: usize = 1023;
+const KEYS_LEN: usize = 66;
+
+/// Errors related to silent payments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SilentPaymentError {
+    /// The silent payment address couldn't be parsed
+    InvalidAddress(String),
+    /// The address belongs to this network, which is not the network of the wallet
+    InvalidNetwork(Network),
+    /// The input at this index spends a taproot output, or an output whose type is unknown
+    UnsupportedInput(usize),
+    /// The wallet doesn't have the private key of the input at this index
+    MissingInputKey(usize),
+    /// None of the inputs of the transaction can be used to derive the outputs
+    NoEligibleInputs,
+    /// The private keys of the inputs add up to zero, or a tweak is out of range
+    InvalidSharedSecret,
+    /// Secp256k1 error
+    Secp256k1(secp256k1::Error),
+}
+
+impl fmt::Display for SilentPaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SilentPaymentError::InvalidAddress(e) => {
+                write!(f, "Invalid silent payment address: {}", e)
+            }
+            SilentPaymentError::InvalidNetwork(network) => {
+                write!(
+                    f,
+                    "The silent payment address is for the {} network",
+                    network
+                )
+            }
+            SilentPaymentError::UnsupportedInput(index) => write!(
+                f,
+                "The type of input {} is not supported by silent payments",
+                index
+            ),
+            SilentPaymentError::MissingInputKey(index) => {
+                write!(f, "Missing the private key of input {}", index)
+            }
+            SilentPaymentError::NoEligibleInputs => {
+                write!(
+                    f,
+                    "No input can be used to derive the silent payment outputs"
+                )
+            }
+            SilentPaymentError::InvalidSharedSecret => write!(f, "Invalid shared secret"),
+            SilentPaymentError::Secp256k1(e) => write!(f, "Secp256k1 error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SilentPaymentError {}
+
+impl From<secp256k1::Error> for SilentPaymentError {
+    fn from(e: secp256k1::Error) -> Self {
+        SilentPaymentError::Secp256k1(e)
+    }
+}
+
+/// A version 0 silent payment address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SilentPaymentAddress {
+    network: Network,
+    scan_key: PublicKey,
+    spend_key: PublicKey,
+}
+
+impl SilentPaymentAddress {
+    /// Create a new address from the scan and spend public keys of the recipient
+    pub fn new(network: Network, scan_key: PublicKey, spend_key: PublicKey) -> Self {
+        SilentPaymentAddress {
+            network,
+            scan_key,
+            spend_key,
+        }
+    }
+
+    /// Return the network of the address
+    ///
+    /// Addresses parsed with the `tsp` prefix, shared by testnet and signet, return
+    /// [`Network::Testnet`].
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Return the public key used to find the payments to this address
+    pub fn scan_key(&self) -> PublicKey {
+        self.scan_key
+    }
+
+    /// Return the public key tweaked by the sender to derive the outputs of the payments
+    pub fn spend_key(&self) -> PublicKey {
+        self.spend_key
+    }
+
+    /// Return whether the address can be used on `network`
+    pub fn is_valid_for_network(&self, network: Network) -> bool {
+        address_hrp(self.network) == address_hrp(network)
+    }
+}
+
+impl fmt::Display for SilentPaymentAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut keys = Vec::with_capacity(KEYS_LEN);
+        keys.extend_from_slice(&self.scan_key.serialize());
+        keys.extend_from_slice(&self.spend_key.serialize());
+
+        let mut data = vec![0];
+        data.extend(convert_bits(&keys, 8, 5, true).expect("Padding is enabled"));
+        write!(f, "{}", bech32m_encode(address_hrp(self.network), &data))
+    }
+}
+
+impl FromStr for SilentPaymentAddress {
+    type Err = SilentPaymentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hrp, data) = bech32m_decode(s)?;
+        let network = match hrp.as_str() {
+            "sp" => Network::Bitcoin,
+            "tsp" => Network::Testnet,
+            "sprt" => Network::Regtest,
+            _ => {
+                return Err(SilentPaymentError::InvalidAddress(format!(
+                    "Unknown prefix `{}`",
+                    hrp
+                )))
+            }
+        };
+
+        let (version, data) = data
+            .split_first()
+            .ok_or_else(|| SilentPaymentError::InvalidAddress("Missing version".to_string()))?;
+        let keys = convert_bits(data, 5, 8, false)?;
+        // Future versions may append data to the keys, which must be ignored
+        match (*version, keys.len()) {
+            (0, KEYS_LEN) => {}
+            (1..=30, len) if len >= KEYS_LEN => {}
+            (31, _) => {
+                return Err(SilentPaymentError::InvalidAddress(
+                    "Unsupported version 31".to_string(),
+                ))
+            }
+            _ => {
+                return Err(SilentPaymentError::InvalidAddress(format!(
+                    "Invalid data length {}",
+                    keys.len()
+                )))
+            }
+        }
+
+        let invalid_key = |e: secp256k1::Error| SilentPaymentError::InvalidAddress(e.to_string());
+        Ok(SilentPaymentAddress {
+            network,
+            scan_key: PublicKey::from_slice(&keys[..33]).map_err(invalid_key)?,
+            spend_key: PublicKey::from_slice(&keys[33..KEYS_LEN]).map_err(invalid_key)?,
+        })
+    }
+}
+
+fn address_hrp(network: Network) -> &'static str {
+    match network {
+        Network::Bitcoin => "sp",
+        Network::Testnet | Network::Signet => "tsp",
+        Network::Regtest => "sprt",
+    }
+}
+
+fn bech32m_polymod(values: &[u8]) -> u32 {
+    const GENERATORS: [u32; 5] = [
+        0x3b6a_57b2,
+        0x2650_8e6d,
+        0x1ea1_19fa,
+        0x3d42_33dd,
+        0x2a14_62b3,
+    ];
+
+    values.iter().fold(1, |checksum, value| {
+        let top = checksum >> 25;
+        let checksum = ((checksum & 0x01ff_ffff) << 5) ^ u32::from(*value);
+        GENERATORS
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| (top >> i) & 1 == 1)
+            .fold(checksum, |checksum, (_, generator)| checksum ^ generator)
+    })
+}
+
+fn bech32m_hrp_expand(hrp: &str) -> Vec<u8> {
+    hrp.bytes()
+        .map(|b| b >> 5)
+        .chain(std::iter::once(0))
+        .chain(hrp.bytes().map(|b| b & 0x1f))
+        .collect()
+}
+
+// Encode the 5-bit values of `data`
+fn bech32m_encode(hrp: &str, data: &[u8]) -> String {
+    let mut values = bech32m_hrp_expand(hrp);
+    values.extend_from_slice(data);
+    values.extend_from_slice(&[0; 6]);
+    let checksum = bech32m_polymod(&values) ^ BECH32M_CONST;
+
+    let checksum = (0..6).map(|i| ((checksum >> (5 * (5 - i))) & 0x1f) as u8);
+    let data: String = data
+        .iter()
+        .copied()
+        .chain(checksum)
+        .map(|value| BECH32_CHARSET[value as usize] as char)
+        .collect();
+    format!("{}1{}", hrp, data)
+}
+
+// Decode a string into its human readable part and 5-bit values, without the checksum
+fn bech32m_decode(s: &str) -> Result<(String, Vec<u8>), SilentPaymentError> {
+    let invalid = |msg: &str| SilentPaymentError::InvalidAddress(msg.to_string());
+
+    if s.len() > MAX_ADDRESS_LEN {
+        return Err(invalid("Too long"));
+    }
+    if s.chars().any(|c| c.is_ascii_lowercase()) && s.chars().any(|c| c.is_ascii_uppercase()) {
+        return Err(invalid("Mixed case"));
+    }
+    let s = s.to_ascii_lowercase();
+
+    let (hrp, data) = match s.rfind('1') {
+        Some(pos) if pos > 0 && s.len() - pos > 6 => (&s[..pos], &s[pos + 1..]),
+        _ => return Err(invalid("Missing separator")),
+    };
+    let data = data
+        .bytes()
+        .map(|c| {
+            BECH32_CHARSET
+                .iter()
+                .position(|&x| x == c)
+                .map(|pos| pos as u8)
+                .ok_or_else(|| invalid("Invalid character"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut values = bech32m_hrp_expand(hrp);
+    values.extend_from_slice(&data);
+    if bech32m_polymod(&values) != BECH32M_CONST {
+        return Err(invalid("Invalid checksum"));
+    }
+
+    Ok((hrp.to_string(), data[..data.len() - 6].to_vec()))
+}
+
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Result<Vec<u8>, SilentPaymentError> {
+    let mut acc = 0u32;
+    let mut bits = 0u32;
+    let mut result = Vec::with_capacity(data.len() * from as usize / to as usize + 1);
+    let max = (1 << to) - 1;
+
+    for value in data {
+        acc = (acc << from) | u32::from(*value);
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            result.push(((acc >> bits) & max) as u8);
+        }
+    }
+
+    if pad {
+        if bits > 0 {
+            result.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || (acc << (to - bits)) & max != 0 {
+        return Err(SilentPaymentError::InvalidAddress(
+            "Invalid padding".to_string(),
+        ));
+    }
+
+    Ok(result)
+}
+
+fn tagged_hash(tag: &str, data: &[&[u8]]) -> sha256::Hash {
+    let tag = sha256::Hash::hash(tag.as_bytes());
+
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag[..]);
+    engine.input(&tag[..]);
+    for data in data {
+        engine.input(data);
+    }
+    sha256::Hash::from_engine(engine)
+}
+
+// P2TR output spendable with the key path of `key`
+fn p2tr_script(key: &PublicKey) -> Script {
+    Builder::new()
+        .push_int(1)
+        .push_slice(&key.serialize()[1..])
+        .into_script()
+}
+
+// Whether `script` is a P2TR output
+fn is_p2tr(script: &Script) -> bool {
+    script.is_witness_program() && script.len() == 34 && script.as_bytes()[0] == 0x51
+}
+
+// Lowest outpoint spent by `tx`, in its serialized form
+fn smallest_outpoint(tx: &Transaction) -> Option<OutPoint> {
+    tx.input
+        .iter()
+        .map(|txin| txin.previous_output)
+        .min_by_key(serialize)
+}
+
+// Hash committing to the inputs of a transaction, given the sum of the public keys of its
+// eligible inputs
+fn input_hash(smallest_outpoint: &OutPoint, input_public_key: &PublicKey) -> sha256::Hash {
+    tagged_hash(
+        INPUTS_TAG,
+        &[&serialize(smallest_outpoint), &input_public_key.serialize()],
+    )
+}
+
+// Tweak added to the spend key for the `counter`-th payment with the same shared secret
+fn output_tweak(shared_secret: &PublicKey, counter: u32) -> sha256::Hash {
+    tagged_hash(
+        SHARED_SECRET_TAG,
+        &[&shared_secret.serialize(), &counter.to_be_bytes()],
+    )
+}
+
+// Placeholder for the output of the `index`-th silent payment of a transaction, with the same
+// size as the P2TR output that replaces it
+pub(crate) fn output_placeholder(index: usize) -> Script {
+    let mut key = [0u8; 32];
+    key[28..].copy_from_slice(&(index as u32).to_be_bytes());
+
+    Builder::new().push_int(1).push_slice(&key).into_script()
+}
+
+// Derive the output script of the payment to each recipient, in order
+//
+// `input_keys` are the private keys of the inputs that take part in the shared secret and
+// `smallest_outpoint` is the lowest outpoint of the transaction, in its serialized form
+fn derive_output_scripts(
+    recipients: &[SilentPaymentAddress],
+    input_keys: &[SecretKey],
+    smallest_outpoint: &OutPoint,
+    secp: &SecpCtx,
+) -> Result<Vec<Script>, SilentPaymentError> {
+    let (first, rest) = input_keys
+        .split_first()
+        .ok_or(SilentPaymentError::NoEligibleInputs)?;
+    let mut input_key = *first;
+    for key in rest {
+        input_key
+            .add_assign(&key[..])
+            .map_err(|_| SilentPaymentError::InvalidSharedSecret)?;
+    }
+
+    let input_hash = input_hash(
+        smallest_outpoint,
+        &PublicKey::from_secret_key(secp, &input_key),
+    );
+    let mut tweak = input_key;
+    tweak
+        .mul_assign(&input_hash[..])
+        .map_err(|_| SilentPaymentError::InvalidSharedSecret)?;
+
+    // Payments to the same scan key are told apart by a counter
+    let mut counters: HashMap<PublicKey, u32> = HashMap::new();
+    recipients
+        .iter()
+        .map(|recipient| {
+            let mut shared_secret = recipient.scan_key;
+            shared_secret.mul_assign(secp, &tweak[..])?;
+
+            let counter = counters.entry(recipient.scan_key).or_insert(0);
+            let output_tweak = output_tweak(&shared_secret, *counter);
+            *counter += 1;
+
+            let mut output_key = recipient.spend_key;
+            output_key
+                .add_exp_assign(secp, &output_tweak[..])
+                .map_err(|_| SilentPaymentError::InvalidSharedSecret)?;
+            Ok(p2tr_script(&output_key))
+        })
+        .collect()
+}
+
+// Public key of the input spending `script_pubkey`, if it takes part in the shared secret
+fn input_public_key(txin: &TxIn, script_pubkey: &Script) -> Option<PublicKey> {
+    if script_pubkey.is_p2pkh() {
+        // The key is pushed by the script_sig, and must match the hash in the script_pubkey
+        txin.script_sig
+            .instructions()
+            .filter_map(|instruction| match instruction {
+                Ok(Instruction::PushBytes(bytes)) if bytes.len() == 33 => Some(bytes),
+                _ => None,
+            })
+            .filter(|bytes| hash160::Hash::hash(bytes)[..] == script_pubkey.as_bytes()[3..23])
+            .last()
+            .and_then(|bytes| PublicKey::from_slice(bytes).ok())
+    } else if script_pubkey.is_v0_p2wpkh() || script_pubkey.is_p2sh() {
+        if script_pubkey.is_p2sh() {
+            let redeem_script = match txin.script_sig.instructions().collect::<Vec<_>>()[..] {
+                [Ok(Instruction::PushBytes(bytes))] => Script::from(bytes.to_vec()),
+                _ => return None,
+            };
+            if !redeem_script.is_v0_p2wpkh() {
+                return None;
+            }
+        }
+
+        match &txin.witness[..] {
+            [_, key] if key.len() == 33 => PublicKey::from_slice(key).ok(),
+            _ => None,
+        }
+    } else if is_p2tr(script_pubkey) {
+        let mut witness = &txin.witness[..];
+        if witness.len() > 1 && witness.last().map(|annex| annex.first()) == Some(Some(&0x50)) {
+            witness = &witness[..witness.len() - 1];
+        }
+        // Script path spends with an unspendable internal key are skipped
+        if witness.len() > 1 {
+            let control_block = witness.last()?;
+            if control_block.len() >= 33 && control_block[1..33] == NUMS_INTERNAL_KEY {
+                return None;
+            }
+        }
+
+        let mut key = [0x02; 33];
+        key[1..].copy_from_slice(&script_pubkey.as_bytes()[2..]);
+        PublicKey::from_slice(&key).ok()
+    } else {
+        None
+    }
+}
+
+/// Compute the tweak data of `tx`, given the outputs spent by each of its inputs
+///
+/// The tweak data is the sum of the public keys of the eligible inputs, multiplied by the hash of
+/// the inputs. It's all a receiver needs to scan a transaction, and it's what BIP-352 indexers
+/// serve to light clients. Returns `None` if the transaction can't contain silent payments.
+pub fn compute_tweak(tx: &Transaction, prevouts: &[TxOut], secp: &SecpCtx) -> Option<PublicKey> {
+    if tx.is_coin_base()
+        || tx.input.len() != prevouts.len()
+        || !tx
+            .output
+            .iter()
+            .any(|output| is_p2tr(&output.script_pubkey))
+    {
+        return None;
+    }
+
+    let mut keys = Vec::new();
+    for (txin, prevout) in tx.input.iter().zip(prevouts) {
+        let script_pubkey = &prevout.script_pubkey;
+        // Transactions spending future witness versions are ignored
+        if script_pubkey.is_witness_program() && script_pubkey.as_bytes()[0] > 0x51 {
+            return None;
+        }
+        keys.extend(input_public_key(txin, script_pubkey));
+    }
+    if keys.is_empty() {
+        return None;
+    }
+
+    let input_public_key = PublicKey::combine_keys(&keys.iter().collect::<Vec<_>>()).ok()?;
+    let input_hash = input_hash(&smallest_outpoint(tx)?, &input_public_key);
+    let mut tweak = input_public_key;
+    tweak.mul_assign(secp, &input_hash[..]).ok()?;
+    Some(tweak)
+}
+
+/// An output received with silent payments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SilentPaymentOutput {
+    /// Outpoint of the output
+    pub outpoint: OutPoint,
+    /// The output
+    pub txout: TxOut,
+    /// Tweak added to the spend key
+    pub tweak: SecretKey,
+}
+
+impl SilentPaymentOutput {
+    /// Return the private key that spends the output with the key path, given the spend key
+    pub fn secret_key(&self, spend_key: &SecretKey) -> Result<SecretKey, SilentPaymentError> {
+        let mut key = *spend_key;
+        key.add_assign(&self.tweak[..])
+            .map_err(|_| SilentPaymentError::InvalidSharedSecret)?;
+        Ok(key)
+    }
+}
+
+/// Finds the payments received by a silent payment address
+///
+/// Scanning only requires the private scan key, so it can be delegated to a device that can't
+/// spend the funds.
+#[derive(Debug, Clone)]
+pub struct SilentPaymentScanner {
+    scan_key: SecretKey,
+    spend_key: PublicKey,
+}
+
+impl SilentPaymentScanner {
+    /// Create a scanner from the private scan key and the public spend key of an address
+    pub fn new(scan_key: SecretKey, spend_key: PublicKey) -> Self {
+        SilentPaymentScanner {
+            scan_key,
+            spend_key,
+        }
+    }
+
+    /// Create the scanner of an account, with the keys derived from `master` as specified by
+    /// BIP-352
+    ///
+    /// The scan key is derived at `m/352'/coin_type'/account'/1'/0` and the spend key at
+    /// `m/352'/coin_type'/account'/0'/0`.
+    pub fn from_master(
+        master: &ExtendedPrivKey,
+        account: u32,
+        secp: &SecpCtx,
+    ) -> Result<Self, Error> {
+        let coin_type = match master.network {
+            Network::Bitcoin => 0,
+            _ => 1,
+        };
+        let derive = |branch: u32| -> Result<ExtendedPrivKey, Error> {
+            let path: DerivationPath = vec![
+                ChildNumber::from_hardened_idx(352)?,
+                ChildNumber::from_hardened_idx(coin_type)?,
+                ChildNumber::from_hardened_idx(account)?,
+                ChildNumber::from_hardened_idx(branch)?,
+                ChildNumber::from_normal_idx(0)?,
+            ]
+            .into();
+            Ok(master.derive_priv(secp, &path)?)
+        };
+
+        Ok(SilentPaymentScanner {
+            scan_key: derive(1)?.private_key.key,
+            spend_key: derive(0)?.private_key.public_key(secp).key,
+        })
+    }
+
+    /// Return the silent payment address of the scanner
+    pub fn address(&self, network: Network, secp: &SecpCtx) -> SilentPaymentAddress {
+        SilentPaymentAddress::new(
+            network,
+            PublicKey::from_secret_key(secp, &self.scan_key),
+            self.spend_key,
+        )
+    }
+
+    /// Find the payments in `tx`, given the outputs spent by each of its inputs
+    pub fn scan_transaction(
+        &self,
+        tx: &Transaction,
+        prevouts: &[TxOut],
+        secp: &SecpCtx,
+    ) -> Result<Vec<SilentPaymentOutput>, SilentPaymentError> {
+        match compute_tweak(tx, prevouts, secp) {
+            Some(tweak) => self.scan_with_tweak(tx, &tweak, secp),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Find the payments in `tx`, given its tweak data
+    ///
+    /// See [`compute_tweak`].
+    pub fn scan_with_tweak(
+        &self,
+        tx: &Transaction,
+        tweak: &PublicKey,
+        secp: &SecpCtx,
+    ) -> Result<Vec<SilentPaymentOutput>, SilentPaymentError> {
+        let mut shared_secret = *tweak;
+        shared_secret
+            .mul_assign(secp, &self.scan_key[..])
+            .map_err(|_| SilentPaymentError::InvalidSharedSecret)?;
+
+        // Payments are numbered from zero, so the first missing one ends the search
+        let txid = tx.txid();
+        let mut found = Vec::new();
+        for counter in 0.. {
+            let tweak = SecretKey::from_slice(&output_tweak(&shared_secret, counter)[..])
+                .map_err(|_| SilentPaymentError::InvalidSharedSecret)?;
+            let mut output_key = self.spend_key;
+            output_key
+                .add_exp_assign(secp, &tweak[..])
+                .map_err(|_| SilentPaymentError::InvalidSharedSecret)?;
+            let script_pubkey = p2tr_script(&output_key);
+
+            match tx
+                .output
+                .iter()
+                .enumerate()
+                .find(|(_, output)| output.script_pubkey == script_pubkey)
+            {
+                Some((vout, txout)) => found.push(SilentPaymentOutput {
+                    outpoint: OutPoint::new(txid, vout as u32),
+                    txout: txout.clone(),
+                    tweak,
+                }),
+                None => break,
+            }
+        }
+
+        Ok(found)
+    }
+}
+
+/// The transactions of a block that may contain silent payments
+#[derive(Debug, Clone)]
+pub struct BlockTweaks {
+    /// Height of the block
+    pub height: u32,
+    /// Timestamp of the block
+    pub timestamp: u64,
+    /// Transactions of the block with their tweak data
+    ///
+    /// Transactions without taproot outputs, or without eligible inputs, can be left out.
+    pub transactions: Vec<(Transaction, PublicKey)>,
+}
+
+/// Trait for the blockchain backends that can provide the tweak data of silent payments
+///
+/// Backends can either fetch the tweak data from a BIP-352 index, or compute it from full blocks
+/// and the outputs they spend with [`compute_tweak`].
+#[maybe_async]
+pub trait SilentPaymentSource {
+    /// Return the transactions of the block at `height` that may contain silent payments
+    fn get_block_tweaks(&self, height: u32) -> Result<BlockTweaks, Error>;
+}
+
+impl<B, D> Wallet<B, D>
+where
+    D: BatchDatabase,
+{
+    // Replace the placeholders added by `TxBuilder::add_silent_payment_recipient` with the
+    // outputs derived from the inputs of `psbt`
+    pub(crate) fn fill_silent_payment_outputs(
+        &self,
+        mut psbt: Psbt,
+        recipients: &[SilentPaymentAddress],
+    ) -> Result<Psbt, Error> {
+        let mut input_keys = Vec::new();
+        for (index, input) in psbt.inputs.iter().enumerate() {
+            let script_pubkey = psbt
+                .get_utxo_for(index)
+                .ok_or(SilentPaymentError::UnsupportedInput(index))?
+                .script_pubkey;
+
+            let eligible = if script_pubkey.is_p2pkh() || script_pubkey.is_v0_p2wpkh() {
+                true
+            } else if script_pubkey.is_p2sh() {
+                input
+                    .redeem_script
+                    .as_ref()
+                    .ok_or(SilentPaymentError::UnsupportedInput(index))?
+                    .is_v0_p2wpkh()
+            } else if script_pubkey.is_witness_program() {
+                // Taproot inputs are eligible, and newer witness versions make the transaction
+                // invalid for silent payments
+                if script_pubkey.as_bytes()[0] != 0 {
+                    return Err(SilentPaymentError::UnsupportedInput(index).into());
+                }
+                false
+            } else {
+                false
+            };
+            if !eligible {
+                continue;
+            }
+
+            let key = self
+                .psbt_input_secret_key(&psbt, index)
+                .ok_or(SilentPaymentError::MissingInputKey(index))?;
+            // Uncompressed keys are ignored by the receivers
+            if key.compressed {
+                input_keys.push(key.key);
+            }
+        }
+
+        let smallest_outpoint =
+            smallest_outpoint(&psbt.global.unsigned_tx).expect("The TxBuilder always adds inputs");
+
+        // Recipients whose placeholder was removed with `TxBuilder::set_recipients` are skipped,
+        // so that the counters of the following ones stay contiguous
+        let outputs = &psbt.global.unsigned_tx.output;
+        let (placeholders, recipients): (Vec<_>, Vec<_>) = recipients
+            .iter()
+            .enumerate()
+            .map(|(index, recipient)| (output_placeholder(index), *recipient))
+            .filter(|(placeholder, _)| {
+                outputs
+                    .iter()
+                    .any(|output| &output.script_pubkey == placeholder)
+            })
+            .unzip();
+
+        let scripts =
+            derive_output_scripts(&recipients, &input_keys, &smallest_outpoint, &self.secp)?;
+        for (placeholder, script) in placeholders.into_iter().zip(scripts) {
+            for output in psbt.global.unsigned_tx.output.iter_mut() {
+                if output.script_pubkey == placeholder {
+                    output.script_pubkey = script.clone();
+                }
+            }
+        }
+
+        Ok(psbt)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::hashes::hex::{FromHex, ToHex};
+    use bitcoin::secp256k1::Secp256k1;
+    use bitcoin::{PrivateKey, Txid};
+
+    use super::*;
+    use crate::wallet::test::{get_funded_wallet, get_test_wpkh};
+    use crate::SignOptions;
+
+    const ADDRESS: &str = "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv";
+    const SCAN_KEY: &str = "0f694e068028a717f8af6b9411f9a133dd3565258714cc226594b34db90c1f2c";
+    const SPEND_KEY: &str = "9d6ad855ce3417ef84e836892e5a56392bfba05fa5d97ccea30e266f540e08b3";
+
+    fn secret_key(hex: &str) -> SecretKey {
+        SecretKey::from_slice(&Vec::<u8>::from_hex(hex).unwrap()).unwrap()
+    }
+
+    fn scanner() -> SilentPaymentScanner {
+        let secp = Secp256k1::new();
+        SilentPaymentScanner::new(
+            secret_key(SCAN_KEY),
+            PublicKey::from_secret_key(&secp, &secret_key(SPEND_KEY)),
+        )
+    }
+
+    // Signed transaction paying `amount` to the silent payment address of `scanner()`, with the
+    // outputs spent by its inputs
+    fn silent_payment_tx(amount: u64) -> (Transaction, Vec<TxOut>) {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let mut builder = wallet.build_tx();
+        builder
+            .add_silent_payment_recipient(&regtest_address(), amount)
+            .unwrap();
+        let (mut psbt, _) = builder.finish().unwrap();
+        assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+
+        let prevouts = psbt
+            .inputs
+            .iter()
+            .map(|input| input.witness_utxo.clone().unwrap())
+            .collect();
+        (psbt.extract_tx(), prevouts)
+    }
+
+    fn regtest_address() -> SilentPaymentAddress {
+        let address = SilentPaymentAddress::from_str(ADDRESS).unwrap();
+        SilentPaymentAddress::new(Network::Regtest, address.scan_key(), address.spend_key())
+    }
+
+    #[test]
+    fn test_silent_payment_address() {
+        let address = SilentPaymentAddress::from_str(ADDRESS).unwrap();
+        assert_eq!(address.network(), Network::Bitcoin);
+        assert_eq!(
+            address.scan_key().to_string(),
+            "0220bcfac5b99e04ad1a06ddfb016ee13582609d60b6291e98d01a9bc9a16c96d4"
+        );
+        assert_eq!(
+            address.spend_key().to_string(),
+            "025cc9856d6f8375350e123978daac200c260cb5b5ae83106cab90484dcd8fcf36"
+        );
+        assert_eq!(address.to_string(), ADDRESS);
+        assert_eq!(
+            SilentPaymentAddress::from_str(&ADDRESS.to_uppercase()).unwrap(),
+            address
+        );
+
+        let testnet =
+            SilentPaymentAddress::new(Network::Signet, address.scan_key(), address.spend_key());
+        assert!(testnet.to_string().starts_with("tsp1q"));
+        assert!(testnet.is_valid_for_network(Network::Testnet));
+        assert!(!testnet.is_valid_for_network(Network::Bitcoin));
+        assert_eq!(
+            SilentPaymentAddress::from_str(&testnet.to_string())
+                .unwrap()
+                .network(),
+            Network::Testnet
+        );
+    }
+
+    #[test]
+    fn test_invalid_silent_payment_address() {
+        let mut corrupted = ADDRESS.to_string();
+        corrupted.replace_range(10..11, "x");
+        assert!(matches!(
+            SilentPaymentAddress::from_str(&corrupted),
+            Err(SilentPaymentError::InvalidAddress(_))
+        ));
+
+        let keys = Vec::<u8>::from_hex("0220bcfac5b99e04ad1a06ddfb016ee13582609d60b6291e98d01a9bc9a16c96d4025cc9856d6f8375350e123978daac200c260cb5b5ae83106cab90484dcd8fcf36").unwrap();
+        let encode = |version: u8, keys: &[u8]| {
+            let mut data = vec![version];
+            data.extend(convert_bits(keys, 8, 5, true).unwrap());
+            bech32m_encode("sp", &data)
+        };
+
+        // Version 0 addresses must only contain the two keys
+        let mut extended = keys.clone();
+        extended.extend_from_slice(&[0x00; 4]);
+        assert!(SilentPaymentAddress::from_str(&encode(0, &extended)).is_err());
+        // Newer versions can append data
+        assert_eq!(
+            SilentPaymentAddress::from_str(&encode(1, &extended)).unwrap(),
+            SilentPaymentAddress::from_str(ADDRESS).unwrap()
+        );
+        assert!(SilentPaymentAddress::from_str(&encode(31, &keys)).is_err());
+    }
+
+    #[test]
+    fn test_derive_output_scripts() {
+        let secp = Secp256k1::new();
+        let address = SilentPaymentAddress::from_str(ADDRESS).unwrap();
+        let input_keys = [
+            secret_key("eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1"),
+            secret_key("93f5ed907ad5b2bdbbdcb5d9116ebc0a4e1f92f910d5260237fa45a9408aad16"),
+        ];
+        let outpoint = OutPoint::new(
+            Txid::from_str("a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d")
+                .unwrap(),
+            0,
+        );
+
+        let scripts = derive_output_scripts(&[address], &input_keys, &outpoint, &secp).unwrap();
+        assert_eq!(
+            scripts[0].as_bytes()[2..].to_hex(),
+            "3e9fce73d4e77a4809908e3c3a2e54ee147b9312dc5044a193d1fc85de46e3c1"
+        );
+        assert!(scripts[0].is_witness_program());
+
+        // The second payment to the same address uses the next counter
+        let scripts =
+            derive_output_scripts(&[address, address], &input_keys, &outpoint, &secp).unwrap();
+        assert_eq!(
+            scripts[0].as_bytes()[2..].to_hex(),
+            "3e9fce73d4e77a4809908e3c3a2e54ee147b9312dc5044a193d1fc85de46e3c1"
+        );
+        assert_eq!(
+            scripts[1].as_bytes()[2..].to_hex(),
+            "0ffe0b3d72d66b785e1a7ad416edcc22b951293b1507aa04850e890b002c60f1"
+        );
+
+        assert_eq!(
+            derive_output_scripts(&[address], &[], &outpoint, &secp),
+            Err(SilentPaymentError::NoEligibleInputs)
+        );
+    }
+
+    #[test]
+    fn test_silent_payment_tx_builder() {
+        let secp = Secp256k1::new();
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let address = regtest_address();
+
+        let mut builder = wallet.build_tx();
+        builder
+            .add_silent_payment_recipient(&address, 25_000)
+            .unwrap();
+        let (psbt, details) = builder.finish().unwrap();
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(details.txid, tx.txid());
+
+        // Find the payment the way the receiver does, from the public key of the input
+        let input_public_key =
+            PrivateKey::from_wif("cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW")
+                .unwrap()
+                .public_key(&secp)
+                .key;
+        let input_hash = tagged_hash(
+            INPUTS_TAG,
+            &[
+                &serialize(&tx.input[0].previous_output),
+                &input_public_key.serialize(),
+            ],
+        );
+        let mut shared_secret = input_public_key;
+        shared_secret.mul_assign(&secp, &input_hash[..]).unwrap();
+        shared_secret
+            .mul_assign(&secp, &secret_key(SCAN_KEY)[..])
+            .unwrap();
+        let output_tweak = tagged_hash(
+            SHARED_SECRET_TAG,
+            &[&shared_secret.serialize(), &0u32.to_be_bytes()],
+        );
+        let mut output_key = address.spend_key();
+        output_key.add_exp_assign(&secp, &output_tweak[..]).unwrap();
+
+        let output = tx
+            .output
+            .iter()
+            .find(|output| output.script_pubkey == p2tr_script(&output_key))
+            .unwrap();
+        assert_eq!(output.value, 25_000);
+        assert!(!tx
+            .output
+            .iter()
+            .any(|output| output.script_pubkey == output_placeholder(0)));
+    }
+
+    #[test]
+    fn test_silent_payment_bip69_ordering() {
+        use crate::wallet::tx_builder::TxOrdering;
+        use bitcoin::Address;
+
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), 10_000)
+            .ordering(TxOrdering::Bip69Lexicographic);
+        builder
+            .add_silent_payment_recipient(&regtest_address(), 10_000)
+            .unwrap();
+        let (psbt, _) = builder.finish().unwrap();
+
+        // The outputs are sorted with the derived script, not with the placeholder
+        let outputs = &psbt.global.unsigned_tx.output;
+        assert_eq!(outputs.len(), 3);
+        assert_eq!(psbt.outputs.len(), 3);
+        assert!(!outputs
+            .iter()
+            .any(|output| output.script_pubkey == output_placeholder(0)));
+        assert!(outputs.windows(2).all(|pair| {
+            (pair[0].value, &pair[0].script_pubkey) <= (pair[1].value, &pair[1].script_pubkey)
+        }));
+    }
+
+    #[test]
+    fn test_silent_payment_missing_input_key() {
+        let (wallet, _, _) = get_funded_wallet("wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)");
+
+        let mut builder = wallet.build_tx();
+        builder
+            .add_silent_payment_recipient(&regtest_address(), 25_000)
+            .unwrap();
+        assert!(matches!(
+            builder.finish(),
+            Err(Error::SilentPayment(SilentPaymentError::MissingInputKey(0)))
+        ));
+    }
+
+    #[test]
+    fn test_silent_payment_invalid_network() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let address = SilentPaymentAddress::from_str(ADDRESS).unwrap();
+
+        assert!(matches!(
+            wallet
+                .build_tx()
+                .add_silent_payment_recipient(&address, 25_000),
+            Err(Error::SilentPayment(SilentPaymentError::InvalidNetwork(
+                Network::Bitcoin
+            )))
+        ));
+    }
+
+    #[test]
+    fn test_silent_payment_scanner() {
+        let secp = Secp256k1::new();
+        let scanner = scanner();
+        assert_eq!(scanner.address(Network::Regtest, &secp), regtest_address());
+
+        let (tx, prevouts) = silent_payment_tx(25_000);
+        let found = scanner.scan_transaction(&tx, &prevouts, &secp).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].txout.value, 25_000);
+        assert_eq!(tx.output[found[0].outpoint.vout as usize], found[0].txout);
+
+        // The tweaked spend key controls the output
+        let output_key = found[0].secret_key(&secret_key(SPEND_KEY)).unwrap();
+        assert_eq!(
+            found[0].txout.script_pubkey,
+            p2tr_script(&PublicKey::from_secret_key(&secp, &output_key))
+        );
+
+        // Other scan keys don't find anything
+        let other = SilentPaymentScanner::new(
+            secret_key(SPEND_KEY),
+            PublicKey::from_secret_key(&secp, &secret_key(SPEND_KEY)),
+        );
+        assert!(other
+            .scan_transaction(&tx, &prevouts, &secp)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_compute_tweak() {
+        let secp = Secp256k1::new();
+        let (tx, prevouts) = silent_payment_tx(25_000);
+
+        let tweak = compute_tweak(&tx, &prevouts, &secp).unwrap();
+        assert_eq!(
+            scanner().scan_with_tweak(&tx, &tweak, &secp).unwrap(),
+            scanner().scan_transaction(&tx, &prevouts, &secp).unwrap()
+        );
+
+        // Inputs spending future witness versions exclude the transaction
+        let mut future_prevouts = prevouts.clone();
+        future_prevouts[0].script_pubkey = Builder::new()
+            .push_int(2)
+            .push_slice(&[0x42; 32])
+            .into_script();
+        assert_eq!(compute_tweak(&tx, &future_prevouts, &secp), None);
+        assert_eq!(compute_tweak(&tx, &prevouts[1..], &secp), None);
+    }
+
+    #[test]
+    fn test_scanner_from_master() {
+        let secp = Secp256k1::new();
+        let master = ExtendedPrivKey::from_str("tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS").unwrap();
+        let scanner = SilentPaymentScanner::from_master(&master, 0, &secp).unwrap();
+
+        let scan_key = master
+            .derive_priv(
+                &secp,
+                &DerivationPath::from_str("m/352'/1'/0'/1'/0").unwrap(),
+            )
+            .unwrap();
+        let spend_key = master
+            .derive_priv(
+                &secp,
+                &DerivationPath::from_str("m/352'/1'/0'/0'/0").unwrap(),
+            )
+            .unwrap();
+        assert_eq!(
+            scanner.address(Network::Testnet, &secp),
+            SilentPaymentAddress::new(
+                Network::Testnet,
+                scan_key.private_key.public_key(&secp).key,
+                spend_key.private_key.public_key(&secp).key
+            )
+        );
+    }
+}
@@ -36,20 +36,29 @@
 //! # Ok::<(), bdk::Error>(())
 //! ```
 
-use std::collections::BTreeMap;
-use std::collections::HashSet;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::default::Default;
+use std::fmt;
 use std::marker::PhantomData;
+use std::sync::Arc;
 
 use bitcoin::util::psbt::{self, PartiallySignedTransaction as Psbt};
-use bitcoin::{OutPoint, Script, SigHashType, Transaction};
+use bitcoin::{Address, OutPoint, Script, SigHashType, Transaction, TxIn, TxOut};
 
 use miniscript::descriptor::DescriptorTrait;
 
+#[cfg(feature = "bip47")]
+use super::bip47::{notification_placeholder, PaymentCode, NOTIFICATION_AMOUNT};
 use super::coin_selection::{CoinSelectionAlgorithm, DefaultCoinSelectionAlgorithm};
+#[cfg(feature = "silent-payments")]
+use super::silent_payments::{output_placeholder, SilentPaymentAddress, SilentPaymentError};
+use crate::descriptor::derived::AsDerived;
+use crate::descriptor::ExtendedDescriptor;
+use crate::psbt::ProprietaryKey;
 use crate::{database::BatchDatabase, Error, Utxo, Wallet};
 use crate::{
-    types::{FeeRate, KeychainKind, LocalUtxo, WeightedUtxo},
+    types::{FeeEstimates, FeeRate, KeychainKind, LocalUtxo, WeightedUtxo},
     TransactionDetails,
 };
 /// Context in which the [`TxBuilder`] is valid
@@ -134,22 +143,43 @@ pub(crate) struct TxParams {
     pub(crate) recipients: Vec<(Script, u64)>,
     pub(crate) drain_wallet: bool,
     pub(crate) drain_to: Option<Script>,
+    pub(crate) drain_to_multiple: Vec<(Script, u32)>,
+    pub(crate) consolidate: Option<usize>,
+    pub(crate) ephemeral_anchor: bool,
+    pub(crate) data: Vec<Script>,
+    pub(crate) allow_nonstandard_data: bool,
     pub(crate) fee_policy: Option<FeePolicy>,
+    pub(crate) long_term_fee_rate: Option<FeeRate>,
+    pub(crate) max_absolute_fee: Option<u64>,
+    pub(crate) max_fee_rate: Option<FeeRate>,
+    pub(crate) disable_grinding: bool,
     pub(crate) internal_policy_path: Option<BTreeMap<String, Vec<usize>>>,
     pub(crate) external_policy_path: Option<BTreeMap<String, Vec<usize>>>,
     pub(crate) utxos: Vec<WeightedUtxo>,
     pub(crate) unspendable: HashSet<OutPoint>,
+    pub(crate) min_confirmations: u32,
+    pub(crate) exclude_low_feerate_ancestors: bool,
     pub(crate) manually_selected_only: bool,
     pub(crate) sighash: Option<SigHashType>,
+    pub(crate) input_sighashes: HashMap<OutPoint, SigHashType>,
+    pub(crate) proprietary_global: Vec<(ProprietaryKey, Vec<u8>)>,
+    pub(crate) proprietary_inputs: Vec<(OutPoint, ProprietaryKey, Vec<u8>)>,
+    pub(crate) proprietary_outputs: Vec<(Script, ProprietaryKey, Vec<u8>)>,
     pub(crate) ordering: TxOrdering,
     pub(crate) locktime: Option<u32>,
+    pub(crate) disable_anti_fee_sniping: bool,
     pub(crate) rbf: Option<RbfValue>,
     pub(crate) version: Option<Version>,
     pub(crate) change_policy: ChangeSpendPolicy,
     pub(crate) only_witness_utxo: bool,
+    pub(crate) require_non_witness_utxo: bool,
     pub(crate) add_global_xpubs: bool,
     pub(crate) include_output_redeem_witness_script: bool,
     pub(crate) bumping_fee: Option<PreviousFee>,
+    #[cfg(feature = "bip47")]
+    pub(crate) bip47_notification: Option<(PaymentCode, PaymentCode)>,
+    #[cfg(feature = "silent-payments")]
+    pub(crate) silent_payments: Vec<SilentPaymentAddress>,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -162,6 +192,8 @@ pub(crate) struct PreviousFee {
 pub(crate) enum FeePolicy {
     FeeRate(FeeRate),
     FeeAmount(u64),
+    // set from empty fee estimates
+    Unavailable,
 }
 
 impl std::default::Default for FeePolicy {
@@ -191,12 +223,75 @@ impl<'a, B, D: BatchDatabase, Cs: CoinSelectionAlgorithm<D>, Ctx: TxBuilderConte
         self
     }
 
+    /// Set the fee rate from a set of [`FeeEstimates`], to confirm the transaction within `target`
+    /// blocks
+    ///
+    /// See [`FeeEstimates::get`] for the fee rate used when there's no estimate for exactly
+    /// `target`. If `estimates` is empty, creating the transaction fails with
+    /// [`Error::FeeRateUnavailable`].
+    pub fn fee_estimate(&mut self, estimates: &FeeEstimates, target: usize) -> &mut Self {
+        self.params.fee_policy = Some(match estimates.get(target) {
+            Some(fee_rate) => FeePolicy::FeeRate(fee_rate),
+            None => FeePolicy::Unavailable,
+        });
+        self
+    }
+
     /// Set an absolute fee
     pub fn fee_absolute(&mut self, fee_amount: u64) -> &mut Self {
         self.params.fee_policy = Some(FeePolicy::FeeAmount(fee_amount));
         self
     }
 
+    /// Fail with [`Error::FeeTooHigh`] if the fee of the transaction is higher than `max`
+    /// satoshis
+    ///
+    /// This protects against bugs in the fee estimation or unit mistakes, which would otherwise
+    /// silently result in an overpaying transaction.
+    pub fn max_absolute_fee(&mut self, max: u64) -> &mut Self {
+        self.params.max_absolute_fee = Some(max);
+        self
+    }
+
+    /// Fail with [`Error::FeeRateTooHigh`] if the fee rate of the transaction is higher than `max`
+    ///
+    /// The fee rate is computed on the estimated size of the signed transaction, so this also
+    /// catches excess value that would be added to the fee when the change is below the dust
+    /// limit.
+    pub fn max_fee_rate(&mut self, max: FeeRate) -> &mut Self {
+        self.params.max_fee_rate = Some(max);
+        self
+    }
+
+    /// Set the fee rate the utxos are expected to be spent at in the long term
+    ///
+    /// This is passed to the coin selection algorithm: with the default
+    /// [`BranchAndBoundCoinSelection`](crate::wallet::coin_selection::BranchAndBoundCoinSelection)
+    /// the candidate selections are compared using their
+    /// [waste](crate::wallet::coin_selection::calculate_waste), so fewer inputs are spent when
+    /// `fee_rate` is higher than the long-term fee rate, and more are consolidated when it's lower.
+    pub fn long_term_fee_rate(&mut self, long_term_fee_rate: FeeRate) -> &mut Self {
+        self.params.long_term_fee_rate = Some(long_term_fee_rate);
+        self
+    }
+
+    /// Set whether the wallet's utxos will be signed with low-R signatures
+    ///
+    /// The fees are estimated assuming that the signatures of the wallet's utxos are one byte
+    /// shorter than the largest ECDSA signatures, which is the case when signing with the
+    /// software signers and [`SignOptions::allow_grinding`] enabled, the default. If the
+    /// transaction is going to be signed without grinding, for example by a hardware signer, this
+    /// should be set to `false` so that the fee rate doesn't end up slightly below the target.
+    /// The foreign utxos always assume the largest signatures.
+    ///
+    /// Defaults to `true`.
+    ///
+    /// [`SignOptions::allow_grinding`]: crate::SignOptions::allow_grinding
+    pub fn allow_grinding(&mut self, allow_grinding: bool) -> &mut Self {
+        self.params.disable_grinding = !allow_grinding;
+        self
+    }
+
     /// Set the policy path to use while creating the transaction for a given keychain.
     ///
     /// This method accepts a map where the key is the policy node id (see
@@ -380,6 +475,48 @@ impl<'a, B, D: BatchDatabase, Cs: CoinSelectionAlgorithm<D>, Ctx: TxBuilderConte
         Ok(self)
     }
 
+    /// Add a foreign UTXO, computing its satisfaction weight from the `descriptor` that owns it
+    ///
+    /// This works like [`add_foreign_utxo`], but instead of trusting a satisfaction weight
+    /// provided by the caller it checks that the `script_pubkey` of the UTXO matches the one of
+    /// `descriptor` derived at `derivation_index`, and then uses the [`max_satisfaction_weight`]
+    /// of the descriptor.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the errors returned by [`add_foreign_utxo`], this method fails if the
+    /// `script_pubkey` of the UTXO doesn't match the descriptor.
+    ///
+    /// [`add_foreign_utxo`]: Self::add_foreign_utxo
+    /// [`max_satisfaction_weight`]: miniscript::Descriptor::max_satisfaction_weight
+    pub fn add_foreign_utxo_with_descriptor(
+        &mut self,
+        outpoint: OutPoint,
+        psbt_input: psbt::Input,
+        descriptor: &ExtendedDescriptor,
+        derivation_index: u32,
+    ) -> Result<&mut Self, Error> {
+        let script_pubkey = match (&psbt_input.witness_utxo, &psbt_input.non_witness_utxo) {
+            (Some(txout), _) => Some(&txout.script_pubkey),
+            (None, Some(tx)) => tx
+                .output
+                .get(outpoint.vout as usize)
+                .map(|txout| &txout.script_pubkey),
+            (None, None) => None,
+        };
+        if let Some(script_pubkey) = script_pubkey {
+            let derived = descriptor.as_derived(derivation_index, &self.wallet.secp);
+            if &derived.script_pubkey() != script_pubkey {
+                return Err(Error::Generic(
+                    "Foreign utxo script_pubkey does not match the descriptor".into(),
+                ));
+            }
+        }
+
+        let satisfaction_weight = descriptor.max_satisfaction_weight()?;
+        self.add_foreign_utxo(outpoint, psbt_input, satisfaction_weight)
+    }
+
     /// Only spend utxos added by [`add_utxo`].
     ///
     /// The wallet will **not** add additional utxos to the transaction even if they are needed to
@@ -400,6 +537,35 @@ impl<'a, B, D: BatchDatabase, Cs: CoinSelectionAlgorithm<D>, Ctx: TxBuilderConte
         self
     }
 
+    /// Only spend utxos that have at least `confirmations` confirmations
+    ///
+    /// The depth of the utxos is computed against the current height of the chain known by the
+    /// wallet, see [`Wallet::current_height`], which is refreshed on every [`Wallet::sync`]. If
+    /// the wallet doesn't know the current height, confirmed utxos are assumed to have
+    /// exactly one confirmation, so any value greater than `1` excludes every utxo.
+    ///
+    /// Utxos added manually with [`add_utxo`](Self::add_utxo) are not filtered.
+    pub fn min_confirmations(&mut self, confirmations: u32) -> &mut Self {
+        self.params.min_confirmations = confirmations;
+        self
+    }
+
+    /// Only spend confirmed utxos, equivalent to [`min_confirmations(1)`](Self::min_confirmations)
+    pub fn exclude_unconfirmed(&mut self) -> &mut Self {
+        self.min_confirmations(1)
+    }
+
+    /// Don't spend unconfirmed utxos whose ancestors pay less than the target fee rate
+    ///
+    /// By default these utxos can be spent, and the transaction pays the fees missing from the
+    /// unconfirmed ancestors so that the whole package reaches the target fee rate.
+    ///
+    /// Utxos added manually with [`add_utxo`](Self::add_utxo) are not filtered.
+    pub fn exclude_low_feerate_ancestors(&mut self) -> &mut Self {
+        self.params.exclude_low_feerate_ancestors = true;
+        self
+    }
+
     /// Add a utxo to the internal list of unspendable utxos
     ///
     /// It's important to note that the "must-be-spent" utxos added with [`TxBuilder::add_utxo`]
@@ -417,6 +583,68 @@ impl<'a, B, D: BatchDatabase, Cs: CoinSelectionAlgorithm<D>, Ctx: TxBuilderConte
         self
     }
 
+    /// Sign a specific input with a specific sig hash, overriding the one set with
+    /// [`sighash`](Self::sighash)
+    ///
+    /// This can be used for protocols like crowdfunding (`SIGHASH_ALL | SIGHASH_ANYONECANPAY`) or
+    /// collaborative transactions (`SIGHASH_SINGLE`). Every sighash type can be used with the
+    /// descriptors supported by this library, but [`finish`](Self::finish) fails if `outpoint` is
+    /// not spent by the transaction or if `SIGHASH_SINGLE` is used on an input that doesn't have
+    /// an output with the same index, after the transaction has been sorted.
+    ///
+    /// Since the signers refuse to sign with non-`SIGHASH_ALL` sighashes by default, the same
+    /// sighashes should be set in [`SignOptions::input_sighashes`] or
+    /// [`SignOptions::allow_all_sighashes`] should be enabled.
+    ///
+    /// **Use this option very carefully**
+    ///
+    /// [`SignOptions::input_sighashes`]: crate::SignOptions::input_sighashes
+    /// [`SignOptions::allow_all_sighashes`]: crate::SignOptions::allow_all_sighashes
+    pub fn input_sighash(&mut self, outpoint: OutPoint, sighash: SigHashType) -> &mut Self {
+        self.params.input_sighashes.insert(outpoint, sighash);
+        self
+    }
+
+    /// Add a proprietary field to the global map of the PSBT
+    ///
+    /// Proprietary fields let custom protocols, like co-signing services or hardware wallet
+    /// extensions, carry their own metadata in the PSBT. They are preserved by the signers and
+    /// by [`Wallet::combine_psbts`].
+    pub fn add_proprietary_global(&mut self, key: ProprietaryKey, value: Vec<u8>) -> &mut Self {
+        self.params.proprietary_global.push((key, value));
+        self
+    }
+
+    /// Add a proprietary field to the PSBT input spending `outpoint`
+    ///
+    /// [`finish`](Self::finish) fails if `outpoint` is not spent by the transaction. See
+    /// [`add_proprietary_global`](Self::add_proprietary_global) for more details.
+    pub fn add_proprietary_input(
+        &mut self,
+        outpoint: OutPoint,
+        key: ProprietaryKey,
+        value: Vec<u8>,
+    ) -> &mut Self {
+        self.params.proprietary_inputs.push((outpoint, key, value));
+        self
+    }
+
+    /// Add a proprietary field to every PSBT output paying to `script_pubkey`
+    ///
+    /// [`finish`](Self::finish) fails if no output pays to `script_pubkey`. See
+    /// [`add_proprietary_global`](Self::add_proprietary_global) for more details.
+    pub fn add_proprietary_output(
+        &mut self,
+        script_pubkey: Script,
+        key: ProprietaryKey,
+        value: Vec<u8>,
+    ) -> &mut Self {
+        self.params
+            .proprietary_outputs
+            .push((script_pubkey, key, value));
+        self
+    }
+
     /// Choose the ordering for inputs and outputs of the transaction
     pub fn ordering(&mut self, ordering: TxOrdering) -> &mut Self {
         self.params.ordering = ordering;
@@ -431,10 +659,25 @@ impl<'a, B, D: BatchDatabase, Cs: CoinSelectionAlgorithm<D>, Ctx: TxBuilderConte
         self
     }
 
+    /// Don't set the nLockTime to the current height by default
+    ///
+    /// When the wallet knows the current height of the chain and no specific nLockTime is set
+    /// with [`nlocktime`](Self::nlocktime), the transaction is locked to the current height (or,
+    /// occasionally, to a slightly lower height) to discourage fee sniping, like Bitcoin Core
+    /// does. This option disables that behavior, so the nLockTime is `0` unless the wallet's
+    /// descriptors require a specific value.
+    pub fn disable_anti_fee_sniping(&mut self) -> &mut Self {
+        self.params.disable_anti_fee_sniping = true;
+        self
+    }
+
     /// Build a transaction with a specific version
     ///
     /// The `version` should always be greater than `0` and greater than `1` if the wallet's
     /// descriptors contain an "older" (OP_CSV) operator.
+    ///
+    /// Version `3` builds a TRUC transaction (see BIP-431): [`finish`](Self::finish) fails with
+    /// [`Error::TrucViolation`] if the transaction doesn't respect their topology restrictions.
     pub fn version(&mut self, version: i32) -> &mut Self {
         self.params.version = Some(Version(version));
         self
@@ -475,6 +718,20 @@ impl<'a, B, D: BatchDatabase, Cs: CoinSelectionAlgorithm<D>, Ctx: TxBuilderConte
         self
     }
 
+    /// Fail with [`Error::MissingNonWitnessUtxo`] unless the
+    /// [`psbt::Input::non_witness_utxo`](bitcoin::util::psbt::Input::non_witness_utxo) field can
+    /// be filled for every input, including the SegWit ones.
+    ///
+    /// Some hardware wallets refuse to sign without the full previous transactions. The wallet
+    /// takes them from its database, so they must have been downloaded during the sync: if that's
+    /// not the case they can be fetched later with [`Wallet::populate_non_witness_utxos`].
+    ///
+    /// This option takes precedence over [`TxBuilder::only_witness_utxo`].
+    pub fn require_non_witness_utxo(&mut self) -> &mut Self {
+        self.params.require_non_witness_utxo = true;
+        self
+    }
+
     /// Fill-in the [`psbt::Output::redeem_script`](bitcoin::util::psbt::Output::redeem_script) and
     /// [`psbt::Output::witness_script`](bitcoin::util::psbt::Output::witness_script) fields.
     ///
@@ -560,6 +817,47 @@ impl<'a, B, D: BatchDatabase, Cs: CoinSelectionAlgorithm<D>> TxBuilder<'a, B, D,
         self
     }
 
+    /// Add a recipient to the internal list, using its address
+    ///
+    /// Returns [`Error::InvalidAddressNetwork`] if `address` is not valid for the network of the
+    /// wallet. See [`Wallet::check_address_network`].
+    pub fn add_recipient_address(
+        &mut self,
+        address: &Address,
+        amount: u64,
+    ) -> Result<&mut Self, Error> {
+        self.wallet.check_address_network(address)?;
+        Ok(self.add_recipient(address.script_pubkey(), amount))
+    }
+
+    /// Add a [silent payment](crate::wallet::silent_payments) of `amount` to `address`
+    ///
+    /// The output is derived from the inputs once they have been selected, so
+    /// [`finish`](Self::finish) fails if the wallet doesn't have the private key of every P2PKH,
+    /// P2WPKH and P2SH-P2WPKH input, or if any of the inputs is a taproot output. Since adding
+    /// inputs would change the output, the fee of the transaction can only be bumped by reducing
+    /// the change. With a deterministic [`ordering`](Self::ordering), the outputs are sorted with
+    /// the derived script.
+    ///
+    /// Returns [`SilentPaymentError::InvalidNetwork`] if `address` is not valid for the network
+    /// of the wallet.
+    #[cfg(feature = "silent-payments")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "silent-payments")))]
+    pub fn add_silent_payment_recipient(
+        &mut self,
+        address: &SilentPaymentAddress,
+        amount: u64,
+    ) -> Result<&mut Self, Error> {
+        if !address.is_valid_for_network(self.wallet.network()) {
+            return Err(SilentPaymentError::InvalidNetwork(address.network()).into());
+        }
+
+        let placeholder = output_placeholder(self.params.silent_payments.len());
+        self.params.recipients.push((placeholder, amount));
+        self.params.silent_payments.push(*address);
+        Ok(self)
+    }
+
     /// Sets the address to *drain* excess coins to.
     ///
     /// Usually, when there are excess coins they are sent to a change address generated by the
@@ -604,6 +902,169 @@ impl<'a, B, D: BatchDatabase, Cs: CoinSelectionAlgorithm<D>> TxBuilder<'a, B, D,
         self.params.drain_to = Some(script_pubkey);
         self
     }
+
+    /// Sets the address to *drain* excess coins to, see [`drain_to`]
+    ///
+    /// Returns [`Error::InvalidAddressNetwork`] if `address` is not valid for the network of the
+    /// wallet. See [`Wallet::check_address_network`].
+    ///
+    /// [`drain_to`]: Self::drain_to
+    pub fn drain_to_address(&mut self, address: &Address) -> Result<&mut Self, Error> {
+        self.wallet.check_address_network(address)?;
+        Ok(self.drain_to(address.script_pubkey()))
+    }
+
+    /// Sets multiple addresses to *drain* excess coins to, splitting the value according to the
+    /// given weights
+    ///
+    /// This works like [`drain_to`], but the drained value is split between all the
+    /// `script_pubkey`s proportionally to their weight, so that the fees are also paid
+    /// proportionally by each of them. For example, weights of `70` and `30` send 70% and 30% of
+    /// the value left after paying the fees to the two scripts.
+    ///
+    /// If any share would be below the dust limit an [`Error::InsufficientFunds`] is returned.
+    /// Every weight must be greater than zero and this can't be used together with [`drain_to`].
+    ///
+    /// ```
+    /// # use std::str::FromStr;
+    /// # use bitcoin::*;
+    /// # use bdk::*;
+    /// # let alice = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt").unwrap();
+    /// # let bob = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+    /// # let wallet = doctest_wallet!();
+    /// let mut tx_builder = wallet.build_tx();
+    ///
+    /// tx_builder
+    ///     .drain_wallet()
+    ///     // Alice gets 70% of the coins, Bob the remaining 30%
+    ///     .drain_to_multiple(vec![(alice.script_pubkey(), 70), (bob.script_pubkey(), 30)]);
+    /// let (psbt, tx_details) = tx_builder.finish()?;
+    /// # Ok::<(), bdk::Error>(())
+    /// ```
+    ///
+    /// [`drain_to`]: Self::drain_to
+    pub fn drain_to_multiple(&mut self, recipients: Vec<(Script, u32)>) -> &mut Self {
+        self.params.drain_to_multiple = recipients;
+        self
+    }
+
+    /// Add an `OP_RETURN` output carrying `data` to the transaction
+    ///
+    /// This can be called multiple times to add multiple data carrier outputs. By default
+    /// [`finish`](Self::finish) fails with [`Error::NonStandardData`] if the total size of the
+    /// data carrier scripts is more than 83 bytes, the limit relayed by default by Bitcoin Core,
+    /// which leaves room for 80 bytes of data in a single output.
+    ///
+    /// ```
+    /// # use bdk::*;
+    /// # let wallet = doctest_wallet!();
+    /// let mut tx_builder = wallet.build_tx();
+    ///
+    /// tx_builder
+    ///     .add_data(b"hello")
+    ///     .add_data(b"world");
+    /// let (psbt, tx_details) = tx_builder.finish()?;
+    /// # Ok::<(), bdk::Error>(())
+    /// ```
+    pub fn add_data(&mut self, data: &[u8]) -> &mut Self {
+        let script = bitcoin::blockdata::script::Builder::new()
+            .push_opcode(bitcoin::blockdata::opcodes::all::OP_RETURN)
+            .push_slice(data)
+            .into_script();
+        self.add_data_script(script)
+    }
+
+    /// Add a data carrier output with an arbitrary `OP_RETURN` script to the transaction
+    ///
+    /// Unless [`allow_nonstandard_data`](Self::allow_nonstandard_data) is used, the script must be
+    /// followed only by data pushes. See [`add_data`](Self::add_data) for the size limits.
+    pub fn add_data_script(&mut self, script: Script) -> &mut Self {
+        self.params.data.push(script);
+        self
+    }
+
+    /// Turn the transaction into a [BIP-47](crate::wallet::bip47) notification transaction,
+    /// which tells `recipient` the payment code `sender`
+    ///
+    /// This adds a payment of [`NOTIFICATION_AMOUNT`](crate::wallet::bip47::NOTIFICATION_AMOUNT)
+    /// to the notification address of `recipient`, and an `OP_RETURN` output with `sender`
+    /// blinded with the private key of the first input, which takes the whole data carrier
+    /// budget. [`finish`](Self::finish) fails with
+    /// [`Bip47Error::MissingDesignatedKey`](crate::wallet::bip47::Bip47Error::MissingDesignatedKey)
+    /// if the first input doesn't reveal its public key when spent (P2PKH, P2WPKH or P2SH-P2WPKH),
+    /// or if the wallet doesn't have its private key.
+    #[cfg(feature = "bip47")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "bip47")))]
+    pub fn add_bip47_notification(
+        &mut self,
+        sender: &PaymentCode,
+        recipient: &PaymentCode,
+    ) -> Result<&mut Self, Error> {
+        let address =
+            recipient.notification_address(self.wallet.network(), self.wallet.secp_ctx())?;
+        self.params
+            .recipients
+            .push((address.script_pubkey(), NOTIFICATION_AMOUNT));
+        self.params.data.push(notification_placeholder());
+        self.params.bip47_notification = Some((*sender, *recipient));
+        Ok(self)
+    }
+
+    /// Allow data carrier outputs that wouldn't be relayed by default, because they are too big or
+    /// their scripts are not push-only
+    ///
+    /// The resulting transaction has to be sent directly to a miner that accepts it.
+    pub fn allow_nonstandard_data(&mut self) -> &mut Self {
+        self.params.allow_nonstandard_data = true;
+        self
+    }
+
+    /// Add an ephemeral anchor output to the transaction
+    ///
+    /// The anchor is a pay-to-anchor output with a value of zero, that anyone can spend to bump the
+    /// fees of the transaction with a CPFP. Since the output is below the dust limit, the transaction
+    /// must pay zero fees and is usually built as a TRUC transaction with
+    /// [`version(3)`](super::tx_builder::TxBuilder::version), so that it can be relayed together with
+    /// the child paying for it.
+    ///
+    /// When the fee is not set explicitly it defaults to zero, and setting a non-zero fee makes
+    /// [`finish`](super::tx_builder::TxBuilder::finish) fail.
+    pub fn add_ephemeral_anchor(&mut self) -> &mut Self {
+        self.params.ephemeral_anchor = true;
+        self
+    }
+
+    /// Consolidate the small UTXOs of the wallet
+    ///
+    /// The available UTXOs are spent starting from the smallest ones until the weight of the
+    /// inputs would exceed `max_input_weight`, and the value is then sent to a new internal address
+    /// (unless a different script is set with [`drain_to`]). UTXOs that would cost more in fees
+    /// than their value at the chosen fee rate are not spent.
+    ///
+    /// This is intended to be run when fee rates are low, to avoid having to spend many small
+    /// UTXOs later when fees are higher. Filters like [`unspendable`], [`min_confirmations`] and
+    /// the change policy are respected.
+    ///
+    /// ```
+    /// # use bdk::*;
+    /// # let wallet = doctest_wallet!();
+    /// let mut tx_builder = wallet.build_tx();
+    ///
+    /// tx_builder
+    ///     // Spend as many utxos as fit in 100k weight units
+    ///     .consolidate(100_000)
+    ///     .fee_rate(FeeRate::from_sat_per_vb(1.0));
+    /// let (psbt, tx_details) = tx_builder.finish()?;
+    /// # Ok::<(), bdk::Error>(())
+    /// ```
+    ///
+    /// [`drain_to`]: Self::drain_to
+    /// [`unspendable`]: Self::unspendable
+    /// [`min_confirmations`]: Self::min_confirmations
+    pub fn consolidate(&mut self, max_input_weight: usize) -> &mut Self {
+        self.params.consolidate = Some(max_input_weight);
+        self
+    }
 }
 
 // methods supported only by bump_fee
@@ -639,7 +1100,7 @@ impl<'a, B, D: BatchDatabase> TxBuilder<'a, B, D, DefaultCoinSelectionAlgorithm,
 }
 
 /// Ordering of the transaction's inputs and outputs
-#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Hash, Clone, Copy)]
+#[derive(Clone)]
 pub enum TxOrdering {
     /// Randomized (default)
     Shuffle,
@@ -647,6 +1108,63 @@ pub enum TxOrdering {
     Untouched,
     /// BIP69 / Lexicographic
     Bip69Lexicographic,
+    /// Custom ordering, defined by a comparator for the inputs and one for the outputs
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use std::sync::Arc;
+    /// # use bitcoin::{TxIn, TxOut};
+    /// # use bdk::wallet::tx_builder::TxOrdering;
+    /// // sort the inputs by output index and put the largest outputs first
+    /// let ordering = TxOrdering::Custom {
+    ///     input_sort: Arc::new(|a: &TxIn, b: &TxIn| {
+    ///         a.previous_output.vout.cmp(&b.previous_output.vout)
+    ///     }),
+    ///     output_sort: Arc::new(|a: &TxOut, b: &TxOut| b.value.cmp(&a.value)),
+    /// };
+    /// ```
+    Custom {
+        /// Comparator used to sort the inputs
+        input_sort: Arc<TxSortFn<TxIn>>,
+        /// Comparator used to sort the outputs
+        output_sort: Arc<TxSortFn<TxOut>>,
+    },
+}
+
+/// Comparator used by [`TxOrdering::Custom`]
+pub type TxSortFn<T> = dyn Fn(&T, &T) -> Ordering + Send + Sync;
+
+impl fmt::Debug for TxOrdering {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxOrdering::Shuffle => write!(f, "Shuffle"),
+            TxOrdering::Untouched => write!(f, "Untouched"),
+            TxOrdering::Bip69Lexicographic => write!(f, "Bip69Lexicographic"),
+            TxOrdering::Custom { .. } => write!(f, "Custom"),
+        }
+    }
+}
+
+impl PartialEq for TxOrdering {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (TxOrdering::Shuffle, TxOrdering::Shuffle)
+            | (TxOrdering::Untouched, TxOrdering::Untouched)
+            | (TxOrdering::Bip69Lexicographic, TxOrdering::Bip69Lexicographic) => true,
+            (
+                TxOrdering::Custom {
+                    input_sort: a_input,
+                    output_sort: a_output,
+                },
+                TxOrdering::Custom {
+                    input_sort: b_input,
+                    output_sort: b_output,
+                },
+            ) => Arc::ptr_eq(a_input, b_input) && Arc::ptr_eq(a_output, b_output),
+            _ => false,
+        }
+    }
 }
 
 impl Default for TxOrdering {
@@ -679,8 +1197,40 @@ impl TxOrdering {
                 tx.output
                     .sort_unstable_by_key(|txout| (txout.value, txout.script_pubkey.clone()));
             }
+            TxOrdering::Custom {
+                input_sort,
+                output_sort,
+            } => {
+                tx.input.sort_by(|a, b| input_sort(a, b));
+                tx.output.sort_by(|a, b| output_sort(a, b));
+            }
         }
     }
+
+    // Sort again the outputs of `psbt`, moving the psbt outputs along with them, after some of
+    // their scripts have been replaced. The outputs are only shuffled once
+    #[cfg(feature = "silent-payments")]
+    pub(crate) fn sort_psbt_outputs(&self, psbt: &mut Psbt) {
+        let output_sort: Arc<TxSortFn<TxOut>> = match self {
+            TxOrdering::Untouched | TxOrdering::Shuffle => return,
+            TxOrdering::Bip69Lexicographic => Arc::new(|a: &TxOut, b: &TxOut| {
+                (a.value, &a.script_pubkey).cmp(&(b.value, &b.script_pubkey))
+            }),
+            TxOrdering::Custom { output_sort, .. } => Arc::clone(output_sort),
+        };
+
+        let mut outputs = psbt
+            .global
+            .unsigned_tx
+            .output
+            .drain(..)
+            .zip(psbt.outputs.drain(..))
+            .collect::<Vec<_>>();
+        outputs.sort_by(|(a, _), (b, _)| output_sort(a, b));
+        let (txouts, outputs) = outputs.into_iter().unzip();
+        psbt.global.unsigned_tx.output = txouts;
+        psbt.outputs = outputs;
+    }
 }
 
 /// Transaction version
@@ -822,6 +1372,36 @@ mod test {
         assert_eq!(tx.output[2].script_pubkey, From::from(vec![0xAA, 0xEE]));
     }
 
+    #[test]
+    fn test_output_ordering_custom() {
+        let original_tx = ordering_test_tx!();
+        let mut tx = original_tx.clone();
+
+        let ordering = TxOrdering::Custom {
+            input_sort: Arc::new(|a: &TxIn, b: &TxIn| {
+                b.previous_output.vout.cmp(&a.previous_output.vout)
+            }),
+            output_sort: Arc::new(|a: &TxOut, b: &TxOut| {
+                (b.value, &b.script_pubkey).cmp(&(a.value, &a.script_pubkey))
+            }),
+        };
+        ordering.sort_tx(&mut tx);
+
+        let vouts = tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output.vout)
+            .collect::<Vec<_>>();
+        assert_eq!(vouts, vec![5, 1, 0]);
+
+        assert_eq!(tx.output[0].script_pubkey, From::from(vec![0xAA, 0xEE]));
+        assert_eq!(tx.output[1].script_pubkey, From::from(vec![0xAA]));
+        assert_eq!(tx.output[2].value, 800);
+
+        assert_eq!(ordering.clone(), ordering);
+        assert_ne!(ordering, TxOrdering::Untouched);
+    }
+
     fn get_test_utxos() -> Vec<LocalUtxo> {
         vec![
             LocalUtxo {
@@ -9,7 +9,11 @@
 // You may not use this file except in accordance with one or both of these
 // licenses.
 
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::Instruction;
 use bitcoin::secp256k1::{All, Secp256k1};
+use bitcoin::util::address::Payload;
+use bitcoin::{Address, Network, Script};
 
 use miniscript::{MiniscriptKey, Satisfier, ToPublicKey};
 
@@ -28,6 +32,10 @@ pub(crate) const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000FFFF;
 // Threshold for nLockTime to be considered a block-height-based timelock rather than time-based
 pub(crate) const BLOCKS_TIMELOCK_THRESHOLD: u32 = 500000000;
 
+// Maximum total size in bytes of the data carrier (`OP_RETURN`) scripts relayed by default by
+// Bitcoin Core
+pub(crate) const MAX_DATA_CARRIER_SIZE: usize = 83;
+
 /// Trait to check if a value is below the dust limit
 // we implement this trait to make sure we don't mess up the comparison with off-by-one like a <
 // instead of a <= etc. The constant value for the dust limit is not public on purpose, to
@@ -45,13 +53,19 @@ impl IsDust for u64 {
 
 pub struct After {
     pub current_height: Option<u32>,
+    pub median_time_past: Option<u32>,
     pub assume_height_reached: bool,
 }
 
 impl After {
-    pub(crate) fn new(current_height: Option<u32>, assume_height_reached: bool) -> After {
+    pub(crate) fn new(
+        current_height: Option<u32>,
+        median_time_past: Option<u32>,
+        assume_height_reached: bool,
+    ) -> After {
         After {
             current_height,
+            median_time_past,
             assume_height_reached,
         }
     }
@@ -97,6 +111,14 @@ pub(crate) fn check_nlocktime(nlocktime: u32, required: u32) -> bool {
 
 impl<Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for After {
     fn check_after(&self, n: u32) -> bool {
+        // time-based timelocks are compared to the median time past, and the transaction can only
+        // be mined once it's strictly greater than its nLockTime
+        if n >= BLOCKS_TIMELOCK_THRESHOLD {
+            if let Some(median_time_past) = self.median_time_past {
+                return median_time_past > n;
+            }
+        }
+
         if let Some(current_height) = self.current_height {
             current_height >= n
         } else {
@@ -136,6 +158,51 @@ impl<Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for Older {
     }
 }
 
+// Testnet addresses are parsed as `Network::Testnet`, but they can also be valid on signet (which
+// uses the same prefixes) and on regtest (which shares the base58 prefixes but not the bech32 hrp)
+pub(crate) fn is_address_valid_for_network(address: &Address, network: Network) -> bool {
+    match (address.network, network) {
+        (found, expected) if found == expected => true,
+        (Network::Testnet, Network::Signet) => true,
+        (Network::Testnet, Network::Regtest) => {
+            !matches!(address.payload, Payload::WitnessProgram { .. })
+        }
+        _ => false,
+    }
+}
+
+// Check that the data carrier outputs of a transaction would be relayed by default by Bitcoin
+// Core: every script must be an `OP_RETURN` followed only by pushes, and their total size can't
+// exceed `MAX_DATA_CARRIER_SIZE`. Returns a description of the first problem found
+pub(crate) fn check_data_carrier_scripts(scripts: &[Script]) -> Result<(), String> {
+    for script in scripts {
+        let is_push_only = script
+            .instructions()
+            .skip(1)
+            .all(|instruction| match instruction {
+                Ok(Instruction::PushBytes(_)) => true,
+                Ok(Instruction::Op(op)) => op.into_u8() <= opcodes::all::OP_PUSHNUM_16.into_u8(),
+                Err(_) => false,
+            });
+        if !is_push_only {
+            return Err(format!(
+                "The data carrier script `{}` is not push-only",
+                script
+            ));
+        }
+    }
+
+    let total_size = scripts.iter().map(|script| script.len()).sum::<usize>();
+    if total_size > MAX_DATA_CARRIER_SIZE {
+        return Err(format!(
+            "The data carrier scripts are {} bytes, more than the maximum of {}",
+            total_size, MAX_DATA_CARRIER_SIZE
+        ));
+    }
+
+    Ok(())
+}
+
 pub(crate) type SecpCtx = Secp256k1<All>;
 
 pub struct ChunksIterator<I: Iterator> {
@@ -174,8 +241,16 @@ impl<I: Iterator> Iterator for ChunksIterator<I> {
 
 #[cfg(test)]
 mod test {
+    use std::str::FromStr;
+
+    use bitcoin::{Address, Network};
+
+    use bitcoin::blockdata::opcodes;
+    use bitcoin::blockdata::script::Builder;
+
     use super::{
-        check_nlocktime, check_nsequence_rbf, BLOCKS_TIMELOCK_THRESHOLD,
+        check_data_carrier_scripts, check_nlocktime, check_nsequence_rbf,
+        is_address_valid_for_network, BLOCKS_TIMELOCK_THRESHOLD, MAX_DATA_CARRIER_SIZE,
         SEQUENCE_LOCKTIME_TYPE_FLAG,
     };
     use crate::types::FeeRate;
@@ -263,4 +338,59 @@ mod test {
         );
         assert!(result);
     }
+
+    #[test]
+    fn test_is_address_valid_for_network() {
+        let mainnet = Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap();
+        let testnet = Address::from_str("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx").unwrap();
+        let testnet_p2sh = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let regtest = Address::from_str("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080").unwrap();
+
+        assert!(is_address_valid_for_network(&mainnet, Network::Bitcoin));
+        assert!(!is_address_valid_for_network(&mainnet, Network::Testnet));
+
+        assert!(is_address_valid_for_network(&testnet, Network::Testnet));
+        assert!(is_address_valid_for_network(&testnet, Network::Signet));
+        assert!(!is_address_valid_for_network(&testnet, Network::Regtest));
+        assert!(!is_address_valid_for_network(&testnet, Network::Bitcoin));
+
+        assert!(is_address_valid_for_network(
+            &testnet_p2sh,
+            Network::Regtest
+        ));
+        assert!(is_address_valid_for_network(&regtest, Network::Regtest));
+        assert!(!is_address_valid_for_network(&regtest, Network::Testnet));
+    }
+
+    #[test]
+    fn test_check_data_carrier_scripts() {
+        let data_script = |data: &[u8]| {
+            Builder::new()
+                .push_opcode(opcodes::all::OP_RETURN)
+                .push_slice(data)
+                .into_script()
+        };
+
+        assert!(check_data_carrier_scripts(&[]).is_ok());
+        assert!(check_data_carrier_scripts(&[data_script(&[0x42; 80])]).is_ok());
+        assert!(
+            check_data_carrier_scripts(&[data_script(&[0x42; 40]), data_script(&[0x42; 39])])
+                .is_ok()
+        );
+
+        // too big, alone or in total
+        assert!(check_data_carrier_scripts(&[data_script(&[0x42; 81])]).is_err());
+        assert!(
+            check_data_carrier_scripts(&[data_script(&[0x42; 40]), data_script(&[0x42; 40])])
+                .is_err()
+        );
+        assert_eq!(data_script(&[0x42; 81]).len(), MAX_DATA_CARRIER_SIZE + 1);
+
+        // not push-only
+        let script = Builder::new()
+            .push_opcode(opcodes::all::OP_RETURN)
+            .push_opcode(opcodes::all::OP_CHECKSIG)
+            .into_script();
+        assert!(check_data_carrier_scripts(&[script]).is_err());
+    }
 }